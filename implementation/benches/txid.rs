@@ -0,0 +1,29 @@
+// Benchmark of the txid and wtxid computation over the mempool snapshot
+// run with: cargo bench --bench txid
+
+use bitcoin_block_builder::parsing::parse_transactions_from_dir;
+use bitcoin_block_builder::validation::validate_parsing::validate_txid_hash_filename;
+use std::time::Instant;
+
+const MEMPOOL_DIR: &str = "../mempool";
+const ITERATIONS: u32 = 20;
+
+fn main() {
+    let mut transactions =
+        parse_transactions_from_dir(MEMPOOL_DIR).expect("Parsing the mempool failed");
+
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        for tx in transactions.iter_mut() {
+            validate_txid_hash_filename(tx);
+        }
+    }
+    let elapsed = start.elapsed();
+    println!(
+        "txid computation: {} transactions x {} iterations in {:?} ({:?} per transaction)",
+        transactions.len(),
+        ITERATIONS,
+        elapsed,
+        elapsed / (transactions.len() as u32 * ITERATIONS)
+    );
+}
@@ -0,0 +1,183 @@
+// Decoding of bitcoin addresses into the scriptpubkey they pay to: base58check P2PKH and P2SH
+// addresses (BIP13) and bech32 (BIP173, witness v0) or bech32m (BIP350, witness v1+) segwit
+// addresses. Mainnet, testnet/signet and regtest prefixes are accepted, the network isn't
+// reported as the scriptpubkey is the same on all of them.
+
+use crate::validation::utils::double_hash;
+
+const BASE58_ALPHABET: &[u8] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+const BECH32_CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+const SEGWIT_HRPS: [&str; 3] = ["bc", "tb", "bcrt"];
+const BECH32_CONST: u32 = 1;
+const BECH32M_CONST: u32 = 0x2bc830a3;
+const BECH32_MAX_LENGTH: usize = 90;
+
+// base58 version bytes of mainnet and testnet/regtest
+const P2PKH_VERSIONS: [u8; 2] = [0x00, 0x6f];
+const P2SH_VERSIONS: [u8; 2] = [0x05, 0xc4];
+
+// returns: scriptpubkey the address pays to or Err(reason) if it is no valid address
+pub fn decode_address(address: &str) -> Result<Vec<u8>, String> {
+    let lowercase = address.to_ascii_lowercase();
+    match SEGWIT_HRPS
+        .iter()
+        .any(|hrp| lowercase.starts_with(&format!("{}1", hrp)))
+    {
+        true => decode_segwit_address(address),
+        false => decode_base58_address(address),
+    }
+}
+
+// returns: bytes of the base58 string (leading '1's are zero bytes) or None for other
+// characters
+fn decode_base58(encoded: &str) -> Option<Vec<u8>> {
+    let mut bytes: Vec<u8> = Vec::new(); // little endian
+    for character in encoded.bytes() {
+        let mut carry = BASE58_ALPHABET.iter().position(|c| *c == character)? as u32;
+        for byte in bytes.iter_mut() {
+            carry += *byte as u32 * 58;
+            *byte = carry as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            bytes.push(carry as u8);
+            carry >>= 8;
+        }
+    }
+    let leading_zeros = encoded.bytes().take_while(|c| *c == b'1').count();
+    bytes.extend(vec![0; leading_zeros]);
+    bytes.reverse();
+    Some(bytes)
+}
+
+// version byte, 20 byte hash and the first 4 bytes of the double SHA256 as checksum
+// returns: P2PKH or P2SH scriptpubkey of the base58check address
+fn decode_base58_address(address: &str) -> Result<Vec<u8>, String> {
+    let decoded = decode_base58(address).ok_or(format!(
+        "Address {} contains invalid base58 characters",
+        address
+    ))?;
+    if decoded.len() != 25 {
+        return Err(format!("Base58 address {} has an invalid length", address));
+    }
+    let (payload, checksum) = decoded.split_at(21);
+    if double_hash(payload)[..4] != *checksum {
+        return Err(format!(
+            "Base58 address {} has an invalid checksum",
+            address
+        ));
+    }
+    let (version, hash) = (payload[0], &payload[1..]);
+    let mut scriptpubkey = Vec::new();
+    if P2PKH_VERSIONS.contains(&version) {
+        scriptpubkey.extend([0x76, 0xa9, 0x14]); // OP_DUP OP_HASH160 <20 bytes>
+        scriptpubkey.extend(hash);
+        scriptpubkey.extend([0x88, 0xac]); // OP_EQUALVERIFY OP_CHECKSIG
+    } else if P2SH_VERSIONS.contains(&version) {
+        scriptpubkey.extend([0xa9, 0x14]); // OP_HASH160 <20 bytes>
+        scriptpubkey.extend(hash);
+        scriptpubkey.push(0x87); // OP_EQUAL
+    } else {
+        return Err(format!("Base58 address version {:#04x} unknown", version));
+    }
+    Ok(scriptpubkey)
+}
+
+// returns: BIP173 checksum polymod of the 5 bit values
+fn bech32_polymod(values: &[u8]) -> u32 {
+    const GENERATOR: [u32; 5] = [0x3b6a57b2, 0x26508e6d, 0x1ea119fa, 0x3d4233dd, 0x2a1462b3];
+    let mut checksum: u32 = 1;
+    for value in values {
+        let top = checksum >> 25;
+        checksum = (checksum & 0x1ffffff) << 5 ^ *value as u32;
+        for (index, generator) in GENERATOR.iter().enumerate() {
+            if (top >> index) & 1 == 1 {
+                checksum ^= generator;
+            }
+        }
+    }
+    checksum
+}
+
+// returns: hrp expanded for the checksum (high bits, 0, low bits of every character)
+fn hrp_expand(hrp: &str) -> Vec<u8> {
+    let mut expanded: Vec<u8> = hrp.bytes().map(|c| c >> 5).collect();
+    expanded.push(0);
+    expanded.extend(hrp.bytes().map(|c| c & 0x1f));
+    expanded
+}
+
+// regroups the 5 bit values into bytes, the padding has to be less than 5 zero bits
+// returns: bytes or None for invalid padding
+fn from_5_bit(values: &[u8]) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let (mut accumulator, mut bits) = (0u32, 0);
+    for value in values {
+        accumulator = (accumulator << 5 | *value as u32) & 0xfff; // at most 12 pending bits
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((accumulator >> bits) as u8);
+        }
+    }
+    match bits < 5 && accumulator & ((1 << bits) - 1) == 0 {
+        true => Some(bytes),
+        false => None,
+    }
+}
+
+// hrp, separator '1', witness version, program and 6 checksum characters (bech32 for v0,
+// bech32m for v1 and later), all lowercase or all uppercase
+// returns: witness program scriptpubkey (version opcode and program push)
+fn decode_segwit_address(address: &str) -> Result<Vec<u8>, String> {
+    let invalid = |reason: &str| Err(format!("Segwit address {} {}", address, reason));
+    if address.len() > BECH32_MAX_LENGTH {
+        return invalid("is too long");
+    }
+    let lowercase = address.to_ascii_lowercase();
+    if address != lowercase && address != address.to_ascii_uppercase() {
+        return invalid("has mixed case");
+    }
+    let (hrp, data) = lowercase
+        .rsplit_once('1')
+        .expect("Segwit hrp without separator");
+    if !SEGWIT_HRPS.contains(&hrp) {
+        return invalid("has an unknown hrp");
+    }
+    let values = match data
+        .bytes()
+        .map(|c| BECH32_CHARSET.iter().position(|b| *b == c).map(|v| v as u8))
+        .collect::<Option<Vec<u8>>>()
+    {
+        Some(values) if values.len() > 6 => values,
+        Some(_) => return invalid("is too short"),
+        None => return invalid("contains invalid bech32 characters"),
+    };
+
+    let mut checked = hrp_expand(hrp);
+    checked.extend(&values);
+    let version = values[0];
+    let expected_const = if version == 0 {
+        BECH32_CONST
+    } else {
+        BECH32M_CONST
+    };
+    if bech32_polymod(&checked) != expected_const {
+        return invalid("has an invalid checksum");
+    }
+    let program = match from_5_bit(&values[1..values.len() - 6]) {
+        Some(program) => program,
+        None => return invalid("has invalid padding"),
+    };
+    if version > 16 || program.len() < 2 || program.len() > 40 {
+        return invalid("has an invalid witness version or program length");
+    }
+    if version == 0 && program.len() != 20 && program.len() != 32 {
+        return invalid("has an invalid witness v0 program length");
+    }
+
+    let mut scriptpubkey = vec![if version == 0 { 0x00 } else { 0x50 + version }];
+    scriptpubkey.push(program.len() as u8);
+    scriptpubkey.extend(program);
+    Ok(scriptpubkey)
+}
@@ -0,0 +1,186 @@
+// Fixed precision conversion between sat amounts and BTC strings (8 decimals, no floats),
+// used for all BTC figures in the outputs so they are formatted the same way everywhere.
+// Also the checked parsing of the sat value fields of the transaction json files.
+
+use serde::de::{Deserialize, Deserializer, Error};
+use serde_json::Value;
+
+pub const SAT_PER_BTC: u64 = 100_000_000;
+// no amount (single value or sum) can exceed the total supply
+pub const MAX_MONEY: u64 = 21_000_000 * SAT_PER_BTC;
+
+// formats the sat amount as BTC with exactly 8 decimals and a '.' separator
+// returns: e.g. "6.25000000" for 625000000 sat
+pub fn format_btc(sat: u64) -> String {
+    format!("{}.{:08}", sat / SAT_PER_BTC, sat % SAT_PER_BTC)
+}
+
+// parses a BTC string of digits with up to 8 decimals after an optional '.' (no sign, no
+// exponent, no thousands separators)
+// returns: amount in sat or Err(message) if the string is malformed or overflows
+pub fn parse_btc(btc: &str) -> Result<u64, String> {
+    let (whole, fraction) = btc.split_once('.').unwrap_or((btc, ""));
+    let is_digits = |part: &str| part.bytes().all(|byte| byte.is_ascii_digit());
+    if whole.is_empty() || !is_digits(whole) || !is_digits(fraction) {
+        return Err(format!("{} is no valid BTC amount", btc));
+    }
+    if fraction.len() > 8 || (btc.contains('.') && fraction.is_empty()) {
+        return Err(format!("{} needs 1 to 8 decimals after the '.'", btc));
+    }
+
+    let whole: u64 = whole.parse().map_err(|_| format!("{} is too large", btc))?;
+    let fraction: u64 = format!("{:0<8}", fraction)
+        .parse()
+        .expect("Fraction is no number");
+    whole
+        .checked_mul(SAT_PER_BTC)
+        .and_then(|sat| sat.checked_add(fraction))
+        .ok_or(format!("{} is too large", btc))
+}
+
+// parses a sat amount written as decimal integer, optionally with fraction digits and an
+// exponent (e.g. "12500", "1.25e4", "125E2"), exactly without floating point conversion
+// returns: amount in sat or Err(message) if it is negative, fractional, malformed or too large
+pub fn parse_sat(text: &str) -> Result<u64, String> {
+    if text.starts_with('-') {
+        return Err(format!("{} is negative", text));
+    }
+    let (mantissa, exponent) = match text.split_once(['e', 'E']) {
+        Some((mantissa, exponent)) => (
+            mantissa,
+            exponent
+                .parse::<i32>()
+                .map_err(|_| format!("{} has an invalid exponent", text))?,
+        ),
+        None => (text, 0),
+    };
+    let (whole, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    let digits = format!("{}{}", whole, fraction);
+    if whole.is_empty() || !digits.bytes().all(|byte| byte.is_ascii_digit()) {
+        return Err(format!("{} is no valid amount", text));
+    }
+    let shift = exponent as i64 - fraction.len() as i64;
+    let too_large = || format!("{} is too large", text);
+
+    // the digits shifted by 10^shift, digits shifted out to the right have to be zero
+    let significant = match shift {
+        _ if shift >= 0 => digits.trim_start_matches('0'),
+        _ => {
+            let cut = digits.len().saturating_sub(shift.unsigned_abs() as usize);
+            if digits[cut..].bytes().any(|byte| byte != b'0') {
+                return Err(format!("{} is fractional", text));
+            }
+            digits[..cut].trim_start_matches('0')
+        }
+    };
+    if significant.is_empty() {
+        return Ok(0);
+    }
+    let mut sat: u64 = significant.parse().map_err(|_| too_large())?;
+    for _ in 0..shift.max(0) {
+        sat = sat.checked_mul(10).ok_or_else(too_large)?;
+    }
+    Ok(sat)
+}
+
+// largest integer a json float can represent without losing precision (2^53)
+const MAX_EXACT_FLOAT: f64 = 9_007_199_254_740_992.0;
+
+// parses the sat amount of a json value field: an integer, a string of an integer or an
+// integer in scientific notation (number or string, see parse_sat()). Json numbers in
+// scientific notation arrive as float and are only accepted if they are exact integers.
+// returns: amount in sat or Err(message) for floats, negative or too large amounts
+pub fn parse_json_amount(value: &Value) -> Result<u64, String> {
+    match value {
+        Value::Number(number) => match (number.as_u64(), number.as_f64()) {
+            (Some(sat), _) => Ok(sat),
+            (None, Some(float)) if float < 0.0 => Err(format!("{} is negative", number)),
+            (None, Some(float)) if float.fract() != 0.0 => {
+                Err(format!("{} is a float, expected sat as integer", number))
+            }
+            (None, Some(float)) if float > MAX_EXACT_FLOAT => {
+                Err(format!("{} is too large for an exact float", number))
+            }
+            (None, Some(float)) => Ok(float as u64),
+            (None, None) => Err(format!("{} is no valid amount", number)),
+        },
+        Value::String(text) => parse_sat(text),
+        _ => Err(format!("{} is no amount", value)),
+    }
+}
+
+// serde deserializer of the sat value fields accepting the encodings of parse_json_amount()
+pub fn deserialize_amount<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    let value = Value::deserialize(deserializer)?;
+    parse_json_amount(&value).map_err(D::Error::custom)
+}
+
+// parses a BTC amount of bitcoin core's json: a number with up to 8 decimals, which arrives as
+// float and is rounded to the nearest sat (exact below 2^53 sat), or a string (see parse_btc())
+// returns: amount in sat or Err(message) for negative, too large or malformed amounts
+pub fn parse_json_btc(value: &Value) -> Result<u64, String> {
+    match value {
+        Value::Number(number) => match number.as_f64() {
+            Some(btc) if btc < 0.0 => Err(format!("{} is negative", number)),
+            Some(btc) if btc > (MAX_MONEY / SAT_PER_BTC) as f64 => {
+                Err(format!("{} is above the total supply", number))
+            }
+            Some(btc) => Ok((btc * SAT_PER_BTC as f64).round() as u64),
+            None => Err(format!("{} is no valid amount", number)),
+        },
+        Value::String(btc) => parse_btc(btc),
+        _ => Err(format!("{} is no amount", value)),
+    }
+}
+
+// serde deserializer of the BTC amount fields of bitcoin core's json (see parse_json_btc())
+pub fn deserialize_btc<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    let value = Value::deserialize(deserializer)?;
+    parse_json_btc(&value).map_err(D::Error::custom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn btc_strings_have_8_decimals_and_round_trip() {
+        assert_eq!(format_btc(625000000), "6.25000000");
+        assert_eq!(format_btc(1), "0.00000001");
+        assert_eq!(format_btc(MAX_MONEY), "21000000.00000000");
+        for sat in [
+            0,
+            1,
+            99_999_999,
+            SAT_PER_BTC,
+            312_500_001,
+            MAX_MONEY,
+            u64::MAX,
+        ] {
+            assert_eq!(parse_btc(&format_btc(sat)), Ok(sat));
+        }
+        // fewer decimals are filled up, the '.' is optional
+        assert_eq!(parse_btc("0.1"), Ok(10_000_000));
+        assert_eq!(parse_btc("21"), Ok(21 * SAT_PER_BTC));
+    }
+
+    #[test]
+    fn malformed_btc_strings_are_rejected() {
+        for btc in [
+            "",
+            "1.",
+            ".5",
+            "1.123456789",
+            "-1",
+            "+1",
+            "1e3",
+            "1,5",
+            " 1",
+            "0x10",
+        ] {
+            assert!(parse_btc(btc).is_err(), "{} was accepted", btc);
+        }
+        // one sat above u64::MAX
+        assert!(parse_btc("184467440737.09551616").is_err());
+    }
+}
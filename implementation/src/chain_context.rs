@@ -0,0 +1,75 @@
+// Chain state the block is built on top of. Defaults to the values given in the exercise.
+
+use crate::mining::MiningError;
+use crate::validation::validate_values::FinalityTarget;
+use std::collections::HashMap;
+
+pub const EXERCISE_BLOCK_HEIGHT: u64 = 839653;
+pub const EXERCISE_PREVIOUS_BLOCK_HASH: &str =
+    "00000000000000000001901b9f3b6c7a0c34b20b29b950d0d8ffa36c63979c1c";
+pub const EXERCISE_BITS: u32 = 0x1f00ffff;
+
+#[derive(Debug, Clone)]
+pub struct ChainContext {
+    pub height: u64,                            // height of the block that is being built
+    pub previous_block_hash: String,            // hex in display (reversed) byte order
+    pub median_time_past: Option<u32>,          // MTP of the last 11 blocks, unknown if None
+    pub bits: u32,                              // compact target of the header
+    pub node_witness: Option<NodeWitnessData>,  // witness data of a node template (hybrid mode)
+    pub coinbase_heights: HashMap<String, u64>, // coinbase txid -> height, of spent coinbases
+}
+
+// witness commitment data taken from the getblocktemplate response of a node. The node
+// commitment is only used after verifying it against the locally computed one.
+#[derive(Debug, Clone, Default)]
+pub struct NodeWitnessData {
+    pub default_witness_commitment: Option<String>, // commitment scriptpubkey hex
+    pub witness_reserved_value: Option<[u8; 32]>,   // coinbase witness, all zero if None
+    pub commitment_position: Option<usize>,         // index of the commitment coinbase output
+}
+
+impl Default for ChainContext {
+    fn default() -> Self {
+        ChainContext {
+            height: EXERCISE_BLOCK_HEIGHT,
+            previous_block_hash: EXERCISE_PREVIOUS_BLOCK_HASH.to_string(),
+            median_time_past: None,
+            bits: EXERCISE_BITS,
+            node_witness: None,
+            coinbase_heights: HashMap::new(),
+        }
+    }
+}
+
+impl ChainContext {
+    // returns: FinalityTarget of the block that is being built
+    pub fn finality_target(&self) -> FinalityTarget {
+        FinalityTarget {
+            height: self.height,
+            median_time_past: self.median_time_past,
+            coinbase_heights: self.coinbase_heights.clone(),
+        }
+    }
+
+    // returns: previous block hash in natural byte order as used in the header or
+    // Err(MiningError::InvalidPreviousBlockHash) if it isn't 32 bytes of hex
+    pub fn previous_block_hash_natural(&self) -> Result<[u8; 32], MiningError> {
+        let mut hash: [u8; 32] = hex::decode(&self.previous_block_hash)
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or(MiningError::InvalidPreviousBlockHash(
+                self.previous_block_hash.clone(),
+            ))?;
+        hash.reverse();
+        Ok(hash)
+    }
+}
+
+// value parser of block hash arguments
+// returns: the lowercase hash or Err(message) if it isn't 64 hex characters
+pub fn parse_block_hash(hash: &str) -> Result<String, String> {
+    if hash.len() != 64 || !hash.chars().all(|char| char.is_ascii_hexdigit()) {
+        return Err("expected a block hash of 64 hex characters".to_string());
+    }
+    Ok(hash.to_ascii_lowercase())
+}
@@ -0,0 +1,460 @@
+use bitcoin_block_builder::chain_context::{
+    parse_block_hash, ChainContext, EXERCISE_BITS, EXERCISE_BLOCK_HEIGHT,
+    EXERCISE_PREVIOUS_BLOCK_HASH,
+};
+use bitcoin_block_builder::feerate::{FeeRate, RelayFeerates};
+use bitcoin_block_builder::mempool_policy::MempoolPolicy;
+use bitcoin_block_builder::mining::assign_parents::PackageLimits;
+use bitcoin_block_builder::mining::conflict_graph::ConflictPolicy;
+use bitcoin_block_builder::parsing::dir_filter::DirFilter;
+use bitcoin_block_builder::parsing::file_states;
+use bitcoin_block_builder::validation::validate_values::DUST_RELAY_FEERATE;
+use bitcoin_block_builder::validation::ValidationOptions;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::time::UNIX_EPOCH;
+
+// command line interface of the block builder, parsed with clap
+#[derive(Parser, Debug)]
+#[command(about = "Constructs a bitcoin block from a directory of mempool transactions")]
+pub struct Cli {
+    /// Directory containing the mempool transaction json files, a single json file with an
+    /// array of the transactions or an object of their txids and transactions, or a tarball of
+    /// the json files (gzip and zstd compressed files are decompressed)
+    #[arg(long, default_value = "../mempool")]
+    pub mempool: String,
+
+    /// Also parse the json files in subdirectories of --mempool and --arrivals
+    #[arg(long)]
+    pub recursive: bool,
+
+    /// Only parse mempool files matching the glob pattern, matched against the file name or,
+    /// if it contains a `/`, the path relative to the mempool directory (`*` within a path
+    /// segment, `**` across segments), can be repeated
+    #[arg(long, value_name = "PATTERN")]
+    pub include: Vec<String>,
+
+    /// Skip mempool files matching the glob pattern (like --include), can be repeated
+    #[arg(long, value_name = "PATTERN")]
+    pub exclude: Vec<String>,
+
+    /// `getrawmempool true` json of a bitcoin core node, used as mempool instead of --mempool
+    #[arg(
+        long,
+        value_name = "FILE",
+        requires = "core_transactions",
+        conflicts_with_all = ["strict", "memory_cap"]
+    )]
+    pub core_mempool: Option<String>,
+
+    /// Json array of the `getrawtransaction <txid> 2` results of the --core-mempool transactions
+    #[arg(long, value_name = "FILE", requires = "core_mempool")]
+    pub core_transactions: Option<String>,
+
+    /// mempool.dat persisted by a bitcoin core node (version 1 or XOR obfuscated version 2),
+    /// used as mempool instead of --mempool
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["strict", "memory_cap", "core_mempool"]
+    )]
+    pub mempool_dat: Option<String>,
+
+    /// Json object of the confirmed outputs spent by the --mempool-dat transactions, keyed by
+    /// "txid:vout" with the value in sat and the scriptpubkey hex
+    #[arg(long, value_name = "FILE", requires = "mempool_dat")]
+    pub prevouts: Option<String>,
+
+    /// Esplora api (http://host[:port][/prefix], no TLS) whose mempool transactions are fetched
+    /// and used as mempool instead of --mempool
+    #[arg(
+        long,
+        value_name = "URL",
+        conflicts_with_all = ["strict", "memory_cap", "core_mempool", "mempool_dat"]
+    )]
+    pub esplora: Option<String>,
+
+    /// Fetch at most this many transactions of the --esplora mempool
+    #[arg(long, value_name = "COUNT", requires = "esplora")]
+    pub esplora_limit: Option<usize>,
+
+    /// Seconds after which connecting to the --esplora api or waiting for its response fails
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        default_value_t = 30,
+        value_parser = clap::value_parser!(u64).range(1..),
+        requires = "esplora"
+    )]
+    pub esplora_timeout: u64,
+
+    /// Finalized PSBT (binary, or one base64 or hex PSBT per line) whose transactions are added
+    /// to the mempool, can be repeated
+    #[arg(long, value_name = "FILE")]
+    pub psbt: Vec<String>,
+
+    /// Binary cache of the parsed and validated mempool: reused if the files of the mempool
+    /// sources (paths, modification times, sizes) and the validation options are unchanged,
+    /// otherwise written after the validation. Not available for --esplora mempools
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["simulate_blocks", "esplora"])]
+    pub cache: Option<String>,
+
+    /// Path of the output.txt if no bundle directory is given
+    /// (block_layout.json is written next to it)
+    #[arg(long, default_value = "../output.txt")]
+    pub output: String,
+
+    /// Cross-check the redundant json data of the transactions and reject inconsistent ones
+    #[arg(long)]
+    pub strict: bool,
+
+    /// Verify the scripts under the standardness policy flags on top of the consensus flags
+    /// (minimal pushes, clean stack, at most 3 public keys in bare multisig scriptpubkeys,
+    /// low S signatures, no reserved OP_NOPx, minimal OP_IF arguments in witness scripts, no
+    /// witness programs of unknown versions) and the standard size limits (at most 400000
+    /// weight units, at least 65 non-witness bytes, scriptsigs of at most 1650 bytes)
+    #[arg(long)]
+    pub policy: bool,
+
+    /// Run all checks on each transaction instead of stopping at the first failure, the
+    /// validation report lists every failed check of the rejected transactions
+    #[arg(long)]
+    pub all_reasons: bool,
+
+    /// Reject transactions whose nLockTime or BIP68 relative locktimes aren't reached at
+    /// --height and --median-time-past or that spend immature coinbase outputs (of
+    /// --coinbase-heights) as invalid instead of deferring them as not includable yet (outputs
+    /// of mempool parents confirm in the same block)
+    #[arg(long)]
+    pub reject_non_final: bool,
+
+    /// Report outputs below the dust threshold in the validation report or reject their
+    /// transactions as invalid
+    #[arg(long, value_enum, value_name = "ACTION")]
+    pub dust: Option<DustAction>,
+
+    /// Dust relay feerate in sat per 1000 vbytes, outputs worth less than spending them at
+    /// this feerate are dust
+    #[arg(long, value_name = "SAT_PER_KVB", default_value_t = DUST_RELAY_FEERATE)]
+    pub dust_relay_feerate: u64,
+
+    /// Json object of coinbase txids and the heights of their blocks, spends of their outputs
+    /// need 100 confirmations at --height
+    #[arg(long, value_name = "FILE")]
+    pub coinbase_heights: Option<String>,
+
+    /// How the surviving spender of conflicting transactions is decided: the highest package
+    /// feerate, or additionally BIP125 replacement rules (higher absolute fee and feerate,
+    /// paying for the own relay bandwidth) with or without the replaceability signal
+    #[arg(long, value_enum, default_value_t = ConflictResolution::PackageFeerate)]
+    pub conflict_policy: ConflictResolution,
+
+    /// Minimum relay feerate in sat per 1000 vbytes, transactions paying less are invalid
+    #[arg(long, value_name = "SAT_PER_KVB", default_value_t = 1000)]
+    pub min_relay_feerate: u64,
+
+    /// Incremental relay feerate in sat per 1000 vbytes a BIP125 replacement has to pay for
+    /// its own size on top of the fees of the replaced transactions
+    #[arg(long, value_name = "SAT_PER_KVB", default_value_t = 1000)]
+    pub incremental_relay_feerate: u64,
+
+    /// Package feerate floor in sat per 1000 vbytes, transactions whose package (with their
+    /// mempool ancestors) pays less are only selected as parents of a CPFP package above it
+    #[arg(long, value_name = "SAT_PER_KVB", default_value_t = 1000)]
+    pub package_feerate_floor: u64,
+
+    /// Maximum number of mempool ancestors (including the transaction) of a selected
+    /// transaction, transactions exceeding the ancestor or descendant limits are removed with
+    /// their descendants before the selection like a node wouldn't accept them
+    #[arg(long, value_name = "N", default_value_t = PackageLimits::default().ancestor_count)]
+    pub limit_ancestor_count: usize,
+
+    /// Maximum summed size of a transaction and its mempool ancestors in vbytes
+    #[arg(long, value_name = "VBYTES", default_value_t = PackageLimits::default().ancestor_vsize)]
+    pub limit_ancestor_size: u64,
+
+    /// Maximum number of mempool descendants (including the transaction) of a transaction
+    #[arg(long, value_name = "N", default_value_t = PackageLimits::default().descendant_count)]
+    pub limit_descendant_count: usize,
+
+    /// Maximum summed size of a transaction and its mempool descendants in vbytes
+    #[arg(long, value_name = "VBYTES", default_value_t = PackageLimits::default().descendant_vsize)]
+    pub limit_descendant_size: u64,
+
+    /// Cap of the memory used by the parsed transactions in MiB, above it the transactions
+    /// with the lowest feerate (and their descendants) are evicted while parsing
+    #[arg(long, value_name = "MIB", conflicts_with = "strict")]
+    pub memory_cap: Option<usize>,
+
+    /// Write all artifacts of the run into this directory instead
+    #[arg(long, value_name = "DIR")]
+    pub bundle: Option<String>,
+
+    /// Write a report of every parsed transaction (txid, wtxid, weight, fee, feerate, input
+    /// types, validation verdict and reasons) to this file
+    #[arg(long, value_name = "FILE")]
+    pub tx_report: Option<String>,
+
+    /// Format of the --tx-report file
+    #[arg(long, value_enum, default_value_t = ReportFormat::Json)]
+    pub tx_report_format: ReportFormat,
+
+    /// Height of the block that is built
+    #[arg(long, default_value_t = EXERCISE_BLOCK_HEIGHT)]
+    pub height: u64,
+
+    /// Hash of the block the new block builds on (hex, display byte order)
+    #[arg(long, default_value = EXERCISE_PREVIOUS_BLOCK_HASH, value_parser = parse_block_hash)]
+    pub prev_block_hash: String,
+
+    /// Median time past of the chain tip (unix time), used for the template mintime
+    #[arg(long)]
+    pub median_time_past: Option<u32>,
+
+    /// Don't check the coinbase height and the previous block hash of the constructed block
+    /// against --height and --prev-block-hash
+    #[arg(long)]
+    pub no_context_check: bool,
+
+    /// getblocktemplate response (json) of a node, its witness commitment is verified and used
+    /// for the coinbase if it matches the locally computed one
+    #[arg(long, value_name = "FILE")]
+    pub gbt: Option<String>,
+
+    /// Template (template.json) of a previous run, the transaction selection is warm started
+    /// from its order and only re-sorts new transactions and those whose package changed
+    #[arg(long, value_name = "FILE")]
+    pub previous_template: Option<String>,
+
+    /// Simulate this many successive blocks instead of building a single block
+    #[arg(long, value_name = "N")]
+    pub simulate_blocks: Option<usize>,
+
+    /// Mempool snapshot directory whose transactions arrive over the simulated time
+    #[arg(long, value_name = "DIR")]
+    pub arrivals: Option<String>,
+
+    /// Mean transaction arrivals per second in the simulation
+    #[arg(long, default_value_t = 5.0, value_parser = parse_positive)]
+    pub arrival_rate: f64,
+
+    /// Mean seconds between simulated blocks
+    #[arg(long, default_value_t = 600.0, value_parser = parse_positive)]
+    pub block_interval: f64,
+
+    /// Seed of the random arrival and block interval process
+    #[arg(long, default_value_t = 0)]
+    pub seed: u64,
+
+    /// Directory the simulated blocks and the fee revenue time series are written to
+    #[arg(long, default_value = "../simulation")]
+    pub simulation_dir: String,
+
+    /// Keep running and rebuild the block (output.txt) whenever files of the --mempool
+    /// directory are added, changed or removed, only the changed files are parsed again
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "simulate_blocks", "core_mempool", "mempool_dat", "esplora", "psbt", "cache",
+            "strict", "memory_cap", "bundle", "tx_report", "previous_template"
+        ]
+    )]
+    pub watch: bool,
+
+    /// Seconds between the listings of the watched mempool directory, on linux file events
+    /// trigger a listing earlier
+    #[arg(long, value_name = "SECONDS", default_value_t = 2)]
+    pub watch_interval: u64,
+
+    /// Maximum milliseconds of a watched rebuild: a slower full rebuild is preceded by an
+    /// incremental block appending the new transactions to the previous template
+    #[arg(
+        long,
+        value_name = "MILLISECONDS",
+        value_parser = clap::value_parser!(u64).range(1..),
+        requires = "watch"
+    )]
+    pub rebuild_budget: Option<u64>,
+
+    /// Format of the error printed on stderr if the run fails
+    #[arg(long, value_enum, default_value_t = ErrorFormat::Text)]
+    pub error_format: ErrorFormat,
+
+    #[command(flatten)]
+    pub artifacts: ArtifactToggles,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+// text prints "Error: <message>", json the BlockBuilderError as json object
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum ErrorFormat {
+    Text,
+    Json,
+}
+
+// file format of the per-transaction report
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum ReportFormat {
+    Json,
+    Csv,
+}
+
+// handling of transactions with dust outputs
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum DustAction {
+    Report,
+    Reject,
+}
+
+// command line names of the ConflictPolicy variants
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum ConflictResolution {
+    PackageFeerate,
+    Bip125,
+    FullRbf,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Interactive shell to load, validate and mine a mempool step by step
+    Repl,
+    /// Print the input types and script features the build can verify
+    Capabilities,
+}
+
+// value parser of rates and intervals, zero, negative or infinite values give endless or
+// nonsensical poisson processes
+// returns: the number or Err(message)
+fn parse_positive(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(number) if number.is_finite() && number > 0.0 => Ok(number),
+        Ok(_) => Err("expected a finite number above 0".to_string()),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+impl Cli {
+    // returns: the DirFilter selecting the files of the mempool directories
+    pub fn dir_filter(&self) -> DirFilter {
+        DirFilter {
+            recursive: self.recursive,
+            include: self.include.clone(),
+            exclude: self.exclude.clone(),
+        }
+    }
+
+    // describes the mempool sources and the options changing the validation verdicts. The
+    // sources are summarized by a hash of the path, modification time and size of every file
+    // they are read from (the files of a directory the --include/--exclude filter selects), so
+    // files added, removed or edited in place change the key.
+    // returns: key of the --cache file
+    pub fn cache_key(&self, options: &ValidationOptions) -> String {
+        let filter = self.dir_filter();
+        let mut paths = vec![&self.mempool];
+        paths.extend(&self.core_mempool);
+        paths.extend(&self.core_transactions);
+        paths.extend(&self.mempool_dat);
+        paths.extend(&self.prevouts);
+        paths.extend(&self.psbt);
+        let mut sources = Sha256::new();
+        for path in paths {
+            // unreadable sources fail the loading after the key is computed
+            let mut states = file_states(path, &filter).unwrap_or_default();
+            states.sort_by(|a, b| a.0.cmp(&b.0));
+            for (file, state) in states {
+                let modified = state
+                    .modified
+                    .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                    .map(|duration| duration.as_nanos());
+                sources.update(format!(
+                    "{} {:?} {}\n",
+                    file.display(),
+                    modified,
+                    state.size
+                ));
+            }
+        }
+        format!(
+            "{:x} {:?} {} {:?} {} {:?}",
+            sources.finalize(),
+            filter,
+            self.strict,
+            self.memory_cap,
+            self.all_reasons,
+            options
+        )
+    }
+
+    // returns: the ChainContext described by the command line options
+    pub fn chain_context(&self) -> ChainContext {
+        ChainContext {
+            height: self.height,
+            previous_block_hash: self.prev_block_hash.clone(),
+            median_time_past: self.median_time_past,
+            bits: EXERCISE_BITS,
+            node_witness: None,
+            coinbase_heights: HashMap::new(),
+        }
+    }
+
+    // returns: the MempoolPolicy described by the command line options
+    pub fn mempool_policy(&self) -> MempoolPolicy {
+        MempoolPolicy {
+            conflict_policy: match self.conflict_policy {
+                ConflictResolution::PackageFeerate => ConflictPolicy::PackageFeerate,
+                ConflictResolution::Bip125 => ConflictPolicy::Bip125,
+                ConflictResolution::FullRbf => ConflictPolicy::FullRbf,
+            },
+            relay_feerates: RelayFeerates {
+                min_relay: FeeRate::from_sat_per_kvb(self.min_relay_feerate),
+                incremental_relay: FeeRate::from_sat_per_kvb(self.incremental_relay_feerate),
+                package_floor: FeeRate::from_sat_per_kvb(self.package_feerate_floor),
+            },
+            package_limits: PackageLimits {
+                ancestor_count: self.limit_ancestor_count,
+                ancestor_vsize: self.limit_ancestor_size,
+                descendant_count: self.limit_descendant_count,
+                descendant_vsize: self.limit_descendant_size,
+            },
+        }
+    }
+}
+
+// artifacts written into the bundle directory, all enabled by default
+#[derive(Args, Debug)]
+pub struct ArtifactToggles {
+    /// Don't write output.txt into the bundle
+    #[arg(long)]
+    pub no_output_txt: bool,
+
+    /// Don't write the raw block as block.bin into the bundle
+    #[arg(long)]
+    pub no_block_bin: bool,
+
+    /// Don't write the raw block as block.hex into the bundle
+    #[arg(long)]
+    pub no_block_hex: bool,
+
+    /// Don't write the getblocktemplate like template.json into the bundle
+    #[arg(long)]
+    pub no_template: bool,
+
+    /// Don't write block_layout.json into the bundle
+    #[arg(long)]
+    pub no_layout: bool,
+
+    /// Don't write validation_report.json into the bundle
+    #[arg(long)]
+    pub no_report: bool,
+
+    /// Don't write stats.csv into the bundle
+    #[arg(long)]
+    pub no_stats: bool,
+
+    /// Don't write the transaction dependency graph dependencies.dot into the bundle
+    #[arg(long)]
+    pub no_dot: bool,
+}
@@ -0,0 +1,119 @@
+// Failures of a builder run. Each kind has its own process exit code, so scripts wrapping the
+// builder can react to it, and can be printed as json (--error-format json).
+// The codes start at 10 as clap exits with 2 on invalid command line arguments.
+
+use crate::mining::MiningError;
+use crate::parsing::ParseError;
+use serde_json::{json, Value};
+use std::fmt;
+
+pub const EXIT_FAILURE: i32 = 1;
+pub const EXIT_PARSE: i32 = 10;
+pub const EXIT_NO_VALID_TRANSACTIONS: i32 = 11;
+pub const EXIT_INVARIANT_VIOLATION: i32 = 12;
+pub const EXIT_IO: i32 = 13;
+pub const EXIT_RPC: i32 = 14;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BlockBuilderError {
+    Parse(ParseError),                    // unreadable mempool dir or invalid node dump
+    NoValidTransactions(usize),           // none of the parsed transactions is valid
+    InvariantViolation(Vec<String>),      // the constructed block violates block invariants
+    Io { path: String, message: String }, // reading an input or writing an artifact failed
+    Rpc(String),                          // a node rpc call failed
+    InvalidInput(String),                 // invalid --gbt, --previous-template or simulation input
+    Mining(MiningError),                  // the selected transactions produce an invalid block
+}
+
+impl BlockBuilderError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            BlockBuilderError::Parse(ParseError::Io { .. }) => EXIT_IO,
+            BlockBuilderError::Parse(_) => EXIT_PARSE,
+            BlockBuilderError::NoValidTransactions(_) => EXIT_NO_VALID_TRANSACTIONS,
+            BlockBuilderError::InvariantViolation(_) => EXIT_INVARIANT_VIOLATION,
+            BlockBuilderError::Io { .. } => EXIT_IO,
+            BlockBuilderError::Rpc(_) => EXIT_RPC,
+            BlockBuilderError::InvalidInput(_) | BlockBuilderError::Mining(_) => EXIT_FAILURE,
+        }
+    }
+
+    // returns: name of the error kind used in the json output
+    pub fn kind(&self) -> &'static str {
+        match self {
+            BlockBuilderError::Parse(ParseError::Io { .. }) => "io",
+            BlockBuilderError::Parse(_) => "parse",
+            BlockBuilderError::NoValidTransactions(_) => "no_valid_transactions",
+            BlockBuilderError::InvariantViolation(_) => "invariant_violation",
+            BlockBuilderError::Io { .. } => "io",
+            BlockBuilderError::Rpc(_) => "rpc",
+            BlockBuilderError::InvalidInput(_) => "invalid_input",
+            BlockBuilderError::Mining(_) => "mining",
+        }
+    }
+
+    // returns: {"kind", "exit_code", "message"} plus the path of io and parse errors and the
+    // violations of an invariant violation
+    pub fn to_json(&self) -> Value {
+        let mut value = json!({
+            "kind": self.kind(),
+            "exit_code": self.exit_code(),
+            "message": self.to_string(),
+        });
+        match self {
+            BlockBuilderError::Parse(
+                ParseError::Io { path, .. }
+                | ParseError::InvalidJson { path, .. }
+                | ParseError::InvalidHex { path, .. },
+            )
+            | BlockBuilderError::Io { path, .. } => value["path"] = json!(path),
+            BlockBuilderError::InvariantViolation(violations) => {
+                value["violations"] = json!(violations)
+            }
+            _ => {}
+        }
+        value
+    }
+}
+
+impl fmt::Display for BlockBuilderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BlockBuilderError::Parse(err) => write!(f, "parsing the mempool failed: {}", err),
+            BlockBuilderError::NoValidTransactions(parsed) => {
+                write!(f, "none of the {} parsed transactions is valid", parsed)
+            }
+            BlockBuilderError::InvariantViolation(violations) => write!(
+                f,
+                "constructed block violates invariants: {}",
+                violations.join(", ")
+            ),
+            BlockBuilderError::Io { path, message } => write!(f, "{}: {}", path, message),
+            BlockBuilderError::Rpc(message) => write!(f, "rpc call failed: {}", message),
+            BlockBuilderError::InvalidInput(message) => write!(f, "{}", message),
+            BlockBuilderError::Mining(err) => write!(f, "no valid block constructed: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for BlockBuilderError {}
+
+impl From<ParseError> for BlockBuilderError {
+    fn from(err: ParseError) -> Self {
+        BlockBuilderError::Parse(err)
+    }
+}
+
+impl From<MiningError> for BlockBuilderError {
+    fn from(err: MiningError) -> Self {
+        BlockBuilderError::Mining(err)
+    }
+}
+
+// returns: closure converting an io error of the path into BlockBuilderError::Io
+pub fn io_error(path: &std::path::Path) -> impl Fn(std::io::Error) -> BlockBuilderError + '_ {
+    move |err| BlockBuilderError::Io {
+        path: path.display().to_string(),
+        message: err.to_string(),
+    }
+}
@@ -0,0 +1,78 @@
+// Fixed point feerates in sat per 1000 weight units (sat/kWU). Dividing the fee by the weight
+// in whole sat/WU collapses almost all mempool feerates to 0 or 1, the 1000 fold resolution
+// keeps the ordering of the transactions and packages intact.
+
+use std::fmt;
+
+pub const WITNESS_SCALE_FACTOR: u64 = 4; // weight units per vbyte
+
+// a started vbyte counts fully like in bitcoin core, flooring overestimates the feerate
+// returns: virtual size in vbytes of the weight
+pub fn vsize(weight: u64) -> u64 {
+    weight.div_ceil(WITNESS_SCALE_FACTOR)
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FeeRate(u64); // sat/kWU
+
+impl FeeRate {
+    pub const ZERO: FeeRate = FeeRate(0);
+    // minimum relay feerate of 1 sat/vB
+    pub const MIN_RELAY: FeeRate = FeeRate::from_sat_per_vb(1);
+
+    pub const fn from_sat_per_kwu(sat_per_kwu: u64) -> FeeRate {
+        FeeRate(sat_per_kwu)
+    }
+
+    pub const fn from_sat_per_vb(sat_per_vb: u64) -> FeeRate {
+        FeeRate(sat_per_vb * 1000 / WITNESS_SCALE_FACTOR)
+    }
+
+    // rounds up to the next sat/kWU so a configured floor is never lowered
+    pub const fn from_sat_per_kvb(sat_per_kvb: u64) -> FeeRate {
+        FeeRate(sat_per_kvb.div_ceil(WITNESS_SCALE_FACTOR))
+    }
+
+    // rounds down to the next sat/kWU, zero weight counts as 1 weight unit
+    // returns: FeeRate of paying fee for weight
+    pub fn from_fee_weight(fee: u64, weight: u64) -> FeeRate {
+        let sat_per_kwu = fee as u128 * 1000 / weight.max(1) as u128;
+        FeeRate(sat_per_kwu.min(u64::MAX as u128) as u64)
+    }
+
+    // rounds down to the next sat/kWU, zero vsize counts as 1 vbyte
+    // returns: FeeRate of paying fee for vsize vbytes
+    pub fn from_fee_vsize(fee: u64, vsize: u64) -> FeeRate {
+        FeeRate::from_fee_weight(fee, vsize.max(1).saturating_mul(WITNESS_SCALE_FACTOR))
+    }
+
+    pub fn to_sat_per_kwu(self) -> u64 {
+        self.0
+    }
+}
+
+// feerate floors of the relay and mining policy, bitcoin core's defaults are 1 sat/vB each
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RelayFeerates {
+    pub min_relay: FeeRate, // transactions paying less are invalid (not relayed)
+    pub incremental_relay: FeeRate, // a BIP125 replacement pays it on top for its own size
+    pub package_floor: FeeRate, // packages paying less aren't selected into the block
+}
+
+impl Default for RelayFeerates {
+    fn default() -> Self {
+        RelayFeerates {
+            min_relay: FeeRate::MIN_RELAY,
+            incremental_relay: FeeRate::MIN_RELAY,
+            package_floor: FeeRate::MIN_RELAY,
+        }
+    }
+}
+
+// formats the feerate in sat/vB with 3 decimals (e.g. "12.345 sat/vB")
+impl fmt::Display for FeeRate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sat_per_kvb = self.0 as u128 * WITNESS_SCALE_FACTOR as u128;
+        write!(f, "{}.{:03} sat/vB", sat_per_kvb / 1000, sat_per_kvb % 1000)
+    }
+}
@@ -0,0 +1,11 @@
+pub mod address;
+pub mod amount;
+pub mod chain_context;
+pub mod error;
+pub mod feerate;
+pub mod mempool_policy;
+pub mod mining;
+pub mod parsing;
+#[cfg(feature = "regtest")]
+pub mod regtest;
+pub mod validation;
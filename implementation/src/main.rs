@@ -1,68 +1,994 @@
-pub mod mining;
-pub mod parsing;
+mod cli;
+mod output;
+mod repl;
+mod tx_report;
 mod utils_main;
-pub mod validation;
 
-use mining::{mine_block, Block};
-use parsing::{parse_transactions_from_dir, transaction_structs::Transaction};
-use std::collections::HashSet;
-use std::fs::File;
-use std::io::prelude::*;
+use bitcoin_block_builder::amount::format_btc;
+use bitcoin_block_builder::chain_context::{ChainContext, NodeWitnessData};
+use bitcoin_block_builder::error::{io_error, BlockBuilderError};
+use bitcoin_block_builder::mempool_policy::MempoolPolicy;
+use bitcoin_block_builder::mining::block_template::{
+    build_block_template, parse_node_witness_data, BlockTemplate,
+};
+use bitcoin_block_builder::mining::construct_coinbase::{WitnessCommitmentSource, BLOCK_SUBSIDY};
+use bitcoin_block_builder::mining::invariants::Limits;
+use bitcoin_block_builder::mining::rebuilds::Rebuilds;
+use bitcoin_block_builder::mining::simulation::{simulate_blocks, SimulationConfig};
+use bitcoin_block_builder::mining::warm_start::{append_to_template, SelectionHint};
+use bitcoin_block_builder::mining::{mine_block, prepare_ordered_block, Block};
+use bitcoin_block_builder::parsing::cache::{read_cache, write_cache, CacheError, CachedSnapshot};
+use bitcoin_block_builder::parsing::core_mempool::{core_mempool_transactions, parse_core_mempool};
+use bitcoin_block_builder::parsing::dir_filter::DirFilter;
+use bitcoin_block_builder::parsing::esplora::EsploraClient;
+use bitcoin_block_builder::parsing::mempool_dat::{mempool_dat_transactions, parse_mempool_dat};
+use bitcoin_block_builder::parsing::psbt::parse_psbt_file;
+use bitcoin_block_builder::parsing::raw::Prevout;
+use bitcoin_block_builder::parsing::watch::MempoolWatcher;
+use bitcoin_block_builder::parsing::{
+    parse_transactions_from_dir_capped, parse_transactions_from_dir_skipping,
+    parse_transactions_from_dir_strict, parse_transactions_from_file, strict::Inconsistency,
+    transaction_structs::Transaction, ParseError, ParsedSnapshot,
+};
+use bitcoin_block_builder::validation::includability::{
+    classify_includable, IncludabilitySets, PendingTransaction,
+};
+use bitcoin_block_builder::validation::script_flags::ScriptFlags;
+use bitcoin_block_builder::validation::validate_values::{
+    find_dust_output, validate_sequence_locks_final, FinalityTarget,
+};
+use bitcoin_block_builder::validation::weight_stats::weight_stats;
+use bitcoin_block_builder::validation::{capabilities, ValidationError, ValidationOptions};
+use clap::Parser;
+use cli::{Cli, Command, DustAction, ErrorFormat};
+use output::{
+    output_block, output_block_layout, output_bundle, output_simulation, ReportEntry, RunStats,
+};
+use repl::run_repl;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use std::process;
+use std::time::Duration;
+use tx_report::{transaction_rows, write_transaction_report};
 use utils_main::remove_invalid_transactions;
-use validation::ValidationResult;
 
-// writes the final content stored in the Block struct to the passed output_path
-// as output.txt formatted according to the exercise specification
-fn output_block(mined_block: &Block, output_path: &str) {
-    let mut output_file = File::create(output_path).expect("Unable to create output file");
+// validates the transaction, with all_reasons every failed check is collected
+// returns: the rejection reason or None if the transaction is valid
+fn validate_transaction(
+    tx: &mut Transaction,
+    options: &ValidationOptions,
+    all_reasons: bool,
+) -> Option<String> {
+    if all_reasons {
+        let failures: Vec<String> = tx
+            .validate_all(options)
+            .iter()
+            .map(|error| error.to_string())
+            .collect();
+        return (!failures.is_empty()).then(|| failures.join("; "));
+    }
+    tx.validate(options).err().map(|error| error.to_string())
+}
+
+// BIP68 locks depend on the mempool, outputs of the passed transactions confirm in the block.
+// Transactions that are already invalid keep their reason.
+fn reject_sequence_locked(
+    transactions: &[Transaction],
+    target: &FinalityTarget,
+    invalid_transactions: &mut HashMap<String, String>,
+) {
+    let txids: HashSet<&str> = transactions
+        .iter()
+        .map(|tx| tx.meta.txid_hex.as_str())
+        .collect();
+    for tx in transactions {
+        if !invalid_transactions.contains_key(&tx.meta.txid_hex)
+            && !validate_sequence_locks_final(tx, target, |txid| txids.contains(txid))
+        {
+            invalid_transactions.insert(
+                tx.meta.txid_hex.clone(),
+                ValidationError::RelativeLocktime {
+                    height: target.height,
+                }
+                .to_string(),
+            );
+        }
+    }
+}
+
+// calls validate() with the passed ValidationOptions on each Transaction in the passed Vec of Transaction,
+// with ValidationOptions::finality also the BIP68 relative locktimes are checked. With all_reasons
+// validate_all() is called and the reasons of all failed checks are joined with "; ".
+// returns: HashMap(txid as hex String, reason String) of all invalid and untested transactions
+fn validate_transactions(
+    parsed_transactions: &mut [Transaction],
+    options: &ValidationOptions,
+    all_reasons: bool,
+) -> HashMap<String, String> {
+    let mut invalid_transactions: HashMap<String, String> = HashMap::new();
+
+    for tx in parsed_transactions.iter_mut() {
+        if let Some(reason) = validate_transaction(tx, options, all_reasons) {
+            invalid_transactions.insert(tx.meta.txid_hex.clone(), reason);
+        }
+    }
+    if let Some(target) = &options.finality {
+        reject_sequence_locked(parsed_transactions, target, &mut invalid_transactions);
+    }
+    invalid_transactions
+}
+
+// parses all json transactions of the directory, in strict mode the redundant json data
+// is cross-checked and each Inconsistency is printed. With a memory cap (MiB) the lowest
+// feerate transactions are evicted while parsing. Unparsable files are skipped. A json file
+// instead of the directory contains the whole snapshot.
+// returns: ParsedSnapshot or Err(BlockBuilderError::Parse) if the directory isn't readable
+fn parse_snapshot(
+    directory_path: &str,
+    filter: &DirFilter,
+    strict: bool,
+    memory_cap: Option<usize>,
+) -> Result<ParsedSnapshot, BlockBuilderError> {
+    if Path::new(directory_path).is_file() {
+        if strict || memory_cap.is_some() {
+            return Err(BlockBuilderError::InvalidInput(format!(
+                "{}: --strict and --memory-cap need a mempool directory",
+                directory_path
+            )));
+        }
+        return Ok(parse_transactions_from_file(directory_path)?);
+    }
+    if let Some(memory_cap) = memory_cap {
+        let snapshot =
+            parse_transactions_from_dir_capped(directory_path, filter, memory_cap * 1024 * 1024)?;
+        if !snapshot.evicted.is_empty() {
+            println!(
+                "Memory cap: evicted {} transactions ({} bytes), keeping {} ({} bytes)",
+                snapshot.evicted.len(),
+                snapshot
+                    .evicted
+                    .iter()
+                    .map(|tx| tx.memory_size)
+                    .sum::<usize>(),
+                snapshot.transactions.len(),
+                snapshot
+                    .transactions
+                    .iter()
+                    .map(|tx| tx.meta.memory_size)
+                    .sum::<usize>()
+            );
+        }
+        return Ok(snapshot);
+    }
+    if !strict {
+        return Ok(parse_transactions_from_dir_skipping(
+            directory_path,
+            filter,
+        )?);
+    }
+    let snapshot = parse_transactions_from_dir_strict(directory_path, filter)?;
+    for inconsistency in &snapshot.inconsistencies {
+        println!("Strict parsing: {}", inconsistency);
+    }
+    Ok(snapshot)
+}
+
+// prints the summary of the files skipped while parsing
+fn print_parse_errors(errors: &[ParseError]) {
+    if errors.is_empty() {
+        return;
+    }
+    println!("Skipped {} unparsable files:", errors.len());
+    for err in errors {
+        println!("  {}", err);
+    }
+}
+
+// parses the mempool source of the command line, the --mempool directory if no node dump is given
+// returns: ParsedSnapshot (inconsistencies and skipped files only for the directory)
+// or Err(BlockBuilderError)
+fn load_mempool(cli: &Cli) -> Result<ParsedSnapshot, BlockBuilderError> {
+    let dump = match (&cli.core_mempool, &cli.core_transactions) {
+        (Some(mempool_path), Some(transactions_path)) => {
+            Some(load_core_mempool(mempool_path, transactions_path)?)
+        }
+        _ => match (&cli.mempool_dat, &cli.esplora) {
+            (Some(path), _) => Some(load_mempool_dat(path, cli.prevouts.as_deref())?),
+            (None, Some(url)) => Some(load_esplora(
+                url,
+                cli.esplora_limit,
+                Duration::from_secs(cli.esplora_timeout),
+            )?),
+            (None, None) => None,
+        },
+    };
+    let mut snapshot = match dump {
+        Some(transactions) => ParsedSnapshot {
+            transactions,
+            ..Default::default()
+        },
+        None => parse_snapshot(&cli.mempool, &cli.dir_filter(), cli.strict, cli.memory_cap)?,
+    };
+    for path in &cli.psbt {
+        let psbt_transactions = load_psbt(path, &snapshot.transactions)?;
+        snapshot.transactions.extend(psbt_transactions);
+    }
+    Ok(snapshot)
+}
+
+// parses and validates the mempool, with --cache the validated transactions are loaded from
+// the cache file if its key matches and written to it otherwise
+// returns: transactions with their rejection reasons and the skipped files (none if cached)
+// or Err(BlockBuilderError)
+fn load_validated_mempool(
+    cli: &Cli,
+    options: &ValidationOptions,
+) -> Result<(CachedSnapshot, Vec<ParseError>), BlockBuilderError> {
+    let cache = cli
+        .cache
+        .as_ref()
+        .map(|path| (path, cli.cache_key(options)));
+    let cache_error = |path: &str| {
+        let path = path.to_string();
+        move |err: CacheError| BlockBuilderError::InvalidInput(format!("{}: {}", path, err))
+    };
+    if let Some((path, key)) = &cache {
+        if let Some(snapshot) = read_cache(Path::new(path), key).map_err(cache_error(path))? {
+            println!(
+                "Loaded {} validated transactions from the cache {}",
+                snapshot.transactions.len(),
+                path
+            );
+            return Ok((snapshot, Vec::new()));
+        }
+    }
+
+    let ParsedSnapshot {
+        mut transactions,
+        inconsistencies,
+        skipped,
+        ..
+    } = load_mempool(cli)?;
+    let mut invalid = validate_transactions(&mut transactions, options, cli.all_reasons);
+    reject_inconsistent(&transactions, &inconsistencies, &mut invalid);
+    let snapshot = CachedSnapshot {
+        transactions,
+        invalid,
+    };
+    if let Some((path, key)) = &cache {
+        write_cache(Path::new(path), key, &snapshot).map_err(cache_error(path))?;
+    }
+    Ok((snapshot, skipped))
+}
+
+// marks the transactions of the files with an Inconsistency as invalid, has to be called
+// after validation as the txids are calculated there. Earlier rejection reasons are kept.
+fn reject_inconsistent(
+    transactions: &[Transaction],
+    inconsistencies: &[Inconsistency],
+    invalid_transactions: &mut HashMap<String, String>,
+) {
+    for inconsistency in inconsistencies {
+        if let Some(tx) = transactions
+            .iter()
+            .find(|tx| tx.meta.json_path.as_deref() == Some(inconsistency.file.as_str()))
+        {
+            invalid_transactions
+                .entry(tx.meta.txid_hex.clone())
+                .or_insert(format!(
+                    "Strict parsing: {}: {}",
+                    inconsistency.field, inconsistency.message
+                ));
+        }
+    }
+}
 
-    writeln!(output_file, "{}", mined_block.header_hex).expect("Unable to write to file");
-    writeln!(output_file, "{}", mined_block.coinbase_tx_hex).expect("Unable to write to file");
+// finds the valid transactions with an output below the dust threshold and prints their number
+// returns: HashMap txid -> description of the first dust output
+fn report_dust(transactions: &[&Transaction], dust_relay_feerate: u64) -> HashMap<String, String> {
+    let dust: HashMap<String, String> = transactions
+        .iter()
+        .filter_map(|tx| {
+            find_dust_output(tx, dust_relay_feerate).map(|(index, threshold)| {
+                let note = format!(
+                    "Output {} value {} below dust threshold {}",
+                    index, tx.vout[index].value, threshold
+                );
+                (tx.meta.txid_hex.clone(), note)
+            })
+        })
+        .collect();
+    if !dust.is_empty() {
+        println!("{} valid transactions have dust outputs", dust.len());
+    }
+    dust
+}
 
-    let len = mined_block.txids_hex.len();
-    for (index, tx) in mined_block.txids_hex.iter().enumerate() {
-        if index < len - 1 {
-            writeln!(output_file, "{}", tx).expect("Unable to write to file");
+// creates a ReportEntry for each parsed txid. Transactions that passed validation but got
+// removed afterwards have an invalid mempool parent. Relay-valid transactions are valid with
+// the reason they can't be included in the block yet as pending. Reported dust outputs of valid
+// transactions are noted as dust.
+fn validation_report(
+    parsed_txids: Vec<String>,
+    invalid_transactions: &HashMap<String, String>,
+    block_valid: &HashMap<String, Transaction>,
+    relay_valid: &HashMap<String, PendingTransaction>,
+    dust: &HashMap<String, String>,
+) -> Vec<ReportEntry> {
+    let mut report = Vec::new();
+
+    for txid in parsed_txids {
+        let pending = relay_valid
+            .get(&txid)
+            .map(|pending| pending.reason.to_string());
+        let reason = if let Some(reason) = invalid_transactions.get(&txid) {
+            Some(reason.clone())
+        } else if !block_valid.contains_key(&txid) && pending.is_none() {
+            Some(ValidationError::InvalidParent.to_string())
         } else {
-            write!(output_file, "{}", tx).expect("Unable to write to file");
+            None
+        };
+        report.push(ReportEntry {
+            dust: dust.get(&txid).cloned(),
+            txid,
+            valid: reason.is_none(),
+            reason,
+            pending,
+        });
+    }
+    report
+}
+
+// parses and validates the mempool snapshot and the arrivals snapshot, then simulates the
+// passed amount of blocks and writes the blocks and fee revenue time series
+// returns: (mined transactions, collected fees in sat) of all simulated blocks
+fn run_simulation(
+    cli: &Cli,
+    blocks: usize,
+    options: &ValidationOptions,
+    chain_context: &ChainContext,
+    policy: &MempoolPolicy,
+) -> Result<(usize, u64), BlockBuilderError> {
+    let ParsedSnapshot {
+        transactions: mut parsed_transactions,
+        inconsistencies,
+        skipped: mut parse_errors,
+        ..
+    } = load_mempool(cli)?;
+    let ParsedSnapshot {
+        transactions: mut arrival_transactions,
+        inconsistencies: arrival_inconsistencies,
+        skipped: arrival_errors,
+        ..
+    } = match &cli.arrivals {
+        Some(arrivals_dir) => {
+            parse_snapshot(arrivals_dir, &cli.dir_filter(), cli.strict, cli.memory_cap)?
+        }
+        None => ParsedSnapshot::default(),
+    };
+    parse_errors.extend(arrival_errors);
+    let parsed_count = parsed_transactions.len() + arrival_transactions.len();
+    let mut invalid_transactions =
+        validate_transactions(&mut parsed_transactions, options, cli.all_reasons);
+    invalid_transactions.extend(validate_transactions(
+        &mut arrival_transactions,
+        options,
+        cli.all_reasons,
+    ));
+    reject_inconsistent(
+        &parsed_transactions,
+        &inconsistencies,
+        &mut invalid_transactions,
+    );
+    reject_inconsistent(
+        &arrival_transactions,
+        &arrival_inconsistencies,
+        &mut invalid_transactions,
+    );
+    let arrival_txids: HashSet<String> = arrival_transactions
+        .iter()
+        .map(|tx| tx.meta.txid_hex.clone())
+        .collect();
+
+    // children of invalid transactions are removed across both snapshots
+    parsed_transactions.extend(arrival_transactions);
+    let valid_transactions = remove_invalid_transactions(
+        parsed_transactions,
+        invalid_transactions.keys().cloned().collect(),
+    );
+    if valid_transactions.is_empty() {
+        print_parse_errors(&parse_errors);
+        return Err(BlockBuilderError::NoValidTransactions(parsed_count));
+    }
+    let (arrivals, mempool): (Vec<Transaction>, Vec<Transaction>) = valid_transactions
+        .into_values()
+        .partition(|tx| arrival_txids.contains(&tx.meta.txid_hex));
+
+    let config = SimulationConfig {
+        blocks,
+        arrival_rate: cli.arrival_rate,
+        block_interval: cli.block_interval,
+        seed: cli.seed,
+    };
+    let mempool = mempool
+        .into_iter()
+        .map(|tx| (tx.meta.txid_hex.clone(), tx))
+        .collect();
+    let steps = simulate_blocks(mempool, arrivals, chain_context, policy, &config)?;
+    output_simulation(Path::new(&cli.simulation_dir), &steps, chain_context)?;
+    print_parse_errors(&parse_errors);
+    Ok((
+        steps.iter().map(|step| step.block.transactions.len()).sum(),
+        steps.iter().map(|step| step.fees).sum(),
+    ))
+}
+
+// checks the invariants of the constructed block and, with context_check, its chain context
+// returns: Err(BlockBuilderError::InvariantViolation) listing all violations
+fn check_block(
+    block: &Block,
+    context_check: bool,
+    chain_context: &ChainContext,
+) -> Result<(), BlockBuilderError> {
+    let mut violations = block.check_invariants(&Limits::default());
+    if context_check {
+        violations.extend(block.check_chain_context(chain_context));
+    }
+    if !violations.is_empty() {
+        return Err(BlockBuilderError::InvariantViolation(
+            violations
+                .iter()
+                .map(|violation| violation.to_string())
+                .collect(),
+        ));
+    }
+    Ok(())
+}
+
+// removes the invalid transactions with their descendants and splits the valid ones into the
+// block-valid and the not yet includable relay-valid transactions
+fn includable_transactions(
+    transactions: Vec<Transaction>,
+    invalid_transactions: &HashMap<String, String>,
+    chain_context: &ChainContext,
+) -> IncludabilitySets {
+    let valid_transactions =
+        remove_invalid_transactions(transactions, invalid_transactions.keys().cloned().collect());
+    classify_includable(valid_transactions, &HashMap::new(), chain_context)
+}
+
+// mines the block-valid transactions under the MempoolPolicy and self-verifies the block, a block
+// violating invariants isn't returned. The optional SelectionHint warm starts the selection.
+// returns: the Block or Err(BlockBuilderError)
+fn build_block(
+    block_valid: &mut HashMap<String, Transaction>,
+    chain_context: &ChainContext,
+    policy: &MempoolPolicy,
+    hint: Option<&SelectionHint>,
+    context_check: bool,
+) -> Result<Block, BlockBuilderError> {
+    let block = mine_block(block_valid, chain_context, policy, hint)?;
+    if let WitnessCommitmentSource::Mismatch { node } = &block.coinbase_tx.witness_commitment_source
+    {
+        println!(
+            "Warning: node witness commitment {} doesn't match the local commitment {}, using the local one",
+            node,
+            hex::encode(&block.coinbase_tx.witness_commitment_scriptpubkey)
+        );
+    }
+    check_block(&block, context_check, chain_context)?;
+    Ok(block)
+}
+
+// collects the block-valid transactions of the watched mempool, the relative locktimes are
+// checked against the whole mempool as it changes between builds
+// returns: block-valid transactions or Err(BlockBuilderError) if there are none
+fn watched_block_valid(
+    mempool: &HashMap<String, Vec<(Transaction, Option<String>)>>,
+    options: &ValidationOptions,
+    chain_context: &ChainContext,
+) -> Result<HashMap<String, Transaction>, BlockBuilderError> {
+    let transactions: Vec<Transaction> = mempool
+        .values()
+        .flatten()
+        .map(|(tx, _)| tx.clone())
+        .collect();
+    let mut invalid_transactions: HashMap<String, String> = mempool
+        .values()
+        .flatten()
+        .filter_map(|(tx, reason)| Some((tx.meta.txid_hex.clone(), reason.clone()?)))
+        .collect();
+    if let Some(target) = &options.finality {
+        reject_sequence_locked(&transactions, target, &mut invalid_transactions);
+    }
+    let block_valid =
+        includable_transactions(transactions, &invalid_transactions, chain_context).block_valid;
+    if block_valid.is_empty() {
+        return Err(BlockBuilderError::NoValidTransactions(transactions_count(
+            mempool,
+        )));
+    }
+    Ok(block_valid)
+}
+
+// full rebuild of a watched block on a background thread, warm started from the previous
+// template. It replaces the pending rebuild.
+fn spawn_watched_rebuild(
+    rebuilds: &mut Rebuilds<Result<Block, BlockBuilderError>>,
+    mut block_valid: HashMap<String, Transaction>,
+    previous: Option<&BlockTemplate>,
+    chain_context: &ChainContext,
+    policy: &MempoolPolicy,
+    context_check: bool,
+) {
+    let hint = previous.map(SelectionHint::from_template);
+    let (chain_context, policy) = (chain_context.clone(), policy.clone());
+    rebuilds.spawn(move || {
+        build_block(
+            &mut block_valid,
+            &chain_context,
+            &policy,
+            hint.as_ref(),
+            context_check,
+        )
+    });
+}
+
+// appends the new transactions to the previous template (append_to_template()) for a rebuild
+// exceeding its latency budget and self-verifies the block like build_block()
+// returns: the Block or Err(BlockBuilderError)
+fn build_incremental_block(
+    mut block_valid: HashMap<String, Transaction>,
+    previous: &BlockTemplate,
+    chain_context: &ChainContext,
+    context_check: bool,
+) -> Result<Block, BlockBuilderError> {
+    let block_ordered = append_to_template(&mut block_valid, previous);
+    let block = prepare_ordered_block(block_ordered, None, chain_context)?.mine();
+    check_block(&block, context_check, chain_context)?;
+    Ok(block)
+}
+
+// writes output.txt and block_layout.json of a watched block and prints its summary
+// returns: the template of the block for the next rebuild or Err(BlockBuilderError)
+fn write_watched_block(
+    cli: &Cli,
+    block: &Block,
+    chain_context: &ChainContext,
+    kind: &str,
+) -> Result<BlockTemplate, BlockBuilderError> {
+    let output_path = Path::new(&cli.output);
+    output_block(block, output_path)?;
+    output_block_layout(block, &output_path.with_file_name("block_layout.json"))?;
+    println!(
+        "{} block: {} transactions, fees: {} BTC",
+        kind,
+        block.txids_hex.len(),
+        format_btc(block.coinbase_tx.reward - BLOCK_SUBSIDY)
+    );
+    Ok(build_block_template(block, chain_context))
+}
+
+// writes the block of a finished full rebuild
+// returns: its template for the next rebuild or None if the rebuild failed (printed)
+fn finish_watched_rebuild(
+    cli: &Cli,
+    block: Result<Block, BlockBuilderError>,
+    chain_context: &ChainContext,
+) -> Option<BlockTemplate> {
+    match block.and_then(|block| write_watched_block(cli, &block, chain_context, "Rebuilt")) {
+        Ok(template) => Some(template),
+        Err(err) => {
+            println!("No block built: {}", err);
+            None
         }
     }
 }
 
-// calls validate() on each Transaction in the passed Vec of Transaction
-// returns: HashSet(txid as hex String) of all invalid and untested transactions
-fn validate_transactions(parsed_transactions: &mut Vec<Transaction>) -> HashSet<String> {
-    let mut invalid_transactions: HashSet<String> = HashSet::new();
+// returns: number of transactions of the watched mempool
+fn transactions_count(mempool: &HashMap<String, Vec<(Transaction, Option<String>)>>) -> usize {
+    mempool.values().map(Vec::len).sum()
+}
+
+// live template mode: lists the mempool directory when file events arrive, at the latest every
+// --watch-interval seconds, and rebuilds the block whenever files were added, changed or
+// removed. Only the transactions of the changed files are parsed and validated again, a failed
+// build is printed and the next change awaited. The full rebuild runs on a background thread:
+// if it takes longer than --rebuild-budget, an incremental block appending the new
+// transactions to the previous template is written meanwhile and replaced by the full one.
+// returns: only Err(BlockBuilderError) if the mempool directory isn't readable
+fn run_watch(
+    cli: &Cli,
+    options: &ValidationOptions,
+    chain_context: &ChainContext,
+    policy: &MempoolPolicy,
+) -> Result<(), BlockBuilderError> {
+    if !Path::new(&cli.mempool).is_dir() {
+        return Err(BlockBuilderError::InvalidInput(format!(
+            "{}: --watch needs a mempool directory",
+            cli.mempool
+        )));
+    }
+    let mut watcher = MempoolWatcher::new(&cli.mempool, cli.dir_filter());
+    let interval = Duration::from_secs(cli.watch_interval);
+    let context_check = !cli.no_context_check;
+    // file path -> transactions of the file and their rejection reasons
+    let mut mempool: HashMap<String, Vec<(Transaction, Option<String>)>> = HashMap::new();
+    let mut previous: Option<BlockTemplate> = None;
+    let mut rebuilds = Rebuilds::new();
+    let mut first_poll = true;
 
-    for tx in parsed_transactions {
-        match tx.validate() {
-            ValidationResult::Valid => {}
-            ValidationResult::Invalid(_) => {
-                invalid_transactions.insert(tx.meta.txid_hex.clone());
+    loop {
+        let changes = watcher.poll()?;
+        if first_poll || !changes.is_empty() {
+            first_poll = false;
+            for path in &changes.removed {
+                mempool.remove(path);
             }
+            let added = changes.added.len();
+            for (path, mut tx) in changes.added {
+                let reason = validate_transaction(&mut tx, options, cli.all_reasons);
+                mempool.entry(path).or_default().push((tx, reason));
+            }
+            print_parse_errors(&changes.skipped);
+            println!(
+                "Mempool changed: {} transactions added or changed, {} files removed, {} transactions",
+                added,
+                changes.removed.len(),
+                transactions_count(&mempool)
+            );
+            match watched_block_valid(&mempool, options, chain_context) {
+                Ok(block_valid) => {
+                    // the incremental block needs the previous template
+                    let incremental = cli
+                        .rebuild_budget
+                        .filter(|_| previous.is_some())
+                        .map(|budget| (Duration::from_millis(budget), block_valid.clone()));
+                    spawn_watched_rebuild(
+                        &mut rebuilds,
+                        block_valid,
+                        previous.as_ref(),
+                        chain_context,
+                        policy,
+                        context_check,
+                    );
+                    if let Some((budget, block_valid)) = incremental {
+                        match (rebuilds.wait(budget), &previous) {
+                            (Some(block), _) => {
+                                if let Some(template) =
+                                    finish_watched_rebuild(cli, block, chain_context)
+                                {
+                                    previous = Some(template);
+                                }
+                            }
+                            (None, Some(template)) => {
+                                println!(
+                                    "Rebuild exceeded the budget of {} ms, appending to the previous template",
+                                    budget.as_millis()
+                                );
+                                if let Err(err) = build_incremental_block(
+                                    block_valid,
+                                    template,
+                                    chain_context,
+                                    context_check,
+                                )
+                                .and_then(|block| {
+                                    write_watched_block(cli, &block, chain_context, "Incremental")
+                                }) {
+                                    println!("No incremental block built: {}", err);
+                                }
+                            }
+                            (None, None) => {}
+                        }
+                    }
+                }
+                Err(err) => {
+                    rebuilds.discard();
+                    println!("No block built: {}", err);
+                }
+            }
+        }
+        if !rebuilds.is_pending() {
+            watcher.wait(interval);
+            continue;
+        }
+        // the directory is listed again if the full rebuild takes longer than the interval
+        if let Some(template) = rebuilds
+            .wait(interval)
+            .and_then(|block| finish_watched_rebuild(cli, block, chain_context))
+        {
+            previous = Some(template);
         }
     }
-    invalid_transactions
+}
+
+// returns: content of the input file at path or Err(BlockBuilderError::Io)
+fn read_input(path: &str) -> Result<String, BlockBuilderError> {
+    fs::read_to_string(path).map_err(io_error(Path::new(path)))
+}
+
+// reads the witness data of the getblocktemplate response at path
+fn load_node_witness_data(path: &str) -> Result<NodeWitnessData, BlockBuilderError> {
+    parse_node_witness_data(&read_input(path)?)
+        .map_err(|err| BlockBuilderError::InvalidInput(format!("{}: {}", path, err)))
+}
+
+// reads the json object of coinbase txids and their block heights at path
+fn load_coinbase_heights(path: &str) -> Result<HashMap<String, u64>, BlockBuilderError> {
+    serde_json::from_str(&read_input(path)?).map_err(|err| {
+        BlockBuilderError::InvalidInput(format!("{}: invalid coinbase heights: {}", path, err))
+    })
+}
+
+// reads the getrawmempool true json at mempool_path and the getrawtransaction <txid> 2 results
+// of its transactions at transactions_path
+// returns: Vec of Transaction structs or Err(BlockBuilderError::Parse) naming the invalid file
+fn load_core_mempool(
+    mempool_path: &str,
+    transactions_path: &str,
+) -> Result<Vec<Transaction>, BlockBuilderError> {
+    let invalid_json = |path: &str| {
+        let path = path.to_string();
+        move |message| BlockBuilderError::Parse(ParseError::InvalidJson { path, message })
+    };
+    let entries =
+        parse_core_mempool(&read_input(mempool_path)?).map_err(invalid_json(mempool_path))?;
+    core_mempool_transactions(&entries, &read_input(transactions_path)?)
+        .map_err(invalid_json(transactions_path))
+}
+
+// reads the mempool.dat at path and the confirmed prevouts json at prevouts_path, inputs
+// without known prevout are printed and rejected by the validation
+// returns: Vec of Transaction structs or Err(BlockBuilderError)
+fn load_mempool_dat(
+    path: &str,
+    prevouts_path: Option<&str>,
+) -> Result<Vec<Transaction>, BlockBuilderError> {
+    let bytes = fs::read(path).map_err(io_error(Path::new(path)))?;
+    let dat = parse_mempool_dat(&bytes)
+        .map_err(|err| BlockBuilderError::InvalidInput(format!("{}: {}", path, err)))?;
+    let confirmed: HashMap<String, Prevout> = match prevouts_path {
+        Some(prevouts_path) => {
+            serde_json::from_str(&read_input(prevouts_path)?).map_err(|err| {
+                BlockBuilderError::InvalidInput(format!(
+                    "{}: invalid prevouts: {}",
+                    prevouts_path, err
+                ))
+            })?
+        }
+        None => HashMap::new(),
+    };
+    let (transactions, missing) =
+        mempool_dat_transactions(dat, &confirmed).map_err(BlockBuilderError::InvalidInput)?;
+    if missing > 0 {
+        println!("mempool.dat: {} inputs spend unknown prevouts", missing);
+    }
+    Ok(transactions)
+}
+
+// fetches the mempool transactions of the esplora api at url, at most limit
+// returns: Vec of Transaction structs or Err(BlockBuilderError::InvalidInput)
+fn load_esplora(
+    url: &str,
+    limit: Option<usize>,
+    timeout: Duration,
+) -> Result<Vec<Transaction>, BlockBuilderError> {
+    EsploraClient::new(url)
+        .and_then(|mut client| {
+            client.timeout = timeout;
+            client.fetch_mempool(limit)
+        })
+        .map_err(|err| BlockBuilderError::InvalidInput(format!("esplora {}", err)))
+}
+
+// reads the finalized PSBTs at path, transactions already in the mempool are skipped
+// returns: Vec of the new Transaction structs or Err(BlockBuilderError)
+fn load_psbt(path: &str, mempool: &[Transaction]) -> Result<Vec<Transaction>, BlockBuilderError> {
+    let content = fs::read(path).map_err(io_error(Path::new(path)))?;
+    let transactions = parse_psbt_file(&content)
+        .map_err(|err| BlockBuilderError::InvalidInput(format!("{}: {}", path, err)))?;
+    Ok(transactions
+        .into_iter()
+        .filter(|tx| {
+            mempool
+                .iter()
+                .all(|mempool_tx| mempool_tx.meta.txid_hex != tx.meta.txid_hex)
+        })
+        .collect())
+}
+
+// reads the previous template at path as SelectionHint
+fn load_selection_hint(path: &str) -> Result<SelectionHint, BlockBuilderError> {
+    let template: BlockTemplate = serde_json::from_str(&read_input(path)?).map_err(|err| {
+        BlockBuilderError::InvalidInput(format!("{}: invalid template: {}", path, err))
+    })?;
+    Ok(SelectionHint::from_template(&template))
+}
+
+// prints the error in the --error-format on stderr and exits with its exit code
+fn exit_with_error(err: BlockBuilderError, format: ErrorFormat) -> ! {
+    match format {
+        ErrorFormat::Text => eprintln!("Error: {}", err),
+        ErrorFormat::Json => eprintln!("{}", err.to_json()),
+    }
+    process::exit(err.exit_code())
 }
 
 fn main() {
-    // parses all json transactions in a Vec of Transaction structs
-    let mut parsed_transactions = parse_transactions_from_dir("../mempool");
+    let cli = Cli::parse();
+    if let Err(err) = run(&cli) {
+        exit_with_error(err, cli.error_format);
+    }
+}
 
-    // creates a Hashset of the TXIDs of all invalid and non verified transactions
-    let invalid_transactions = validate_transactions(&mut parsed_transactions);
+// runs the command or block construction of the parsed command line
+fn run(cli: &Cli) -> Result<(), BlockBuilderError> {
+    let mut chain_context = cli.chain_context();
+    let policy = cli.mempool_policy();
+    if let Some(gbt_path) = &cli.gbt {
+        chain_context.node_witness = Some(load_node_witness_data(gbt_path)?);
+    }
+    if let Some(path) = &cli.coinbase_heights {
+        chain_context.coinbase_heights = load_coinbase_heights(path)?;
+    }
 
-    // stores all transactions that are not invalid in a HashMap (TXID(hex String), Transaction Struct)
-    let mut valid_transactions =
-    remove_invalid_transactions(parsed_transactions, invalid_transactions);
+    // all standard sighash types (ALL, NONE, SINGLE, each optionally with ANYONECANPAY)
+    let validation_options = ValidationOptions {
+        flags: match cli.policy {
+            true => ScriptFlags::STANDARD,
+            false => ScriptFlags::CONSENSUS,
+        },
+        finality: cli
+            .reject_non_final
+            .then(|| chain_context.finality_target()),
+        reject_dust: match cli.dust {
+            Some(DustAction::Reject) => Some(cli.dust_relay_feerate),
+            _ => None,
+        },
+        standard_size: cli.policy,
+        min_relay_feerate: policy.relay_feerates.min_relay,
+        ..ValidationOptions::standard_sighash_types()
+    };
+
+    match cli.command {
+        Some(Command::Repl) => {
+            run_repl(
+                chain_context,
+                policy,
+                validation_options,
+                !cli.no_context_check,
+            );
+            return Ok(());
+        }
+        Some(Command::Capabilities) => {
+            for capability in capabilities() {
+                println!(
+                    "{:<12} {:<4} {}",
+                    capability.name,
+                    if capability.supported { "yes" } else { "no" },
+                    capability.feature.unwrap_or("-")
+                );
+            }
+            return Ok(());
+        }
+        None => {}
+    }
+
+    if cli.watch {
+        return run_watch(cli, &validation_options, &chain_context, &policy);
+    }
+    if let Some(blocks) = cli.simulate_blocks {
+        let (mined, fees) =
+            run_simulation(cli, blocks, &validation_options, &chain_context, &policy)?;
+        println!(
+            "\nDone. Simulated {} blocks with {} mined transactions and {} BTC fees\n",
+            blocks,
+            mined,
+            format_btc(fees)
+        );
+        return Ok(());
+    }
+
+    // parses all json transactions in a Vec of Transaction structs and creates a HashMap of the
+    // TXIDs and rejection reasons of all invalid and non verified transactions
+    let (
+        CachedSnapshot {
+            transactions: parsed_transactions,
+            invalid: invalid_transactions,
+        },
+        parse_errors,
+    ) = load_validated_mempool(cli, &validation_options)?;
+    let parsed_txids: Vec<String> = parsed_transactions
+        .iter()
+        .map(|tx| tx.meta.txid_hex.clone())
+        .collect();
+    let transaction_report = cli
+        .tx_report
+        .as_ref()
+        .map(|_| transaction_rows(&parsed_transactions));
+
+    // only the block-valid transactions are includable, the relay-valid ones are timelocked,
+    // spend immature coinbase outputs or descend from such a transaction
+    let sets = includable_transactions(parsed_transactions, &invalid_transactions, &chain_context);
+    let valid: Vec<&Transaction> = sets
+        .block_valid
+        .values()
+        .chain(sets.relay_valid.values().map(|pending| &pending.tx))
+        .collect();
+    if valid.is_empty() {
+        print_parse_errors(&parse_errors);
+        return Err(BlockBuilderError::NoValidTransactions(parsed_txids.len()));
+    }
+    let weights = weight_stats(valid.iter().copied());
+    let dust = match cli.dust {
+        Some(DustAction::Report) => report_dust(&valid, cli.dust_relay_feerate),
+        _ => HashMap::new(),
+    };
+    if !sets.relay_valid.is_empty() {
+        println!(
+            "{} valid transactions are not includable in this block yet",
+            sets.relay_valid.len()
+        );
+    }
+    let mut valid_transactions = sets.block_valid;
+    let stats = RunStats {
+        mempool_transactions: parsed_txids.len(),
+        valid_transactions: valid_transactions.len() + sets.relay_valid.len(),
+        relay_valid_transactions: sets.relay_valid.len(),
+        weights,
+    };
+    let report = validation_report(
+        parsed_txids,
+        &invalid_transactions,
+        &valid_transactions,
+        &sets.relay_valid,
+        &dust,
+    );
+    // written before mining so the verdicts are available even if no block can be built
+    if let (Some(path), Some(rows)) = (&cli.tx_report, transaction_report) {
+        write_transaction_report(Path::new(path), cli.tx_report_format, rows, &report)?;
+    }
 
     // returns a Block struckt containing header, coinbase and final transaction list
-    let block: Block = mine_block(&mut valid_transactions);
+    // or an error if no valid block can be constructed from the transactions
+    let hint = match &cli.previous_template {
+        Some(path) => Some(load_selection_hint(path)?),
+        None => None,
+    };
+    // self-verification of the constructed block, a block violating invariants isn't written
+    let block: Block = build_block(
+        &mut valid_transactions,
+        &chain_context,
+        &policy,
+        hint.as_ref(),
+        !cli.no_context_check,
+    )?;
+
+    if let Some(bundle_dir) = &cli.bundle {
+        // writes all enabled artifacts of the run into the bundle directory
+        output_bundle(
+            Path::new(bundle_dir),
+            &block,
+            &report,
+            &stats,
+            &cli.artifacts,
+            &chain_context,
+        )?;
+    } else {
+        // writes blockfile to output.txt according to exercise specification
+        let output_path = Path::new(&cli.output);
+        output_block(&block, output_path)?;
 
-    // writes blockfile to output.txt according to exercise specification
-    output_block(&block, "../output.txt");
+        // writes the package layout (offsets, weights, cutoff) of the block to block_layout.json
+        output_block_layout(&block, &output_path.with_file_name("block_layout.json"))?;
+    }
     println!(
-        "\nDone. Number of mined transactions: {}\n",
-        &block.txids_hex.len()
+        "\nDone. Number of mined transactions: {}, fees: {} BTC, coinbase reward: {} BTC\n",
+        &block.txids_hex.len(),
+        format_btc(block.coinbase_tx.reward - BLOCK_SUBSIDY),
+        format_btc(block.coinbase_tx.reward)
     );
+    print_parse_errors(&parse_errors);
+    Ok(())
 }
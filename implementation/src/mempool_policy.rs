@@ -0,0 +1,14 @@
+// Mempool policy of the node the block is built for: how conflicts are resolved, the relay
+// feerates and the package limits. Unlike the ChainContext it doesn't depend on the chain tip,
+// defaults to the bitcoin core defaults.
+
+use crate::feerate::RelayFeerates;
+use crate::mining::assign_parents::PackageLimits;
+use crate::mining::conflict_graph::ConflictPolicy;
+
+#[derive(Debug, Clone, Default)]
+pub struct MempoolPolicy {
+    pub conflict_policy: ConflictPolicy, // decides the surviving spender of conflicts
+    pub relay_feerates: RelayFeerates,   // min relay, incremental relay and package feerate floors
+    pub package_limits: PackageLimits,   // mempool ancestor and descendant limits
+}
@@ -1,5 +1,28 @@
+use crate::feerate::{vsize, FeeRate};
 use crate::parsing::transaction_structs::Transaction;
-use std::collections::HashMap;
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+
+// mempool chain limits of bitcoin core (-limitancestorcount, -limitancestorsize,
+// -limitdescendantcount, -limitdescendantsize), counts and sizes include the transaction itself
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PackageLimits {
+    pub ancestor_count: usize,
+    pub ancestor_vsize: u64, // vbytes
+    pub descendant_count: usize,
+    pub descendant_vsize: u64, // vbytes
+}
+
+impl Default for PackageLimits {
+    fn default() -> Self {
+        PackageLimits {
+            ancestor_count: 25,
+            ancestor_vsize: 101_000,
+            descendant_count: 25,
+            descendant_vsize: 101_000,
+        }
+    }
+}
 
 // search mempool for outpoints referenced in transactions and stores them in
 // transaction.meta.parents as hex txid to respect parent child order in transaction sorting
@@ -20,9 +43,126 @@ pub fn assign_mempool_parents(transactions: &mut HashMap<String, Transaction>) {
         }
     }
 
-    for (txid, parents) in parent_transactions.iter_mut() {
-        if let Some(transaction) = transactions.get_mut(txid) {
-            transaction.meta.parents = Some(std::mem::take(parents));
+    // also resets the parents of transactions whose parents are not in the mempool anymore
+    for (txid, transaction) in transactions.iter_mut() {
+        transaction.meta.parents = parent_transactions.remove(txid);
+    }
+}
+
+// walks the assigned parents of the transaction with txid up to the transactions
+// without mempool parents, assign_mempool_parents() has to be called before
+// returns: Vec of all mempool ancestor txids (depth first) or Err if txid is not in the mempool
+pub fn mempool_ancestors(
+    transactions: &HashMap<String, Transaction>,
+    txid: &str,
+) -> Result<Vec<String>, String> {
+    let tx = match transactions.get(txid) {
+        Some(tx) => tx,
+        None => return Err(format!("Transaction {} not in mempool", txid)),
+    };
+    let mut ancestors: Vec<String> = Vec::new();
+    let mut visited: HashSet<String> = HashSet::new();
+    let mut to_visit: Vec<String> = tx.meta.parents.clone().unwrap_or_default();
+    to_visit.reverse();
+
+    while let Some(ancestor) = to_visit.pop() {
+        if !visited.insert(ancestor.clone()) {
+            continue;
+        }
+        if let Some(parents) = transactions
+            .get(&ancestor)
+            .and_then(|tx| tx.meta.parents.as_ref())
+        {
+            to_visit.extend(parents.iter().rev().cloned());
         }
+        ancestors.push(ancestor);
+    }
+    Ok(ancestors)
+}
+
+// returns: length of the longest chain of mempool parents above the transaction
+fn ancestor_depth(
+    transactions: &HashMap<String, Transaction>,
+    txid: &str,
+    depths: &mut HashMap<String, usize>,
+) -> usize {
+    if let Some(depth) = depths.get(txid) {
+        return *depth;
+    }
+    let depth = transactions[txid]
+        .meta
+        .parents
+        .iter()
+        .flatten()
+        .map(|parent| ancestor_depth(transactions, parent, depths) + 1)
+        .max()
+        .unwrap_or(0);
+    depths.insert(txid.to_string(), depth);
+    depth
+}
+
+// accepts the transactions like a node would into its mempool, parents first and the higher
+// feerate first among transactions of the same depth. A transaction is rejected if it and its
+// ancestors exceed the ancestor limits or if it would push one of its ancestors above the
+// descendant limits, its descendants are rejected with it. assign_mempool_parents() has to be
+// called before.
+// returns: txids of the rejected transactions, which are removed from the map
+pub fn trim_to_package_limits(
+    transactions: &mut HashMap<String, Transaction>,
+    limits: &PackageLimits,
+) -> Vec<String> {
+    let mut depths: HashMap<String, usize> = HashMap::new();
+    let mut order: Vec<(usize, Reverse<FeeRate>, &String)> = transactions
+        .iter()
+        .map(|(txid, tx)| {
+            let feerate = FeeRate::from_fee_weight(tx.meta.fee, tx.meta.weight);
+            (
+                ancestor_depth(transactions, txid, &mut depths),
+                Reverse(feerate),
+                txid,
+            )
+        })
+        .collect();
+    order.sort();
+
+    // txid -> (count, vsize) of the accepted transaction and its accepted descendants
+    let mut descendants: HashMap<&String, (usize, u64)> = HashMap::new();
+    let mut rejected: Vec<String> = Vec::new();
+    for (_, _, txid) in order {
+        let tx_vsize = vsize(transactions[txid].meta.weight);
+        let mut parents = transactions[txid].meta.parents.iter().flatten();
+        if !parents.all(|parent| descendants.contains_key(parent)) {
+            rejected.push(txid.clone());
+            continue;
+        }
+        let ancestors = mempool_ancestors(transactions, txid).expect("Transaction not in mempool");
+        let ancestor_vsize: u64 = ancestors
+            .iter()
+            .map(|ancestor| vsize(transactions[ancestor].meta.weight))
+            .sum();
+        let within_limits = ancestors.len() < limits.ancestor_count
+            && ancestor_vsize + tx_vsize <= limits.ancestor_vsize
+            && ancestors.iter().all(|ancestor| {
+                let (count, size) = descendants[ancestor];
+                count < limits.descendant_count && size + tx_vsize <= limits.descendant_vsize
+            });
+        if !within_limits {
+            rejected.push(txid.clone());
+            continue;
+        }
+        for ancestor in &ancestors {
+            let entry = descendants
+                .get_mut(ancestor)
+                .expect("Ancestor not accepted");
+            entry.0 += 1;
+            entry.1 += tx_vsize;
+        }
+        descendants.insert(txid, (1, tx_vsize));
+    }
+
+    rejected.sort();
+    for txid in &rejected {
+        transactions.remove(txid);
     }
+    rejected
 }
@@ -0,0 +1,129 @@
+use super::construct_coinbase::CoinbaseTxData;
+use crate::parsing::transaction_structs::Transaction;
+use crate::validation::utils::varint;
+use serde::Serialize;
+use std::collections::HashMap;
+
+const MAX_BLOCK_WEIGHT: u64 = 4000000;
+const HEADER_WEIGHT: u64 = 80 * 4;
+
+#[derive(Serialize, Debug, Clone)]
+pub struct LayoutTx {
+    pub txid: String,
+    pub offset: u64, // weight units from the start of the block
+    pub weight: u64,
+    pub fee: u64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct LayoutPackage {
+    pub offset: u64, // offset of the first package member in the block
+    pub weight: u64,
+    pub fee: u64,
+    pub transactions: Vec<LayoutTx>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct LayoutCutoff {
+    pub weight_budget: u64,
+    pub first_excluded_txid: Option<String>,
+    pub first_excluded_weight: Option<u64>,
+}
+
+// describes how the included transactions tile the block weight space,
+// grouped by CPFP packages (connected parent/child transactions in the block)
+#[derive(Serialize, Debug, Clone)]
+pub struct BlockLayout {
+    pub max_block_weight: u64,
+    pub header_weight: u64,
+    pub coinbase_weight: u64,
+    pub used_weight: u64,
+    pub cutoff: LayoutCutoff,
+    pub packages: Vec<LayoutPackage>,
+}
+
+// assigns every block transaction a package index. Transactions without parents in the block start
+// a new package, children join the package of their parents (merging packages if they have several)
+// returns: Vec of package indices in block order
+fn group_packages(block_txs: &[Transaction]) -> Vec<usize> {
+    let mut package_of: HashMap<&String, usize> = HashMap::new();
+    let mut packages: Vec<usize> = Vec::new();
+
+    for (index, tx) in block_txs.iter().enumerate() {
+        let mut parent_packages: Vec<usize> = Vec::new();
+        if let Some(parents) = tx.meta.parents.as_ref() {
+            for parent in parents {
+                if let Some(package) = package_of.get(parent) {
+                    parent_packages.push(*package);
+                }
+            }
+        }
+        let package = match parent_packages.iter().min() {
+            Some(lowest) => *lowest,
+            None => index,
+        };
+        for other in parent_packages.iter().filter(|p| **p != package) {
+            for assigned in packages.iter_mut().filter(|p| **p == *other) {
+                *assigned = package;
+            }
+            for assigned in package_of.values_mut().filter(|p| **p == *other) {
+                *assigned = package;
+            }
+        }
+        package_of.insert(&tx.meta.txid_hex, package);
+        packages.push(package);
+    }
+    packages
+}
+
+// calculates the offset and weight of each transaction in the block and groups them in packages.
+// first_excluded is the best candidate that did not fit anymore (where the weight cutoff landed)
+// returns: BlockLayout ready to be serialized as JSON
+pub fn calculate_block_layout(
+    block_txs: &[Transaction],
+    coinbase_tx: &CoinbaseTxData,
+    first_excluded: Option<&Transaction>,
+    weight_budget: u64,
+) -> BlockLayout {
+    let tx_count_weight = varint(block_txs.len() as u128 + 1).len() as u64 * 4;
+    let mut offset = HEADER_WEIGHT + tx_count_weight + coinbase_tx.weight;
+    let mut packages: Vec<LayoutPackage> = Vec::new();
+    let mut package_positions: HashMap<usize, usize> = HashMap::new();
+
+    for (tx, package) in block_txs.iter().zip(group_packages(block_txs)) {
+        let layout_tx = LayoutTx {
+            txid: tx.meta.txid_hex.clone(),
+            offset,
+            weight: tx.meta.weight,
+            fee: tx.meta.fee,
+        };
+        offset += tx.meta.weight;
+
+        let position = *package_positions.entry(package).or_insert_with(|| {
+            packages.push(LayoutPackage {
+                offset: layout_tx.offset,
+                weight: 0,
+                fee: 0,
+                transactions: Vec::new(),
+            });
+            packages.len() - 1
+        });
+        let layout_package = &mut packages[position];
+        layout_package.weight += layout_tx.weight;
+        layout_package.fee += layout_tx.fee;
+        layout_package.transactions.push(layout_tx);
+    }
+
+    BlockLayout {
+        max_block_weight: MAX_BLOCK_WEIGHT,
+        header_weight: HEADER_WEIGHT,
+        coinbase_weight: coinbase_tx.weight,
+        used_weight: offset,
+        cutoff: LayoutCutoff {
+            weight_budget,
+            first_excluded_txid: first_excluded.map(|tx| tx.meta.txid_hex.clone()),
+            first_excluded_weight: first_excluded.map(|tx| tx.meta.weight),
+        },
+        packages,
+    }
+}
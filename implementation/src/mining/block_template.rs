@@ -0,0 +1,137 @@
+use super::Block;
+use crate::chain_context::{ChainContext, NodeWitnessData};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+// header timestamps may be at most 2 hours in the future of the network adjusted time
+pub const MAX_FUTURE_BLOCK_TIME: u32 = 2 * 60 * 60;
+// suggested lifetime of a template (one expected block interval) before it should be refreshed
+pub const TEMPLATE_LIFETIME: u32 = 10 * 60;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TemplateTransaction {
+    pub data: String, // raw transaction hex
+    pub txid: String,
+    pub hash: String,        // wtxid
+    pub depends: Vec<usize>, // 1-based indices of parents in the template transactions
+    pub fee: u64,
+    pub weight: u64,
+}
+
+// getblocktemplate (BIP22) like description of the constructed block
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BlockTemplate {
+    pub version: u32,
+    pub previousblockhash: String,
+    pub transactions: Vec<TemplateTransaction>,
+    pub coinbasevalue: u64,
+    pub default_witness_commitment: String,
+    pub curtime: u32,
+    pub mintime: u32, // lowest valid header timestamp (median time past + 1)
+    pub maxtime: u32, // highest header timestamp nodes accept (2 hour future bound)
+    pub expires: u32, // suggested unix time after which the template should be refreshed
+    pub bits: String,
+    pub height: u64,
+}
+
+// witness fields of a node getblocktemplate response. witness_reserved_value and
+// witness_commitment_position are no bitcoin core fields but may be added by pool software.
+#[derive(Deserialize, Debug)]
+struct NodeTemplateWitness {
+    default_witness_commitment: Option<String>,
+    witness_reserved_value: Option<String>,
+    witness_commitment_position: Option<usize>,
+}
+
+// parses the witness fields of a getblocktemplate response, either the bare template or
+// the complete json-rpc response containing it as "result"
+// returns: NodeWitnessData or Err(message) if the json or a field is invalid
+pub fn parse_node_witness_data(json: &str) -> Result<NodeWitnessData, String> {
+    let mut value: serde_json::Value =
+        serde_json::from_str(json).map_err(|err| format!("invalid json: {}", err))?;
+    if let Some(result) = value.get_mut("result") {
+        value = result.take();
+    }
+    let template: NodeTemplateWitness =
+        serde_json::from_value(value).map_err(|err| format!("invalid template: {}", err))?;
+
+    let witness_reserved_value = match &template.witness_reserved_value {
+        Some(reserved_hex) => Some(
+            hex::decode(reserved_hex)
+                .ok()
+                .and_then(|reserved| <[u8; 32]>::try_from(reserved).ok())
+                .ok_or("witness_reserved_value is no 32 byte hex value")?,
+        ),
+        None => None,
+    };
+    if template
+        .witness_commitment_position
+        .is_some_and(|position| position > 1)
+    {
+        return Err(
+            "witness_commitment_position must be 0 or 1 (two coinbase outputs)".to_string(),
+        );
+    }
+    Ok(NodeWitnessData {
+        default_witness_commitment: template.default_witness_commitment,
+        witness_reserved_value,
+        commitment_position: template.witness_commitment_position,
+    })
+}
+
+// reads a little endian u32 from the header bytes at offset
+fn header_u32(header: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0u8; 4];
+    bytes.copy_from_slice(&header[offset..offset + 4]);
+    u32::from_le_bytes(bytes)
+}
+
+// builds the BlockTemplate from the mined Block, header fields are read from the final header.
+// The ntime range is derived from the ChainContext, if the median time past is unknown
+// the header time is used as lower bound so the timestamp is only rolled forward.
+pub fn build_block_template(block: &Block, context: &ChainContext) -> BlockTemplate {
+    let header = hex::decode(&block.header_hex).expect("Header hex invalid");
+    let previous_block: Vec<u8> = header[4..36].iter().rev().cloned().collect();
+    let mut template_indices: HashMap<&String, usize> = HashMap::new();
+    let mut transactions: Vec<TemplateTransaction> = Vec::new();
+
+    for (index, tx) in block.transactions.iter().enumerate() {
+        template_indices.insert(&tx.meta.txid_hex, index + 1);
+        let depends = tx
+            .meta
+            .parents
+            .iter()
+            .flatten()
+            .filter_map(|parent| template_indices.get(parent).copied())
+            .collect();
+        transactions.push(TemplateTransaction {
+            data: hex::encode(tx.serialize(true)),
+            txid: tx.meta.txid_hex.clone(),
+            hash: tx.meta.wtxid_hex.clone(),
+            depends,
+            fee: tx.meta.fee,
+            weight: tx.meta.weight,
+        });
+    }
+
+    let curtime = header_u32(&header, 68);
+    let mintime = match context.median_time_past {
+        Some(median_time_past) => median_time_past + 1,
+        None => curtime,
+    };
+    let maxtime = curtime + MAX_FUTURE_BLOCK_TIME;
+
+    BlockTemplate {
+        version: header_u32(&header, 0),
+        previousblockhash: hex::encode(previous_block),
+        transactions,
+        coinbasevalue: block.coinbase_tx.reward,
+        default_witness_commitment: hex::encode(&block.coinbase_tx.witness_commitment_scriptpubkey),
+        curtime,
+        mintime,
+        maxtime,
+        expires: maxtime.min(curtime + TEMPLATE_LIFETIME),
+        bits: format!("{:08x}", header_u32(&header, 72)),
+        height: context.height,
+    }
+}
@@ -0,0 +1,248 @@
+// Conflict graph of the mempool: transactions spending the same outpoint (e.g. RBF
+// replacements) exclude each other, only one of them can be included in a block.
+// Conflicts are resolved before the selection by the package feerate of each choice, with the
+// BIP125 policies a choice additionally has to be a valid replacement of its conflicts.
+
+use crate::feerate::{vsize, FeeRate};
+use crate::parsing::transaction_structs::Transaction;
+use std::collections::{HashMap, HashSet};
+
+// sequence below 0xfffffffe signals replaceability (BIP125 rule 1)
+const MAX_BIP125_RBF_SEQUENCE: u32 = 0xfffffffd;
+// maximum number of transactions a replacement evicts (BIP125 rule 5)
+const MAX_REPLACEMENT_CANDIDATES: usize = 100;
+
+// how the spender surviving a conflict is decided
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConflictPolicy {
+    #[default]
+    PackageFeerate, // the choice with the highest package feerate is kept
+    Bip125,  // choices have to be BIP125 replacements of signaling conflicts
+    FullRbf, // BIP125 without requiring the replaceability signal
+}
+
+pub struct ConflictGraph {
+    pub conflicts: HashMap<String, HashSet<String>>, // txid -> txids spending a common outpoint
+    children: HashMap<String, Vec<String>>,          // txid -> mempool children (parent graph)
+}
+
+impl ConflictGraph {
+    // links all transactions spending a common outpoint and the mempool children of each
+    // transaction
+    pub fn new(transactions: &HashMap<String, Transaction>) -> ConflictGraph {
+        let mut spenders: HashMap<&[u8], Vec<&str>> = HashMap::new();
+        let mut children: HashMap<String, Vec<String>> = HashMap::new();
+
+        for (txid, tx) in transactions {
+            for txin in &tx.vin {
+                spenders.entry(&txin.outpoint).or_default().push(txid);
+                if transactions.contains_key(&txin.txid) {
+                    children
+                        .entry(txin.txid.clone())
+                        .or_default()
+                        .push(txid.clone());
+                }
+            }
+        }
+        let mut conflicts: HashMap<String, HashSet<String>> = HashMap::new();
+        for txids in spenders.values().filter(|txids| txids.len() > 1) {
+            for txid in txids {
+                let others: Vec<String> = txids
+                    .iter()
+                    .filter(|other| *other != txid)
+                    .map(|other| other.to_string())
+                    .collect();
+                if !others.is_empty() {
+                    conflicts
+                        .entry(txid.to_string())
+                        .or_default()
+                        .extend(others);
+                }
+            }
+        }
+        ConflictGraph {
+            conflicts,
+            children,
+        }
+    }
+
+    // returns: txids of all mempool descendants of txid
+    fn descendants(&self, txid: &str) -> HashSet<String> {
+        let mut descendants: HashSet<String> = HashSet::new();
+        let mut to_visit: Vec<&String> = self.children.get(txid).into_iter().flatten().collect();
+
+        while let Some(descendant) = to_visit.pop() {
+            if descendants.insert(descendant.clone()) {
+                to_visit.extend(self.children.get(descendant).into_iter().flatten());
+            }
+        }
+        descendants
+    }
+}
+
+// returns: txids of the transaction and all its mempool ancestors
+fn with_ancestors(transactions: &HashMap<String, Transaction>, txid: &str) -> HashSet<String> {
+    let mut ancestors: HashSet<String> = HashSet::new();
+    let mut to_visit: Vec<String> = vec![txid.to_string()];
+
+    while let Some(ancestor) = to_visit.pop() {
+        if let Some(tx) = transactions.get(&ancestor) {
+            if ancestors.insert(ancestor) {
+                to_visit.extend(tx.vin.iter().map(|txin| txin.txid.clone()));
+            }
+        }
+    }
+    ancestors
+}
+
+// returns: true if the transaction or one of its mempool ancestors signals replaceability
+fn signals_replaceability(transactions: &HashMap<String, Transaction>, txid: &str) -> bool {
+    with_ancestors(transactions, txid).iter().any(|txid| {
+        transactions[txid]
+            .vin
+            .iter()
+            .any(|txin| txin.sequence <= MAX_BIP125_RBF_SEQUENCE)
+    })
+}
+
+// BIP125 rules for the replacement (a choice with its not yet kept ancestors) of the directly
+// conflicting transactions and the replaced set (conflicts and their descendants):
+// 1. all conflicts signal replaceability (explicitly or inherited), not checked for full RBF
+// 3. the replacement pays at least the absolute fee of the replaced transactions
+// 4. the additional fee pays the incremental relay feerate for the replacement size
+// 5. at most MAX_REPLACEMENT_CANDIDATES transactions are replaced
+// 6. the feerate of the replacement is higher than the feerate of each conflict
+// Rule 2 (no new unconfirmed inputs) is left out as the ancestors are kept with the replacement.
+// returns: true if the replacement is valid
+fn is_bip125_replacement(
+    transactions: &HashMap<String, Transaction>,
+    replacement: &HashSet<String>,
+    conflicts: &HashSet<&String>,
+    replaced: &HashSet<String>,
+    policy: ConflictPolicy,
+    incremental_relay_feerate: FeeRate,
+) -> bool {
+    // nothing left to replace, the conflicts were already removed
+    if conflicts.is_empty() {
+        return true;
+    }
+    let sum = |txids: &mut dyn Iterator<Item = &String>| -> (u64, u64) {
+        txids.fold((0, 0), |(fee, weight), txid| {
+            let meta = &transactions[txid].meta;
+            (fee + meta.fee, weight + meta.weight)
+        })
+    };
+    let (fee, weight) = sum(&mut replacement.iter());
+    let (replaced_fee, _) = sum(&mut replaced.iter());
+
+    let signaled = policy == ConflictPolicy::FullRbf
+        || conflicts
+            .iter()
+            .all(|txid| signals_replaceability(transactions, txid));
+    let higher_feerate = conflicts.iter().all(|txid| {
+        let meta = &transactions[*txid].meta;
+        fee as u128 * meta.weight as u128 > meta.fee as u128 * weight as u128
+    });
+    signaled
+        && fee >= replaced_fee
+        && FeeRate::from_fee_vsize(fee - replaced_fee, vsize(weight)) >= incremental_relay_feerate
+        && replaced.len() <= MAX_REPLACEMENT_CANDIDATES
+        && higher_feerate
+}
+
+// Treats the conflicting transactions as mutually exclusive choices: the choice with the
+// highest package feerate (fee per weight unit of the transaction and all its mempool
+// descendants, ties by the higher package fee) is kept first, together with its ancestors.
+// Transactions conflicting with kept ones are removed with their descendants, a choice whose
+// ancestors conflict with kept transactions is skipped. With the BIP125 policies a choice that
+// is no valid replacement of its remaining conflicts is removed instead, so the conflicts
+// (originals) it failed to replace stay choices. Replacements pay the incremental relay
+// feerate for their size on top of the replaced fees.
+// returns: txids of the removed transactions
+pub fn resolve_conflicts(
+    transactions: &mut HashMap<String, Transaction>,
+    policy: ConflictPolicy,
+    incremental_relay_feerate: FeeRate,
+) -> Vec<String> {
+    let graph = ConflictGraph::new(transactions);
+    if graph.conflicts.is_empty() {
+        return Vec::new();
+    }
+    // returns: (fee, weight) of the transaction and its mempool descendants
+    let package = |txid: &str| -> (u64, u64) {
+        graph
+            .descendants(txid)
+            .iter()
+            .chain([txid.to_string()].iter())
+            .map(|txid| &transactions[txid].meta)
+            .fold((0, 0), |(fee, weight), meta| {
+                (fee + meta.fee, weight + meta.weight)
+            })
+    };
+    let mut choices: Vec<((u64, u64), &String)> = graph
+        .conflicts
+        .keys()
+        .map(|txid| (package(txid), txid))
+        .collect();
+    // feerates compared by cross multiplication, ties by fee and txid (deterministic)
+    choices.sort_by(|((fee_a, weight_a), txid_a), ((fee_b, weight_b), txid_b)| {
+        (*fee_b as u128 * *weight_a as u128)
+            .cmp(&(*fee_a as u128 * *weight_b as u128))
+            .then(fee_b.cmp(fee_a))
+            .then(txid_a.cmp(txid_b))
+    });
+
+    let mut kept: HashSet<String> = HashSet::new();
+    let mut removed: HashSet<String> = HashSet::new();
+    for (_, txid) in choices {
+        let choice = with_ancestors(transactions, txid);
+        let choice_conflicts: HashSet<&String> = choice
+            .iter()
+            .filter_map(|txid| graph.conflicts.get(txid))
+            .flatten()
+            .collect();
+        if choice.iter().any(|txid| removed.contains(txid))
+            || choice_conflicts.iter().any(|txid| kept.contains(*txid))
+        {
+            continue;
+        }
+        if policy != ConflictPolicy::PackageFeerate {
+            let replacement: HashSet<String> = choice.difference(&kept).cloned().collect();
+            let mut replaced: HashSet<String> = HashSet::new();
+            for conflict in &choice_conflicts {
+                replaced.insert(conflict.to_string());
+                replaced.extend(graph.descendants(conflict));
+            }
+            replaced.retain(|txid| !removed.contains(txid));
+            let conflicts: HashSet<&String> = choice_conflicts
+                .iter()
+                .filter(|txid| !removed.contains(**txid))
+                .copied()
+                .collect();
+            if !is_bip125_replacement(
+                transactions,
+                &replacement,
+                &conflicts,
+                &replaced,
+                policy,
+                incremental_relay_feerate,
+            ) {
+                removed.insert(txid.clone());
+                removed.extend(graph.descendants(txid));
+                continue;
+            }
+        }
+        for conflict in choice_conflicts {
+            removed.insert(conflict.clone());
+            removed.extend(graph.descendants(conflict));
+        }
+        kept.extend(choice);
+    }
+
+    let mut removed: Vec<String> = removed.into_iter().collect();
+    removed.sort();
+    for txid in &removed {
+        transactions.remove(txid);
+    }
+    removed
+}
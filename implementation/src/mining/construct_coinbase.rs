@@ -1,11 +1,35 @@
-use crate::validation::utils::{double_hash, varint};
+use crate::chain_context::{ChainContext, NodeWitnessData};
+use crate::validation::utils::{decode_num, double_hash, encode_num, varint};
 use crate::{parsing::transaction_structs::Transaction, validation::validate_parsing::get_txid};
 use hex_literal::hex as hexlit;
 
+// block subsidy in satoshi
+pub const BLOCK_SUBSIDY: u64 = 625000000;
+
+// size of the zeroed region in the coinbase scriptsig reserved for pool extranonces
+pub const EXTRANONCE1_SIZE: usize = 4;
+pub const EXTRANONCE2_SIZE: usize = 4;
+
+#[derive(Debug, Clone)]
 pub struct CoinbaseTxData {
     pub txid_hex: String,
     pub txid_natural_bytes: Vec<u8>,
     pub assembled_tx: Vec<u8>,
+    pub assembled_tx_no_witness: Vec<u8>,
+    pub extranonce_offset: usize, // offset of the extranonce region in assembled_tx_no_witness
+    pub weight: u64,
+    pub reward: u64, // subsidy + fees
+    pub witness_commitment_scriptpubkey: Vec<u8>,
+    pub witness_reserved_value: [u8; 32],
+    pub witness_commitment_source: WitnessCommitmentSource,
+}
+
+// origin of the witness commitment used in the coinbase
+#[derive(Debug, Clone, PartialEq)]
+pub enum WitnessCommitmentSource {
+    Local,                     // no node commitment supplied
+    Node,                      // node commitment verified against the local one
+    Mismatch { node: String }, // node commitment differs, the local one is used
 }
 
 // calculates the HASH256 merkle root of a Vec of Vec<u8> ([w]txids).
@@ -18,7 +42,7 @@ pub fn get_merkle_root(block_txs: &[Vec<u8>]) -> Vec<u8> {
     }
 
     while merkle_tree.len() > 1 {
-        if merkle_tree.len() % 2 != 0 {
+        if !merkle_tree.len().is_multiple_of(2) {
             let last: Vec<u8> = merkle_tree.last().unwrap().clone();
             merkle_tree.push(last);
         }
@@ -41,9 +65,12 @@ pub fn get_merkle_root(block_txs: &[Vec<u8>]) -> Vec<u8> {
 }
 
 // assembles the scriptpubkey for use as witness commitment in the coinbase tx.
-// calculates the witness root hash and prepends it with the according opcodes
-// ready for use as scriptpubkey returned as Vec<u8>
-fn calc_wtxid_commitment_scriptpubkey(block_txs: &Vec<Transaction>) -> Vec<u8> {
+// calculates the witness root hash, commits to it together with the witness reserved value
+// and prepends it with the according opcodes ready for use as scriptpubkey returned as Vec<u8>
+pub fn calc_wtxid_commitment_scriptpubkey(
+    block_txs: &Vec<Transaction>,
+    witness_reserved_value: &[u8; 32],
+) -> Vec<u8> {
     let mut txids_bytes: Vec<Vec<u8>> = Vec::new();
 
     txids_bytes
@@ -55,17 +82,119 @@ fn calc_wtxid_commitment_scriptpubkey(block_txs: &Vec<Transaction>) -> Vec<u8> {
         txids_bytes.push(rev_txid_bytes);
     }
     let mut wtxid_merkle_root = get_merkle_root(&txids_bytes);
-    wtxid_merkle_root.extend(hexlit!(
-        "0000000000000000000000000000000000000000000000000000000000000000"
-    ));
+    wtxid_merkle_root.extend(witness_reserved_value);
     let witness_commitment = double_hash(&wtxid_merkle_root);
     let mut witness_commitment_scriptpubkey = hexlit!("6a24aa21a9ed").to_vec(); // OP_RETURN + len + witness code
     witness_commitment_scriptpubkey.extend(&witness_commitment);
     witness_commitment_scriptpubkey
 }
 
+// compares the commitment of the node template with the locally computed one
+// returns: (commitment scriptpubkey to use, its WitnessCommitmentSource)
+fn resolve_witness_commitment(
+    local: Vec<u8>,
+    node_witness: Option<&NodeWitnessData>,
+) -> (Vec<u8>, WitnessCommitmentSource) {
+    let node = match node_witness.and_then(|data| data.default_witness_commitment.as_ref()) {
+        Some(node) => node,
+        None => return (local, WitnessCommitmentSource::Local),
+    };
+    if hex::decode(node).is_ok_and(|node_bytes| node_bytes == local) {
+        (local, WitnessCommitmentSource::Node)
+    } else {
+        (
+            local,
+            WitnessCommitmentSource::Mismatch { node: node.clone() },
+        )
+    }
+}
+
+// returns the merkle branch (sibling hashes from the bottom to the top of the tree) of the
+// first leaf, which allows to recalculate the root if only the coinbase txid changes
+pub fn get_merkle_branch(block_txs: &[Vec<u8>]) -> Vec<Vec<u8>> {
+    let mut merkle_tree: Vec<Vec<u8>> = block_txs.to_owned();
+    let mut branch: Vec<Vec<u8>> = Vec::new();
+
+    while merkle_tree.len() > 1 {
+        if !merkle_tree.len().is_multiple_of(2) {
+            let last: Vec<u8> = merkle_tree.last().unwrap().clone();
+            merkle_tree.push(last);
+        }
+        branch.push(merkle_tree[1].clone());
+
+        let mut next_stage: Vec<Vec<u8>> = Vec::new();
+        for pair in merkle_tree.chunks(2) {
+            let mut concat = pair[0].clone();
+            concat.extend(&pair[1]);
+            next_stage.push(double_hash(&concat));
+        }
+        merkle_tree = next_stage;
+    }
+    branch
+}
+
+// folds the first leaf (coinbase txid in natural bytes) with its merkle branch
+// returns: merkle root as Vec<u8>
+pub fn get_merkle_root_from_branch(first_leaf: &[u8], branch: &[Vec<u8>]) -> Vec<u8> {
+    let mut root = first_leaf.to_vec();
+
+    for sibling in branch {
+        root.extend(sibling);
+        root = double_hash(&root);
+    }
+    root
+}
+
+// BIP34 height push at the start of the coinbase scriptsig, like `CScript() << height`:
+// OP_0 and OP_1 to OP_16 for small heights, the minimal script number push otherwise
+// returns: push opcode and data
+fn height_push(height: u64) -> Vec<u8> {
+    match height {
+        0 => vec![0x00],
+        1..=16 => vec![0x50 + height as u8],
+        _ => {
+            let number = encode_num(height as i64);
+            let mut push = vec![number.len() as u8];
+            push.extend(number);
+            push
+        }
+    }
+}
+
+// reads the BIP34 height push at the start of the scriptsig of the serialized coinbase
+// transaction (with or without witness)
+// returns: height or None if the scriptsig doesn't start with a height push
+pub fn coinbase_height(coinbase_tx: &[u8]) -> Option<u64> {
+    let segwit = coinbase_tx.get(4..6) == Some(&[0x00, 0x01]);
+    let scriptsig_offset = 4 + if segwit { 2 } else { 0 } + 1 + 36; // version, input count, outpoint
+    let scriptsig_length = *coinbase_tx.get(scriptsig_offset)? as usize;
+    let scriptsig =
+        coinbase_tx.get(scriptsig_offset + 1..scriptsig_offset + 1 + scriptsig_length)?;
+    match *scriptsig.first()? {
+        0x00 => Some(0),
+        opcode @ 0x51..=0x60 => Some((opcode - 0x50) as u64),
+        length @ 0x01..=0x08 => {
+            let height = decode_num(scriptsig.get(1..1 + length as usize)?);
+            u64::try_from(height).ok()
+        }
+        _ => None,
+    }
+}
+
+// assembles the coinbase scriptsig consisting of the passed block height (BIP34), the zeroed
+// extranonce region and a message.
+// returns: scriptsig bytes and offset of the extranonce region in the scriptsig
+fn coinbase_scriptsig(height: u64) -> (Vec<u8>, usize) {
+    let mut scriptsig = height_push(height); // blockheight
+    scriptsig.extend(varint((EXTRANONCE1_SIZE + EXTRANONCE2_SIZE) as u128)); // pushbytes len extranonce
+    let extranonce_offset = scriptsig.len();
+    scriptsig.extend(vec![0u8; EXTRANONCE1_SIZE + EXTRANONCE2_SIZE]); // extranonce region
+    scriptsig.extend(hexlit!("1043797068657270756E6B467574757265")); // this is 16 + secret ascii message :)
+    (scriptsig, extranonce_offset)
+}
+
 // returns the sum of all fees in a Vec<Transaction>
-fn count_fees(block_txs: &Vec<Transaction>) -> u64 {
+pub fn count_fees(block_txs: &Vec<Transaction>) -> u64 {
     let mut all_fees = 0;
 
     for tx in block_txs {
@@ -75,11 +204,20 @@ fn count_fees(block_txs: &Vec<Transaction>) -> u64 {
 }
 
 // serializes the coinbase transaction as Vec<u8>. If is_segwit is true it will include marker, flag
-// and the witness reserved value.
-fn serialize_coinbase_transaction(block_txs: &Vec<Transaction>, is_segwit: bool) -> Vec<u8> {
+// and the witness reserved value. The commitment output is placed at commitment_position
+// (0 = in front of the reward output, else behind it).
+fn serialize_coinbase_transaction(
+    block_txs: &Vec<Transaction>,
+    is_segwit: bool,
+    context: &ChainContext,
+    wtxid_commitment_scriptpubkey: &[u8],
+    witness_reserved_value: &[u8; 32],
+    commitment_position: usize,
+) -> Vec<u8> {
     let mut coinbase_transaction: Vec<u8> = Vec::new();
-    let wtxid_commitment_scriptpubkey: Vec<u8> = calc_wtxid_commitment_scriptpubkey(block_txs);
-    let reward: u64 = count_fees(block_txs) + 625000000;
+    let reward: u64 = count_fees(block_txs) + BLOCK_SUBSIDY;
+    let mut reward_output: Vec<u8> = reward.to_le_bytes().to_vec();
+    let mut commitment_output: Vec<u8> = hexlit!("0000000000000000").to_vec(); // witness amount
 
     coinbase_transaction.extend(hexlit!("01000000")); // version
     if is_segwit {
@@ -88,39 +226,81 @@ fn serialize_coinbase_transaction(block_txs: &Vec<Transaction>, is_segwit: bool)
     coinbase_transaction.extend(hexlit!(
         "010000000000000000000000000000000000000000000000000000000000000000ffffffff"
     )); // input count + input + index
-    let mut scriptsig = varint(varint(839653).len() as u128); //pushbytes len blockheight
-    scriptsig.extend(varint(839653)); // blockheight
-    scriptsig.extend(hexlit!("1043797068657270756E6B467574757265")); // this is 16 + secret ascii message :)
+    let (scriptsig, _) = coinbase_scriptsig(context.height);
     coinbase_transaction.extend(varint(scriptsig.len() as u128));
     coinbase_transaction.extend(scriptsig);
     coinbase_transaction.extend(hexlit!("ffffffff")); // sequence
     coinbase_transaction.extend(hexlit!("02")); // 2 outputs (reward and witness commitment op_return)
-    coinbase_transaction.extend(reward.to_le_bytes());
-    coinbase_transaction.extend(varint(
+    reward_output.extend(varint(
         hexlit!("001435f6de260c9f3bdee47524c473a6016c0c055cb9").len() as u128,
     )); // reward p2wpkh scriptpubkey
-    coinbase_transaction.extend(hexlit!("001435f6de260c9f3bdee47524c473a6016c0c055cb9"));
-    coinbase_transaction.extend(hexlit!("0000000000000000")); // witness amount
-    coinbase_transaction.extend(varint(wtxid_commitment_scriptpubkey.len() as u128)); // len wtxid commitment
-    coinbase_transaction.extend(wtxid_commitment_scriptpubkey);
+    reward_output.extend(hexlit!("001435f6de260c9f3bdee47524c473a6016c0c055cb9"));
+    commitment_output.extend(varint(wtxid_commitment_scriptpubkey.len() as u128)); // len wtxid commitment
+    commitment_output.extend(wtxid_commitment_scriptpubkey);
+    if commitment_position == 0 {
+        coinbase_transaction.extend(commitment_output);
+        coinbase_transaction.extend(reward_output);
+    } else {
+        coinbase_transaction.extend(reward_output);
+        coinbase_transaction.extend(commitment_output);
+    }
     // amnt witness stack items + len witness reserved value + value
     if is_segwit {
-        coinbase_transaction.extend(hexlit!(
-            "01200000000000000000000000000000000000000000000000000000000000000000"
-        ));
+        coinbase_transaction.extend(hexlit!("0120"));
+        coinbase_transaction.extend(witness_reserved_value);
     }
     coinbase_transaction.extend(hexlit!("00000000")); // locktime
     coinbase_transaction
 }
 
-// entry function to assemble the coinbase transaction which is returned as CoinbasTxData struct
-pub fn assemble_coinbase_transaction(block_txs: &Vec<Transaction>) -> CoinbaseTxData {
-    let coinbase_tx_witness = serialize_coinbase_transaction(block_txs, true);
-    let coinbase_tx_no_witness = serialize_coinbase_transaction(block_txs, false);
+// entry function to assemble the coinbase transaction which is returned as CoinbasTxData struct.
+// Witness reserved value and commitment position are taken from the node template if supplied.
+pub fn assemble_coinbase_transaction(
+    block_txs: &Vec<Transaction>,
+    context: &ChainContext,
+) -> CoinbaseTxData {
+    let node_witness = context.node_witness.as_ref();
+    let witness_reserved_value = node_witness
+        .and_then(|data| data.witness_reserved_value)
+        .unwrap_or([0u8; 32]);
+    let commitment_position = node_witness
+        .and_then(|data| data.commitment_position)
+        .unwrap_or(1);
+    let (witness_commitment_scriptpubkey, witness_commitment_source) = resolve_witness_commitment(
+        calc_wtxid_commitment_scriptpubkey(block_txs, &witness_reserved_value),
+        node_witness,
+    );
+    let coinbase_tx_witness = serialize_coinbase_transaction(
+        block_txs,
+        true,
+        context,
+        &witness_commitment_scriptpubkey,
+        &witness_reserved_value,
+        commitment_position,
+    );
+    let coinbase_tx_no_witness = serialize_coinbase_transaction(
+        block_txs,
+        false,
+        context,
+        &witness_commitment_scriptpubkey,
+        &witness_reserved_value,
+        commitment_position,
+    );
+    let (scriptsig, scriptsig_extranonce_offset) = coinbase_scriptsig(context.height);
+    // version + input count + outpoint + scriptsig length
+    let extranonce_offset =
+        4 + 1 + 36 + varint(scriptsig.len() as u128).len() + scriptsig_extranonce_offset;
 
     CoinbaseTxData {
         txid_hex: hex::encode(get_txid(&coinbase_tx_no_witness)),
         txid_natural_bytes: double_hash(&coinbase_tx_no_witness),
+        weight: coinbase_tx_no_witness.len() as u64 * 3 + coinbase_tx_witness.len() as u64,
         assembled_tx: coinbase_tx_witness,
+        assembled_tx_no_witness: coinbase_tx_no_witness,
+        extranonce_offset,
+        reward: count_fees(block_txs) + BLOCK_SUBSIDY,
+        witness_commitment_scriptpubkey,
+        witness_reserved_value,
+        witness_commitment_source,
     }
 }
@@ -0,0 +1,36 @@
+use super::Block;
+use crate::feerate::{vsize, FeeRate};
+use std::collections::HashSet;
+
+// renders the parent -> child relations of the block transactions in the graphviz DOT format.
+// only transactions with parents or children in the block are included, labeled with
+// the first 8 hex characters of their txid and their feerate
+// returns: DOT digraph as String
+pub fn dependency_dot(block: &Block) -> String {
+    let mut dot = String::from("digraph block_dependencies {\n    rankdir=LR;\n");
+    let mut nodes: HashSet<&str> = HashSet::new();
+
+    for tx in &block.transactions {
+        for parent in tx.meta.parents.iter().flatten() {
+            nodes.insert(parent);
+            nodes.insert(&tx.meta.txid_hex);
+            dot.push_str(&format!(
+                "    \"{}\" -> \"{}\";\n",
+                &parent[..8],
+                &tx.meta.txid_hex[..8]
+            ));
+        }
+    }
+    for tx in &block.transactions {
+        if nodes.contains(tx.meta.txid_hex.as_str()) {
+            dot.push_str(&format!(
+                "    \"{}\" [label=\"{}\\n{}\"];\n",
+                &tx.meta.txid_hex[..8],
+                &tx.meta.txid_hex[..8],
+                FeeRate::from_fee_vsize(tx.meta.fee, vsize(tx.meta.weight))
+            ));
+        }
+    }
+    dot.push_str("}\n");
+    dot
+}
@@ -0,0 +1,48 @@
+use super::construct_coinbase::{get_merkle_root_from_branch, EXTRANONCE1_SIZE, EXTRANONCE2_SIZE};
+use super::Block;
+use crate::validation::utils::double_hash;
+
+pub struct RolledCoinbase {
+    pub coinbase_tx: Vec<u8>, // serialized including witness, as written in the block
+    pub txid_hex: String,
+    pub merkle_root: Vec<u8>, // natural byte order as used in the header
+}
+
+// inserts extranonce1 + extranonce2 into the reserved region of the coinbase scriptsig of the
+// constructed block and recalculates the coinbase txid and the merkle root using the stored
+// coinbase merkle branch, so the template doesn't have to be rebuilt to roll work.
+// returns: RolledCoinbase or Err(reason) if the extranonce sizes don't fit the reserved region
+pub fn roll_extranonce(
+    block: &Block,
+    extranonce1: &[u8],
+    extranonce2: &[u8],
+) -> Result<RolledCoinbase, String> {
+    if extranonce1.len() != EXTRANONCE1_SIZE || extranonce2.len() != EXTRANONCE2_SIZE {
+        return Err(format!(
+            "Extranonce sizes {}+{} don't match reserved {}+{} bytes",
+            extranonce1.len(),
+            extranonce2.len(),
+            EXTRANONCE1_SIZE,
+            EXTRANONCE2_SIZE
+        ));
+    }
+    let mut extranonce = extranonce1.to_vec();
+    extranonce.extend(extranonce2);
+
+    let offset = block.coinbase_tx.extranonce_offset;
+    let mut coinbase_no_witness = block.coinbase_tx.assembled_tx_no_witness.clone();
+    coinbase_no_witness[offset..offset + extranonce.len()].copy_from_slice(&extranonce);
+    let mut coinbase_tx = block.coinbase_tx.assembled_tx.clone();
+    let offset = offset + 2; // marker + flag come before the input in the witness serialization
+    coinbase_tx[offset..offset + extranonce.len()].copy_from_slice(&extranonce);
+
+    let txid_natural_bytes = double_hash(&coinbase_no_witness);
+    let merkle_root = get_merkle_root_from_branch(&txid_natural_bytes, &block.merkle_branch);
+    let txid_hex = hex::encode(txid_natural_bytes.into_iter().rev().collect::<Vec<u8>>());
+
+    Ok(RolledCoinbase {
+        coinbase_tx,
+        txid_hex,
+        merkle_root,
+    })
+}
@@ -0,0 +1,48 @@
+use super::select_transactions;
+use crate::feerate::FeeRate;
+use crate::mempool_policy::MempoolPolicy;
+use crate::parsing::transaction_structs::Transaction;
+use std::collections::HashMap;
+
+pub struct FeeBumpResult {
+    pub included: bool, // true if the transaction (and so its whole package) is included
+    pub position: Option<usize>, // index in the block transactions (coinbase not counted)
+    pub packet_fee_sat: u64, // fee of the transaction and its mempool ancestors after the bump
+    pub packet_weight: u64,
+    pub packet_feerate: FeeRate,
+}
+
+// simulates increasing the fee of the transaction with txid by fee_delta (satoshi) on a copy
+// of the valid mempool transactions and re-runs the transaction selection of mine_block().
+// Useful to test RBF/CPFP strategies against real mempool snapshots, conflicts of the bumped
+// transaction are resolved with the ConflictPolicy of the passed MempoolPolicy, its relay
+// feerates and package limits apply as in mine_block().
+// returns: FeeBumpResult or Err(reason) if txid is not in the passed transactions or was removed
+// by the selection (lost a conflict, exceeds the package limits)
+pub fn simulate_fee_bump(
+    txid_tx_map: &HashMap<String, Transaction>,
+    txid: &str,
+    fee_delta: u64,
+    policy: &MempoolPolicy,
+) -> Result<FeeBumpResult, String> {
+    let mut simulated = txid_tx_map.clone();
+
+    match simulated.get_mut(txid) {
+        Some(tx) => tx.meta.fee += fee_delta,
+        None => return Err(format!("Transaction {} not in mempool", txid)),
+    }
+    let (_, block_ordered) = select_transactions(&mut simulated, None, policy);
+
+    let position = block_ordered.iter().position(|tx| tx.meta.txid_hex == txid);
+    let packet = match simulated.get(txid) {
+        Some(tx) => &tx.meta.packet_data,
+        None => return Err(format!("Transaction {} removed by the selection", txid)),
+    };
+    Ok(FeeBumpResult {
+        included: position.is_some(),
+        position,
+        packet_fee_sat: packet.packet_fee_sat,
+        packet_weight: packet.packet_weight,
+        packet_feerate: packet.packet_feerate,
+    })
+}
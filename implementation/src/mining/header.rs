@@ -1,19 +1,29 @@
 use super::construct_coinbase::{get_merkle_root, CoinbaseTxData};
+use super::MiningError;
+use crate::chain_context::ChainContext;
 use crate::{parsing::transaction_structs::Transaction, validation::utils::double_hash};
 use hex_literal::hex as hexlit;
 use num_bigint::BigUint;
 use std::time::{SystemTime, UNIX_EPOCH};
 
+// target the HASH256 of the header has to be below (big endian)
+pub const HEADER_TARGET: [u8; 32] =
+    hexlit!("00000ffff0000000000000000000000000000000000000000000000000000000");
+
+// Comparison of the hash against the target happens as BigUint integer
+// returns: true if the HASH256 of the complete 80 byte header is below HEADER_TARGET
+pub fn header_hash_below_target(block_header: &[u8]) -> bool {
+    let block_hash: Vec<u8> = double_hash(block_header);
+    BigUint::from_bytes_le(&block_hash) < BigUint::from_bytes_be(&HEADER_TARGET)
+}
+
 // changes the 4 byte nonce at the end of the header to change the HASH256
 // so long till the header + nonce produce a HASH256 below the specified target
-// Comparison of the hash against the target happens as BigUint integer
 // returns: nonce that produces a valid hash as u32
-fn mine_nonce(block_header: &[u8]) -> u32 {
-    let target = BigUint::from_bytes_be(&hexlit!(
-        "00000ffff0000000000000000000000000000000000000000000000000000000"
-    ));
-    let max_nonce = std::u32::MAX;
-    let mut candidate = block_header.to_vec();
+pub fn mine_nonce(header_prefix: &[u8]) -> u32 {
+    let target = BigUint::from_bytes_be(&HEADER_TARGET);
+    let max_nonce = u32::MAX;
+    let mut candidate = header_prefix.to_vec();
     candidate.extend(0_u32.to_le_bytes());
 
     for nonce in 0..=max_nonce {
@@ -30,22 +40,11 @@ fn mine_nonce(block_header: &[u8]) -> u32 {
     panic!("All nonces used in mining!");
 }
 
-// assembles the blockheader according to the specification using hardcoded previous block, version
-// and target according to the exercise
-pub fn construct_header(
+// returns: txids of the coinbase and all block transactions in natural byte order (merkle leaves)
+pub fn get_block_txids_natural(
     block_transactions: &Vec<Transaction>,
     coinbase_tx: &CoinbaseTxData,
-) -> Vec<u8> {
-    let mut block_header: Vec<u8> = Vec::new();
-
-    block_header.extend(hexlit!("20000000")); // version not signaling updates
-    let previous_block_bytes: Vec<u8> =
-        hexlit!("00000000000000000001901b9f3b6c7a0c34b20b29b950d0d8ffa36c63979c1c")
-            .into_iter()
-            .rev()
-            .collect();
-    block_header.extend(previous_block_bytes); // rev bytes of previous block hash (natural order)
-
+) -> Vec<Vec<u8>> {
     let mut txids_bytes: Vec<Vec<u8>> = Vec::new();
     txids_bytes.push(coinbase_tx.txid_natural_bytes.clone());
     for tx in block_transactions {
@@ -54,6 +53,25 @@ pub fn construct_header(
         let rev_txid_bytes: Vec<u8> = txid_bytes.into_iter().rev().collect();
         txids_bytes.push(rev_txid_bytes);
     }
+    txids_bytes
+}
+
+// assembles the first 76 bytes of the blockheader (everything except the nonce) according to
+// the specification using the previous block and compact target of the ChainContext and
+// a hardcoded version
+// returns: header prefix or Err(MiningError) if the previous block hash is invalid
+pub fn construct_header_prefix(
+    block_transactions: &Vec<Transaction>,
+    coinbase_tx: &CoinbaseTxData,
+    context: &ChainContext,
+) -> Result<Vec<u8>, MiningError> {
+    let mut block_header: Vec<u8> = Vec::new();
+
+    block_header.extend(hexlit!("20000000")); // version not signaling updates
+    let previous_block_bytes = context.previous_block_hash_natural()?;
+    block_header.extend(previous_block_bytes); // rev bytes of previous block hash (natural order)
+
+    let txids_bytes = get_block_txids_natural(block_transactions, coinbase_tx);
     block_header.extend(get_merkle_root(&txids_bytes)); // merkle root
 
     if let Ok(time_sec) = SystemTime::now().duration_since(UNIX_EPOCH) {
@@ -63,9 +81,6 @@ pub fn construct_header(
         panic!("Error getting unix time in header construction!")
     };
 
-    let target_bits = u32::to_le_bytes(0x1f00ffff); // target
-    block_header.extend(target_bits);
-    let nonce: u32 = mine_nonce(&block_header);
-    block_header.extend(nonce.to_le_bytes());
-    block_header
+    block_header.extend(context.bits.to_le_bytes()); // compact target
+    Ok(block_header)
 }
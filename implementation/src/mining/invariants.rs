@@ -0,0 +1,313 @@
+use super::construct_coinbase::{
+    calc_wtxid_commitment_scriptpubkey, coinbase_height, get_merkle_root, BLOCK_SUBSIDY,
+};
+use super::header::get_block_txids_natural;
+use super::Block;
+use crate::chain_context::ChainContext;
+use crate::validation::sigops::{sigops_cost, MAX_BLOCK_SIGOPS_COST};
+use crate::validation::utils::{double_hash, varint};
+use num_bigint::BigUint;
+use std::collections::HashMap;
+use std::fmt;
+
+// consensus limits a block is checked against
+#[derive(Debug, Clone)]
+pub struct Limits {
+    pub max_block_weight: u64,
+    pub max_block_sigops_cost: u64,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_block_weight: 4000000,
+            max_block_sigops_cost: MAX_BLOCK_SIGOPS_COST,
+        }
+    }
+}
+
+// consensus rule broken by a constructed block, returned by Block::check_invariants()
+#[derive(Debug, Clone, PartialEq)]
+pub enum InvariantViolation {
+    Weight {
+        weight: u64,
+        limit: u64,
+    },
+    Sigops {
+        cost: u64,
+        limit: u64,
+    },
+    DuplicateTransaction {
+        txid: String,
+    },
+    // parent not in front of the child
+    Ordering {
+        txid: String,
+        parent: String,
+    },
+    // outpoint already spent in the block
+    Conflict {
+        outpoint: String,
+        txid: String,
+    },
+    // actual None: outputs > inputs
+    Fee {
+        txid: String,
+        claimed: u64,
+        actual: Option<u64>,
+    },
+    CoinbaseValue {
+        reward: u64,
+        allowed: u64,
+    },
+    MerkleRoot {
+        header: String,
+        computed: String,
+    },
+    // commitment missing in the coinbase outputs
+    WitnessCommitment {
+        expected: String,
+    },
+    ProofOfWork {
+        hash: String,
+    },
+    // coinbase None: the scriptsig doesn't start with a BIP34 height push
+    CoinbaseHeight {
+        coinbase: Option<u64>,
+        context: u64,
+    },
+    // header doesn't build on the chain tip of the context
+    PreviousBlockHash {
+        header: String,
+        context: String,
+    },
+}
+
+impl fmt::Display for InvariantViolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InvariantViolation::Weight { weight, limit } => {
+                write!(f, "block weight {} exceeds {}", weight, limit)
+            }
+            InvariantViolation::Sigops { cost, limit } => {
+                write!(f, "block sigops cost {} exceeds {}", cost, limit)
+            }
+            InvariantViolation::DuplicateTransaction { txid } => {
+                write!(f, "transaction {} included twice", txid)
+            }
+            InvariantViolation::Ordering { txid, parent } => {
+                write!(
+                    f,
+                    "transaction {} is included before its parent {}",
+                    txid, parent
+                )
+            }
+            InvariantViolation::Conflict { outpoint, txid } => {
+                write!(
+                    f,
+                    "transaction {} spends the already spent {}",
+                    txid, outpoint
+                )
+            }
+            InvariantViolation::Fee {
+                txid,
+                claimed,
+                actual,
+            } => match actual {
+                Some(actual) => write!(
+                    f,
+                    "transaction {} claims {} sat fee but pays {} sat",
+                    txid, claimed, actual
+                ),
+                None => write!(f, "transaction {} spends more than its inputs", txid),
+            },
+            InvariantViolation::CoinbaseValue { reward, allowed } => {
+                write!(
+                    f,
+                    "coinbase pays {} sat but only {} sat are allowed",
+                    reward, allowed
+                )
+            }
+            InvariantViolation::MerkleRoot { header, computed } => write!(
+                f,
+                "header merkle root {} doesn't match the transactions ({})",
+                header, computed
+            ),
+            InvariantViolation::WitnessCommitment { expected } => {
+                write!(f, "coinbase doesn't commit to the witnesses ({})", expected)
+            }
+            InvariantViolation::ProofOfWork { hash } => {
+                write!(f, "block hash {} is above the header target", hash)
+            }
+            InvariantViolation::CoinbaseHeight { coinbase, context } => match coinbase {
+                Some(height) => write!(
+                    f,
+                    "coinbase height {} doesn't match the context height {}",
+                    height, context
+                ),
+                None => write!(f, "coinbase scriptsig doesn't start with the block height"),
+            },
+            InvariantViolation::PreviousBlockHash { header, context } => write!(
+                f,
+                "header builds on {} instead of the context tip {}",
+                header, context
+            ),
+        }
+    }
+}
+
+// sigops cost of the block: legacy sigops of all scriptsigs and scriptpubkeys and P2SH redeem
+// scripts scaled by 4 plus the witness sigops (validation::sigops)
+fn block_sigops_cost(block: &Block) -> u64 {
+    block.transactions.iter().map(sigops_cost).sum()
+}
+
+// returns: target encoded in the compact bits of the header
+fn compact_target(bits: u32) -> BigUint {
+    let exponent = bits >> 24;
+    let mantissa = BigUint::from(bits & 0x007fffff);
+    if exponent <= 3 {
+        mantissa >> (8 * (3 - exponent))
+    } else {
+        mantissa << (8 * (exponent - 3))
+    }
+}
+
+impl Block {
+    // checks the consensus invariants of the block: weight, sigops, duplicate transactions,
+    // parent before child ordering, conflicting spends, fees and coinbase value, merkle root,
+    // witness commitment and proof of work. Used to verify blocks before submitting them.
+    // returns: Vec of all found InvariantViolation, empty if the block is valid
+    pub fn check_invariants(&self, limits: &Limits) -> Vec<InvariantViolation> {
+        let mut violations = Vec::new();
+        let header = hex::decode(&self.header_hex).expect("Header hex invalid");
+
+        let weight = 4 * (header.len() as u64 + varint(self.txids_hex.len() as u128).len() as u64)
+            + self.coinbase_tx.weight
+            + self
+                .transactions
+                .iter()
+                .map(|tx| tx.meta.weight)
+                .sum::<u64>();
+        if weight > limits.max_block_weight {
+            violations.push(InvariantViolation::Weight {
+                weight,
+                limit: limits.max_block_weight,
+            });
+        }
+        let cost = block_sigops_cost(self);
+        if cost > limits.max_block_sigops_cost {
+            violations.push(InvariantViolation::Sigops {
+                cost,
+                limit: limits.max_block_sigops_cost,
+            });
+        }
+
+        let mut positions: HashMap<&str, usize> = HashMap::new();
+        for (position, tx) in self.transactions.iter().enumerate() {
+            if positions.insert(&tx.meta.txid_hex, position).is_some() {
+                violations.push(InvariantViolation::DuplicateTransaction {
+                    txid: tx.meta.txid_hex.clone(),
+                });
+            }
+        }
+        let mut spent: HashMap<(&str, u32), &str> = HashMap::new();
+        let mut fees = 0;
+        for (position, tx) in self.transactions.iter().enumerate() {
+            for txin in &tx.vin {
+                if positions
+                    .get(txin.txid.as_str())
+                    .is_some_and(|parent_position| *parent_position >= position)
+                {
+                    violations.push(InvariantViolation::Ordering {
+                        txid: tx.meta.txid_hex.clone(),
+                        parent: txin.txid.clone(),
+                    });
+                }
+                if spent
+                    .insert((&txin.txid, txin.vout), &tx.meta.txid_hex)
+                    .is_some()
+                {
+                    violations.push(InvariantViolation::Conflict {
+                        outpoint: format!("{}:{}", txin.txid, txin.vout),
+                        txid: tx.meta.txid_hex.clone(),
+                    });
+                }
+            }
+            let inputs: u64 = tx.vin.iter().map(|txin| txin.prevout.value).sum();
+            let outputs: u64 = tx.vout.iter().map(|txout| txout.value).sum();
+            let actual = inputs.checked_sub(outputs);
+            if actual != Some(tx.meta.fee) {
+                violations.push(InvariantViolation::Fee {
+                    txid: tx.meta.txid_hex.clone(),
+                    claimed: tx.meta.fee,
+                    actual,
+                });
+            }
+            fees += actual.unwrap_or(0);
+        }
+        if self.coinbase_tx.reward > BLOCK_SUBSIDY + fees {
+            violations.push(InvariantViolation::CoinbaseValue {
+                reward: self.coinbase_tx.reward,
+                allowed: BLOCK_SUBSIDY + fees,
+            });
+        }
+
+        let merkle_root = get_merkle_root(&get_block_txids_natural(
+            &self.transactions,
+            &self.coinbase_tx,
+        ));
+        if header[36..68] != merkle_root[..] {
+            violations.push(InvariantViolation::MerkleRoot {
+                header: hex::encode(&header[36..68]),
+                computed: hex::encode(&merkle_root),
+            });
+        }
+        let commitment = calc_wtxid_commitment_scriptpubkey(
+            &self.transactions,
+            &self.coinbase_tx.witness_reserved_value,
+        );
+        if !self
+            .coinbase_tx
+            .assembled_tx
+            .windows(commitment.len())
+            .any(|window| window == commitment)
+        {
+            violations.push(InvariantViolation::WitnessCommitment {
+                expected: hex::encode(&commitment),
+            });
+        }
+        let block_hash = double_hash(&header);
+        let bits = u32::from_le_bytes(header[72..76].try_into().unwrap());
+        if BigUint::from_bytes_le(&block_hash) > compact_target(bits) {
+            violations.push(InvariantViolation::ProofOfWork {
+                hash: hex::encode(block_hash.iter().rev().cloned().collect::<Vec<u8>>()),
+            });
+        }
+        violations
+    }
+
+    // checks the block against the chain context it is supposed to extend (e.g. the tip of a
+    // node queried over RPC): the BIP34 height of the coinbase has to be the context height and
+    // the previous block hash of the header the context tip, each mismatch is reported separately
+    // returns: Vec of CoinbaseHeight and PreviousBlockHash violations, empty if consistent
+    pub fn check_chain_context(&self, context: &ChainContext) -> Vec<InvariantViolation> {
+        let mut violations = Vec::new();
+        let header = hex::decode(&self.header_hex).expect("Header hex invalid");
+
+        let height = coinbase_height(&self.coinbase_tx.assembled_tx_no_witness);
+        if height != Some(context.height) {
+            violations.push(InvariantViolation::CoinbaseHeight {
+                coinbase: height,
+                context: context.height,
+            });
+        }
+        if context.previous_block_hash_natural().ok() != header[4..36].try_into().ok() {
+            violations.push(InvariantViolation::PreviousBlockHash {
+                header: hex::encode(header[4..36].iter().rev().cloned().collect::<Vec<u8>>()),
+                context: context.previous_block_hash.clone(),
+            });
+        }
+        violations
+    }
+}
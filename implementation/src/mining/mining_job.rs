@@ -0,0 +1,126 @@
+// Proof of work search on a background thread that reports its progress and can be cancelled,
+// e.g. to abort the search as soon as a new chain tip arrives instead of blocking in
+// mine_nonce() until all nonces are tried.
+
+use crate::validation::utils::double_hash;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+// nonces hashed between two checks of the cancel flag and progress updates
+const NONCE_BATCH: u64 = 4096;
+const NONCE_SPACE: u64 = u32::MAX as u64 + 1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JobResult {
+    Found(u32), // nonce whose header hash is below the target
+    Cancelled,
+    Exhausted, // no nonce of the 2^32 nonce space hashes below the target
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct MiningProgress {
+    pub hashes: u64, // nonces tried so far
+    pub elapsed: Duration,
+    pub finished: bool,
+}
+
+impl MiningProgress {
+    // returns: hashes per second since the start of the job
+    pub fn hashrate(&self) -> f64 {
+        self.hashes as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+
+    // returns: tried share of the nonce space between 0.0 and 1.0
+    pub fn fraction(&self) -> f64 {
+        self.hashes as f64 / NONCE_SPACE as f64
+    }
+}
+
+pub struct MiningJob;
+
+// handle of a running MiningJob, dropping it doesn't stop the search (call cancel())
+pub struct JobHandle {
+    cancelled: Arc<AtomicBool>,
+    hashes: Arc<AtomicU64>,
+    started: Instant,
+    thread: JoinHandle<JobResult>,
+}
+
+// returns: true if the HASH256 (little endian) of the header is below the big endian target
+fn hash_below_target(header: &[u8], target: &[u8; 32]) -> bool {
+    double_hash(header).iter().rev().lt(target.iter())
+}
+
+impl MiningJob {
+    // starts the nonce search for the 76 byte header prefix (header without nonce) and the
+    // big endian target on a new thread
+    // returns: JobHandle to query the progress, cancel or await the JobResult
+    pub fn start(header_prefix: Vec<u8>, target: [u8; 32]) -> JobHandle {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let hashes = Arc::new(AtomicU64::new(0));
+        let thread = {
+            let cancelled = Arc::clone(&cancelled);
+            let hashes = Arc::clone(&hashes);
+            thread::spawn(move || search_nonce(header_prefix, target, &cancelled, &hashes))
+        };
+        JobHandle {
+            cancelled,
+            hashes,
+            started: Instant::now(),
+            thread,
+        }
+    }
+}
+
+// hashes the nonces in batches, after each batch the progress is published and the
+// cancel flag checked
+fn search_nonce(
+    header_prefix: Vec<u8>,
+    target: [u8; 32],
+    cancelled: &AtomicBool,
+    hashes: &AtomicU64,
+) -> JobResult {
+    let mut candidate = header_prefix;
+    candidate.extend(0_u32.to_le_bytes());
+    let nonce_offset = candidate.len() - 4;
+
+    for batch_start in (0..NONCE_SPACE).step_by(NONCE_BATCH as usize) {
+        if cancelled.load(Ordering::Relaxed) {
+            return JobResult::Cancelled;
+        }
+        for nonce in batch_start..batch_start + NONCE_BATCH {
+            candidate[nonce_offset..].copy_from_slice(&(nonce as u32).to_le_bytes());
+            if hash_below_target(&candidate, &target) {
+                hashes.store(nonce + 1, Ordering::Relaxed);
+                return JobResult::Found(nonce as u32);
+            }
+        }
+        hashes.store(batch_start + NONCE_BATCH, Ordering::Relaxed);
+    }
+    JobResult::Exhausted
+}
+
+impl JobHandle {
+    // returns: MiningProgress of the search, updated every NONCE_BATCH nonces
+    pub fn progress(&self) -> MiningProgress {
+        MiningProgress {
+            hashes: self.hashes.load(Ordering::Relaxed),
+            elapsed: self.started.elapsed(),
+            finished: self.thread.is_finished(),
+        }
+    }
+
+    // stops the search after the current batch, await_result() returns JobResult::Cancelled
+    // unless a nonce was found before
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    // blocks until the search found a nonce, got cancelled or exhausted the nonce space
+    // returns: JobResult
+    pub fn await_result(self) -> JobResult {
+        self.thread.join().expect("Mining thread panicked")
+    }
+}
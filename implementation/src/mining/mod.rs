@@ -1,23 +1,91 @@
-mod assign_parents;
-mod construct_coinbase;
+pub mod assign_parents;
+pub mod block_layout;
+pub mod block_template;
+pub mod conflict_graph;
+pub mod construct_coinbase;
+pub mod dependency_graph;
+pub mod extranonce;
+pub mod fee_bump;
 mod header;
+pub mod invariants;
+pub mod mining_job;
 mod packet_weight;
+pub mod rebuilds;
+pub mod serialize_block;
+pub mod simulation;
+pub mod template_delta;
+#[cfg(test)]
+mod tests;
+pub mod timelock;
 mod transaction_sorting;
+pub mod warm_start;
+pub mod work;
 
 use self::{
-    assign_parents::assign_mempool_parents,
-    construct_coinbase::{assemble_coinbase_transaction, CoinbaseTxData},
-    header::construct_header,
+    assign_parents::{assign_mempool_parents, trim_to_package_limits},
+    block_layout::{calculate_block_layout, BlockLayout},
+    conflict_graph::resolve_conflicts,
+    construct_coinbase::{assemble_coinbase_transaction, get_merkle_branch, CoinbaseTxData},
+    header::{construct_header_prefix, get_block_txids_natural},
     packet_weight::calculate_packet_weights,
-    transaction_sorting::{cut_size, sort_transactions},
+    transaction_sorting::{apply_package_floor, cut_size, sort_transactions, BLOCK_WEIGHT_BUDGET},
+    warm_start::{sort_transactions_warm, SelectionHint},
 };
+use crate::chain_context::ChainContext;
+use crate::mempool_policy::MempoolPolicy;
 use crate::parsing::transaction_structs::Transaction;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+// reason why no valid block could be constructed from the selected transactions
+#[derive(Debug, Clone, PartialEq)]
+pub enum MiningError {
+    CoinbaseTxidCollision(String), // a block transaction has the txid of the constructed coinbase
+    DuplicateTxid(String),         // BIP30 like: the txid is contained twice in the block
+    InvalidNonce(u32),             // submitted nonce doesn't produce a hash below the target
+    InvalidPreviousBlockHash(String), // previous block hash of the context isn't 32 bytes hex
+}
+
+impl fmt::Display for MiningError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MiningError::CoinbaseTxidCollision(txid) => {
+                write!(
+                    f,
+                    "block transaction {} collides with the coinbase txid",
+                    txid
+                )
+            }
+            MiningError::DuplicateTxid(txid) => {
+                write!(f, "txid {} is contained twice in the block", txid)
+            }
+            MiningError::InvalidNonce(nonce) => {
+                write!(f, "nonce {} doesn't produce a hash below the target", nonce)
+            }
+            MiningError::InvalidPreviousBlockHash(hash) => {
+                write!(f, "previous block hash {} isn't 32 bytes of hex", hash)
+            }
+        }
+    }
+}
 
 pub struct Block {
     pub header_hex: String,
     pub coinbase_tx_hex: String,
     pub txids_hex: Vec<String>,
+    pub layout: BlockLayout,
+    pub coinbase_tx: CoinbaseTxData,
+    pub merkle_branch: Vec<Vec<u8>>, // coinbase merkle branch for extranonce rolling
+    pub transactions: Vec<Transaction>, // block transactions without coinbase in block order
+}
+
+// constructed block without proof of work, the nonce is searched with UnminedBlock::mine() or
+// by external hashers with the work units of UnminedBlock::work_units()
+pub struct UnminedBlock {
+    pub header_prefix: Vec<u8>, // 76 header bytes without the nonce
+    pub coinbase_tx: CoinbaseTxData,
+    pub transactions: Vec<Transaction>, // block transactions without coinbase in block order
+    pub layout: BlockLayout,
 }
 
 // hex encodes header and coinbase tx and creates a Vec<hex txid String> including
@@ -25,42 +93,134 @@ pub struct Block {
 fn return_block(
     block_header_bytes: &[u8],
     coinbase_tx: CoinbaseTxData,
-    transactions: &Vec<Transaction>,
+    transactions: Vec<Transaction>,
+    layout: BlockLayout,
 ) -> Block {
     let header_hex = hex::encode(block_header_bytes);
-    let coinbase_tx_hex = hex::encode(coinbase_tx.assembled_tx);
-    let mut txids_hex: Vec<String> = vec![coinbase_tx.txid_hex];
-    for tx in transactions {
+    let coinbase_tx_hex = hex::encode(&coinbase_tx.assembled_tx);
+    let merkle_branch = get_merkle_branch(&get_block_txids_natural(&transactions, &coinbase_tx));
+    let mut txids_hex: Vec<String> = vec![coinbase_tx.txid_hex.clone()];
+    for tx in &transactions {
         txids_hex.push(tx.meta.txid_hex.clone());
     }
     Block {
         header_hex,
         coinbase_tx_hex,
         txids_hex,
+        layout,
+        coinbase_tx,
+        merkle_branch,
+        transactions,
     }
 }
 
-// main "mining" function. Takes a HashMap of valid transactions,
-// Returns a Block struct with a blockheader, coinbase transaction and
-// a Vec of txids sorted to maximise fee revenue and block space utilization
-pub fn mine_block(txid_tx_map: &mut HashMap<String, Transaction>) -> Block {
-    // link children with parent transactions
+// removes the losing side of conflicting spends (decided by the ConflictPolicy of the policy)
+// from the map, links children with their mempool parents, removes the transactions exceeding
+// the PackageLimits, calculates the packet weights and sorts the transactions by packet feerate
+// and ancestry. With a SelectionHint of a previous template the sorting starts from its order.
+// Packages below the package feerate floor of the RelayFeerates aren't selected.
+// returns: (all sorted transactions, the leading ones fitting into the block weight budget)
+pub fn select_transactions(
+    txid_tx_map: &mut HashMap<String, Transaction>,
+    hint: Option<&SelectionHint>,
+    policy: &MempoolPolicy,
+) -> (Vec<Transaction>, Vec<Transaction>) {
+    let relay_feerates = &policy.relay_feerates;
+    resolve_conflicts(
+        txid_tx_map,
+        policy.conflict_policy,
+        relay_feerates.incremental_relay,
+    );
     assign_mempool_parents(txid_tx_map);
-
-    // calculate packet weights for transactions with ancestors in mempool
+    trim_to_package_limits(txid_tx_map, &policy.package_limits);
     calculate_packet_weights(txid_tx_map);
+    let sorted_transactions: Vec<Transaction> = match hint {
+        Some(hint) => sort_transactions_warm(txid_tx_map, hint),
+        None => sort_transactions(txid_tx_map),
+    };
+    let sorted_transactions =
+        apply_package_floor(sorted_transactions, relay_feerates.package_floor);
+    let block_ordered: Vec<Transaction> = cut_size(&sorted_transactions);
+    (sorted_transactions, block_ordered)
+}
 
-    // sorts transactions by packet feerate and ancestry and removes enough respect block size
-    let block_ordered: Vec<Transaction> = cut_size(sort_transactions(txid_tx_map));
+// checks that the txids of the block transactions are unique and don't collide with the coinbase
+// returns: Err(MiningError) of the first collision found
+fn check_unique_txids(
+    block_transactions: &[Transaction],
+    coinbase_tx: &CoinbaseTxData,
+) -> Result<(), MiningError> {
+    let mut txids: HashSet<&str> = HashSet::new();
 
+    for tx in block_transactions {
+        if tx.meta.txid_hex == coinbase_tx.txid_hex {
+            return Err(MiningError::CoinbaseTxidCollision(tx.meta.txid_hex.clone()));
+        }
+        if !txids.insert(&tx.meta.txid_hex) {
+            return Err(MiningError::DuplicateTxid(tx.meta.txid_hex.clone()));
+        }
+    }
+    Ok(())
+}
+
+// main "mining" function. Takes a HashMap of valid transactions, the ChainContext to build on
+// and the MempoolPolicy of the selection, Returns a Block struct with a blockheader, coinbase transaction and
+// a Vec of txids sorted to maximise fee revenue and block space utilization
+// or Err(MiningError) if the transactions would produce an invalid block.
+// The optional SelectionHint warm starts the selection from a previous template.
+pub fn mine_block(
+    txid_tx_map: &mut HashMap<String, Transaction>,
+    context: &ChainContext,
+    policy: &MempoolPolicy,
+    hint: Option<&SelectionHint>,
+) -> Result<Block, MiningError> {
+    Ok(prepare_block(txid_tx_map, context, policy, hint)?.mine())
+}
+
+// constructs the block like mine_block() but stops before the proof of work
+// returns: UnminedBlock or Err(MiningError) if the transactions would produce an invalid block
+pub fn prepare_block(
+    txid_tx_map: &mut HashMap<String, Transaction>,
+    context: &ChainContext,
+    policy: &MempoolPolicy,
+    hint: Option<&SelectionHint>,
+) -> Result<UnminedBlock, MiningError> {
+    // links parents, calculates packet weights, sorts transactions by packet feerate and
+    // ancestry and removes enough to respect the block size
+    let (sorted_transactions, block_ordered) = select_transactions(txid_tx_map, hint, policy);
+    let first_excluded = sorted_transactions.get(block_ordered.len());
+    prepare_ordered_block(block_ordered, first_excluded, context)
+}
+
+// constructs the block of already selected transactions in block order like prepare_block(),
+// first_excluded is the next transaction of the selection that didn't fit into the block
+// returns: UnminedBlock or Err(MiningError) if the transactions would produce an invalid block
+pub fn prepare_ordered_block(
+    block_ordered: Vec<Transaction>,
+    first_excluded: Option<&Transaction>,
+    context: &ChainContext,
+) -> Result<UnminedBlock, MiningError> {
     // assembles the coinbase transaction including the witness commitment
-    let coinbase_tx: CoinbaseTxData = assemble_coinbase_transaction(&block_ordered);
+    let coinbase_tx: CoinbaseTxData = assemble_coinbase_transaction(&block_ordered, context);
+    check_unique_txids(&block_ordered, &coinbase_tx)?;
+
+    // records how the packages fill the block and where the weight cutoff landed
+    let layout = calculate_block_layout(
+        &block_ordered,
+        &coinbase_tx,
+        first_excluded,
+        BLOCK_WEIGHT_BUDGET,
+    );
 
-    // assembles the block header
-    let block_header = construct_header(&block_ordered, &coinbase_tx);
+    // assembles the block header without nonce
+    let header_prefix = construct_header_prefix(&block_ordered, &coinbase_tx, context)?;
 
-    // encode in Block struct and returns final data needed for output.txt
-    return_block(&block_header, coinbase_tx, &block_ordered)
+    Ok(UnminedBlock {
+        header_prefix,
+        coinbase_tx,
+        transactions: block_ordered,
+        layout,
+    })
 }
 
 // -----------------------
@@ -1,3 +1,4 @@
+use crate::feerate::FeeRate;
 use crate::parsing::transaction_structs::Transaction;
 use std::collections::HashMap;
 
@@ -43,6 +44,7 @@ pub fn calculate_packet_weights(transactions: &mut HashMap<String, Transaction>)
         tx.meta.packet_data.packet_fee_sat = temp_result.fee;
         tx.meta.packet_data.packet_weight = temp_result.weight;
 
-        tx.meta.packet_data.packet_feerate_weight = temp_result.fee / temp_result.weight;
+        tx.meta.packet_data.packet_feerate =
+            FeeRate::from_fee_weight(temp_result.fee, temp_result.weight);
     }
 }
@@ -0,0 +1,76 @@
+// Rebuilds of the live template mode on background threads. Only the result of the latest
+// rebuild is awaited, the results of the rebuilds it replaced are dropped when they arrive.
+
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub struct Rebuilds<T> {
+    sender: mpsc::Sender<(u64, T)>,
+    receiver: mpsc::Receiver<(u64, T)>,
+    generation: u64, // of the latest rebuild
+    pending: bool,   // the result of the latest rebuild hasn't been returned by wait()
+}
+
+impl<T: Send + 'static> Default for Rebuilds<T> {
+    fn default() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        Rebuilds {
+            sender,
+            receiver,
+            generation: 0,
+            pending: false,
+        }
+    }
+}
+
+impl<T: Send + 'static> Rebuilds<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // runs the rebuild on a new thread, it replaces the pending rebuild
+    pub fn spawn<F>(&mut self, rebuild: F)
+    where
+        F: FnOnce() -> T + Send + 'static,
+    {
+        self.generation += 1;
+        self.pending = true;
+        let (generation, sender) = (self.generation, self.sender.clone());
+        thread::spawn(move || {
+            // the receiver is gone if the Rebuilds were dropped
+            let _ = sender.send((generation, rebuild()));
+        });
+    }
+
+    // drops the result of the pending rebuild when it arrives
+    pub fn discard(&mut self) {
+        self.generation += 1;
+        self.pending = false;
+    }
+
+    // returns: true if a rebuild was spawned whose result wasn't returned by wait()
+    pub fn is_pending(&self) -> bool {
+        self.pending
+    }
+
+    // waits for the result of the pending rebuild, results of replaced rebuilds are dropped
+    // returns: the result or None if no rebuild is pending or it didn't finish within the timeout
+    pub fn wait(&mut self, timeout: Duration) -> Option<T> {
+        if !self.pending {
+            return None;
+        }
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match self.receiver.recv_timeout(remaining) {
+                Ok((generation, result)) if generation == self.generation => {
+                    self.pending = false;
+                    return Some(result);
+                }
+                Ok(_) => continue,
+                Err(_) => return None,
+            }
+        }
+    }
+}
@@ -0,0 +1,16 @@
+use super::Block;
+use crate::validation::utils::varint;
+
+// serializes the complete block: header, transaction count, coinbase transaction and
+// all block transactions (including witnesses if the transaction is segwit)
+// returns: raw block bytes as Vec<u8>
+pub fn serialize_block(block: &Block) -> Vec<u8> {
+    let mut raw_block: Vec<u8> = hex::decode(&block.header_hex).expect("Header hex invalid");
+
+    raw_block.extend(varint(block.transactions.len() as u128 + 1)); // + coinbase
+    raw_block.extend(&block.coinbase_tx.assembled_tx);
+    for tx in &block.transactions {
+        raw_block.extend(tx.serialize(true));
+    }
+    raw_block
+}
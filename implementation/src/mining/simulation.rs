@@ -0,0 +1,178 @@
+use super::timelock::Confirmation;
+use super::{mine_block, Block, MiningError};
+use crate::chain_context::ChainContext;
+use crate::mempool_policy::MempoolPolicy;
+use crate::parsing::transaction_structs::Transaction;
+use crate::validation::includability::classify_includable;
+use crate::validation::utils::double_hash;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use std::collections::{HashMap, VecDeque};
+use std::mem;
+
+// number of blocks the median time past is calculated of
+const MEDIAN_TIME_SPAN: usize = 11;
+
+#[derive(Debug, Clone)]
+pub struct SimulationConfig {
+    pub blocks: usize,       // number of successive blocks to build
+    pub arrival_rate: f64,   // mean transaction arrivals per second
+    pub block_interval: f64, // mean seconds between blocks
+    pub seed: u64,
+}
+
+pub struct SimulationStep {
+    pub block: Block,
+    pub height: u64,
+    pub time: f64, // simulated seconds since the start at which the block was found
+    pub median_time_past: Option<u32>, // MTP the block was built with
+    pub arrived: usize, // transactions that arrived in the mempool since the last block
+    pub deferred: usize, // timelocked mempool transactions (and descendants) not yet includable
+    pub mempool_size: usize, // transactions left in the mempool after the block
+    pub fees: u64,
+    pub cumulative_fees: u64,
+}
+
+// samples an exponentially distributed waiting time for a poisson process with the passed rate
+fn exponential(rng: &mut StdRng, rate: f64) -> f64 {
+    -(1.0 - rng.gen::<f64>()).ln() / rate
+}
+
+// draws poisson arrival times for the arrival transactions in random order. Children never
+// arrive before their parents contained in the arrivals.
+// returns: Vec of (arrival time, Transaction) sorted by arrival time
+fn schedule_arrivals(
+    mut arrivals: Vec<Transaction>,
+    rng: &mut StdRng,
+    rate: f64,
+) -> Vec<(f64, Transaction)> {
+    let mut arrival_times: HashMap<String, f64> = HashMap::new();
+    let mut time = 0.0;
+
+    arrivals.shuffle(rng);
+    for tx in &arrivals {
+        time += exponential(rng, rate);
+        arrival_times.insert(tx.meta.txid_hex.clone(), time);
+    }
+    // children could be scheduled before their parents, push them back until stable
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for tx in &arrivals {
+            let own_time = arrival_times[&tx.meta.txid_hex];
+            let latest_parent = tx
+                .vin
+                .iter()
+                .filter_map(|input| arrival_times.get(&input.txid))
+                .fold(own_time, |latest, parent_time| latest.max(*parent_time));
+            if latest_parent > own_time {
+                arrival_times.insert(tx.meta.txid_hex.clone(), latest_parent);
+                changed = true;
+            }
+        }
+    }
+    let mut scheduled: Vec<(f64, Transaction)> = arrivals
+        .into_iter()
+        .map(|tx| (arrival_times[&tx.meta.txid_hex], tx))
+        .collect();
+    scheduled.sort_by(|a, b| a.0.total_cmp(&b.0));
+    scheduled
+}
+
+// returns: median of the block timestamps
+fn median(timestamps: &VecDeque<u32>) -> u32 {
+    let mut sorted: Vec<u32> = timestamps.iter().copied().collect();
+    sorted.sort_unstable();
+    sorted[sorted.len() / 2]
+}
+
+// replays the valid mempool snapshot and lets the valid arrival transactions flow in over
+// simulated time (poisson arrivals) while blocks are found in exponentially distributed
+// intervals. Each block is built with mine_block() and the MempoolPolicy on top of the previous
+// simulated block.
+// Timelocked transactions stay in the mempool until the first block they are valid in. The
+// block timestamps are the MTP of the context plus the simulated time, the blocks before the
+// simulation are assumed to have the MTP as timestamp.
+// returns: Vec of SimulationStep, one per block, forming the fee revenue time series
+// or the MiningError of the first block that couldn't be constructed
+pub fn simulate_blocks(
+    mut mempool: HashMap<String, Transaction>,
+    arrivals: Vec<Transaction>,
+    context: &ChainContext,
+    policy: &MempoolPolicy,
+    config: &SimulationConfig,
+) -> Result<Vec<SimulationStep>, MiningError> {
+    let mut rng = StdRng::seed_from_u64(config.seed);
+    let mut pending = schedule_arrivals(arrivals, &mut rng, config.arrival_rate).into_iter();
+    let mut next_arrival = pending.next();
+    let mut context = context.clone();
+    let mut steps: Vec<SimulationStep> = Vec::new();
+    let mut time = 0.0;
+    let mut cumulative_fees = 0;
+    let mut confirmed: HashMap<String, Confirmation> = HashMap::new();
+    let start_time = context.median_time_past;
+    let mut timestamps: VecDeque<u32> = vec![start_time.unwrap_or(0); MEDIAN_TIME_SPAN].into();
+
+    for _ in 0..config.blocks {
+        time += exponential(&mut rng, 1.0 / config.block_interval);
+        let mut arrived = 0;
+        while let Some((arrival_time, tx)) = next_arrival.take() {
+            if arrival_time > time {
+                next_arrival = Some((arrival_time, tx));
+                break;
+            }
+            mempool.insert(tx.meta.txid_hex.clone(), tx);
+            arrived += 1;
+            next_arrival = pending.next();
+        }
+
+        // relay-valid transactions (timelocked and their descendants) wait for a later block
+        let sets = classify_includable(mem::take(&mut mempool), &confirmed, &context);
+        mempool = sets.block_valid;
+        let deferred = sets.relay_valid;
+        let block = mine_block(&mut mempool, &context, policy, None)?;
+        let mut fees = 0;
+        for tx in &block.transactions {
+            fees += tx.meta.fee;
+            mempool.remove(&tx.meta.txid_hex);
+            confirmed.insert(
+                tx.meta.txid_hex.clone(),
+                Confirmation {
+                    height: context.height,
+                    median_time_past: context.median_time_past,
+                    coinbase: false,
+                },
+            );
+        }
+        cumulative_fees += fees;
+        let deferred_count = deferred.len();
+        mempool.extend(
+            deferred
+                .into_iter()
+                .map(|(txid, pending)| (txid, pending.tx)),
+        );
+
+        let header = hex::decode(&block.header_hex).expect("Header hex invalid");
+        let block_hash: Vec<u8> = double_hash(&header).into_iter().rev().collect();
+        steps.push(SimulationStep {
+            block,
+            height: context.height,
+            time,
+            median_time_past: context.median_time_past,
+            arrived,
+            deferred: deferred_count,
+            mempool_size: mempool.len(),
+            fees,
+            cumulative_fees,
+        });
+        context.height += 1;
+        context.previous_block_hash = hex::encode(block_hash);
+        if let Some(start_time) = start_time {
+            timestamps.pop_front();
+            timestamps.push_back(start_time + time as u32);
+            context.median_time_past = Some(median(&timestamps));
+        }
+    }
+    Ok(steps)
+}
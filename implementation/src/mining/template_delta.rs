@@ -0,0 +1,220 @@
+// Compact delta between two successive block templates, so consumers polling the builder
+// only receive the changed transactions and header fields instead of the full template
+
+use super::block_template::{BlockTemplate, TemplateTransaction};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AddedTransaction {
+    pub position: usize, // 0-based index in the transactions of the new template
+    pub transaction: TemplateTransaction,
+}
+
+// template fields that changed, None if unchanged
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct HeaderChanges {
+    pub version: Option<u32>,
+    pub previousblockhash: Option<String>,
+    pub coinbasevalue: Option<u64>,
+    pub default_witness_commitment: Option<String>,
+    pub curtime: Option<u32>,
+    pub mintime: Option<u32>,
+    pub maxtime: Option<u32>,
+    pub expires: Option<u32>,
+    pub bits: Option<String>,
+    pub height: Option<u64>,
+}
+
+// transactions that changed their position relative to the others are sent as removed and
+// added again. The depends indices of the kept transactions are remapped when applying.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct TemplateDelta {
+    pub removed: Vec<String>, // txids
+    pub added: Vec<AddedTransaction>,
+    pub header: HeaderChanges,
+}
+
+// returns: Some(next) if the value changed
+fn changed<T: PartialEq + Clone>(previous: &T, next: &T) -> Option<T> {
+    if previous == next {
+        None
+    } else {
+        Some(next.clone())
+    }
+}
+
+// longest subsequence of increasing values (patience sorting)
+// returns: indices into values of one longest strictly increasing subsequence
+fn longest_increasing_subsequence(values: &[usize]) -> Vec<usize> {
+    let mut tails: Vec<usize> = Vec::new(); // index of the smallest tail of each length
+    let mut predecessors: Vec<Option<usize>> = vec![None; values.len()];
+
+    for (index, value) in values.iter().enumerate() {
+        let length = tails.partition_point(|tail| values[*tail] < *value);
+        predecessors[index] = length.checked_sub(1).map(|previous| tails[previous]);
+        if length == tails.len() {
+            tails.push(index);
+        } else {
+            tails[length] = index;
+        }
+    }
+    let mut subsequence = Vec::new();
+    let mut current = tails.last().copied();
+    while let Some(index) = current {
+        subsequence.push(index);
+        current = predecessors[index];
+    }
+    subsequence.reverse();
+    subsequence
+}
+
+// computes the delta turning the previous template into the next one. The largest set of
+// transactions keeping their relative order is kept, all others are removed/added.
+// returns: TemplateDelta
+pub fn template_delta(previous: &BlockTemplate, next: &BlockTemplate) -> TemplateDelta {
+    let previous_positions: HashMap<&str, usize> = previous
+        .transactions
+        .iter()
+        .enumerate()
+        .map(|(position, tx)| (tx.txid.as_str(), position))
+        .collect();
+    // next positions of the transactions contained in both templates
+    let common: Vec<usize> = (0..next.transactions.len())
+        .filter(|position| {
+            previous_positions.contains_key(next.transactions[*position].txid.as_str())
+        })
+        .collect();
+    let previous_order: Vec<usize> = common
+        .iter()
+        .map(|position| previous_positions[next.transactions[*position].txid.as_str()])
+        .collect();
+    let kept: HashSet<&str> = longest_increasing_subsequence(&previous_order)
+        .into_iter()
+        .map(|index| next.transactions[common[index]].txid.as_str())
+        .collect();
+
+    TemplateDelta {
+        removed: previous
+            .transactions
+            .iter()
+            .filter(|tx| !kept.contains(tx.txid.as_str()))
+            .map(|tx| tx.txid.clone())
+            .collect(),
+        added: next
+            .transactions
+            .iter()
+            .enumerate()
+            .filter(|(_, tx)| !kept.contains(tx.txid.as_str()))
+            .map(|(position, tx)| AddedTransaction {
+                position,
+                transaction: tx.clone(),
+            })
+            .collect(),
+        header: HeaderChanges {
+            version: changed(&previous.version, &next.version),
+            previousblockhash: changed(&previous.previousblockhash, &next.previousblockhash),
+            coinbasevalue: changed(&previous.coinbasevalue, &next.coinbasevalue),
+            default_witness_commitment: changed(
+                &previous.default_witness_commitment,
+                &next.default_witness_commitment,
+            ),
+            curtime: changed(&previous.curtime, &next.curtime),
+            mintime: changed(&previous.mintime, &next.mintime),
+            maxtime: changed(&previous.maxtime, &next.maxtime),
+            expires: changed(&previous.expires, &next.expires),
+            bits: changed(&previous.bits, &next.bits),
+            height: changed(&previous.height, &next.height),
+        },
+    }
+}
+
+// applies a delta created by template_delta() to the template it was computed from
+// returns: the next template or Err(message) if the delta doesn't fit the template
+pub fn apply_template_delta(
+    template: &BlockTemplate,
+    delta: &TemplateDelta,
+) -> Result<BlockTemplate, String> {
+    let removed: HashSet<&str> = delta.removed.iter().map(String::as_str).collect();
+    let mut kept: Vec<(TemplateTransaction, Vec<String>)> = Vec::new(); // with parent txids
+    let mut found = 0;
+
+    for tx in &template.transactions {
+        if removed.contains(tx.txid.as_str()) {
+            found += 1;
+            continue;
+        }
+        let parents = tx
+            .depends
+            .iter()
+            .map(|index| {
+                template
+                    .transactions
+                    .get(index.wrapping_sub(1))
+                    .map(|parent| parent.txid.clone())
+                    .ok_or(format!("{} depends on missing index {}", tx.txid, index))
+            })
+            .collect::<Result<Vec<String>, String>>()?;
+        kept.push((tx.clone(), parents));
+    }
+    if found != removed.len() {
+        return Err("delta removes transactions not contained in the template".to_string());
+    }
+
+    let length = kept.len() + delta.added.len();
+    let mut slots: Vec<Option<TemplateTransaction>> = vec![None; length];
+    for added in &delta.added {
+        match slots.get_mut(added.position) {
+            Some(slot @ None) => *slot = Some(added.transaction.clone()),
+            _ => return Err(format!("invalid added position {}", added.position)),
+        }
+    }
+    let mut kept = kept.into_iter();
+    let mut kept_parents: Vec<(usize, Vec<String>)> = Vec::new();
+    for (position, slot) in slots.iter_mut().enumerate() {
+        if slot.is_none() {
+            let (tx, parents) = kept.next().expect("Slot count mismatch");
+            kept_parents.push((position, parents));
+            *slot = Some(tx);
+        }
+    }
+    let mut transactions: Vec<TemplateTransaction> = slots.into_iter().flatten().collect();
+
+    let positions: HashMap<String, usize> = transactions
+        .iter()
+        .enumerate()
+        .map(|(position, tx)| (tx.txid.clone(), position + 1))
+        .collect();
+    for (position, parents) in kept_parents {
+        transactions[position].depends = parents
+            .iter()
+            .map(|parent| {
+                positions
+                    .get(parent)
+                    .copied()
+                    .ok_or(format!("parent {} removed by the delta", parent))
+            })
+            .collect::<Result<Vec<usize>, String>>()?;
+    }
+
+    let header = &delta.header;
+    Ok(BlockTemplate {
+        version: header.version.unwrap_or(template.version),
+        previousblockhash: header
+            .previousblockhash
+            .clone()
+            .unwrap_or(template.previousblockhash.clone()),
+        transactions,
+        coinbasevalue: header.coinbasevalue.unwrap_or(template.coinbasevalue),
+        default_witness_commitment: header
+            .default_witness_commitment
+            .clone()
+            .unwrap_or(template.default_witness_commitment.clone()),
+        curtime: header.curtime.unwrap_or(template.curtime),
+        mintime: header.mintime.unwrap_or(template.mintime),
+        maxtime: header.maxtime.unwrap_or(template.maxtime),
+        expires: header.expires.unwrap_or(template.expires),
+        bits: header.bits.clone().unwrap_or(template.bits.clone()),
+        height: header.height.unwrap_or(template.height),
+    })
+}
@@ -0,0 +1,831 @@
+// Tests of the transaction selection on small synthetic mempools: conflict resolution and the
+// mempool package limits. The transactions are copies of a fixture with the inputs, fee and
+// weight replaced, their txids are short names. The block construction is tested on blocks of
+// the p2pkh fixtures.
+
+use super::assign_parents::{assign_mempool_parents, trim_to_package_limits, PackageLimits};
+use super::block_layout::calculate_block_layout;
+use super::block_template::{
+    build_block_template, BlockTemplate, TemplateTransaction, MAX_FUTURE_BLOCK_TIME,
+    TEMPLATE_LIFETIME,
+};
+use super::conflict_graph::{resolve_conflicts, ConflictPolicy};
+use super::construct_coinbase::{assemble_coinbase_transaction, get_merkle_root};
+use super::extranonce::roll_extranonce;
+use super::fee_bump::simulate_fee_bump;
+use super::header::HEADER_TARGET;
+use super::invariants::{InvariantViolation, Limits};
+use super::mining_job::{JobResult, MiningJob};
+use super::rebuilds::Rebuilds;
+use super::simulation::{simulate_blocks, SimulationConfig};
+use super::template_delta::{apply_template_delta, template_delta, AddedTransaction};
+use super::warm_start::append_to_template;
+use super::{prepare_block, return_block, Block, MiningError, UnminedBlock};
+use crate::chain_context::{parse_block_hash, ChainContext};
+use crate::feerate::FeeRate;
+use crate::mempool_policy::MempoolPolicy;
+use crate::parsing::parse_transactions_from_dir;
+use crate::parsing::transaction_structs::Transaction;
+use crate::validation::utils::double_hash;
+use crate::validation::validate_parsing::validate_txid_hash_filename;
+use crate::validation::validate_values::validate_values_and_set_fee;
+use crate::validation::weight_calculation::validate_and_set_weight;
+use sha2::digest::generic_array::GenericArray;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+const FINAL_SEQUENCE: u32 = 0xffffffff;
+const RBF_SEQUENCE: u32 = 0xfffffffd; // signals replaceability (BIP125)
+
+// returns: transaction named txid spending the outputs (txid, vout) with the sequence
+fn synthetic_tx(
+    txid: &str,
+    inputs: &[(&str, u32)],
+    sequence: u32,
+    fee: u64,
+    weight: u64,
+) -> Transaction {
+    let mut tx = parse_transactions_from_dir("test_scripts/testfiles/p2pkh")
+        .expect("Parsing the fixtures failed")
+        .remove(0);
+    let template = tx.vin[0].clone();
+    tx.vin = inputs
+        .iter()
+        .map(|(parent, vout)| {
+            let mut txin = template.clone();
+            txin.txid = parent.to_string();
+            txin.vout = *vout;
+            txin.outpoint = format!("{}:{}", parent, vout).into_bytes();
+            txin.sequence = sequence;
+            txin
+        })
+        .collect();
+    tx.meta.txid_hex = txid.to_string();
+    tx.meta.fee = fee;
+    tx.meta.weight = weight;
+    tx
+}
+
+fn mempool(transactions: Vec<Transaction>) -> HashMap<String, Transaction> {
+    transactions
+        .into_iter()
+        .map(|tx| (tx.meta.txid_hex.clone(), tx))
+        .collect()
+}
+
+#[test]
+fn two_spenders_keep_the_higher_package_feerate() {
+    let mut transactions = mempool(vec![
+        synthetic_tx("low", &[("confirmed", 0)], FINAL_SEQUENCE, 1000, 400),
+        synthetic_tx("low_child", &[("low", 0)], FINAL_SEQUENCE, 200, 400),
+        synthetic_tx("high", &[("confirmed", 0)], FINAL_SEQUENCE, 4000, 400),
+    ]);
+
+    let removed = resolve_conflicts(
+        &mut transactions,
+        ConflictPolicy::PackageFeerate,
+        FeeRate::default(),
+    );
+    assert_eq!(removed, vec!["low", "low_child"]);
+    assert!(transactions.contains_key("high"));
+}
+
+#[test]
+fn child_fees_count_for_the_package_of_a_spender() {
+    // the child lifts the package feerate of the low fee spender above the other spender
+    let mut transactions = mempool(vec![
+        synthetic_tx("parent", &[("confirmed", 0)], FINAL_SEQUENCE, 1000, 400),
+        synthetic_tx("child", &[("parent", 0)], FINAL_SEQUENCE, 9000, 400),
+        synthetic_tx("other", &[("confirmed", 0)], FINAL_SEQUENCE, 4000, 400),
+    ]);
+
+    let removed = resolve_conflicts(
+        &mut transactions,
+        ConflictPolicy::PackageFeerate,
+        FeeRate::default(),
+    );
+    assert_eq!(removed, vec!["other"]);
+    assert!(transactions.contains_key("parent") && transactions.contains_key("child"));
+}
+
+#[test]
+fn failed_replacement_keeps_the_original() {
+    // higher feerate but a lower absolute fee than the original (BIP125 rule 3)
+    let mut transactions = mempool(vec![
+        synthetic_tx("original", &[("confirmed", 0)], RBF_SEQUENCE, 10000, 1000),
+        synthetic_tx(
+            "replacement",
+            &[("confirmed", 0)],
+            FINAL_SEQUENCE,
+            8000,
+            400,
+        ),
+        synthetic_tx(
+            "replacement_child",
+            &[("replacement", 0)],
+            FINAL_SEQUENCE,
+            100,
+            400,
+        ),
+    ]);
+
+    let removed = resolve_conflicts(
+        &mut transactions,
+        ConflictPolicy::Bip125,
+        FeeRate::default(),
+    );
+    assert_eq!(removed, vec!["replacement", "replacement_child"]);
+    assert!(transactions.contains_key("original"));
+}
+
+#[test]
+fn replacement_of_a_non_signaling_original_needs_full_rbf() {
+    let transactions = mempool(vec![
+        synthetic_tx("original", &[("confirmed", 0)], FINAL_SEQUENCE, 1000, 400),
+        synthetic_tx(
+            "replacement",
+            &[("confirmed", 0)],
+            FINAL_SEQUENCE,
+            20000,
+            400,
+        ),
+    ]);
+
+    let mut bip125 = transactions.clone();
+    let removed = resolve_conflicts(&mut bip125, ConflictPolicy::Bip125, FeeRate::default());
+    assert_eq!(removed, vec!["replacement"]);
+
+    let mut full_rbf = transactions;
+    let removed = resolve_conflicts(&mut full_rbf, ConflictPolicy::FullRbf, FeeRate::default());
+    assert_eq!(removed, vec!["original"]);
+}
+
+#[test]
+fn replaceability_is_inherited_from_mempool_ancestors() {
+    // the original doesn't signal itself, its unconfirmed parent does
+    let mut transactions = mempool(vec![
+        synthetic_tx("parent", &[("confirmed", 0)], RBF_SEQUENCE, 1000, 400),
+        synthetic_tx(
+            "original",
+            &[("parent", 0), ("confirmed", 1)],
+            FINAL_SEQUENCE,
+            1000,
+            400,
+        ),
+        synthetic_tx(
+            "replacement",
+            &[("confirmed", 1)],
+            FINAL_SEQUENCE,
+            20000,
+            400,
+        ),
+    ]);
+
+    let removed = resolve_conflicts(
+        &mut transactions,
+        ConflictPolicy::Bip125,
+        FeeRate::default(),
+    );
+    assert_eq!(removed, vec!["original"]);
+    assert!(transactions.contains_key("parent") && transactions.contains_key("replacement"));
+}
+
+// returns: chain of transactions named 00, 01, .. each spending the previous one
+fn chain(length: usize, fee: u64, weight: u64) -> Vec<Transaction> {
+    (0..length)
+        .map(|index| {
+            let parent = match index {
+                0 => "confirmed".to_string(),
+                _ => format!("{:02}", index - 1),
+            };
+            synthetic_tx(
+                &format!("{:02}", index),
+                &[(&parent, 0)],
+                FINAL_SEQUENCE,
+                fee,
+                weight,
+            )
+        })
+        .collect()
+}
+
+// returns: txids the default package limits remove from the mempool
+fn trimmed(transactions: Vec<Transaction>) -> Vec<String> {
+    let mut transactions = mempool(transactions);
+    assign_mempool_parents(&mut transactions);
+    trim_to_package_limits(&mut transactions, &PackageLimits::default())
+}
+
+#[test]
+fn ancestor_count_limit_removes_the_26th_chain_transaction_and_its_descendants() {
+    // 00 to 24 are within 25 ancestors (including the transaction), 25 and 26 exceed them
+    assert_eq!(trimmed(chain(25, 1000, 400)), Vec::<String>::new());
+    assert_eq!(trimmed(chain(27, 1000, 400)), vec!["25", "26"]);
+}
+
+#[test]
+fn ancestor_size_limit_removes_the_transaction_and_its_descendants() {
+    // 60000 + 50000 vbytes exceed the 101000 vbytes of a transaction with its ancestors
+    let transactions = vec![
+        synthetic_tx("parent", &[("confirmed", 0)], FINAL_SEQUENCE, 60000, 240000),
+        synthetic_tx("child", &[("parent", 0)], FINAL_SEQUENCE, 50000, 200000),
+        synthetic_tx("grandchild", &[("child", 0)], FINAL_SEQUENCE, 1000, 400),
+        synthetic_tx("small_child", &[("parent", 1)], FINAL_SEQUENCE, 1000, 400),
+    ];
+    assert_eq!(trimmed(transactions), vec!["child", "grandchild"]);
+}
+
+#[test]
+fn descendant_count_limit_removes_the_lowest_feerate_child() {
+    // the parent and 24 children reach 25 descendants, the child paying least is rejected
+    let mut transactions = vec![synthetic_tx(
+        "parent",
+        &[("confirmed", 0)],
+        FINAL_SEQUENCE,
+        1000,
+        400,
+    )];
+    transactions.extend((0..25).map(|index| {
+        synthetic_tx(
+            &format!("child_{:02}", index),
+            &[("parent", index)],
+            FINAL_SEQUENCE,
+            1000 + index as u64,
+            400,
+        )
+    }));
+    assert_eq!(trimmed(transactions), vec!["child_00"]);
+}
+
+// returns: template of the transactions named by the txids, in this order and without data
+fn template(txids: &[&str]) -> BlockTemplate {
+    BlockTemplate {
+        version: 0x20000000,
+        previousblockhash: String::new(),
+        transactions: txids
+            .iter()
+            .map(|txid| TemplateTransaction {
+                data: String::new(),
+                txid: txid.to_string(),
+                hash: txid.to_string(),
+                depends: Vec::new(),
+                fee: 0,
+                weight: 0,
+            })
+            .collect(),
+        coinbasevalue: 0,
+        default_witness_commitment: String::new(),
+        curtime: 0,
+        mintime: 0,
+        maxtime: 0,
+        expires: 0,
+        bits: String::new(),
+        height: 0,
+    }
+}
+
+#[test]
+fn incremental_selection_appends_new_transactions_to_the_previous_template() {
+    let mut transactions = mempool(vec![
+        synthetic_tx("parent", &[("confirmed", 0)], FINAL_SEQUENCE, 1000, 400),
+        synthetic_tx("child", &[("parent", 0)], FINAL_SEQUENCE, 1000, 400),
+        // spends the outpoint of the kept parent
+        synthetic_tx("conflict", &[("confirmed", 0)], FINAL_SEQUENCE, 90000, 400),
+        synthetic_tx("low", &[("confirmed", 1)], FINAL_SEQUENCE, 1000, 400),
+        synthetic_tx("high", &[("confirmed", 2)], FINAL_SEQUENCE, 9000, 400),
+        // skipped, its parent is only appended after it by feerate
+        synthetic_tx("orphan", &[("low", 0)], FINAL_SEQUENCE, 50000, 400),
+    ]);
+
+    // "removed" left the mempool since the previous template
+    let block = append_to_template(
+        &mut transactions,
+        &template(&["parent", "removed", "child"]),
+    );
+    let txids: Vec<&str> = block.iter().map(|tx| tx.meta.txid_hex.as_str()).collect();
+    assert_eq!(txids, vec!["parent", "child", "high", "low"]);
+}
+
+#[test]
+fn rebuild_finishing_within_the_budget_is_returned() {
+    let mut rebuilds = Rebuilds::new();
+    rebuilds.spawn(|| "rebuilt");
+    assert_eq!(rebuilds.wait(Duration::from_secs(10)), Some("rebuilt"));
+    // the result is only returned once, nothing is left to wait for
+    assert!(!rebuilds.is_pending());
+    assert_eq!(rebuilds.wait(Duration::from_millis(10)), None);
+}
+
+#[test]
+fn rebuild_exceeding_the_budget_stays_pending() {
+    let (release, released) = mpsc::channel::<()>();
+    let mut rebuilds = Rebuilds::new();
+    rebuilds.spawn(move || {
+        let _ = released.recv();
+        "rebuilt"
+    });
+    assert_eq!(rebuilds.wait(Duration::from_millis(10)), None);
+    assert!(rebuilds.is_pending());
+    release.send(()).unwrap();
+    assert_eq!(rebuilds.wait(Duration::from_secs(10)), Some("rebuilt"));
+}
+
+#[test]
+fn results_of_replaced_and_discarded_rebuilds_are_dropped() {
+    let mut rebuilds = Rebuilds::new();
+    rebuilds.spawn(|| "replaced");
+    rebuilds.spawn(|| "latest");
+    assert_eq!(rebuilds.wait(Duration::from_secs(10)), Some("latest"));
+
+    rebuilds.spawn(|| "discarded");
+    rebuilds.discard();
+    assert_eq!(rebuilds.wait(Duration::from_millis(10)), None);
+}
+
+#[test]
+fn layout_groups_children_with_all_their_parents() {
+    let mut transactions = vec![
+        synthetic_tx("parent", &[("confirmed", 0)], FINAL_SEQUENCE, 1000, 400),
+        synthetic_tx("single", &[("confirmed", 1)], FINAL_SEQUENCE, 3000, 800),
+        synthetic_tx(
+            "second_parent",
+            &[("confirmed", 2)],
+            FINAL_SEQUENCE,
+            500,
+            400,
+        ),
+        synthetic_tx(
+            "child",
+            &[("parent", 0), ("second_parent", 0)],
+            FINAL_SEQUENCE,
+            2000,
+            600,
+        ),
+    ];
+    transactions[3].meta.parents = Some(vec!["parent".into(), "second_parent".into()]);
+    let excluded = synthetic_tx("excluded", &[("confirmed", 3)], FINAL_SEQUENCE, 100, 1000);
+    let coinbase_tx = assemble_coinbase_transaction(&Vec::new(), &ChainContext::default());
+
+    let layout = calculate_block_layout(&transactions, &coinbase_tx, Some(&excluded), 2500);
+    // header and the 1 byte transaction count precede the coinbase
+    let start = 320 + 4 + coinbase_tx.weight;
+    assert_eq!(layout.coinbase_weight, coinbase_tx.weight);
+    assert_eq!(layout.used_weight, start + 2200);
+
+    // the child joins the package of its first parent and merges the second one into it
+    let members = |package: usize| -> Vec<(&str, u64)> {
+        layout.packages[package]
+            .transactions
+            .iter()
+            .map(|tx| (tx.txid.as_str(), tx.offset))
+            .collect()
+    };
+    assert_eq!(layout.packages.len(), 2);
+    let (package, single) = (&layout.packages[0], &layout.packages[1]);
+    assert_eq!(
+        (package.offset, package.weight, package.fee),
+        (start, 1400, 3500)
+    );
+    assert_eq!(
+        members(0),
+        vec![
+            ("parent", start),
+            ("second_parent", start + 1200),
+            ("child", start + 1600)
+        ]
+    );
+    assert_eq!(
+        (single.offset, single.weight, single.fee),
+        (start + 400, 800, 3000)
+    );
+    assert_eq!(members(1), vec![("single", start + 400)]);
+    assert_eq!(layout.cutoff.weight_budget, 2500);
+    assert_eq!(
+        layout.cutoff.first_excluded_txid.as_deref(),
+        Some("excluded")
+    );
+    assert_eq!(layout.cutoff.first_excluded_weight, Some(1000));
+}
+
+// returns: the p2pkh fixtures with txids, weights and fees set as by the validation
+fn fixtures() -> Vec<Transaction> {
+    let mut transactions = parse_transactions_from_dir("test_scripts/testfiles/p2pkh")
+        .expect("Parsing the fixtures failed");
+    for tx in &mut transactions {
+        assert!(validate_txid_hash_filename(tx) && validate_and_set_weight(tx));
+        validate_values_and_set_fee(tx).expect("Invalid fixture values");
+    }
+    transactions
+}
+
+// returns: block of the p2pkh fixtures on top of the context, without proof of work
+fn fixture_block(context: &ChainContext) -> UnminedBlock {
+    prepare_block(
+        &mut mempool(fixtures()),
+        context,
+        &MempoolPolicy::default(),
+        None,
+    )
+    .expect("Constructing the fixture block failed")
+}
+
+// the nonce search takes seconds in debug builds, most tests assemble their blocks themselves
+// returns: Block of the UnminedBlock with the nonce, which isn't checked against any target
+fn assembled(block: UnminedBlock, nonce: u32) -> Block {
+    let mut header = block.header_prefix;
+    header.extend(nonce.to_le_bytes());
+    return_block(&header, block.coinbase_tx, block.transactions, block.layout)
+}
+
+// returns: first nonce whose header hash is below the 0x1f00ffff target of the default context
+// (about 1 in 2^16), far above the HEADER_TARGET of the nonce search
+fn nonce_below_bits(header_prefix: &[u8]) -> u32 {
+    (0..=u32::MAX)
+        .find(|nonce| {
+            let mut header = header_prefix.to_vec();
+            header.extend(nonce.to_le_bytes());
+            let hash = double_hash(&header); // little endian
+            hash[30..32] == [0, 0] && hash[28..30] != [0xff, 0xff]
+        })
+        .expect("No nonce meets the target")
+}
+
+// returns: merkle root of the coinbase txid (display hex) and the block transactions
+fn merkle_root(coinbase_txid_hex: &str, transactions: &[Transaction]) -> Vec<u8> {
+    let leaves: Vec<Vec<u8>> = std::iter::once(coinbase_txid_hex)
+        .chain(transactions.iter().map(|tx| tx.meta.txid_hex.as_str()))
+        .map(|txid| hex::decode(txid).unwrap().into_iter().rev().collect())
+        .collect();
+    get_merkle_root(&leaves)
+}
+
+#[test]
+fn rolled_extranonce_updates_the_coinbase_txid_and_merkle_root() {
+    let block = assembled(fixture_block(&ChainContext::default()), 0);
+    let header = hex::decode(&block.header_hex).unwrap();
+    assert_eq!(block.transactions.len(), 3);
+
+    // the reserved region is zeroed, rolling zeros reproduces the block
+    let unchanged = roll_extranonce(&block, &[0; 4], &[0; 4]).unwrap();
+    assert_eq!(unchanged.txid_hex, block.coinbase_tx.txid_hex);
+    assert_eq!(unchanged.coinbase_tx, block.coinbase_tx.assembled_tx);
+    assert_eq!(unchanged.merkle_root, header[36..68]);
+
+    let rolled = roll_extranonce(&block, &[1, 2, 3, 4], &[5, 6, 7, 8]).unwrap();
+    let offset = block.coinbase_tx.extranonce_offset + 2; // behind marker and flag
+    assert_eq!(
+        rolled.coinbase_tx[offset..offset + 8],
+        [1, 2, 3, 4, 5, 6, 7, 8]
+    );
+    assert_ne!(rolled.txid_hex, block.coinbase_tx.txid_hex);
+    assert_eq!(
+        rolled.merkle_root,
+        merkle_root(&rolled.txid_hex, &block.transactions)
+    );
+
+    assert!(roll_extranonce(&block, &[1, 2, 3], &[5, 6, 7, 8]).is_err());
+    assert!(roll_extranonce(&block, &[1, 2, 3, 4], &[5, 6, 7, 8, 9]).is_err());
+}
+
+#[test]
+fn fee_bump_of_a_child_moves_its_package_into_the_block() {
+    // 9 of the 10 fillers (about 5 sat/vB) fill the block weight budget
+    let mut transactions: Vec<Transaction> = (0..10)
+        .map(|index| {
+            let txid = format!("filler_{}", index);
+            synthetic_tx(
+                &txid,
+                &[("confirmed", index)],
+                FINAL_SEQUENCE,
+                500000,
+                397000,
+            )
+        })
+        .collect();
+    transactions.push(synthetic_tx(
+        "parent",
+        &[("confirmed", 10)],
+        FINAL_SEQUENCE,
+        400,
+        400,
+    ));
+    transactions.push(synthetic_tx(
+        "child",
+        &[("parent", 0)],
+        FINAL_SEQUENCE,
+        400,
+        400,
+    ));
+    let transactions = mempool(transactions);
+    let policy = MempoolPolicy::default();
+
+    let unchanged = simulate_fee_bump(&transactions, "child", 0, &policy).unwrap();
+    assert!(!unchanged.included);
+    assert_eq!(unchanged.position, None);
+
+    // the package of parent and child pays 54 sat/vB after the bump
+    let bumped = simulate_fee_bump(&transactions, "child", 10000, &policy).unwrap();
+    assert!(bumped.included);
+    assert_eq!(bumped.position, Some(1));
+    assert_eq!((bumped.packet_fee_sat, bumped.packet_weight), (10800, 800));
+    assert_eq!(bumped.packet_feerate, FeeRate::from_fee_vsize(10800, 200));
+    // the bump is simulated on a copy
+    assert_eq!(transactions["child"].meta.fee, 400);
+
+    assert!(simulate_fee_bump(&transactions, "unknown", 10000, &policy).is_err());
+}
+
+#[test]
+fn template_ntime_range_starts_after_the_median_time_past() {
+    let context = ChainContext {
+        median_time_past: Some(1_700_000_000),
+        ..ChainContext::default()
+    };
+    let template = build_block_template(&assembled(fixture_block(&context), 0), &context);
+    assert_eq!(template.previousblockhash, context.previous_block_hash);
+    assert_eq!(template.height, context.height);
+    assert_eq!(template.transactions.len(), 3);
+    assert_eq!(template.mintime, 1_700_000_001);
+    assert!(template.curtime >= template.mintime);
+    assert_eq!(template.maxtime, template.curtime + MAX_FUTURE_BLOCK_TIME);
+    assert_eq!(template.expires, template.curtime + TEMPLATE_LIFETIME);
+
+    // without the median time past the header time is only rolled forward
+    let context = ChainContext::default();
+    let template = build_block_template(&assembled(fixture_block(&context), 0), &context);
+    assert_eq!(template.mintime, template.curtime);
+}
+
+#[test]
+fn previous_block_hash_has_to_be_32_bytes_of_hex() {
+    let hash = "00000000000000000001901B9F3B6C7A0C34B20B29B950D0D8FFA36C63979C1C";
+    assert_eq!(parse_block_hash(hash), Ok(hash.to_ascii_lowercase()));
+    assert!(parse_block_hash(&hash[2..]).is_err());
+    assert!(parse_block_hash(&hash.replace('F', "g")).is_err());
+
+    let context = ChainContext {
+        previous_block_hash: "00ff".to_string(),
+        ..ChainContext::default()
+    };
+    let result = prepare_block(
+        &mut mempool(fixtures()),
+        &context,
+        &MempoolPolicy::default(),
+        None,
+    );
+    assert_eq!(
+        result.err(),
+        Some(MiningError::InvalidPreviousBlockHash("00ff".to_string()))
+    );
+}
+
+#[test]
+fn simulated_blocks_build_on_each_other() {
+    let mut arrivals = fixtures();
+    let snapshot = mempool(vec![arrivals.remove(0)]);
+    let snapshot_txid = snapshot.keys().next().unwrap().clone();
+    let context = ChainContext {
+        median_time_past: Some(1_700_000_000),
+        ..ChainContext::default()
+    };
+    // transactions arrive far faster than blocks are found
+    let config = SimulationConfig {
+        blocks: 2,
+        arrival_rate: 1.0,
+        block_interval: 600.0,
+        seed: 7,
+    };
+    let steps = simulate_blocks(
+        snapshot,
+        arrivals,
+        &context,
+        &MempoolPolicy::default(),
+        &config,
+    )
+    .unwrap();
+    assert_eq!(steps.len(), 2);
+    let (first, second) = (&steps[0], &steps[1]);
+
+    assert_eq!(
+        (first.height, second.height),
+        (context.height, context.height + 1)
+    );
+    assert!(first.time < second.time);
+    let first_header = hex::decode(&first.block.header_hex).unwrap();
+    let second_header = hex::decode(&second.block.header_hex).unwrap();
+    assert_eq!(
+        second_header[4..36],
+        double_hash(&first_header)[..],
+        "the second block doesn't commit to the first one"
+    );
+
+    // the snapshot and both arrivals are confirmed in the first block, nothing is left
+    let txids: Vec<&str> = first.block.txids_hex[1..]
+        .iter()
+        .map(String::as_str)
+        .collect();
+    assert!(txids.contains(&snapshot_txid.as_str()) && txids.len() == 3);
+    assert_eq!((first.arrived, first.mempool_size), (2, 0));
+    assert!(second.block.transactions.is_empty());
+    let fees: u64 = first.block.transactions.iter().map(|tx| tx.meta.fee).sum();
+    assert_eq!((first.fees, first.cumulative_fees), (fees, fees));
+    assert_eq!((second.fees, second.cumulative_fees), (0, fees));
+    assert_eq!(second.median_time_past, Some(1_700_000_000));
+}
+
+#[test]
+fn invariant_checker_reports_tampered_blocks() {
+    let context = ChainContext::default();
+    let block = fixture_block(&context);
+    let nonce = nonce_below_bits(&block.header_prefix);
+    let mut block = assembled(block, nonce);
+    assert_eq!(block.check_invariants(&Limits::default()), Vec::new());
+    assert_eq!(block.check_chain_context(&context), Vec::new());
+
+    let weight_limit = Limits {
+        max_block_weight: 4000,
+        ..Limits::default()
+    };
+    assert!(matches!(
+        block.check_invariants(&weight_limit)[..],
+        [InvariantViolation::Weight { limit: 4000, .. }]
+    ));
+
+    // the claimed fee isn't the input minus the output sum, the coinbase claims one sat more
+    let actual = block.transactions[1].meta.fee;
+    block.transactions[1].meta.fee += 1000;
+    block.coinbase_tx.reward += 1;
+    let allowed = block.coinbase_tx.reward - 1;
+    assert_eq!(
+        block.check_invariants(&Limits::default()),
+        vec![
+            InvariantViolation::Fee {
+                txid: block.transactions[1].meta.txid_hex.clone(),
+                claimed: actual + 1000,
+                actual: Some(actual),
+            },
+            InvariantViolation::CoinbaseValue {
+                reward: allowed + 1,
+                allowed,
+            },
+        ]
+    );
+
+    // a transaction included twice spends its outpoints twice and changes the merkle root
+    let duplicate = block.transactions[0].clone();
+    block.transactions.push(duplicate);
+    let violations = block.check_invariants(&Limits::default());
+    let txid = block.transactions[0].meta.txid_hex.clone();
+    assert!(violations.contains(&InvariantViolation::DuplicateTransaction { txid }));
+    assert!(violations
+        .iter()
+        .any(|violation| matches!(violation, InvariantViolation::Conflict { .. })));
+    assert!(violations
+        .iter()
+        .any(|violation| matches!(violation, InvariantViolation::MerkleRoot { .. })));
+
+    let next = ChainContext {
+        height: context.height + 1,
+        previous_block_hash: "00".repeat(32),
+        ..ChainContext::default()
+    };
+    assert_eq!(
+        block.check_chain_context(&next),
+        vec![
+            InvariantViolation::CoinbaseHeight {
+                coinbase: Some(context.height),
+                context: next.height,
+            },
+            InvariantViolation::PreviousBlockHash {
+                header: context.previous_block_hash.clone(),
+                context: next.previous_block_hash.clone(),
+            },
+        ]
+    );
+}
+
+// header of the genesis block, its hash is far below the HEADER_TARGET
+const GENESIS_HEADER: &str = "0100000000000000000000000000000000000000000000000000000000000000000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a29ab5f49ffff001d1dac2b7c";
+const GENESIS_NONCE: u32 = 0x7c2bac1d;
+
+#[test]
+fn work_units_cover_all_nonces_and_resume_from_the_midstate() {
+    let block = fixture_block(&ChainContext::default());
+    let units = block.work_units(3);
+    assert_eq!(units.len(), 3);
+    assert_eq!(*units[0].nonce_range.start(), 0);
+    assert_eq!(*units[2].nonce_range.end(), u32::MAX);
+    for pair in units.windows(2) {
+        assert_eq!(*pair[0].nonce_range.end() + 1, *pair[1].nonce_range.start());
+    }
+    let unit = &units[1];
+    assert_eq!(unit.target, HEADER_TARGET);
+    assert_eq!(unit.tail, block.header_prefix[64..76]);
+
+    // a hasher finishes the first SHA256 of the header with the padded second block
+    let nonce = *unit.nonce_range.start();
+    let mut state: [u32; 8] = [0; 8];
+    for (word, bytes) in state.iter_mut().zip(unit.midstate.chunks(4)) {
+        *word = u32::from_be_bytes(bytes.try_into().unwrap());
+    }
+    let mut second_block = unit.tail.clone();
+    second_block.extend(nonce.to_le_bytes());
+    second_block.push(0x80);
+    second_block.resize(56, 0);
+    second_block.extend((80u64 * 8).to_be_bytes());
+    sha2::compress256(&mut state, &[*GenericArray::from_slice(&second_block)]);
+    let mut header = block.header_prefix.clone();
+    header.extend(nonce.to_le_bytes());
+    let digest: Vec<u8> = state.iter().flat_map(|word| word.to_be_bytes()).collect();
+    assert_eq!(digest, Sha256::digest(&header).to_vec());
+}
+
+#[test]
+fn submitted_nonces_are_checked_against_the_target() {
+    let genesis = hex::decode(GENESIS_HEADER).unwrap();
+    let unmined = || UnminedBlock {
+        header_prefix: genesis[..76].to_vec(),
+        ..fixture_block(&ChainContext::default())
+    };
+    assert!(unmined().check_nonce(GENESIS_NONCE));
+    assert_eq!(
+        unmined().submit_nonce(GENESIS_NONCE + 1).err(),
+        Some(MiningError::InvalidNonce(GENESIS_NONCE + 1))
+    );
+    let block = unmined().submit_nonce(GENESIS_NONCE).unwrap();
+    assert_eq!(block.header_hex, GENESIS_HEADER);
+    assert_eq!(block.txids_hex.len(), 4);
+}
+
+#[test]
+fn applied_template_delta_reproduces_the_next_template() {
+    // c depends on b, which moves in front of a
+    let mut previous = template(&["a", "b", "c"]);
+    previous.transactions[2].depends = vec![2];
+    let mut next = template(&["b", "e", "c", "a"]);
+    next.transactions[2].depends = vec![1];
+    next.curtime = 20;
+
+    let delta = template_delta(&previous, &next);
+    assert_eq!(delta.removed, vec!["a"]);
+    let added: Vec<(usize, &str)> = delta
+        .added
+        .iter()
+        .map(|added| (added.position, added.transaction.txid.as_str()))
+        .collect();
+    assert_eq!(added, vec![(1, "e"), (3, "a")]);
+    assert_eq!(delta.header.curtime, Some(20));
+    assert_eq!(delta.header.height, None);
+    assert_eq!(apply_template_delta(&previous, &delta), Ok(next.clone()));
+
+    // the delta doesn't fit other templates
+    assert!(apply_template_delta(&template(&["b", "c"]), &delta).is_err());
+    let mut outside = delta.clone();
+    outside.added.push(AddedTransaction {
+        position: 9,
+        transaction: next.transactions[1].clone(),
+    });
+    assert!(apply_template_delta(&previous, &outside).is_err());
+}
+
+#[test]
+fn mining_job_reports_the_first_nonce_below_the_target() {
+    let header_prefix = hex::decode(GENESIS_HEADER).unwrap()[..76].to_vec();
+    // about 1 in 256 hashes starts with a zero byte
+    let mut target = [0xff; 32];
+    target[0] = 0;
+    let job = MiningJob::start(header_prefix.clone(), target);
+    while !job.progress().finished {
+        thread::sleep(Duration::from_millis(1));
+    }
+    let progress = job.progress();
+    let nonce = match job.await_result() {
+        JobResult::Found(nonce) => nonce,
+        result => panic!("No nonce found: {:?}", result),
+    };
+    assert_eq!(progress.hashes, nonce as u64 + 1);
+    let below_target = |nonce: u32| {
+        let mut header = header_prefix.clone();
+        header.extend(nonce.to_le_bytes());
+        double_hash(&header)[31] == 0
+    };
+    assert!(below_target(nonce));
+    assert!(!(0..nonce).any(below_target));
+}
+
+#[test]
+fn cancelled_mining_job_reports_its_progress_and_stops() {
+    let header_prefix = hex::decode(GENESIS_HEADER).unwrap()[..76].to_vec();
+    // no hash is below a zero target
+    let job = MiningJob::start(header_prefix, [0; 32]);
+    while job.progress().hashes == 0 {
+        thread::sleep(Duration::from_millis(1));
+    }
+    job.cancel();
+    let progress = job.progress();
+    assert!(progress.fraction() > 0.0 && progress.fraction() < 1.0);
+    assert_eq!(job.await_result(), JobResult::Cancelled);
+}
@@ -0,0 +1,94 @@
+// Absolute (nLockTime) and relative (BIP68 nSequence) timelock checks of the transactions
+// against the block they would be included in
+
+use crate::parsing::transaction_structs::Transaction;
+
+// locktimes below are block heights, at or above unix timestamps
+pub const LOCKTIME_THRESHOLD: u32 = 500_000_000;
+// confirmations a coinbase output needs before it can be spent
+pub const COINBASE_MATURITY: u64 = 100;
+pub const SEQUENCE_FINAL: u32 = 0xffffffff;
+pub const SEQUENCE_LOCKTIME_DISABLE_FLAG: u32 = 1 << 31;
+pub const SEQUENCE_LOCKTIME_TYPE_FLAG: u32 = 1 << 22;
+pub const SEQUENCE_LOCKTIME_MASK: u32 = 0x0000ffff;
+const SEQUENCE_LOCKTIME_GRANULARITY: u32 = 9; // time based locks in units of 512 seconds
+
+// confirmation of an output spent by a transaction
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Confirmation {
+    pub height: u64,
+    pub median_time_past: Option<u32>, // MTP of the block before the confirming block
+    pub coinbase: bool,                // the spent output is a coinbase output
+}
+
+// checks the nLockTime against the height and the MTP of the block, time based locktimes
+// can't be checked if the MTP is unknown and are considered final
+// returns: true if the transaction can be included in the block
+pub fn is_final(tx: &Transaction, height: u64, median_time_past: Option<u32>) -> bool {
+    if tx.locktime == 0 || tx.vin.iter().all(|txin| txin.sequence == SEQUENCE_FINAL) {
+        return true;
+    }
+    if tx.locktime < LOCKTIME_THRESHOLD {
+        (tx.locktime as u64) < height
+    } else {
+        median_time_past.is_none_or(|time| tx.locktime < time)
+    }
+}
+
+// checks the BIP68 relative locktimes (version 2 transactions, inputs without disable flag)
+// against the block. confirmation returns the confirmation of the spent output of input index,
+// None if the output is unconfirmed, outputs confirmed before the snapshot have to be
+// reported with an old enough confirmation.
+// returns: true if all relative locktimes are satisfied in the block
+pub fn sequence_locks_satisfied(
+    tx: &Transaction,
+    height: u64,
+    median_time_past: Option<u32>,
+    confirmation: impl Fn(usize) -> Option<Confirmation>,
+) -> bool {
+    if tx.version < 2 {
+        return true;
+    }
+    for (index, txin) in tx.vin.iter().enumerate() {
+        if txin.sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG != 0 {
+            continue;
+        }
+        let value = txin.sequence & SEQUENCE_LOCKTIME_MASK;
+        // unconfirmed outputs are confirmed in the same block
+        let spent = confirmation(index).unwrap_or(Confirmation {
+            height,
+            median_time_past,
+            coinbase: false,
+        });
+        let satisfied = if txin.sequence & SEQUENCE_LOCKTIME_TYPE_FLAG == 0 {
+            spent.height + value as u64 <= height
+        } else {
+            match (spent.median_time_past, median_time_past) {
+                (Some(confirmed), Some(time)) => {
+                    confirmed as u64 + ((value as u64) << SEQUENCE_LOCKTIME_GRANULARITY)
+                        <= time as u64
+                }
+                // unknown MTP: only outputs confirmed in an earlier block can satisfy the lock
+                _ => value == 0 || confirmation(index).is_some(),
+            }
+        };
+        if !satisfied {
+            return false;
+        }
+    }
+    true
+}
+
+// checks that the coinbase outputs spent by the transaction have COINBASE_MATURITY
+// confirmations in the block, confirmation as in sequence_locks_satisfied()
+// returns: true if no input spends an immature coinbase output
+pub fn coinbase_spends_mature(
+    tx: &Transaction,
+    height: u64,
+    confirmation: impl Fn(usize) -> Option<Confirmation>,
+) -> bool {
+    (0..tx.vin.len()).all(|index| match confirmation(index) {
+        Some(spent) if spent.coinbase => spent.height + COINBASE_MATURITY <= height,
+        _ => true,
+    })
+}
@@ -1,55 +1,50 @@
+use crate::feerate::FeeRate;
 use crate::parsing::transaction_structs::Transaction;
-use std::collections::HashMap;
+use crate::validation::sigops::MAX_BLOCK_SIGOPS_COST;
+use std::collections::{HashMap, HashSet};
 
-// returns the index of txid in Vec<Transaction> transactions.
-fn get_parent_index(transactions: &Vec<Transaction>, txid: &String) -> usize {
-    let mut parent_index: usize = 0;
-
-    for tx in transactions {
-        if *tx.meta.txid_hex == *txid {
-            break;
-        };
-        parent_index += 1;
-    }
-    parent_index
-}
-
-// gets called by put_parents_in_front to take the Transaction at parent_index and put it in front of
-// child_index
-fn push_parent_in_front(
-    transactions: &mut Vec<Transaction>,
-    parent_index: usize,
-    child_index: usize,
+// appends the transaction at index to ordered after its not yet ordered mempool ancestors,
+// parents in the order of meta.parents. Parents missing from the slice are skipped.
+fn order_with_ancestors(
+    presorted: &[Transaction],
+    index: usize,
+    positions: &HashMap<&str, usize>,
+    ordered: &mut Vec<usize>,
+    placed: &mut [bool],
 ) {
-    if parent_index < transactions.len() && child_index < transactions.len() {
-        let parent = transactions.remove(parent_index);
-        transactions.insert(child_index, parent);
+    if placed[index] {
+        return;
+    }
+    placed[index] = true;
+    for parent_txid in presorted[index].meta.parents.iter().flatten() {
+        if let Some(&parent_index) = positions.get(parent_txid.as_str()) {
+            order_with_ancestors(presorted, parent_index, positions, ordered, placed);
+        }
     }
+    ordered.push(index);
 }
 
-// puts parents in front of their children in the presorted Vec<Transaction>
-fn put_parents_in_front(presorted: &mut Vec<Transaction>) {
-    let mut nothing_changed: bool = false;
-
-    'outer: while !nothing_changed {
-        nothing_changed = true;
-        let mut tx_index: usize = 0;
-
-        let transactions_cloned = presorted.clone();
-        for tx in transactions_cloned.iter() {
-            if let Some(parents) = tx.meta.parents.as_ref() {
-                for parent_txid in parents {
-                    let parent_index = get_parent_index(presorted, parent_txid);
-                    if parent_index > tx_index {
-                        push_parent_in_front(presorted, parent_index, tx_index);
-                        nothing_changed = false;
-                        continue 'outer;
-                    };
-                }
-            };
-            tx_index += 1;
-        }
+// puts parents in front of their children in the presorted Vec<Transaction>: every transaction
+// keeps its position unless it is pulled directly in front of its first descendant
+pub fn put_parents_in_front(presorted: &mut Vec<Transaction>) {
+    let positions: HashMap<&str, usize> = presorted
+        .iter()
+        .enumerate()
+        .map(|(index, tx)| (tx.meta.txid_hex.as_str(), index))
+        .collect();
+    let mut ordered: Vec<usize> = Vec::with_capacity(presorted.len());
+    let mut placed: Vec<bool> = vec![false; presorted.len()];
+    for index in 0..presorted.len() {
+        order_with_ancestors(presorted, index, &positions, &mut ordered, &mut placed);
     }
+    drop(positions);
+
+    let mut transactions: Vec<Option<Transaction>> = presorted.drain(..).map(Some).collect();
+    presorted.extend(
+        ordered
+            .into_iter()
+            .filter_map(|index| transactions[index].take()),
+    );
 }
 
 // entry function for sorting. sorts by packet feerate, then puts the parents in front
@@ -59,8 +54,8 @@ pub fn sort_transactions(txid_tx_map: &HashMap<String, Transaction>) -> Vec<Tran
     transactions.sort_by(|a, b: &&Transaction| {
         b.meta
             .packet_data
-            .packet_feerate_weight
-            .cmp(&a.meta.packet_data.packet_feerate_weight)
+            .packet_feerate
+            .cmp(&a.meta.packet_data.packet_feerate)
     });
 
     let mut sorted_transactions: Vec<Transaction> = transactions.into_iter().cloned().collect();
@@ -69,15 +64,43 @@ pub fn sort_transactions(txid_tx_map: &HashMap<String, Transaction>) -> Vec<Tran
     sorted_transactions
 }
 
-// removes enough Transactions from the sorted Vec<Transaction> to respect the
-// block size limit of 4 000 000 weight units
-pub fn cut_size(sorted_transactions: Vec<Transaction>) -> Vec<Transaction> {
+// removes the transactions whose packet feerate is below the package feerate floor, unless
+// they are ancestors of a kept transaction (low fee parents of a CPFP package stay with the
+// child paying for them). Parents have to be in front of their children.
+pub fn apply_package_floor(
+    sorted_transactions: Vec<Transaction>,
+    floor: FeeRate,
+) -> Vec<Transaction> {
+    let mut needed_parents: HashSet<String> = HashSet::new();
+    let mut kept: Vec<Transaction> = Vec::new();
+
+    for tx in sorted_transactions.into_iter().rev() {
+        if tx.meta.packet_data.packet_feerate >= floor || needed_parents.contains(&tx.meta.txid_hex)
+        {
+            needed_parents.extend(tx.meta.parents.iter().flatten().cloned());
+            kept.push(tx);
+        }
+    }
+    kept.reverse();
+    kept
+}
+
+// weight units available for mempool transactions (header and coinbase need some space too)
+pub const BLOCK_WEIGHT_BUDGET: u64 = 3970000;
+// sigops cost available for mempool transactions, the rest is reserved for the coinbase
+pub const BLOCK_SIGOPS_BUDGET: u64 = MAX_BLOCK_SIGOPS_COST - 400;
+
+// copies the best Transactions from the sorted slice of Transactions until the
+// block size limit of 4 000 000 weight units or the sigops limit would be exceeded
+pub fn cut_size(sorted_transactions: &[Transaction]) -> Vec<Transaction> {
     let mut block: Vec<Transaction> = Vec::new();
-    let mut free_block_space: i64 = 3970000;
+    let mut free_block_space: i64 = BLOCK_WEIGHT_BUDGET as i64;
+    let mut free_sigops: u64 = BLOCK_SIGOPS_BUDGET;
     for tx in sorted_transactions {
-        if free_block_space > tx.meta.weight as i64 {
+        if free_block_space > tx.meta.weight as i64 && free_sigops >= tx.meta.sigops_cost {
             free_block_space -= tx.meta.weight as i64;
-            block.push(tx);
+            free_sigops -= tx.meta.sigops_cost;
+            block.push(tx.clone());
         } else {
             break;
         };
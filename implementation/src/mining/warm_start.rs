@@ -0,0 +1,153 @@
+// Warm start of the transaction selection from a previous template: transactions of the
+// previous template whose packet feerate didn't change keep their previous relative order,
+// only new and changed transactions are sorted and merged into it. A rebuild exceeding its
+// latency budget only appends the new transactions to the previous template instead.
+
+use super::assign_parents::assign_mempool_parents;
+use super::block_template::BlockTemplate;
+use super::transaction_sorting::{put_parents_in_front, BLOCK_SIGOPS_BUDGET, BLOCK_WEIGHT_BUDGET};
+use crate::feerate::FeeRate;
+use crate::parsing::transaction_structs::Transaction;
+use std::collections::{HashMap, HashSet};
+
+// order and packet feerates of the transactions of a previous solution
+#[derive(Debug, Clone, Default)]
+pub struct SelectionHint {
+    positions: HashMap<String, usize>, // txid -> position in the previous template
+    packet_feerates: HashMap<String, FeeRate>, // txid -> packet feerate
+}
+
+impl SelectionHint {
+    // recomputes the packet feerates like calculate_packet_weights() from the fee, weight
+    // and depends of the template transactions (parents are always in front of children)
+    pub fn from_template(template: &BlockTemplate) -> SelectionHint {
+        let mut packets: Vec<(u64, u64)> = Vec::new(); // (packet fee, packet weight)
+        let mut hint = SelectionHint::default();
+
+        for (position, tx) in template.transactions.iter().enumerate() {
+            let (mut fee, mut weight) = (tx.fee, tx.weight);
+            for parent in tx.depends.iter().filter_map(|index| index.checked_sub(1)) {
+                if let Some((parent_fee, parent_weight)) = packets.get(parent) {
+                    fee += parent_fee;
+                    weight += parent_weight;
+                }
+            }
+            packets.push((fee, weight));
+            hint.positions.insert(tx.txid.clone(), position);
+            hint.packet_feerates
+                .insert(tx.txid.clone(), FeeRate::from_fee_weight(fee, weight));
+        }
+        hint
+    }
+
+    // returns: true if the transaction was part of the previous solution with the same
+    // packet feerate
+    fn is_unchanged(&self, tx: &Transaction) -> bool {
+        self.packet_feerates.get(&tx.meta.txid_hex) == Some(&tx.meta.packet_data.packet_feerate)
+    }
+}
+
+fn packet_feerate(tx: &Transaction) -> FeeRate {
+    tx.meta.packet_data.packet_feerate
+}
+
+// sorts like sort_transactions() seeded with the hint: the unchanged transactions keep their
+// previous order, the new and changed ones are sorted by packet feerate and merged into it.
+// Parents moved in front of their children break the feerate order of the previous template,
+// so a transaction is merged in front of the first unchanged transaction after which no
+// unchanged one has a higher packet feerate. Parents are put in front of their children
+// afterwards, which only moves the re-evaluated transactions.
+pub fn sort_transactions_warm(
+    txid_tx_map: &HashMap<String, Transaction>,
+    hint: &SelectionHint,
+) -> Vec<Transaction> {
+    let (mut unchanged, mut changed): (Vec<&Transaction>, Vec<&Transaction>) =
+        txid_tx_map.values().partition(|tx| hint.is_unchanged(tx));
+    unchanged.sort_by_key(|tx| hint.positions[&tx.meta.txid_hex]);
+    changed.sort_by(|a, b| {
+        packet_feerate(b)
+            .cmp(&packet_feerate(a))
+            .then(a.meta.txid_hex.cmp(&b.meta.txid_hex))
+    });
+    // highest packet feerate of each unchanged transaction and the ones after it
+    let mut remaining_max: Vec<FeeRate> = unchanged.iter().map(|tx| packet_feerate(tx)).collect();
+    for index in (1..remaining_max.len()).rev() {
+        remaining_max[index - 1] = remaining_max[index - 1].max(remaining_max[index]);
+    }
+
+    let mut sorted_transactions: Vec<Transaction> = Vec::with_capacity(txid_tx_map.len());
+    let mut changed = changed.into_iter().peekable();
+    for (tx, max_feerate) in unchanged.into_iter().zip(remaining_max) {
+        while let Some(next) = changed.next_if(|next| packet_feerate(next) > max_feerate) {
+            sorted_transactions.push(next.clone());
+        }
+        sorted_transactions.push(tx.clone());
+    }
+    sorted_transactions.extend(changed.cloned());
+    put_parents_in_front(&mut sorted_transactions);
+    sorted_transactions
+}
+
+// incremental selection of a rebuild that exceeded its latency budget: the transactions of the
+// previous template still in the map keep their order, then the new transactions are appended
+// by feerate while the block has room for them. A transaction is skipped if a mempool parent
+// isn't in front of it or it spends an outpoint already spent in the block. Package feerates,
+// the conflict policy and the package limits aren't evaluated again, the full selection
+// replaces the block.
+// returns: block transactions in block order
+pub fn append_to_template(
+    txid_tx_map: &mut HashMap<String, Transaction>,
+    template: &BlockTemplate,
+) -> Vec<Transaction> {
+    assign_mempool_parents(txid_tx_map);
+    let txid_tx_map = &*txid_tx_map;
+    let previous: HashSet<&str> = template
+        .transactions
+        .iter()
+        .map(|tx| tx.txid.as_str())
+        .collect();
+    let mut new: Vec<&Transaction> = txid_tx_map
+        .values()
+        .filter(|tx| !previous.contains(tx.meta.txid_hex.as_str()))
+        .collect();
+    new.sort_by(|a, b| {
+        let feerate = |tx: &Transaction| FeeRate::from_fee_weight(tx.meta.fee, tx.meta.weight);
+        feerate(b)
+            .cmp(&feerate(a))
+            .then(a.meta.txid_hex.cmp(&b.meta.txid_hex))
+    });
+    let candidates = template
+        .transactions
+        .iter()
+        .filter_map(|tx| txid_tx_map.get(&tx.txid))
+        .chain(new);
+
+    let mut block: Vec<Transaction> = Vec::new();
+    let mut included: HashSet<&str> = HashSet::new();
+    let mut spent: HashSet<(&str, u32)> = HashSet::new();
+    let mut free_weight = BLOCK_WEIGHT_BUDGET;
+    let mut free_sigops = BLOCK_SIGOPS_BUDGET;
+    for tx in candidates {
+        let appendable = free_weight > tx.meta.weight
+            && free_sigops >= tx.meta.sigops_cost
+            && tx
+                .meta
+                .parents
+                .iter()
+                .flatten()
+                .all(|parent| included.contains(parent.as_str()))
+            && tx
+                .vin
+                .iter()
+                .all(|txin| !spent.contains(&(txin.txid.as_str(), txin.vout)));
+        if !appendable {
+            continue;
+        }
+        free_weight -= tx.meta.weight;
+        free_sigops -= tx.meta.sigops_cost;
+        included.insert(&tx.meta.txid_hex);
+        spent.extend(tx.vin.iter().map(|txin| (txin.txid.as_str(), txin.vout)));
+        block.push(tx.clone());
+    }
+    block
+}
@@ -0,0 +1,109 @@
+// Splits the proof of work of an UnminedBlock into work units for external hashing hardware or
+// software and assembles the final Block from a returned winning nonce.
+
+use super::header::{header_hash_below_target, mine_nonce, HEADER_TARGET};
+use super::mining_job::{JobHandle, MiningJob};
+use super::{return_block, Block, MiningError, UnminedBlock};
+use sha2::digest::generic_array::GenericArray;
+use std::ops::RangeInclusive;
+
+// initial SHA256 hash values
+const SHA256_IV: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+// work for one hasher: hash header_prefix + nonce (4 byte LE) for all nonces of nonce_range
+// and return a nonce whose HASH256 is below the target
+#[derive(Debug, Clone)]
+pub struct WorkUnit {
+    pub header_prefix: Vec<u8>, // 76 header bytes without the nonce
+    pub midstate: [u8; 32],     // SHA256 state after the first 64 header bytes (words big endian)
+    pub tail: Vec<u8>,          // header bytes 64..76 (end of the merkle root, time, bits)
+    pub target: [u8; 32],       // big endian
+    pub nonce_range: RangeInclusive<u32>,
+}
+
+// returns: SHA256 midstate of the first 64 byte block of the header
+fn header_midstate(header_prefix: &[u8]) -> [u8; 32] {
+    let mut state = SHA256_IV;
+    sha2::compress256(
+        &mut state,
+        &[*GenericArray::from_slice(&header_prefix[..64])],
+    );
+
+    let mut midstate = [0u8; 32];
+    for (index, word) in state.iter().enumerate() {
+        midstate[index * 4..index * 4 + 4].copy_from_slice(&word.to_be_bytes());
+    }
+    midstate
+}
+
+impl UnminedBlock {
+    // splits the nonce space of the header into parts work units of (nearly) equal size,
+    // the last unit also covers the remainder
+    // returns: Vec of WorkUnit covering all 2^32 nonces
+    pub fn work_units(&self, parts: u32) -> Vec<WorkUnit> {
+        let parts = parts.max(1) as u64;
+        let range_size = (u32::MAX as u64 + 1) / parts;
+        let midstate = header_midstate(&self.header_prefix);
+
+        (0..parts)
+            .map(|part| {
+                let start = part * range_size;
+                let end = if part == parts - 1 {
+                    u32::MAX as u64
+                } else {
+                    start + range_size - 1
+                };
+                WorkUnit {
+                    header_prefix: self.header_prefix.clone(),
+                    midstate,
+                    tail: self.header_prefix[64..76].to_vec(),
+                    target: HEADER_TARGET,
+                    nonce_range: start as u32..=end as u32,
+                }
+            })
+            .collect()
+    }
+
+    // returns: true if the header with the passed nonce hashes below the target
+    pub fn check_nonce(&self, nonce: u32) -> bool {
+        let mut header = self.header_prefix.clone();
+        header.extend(nonce.to_le_bytes());
+        header_hash_below_target(&header)
+    }
+
+    // verifies the nonce returned by an external hasher and assembles the final Block
+    // returns: Block or Err(MiningError::InvalidNonce) if the proof of work is insufficient
+    pub fn submit_nonce(self, nonce: u32) -> Result<Block, MiningError> {
+        if !self.check_nonce(nonce) {
+            return Err(MiningError::InvalidNonce(nonce));
+        }
+        Ok(self.assemble(nonce))
+    }
+
+    // searches the nonce on this thread and assembles the final Block
+    pub fn mine(self) -> Block {
+        let nonce = mine_nonce(&self.header_prefix);
+        self.assemble(nonce)
+    }
+
+    // starts a cancellable nonce search on a background thread, a found nonce is turned into
+    // the final Block with submit_nonce()
+    // returns: JobHandle of the MiningJob
+    pub fn start_mining(&self) -> JobHandle {
+        MiningJob::start(self.header_prefix.clone(), HEADER_TARGET)
+    }
+
+    // encode in Block struct and returns final data needed for output.txt
+    fn assemble(self, nonce: u32) -> Block {
+        let mut block_header = self.header_prefix;
+        block_header.extend(nonce.to_le_bytes());
+        return_block(
+            &block_header,
+            self.coinbase_tx,
+            self.transactions,
+            self.layout,
+        )
+    }
+}
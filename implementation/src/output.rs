@@ -0,0 +1,234 @@
+use crate::cli::ArtifactToggles;
+use bitcoin_block_builder::amount::format_btc;
+use bitcoin_block_builder::chain_context::ChainContext;
+use bitcoin_block_builder::error::{io_error, BlockBuilderError};
+use bitcoin_block_builder::mining::{
+    block_template::build_block_template, construct_coinbase::count_fees,
+    dependency_graph::dependency_dot, serialize_block::serialize_block, simulation::SimulationStep,
+    template_delta::template_delta, Block,
+};
+use bitcoin_block_builder::validation::weight_stats::WeightStats;
+use serde::Serialize;
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::path::Path;
+
+// validation verdict of a single parsed transaction
+#[derive(Serialize, Debug)]
+pub struct ReportEntry {
+    pub txid: String,
+    pub valid: bool,
+    pub reason: Option<String>,
+    pub pending: Option<String>, // why a valid transaction isn't includable in the block yet
+    pub dust: Option<String>,    // dust output of a valid transaction with --dust report
+}
+
+// numbers of the run written to stats.csv
+pub struct RunStats {
+    pub mempool_transactions: usize,
+    pub valid_transactions: usize,
+    pub relay_valid_transactions: usize, // valid but not includable in the block yet
+    pub weights: WeightStats,            // weights of the valid transactions
+}
+
+// writes the final content stored in the Block struct to the passed output_path
+// as output.txt formatted according to the exercise specification
+pub fn output_block(mined_block: &Block, output_path: &Path) -> Result<(), BlockBuilderError> {
+    let mut output_file = File::create(output_path).map_err(io_error(output_path))?;
+
+    writeln!(output_file, "{}", mined_block.header_hex).map_err(io_error(output_path))?;
+    writeln!(output_file, "{}", mined_block.coinbase_tx_hex).map_err(io_error(output_path))?;
+
+    let len = mined_block.txids_hex.len();
+    for (index, tx) in mined_block.txids_hex.iter().enumerate() {
+        if index < len - 1 {
+            writeln!(output_file, "{}", tx).map_err(io_error(output_path))?;
+        } else {
+            write!(output_file, "{}", tx).map_err(io_error(output_path))?;
+        }
+    }
+    Ok(())
+}
+
+// writes the contents to path
+// returns: Err(BlockBuilderError::Io) if writing fails
+fn write_file(path: &Path, contents: impl AsRef<[u8]>) -> Result<(), BlockBuilderError> {
+    fs::write(path, contents).map_err(io_error(path))
+}
+
+// writes the package layout of the block as pretty printed JSON to the passed layout_path
+pub fn output_block_layout(
+    mined_block: &Block,
+    layout_path: &Path,
+) -> Result<(), BlockBuilderError> {
+    let layout_json =
+        serde_json::to_string_pretty(&mined_block.layout).expect("Unable to serialize layout");
+    write_file(layout_path, layout_json)
+}
+
+// formats the RunStats and block numbers as metric,value csv, followed by the weight
+// histogram buckets (weight_histogram_<from>_<to>) and the weight outliers (weight_outlier_<txid>)
+fn stats_csv(mined_block: &Block, stats: &RunStats) -> String {
+    let block_fees = count_fees(&mined_block.transactions);
+    let rows: Vec<(&str, String)> = vec![
+        (
+            "mempool_transactions",
+            stats.mempool_transactions.to_string(),
+        ),
+        ("valid_transactions", stats.valid_transactions.to_string()),
+        (
+            "invalid_transactions",
+            (stats.mempool_transactions - stats.valid_transactions).to_string(),
+        ),
+        (
+            "block_valid_transactions",
+            (stats.valid_transactions - stats.relay_valid_transactions).to_string(),
+        ),
+        (
+            "relay_valid_transactions",
+            stats.relay_valid_transactions.to_string(),
+        ),
+        (
+            "block_transactions",
+            mined_block.transactions.len().to_string(),
+        ),
+        ("block_weight", mined_block.layout.used_weight.to_string()),
+        ("block_fees_sat", block_fees.to_string()),
+        ("block_fees_btc", format_btc(block_fees)),
+        (
+            "coinbase_reward_sat",
+            mined_block.coinbase_tx.reward.to_string(),
+        ),
+        (
+            "coinbase_reward_btc",
+            format_btc(mined_block.coinbase_tx.reward),
+        ),
+    ];
+    let mut csv = String::from("metric,value\n");
+    for (metric, value) in rows {
+        csv.push_str(&format!("{},{}\n", metric, value));
+    }
+    for (lower_bound, count) in &stats.weights.histogram {
+        csv.push_str(&format!(
+            "weight_histogram_{}_{},{}\n",
+            lower_bound,
+            (lower_bound * 2).max(1) - 1,
+            count
+        ));
+    }
+    csv.push_str(&format!(
+        "weight_outlier_threshold,{}\nweight_outliers,{}\n",
+        stats.weights.outlier_threshold,
+        stats.weights.outliers.len()
+    ));
+    for (txid, weight) in &stats.weights.outliers {
+        csv.push_str(&format!("weight_outlier_{},{}\n", txid, weight));
+    }
+    csv
+}
+
+// writes all artifacts of the run that are not disabled in the ArtifactToggles into bundle_dir
+pub fn output_bundle(
+    bundle_dir: &Path,
+    mined_block: &Block,
+    report: &[ReportEntry],
+    stats: &RunStats,
+    toggles: &ArtifactToggles,
+    context: &ChainContext,
+) -> Result<(), BlockBuilderError> {
+    fs::create_dir_all(bundle_dir).map_err(io_error(bundle_dir))?;
+
+    if !toggles.no_output_txt {
+        output_block(mined_block, &bundle_dir.join("output.txt"))?;
+    }
+    if !toggles.no_block_bin || !toggles.no_block_hex {
+        let raw_block = serialize_block(mined_block);
+        if !toggles.no_block_bin {
+            write_file(&bundle_dir.join("block.bin"), &raw_block)?;
+        }
+        if !toggles.no_block_hex {
+            write_file(&bundle_dir.join("block.hex"), hex::encode(&raw_block))?;
+        }
+    }
+    if !toggles.no_template {
+        let template_json =
+            serde_json::to_string_pretty(&build_block_template(mined_block, context))
+                .expect("Unable to serialize template");
+        write_file(&bundle_dir.join("template.json"), template_json)?;
+    }
+    if !toggles.no_layout {
+        output_block_layout(mined_block, &bundle_dir.join("block_layout.json"))?;
+    }
+    if !toggles.no_report {
+        let report_json =
+            serde_json::to_string_pretty(report).expect("Unable to serialize validation report");
+        write_file(&bundle_dir.join("validation_report.json"), report_json)?;
+    }
+    if !toggles.no_stats {
+        write_file(&bundle_dir.join("stats.csv"), stats_csv(mined_block, stats))?;
+    }
+    if !toggles.no_dot {
+        write_file(
+            &bundle_dir.join("dependencies.dot"),
+            dependency_dot(mined_block),
+        )?;
+    }
+    Ok(())
+}
+
+// writes each simulated block as block_<height>.txt (output.txt format) and the fee revenue
+// time series as simulation.csv into simulation_dir. The template of the first block is written
+// as template_<height>.json, later templates as template_delta_<height>.json against the
+// previous template.
+pub fn output_simulation(
+    simulation_dir: &Path,
+    steps: &[SimulationStep],
+    context: &ChainContext,
+) -> Result<(), BlockBuilderError> {
+    fs::create_dir_all(simulation_dir).map_err(io_error(simulation_dir))?;
+    let mut csv =
+        String::from("height,time,arrived,deferred,block_transactions,mempool_size,fees,cumulative_fees,fees_btc,cumulative_fees_btc\n");
+    let mut previous_template = None;
+
+    for step in steps {
+        output_block(
+            &step.block,
+            &simulation_dir.join(format!("block_{}.txt", step.height)),
+        )?;
+        let step_context = ChainContext {
+            height: step.height,
+            median_time_past: step.median_time_past,
+            ..context.clone()
+        };
+        let template = build_block_template(&step.block, &step_context);
+        let (file_name, template_json) = match &previous_template {
+            Some(previous) => (
+                format!("template_delta_{}.json", step.height),
+                serde_json::to_string(&template_delta(previous, &template)),
+            ),
+            None => (
+                format!("template_{}.json", step.height),
+                serde_json::to_string(&template),
+            ),
+        };
+        write_file(
+            &simulation_dir.join(file_name),
+            template_json.expect("Template serialization failed"),
+        )?;
+        previous_template = Some(template);
+        csv.push_str(&format!(
+            "{},{:.0},{},{},{},{},{},{},{},{}\n",
+            step.height,
+            step.time,
+            step.arrived,
+            step.deferred,
+            step.block.transactions.len(),
+            step.mempool_size,
+            step.fees,
+            step.cumulative_fees,
+            format_btc(step.fees),
+            format_btc(step.cumulative_fees)
+        ));
+    }
+    write_file(&simulation_dir.join("simulation.csv"), csv)
+}
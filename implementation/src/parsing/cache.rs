@@ -0,0 +1,354 @@
+// Binary cache of a parsed and validated mempool, reloading it skips the json parsing and the
+// (signature) validation of repeated runs on the same snapshot. The file starts with a magic,
+// the format version and a key describing the inputs of the run (source paths, validation
+// options), a cache with a different version or key is stale and ignored. Integers are little
+// endian, strings and lists are compact size prefixed like the consensus encoding, optional
+// fields have a 0/1 presence byte. The format is hand-rolled as no bincode or CBOR crate is
+// vendored and a json cache would repeat the parsing it saves; reusing the raw transaction
+// Reader and varint keeps it small, CACHE_VERSION has to be bumped when a field changes.
+// Scripts, witnesses and outpoints are stored as bytes, the hex fields are encoded from them
+// while loading like for raw transactions (Transaction::encode_hex_fields()).
+
+use super::raw::{RawTxError, Reader};
+use super::transaction_structs::{InputType, Script, Transaction, TxIn, TxMetadata, TxOut};
+use crate::validation::utils::varint;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+const CACHE_MAGIC: &[u8] = b"bbcache\x00";
+const CACHE_VERSION: u32 = 2;
+
+// reason why a cache file couldn't be read or written
+#[derive(Debug, Clone, PartialEq)]
+pub enum CacheError {
+    Io(String),
+    MissingMagic,    // not a cache file, it isn't overwritten
+    Corrupt(String), // truncated or invalid content
+}
+
+impl fmt::Display for CacheError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CacheError::Io(message) => write!(f, "cache io error ({})", message),
+            CacheError::MissingMagic => write!(f, "file is no mempool cache"),
+            CacheError::Corrupt(message) => write!(f, "corrupt mempool cache ({})", message),
+        }
+    }
+}
+
+impl std::error::Error for CacheError {}
+
+impl From<RawTxError> for CacheError {
+    fn from(err: RawTxError) -> Self {
+        CacheError::Corrupt(err.to_string())
+    }
+}
+
+impl From<io::Error> for CacheError {
+    fn from(err: io::Error) -> Self {
+        CacheError::Io(err.to_string())
+    }
+}
+
+// parsed transactions with the rejection reasons of their validation
+#[derive(Debug, Default)]
+pub struct CachedSnapshot {
+    pub transactions: Vec<Transaction>,
+    pub invalid: HashMap<String, String>, // txid -> rejection reason
+}
+
+fn write_u32(buffer: &mut Vec<u8>, value: u32) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+fn write_u64(buffer: &mut Vec<u8>, value: u64) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+fn write_count(buffer: &mut Vec<u8>, count: usize) {
+    buffer.extend(varint(count as u128));
+}
+
+fn write_string(buffer: &mut Vec<u8>, string: &str) {
+    write_count(buffer, string.len());
+    buffer.extend_from_slice(string.as_bytes());
+}
+
+fn write_optional_string(buffer: &mut Vec<u8>, string: &Option<String>) {
+    buffer.push(string.is_some() as u8);
+    if let Some(string) = string {
+        write_string(buffer, string);
+    }
+}
+
+fn write_optional_strings(buffer: &mut Vec<u8>, strings: &Option<Vec<String>>) {
+    buffer.push(strings.is_some() as u8);
+    if let Some(strings) = strings {
+        write_count(buffer, strings.len());
+        for string in strings {
+            write_string(buffer, string);
+        }
+    }
+}
+
+fn write_bytes(buffer: &mut Vec<u8>, bytes: &[u8]) {
+    write_count(buffer, bytes.len());
+    buffer.extend_from_slice(bytes);
+}
+
+fn write_optional_byte_lists(buffer: &mut Vec<u8>, lists: &Option<Vec<Vec<u8>>>) {
+    buffer.push(lists.is_some() as u8);
+    if let Some(lists) = lists {
+        write_count(buffer, lists.len());
+        for bytes in lists {
+            write_bytes(buffer, bytes);
+        }
+    }
+}
+
+fn read_bytes(reader: &mut Reader, field: &'static str) -> Result<Vec<u8>, CacheError> {
+    Ok(reader.read_var_bytes(field)?.to_vec())
+}
+
+fn read_string(reader: &mut Reader, field: &'static str) -> Result<String, CacheError> {
+    String::from_utf8(reader.read_var_bytes(field)?.to_vec())
+        .map_err(|_| CacheError::Corrupt(format!("{} is no utf-8 string", field)))
+}
+
+fn read_flag(reader: &mut Reader, field: &'static str) -> Result<bool, CacheError> {
+    match reader.read(1, field)?[0] {
+        0 => Ok(false),
+        1 => Ok(true),
+        flag => Err(CacheError::Corrupt(format!("{} flag {}", field, flag))),
+    }
+}
+
+fn read_optional_string(
+    reader: &mut Reader,
+    field: &'static str,
+) -> Result<Option<String>, CacheError> {
+    match read_flag(reader, field)? {
+        true => Ok(Some(read_string(reader, field)?)),
+        false => Ok(None),
+    }
+}
+
+fn read_optional_strings(
+    reader: &mut Reader,
+    field: &'static str,
+) -> Result<Option<Vec<String>>, CacheError> {
+    if !read_flag(reader, field)? {
+        return Ok(None);
+    }
+    let count = reader.read_count(field)?;
+    (0..count)
+        .map(|_| read_string(reader, field))
+        .collect::<Result<_, _>>()
+        .map(Some)
+}
+
+fn read_optional_byte_lists(
+    reader: &mut Reader,
+    field: &'static str,
+) -> Result<Option<Vec<Vec<u8>>>, CacheError> {
+    if !read_flag(reader, field)? {
+        return Ok(None);
+    }
+    let count = reader.read_count(field)?;
+    (0..count)
+        .map(|_| read_bytes(reader, field))
+        .collect::<Result<_, _>>()
+        .map(Some)
+}
+
+fn write_transaction(buffer: &mut Vec<u8>, tx: &Transaction) {
+    write_u32(buffer, tx.version as u32);
+    write_u32(buffer, tx.locktime);
+    write_count(buffer, tx.vin.len());
+    for txin in &tx.vin {
+        write_bytes(buffer, &txin.outpoint);
+        write_bytes(buffer, &txin.scriptsig_bytes);
+        write_optional_string(buffer, &txin.scriptsig_asm);
+        write_bytes(buffer, &txin.prevout.scriptpubkey_bytes);
+        write_string(buffer, &txin.prevout.scriptpubkey_asm);
+        write_string(buffer, &txin.prevout.scriptpubkey_type);
+        write_optional_string(buffer, &txin.prevout.scriptpubkey_address);
+        write_u64(buffer, txin.prevout.value);
+        write_optional_byte_lists(buffer, &txin.witness_bytes);
+        write_optional_string(buffer, &txin.inner_witnessscript_asm);
+        write_optional_string(buffer, &txin.inner_redeemscript_asm);
+        buffer.push(txin.is_coinbase as u8);
+        write_u32(buffer, txin.sequence);
+    }
+    write_count(buffer, tx.vout.len());
+    for txout in &tx.vout {
+        write_bytes(buffer, &txout.scriptpubkey_bytes);
+        write_string(buffer, &txout.scriptpubkey_asm);
+        write_string(buffer, &txout.scriptpubkey_type);
+        write_optional_string(buffer, &txout.scriptpubkey_address);
+        write_u64(buffer, txout.value);
+    }
+    write_optional_string(buffer, &tx.meta.json_path);
+    write_string(buffer, &tx.meta.txid_hex);
+    write_string(buffer, &tx.meta.wtxid_hex);
+    write_u64(buffer, tx.meta.weight);
+    write_u64(buffer, tx.meta.sigops_cost);
+    write_u64(buffer, tx.meta.fee);
+    write_optional_strings(buffer, &tx.meta.parents);
+    write_u64(buffer, tx.meta.memory_size as u64);
+    write_u64(buffer, tx.meta.base_size as u64);
+    write_u64(buffer, tx.meta.total_size as u64);
+}
+
+// returns: input with the byte fields, the hex fields are set by encode_hex_fields()
+fn read_txin(reader: &mut Reader) -> Result<TxIn, CacheError> {
+    let outpoint = read_bytes(reader, "outpoint")?;
+    if outpoint.len() != 36 {
+        return Err(CacheError::Corrupt(format!(
+            "outpoint of {} bytes",
+            outpoint.len()
+        )));
+    }
+    Ok(TxIn {
+        in_type: InputType::default(),
+        txid: String::new(),
+        vout: 0,
+        scriptsig: None,
+        scriptsig_bytes: read_bytes(reader, "scriptsig")?,
+        scriptsig_asm: read_optional_string(reader, "scriptsig asm")?,
+        prevout: Script {
+            scriptpubkey: String::new(),
+            scriptpubkey_bytes: read_bytes(reader, "prevout scriptpubkey")?,
+            scriptpubkey_asm: read_string(reader, "prevout asm")?,
+            scriptpubkey_type: read_string(reader, "prevout type")?,
+            scriptpubkey_address: read_optional_string(reader, "prevout address")?,
+            value: reader.read_u64("prevout value")?,
+        },
+        witness: None,
+        witness_bytes: read_optional_byte_lists(reader, "witness")?,
+        inner_witnessscript_asm: read_optional_string(reader, "inner witnessscript asm")?,
+        inner_redeemscript_asm: read_optional_string(reader, "inner redeemscript asm")?,
+        is_coinbase: read_flag(reader, "is coinbase")?,
+        sequence: reader.read_u32("sequence")?,
+        outpoint,
+    })
+}
+
+// returns: output with the byte field, the hex field is set by encode_hex_fields()
+fn read_txout(reader: &mut Reader) -> Result<TxOut, CacheError> {
+    Ok(TxOut {
+        scriptpubkey: None,
+        scriptpubkey_bytes: read_bytes(reader, "output scriptpubkey")?,
+        scriptpubkey_asm: read_string(reader, "output asm")?,
+        scriptpubkey_type: read_string(reader, "output type")?,
+        scriptpubkey_address: read_optional_string(reader, "output address")?,
+        value: reader.read_u64("output value")?,
+    })
+}
+
+fn read_cached_transaction(reader: &mut Reader) -> Result<Transaction, CacheError> {
+    let version = reader.read_u32("version")? as i32;
+    let locktime = reader.read_u32("locktime")?;
+    let vin = (0..reader.read_count("input count")?)
+        .map(|_| read_txin(reader))
+        .collect::<Result<_, _>>()?;
+    let vout = (0..reader.read_count("output count")?)
+        .map(|_| read_txout(reader))
+        .collect::<Result<_, _>>()?;
+    let meta = TxMetadata {
+        json_path: read_optional_string(reader, "json path")?,
+        txid_hex: read_string(reader, "txid")?,
+        wtxid_hex: read_string(reader, "wtxid")?,
+        weight: reader.read_u64("weight")?,
+        sigops_cost: reader.read_u64("sigops cost")?,
+        fee: reader.read_u64("fee")?,
+        parents: read_optional_strings(reader, "parents")?,
+        memory_size: reader.read_u64("memory size")? as usize,
+        base_size: reader.read_u64("base size")? as usize,
+        total_size: reader.read_u64("total size")? as usize,
+        ..Default::default()
+    };
+    let mut tx = Transaction {
+        meta,
+        version,
+        locktime,
+        vin,
+        vout,
+    };
+    tx.encode_hex_fields();
+    for txin in &mut tx.vin {
+        InputType::fetch_type(txin);
+    }
+    Ok(tx)
+}
+
+// returns: the cache file bytes of the validated transactions and their rejection reasons
+pub fn serialize_cache(key: &str, snapshot: &CachedSnapshot) -> Vec<u8> {
+    let mut buffer = CACHE_MAGIC.to_vec();
+    write_u32(&mut buffer, CACHE_VERSION);
+    write_string(&mut buffer, key);
+    write_count(&mut buffer, snapshot.transactions.len());
+    for tx in &snapshot.transactions {
+        write_transaction(&mut buffer, tx);
+    }
+    write_count(&mut buffer, snapshot.invalid.len());
+    for (txid, reason) in &snapshot.invalid {
+        write_string(&mut buffer, txid);
+        write_string(&mut buffer, reason);
+    }
+    buffer
+}
+
+// decodes the cache file bytes
+// returns: CachedSnapshot, None if the format version or key differ (stale cache),
+// or Err(CacheError)
+pub fn deserialize_cache(bytes: &[u8], key: &str) -> Result<Option<CachedSnapshot>, CacheError> {
+    let mut reader = Reader::new(bytes);
+    if reader.read(CACHE_MAGIC.len(), "magic").ok() != Some(CACHE_MAGIC) {
+        return Err(CacheError::MissingMagic);
+    }
+    if reader.read_u32("version")? != CACHE_VERSION || read_string(&mut reader, "key")? != key {
+        return Ok(None);
+    }
+    let transactions = (0..reader.read_count("transaction count")?)
+        .map(|_| read_cached_transaction(&mut reader))
+        .collect::<Result<_, _>>()?;
+    let mut invalid = HashMap::new();
+    for _ in 0..reader.read_count("invalid count")? {
+        let txid = read_string(&mut reader, "invalid txid")?;
+        invalid.insert(txid, read_string(&mut reader, "invalid reason")?);
+    }
+    if reader.remaining() != 0 {
+        return Err(RawTxError::TrailingBytes(reader.remaining()).into());
+    }
+    Ok(Some(CachedSnapshot {
+        transactions,
+        invalid,
+    }))
+}
+
+// reads the cache file at path
+// returns: CachedSnapshot, None if there is no file yet or it is stale, or Err(CacheError)
+pub fn read_cache(path: &Path, key: &str) -> Result<Option<CachedSnapshot>, CacheError> {
+    match fs::read(path) {
+        Ok(bytes) => deserialize_cache(&bytes, key),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+// writes the cache file at path, an existing file has to be a cache (no other file is
+// overwritten by a mistyped path)
+// returns: Err(CacheError) if the file couldn't be written
+pub fn write_cache(path: &Path, key: &str, snapshot: &CachedSnapshot) -> Result<(), CacheError> {
+    if let Ok(existing) = fs::read(path) {
+        if !existing.starts_with(CACHE_MAGIC) {
+            return Err(CacheError::MissingMagic);
+        }
+    }
+    fs::write(path, serialize_cache(key, snapshot))?;
+    Ok(())
+}
@@ -0,0 +1,557 @@
+// Streaming gzip (RFC 1952) decoder with a DEFLATE (RFC 1951) inflater. The compressed input
+// is read in chunks and the output is produced in chunks of about the window size, only the
+// 32 KiB window the back references may point into is kept. Concatenated gzip members are
+// decoded one after the other, the CRC32 and size of every member are checked.
+
+use std::io::{self, Read};
+
+const WINDOW_SIZE: usize = 32 * 1024;
+const INPUT_CHUNK_SIZE: usize = 64 * 1024;
+// codes up to this length are decoded by a single table lookup
+const FAST_BITS: u32 = 10;
+const MAX_CODE_LENGTH: usize = 15;
+
+// gzip header flags
+const FLAG_HEADER_CRC: u8 = 0x02;
+const FLAG_EXTRA: u8 = 0x04;
+const FLAG_NAME: u8 = 0x08;
+const FLAG_COMMENT: u8 = 0x10;
+
+// base lengths and extra bits of the length symbols 257..=285
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA_BITS: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+// base distances and extra bits of the distance symbols 0..=29
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA_BITS: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+// order of the code length code lengths in the header of a dynamic block
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut index = 0;
+    while index < 256 {
+        let mut crc = index as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = match crc & 1 {
+                1 => 0xedb88320 ^ (crc >> 1),
+                _ => crc >> 1,
+            };
+            bit += 1;
+        }
+        table[index] = crc;
+        index += 1;
+    }
+    table
+}
+
+// returns: the CRC32 (ISO-HDLC, as used by gzip) of the bytes continued from crc
+fn crc32(crc: u32, bytes: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for byte in bytes {
+        crc = CRC_TABLE[((crc ^ *byte as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("gzip: {}", message))
+}
+
+// least significant bit first reader of the compressed input
+struct BitReader<R: Read> {
+    inner: R,
+    input: Vec<u8>,
+    position: usize, // next unread byte of input
+    bits: u64,
+    count: u32, // number of valid bits in bits
+}
+
+impl<R: Read> BitReader<R> {
+    // returns: the next byte of the input, None at its end
+    fn next_byte(&mut self) -> io::Result<Option<u8>> {
+        if self.position == self.input.len() {
+            self.input.resize(INPUT_CHUNK_SIZE, 0);
+            let read = loop {
+                match self.inner.read(&mut self.input) {
+                    Ok(read) => break read,
+                    Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(err) => return Err(err),
+                }
+            };
+            self.input.truncate(read);
+            self.position = 0;
+            if read == 0 {
+                return Ok(None);
+            }
+        }
+        self.position += 1;
+        Ok(Some(self.input[self.position - 1]))
+    }
+
+    // fills the bit buffer with at least count bits, fewer at the end of the input
+    fn refill(&mut self, count: u32) -> io::Result<()> {
+        while self.count < count {
+            match self.next_byte()? {
+                Some(byte) => {
+                    self.bits |= (byte as u64) << self.count;
+                    self.count += 8;
+                }
+                None => break,
+            }
+        }
+        Ok(())
+    }
+
+    // returns: the next count bits without consuming them, zero padded past the end of input
+    fn peek(&mut self, count: u32) -> io::Result<u32> {
+        self.refill(count)?;
+        Ok((self.bits & ((1 << count) - 1)) as u32)
+    }
+
+    fn consume(&mut self, count: u32) -> io::Result<()> {
+        if count > self.count {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "gzip: unexpected end of the compressed data",
+            ));
+        }
+        self.bits >>= count;
+        self.count -= count;
+        Ok(())
+    }
+
+    fn read_bits(&mut self, count: u32) -> io::Result<u32> {
+        let value = self.peek(count)?;
+        self.consume(count)?;
+        Ok(value)
+    }
+
+    fn read_byte(&mut self) -> io::Result<u8> {
+        Ok(self.read_bits(8)? as u8)
+    }
+
+    fn read_u16(&mut self) -> io::Result<u16> {
+        Ok(self.read_bits(16)? as u16)
+    }
+
+    fn read_u32(&mut self) -> io::Result<u32> {
+        Ok(self.read_u16()? as u32 | (self.read_u16()? as u32) << 16)
+    }
+
+    // drops the bits up to the next byte boundary
+    fn align_to_byte(&mut self) {
+        let count = self.count % 8;
+        self.bits >>= count;
+        self.count -= count;
+    }
+
+    // returns: true if all input was consumed
+    fn at_end(&mut self) -> io::Result<bool> {
+        self.refill(1)?;
+        Ok(self.count == 0)
+    }
+}
+
+// canonical prefix code of a deflate block
+struct Huffman {
+    counts: [u16; MAX_CODE_LENGTH + 1], // number of codes of each length
+    symbols: Vec<u16>,                  // symbols ordered by their code
+    // symbol << 4 | length of the codes up to FAST_BITS indexed by the next input bits,
+    // 0 for longer codes
+    fast: Vec<u16>,
+}
+
+// returns: the lowest length bits of code in reversed order
+fn reverse_bits(code: u32, length: usize) -> u32 {
+    code.reverse_bits() >> (32 - length)
+}
+
+impl Huffman {
+    // returns: the code of the code lengths of the symbols (0 for unused symbols) or Err if the
+    // lengths over-subscribe the code space
+    fn new(lengths: &[u8]) -> io::Result<Huffman> {
+        let mut counts = [0u16; MAX_CODE_LENGTH + 1];
+        for length in lengths {
+            counts[*length as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut left: i32 = 1;
+        for count in &counts[1..] {
+            left = (left << 1) - *count as i32;
+            if left < 0 {
+                return Err(invalid_data("over-subscribed prefix code"));
+            }
+        }
+
+        let mut offsets = [0u16; MAX_CODE_LENGTH + 2];
+        for length in 1..=MAX_CODE_LENGTH {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+        let mut symbols = vec![0u16; offsets[MAX_CODE_LENGTH + 1] as usize];
+        for (symbol, length) in lengths.iter().enumerate() {
+            if *length != 0 {
+                symbols[offsets[*length as usize] as usize] = symbol as u16;
+                offsets[*length as usize] += 1;
+            }
+        }
+
+        let mut fast = vec![0u16; 1 << FAST_BITS];
+        let mut code = 0;
+        let mut index = 0;
+        for (length, count) in counts
+            .iter()
+            .enumerate()
+            .take(FAST_BITS as usize + 1)
+            .skip(1)
+        {
+            for _ in 0..*count {
+                let entry = symbols[index] << 4 | length as u16;
+                let mut slot = reverse_bits(code, length) as usize;
+                while slot < fast.len() {
+                    fast[slot] = entry;
+                    slot += 1 << length;
+                }
+                code += 1;
+                index += 1;
+            }
+            code <<= 1;
+        }
+        Ok(Huffman {
+            counts,
+            symbols,
+            fast,
+        })
+    }
+
+    // returns: the next symbol of the input or Err if the bits are no code
+    fn decode<R: Read>(&self, reader: &mut BitReader<R>) -> io::Result<u16> {
+        let entry = self.fast[reader.peek(FAST_BITS)? as usize];
+        if entry != 0 {
+            reader.consume((entry & 0xf) as u32)?;
+            return Ok(entry >> 4);
+        }
+        // codes longer than FAST_BITS bit by bit
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+        for count in &self.counts[1..] {
+            code |= reader.read_bits(1)? as i32;
+            let count = *count as i32;
+            if code - count < first {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(invalid_data("invalid prefix code"))
+    }
+}
+
+// returns: the literal/length and distance codes of a block with fixed codes
+fn fixed_codes() -> io::Result<(Huffman, Huffman)> {
+    let mut lengths = [8u8; 288];
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    Ok((Huffman::new(&lengths)?, Huffman::new(&[5; 32])?))
+}
+
+// reads the code lengths of a block with dynamic codes
+// returns: the literal/length and distance codes or Err if they are invalid
+fn dynamic_codes<R: Read>(reader: &mut BitReader<R>) -> io::Result<(Huffman, Huffman)> {
+    let literal_count = reader.read_bits(5)? as usize + 257;
+    let distance_count = reader.read_bits(5)? as usize + 1;
+    let code_length_count = reader.read_bits(4)? as usize + 4;
+    if literal_count > 286 || distance_count > 30 {
+        return Err(invalid_data("too many length or distance symbols"));
+    }
+    let mut code_lengths = [0u8; 19];
+    for index in &CODE_LENGTH_ORDER[..code_length_count] {
+        code_lengths[*index] = reader.read_bits(3)? as u8;
+    }
+    let code_length_code = Huffman::new(&code_lengths)?;
+
+    let mut lengths = vec![0u8; literal_count + distance_count];
+    let mut index = 0;
+    while index < lengths.len() {
+        let symbol = code_length_code.decode(reader)?;
+        let (length, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 if index > 0 => (lengths[index - 1], 3 + reader.read_bits(2)? as usize),
+            16 => return Err(invalid_data("repeated length without a previous length")),
+            17 => (0, 3 + reader.read_bits(3)? as usize),
+            _ => (0, 11 + reader.read_bits(7)? as usize),
+        };
+        if index + repeat > lengths.len() {
+            return Err(invalid_data("too many code lengths"));
+        }
+        lengths[index..index + repeat].fill(length);
+        index += repeat;
+    }
+    if lengths[256] == 0 {
+        return Err(invalid_data("missing end of block code"));
+    }
+    Ok((
+        Huffman::new(&lengths[..literal_count])?,
+        Huffman::new(&lengths[literal_count..])?,
+    ))
+}
+
+// appends the length bytes at distance back in the output
+fn copy_match(output: &mut Vec<u8>, distance: usize, length: usize) {
+    let start = output.len() - distance;
+    if distance >= length {
+        output.extend_from_within(start..start + length);
+    } else {
+        for index in start..start + length {
+            output.push(output[index]);
+        }
+    }
+}
+
+// decodes the symbols of a block with prefix codes until the end of the block or until the
+// output reaches limit bytes
+// returns: true at the end of the block or Err if the data is invalid
+fn inflate_codes<R: Read>(
+    reader: &mut BitReader<R>,
+    literals: &Huffman,
+    distances: &Huffman,
+    output: &mut Vec<u8>,
+    limit: usize,
+) -> io::Result<bool> {
+    while output.len() < limit {
+        let symbol = literals.decode(reader)?;
+        if symbol < 256 {
+            output.push(symbol as u8);
+            continue;
+        }
+        if symbol == 256 {
+            return Ok(true);
+        }
+        let index = symbol as usize - 257;
+        if index >= LENGTH_BASE.len() {
+            return Err(invalid_data("invalid length symbol"));
+        }
+        let length = LENGTH_BASE[index] as usize
+            + reader.read_bits(LENGTH_EXTRA_BITS[index] as u32)? as usize;
+        let index = distances.decode(reader)? as usize;
+        if index >= DISTANCE_BASE.len() {
+            return Err(invalid_data("invalid distance symbol"));
+        }
+        let distance = DISTANCE_BASE[index] as usize
+            + reader.read_bits(DISTANCE_EXTRA_BITS[index] as u32)? as usize;
+        if distance > output.len() {
+            return Err(invalid_data("distance too far back"));
+        }
+        copy_match(output, distance, length);
+    }
+    Ok(false)
+}
+
+enum State {
+    MemberHeader,
+    BlockHeader,
+    Stored(usize),                          // remaining bytes of a stored block
+    Compressed(Box<Huffman>, Box<Huffman>), // literal/length and distance codes of the block
+    MemberTrailer,
+    End,
+}
+
+// decompressing reader of gzip data
+pub struct GzipDecoder<R: Read> {
+    reader: BitReader<R>,
+    state: State,
+    last_block: bool, // the current block is the last one of the member
+    // window of the previous output followed by the output that wasn't read yet
+    output: Vec<u8>,
+    unread: usize,       // start of the output that wasn't read yet
+    crc_position: usize, // end of the output added to crc and size
+    crc: u32,            // of the output of the current member
+    size: u32,           // of the output of the current member, modulo 2^32
+}
+
+impl<R: Read> GzipDecoder<R> {
+    pub fn new(inner: R) -> Self {
+        GzipDecoder {
+            reader: BitReader {
+                inner,
+                input: Vec::new(),
+                position: 0,
+                bits: 0,
+                count: 0,
+            },
+            state: State::MemberHeader,
+            last_block: false,
+            output: Vec::new(),
+            unread: 0,
+            crc_position: 0,
+            crc: 0,
+            size: 0,
+        }
+    }
+
+    // reads the header of a gzip member up to its compressed data
+    fn read_member_header(&mut self) -> io::Result<()> {
+        let reader = &mut self.reader;
+        let mut header = [0u8; 10];
+        for byte in &mut header {
+            *byte = reader.read_byte()?;
+        }
+        if header[..2] != [0x1f, 0x8b] {
+            return Err(invalid_data("not in gzip format"));
+        }
+        if header[2] != 8 {
+            return Err(invalid_data("unknown compression method"));
+        }
+        let flags = header[3];
+        if flags & FLAG_EXTRA != 0 {
+            for _ in 0..reader.read_u16()? {
+                reader.read_byte()?;
+            }
+        }
+        for flag in [FLAG_NAME, FLAG_COMMENT] {
+            if flags & flag != 0 {
+                while reader.read_byte()? != 0 {}
+            }
+        }
+        if flags & FLAG_HEADER_CRC != 0 {
+            reader.read_u16()?;
+        }
+        self.crc = 0;
+        self.size = 0;
+        Ok(())
+    }
+
+    // checks the CRC32 and size of the member
+    fn read_member_trailer(&mut self) -> io::Result<()> {
+        self.reader.align_to_byte();
+        if self.reader.read_u32()? != self.crc {
+            return Err(invalid_data("crc error"));
+        }
+        if self.reader.read_u32()? != self.size {
+            return Err(invalid_data("length error"));
+        }
+        Ok(())
+    }
+
+    // decodes the input until about WINDOW_SIZE bytes of output are produced or it ends
+    fn inflate(&mut self) -> io::Result<()> {
+        let limit = self.output.len() + WINDOW_SIZE;
+        while self.output.len() < limit {
+            self.state = match std::mem::replace(&mut self.state, State::End) {
+                State::MemberHeader => {
+                    self.read_member_header()?;
+                    State::BlockHeader
+                }
+                State::BlockHeader => {
+                    self.last_block = self.reader.read_bits(1)? == 1;
+                    match self.reader.read_bits(2)? {
+                        0 => {
+                            self.reader.align_to_byte();
+                            let length = self.reader.read_u16()?;
+                            if self.reader.read_u16()? != !length {
+                                return Err(invalid_data("stored block length mismatch"));
+                            }
+                            State::Stored(length as usize)
+                        }
+                        1 => {
+                            let (literals, distances) = fixed_codes()?;
+                            State::Compressed(Box::new(literals), Box::new(distances))
+                        }
+                        2 => {
+                            let (literals, distances) = dynamic_codes(&mut self.reader)?;
+                            State::Compressed(Box::new(literals), Box::new(distances))
+                        }
+                        _ => return Err(invalid_data("invalid block type")),
+                    }
+                }
+                State::Stored(remaining) => {
+                    let count = remaining.min(limit - self.output.len());
+                    for _ in 0..count {
+                        self.output.push(self.reader.read_byte()?);
+                    }
+                    match remaining - count {
+                        0 => self.end_of_block(),
+                        remaining => State::Stored(remaining),
+                    }
+                }
+                State::Compressed(literals, distances) => {
+                    let end_of_block = inflate_codes(
+                        &mut self.reader,
+                        &literals,
+                        &distances,
+                        &mut self.output,
+                        limit,
+                    )?;
+                    match end_of_block {
+                        true => self.end_of_block(),
+                        false => State::Compressed(literals, distances),
+                    }
+                }
+                State::MemberTrailer => {
+                    self.update_crc();
+                    self.read_member_trailer()?;
+                    match self.reader.at_end()? {
+                        true => State::End,
+                        false => State::MemberHeader,
+                    }
+                }
+                State::End => break,
+            };
+        }
+        self.update_crc();
+        Ok(())
+    }
+
+    fn end_of_block(&self) -> State {
+        match self.last_block {
+            true => State::MemberTrailer,
+            false => State::BlockHeader,
+        }
+    }
+
+    // adds the output produced since the last update to the crc and size of the member
+    fn update_crc(&mut self) {
+        let produced = &self.output[self.crc_position..];
+        self.crc = crc32(self.crc, produced);
+        self.size = self.size.wrapping_add(produced.len() as u32);
+        self.crc_position = self.output.len();
+    }
+}
+
+impl<R: Read> Read for GzipDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.unread == self.output.len() {
+            if matches!(self.state, State::End) {
+                return Ok(0);
+            }
+            // everything was read, only the window is kept
+            if self.output.len() > 2 * WINDOW_SIZE {
+                self.output.drain(..self.output.len() - WINDOW_SIZE);
+                self.unread = self.output.len();
+                self.crc_position = self.output.len();
+            }
+            self.inflate()?;
+        }
+        let count = buf.len().min(self.output.len() - self.unread);
+        buf[..count].copy_from_slice(&self.output[self.unread..self.unread + count]);
+        self.unread += count;
+        Ok(count)
+    }
+}
@@ -0,0 +1,172 @@
+// Compressed mempool input: gzip (.gz, .tgz) and zstd (.zst, .tzst) files are decompressed
+// in process while loading, tarballs (.tar, optionally compressed) of json files are split into
+// their members. Decompression and tar splitting are streaming, the compressed file is read in
+// chunks and only the window of the decompressor and the current tar member are held in memory.
+
+pub mod gzip;
+pub mod zstd;
+
+use self::gzip::GzipDecoder;
+use self::zstd::ZstdDecoder;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+const TAR_BLOCK_SIZE: usize = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    // returns: Compression of the file name extension, None if uncompressed
+    pub fn from_path(path: &Path) -> Option<Compression> {
+        match path.extension()?.to_str()? {
+            "gz" | "tgz" => Some(Compression::Gzip),
+            "zst" | "tzst" => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+
+    // returns: reader of the decompressed content of the compressed reader
+    pub fn decoder<'a>(self, compressed: impl Read + 'a) -> Box<dyn Read + 'a> {
+        match self {
+            Compression::Gzip => Box::new(GzipDecoder::new(compressed)),
+            Compression::Zstd => Box::new(ZstdDecoder::new(compressed)),
+        }
+    }
+}
+
+// returns: the file name without a compression extension (e.g. "tx.json" of "tx.json.gz")
+pub fn uncompressed_name(path: &Path) -> String {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    match Compression::from_path(path) {
+        Some(_) => match name.rsplit_once('.') {
+            Some((stem, "tgz" | "tzst")) => format!("{}.tar", stem),
+            Some((stem, _)) => stem.to_string(),
+            None => name,
+        },
+        None => name,
+    }
+}
+
+// returns: true for .tar files, also compressed (.tar.gz, .tgz, .tar.zst, .tzst)
+pub fn is_tarball(path: &Path) -> bool {
+    uncompressed_name(path).ends_with(".tar")
+}
+
+// opens the file and decompresses it according to its extension while it's read
+// returns: reader of the (decompressed) content or the io error of opening the file, invalid
+// compressed data fails the reads with io::ErrorKind::InvalidData
+pub fn open_decompressed(path: &Path) -> io::Result<Box<dyn Read>> {
+    let file = File::open(path)?;
+    Ok(match Compression::from_path(path) {
+        Some(compression) => compression.decoder(file),
+        None => Box::new(file),
+    })
+}
+
+// returns: the (decompressed) bytes of the file or the io error
+pub fn read_decompressed(path: &Path) -> io::Result<Vec<u8>> {
+    let mut content = Vec::new();
+    open_decompressed(path)?.read_to_end(&mut content)?;
+    Ok(content)
+}
+
+// returns: the NUL terminated string of the header field
+fn header_field(field: &[u8]) -> String {
+    let end = field
+        .iter()
+        .position(|byte| *byte == 0)
+        .unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).to_string()
+}
+
+fn invalid_tar(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// streaming reader of the regular files of a (ustar, pax or gnu) tar archive, long names of pax
+// and gnu headers are applied, other entries (directories, links) are skipped
+pub struct TarReader<R: Read> {
+    inner: R,
+    offset: u64, // of the next header in the archive
+}
+
+impl<R: Read> TarReader<R> {
+    pub fn new(inner: R) -> Self {
+        TarReader { inner, offset: 0 }
+    }
+
+    // reads exactly count bytes of the archive
+    // returns: the bytes or Err(io::ErrorKind::InvalidData) if the archive is truncated
+    fn read_exact(&mut self, count: u64) -> io::Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        (&mut self.inner).take(count).read_to_end(&mut bytes)?;
+        if (bytes.len() as u64) < count {
+            return Err(invalid_tar(format!(
+                "tar entry at byte {} is truncated",
+                self.offset
+            )));
+        }
+        Ok(bytes)
+    }
+
+    // reads the archive up to the next regular file
+    // returns: name and content of the file, None at the end of the archive or the io error
+    pub fn next_file(&mut self) -> io::Result<Option<(String, Vec<u8>)>> {
+        let mut long_name: Option<String> = None;
+        loop {
+            let mut header = [0u8; TAR_BLOCK_SIZE];
+            let read = (&mut self.inner)
+                .take(TAR_BLOCK_SIZE as u64)
+                .read(&mut header)?;
+            if read == 0 {
+                return Ok(None);
+            }
+            if read < TAR_BLOCK_SIZE {
+                header[read..].copy_from_slice(&self.read_exact((TAR_BLOCK_SIZE - read) as u64)?);
+            }
+            if header.iter().all(|byte| *byte == 0) {
+                return Ok(None);
+            }
+            let size_field = header_field(&header[124..136]);
+            let size = u64::from_str_radix(size_field.trim(), 8).map_err(|_| {
+                invalid_tar(format!("invalid tar size field at byte {}", self.offset))
+            })?;
+            let content = self.read_exact(size)?;
+            let padding = size.next_multiple_of(TAR_BLOCK_SIZE as u64) - size;
+            self.read_exact(padding)?;
+            self.offset += TAR_BLOCK_SIZE as u64 + size + padding;
+
+            match header[156] {
+                b'0' | 0 => {
+                    let prefix = header_field(&header[345..500]);
+                    let name = header_field(&header[..100]);
+                    let name = long_name.take().unwrap_or(match prefix.is_empty() {
+                        true => name,
+                        false => format!("{}/{}", prefix, name),
+                    });
+                    return Ok(Some((name, content)));
+                }
+                b'L' => long_name = Some(header_field(&content)),
+                // pax records "<length> path=<name>\n"
+                b'x' => {
+                    long_name = String::from_utf8_lossy(&content)
+                        .lines()
+                        .find_map(|record| {
+                            record
+                                .split_once(" path=")
+                                .map(|(_, name)| name.to_string())
+                        })
+                }
+                _ => {}
+            }
+        }
+    }
+}
@@ -0,0 +1,929 @@
+// Streaming Zstandard (RFC 8878) decoder. Frames are decoded block by block (at most 128 KiB
+// of output each), the output keeps the window of the frame the matches may point into.
+// Skippable and concatenated frames are supported, dictionaries aren't. The content checksum
+// (XXH64) of a frame is verified if present.
+
+use std::io::{self, Read};
+
+const FRAME_MAGIC: u32 = 0xfd2fb528;
+const SKIPPABLE_MAGIC: u32 = 0x184d2a50; // the lowest 4 bits are user defined
+const MAX_BLOCK_SIZE: usize = 128 * 1024;
+// windows above 128 MiB need --long and --memory to compress and decompress with the zstd tool
+const MAX_WINDOW_SIZE: u64 = 1 << 27;
+const MAX_HUFFMAN_BITS: u32 = 11;
+
+// baselines and extra bits of the literal length codes
+const LITERAL_LENGTH_BASE: [u32; 36] = [
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 18, 20, 22, 24, 28, 32, 40, 48, 64,
+    128, 256, 512, 1024, 2048, 4096, 8192, 16384, 32768, 65536,
+];
+const LITERAL_LENGTH_BITS: [u8; 36] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 3, 3, 4, 6, 7, 8, 9, 10, 11,
+    12, 13, 14, 15, 16,
+];
+// baselines and extra bits of the match length codes
+const MATCH_LENGTH_BASE: [u32; 53] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27,
+    28, 29, 30, 31, 32, 33, 34, 35, 37, 39, 41, 43, 47, 51, 59, 67, 83, 99, 131, 259, 515, 1027,
+    2051, 4099, 8195, 16387, 32771, 65539,
+];
+const MATCH_LENGTH_BITS: [u8; 53] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    1, 1, 1, 1, 2, 2, 3, 3, 4, 4, 5, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16,
+];
+
+// predefined distributions of the sequence codes (-1 is a probability below 1)
+const LITERAL_LENGTH_DEFAULT: [i16; 36] = [
+    4, 3, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2, 2, 3, 2, 1, 1, 1, 1, 1,
+    -1, -1, -1, -1,
+];
+const MATCH_LENGTH_DEFAULT: [i16; 53] = [
+    1, 4, 3, 2, 2, 2, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, -1, -1, -1, -1, -1, -1, -1,
+];
+const OFFSET_DEFAULT: [i16; 29] = [
+    1, 1, 1, 1, 1, 1, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, -1, -1, -1, -1, -1,
+];
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("zstd: {}", message))
+}
+
+fn corrupted() -> io::Error {
+    invalid_data("corrupted block")
+}
+
+fn block_too_large() -> io::Error {
+    invalid_data("decompressed block above 128 KiB")
+}
+
+// returns: the count bytes of data at start as little endian number, Err if data is too short
+fn read_le(data: &[u8], start: usize, count: usize) -> io::Result<u64> {
+    let bytes = data.get(start..start + count).ok_or_else(corrupted)?;
+    Ok(bytes
+        .iter()
+        .rev()
+        .fold(0, |value, byte| value << 8 | *byte as u64))
+}
+
+// returns: up to 8 bytes of data at start as little endian number, zero padded
+fn load_le(data: &[u8], start: usize) -> u64 {
+    let mut bytes = [0u8; 8];
+    if start < data.len() {
+        let end = data.len().min(start + 8);
+        bytes[..end - start].copy_from_slice(&data[start..end]);
+    }
+    u64::from_le_bytes(bytes)
+}
+
+fn highest_bit(value: u32) -> u32 {
+    31 - value.leading_zeros()
+}
+
+// bitstream read from its end towards its start like the entropy coded streams of zstd, the
+// last byte holds a 1 bit marking where the stream starts
+struct BackwardBits<'a> {
+    data: &'a [u8],
+    position: isize, // number of bits not read yet, negative after reading past the start
+}
+
+impl<'a> BackwardBits<'a> {
+    fn new(data: &'a [u8]) -> io::Result<Self> {
+        match data.last() {
+            Some(last) if *last != 0 => Ok(BackwardBits {
+                data,
+                position: (data.len() * 8) as isize - last.leading_zeros() as isize - 1,
+            }),
+            _ => Err(corrupted()),
+        }
+    }
+
+    // returns: the next count (at most 56) bits without consuming them, bits before the start
+    // of the stream are zero
+    fn peek(&self, count: u32) -> u64 {
+        let mask = (1u64 << count) - 1;
+        let start = self.position - count as isize;
+        if start >= 0 {
+            load_le(self.data, start as usize / 8) >> (start % 8) & mask
+        } else if self.position > 0 {
+            let available = self.position as u32;
+            (load_le(self.data, 0) & ((1 << available) - 1)) << (count - available)
+        } else {
+            0
+        }
+    }
+
+    fn read(&mut self, count: u32) -> u64 {
+        let value = self.peek(count);
+        self.position -= count as isize;
+        value
+    }
+
+    fn overflowed(&self) -> bool {
+        self.position < 0
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct FseEntry {
+    symbol: u8,
+    bits: u8,      // number of bits read for the next state
+    baseline: u16, // added to the read bits for the next state
+}
+
+// finite state entropy decoding table
+#[derive(Debug, Clone)]
+struct FseTable {
+    accuracy_log: u32,
+    entries: Vec<FseEntry>,
+}
+
+impl FseTable {
+    // returns: the table of the normalized symbol probabilities, which sum up to
+    // 2^accuracy_log
+    fn new(probabilities: &[i16], accuracy_log: u32) -> FseTable {
+        let size = 1usize << accuracy_log;
+        let mut entries = vec![FseEntry::default(); size];
+        let mut next_state = vec![0u32; probabilities.len()];
+        // symbols with a probability below 1 take the last entries
+        let mut high = size;
+        for (symbol, probability) in probabilities.iter().enumerate() {
+            if *probability == -1 {
+                high -= 1;
+                entries[high].symbol = symbol as u8;
+                next_state[symbol] = 1;
+            } else {
+                next_state[symbol] = *probability as u32;
+            }
+        }
+        let step = (size >> 1) + (size >> 3) + 3;
+        let mut position = 0;
+        for (symbol, probability) in probabilities.iter().enumerate() {
+            for _ in 0..(*probability).max(0) {
+                entries[position].symbol = symbol as u8;
+                position = (position + step) & (size - 1);
+                while position >= high {
+                    position = (position + step) & (size - 1);
+                }
+            }
+        }
+        for entry in &mut entries {
+            let state = next_state[entry.symbol as usize];
+            next_state[entry.symbol as usize] += 1;
+            let bits = accuracy_log - highest_bit(state);
+            entry.bits = bits as u8;
+            entry.baseline = ((state << bits) as usize - size) as u16;
+        }
+        FseTable {
+            accuracy_log,
+            entries,
+        }
+    }
+
+    // returns: the table of a single symbol, read with 0 bits
+    fn rle(symbol: u8) -> FseTable {
+        FseTable {
+            accuracy_log: 0,
+            entries: vec![FseEntry {
+                symbol,
+                bits: 0,
+                baseline: 0,
+            }],
+        }
+    }
+
+    // reads the normalized probabilities of at most max_symbols symbols at the start of data
+    // returns: the table and the number of bytes read or Err if the description is invalid
+    fn read(
+        data: &[u8],
+        max_accuracy_log: u32,
+        max_symbols: usize,
+    ) -> io::Result<(FseTable, usize)> {
+        let mut position = 0; // in bits
+        let mut read_bits = |count: u32, consume: u32| -> io::Result<u32> {
+            if position + count as usize > data.len() * 8 + 64 {
+                return Err(corrupted());
+            }
+            let value = load_le(data, position / 8) >> (position % 8) & ((1 << count) - 1);
+            position += consume as usize;
+            Ok(value as u32)
+        };
+        let accuracy_log = read_bits(4, 4)? + 5;
+        if accuracy_log > max_accuracy_log {
+            return Err(invalid_data("accuracy log too large"));
+        }
+        let mut probabilities: Vec<i16> = Vec::new();
+        let mut remaining = (1i32 << accuracy_log) + 1;
+        let mut threshold = 1i32 << accuracy_log;
+        let mut bits = accuracy_log + 1;
+        while remaining > 1 {
+            if probabilities.len() >= max_symbols {
+                return Err(invalid_data("too many symbols"));
+            }
+            let max = 2 * threshold - 1 - remaining;
+            let value = read_bits(bits, 0)? as i32;
+            let mut count = match value & (threshold - 1) {
+                low if low < max => {
+                    read_bits(0, bits - 1)?;
+                    low
+                }
+                _ => {
+                    read_bits(0, bits)?;
+                    match value & (2 * threshold - 1) {
+                        high if high >= threshold => high - max,
+                        high => high,
+                    }
+                }
+            };
+            count -= 1;
+            remaining -= count.abs();
+            probabilities.push(count as i16);
+            if count == 0 {
+                // 2 bit flags repeating the zero probability, 3 continues
+                loop {
+                    let repeat = read_bits(2, 2)?;
+                    probabilities.extend(std::iter::repeat_n(0, repeat as usize));
+                    if repeat != 3 {
+                        break;
+                    }
+                }
+            }
+            while remaining < threshold && threshold > 1 {
+                bits -= 1;
+                threshold >>= 1;
+            }
+        }
+        let bytes = position.div_ceil(8);
+        if remaining != 1 || bytes > data.len() || probabilities.len() > max_symbols {
+            return Err(corrupted());
+        }
+        Ok((FseTable::new(&probabilities, accuracy_log), bytes))
+    }
+
+    fn symbol(&self, state: usize) -> u8 {
+        self.entries[state].symbol
+    }
+
+    // returns: the state following state, reading its bits from the stream
+    fn next(&self, state: usize, bits: &mut BackwardBits) -> usize {
+        let entry = self.entries[state];
+        entry.baseline as usize + bits.read(entry.bits as u32) as usize
+    }
+}
+
+// prefix code of the literals, indexed by the next max_bits bits of the stream
+#[derive(Debug, Clone)]
+struct HuffmanTable {
+    max_bits: u32,
+    entries: Vec<(u8, u8)>, // symbol and code length
+}
+
+impl HuffmanTable {
+    // reads the tree description at the start of data
+    // returns: the table and the number of bytes read or Err if the description is invalid
+    fn read(data: &[u8]) -> io::Result<(HuffmanTable, usize)> {
+        let header = *data.first().ok_or_else(corrupted)? as usize;
+        let (mut weights, size) = match header {
+            // weights compressed with FSE, two interleaved states share one stream
+            0..=127 => {
+                let compressed = data.get(1..1 + header).ok_or_else(corrupted)?;
+                let (table, used) = FseTable::read(compressed, 6, 256)?;
+                let mut bits = BackwardBits::new(&compressed[used..])?;
+                let mut states = [
+                    bits.read(table.accuracy_log) as usize,
+                    bits.read(table.accuracy_log) as usize,
+                ];
+                let mut weights = Vec::new();
+                for index in [0, 1].into_iter().cycle() {
+                    if weights.len() > 255 {
+                        return Err(corrupted());
+                    }
+                    weights.push(table.symbol(states[index]));
+                    states[index] = table.next(states[index], &mut bits);
+                    if bits.overflowed() {
+                        weights.push(table.symbol(states[1 - index]));
+                        break;
+                    }
+                }
+                (weights, 1 + header)
+            }
+            // 4 bit weights
+            _ => {
+                let count = header - 127;
+                let bytes = data.get(1..1 + count.div_ceil(2)).ok_or_else(corrupted)?;
+                let weights = (0..count)
+                    .map(|index| match index % 2 {
+                        0 => bytes[index / 2] >> 4,
+                        _ => bytes[index / 2] & 0xf,
+                    })
+                    .collect();
+                (weights, 1 + count.div_ceil(2))
+            }
+        };
+
+        // the weight of the last symbol completes the sum to a power of 2
+        if weights.len() > 255 || weights.iter().any(|weight| *weight > 12) {
+            return Err(corrupted());
+        }
+        let sum: u32 = weights
+            .iter()
+            .filter(|weight| **weight > 0)
+            .map(|weight| 1 << (weight - 1))
+            .sum();
+        if sum == 0 {
+            return Err(corrupted());
+        }
+        let max_bits = highest_bit(sum) + 1;
+        let left = (1 << max_bits) - sum;
+        if max_bits > MAX_HUFFMAN_BITS || !left.is_power_of_two() {
+            return Err(corrupted());
+        }
+        weights.push(highest_bit(left) as u8 + 1);
+
+        // codes are assigned by increasing weight, the lowest codes take the first entries
+        let mut entries = Vec::with_capacity(1 << max_bits);
+        for weight in 1..=max_bits as u8 {
+            for (symbol, _) in weights.iter().enumerate().filter(|(_, w)| **w == weight) {
+                let length = (max_bits + 1 - weight as u32) as u8;
+                let count = 1 << (weight - 1);
+                entries.extend(std::iter::repeat_n((symbol as u8, length), count));
+            }
+        }
+        Ok((HuffmanTable { max_bits, entries }, size))
+    }
+
+    // decodes the stream into count literals
+    // returns: Err if the stream doesn't hold exactly count literals
+    fn decode_stream(&self, data: &[u8], count: usize, literals: &mut Vec<u8>) -> io::Result<()> {
+        let mut bits = BackwardBits::new(data)?;
+        for _ in 0..count {
+            let (symbol, length) = self.entries[bits.peek(self.max_bits) as usize];
+            literals.push(symbol);
+            bits.position -= length as isize;
+        }
+        if bits.position != 0 {
+            return Err(corrupted());
+        }
+        Ok(())
+    }
+}
+
+// XXH64 with seed 0, the content checksum of zstd frames
+struct Xxh64 {
+    accumulators: [u64; 4],
+    buffer: Vec<u8>, // input not yet processed in 32 byte stripes
+    length: u64,
+}
+
+const PRIME64_1: u64 = 0x9e3779b185ebca87;
+const PRIME64_2: u64 = 0xc2b2ae3d27d4eb4f;
+const PRIME64_3: u64 = 0x165667b19e3779f9;
+const PRIME64_4: u64 = 0x85ebca77c2b2ae63;
+const PRIME64_5: u64 = 0x27d4eb2f165667c5;
+
+fn xxh64_round(accumulator: u64, input: u64) -> u64 {
+    accumulator
+        .wrapping_add(input.wrapping_mul(PRIME64_2))
+        .rotate_left(31)
+        .wrapping_mul(PRIME64_1)
+}
+
+fn xxh64_merge(hash: u64, accumulator: u64) -> u64 {
+    (hash ^ xxh64_round(0, accumulator))
+        .wrapping_mul(PRIME64_1)
+        .wrapping_add(PRIME64_4)
+}
+
+impl Xxh64 {
+    fn new() -> Self {
+        Xxh64 {
+            accumulators: [
+                PRIME64_1.wrapping_add(PRIME64_2),
+                PRIME64_2,
+                0,
+                0u64.wrapping_sub(PRIME64_1),
+            ],
+            buffer: Vec::with_capacity(32),
+            length: 0,
+        }
+    }
+
+    fn update(&mut self, mut input: &[u8]) {
+        self.length += input.len() as u64;
+        if !self.buffer.is_empty() {
+            let count = input.len().min(32 - self.buffer.len());
+            self.buffer.extend_from_slice(&input[..count]);
+            input = &input[count..];
+            if self.buffer.len() < 32 {
+                return;
+            }
+            let stripe = std::mem::take(&mut self.buffer);
+            self.process(&stripe);
+        }
+        let stripes = input.len() / 32 * 32;
+        for stripe in input[..stripes].chunks_exact(32) {
+            self.process(stripe);
+        }
+        self.buffer.extend_from_slice(&input[stripes..]);
+    }
+
+    fn process(&mut self, stripe: &[u8]) {
+        for (accumulator, lane) in self.accumulators.iter_mut().zip(stripe.chunks_exact(8)) {
+            *accumulator = xxh64_round(*accumulator, load_le(lane, 0));
+        }
+    }
+
+    fn digest(&self) -> u64 {
+        let [v1, v2, v3, v4] = self.accumulators;
+        let mut hash = match self.length >= 32 {
+            true => {
+                let hash = v1
+                    .rotate_left(1)
+                    .wrapping_add(v2.rotate_left(7))
+                    .wrapping_add(v3.rotate_left(12))
+                    .wrapping_add(v4.rotate_left(18));
+                self.accumulators
+                    .iter()
+                    .fold(hash, |hash, v| xxh64_merge(hash, *v))
+            }
+            false => PRIME64_5,
+        };
+        hash = hash.wrapping_add(self.length);
+        let mut rest = self.buffer.as_slice();
+        while rest.len() >= 8 {
+            hash ^= xxh64_round(0, load_le(rest, 0));
+            hash = hash
+                .rotate_left(27)
+                .wrapping_mul(PRIME64_1)
+                .wrapping_add(PRIME64_4);
+            rest = &rest[8..];
+        }
+        if rest.len() >= 4 {
+            hash ^= (load_le(rest, 0) & 0xffffffff).wrapping_mul(PRIME64_1);
+            hash = hash
+                .rotate_left(23)
+                .wrapping_mul(PRIME64_2)
+                .wrapping_add(PRIME64_3);
+            rest = &rest[4..];
+        }
+        for byte in rest {
+            hash ^= (*byte as u64).wrapping_mul(PRIME64_5);
+            hash = hash.rotate_left(11).wrapping_mul(PRIME64_1);
+        }
+        hash ^= hash >> 33;
+        hash = hash.wrapping_mul(PRIME64_2);
+        hash ^= hash >> 29;
+        hash = hash.wrapping_mul(PRIME64_3);
+        hash ^ (hash >> 32)
+    }
+}
+
+// state of the frame being decoded, carried from block to block
+struct Frame {
+    window_size: usize,
+    content_size: Option<u64>,
+    checksum: Option<Xxh64>,
+    produced: u64, // output of the frame so far
+    repeat_offsets: [usize; 3],
+    huffman: Option<HuffmanTable>, // of the previous compressed literals
+    literal_lengths: Option<FseTable>,
+    offsets: Option<FseTable>,
+    match_lengths: Option<FseTable>,
+}
+
+// reads the literals section at the start of the block
+// returns: the number of bytes read or Err if the section is invalid
+fn decode_literals(frame: &mut Frame, block: &[u8], literals: &mut Vec<u8>) -> io::Result<usize> {
+    let header = read_le(block, 0, block.len().min(5))?;
+    let literals_type = header & 3;
+    let size_format = header >> 2 & 3;
+    match literals_type {
+        // raw and rle literals
+        0 | 1 => {
+            let (size, header_size) = match size_format {
+                0 | 2 => (header >> 3 & 0x1f, 1),
+                1 => (header >> 4 & 0xfff, 2),
+                _ => (header >> 4 & 0xfffff, 3),
+            };
+            let size = size as usize;
+            if size > MAX_BLOCK_SIZE {
+                return Err(corrupted());
+            }
+            match literals_type {
+                0 => {
+                    let raw = block
+                        .get(header_size..header_size + size)
+                        .ok_or_else(corrupted)?;
+                    literals.extend_from_slice(raw);
+                    Ok(header_size + size)
+                }
+                _ => {
+                    let byte = *block.get(header_size).ok_or_else(corrupted)?;
+                    literals.resize(size, byte);
+                    Ok(header_size + 1)
+                }
+            }
+        }
+        // huffman coded literals with a new tree (2) or the tree of the previous block (3)
+        _ => {
+            let (header_size, size_bits, streams) = match size_format {
+                0 => (3, 10, 1),
+                1 => (3, 10, 4),
+                2 => (4, 14, 4),
+                _ => (5, 18, 4),
+            };
+            let mask = (1 << size_bits) - 1;
+            let size = (header >> 4 & mask) as usize;
+            let compressed_size = (header >> (4 + size_bits) & mask) as usize;
+            if size > MAX_BLOCK_SIZE {
+                return Err(corrupted());
+            }
+            let mut data = block
+                .get(header_size..header_size + compressed_size)
+                .ok_or_else(corrupted)?;
+            if literals_type == 2 {
+                let (table, used) = HuffmanTable::read(data)?;
+                frame.huffman = Some(table);
+                data = &data[used..];
+            }
+            let table = frame.huffman.as_ref().ok_or_else(corrupted)?;
+            literals.reserve(size);
+            if streams == 1 {
+                table.decode_stream(data, size, literals)?;
+            } else {
+                let segment = size.div_ceil(4);
+                if size < 3 * segment || data.len() < 6 {
+                    return Err(corrupted());
+                }
+                let mut start = 6;
+                for index in 0..4 {
+                    let (length, count) = match index {
+                        3 => (data.len() - start, size - 3 * segment),
+                        _ => (read_le(data, 2 * index, 2)? as usize, segment),
+                    };
+                    let stream = data.get(start..start + length).ok_or_else(corrupted)?;
+                    table.decode_stream(stream, count, literals)?;
+                    start += length;
+                }
+            }
+            Ok(header_size + compressed_size)
+        }
+    }
+}
+
+// reads the table of a sequence code by its compression mode (predefined, rle, fse or repeat)
+fn sequence_table(
+    mode: u64,
+    previous: &mut Option<FseTable>,
+    data: &[u8],
+    position: &mut usize,
+    default: (&[i16], u32),
+    max_accuracy_log: u32,
+    max_symbols: usize,
+) -> io::Result<()> {
+    *previous = match mode {
+        0 => Some(FseTable::new(default.0, default.1)),
+        1 => {
+            let symbol = *data.get(*position).ok_or_else(corrupted)?;
+            *position += 1;
+            Some(FseTable::rle(symbol))
+        }
+        2 => {
+            let rest = data.get(*position..).ok_or_else(corrupted)?;
+            let (table, used) = FseTable::read(rest, max_accuracy_log, max_symbols)?;
+            *position += used;
+            Some(table)
+        }
+        _ => Some(previous.take().ok_or_else(corrupted)?),
+    };
+    Ok(())
+}
+
+// appends the length bytes at offset back in the output
+fn copy_match(output: &mut Vec<u8>, offset: usize, length: usize) {
+    let start = output.len() - offset;
+    if offset >= length {
+        output.extend_from_within(start..start + length);
+    } else {
+        for index in start..start + length {
+            output.push(output[index]);
+        }
+    }
+}
+
+// decodes the sequences section and executes the sequences on the literals
+fn decode_sequences(
+    frame: &mut Frame,
+    data: &[u8],
+    literals: &[u8],
+    output: &mut Vec<u8>,
+) -> io::Result<()> {
+    let first = *data.first().ok_or_else(corrupted)? as usize;
+    let (count, mut position) = match first {
+        0..=127 => (first, 1),
+        128..=254 => (((first - 128) << 8) + read_le(data, 1, 1)? as usize, 2),
+        _ => (read_le(data, 1, 2)? as usize + 0x7f00, 3),
+    };
+    if count == 0 {
+        output.extend_from_slice(literals);
+        return match position == data.len() {
+            true => Ok(()),
+            false => Err(corrupted()),
+        };
+    }
+    let modes = read_le(data, position, 1)?;
+    position += 1;
+    if modes & 3 != 0 {
+        return Err(corrupted());
+    }
+    sequence_table(
+        modes >> 6,
+        &mut frame.literal_lengths,
+        data,
+        &mut position,
+        (&LITERAL_LENGTH_DEFAULT, 6),
+        9,
+        LITERAL_LENGTH_BASE.len(),
+    )?;
+    sequence_table(
+        modes >> 4 & 3,
+        &mut frame.offsets,
+        data,
+        &mut position,
+        (&OFFSET_DEFAULT, 5),
+        8,
+        32,
+    )?;
+    sequence_table(
+        modes >> 2 & 3,
+        &mut frame.match_lengths,
+        data,
+        &mut position,
+        (&MATCH_LENGTH_DEFAULT, 6),
+        9,
+        MATCH_LENGTH_BASE.len(),
+    )?;
+    let (Some(literal_lengths), Some(offsets), Some(match_lengths)) =
+        (&frame.literal_lengths, &frame.offsets, &frame.match_lengths)
+    else {
+        return Err(corrupted());
+    };
+
+    let mut bits = BackwardBits::new(data.get(position..).ok_or_else(corrupted)?)?;
+    let mut literal_length_state = bits.read(literal_lengths.accuracy_log) as usize;
+    let mut offset_state = bits.read(offsets.accuracy_log) as usize;
+    let mut match_length_state = bits.read(match_lengths.accuracy_log) as usize;
+    let block_start = output.len();
+    let mut literal_position = 0;
+    for index in 0..count {
+        let offset_code = offsets.symbol(offset_state) as u32;
+        let match_length_code = match_lengths.symbol(match_length_state) as usize;
+        let literal_length_code = literal_lengths.symbol(literal_length_state) as usize;
+        if offset_code > 31
+            || match_length_code >= MATCH_LENGTH_BASE.len()
+            || literal_length_code >= LITERAL_LENGTH_BASE.len()
+        {
+            return Err(corrupted());
+        }
+        let offset_value = (1u64 << offset_code) + bits.read(offset_code);
+        let match_length = MATCH_LENGTH_BASE[match_length_code] as usize
+            + bits.read(MATCH_LENGTH_BITS[match_length_code] as u32) as usize;
+        let literal_length = LITERAL_LENGTH_BASE[literal_length_code] as usize
+            + bits.read(LITERAL_LENGTH_BITS[literal_length_code] as u32) as usize;
+        if index + 1 < count {
+            literal_length_state = literal_lengths.next(literal_length_state, &mut bits);
+            match_length_state = match_lengths.next(match_length_state, &mut bits);
+            offset_state = offsets.next(offset_state, &mut bits);
+        }
+
+        // offset values 1 to 3 select a repeated offset, shifted by one without literals
+        let repeat = &mut frame.repeat_offsets;
+        let offset = match (offset_value, literal_length) {
+            (4.., _) => {
+                let offset = offset_value as usize - 3;
+                *repeat = [offset, repeat[0], repeat[1]];
+                offset
+            }
+            (1, 1..) => repeat[0],
+            (1, 0) | (2, 1..) => {
+                *repeat = [repeat[1], repeat[0], repeat[2]];
+                repeat[0]
+            }
+            (2, 0) | (3, 1..) => {
+                *repeat = [repeat[2], repeat[0], repeat[1]];
+                repeat[0]
+            }
+            _ => {
+                let offset = repeat[0].checked_sub(1).ok_or_else(corrupted)?;
+                *repeat = [offset, repeat[0], repeat[1]];
+                offset
+            }
+        };
+
+        // a block decompresses to at most 128 KiB, corrupted match lengths would expand a small
+        // block to gigabytes
+        if output.len() - block_start + literal_length + match_length > MAX_BLOCK_SIZE {
+            return Err(block_too_large());
+        }
+        let copied = literals
+            .get(literal_position..literal_position + literal_length)
+            .ok_or_else(corrupted)?;
+        output.extend_from_slice(copied);
+        literal_position += literal_length;
+        let available = frame.produced as usize + output.len() - block_start;
+        if offset == 0 || offset > available || offset > frame.window_size {
+            return Err(invalid_data("match offset out of range"));
+        }
+        copy_match(output, offset, match_length);
+    }
+    if bits.position != 0 {
+        return Err(corrupted());
+    }
+    if output.len() - block_start + literals.len() - literal_position > MAX_BLOCK_SIZE {
+        return Err(block_too_large());
+    }
+    output.extend_from_slice(&literals[literal_position..]);
+    Ok(())
+}
+
+// returns: the next count (at most 8) bytes of the input as little endian number
+fn read_bytes<R: Read>(inner: &mut R, count: usize) -> io::Result<u64> {
+    let mut bytes = [0u8; 8];
+    inner.read_exact(&mut bytes[..count])?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+// decompressing reader of zstd data
+pub struct ZstdDecoder<R: Read> {
+    inner: R,
+    frame: Option<Frame>, // None between frames
+    // window of the previous output of the frame followed by the output that wasn't read yet
+    output: Vec<u8>,
+    unread: usize, // start of the output that wasn't read yet
+    block: Vec<u8>,
+}
+
+impl<R: Read> ZstdDecoder<R> {
+    pub fn new(inner: R) -> Self {
+        ZstdDecoder {
+            inner,
+            frame: None,
+            output: Vec::new(),
+            unread: 0,
+            block: Vec::new(),
+        }
+    }
+
+    // reads the magic number and header of the next frame, skippable frames are skipped
+    // returns: the frame, None at the end of the input or Err if the header is invalid
+    fn read_frame_header(&mut self) -> io::Result<Option<Frame>> {
+        loop {
+            let mut first = [0u8; 1];
+            let read = loop {
+                match self.inner.read(&mut first) {
+                    Ok(read) => break read,
+                    Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(err) => return Err(err),
+                }
+            };
+            if read == 0 {
+                return Ok(None);
+            }
+            let magic = (read_bytes(&mut self.inner, 3)? << 8 | first[0] as u64) as u32;
+            if magic & 0xfffffff0 == SKIPPABLE_MAGIC {
+                let size = read_bytes(&mut self.inner, 4)?;
+                io::copy(&mut (&mut self.inner).take(size), &mut io::sink())?;
+                continue;
+            }
+            if magic != FRAME_MAGIC {
+                return Err(invalid_data("unknown frame format"));
+            }
+            break;
+        }
+
+        let descriptor = read_bytes(&mut self.inner, 1)?;
+        let single_segment = descriptor & 0x20 != 0;
+        if descriptor & 0x08 != 0 {
+            return Err(invalid_data("reserved frame header bit set"));
+        }
+        let mut window_size = match single_segment {
+            true => 0,
+            false => {
+                let window_descriptor = read_bytes(&mut self.inner, 1)?;
+                let base = 1u64 << (10 + (window_descriptor >> 3));
+                base + base / 8 * (window_descriptor & 7)
+            }
+        };
+        let dictionary_bytes = [0, 1, 2, 4][(descriptor & 3) as usize];
+        if read_bytes(&mut self.inner, dictionary_bytes)? != 0 {
+            return Err(invalid_data("dictionaries aren't supported"));
+        }
+        let content_size = match (descriptor >> 6, single_segment) {
+            (0, false) => None,
+            (0, true) => Some(read_bytes(&mut self.inner, 1)?),
+            (1, _) => Some(read_bytes(&mut self.inner, 2)? + 256),
+            (2, _) => Some(read_bytes(&mut self.inner, 4)?),
+            _ => Some(read_bytes(&mut self.inner, 8)?),
+        };
+        if single_segment {
+            window_size = content_size.unwrap_or_default();
+        }
+        if window_size > MAX_WINDOW_SIZE {
+            return Err(invalid_data("window size above 128 MiB"));
+        }
+        Ok(Some(Frame {
+            window_size: window_size as usize,
+            content_size,
+            checksum: (descriptor & 0x04 != 0).then(Xxh64::new),
+            produced: 0,
+            repeat_offsets: [1, 4, 8],
+            huffman: None,
+            literal_lengths: None,
+            offsets: None,
+            match_lengths: None,
+        }))
+    }
+
+    // decodes the next block of the frame, or the header of the next frame
+    // returns: false at the end of the input
+    fn decode_block(&mut self) -> io::Result<bool> {
+        let frame = match &mut self.frame {
+            Some(frame) => frame,
+            None => {
+                // nothing of the previous frame is referenced anymore
+                self.output.clear();
+                self.unread = 0;
+                self.frame = self.read_frame_header()?;
+                return Ok(self.frame.is_some());
+            }
+        };
+        // everything was read, only the window is kept
+        let window_size = frame.window_size;
+        if self.output.len() > window_size + window_size.max(MAX_BLOCK_SIZE) {
+            self.output.drain(..self.output.len() - window_size);
+            self.unread = self.output.len();
+        }
+
+        let mut header = [0u8; 3];
+        self.inner.read_exact(&mut header)?;
+        let header = u32::from_le_bytes([header[0], header[1], header[2], 0]);
+        let last_block = header & 1 == 1;
+        let size = (header >> 3) as usize;
+        if size > MAX_BLOCK_SIZE {
+            return Err(invalid_data("block size above 128 KiB"));
+        }
+        let start = self.output.len();
+        match header >> 1 & 3 {
+            0 => {
+                self.output.resize(start + size, 0);
+                self.inner.read_exact(&mut self.output[start..])?;
+            }
+            1 => {
+                let byte = read_bytes(&mut self.inner, 1)? as u8;
+                self.output.resize(start + size, byte);
+            }
+            2 => {
+                self.block.resize(size, 0);
+                self.inner.read_exact(&mut self.block)?;
+                let mut literals = Vec::new();
+                let used = decode_literals(frame, &self.block, &mut literals)?;
+                decode_sequences(frame, &self.block[used..], &literals, &mut self.output)?;
+            }
+            _ => return Err(invalid_data("reserved block type")),
+        }
+
+        frame.produced += (self.output.len() - start) as u64;
+        if let Some(checksum) = &mut frame.checksum {
+            checksum.update(&self.output[start..]);
+        }
+        if last_block {
+            if frame
+                .content_size
+                .is_some_and(|size| size != frame.produced)
+            {
+                return Err(invalid_data("frame content size mismatch"));
+            }
+            if let Some(checksum) = frame.checksum.take() {
+                if read_bytes(&mut self.inner, 4)? != checksum.digest() & 0xffffffff {
+                    return Err(invalid_data("content checksum mismatch"));
+                }
+            }
+            self.frame = None;
+        }
+        Ok(true)
+    }
+}
+
+impl<R: Read> Read for ZstdDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.unread == self.output.len() {
+            if !self.decode_block()? {
+                return Ok(0);
+            }
+        }
+        let count = buf.len().min(self.output.len() - self.unread);
+        buf[..count].copy_from_slice(&self.output[self.unread..self.unread + count]);
+        self.unread += count;
+        Ok(count)
+    }
+}
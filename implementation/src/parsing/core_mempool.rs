@@ -0,0 +1,140 @@
+// Mempool of a bitcoin core node as transaction source: the `getrawmempool true` dump (fees,
+// weight, depends and spentby of each txid) and the `getrawtransaction <txid> 2` results of its
+// transactions, which contain the raw hex and the spent outputs the mempool dump lacks.
+
+use super::raw::{parse_raw_transaction, set_prevout};
+use super::transaction_structs::Transaction;
+use crate::amount::deserialize_btc;
+use serde::Deserialize;
+use serde_json::from_str;
+use std::collections::{HashMap, HashSet};
+
+// fees of the entry in sat (BTC in the json), modified includes prioritisetransaction deltas
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct MempoolFees {
+    #[serde(deserialize_with = "deserialize_btc")]
+    pub base: u64,
+    #[serde(deserialize_with = "deserialize_btc")]
+    pub modified: u64,
+    #[serde(deserialize_with = "deserialize_btc")]
+    pub ancestor: u64, // modified fees of the transaction and its mempool ancestors
+    #[serde(deserialize_with = "deserialize_btc")]
+    pub descendant: u64, // modified fees of the transaction and its mempool descendants
+}
+
+// entry of a txid in the getrawmempool true result, fields not listed are ignored
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct MempoolEntry {
+    pub vsize: u64,
+    pub weight: u64,
+    pub time: u64,   // unix time the transaction entered the mempool
+    pub height: u64, // block height when the transaction entered the mempool
+    pub wtxid: String,
+    pub fees: MempoolFees,
+    pub depends: Vec<String>, // unconfirmed parents
+    pub spentby: Vec<String>, // unconfirmed children
+    #[serde(rename = "bip125-replaceable", default)]
+    pub bip125_replaceable: bool,
+}
+
+#[derive(Deserialize)]
+struct ScriptPubKey {
+    hex: String,
+}
+
+#[derive(Deserialize)]
+struct VerbosePrevout {
+    #[serde(deserialize_with = "deserialize_btc")]
+    value: u64,
+    #[serde(rename = "scriptPubKey")]
+    script_pubkey: ScriptPubKey,
+}
+
+#[derive(Deserialize)]
+struct VerboseInput {
+    prevout: Option<VerbosePrevout>, // only included with verbosity 2
+}
+
+// getrawtransaction <txid> 2 result, fields not listed are ignored
+#[derive(Deserialize)]
+struct VerboseTransaction {
+    txid: String,
+    hex: String,
+    vin: Vec<VerboseInput>,
+}
+
+// returns: MempoolEntry of each txid of the getrawmempool true json or Err(message)
+pub fn parse_core_mempool(mempool_json: &str) -> Result<HashMap<String, MempoolEntry>, String> {
+    from_str(mempool_json).map_err(|err| err.to_string())
+}
+
+// deserializes the raw hex of the verbose transaction and sets its prevouts
+// returns: Transaction or Err(message) if the hex is invalid, doesn't hash to the txid or an
+// input lacks the prevout
+fn verbose_transaction(verbose: &VerboseTransaction) -> Result<Transaction, String> {
+    let mut tx = parse_raw_transaction(&verbose.hex)
+        .map_err(|err| format!("transaction {}: {}", verbose.txid, err))?;
+    if tx.meta.txid_hex != verbose.txid {
+        return Err(format!(
+            "hex of transaction {} hashes to {}",
+            verbose.txid, tx.meta.txid_hex
+        ));
+    }
+    if tx.vin.len() != verbose.vin.len() {
+        return Err(format!(
+            "vin of transaction {} doesn't match its hex",
+            verbose.txid
+        ));
+    }
+    for (index, (txin, input)) in tx.vin.iter_mut().zip(&verbose.vin).enumerate() {
+        let prevout = input.prevout.as_ref().ok_or(format!(
+            "input {} of transaction {} has no prevout (getrawtransaction verbosity 2 needed)",
+            index, verbose.txid
+        ))?;
+        let scriptpubkey = hex::decode(&prevout.script_pubkey.hex)
+            .map_err(|err| format!("prevout of {}: {}", verbose.txid, err))?;
+        set_prevout(txin, &scriptpubkey, prevout.value);
+    }
+    Ok(tx)
+}
+
+// converts the transactions of a json array of getrawtransaction <txid> 2 results into
+// Transaction structs with the fee, weight, wtxid and parents of their mempool entry. Every entry
+// needs its transaction, transactions that aren't in the mempool and duplicates are skipped.
+// returns: Vec of Transaction structs or Err(message) of the first inconsistency
+pub fn core_mempool_transactions(
+    entries: &HashMap<String, MempoolEntry>,
+    transactions_json: &str,
+) -> Result<Vec<Transaction>, String> {
+    let verbose_transactions: Vec<VerboseTransaction> =
+        from_str(transactions_json).map_err(|err| err.to_string())?;
+    let mut transactions = Vec::new();
+    let mut converted: HashSet<&str> = HashSet::new();
+
+    for verbose in &verbose_transactions {
+        let entry = match entries.get(&verbose.txid) {
+            Some(entry) if converted.insert(&verbose.txid) => entry,
+            _ => continue,
+        };
+        let mut tx = verbose_transaction(verbose)?;
+        if tx.meta.wtxid_hex != entry.wtxid {
+            return Err(format!(
+                "wtxid {} of transaction {} differs from the mempool entry {}",
+                tx.meta.wtxid_hex, verbose.txid, entry.wtxid
+            ));
+        }
+        tx.meta.fee = entry.fees.base;
+        tx.meta.weight = entry.weight;
+        tx.meta.parents = (!entry.depends.is_empty()).then(|| entry.depends.clone());
+        tx.meta.memory_size = tx.memory_size();
+        transactions.push(tx);
+    }
+    if transactions.len() != entries.len() {
+        return Err(format!(
+            "{} of {} mempool entries have no transaction",
+            entries.len() - transactions.len(),
+            entries.len()
+        ));
+    }
+    Ok(transactions)
+}
@@ -0,0 +1,98 @@
+// Selection of the files of a mempool directory: optional recursion into subdirectories and
+// include/exclude glob patterns. Patterns containing a `/` are matched against the path
+// relative to the mempool directory, others against the file name only (like .gitignore).
+// `*` matches within a path segment, `**` across segments and `?` a single character.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+#[derive(Debug, Clone, Default)]
+pub struct DirFilter {
+    pub recursive: bool,
+    pub include: Vec<String>, // a file has to match one of them, all files if empty
+    pub exclude: Vec<String>, // a file matching one of them is skipped
+}
+
+// returns: true if the text matches the glob pattern
+fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern {
+        [] => text.is_empty(),
+        [b'*', b'*', rest @ ..] => {
+            // "**/" also matches no directory at all
+            let rest_without_slash = rest.strip_prefix(b"/").unwrap_or(rest);
+            (0..=text.len()).any(|skip| {
+                glob_match(rest, &text[skip..]) || glob_match(rest_without_slash, &text[skip..])
+            })
+        }
+        [b'*', rest @ ..] => (0..=text.len())
+            .take_while(|skip| !text[..*skip].contains(&b'/'))
+            .any(|skip| glob_match(rest, &text[skip..])),
+        [b'?', rest @ ..] => {
+            matches!(text, [first, text_rest @ ..] if *first != b'/' && glob_match(rest, text_rest))
+        }
+        [first, rest @ ..] => {
+            matches!(text, [text_first, text_rest @ ..] if text_first == first && glob_match(rest, text_rest))
+        }
+    }
+}
+
+// returns: true if the pattern matches the relative path (or its file name)
+fn pattern_matches(pattern: &str, relative_path: &str) -> bool {
+    let text = match pattern.contains('/') {
+        true => relative_path,
+        false => relative_path.rsplit('/').next().unwrap_or(relative_path),
+    };
+    glob_match(pattern.as_bytes(), text.as_bytes())
+}
+
+impl DirFilter {
+    // returns: true if the file at the relative path (`/` separated) is selected
+    pub fn selects(&self, relative_path: &str) -> bool {
+        (self.include.is_empty()
+            || self
+                .include
+                .iter()
+                .any(|pattern| pattern_matches(pattern, relative_path)))
+            && !self
+                .exclude
+                .iter()
+                .any(|pattern| pattern_matches(pattern, relative_path))
+    }
+
+    // collects the selected file entries below the directory, descending into subdirectories
+    // if recursive
+    // returns: the entries or the io error with the path of the unreadable directory
+    pub(super) fn entries(
+        &self,
+        directory: &Path,
+    ) -> Result<Vec<fs::DirEntry>, (std::path::PathBuf, io::Error)> {
+        let mut entries = Vec::new();
+        self.collect_entries(directory, "", &mut entries)?;
+        Ok(entries)
+    }
+
+    fn collect_entries(
+        &self,
+        directory: &Path,
+        prefix: &str,
+        entries: &mut Vec<fs::DirEntry>,
+    ) -> Result<(), (std::path::PathBuf, io::Error)> {
+        let dir_entries = fs::read_dir(directory)
+            .and_then(|entries| entries.collect::<Result<Vec<fs::DirEntry>, io::Error>>())
+            .map_err(|err| (directory.to_path_buf(), err))?;
+
+        for entry in dir_entries {
+            let relative_path = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+            let path = entry.path();
+            if path.is_dir() {
+                if self.recursive {
+                    self.collect_entries(&path, &format!("{}/", relative_path), entries)?;
+                }
+            } else if self.selects(&relative_path) {
+                entries.push(entry);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,187 @@
+// Esplora (blockstream.info/mempool.space) REST API as transaction source. The /tx/<txid> json
+// of esplora is the format of the mempool directory files, inputs without prevout (e.g. from
+// electrs backends not indexing spent outputs) are completed from the output of the parent
+// transaction, the outputs of fetched parents are cached for their other children.
+// Scope: the client is blocking and speaks plain HTTP/1.0 over TCP like the regtest rpc client,
+// not the async https client an esplora integration would usually use. No async runtime and no
+// TLS crate are vendored, so requests are sequential and public instances (https only) need a
+// local TLS terminating proxy. Every connect, read and write is bounded by the timeout so an
+// unresponsive server fails the fetch instead of blocking forever.
+
+use super::parse_json;
+use super::transaction_structs::{InputType, Transaction};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub struct EsploraClient {
+    pub address: String,   // host:port of the server
+    pub prefix: String,    // path of the api without trailing slash, e.g. /api
+    pub timeout: Duration, // of connecting and of each read and write of a request
+    // txid -> vout json of the fetched parents
+    parent_outputs: HashMap<String, Vec<Value>>,
+}
+
+impl EsploraClient {
+    // returns: client of the http://host[:port][/prefix] url or Err(message)
+    pub fn new(url: &str) -> Result<EsploraClient, String> {
+        let without_scheme = url
+            .strip_prefix("http://")
+            .ok_or(format!("{}: only http:// urls are supported", url))?;
+        let (authority, prefix) = match without_scheme.find('/') {
+            Some(index) => without_scheme.split_at(index),
+            None => (without_scheme, ""),
+        };
+        if authority.is_empty() {
+            return Err(format!("{}: missing host", url));
+        }
+        Ok(EsploraClient {
+            address: match authority.contains(':') {
+                true => authority.to_string(),
+                false => format!("{}:80", authority),
+            },
+            prefix: prefix.trim_end_matches('/').to_string(),
+            timeout: DEFAULT_TIMEOUT,
+            parent_outputs: HashMap::new(),
+        })
+    }
+
+    // returns: stream connected to the first reachable address of the server or the io error
+    // of the last attempt
+    fn connect(&self) -> io::Result<TcpStream> {
+        let mut last_err = io::Error::new(io::ErrorKind::NotFound, "host not resolved");
+        for address in self.address.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&address, self.timeout) {
+                Ok(stream) => {
+                    stream.set_read_timeout(Some(self.timeout))?;
+                    stream.set_write_timeout(Some(self.timeout))?;
+                    return Ok(stream);
+                }
+                Err(err) => last_err = err,
+            }
+        }
+        Err(last_err)
+    }
+
+    // sends a GET request for the api path, HTTP/1.0 so the response is never chunked
+    // returns: response body, None for 404 (unknown or evicted txid) or Err(message)
+    pub fn get(&self, path: &str) -> Result<Option<String>, String> {
+        let request = format!(
+            "GET {}{} HTTP/1.0\r\nHost: {}\r\nAccept: application/json\r\n\r\n",
+            self.prefix, path, self.address
+        );
+        let mut stream = self
+            .connect()
+            .map_err(|err| format!("connecting to {}: {}", self.address, err))?;
+        stream
+            .write_all(request.as_bytes())
+            .map_err(|err| format!("requesting {}: {}", path, err))?;
+        let mut response = Vec::new();
+        stream
+            .read_to_end(&mut response)
+            .map_err(|err| format!("reading {}: {}", path, err))?;
+
+        let response = String::from_utf8_lossy(&response);
+        let (head, body) = response
+            .split_once("\r\n\r\n")
+            .ok_or(format!("{}: malformed http response", path))?;
+        let status_line = head.lines().next().unwrap_or("");
+        match status_line.split_whitespace().nth(1) {
+            Some("200") => Ok(Some(body.to_string())),
+            Some("404") => Ok(None),
+            _ => Err(format!("{}: {}", path, status_line)),
+        }
+    }
+
+    // returns: txids of the mempool of the server or Err(message)
+    pub fn mempool_txids(&self) -> Result<Vec<String>, String> {
+        let body = self
+            .get("/mempool/txids")?
+            .ok_or("/mempool/txids: not found".to_string())?;
+        serde_json::from_str(&body).map_err(|err| format!("/mempool/txids: {}", err))
+    }
+
+    // returns: esplora json of the transaction, None if unknown, or Err(message)
+    fn transaction_json(&self, txid: &str) -> Result<Option<Value>, String> {
+        let path = format!("/tx/{}", txid);
+        match self.get(&path)? {
+            Some(body) => serde_json::from_str(&body)
+                .map(Some)
+                .map_err(|err| format!("{}: {}", path, err)),
+            None => Ok(None),
+        }
+    }
+
+    // returns: output vout of the parent transaction, fetched or cached, or Err(message) if
+    // the parent is unknown or has no such output
+    fn parent_output(&mut self, txid: &str, parent_txid: &str, vout: u64) -> Result<Value, String> {
+        if !self.parent_outputs.contains_key(parent_txid) {
+            let mut parent = self
+                .transaction_json(parent_txid)?
+                .ok_or(format!("{}: parent {} not found", txid, parent_txid))?;
+            let outputs = match parent["vout"].take() {
+                Value::Array(outputs) => outputs,
+                _ => return Err(format!("{}: parent {} has no vout", txid, parent_txid)),
+            };
+            self.parent_outputs.insert(parent_txid.to_string(), outputs);
+        }
+        let outputs = &self.parent_outputs[parent_txid];
+        outputs.get(vout as usize).cloned().ok_or(format!(
+            "{}: vout {} out of range, parent {} has {} outputs",
+            txid,
+            vout,
+            parent_txid,
+            outputs.len()
+        ))
+    }
+
+    // fetches the transaction and the outputs of its parents if prevouts are missing
+    // returns: Transaction struct like the directory parsing, None if the txid is unknown
+    // (e.g. mined or evicted after listing the mempool), or Err(message)
+    pub fn transaction(&mut self, txid: &str) -> Result<Option<Transaction>, String> {
+        let mut json = match self.transaction_json(txid)? {
+            Some(json) => json,
+            None => return Ok(None),
+        };
+        for input in json["vin"].as_array_mut().into_iter().flatten() {
+            if !input["prevout"].is_null() || input["is_coinbase"] == Value::Bool(true) {
+                continue;
+            }
+            let (parent_txid, vout) = match (input["txid"].as_str(), input["vout"].as_u64()) {
+                (Some(parent_txid), Some(vout)) => (parent_txid.to_string(), vout),
+                _ => return Err(format!("{}: input without txid and vout", txid)),
+            };
+            input["prevout"] = self.parent_output(txid, &parent_txid, vout)?;
+        }
+
+        let mut tx = parse_json(&json.to_string()).map_err(|err| format!("{}: {}", txid, err))?;
+        for txin in &mut tx.vin {
+            InputType::fetch_type(txin);
+        }
+        tx.decode_hex_fields()
+            .map_err(|err| format!("{}: {}", txid, err))?;
+        tx.meta.txid_hex = txid.to_string();
+        tx.meta.memory_size = tx.memory_size();
+        Ok(Some(tx))
+    }
+
+    // fetches the transactions of the mempool, at most limit if given. Txids that disappear
+    // between listing and fetching are skipped.
+    // returns: Vec of Transaction structs or Err(message) of the first failed request
+    pub fn fetch_mempool(&mut self, limit: Option<usize>) -> Result<Vec<Transaction>, String> {
+        let mut txids = self.mempool_txids()?;
+        txids.truncate(limit.unwrap_or(txids.len()));
+
+        let mut transactions = Vec::new();
+        for txid in &txids {
+            if let Some(tx) = self.transaction(txid)? {
+                transactions.push(tx);
+            }
+        }
+        Ok(transactions)
+    }
+}
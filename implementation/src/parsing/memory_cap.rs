@@ -0,0 +1,127 @@
+// Keeps the transactions of very large snapshots below a total memory cap while parsing.
+// If the cap is exceeded the transactions with the lowest feerate are evicted.
+
+use super::transaction_structs::Transaction;
+use crate::validation::validate_parsing::{assemble_txid_preimage, get_txid};
+use crate::validation::weight_calculation::calculate_weight;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashSet};
+
+#[derive(Debug, Clone)]
+pub struct EvictedTransaction {
+    pub txid: String,
+    pub file: Option<String>,
+    pub memory_size: usize,
+}
+
+// transaction with the fee and weight its eviction order is based on
+struct CappedEntry {
+    fee: u64, // 0 if the outputs exceed the inputs
+    weight: u64,
+    tx: Transaction,
+}
+
+impl CappedEntry {
+    // compares the feerates fee/weight without division
+    fn feerate_cmp(&self, other: &Self) -> Ordering {
+        (self.fee as u128 * other.weight as u128).cmp(&(other.fee as u128 * self.weight as u128))
+    }
+}
+
+impl PartialEq for CappedEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.feerate_cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for CappedEntry {}
+
+impl PartialOrd for CappedEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for CappedEntry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.feerate_cmp(other)
+    }
+}
+
+// set of parsed transactions whose summed memory_size stays below cap bytes
+pub struct MemoryCappedSet {
+    cap: usize,
+    used: usize,
+    entries: BinaryHeap<Reverse<CappedEntry>>, // lowest feerate on top
+    evicted: Vec<EvictedTransaction>,
+}
+
+impl MemoryCappedSet {
+    pub fn new(cap: usize) -> Self {
+        MemoryCappedSet {
+            cap,
+            used: 0,
+            entries: BinaryHeap::new(),
+            evicted: Vec::new(),
+        }
+    }
+
+    // adds the parsed transaction (memory_size has to be set), then evicts the lowest feerate
+    // transactions (possibly the added one) until the set fits into the cap again
+    pub fn insert(&mut self, tx: Transaction) {
+        let inputs: u64 = tx.vin.iter().map(|txin| txin.prevout.value).sum();
+        let outputs: u64 = tx.vout.iter().map(|txout| txout.value).sum();
+
+        self.used += tx.meta.memory_size;
+        self.entries.push(Reverse(CappedEntry {
+            fee: inputs.saturating_sub(outputs),
+            weight: calculate_weight(&tx).max(1) as u64,
+            tx,
+        }));
+        while self.used > self.cap {
+            let Reverse(entry) = self
+                .entries
+                .pop()
+                .expect("Memory cap exceeded by an empty set");
+            self.used -= entry.tx.meta.memory_size;
+            self.evicted.push(EvictedTransaction {
+                txid: hex::encode(get_txid(&assemble_txid_preimage(&entry.tx, false))),
+                file: entry.tx.meta.json_path.clone(),
+                memory_size: entry.tx.meta.memory_size,
+            });
+        }
+    }
+
+    // evicts the held children (and further descendants) of evicted transactions as they
+    // can't be mined without their parents
+    // returns: the held transactions and all evicted ones
+    pub fn finish(self) -> (Vec<Transaction>, Vec<EvictedTransaction>) {
+        let mut transactions: Vec<Transaction> = self
+            .entries
+            .into_iter()
+            .map(|Reverse(entry)| entry.tx)
+            .collect();
+        let mut evicted = self.evicted;
+        let mut evicted_txids: HashSet<String> = evicted.iter().map(|tx| tx.txid.clone()).collect();
+
+        loop {
+            let (orphans, kept): (Vec<Transaction>, Vec<Transaction>) = transactions
+                .into_iter()
+                .partition(|tx| tx.vin.iter().any(|txin| evicted_txids.contains(&txin.txid)));
+            transactions = kept;
+            if orphans.is_empty() {
+                break;
+            }
+            for orphan in orphans {
+                let txid = hex::encode(get_txid(&assemble_txid_preimage(&orphan, false)));
+                evicted_txids.insert(txid.clone());
+                evicted.push(EvictedTransaction {
+                    txid,
+                    file: orphan.meta.json_path.clone(),
+                    memory_size: orphan.meta.memory_size,
+                });
+            }
+        }
+        (transactions, evicted)
+    }
+}
@@ -0,0 +1,137 @@
+// Import of the mempool.dat bitcoin core persists on shutdown (-persistmempool): the version,
+// the witness serialized transactions with their entry time and prioritisetransaction delta,
+// the deltas of txids not in the mempool and the unbroadcast txids. Version 2 files
+// (core 28+) are XOR obfuscated with the key following the version. The dump has no spent
+// outputs, set_prevouts() fills them from the dump itself and known confirmed outputs.
+
+use super::raw::{read_transaction, set_prevouts, Prevout, RawTxError, Reader};
+use super::transaction_structs::Transaction;
+use std::collections::HashMap;
+use std::fmt;
+
+const MEMPOOL_DUMP_VERSION_NO_XOR_KEY: u64 = 1;
+const MEMPOOL_DUMP_VERSION: u64 = 2;
+
+// reason why mempool.dat bytes couldn't be parsed
+#[derive(Debug, Clone, PartialEq)]
+pub enum MempoolDatError {
+    UnsupportedVersion(u64),
+    Raw(RawTxError), // truncated or invalid transaction or record
+}
+
+impl fmt::Display for MempoolDatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MempoolDatError::UnsupportedVersion(version) => {
+                write!(f, "unsupported mempool.dat version {}", version)
+            }
+            MempoolDatError::Raw(err) => write!(f, "invalid mempool.dat ({})", err),
+        }
+    }
+}
+
+impl std::error::Error for MempoolDatError {}
+
+impl From<RawTxError> for MempoolDatError {
+    fn from(err: RawTxError) -> Self {
+        MempoolDatError::Raw(err)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PersistedTransaction {
+    pub tx: Transaction,
+    pub time: i64,      // unix time the transaction entered the mempool
+    pub fee_delta: i64, // prioritisetransaction delta in sat, not applied to the fee
+}
+
+#[derive(Debug, Clone)]
+pub struct MempoolDat {
+    pub version: u64,
+    pub transactions: Vec<PersistedTransaction>,
+    pub fee_deltas: HashMap<String, i64>, // deltas of txids that weren't in the mempool
+    pub unbroadcast: Vec<String>,         // txids not yet announced to a peer
+}
+
+// returns: the txid in display (reversed) hex
+fn txid_hex(bytes: &[u8]) -> String {
+    hex::encode(bytes.iter().rev().copied().collect::<Vec<u8>>())
+}
+
+fn read_i64(reader: &mut Reader, field: &'static str) -> Result<i64, RawTxError> {
+    Ok(reader.read_u64(field)? as i64)
+}
+
+// returns: MempoolDat of the file bytes or Err(MempoolDatError), bytes after the unbroadcast
+// set are ignored like in bitcoin core
+pub fn parse_mempool_dat(bytes: &[u8]) -> Result<MempoolDat, MempoolDatError> {
+    let mut reader = Reader::new(bytes);
+    let version = reader.read_u64("version")?;
+    let deobfuscated: Vec<u8>;
+    match version {
+        MEMPOOL_DUMP_VERSION_NO_XOR_KEY => {}
+        MEMPOOL_DUMP_VERSION => {
+            let key = reader.read_var_bytes("xor key")?.to_vec();
+            // the key applies from the start of the file, offset by the position in the file
+            let start = bytes.len() - reader.remaining();
+            deobfuscated = bytes
+                .iter()
+                .enumerate()
+                .map(|(offset, byte)| match key.is_empty() || offset < start {
+                    true => *byte,
+                    false => byte ^ key[offset % key.len()],
+                })
+                .collect();
+            reader = Reader::new(&deobfuscated);
+            reader.read(start, "xor key")?;
+        }
+        _ => return Err(MempoolDatError::UnsupportedVersion(version)),
+    }
+
+    let count = reader.read_u64("transaction count")?;
+    let mut transactions = Vec::new();
+    for _ in 0..count {
+        let tx = read_transaction(&mut reader)?;
+        let time = read_i64(&mut reader, "entry time")?;
+        let fee_delta = read_i64(&mut reader, "fee delta")?;
+        transactions.push(PersistedTransaction {
+            tx,
+            time,
+            fee_delta,
+        });
+    }
+
+    let mut fee_deltas = HashMap::new();
+    for _ in 0..reader.read_count("fee delta count")? {
+        let txid = txid_hex(reader.read(32, "fee delta txid")?);
+        fee_deltas.insert(txid, read_i64(&mut reader, "fee delta")?);
+    }
+    let mut unbroadcast = Vec::new();
+    for _ in 0..reader.read_count("unbroadcast count")? {
+        unbroadcast.push(txid_hex(reader.read(32, "unbroadcast txid")?));
+    }
+
+    Ok(MempoolDat {
+        version,
+        transactions,
+        fee_deltas,
+        unbroadcast,
+    })
+}
+
+// sets the prevouts of the persisted transactions from the outputs of the dump and the
+// confirmed outputs (keyed by "txid:vout"), fee deltas aren't applied
+// returns: Vec of Transaction structs with the number of inputs left without prevout (these
+// fail the validation) or Err(message) for an invalid confirmed scriptpubkey
+pub fn mempool_dat_transactions(
+    dat: MempoolDat,
+    confirmed: &HashMap<String, Prevout>,
+) -> Result<(Vec<Transaction>, usize), String> {
+    let mut transactions: Vec<Transaction> = dat
+        .transactions
+        .into_iter()
+        .map(|persisted| persisted.tx)
+        .collect();
+    let missing = set_prevouts(&mut transactions, confirmed)?;
+    Ok((transactions, missing))
+}
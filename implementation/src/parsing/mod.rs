@@ -1,70 +1,483 @@
+pub mod cache;
+pub mod compressed;
+pub mod core_mempool;
+pub mod dir_filter;
+pub mod esplora;
+pub mod memory_cap;
+pub mod mempool_dat;
+pub mod psbt;
+pub mod raw;
+pub mod strict;
+#[cfg(test)]
+mod tests;
 pub mod transaction_structs;
+pub mod watch;
 
+use self::compressed::{
+    is_tarball, open_decompressed, read_decompressed, uncompressed_name, TarReader,
+};
+use self::dir_filter::DirFilter;
+use self::memory_cap::{EvictedTransaction, MemoryCappedSet};
+use self::strict::{
+    amount_fields, check_json_ranges, check_prevouts, check_transaction, Inconsistency,
+};
 use self::transaction_structs::{InputType, Transaction};
-use serde_json::from_str;
+use crate::amount::parse_json_amount;
+use crate::validation::validate_parsing::get_txid;
+use serde_json::{from_str, Value};
+use std::fmt;
 use std::fs;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+// reason why a mempool directory couldn't be parsed
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    Io { path: String, message: String }, // directory or file not readable
+    InvalidJson { path: String, message: String }, // file content is no transaction json
+    InvalidHex { path: String, message: String },
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::Io { path, message } => write!(f, "unable to read {}: {}", path, message),
+            ParseError::InvalidJson { path, message } => write!(
+                f,
+                "invalid json content in file {} ({}), delete or correct it",
+                path, message
+            ),
+            ParseError::InvalidHex { path, message } => write!(
+                f,
+                "invalid hex field in file {} ({}), delete or correct it",
+                path, message
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+// returns: ParseError::Io of the path with the message of the io error
+fn io_error(path: &Path, err: std::io::Error) -> ParseError {
+    ParseError::Io {
+        path: path.display().to_string(),
+        message: err.to_string(),
+    }
+}
+
+// returns: the file entries of the directory selected by the filter or ParseError::Io
+fn read_dir_entries(
+    directory_path: &str,
+    filter: &DirFilter,
+) -> Result<Vec<fs::DirEntry>, ParseError> {
+    filter
+        .entries(Path::new(directory_path))
+        .map_err(|(path, err)| io_error(&path, err))
+}
+
+// modification time and size of a mempool file, a change of either marks the file as edited
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FileState {
+    pub modified: Option<SystemTime>,
+    pub size: u64,
+}
+
+// lists the files of the directory selected by the filter, or the file itself if the path is
+// a file. Files removed while listing are left out.
+// returns: path and FileState of each file or ParseError::Io if the directory isn't readable
+pub fn file_states(
+    path: &str,
+    filter: &DirFilter,
+) -> Result<Vec<(PathBuf, FileState)>, ParseError> {
+    let state = |metadata: fs::Metadata| FileState {
+        modified: metadata.modified().ok(),
+        size: metadata.len(),
+    };
+    if Path::new(path).is_file() {
+        let metadata = fs::metadata(path).map_err(|err| io_error(Path::new(path), err))?;
+        return Ok(vec![(PathBuf::from(path), state(metadata))]);
+    }
+    Ok(read_dir_entries(path, filter)?
+        .into_iter()
+        .filter_map(|entry| Some((entry.path(), state(entry.metadata().ok()?))))
+        .collect())
+}
 
 // applies the serde function on the loaded String content of the json
-// returns: Some(Transaction struct) if serde could parse it successfully
-fn parse_json(str_content: &str) -> Option<Transaction> {
-    let tx = from_str::<Transaction>(str_content);
-    if let Ok(tx) = tx {
-        return Some(tx);
-    }
-    println!("{:#?}", tx.err());
-    None
-}
-
-// reads json file parameter into String, calls parse_json on the String and
-// completes the struct with meta information (absolute path to json, input types)
-// returns: Option of Transaction struct
-// panics: if json is invalid
-fn parse_file_content(file_to_load: fs::DirEntry) -> Option<Transaction> {
-    let file_path_buf = file_to_load.path();
-
-    if file_path_buf.extension().expect("Invalid file extension") != "json" {
-        println!(
-            "Invalid file extension: {}, continuing...",
-            file_path_buf.as_path().display()
-        );
-        return None;
-    }
-    let file_content =
-        fs::read_to_string(file_path_buf.as_path()).expect("Reading file content failed");
-
-    match parse_json(&file_content) {
-        Some(mut tx) => {
-            tx.meta.json_path = Some(
-                file_path_buf
-                    .as_path()
-                    .to_str()
-                    .expect("Path to string conversion failed!")
-                    .to_string(),
-            );
+// returns: Transaction struct or Err(serde error message), prefixed with the json path of the
+// field if a sat value field is invalid
+fn parse_json(str_content: &str) -> Result<Transaction, String> {
+    from_str::<Transaction>(str_content)
+        .map_err(|err| json_error(&from_str::<Value>(str_content).unwrap_or_default(), err))
+}
+
+// returns: the serde error message, prefixed with the json path of the first invalid sat value
+// field of the json
+fn json_error(json: &Value, err: serde_json::Error) -> String {
+    match amount_fields(json)
+        .into_iter()
+        .find(|(_, value)| parse_json_amount(value).is_err())
+    {
+        Some((field, _)) => format!("{}: {}", field, err),
+        None => err.to_string(),
+    }
+}
+
+// calls parse_json on the file content and completes the struct with meta information
+// (path of the json, input types, memory size) and the decoded hex fields
+// returns: Transaction struct or Err(ParseError) if the json or a hex field is invalid
+fn parse_content(path: String, file_content: &[u8]) -> Result<Transaction, ParseError> {
+    let file_content = match std::str::from_utf8(file_content) {
+        Ok(file_content) => file_content,
+        Err(err) => {
+            return Err(ParseError::InvalidJson {
+                path,
+                message: err.to_string(),
+            })
+        }
+    };
+    match parse_json(file_content) {
+        Ok(mut tx) => {
+            tx.meta.json_path = Some(path.clone());
             for txin in &mut tx.vin {
                 InputType::fetch_type(txin);
             }
-            Some(tx)
+            if let Err(err) = tx.decode_hex_fields() {
+                return Err(ParseError::InvalidHex {
+                    path,
+                    message: err.to_string(),
+                });
+            }
+            tx.meta.memory_size = tx.memory_size();
+            Ok(tx)
+        }
+        Err(message) => Err(ParseError::InvalidJson { path, message }),
+    }
+}
+
+// returns: true for .json files, also compressed (.json.gz, .json.zst)
+fn is_json_file(path: &Path) -> bool {
+    uncompressed_name(path).ends_with(".json")
+}
+
+// returns: true for json files and tarballs, other files are reported as skipped
+fn is_mempool_file(path: &Path) -> bool {
+    let selected = is_json_file(path) || is_tarball(path);
+    if !selected {
+        println!("Invalid file extension: {}, continuing...", path.display());
+    }
+    selected
+}
+
+// reads the (decompressed) json file, or the json files of the tarball one after the other,
+// and passes the path and content of each on. The members of a tarball are named
+// <tarball>/<member>, only one of them is held in memory.
+// returns: Err(ParseError::Io) if the file isn't readable, the decompression fails or the
+// tarball is truncated
+fn read_json_files(path: &Path, mut on_file: impl FnMut(String, &[u8])) -> Result<(), ParseError> {
+    if !is_tarball(path) {
+        let content = read_decompressed(path).map_err(|err| io_error(path, err))?;
+        on_file(path.display().to_string(), &content);
+        return Ok(());
+    }
+    let mut tarball = TarReader::new(open_decompressed(path).map_err(|err| io_error(path, err))?);
+    while let Some((name, content)) = tarball.next_file().map_err(|err| io_error(path, err))? {
+        if name.ends_with(".json") {
+            on_file(format!("{}/{}", path.display(), name), &content);
+        }
+    }
+    Ok(())
+}
+
+// reads the (decompressed) json file or the json files of the tarball and calls parse_content
+// on each
+// returns: Transaction struct or Err(ParseError) of each json file, nothing for files without
+// json or tar extension and Err(ParseError::Io) last if the file isn't readable
+fn parse_file_content(file_path: &Path) -> Vec<Result<Transaction, ParseError>> {
+    let mut parsed = Vec::new();
+    if !is_mempool_file(file_path) {
+        return parsed;
+    }
+    let read = read_json_files(file_path, |path, content| {
+        parsed.push(parse_content(path, content))
+    });
+    if let Err(err) = read {
+        parsed.push(Err(err));
+    }
+    parsed
+}
+
+// converts an entry of a single file mempool into a Transaction struct, the txid named by the
+// file (key or "txid" field) is checked by the validation, without one the computed txid is set
+// returns: Transaction struct or Err(ParseError) naming the file and entry
+fn parse_file_entry(
+    path: &str,
+    entry_name: String,
+    txid: Option<&str>,
+    json: Value,
+) -> Result<Transaction, ParseError> {
+    let path = format!("{}[{}]", path, entry_name);
+    let mut tx = serde_json::from_value::<Transaction>(json.clone()).map_err(|err| {
+        ParseError::InvalidJson {
+            path: path.clone(),
+            message: json_error(&json, err),
+        }
+    })?;
+    for txin in &mut tx.vin {
+        InputType::fetch_type(txin);
+    }
+    if let Err(err) = tx.decode_hex_fields() {
+        return Err(ParseError::InvalidHex {
+            path,
+            message: err.to_string(),
+        });
+    }
+    tx.meta.txid_hex = match txid {
+        Some(txid) => txid.to_string(),
+        None => hex::encode(get_txid(&tx.serialize(false))),
+    };
+    tx.meta.memory_size = tx.memory_size();
+    Ok(tx)
+}
+
+// splits the json of a single file mempool into its entries
+// returns: entry name, txid if named and json of each transaction or None if the json is no
+// array or object
+fn file_entries(json: Value) -> Option<Vec<(String, Option<String>, Value)>> {
+    match json {
+        Value::Array(transactions) => Some(
+            transactions
+                .into_iter()
+                .enumerate()
+                .map(|(index, json)| {
+                    let txid = json["txid"].as_str().map(str::to_string);
+                    (index.to_string(), txid, json)
+                })
+                .collect(),
+        ),
+        Value::Object(transactions) => Some(
+            transactions
+                .into_iter()
+                .map(|(txid, json)| (txid.clone(), Some(txid), json))
+                .collect(),
+        ),
+        _ => None,
+    }
+}
+
+// parses a file of a watched directory like parse_file_content, a json file holding a single
+// file mempool (an array, or an object of transaction objects) is parsed into all its entries
+// returns: Transaction struct or Err(ParseError) of each transaction of the file
+fn parse_watched_file(file_path: &Path) -> Vec<Result<Transaction, ParseError>> {
+    if !is_json_file(file_path) || is_tarball(file_path) {
+        return parse_file_content(file_path);
+    }
+    let content = match read_decompressed(file_path) {
+        Ok(content) => content,
+        Err(err) => return vec![Err(io_error(file_path, err))],
+    };
+    let path = file_path.display().to_string();
+    let is_file_mempool = |json: &Value| match json {
+        Value::Array(_) => true,
+        Value::Object(entries) => entries.values().all(Value::is_object),
+        _ => false,
+    };
+    match serde_json::from_slice::<Value>(&content) {
+        Ok(json) if is_file_mempool(&json) => file_entries(json)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(entry_name, txid, json)| {
+                parse_file_entry(&path, entry_name, txid.as_deref(), json)
+            })
+            .collect(),
+        _ => vec![parse_content(path, &content)],
+    }
+}
+
+// parses a mempool stored as one json file: an array of transaction objects (optionally with
+// a "txid" field) or an object of txids and their transaction objects, or as tarball of
+// transaction json files. Gzip and zstd compressed files are decompressed while loading.
+// Invalid entries are skipped like unparsable files of a directory.
+// returns: ParsedSnapshot or Err(ParseError) if the file isn't readable or no array/object
+pub fn parse_transactions_from_file(file_path: &str) -> Result<ParsedSnapshot, ParseError> {
+    let path = Path::new(file_path);
+    if is_tarball(path) {
+        let mut snapshot = ParsedSnapshot::default();
+        read_json_files(path, |path, content| match parse_content(path, content) {
+            Ok(tx) => snapshot.transactions.push(tx),
+            Err(err) => snapshot.skipped.push(err),
+        })?;
+        return Ok(snapshot);
+    }
+    let content = open_decompressed(path).map_err(|err| io_error(path, err))?;
+    let entries = match serde_json::from_reader(BufReader::new(content)) {
+        Ok(json) => file_entries(json).ok_or_else(|| ParseError::InvalidJson {
+            path: file_path.to_string(),
+            message: "no array or object of transactions".to_string(),
+        })?,
+        Err(err) if err.is_io() => {
+            return Err(ParseError::Io {
+                path: file_path.to_string(),
+                message: err.to_string(),
+            })
+        }
+        Err(err) => {
+            return Err(ParseError::InvalidJson {
+                path: file_path.to_string(),
+                message: err.to_string(),
+            })
         }
-        None => {
-            panic!(
-                "Invalid Json content in file: {:?}, Delete or correct this file!\n",
-                file_path_buf
-            );
+    };
+
+    let mut snapshot = ParsedSnapshot::default();
+    for (entry_name, txid, json) in entries {
+        match parse_file_entry(file_path, entry_name, txid.as_deref(), json) {
+            Ok(tx) => snapshot.transactions.push(tx),
+            Err(err) => snapshot.skipped.push(err),
         }
     }
+    Ok(snapshot)
+}
+
+// lazily parses the json files of the directory, a file is only read when its transaction is
+// requested, so callers can validate and drop transactions without holding the whole mempool
+// returns: Iterator over the Transaction struct or Err(ParseError) of each json file, a
+// single Err(ParseError::Io) if the directory isn't readable
+pub fn parse_transactions_iter(
+    directory_path: &str,
+) -> impl Iterator<Item = Result<Transaction, ParseError>> {
+    let directory = Path::new(directory_path).to_path_buf();
+    let entries: Box<dyn Iterator<Item = Result<fs::DirEntry, ParseError>>> =
+        match fs::read_dir(&directory) {
+            Ok(entries) => {
+                Box::new(entries.map(move |entry| entry.map_err(|err| io_error(&directory, err))))
+            }
+            Err(err) => Box::new(std::iter::once(Err(io_error(&directory, err)))),
+        };
+    entries.flat_map(|entry| match entry {
+        Ok(entry) => parse_file_content(&entry.path()),
+        Err(err) => vec![Err(err)],
+    })
 }
 
 // opens passed directory calls parse_file_content on each file
-// returns: Vec of Transaction structs
-pub fn parse_transactions_from_dir(directory_path: &str) -> Vec<Transaction> {
+// returns: Vec of Transaction structs or Err(ParseError) of the first unparsable file
+// (parse_transactions_from_dir_skipping continues with the other files)
+pub fn parse_transactions_from_dir(directory_path: &str) -> Result<Vec<Transaction>, ParseError> {
+    parse_transactions_iter(directory_path).collect()
+}
+
+// calls parse_file_content on each file of the directory and passes the transactions on,
+// unparsable files are skipped
+// returns: ParseError of each skipped file or Err(ParseError::Io) if the directory isn't readable
+fn parse_skipping_errors(
+    directory_path: &str,
+    filter: &DirFilter,
+    mut on_transaction: impl FnMut(Transaction),
+) -> Result<Vec<ParseError>, ParseError> {
+    let mut errors: Vec<ParseError> = Vec::new();
+
+    for dir_entry in read_dir_entries(directory_path, filter)? {
+        for parsed in parse_file_content(&dir_entry.path()) {
+            match parsed {
+                Ok(transaction) => on_transaction(transaction),
+                Err(err) => errors.push(err),
+            }
+        }
+    }
+    Ok(errors)
+}
+
+// transactions of a parsed mempool directory with the files skipped while parsing
+#[derive(Debug, Default)]
+pub struct ParsedSnapshot {
+    pub transactions: Vec<Transaction>,
+    pub inconsistencies: Vec<Inconsistency>, // only found by the strict parsing
+    pub evicted: Vec<EvictedTransaction>,    // only evicted by the memory capped parsing
+    pub skipped: Vec<ParseError>,            // unparsable files
+}
+
+// variant of parse_transactions_from_dir that skips unparsable files instead of failing the
+// whole snapshot, the parsed files are selected by the DirFilter (e.g. recursively)
+// returns: ParsedSnapshot or Err(ParseError::Io) if a directory isn't readable
+pub fn parse_transactions_from_dir_skipping(
+    directory_path: &str,
+    filter: &DirFilter,
+) -> Result<ParsedSnapshot, ParseError> {
     let mut transactions: Vec<Transaction> = Vec::new();
+    let skipped = parse_skipping_errors(directory_path, filter, |tx| transactions.push(tx))?;
+    Ok(ParsedSnapshot {
+        transactions,
+        skipped,
+        ..Default::default()
+    })
+}
 
-    for file in fs::read_dir(directory_path).expect("Failed to read directory!") {
-        let dir_entry = file.expect("Failed to read file entry!");
-        if let Some(transaction) = parse_file_content(dir_entry) {
-            transactions.push(transaction);
+// memory capped variant of parse_transactions_from_dir_skipping, the summed memory_size of the
+// returned transactions stays below memory_cap bytes by evicting the lowest feerate transactions
+// (and their descendants) while parsing
+// returns: ParsedSnapshot or Err(ParseError::Io) if a directory isn't readable
+pub fn parse_transactions_from_dir_capped(
+    directory_path: &str,
+    filter: &DirFilter,
+    memory_cap: usize,
+) -> Result<ParsedSnapshot, ParseError> {
+    let mut capped_set = MemoryCappedSet::new(memory_cap);
+    let skipped = parse_skipping_errors(directory_path, filter, |tx| capped_set.insert(tx))?;
+    let (transactions, evicted) = capped_set.finish();
+    Ok(ParsedSnapshot {
+        transactions,
+        evicted,
+        skipped,
+        ..Default::default()
+    })
+}
+
+// strict variant of parse_transactions_from_dir_skipping, checks the raw json ranges of each
+// file before parsing it and cross-checks the redundant json data of the parsed transactions.
+// files with out of range fields are reported and skipped instead of panicking.
+// returns: ParsedSnapshot with all found Inconsistency or Err(ParseError::Io) if a directory
+// isn't readable
+pub fn parse_transactions_from_dir_strict(
+    directory_path: &str,
+    filter: &DirFilter,
+) -> Result<ParsedSnapshot, ParseError> {
+    let mut transactions: Vec<Transaction> = Vec::new();
+    let mut inconsistencies: Vec<Inconsistency> = Vec::new();
+    let mut skipped: Vec<ParseError> = Vec::new();
+
+    for dir_entry in read_dir_entries(directory_path, filter)? {
+        let file_path = dir_entry.path();
+        if !is_mempool_file(&file_path) {
+            continue;
+        }
+        let read = read_json_files(&file_path, |path, content| {
+            if let Ok(json) = serde_json::from_slice::<Value>(content) {
+                let out_of_range = check_json_ranges(&path, &json);
+                if !out_of_range.is_empty() {
+                    inconsistencies.extend(out_of_range);
+                    return;
+                }
+            }
+            match parse_content(path, content) {
+                Ok(transaction) => {
+                    inconsistencies.extend(check_transaction(&transaction));
+                    transactions.push(transaction);
+                }
+                Err(err) => skipped.push(err),
+            }
+        });
+        if let Err(err) = read {
+            skipped.push(err);
         }
     }
-    transactions
+    inconsistencies.extend(check_prevouts(&transactions));
+    Ok(ParsedSnapshot {
+        transactions,
+        inconsistencies,
+        skipped,
+        ..Default::default()
+    })
 }
@@ -0,0 +1,197 @@
+// Ingestion of finalized BIP174 (version 0) PSBTs, e.g. pre-signed packages handed to a miner.
+// The unsigned transaction of the global map is completed with the final scriptsig and witness
+// of each input map, the prevouts come from the witness utxo or the full previous transaction
+// (non-witness utxo) of the input. Partial signatures and derivation paths are ignored.
+
+use super::raw::{deserialize_transaction, set_prevout, RawTxError, Reader};
+use super::transaction_structs::Transaction;
+use crate::validation::script_asm::disassemble;
+use crate::validation::validate_parsing::get_txid;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use std::collections::HashMap;
+use std::fmt;
+
+const PSBT_MAGIC: &[u8] = b"psbt\xff";
+
+const PSBT_GLOBAL_UNSIGNED_TX: u8 = 0x00;
+const PSBT_GLOBAL_VERSION: u8 = 0xfb;
+const PSBT_IN_NON_WITNESS_UTXO: u8 = 0x00;
+const PSBT_IN_WITNESS_UTXO: u8 = 0x01;
+const PSBT_IN_FINAL_SCRIPTSIG: u8 = 0x07;
+const PSBT_IN_FINAL_SCRIPTWITNESS: u8 = 0x08;
+
+// reason why a PSBT couldn't be turned into a transaction
+#[derive(Debug, Clone, PartialEq)]
+pub enum PsbtError {
+    InvalidEncoding(String), // neither binary nor valid base64 or hex
+    MissingMagic,
+    UnsupportedVersion(u32), // only version 0 has the unsigned transaction in the global map
+    DuplicateKey(String),
+    MissingUnsignedTx,
+    NotFinalized { index: usize }, // input without final scriptsig or witness
+    MissingUtxo { index: usize },
+    UtxoMismatch { index: usize }, // non-witness utxo isn't the transaction of the outpoint
+    Raw(RawTxError),
+}
+
+impl fmt::Display for PsbtError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PsbtError::InvalidEncoding(message) => write!(f, "invalid psbt encoding ({})", message),
+            PsbtError::MissingMagic => write!(f, "missing psbt magic bytes"),
+            PsbtError::UnsupportedVersion(version) => {
+                write!(f, "unsupported psbt version {}", version)
+            }
+            PsbtError::DuplicateKey(key) => write!(f, "duplicate psbt key {}", key),
+            PsbtError::MissingUnsignedTx => write!(f, "psbt has no unsigned transaction"),
+            PsbtError::NotFinalized { index } => write!(f, "psbt input {} is not finalized", index),
+            PsbtError::MissingUtxo { index } => write!(f, "psbt input {} has no utxo", index),
+            PsbtError::UtxoMismatch { index } => write!(
+                f,
+                "non-witness utxo of psbt input {} doesn't match its outpoint",
+                index
+            ),
+            PsbtError::Raw(err) => write!(f, "invalid psbt ({})", err),
+        }
+    }
+}
+
+impl std::error::Error for PsbtError {}
+
+impl From<RawTxError> for PsbtError {
+    fn from(err: RawTxError) -> Self {
+        PsbtError::Raw(err)
+    }
+}
+
+// returns: the key-value pairs of the next map or Err(PsbtError) if a key occurs twice
+fn read_map(reader: &mut Reader) -> Result<HashMap<Vec<u8>, Vec<u8>>, PsbtError> {
+    let mut map = HashMap::new();
+    loop {
+        let key_length = reader.read_count("key length")?;
+        if key_length == 0 {
+            return Ok(map);
+        }
+        let key = reader.read(key_length, "key")?.to_vec();
+        let value = reader.read_var_bytes("value")?.to_vec();
+        if map.contains_key(&key) {
+            return Err(PsbtError::DuplicateKey(hex::encode(key)));
+        }
+        map.insert(key, value);
+    }
+}
+
+// returns: value and scriptpubkey of the serialized output
+fn read_utxo(bytes: &[u8]) -> Result<(u64, Vec<u8>), RawTxError> {
+    let mut reader = Reader::new(bytes);
+    let value = reader.read_u64("witness utxo value")?;
+    let scriptpubkey = reader.read_var_bytes("witness utxo scriptpubkey")?.to_vec();
+    Ok((value, scriptpubkey))
+}
+
+// returns: the items of the serialized witness stack
+fn read_witness(bytes: &[u8]) -> Result<Vec<Vec<u8>>, RawTxError> {
+    let mut reader = Reader::new(bytes);
+    let item_count = reader.read_count("final witness item count")?;
+    (0..item_count)
+        .map(|_| Ok(reader.read_var_bytes("final witness item")?.to_vec()))
+        .collect()
+}
+
+// returns: value and scriptpubkey of the output spent by input index
+fn spent_output(
+    index: usize,
+    outpoint_txid: &str,
+    vout: u32,
+    input: &HashMap<Vec<u8>, Vec<u8>>,
+) -> Result<(u64, Vec<u8>), PsbtError> {
+    if let Some(utxo) = input.get([PSBT_IN_WITNESS_UTXO].as_slice()) {
+        return Ok(read_utxo(utxo)?);
+    }
+    let previous = input
+        .get([PSBT_IN_NON_WITNESS_UTXO].as_slice())
+        .ok_or(PsbtError::MissingUtxo { index })?;
+    let previous = deserialize_transaction(previous)?;
+    match previous.vout.get(vout as usize) {
+        Some(txout) if previous.meta.txid_hex == outpoint_txid => {
+            Ok((txout.value, txout.scriptpubkey_bytes.clone()))
+        }
+        _ => Err(PsbtError::UtxoMismatch { index }),
+    }
+}
+
+// extracts the finalized transaction of the binary PSBT
+// returns: Transaction with prevouts or Err(PsbtError)
+pub fn deserialize_psbt(bytes: &[u8]) -> Result<Transaction, PsbtError> {
+    let mut reader = Reader::new(bytes);
+    if reader.read(PSBT_MAGIC.len(), "magic").ok() != Some(PSBT_MAGIC) {
+        return Err(PsbtError::MissingMagic);
+    }
+    let global = read_map(&mut reader)?;
+    if let Some(version) = global.get([PSBT_GLOBAL_VERSION].as_slice()) {
+        let version = Reader::new(version).read_u32("version")?;
+        if version != 0 {
+            return Err(PsbtError::UnsupportedVersion(version));
+        }
+    }
+    let mut tx = deserialize_transaction(
+        global
+            .get([PSBT_GLOBAL_UNSIGNED_TX].as_slice())
+            .ok_or(PsbtError::MissingUnsignedTx)?,
+    )?;
+
+    for (index, txin) in tx.vin.iter_mut().enumerate() {
+        let input = read_map(&mut reader)?;
+        let scriptsig = input.get([PSBT_IN_FINAL_SCRIPTSIG].as_slice());
+        let witness = input.get([PSBT_IN_FINAL_SCRIPTWITNESS].as_slice());
+        if scriptsig.is_none() && witness.is_none() {
+            return Err(PsbtError::NotFinalized { index });
+        }
+        if let Some(scriptsig) = scriptsig.filter(|scriptsig| !scriptsig.is_empty()) {
+            txin.scriptsig_asm = Some(disassemble(scriptsig));
+            txin.scriptsig_bytes = scriptsig.clone();
+        }
+        if let Some(witness) = witness {
+            let items = read_witness(witness)?;
+            if !items.is_empty() {
+                txin.witness_bytes = Some(items);
+            }
+        }
+        let (value, scriptpubkey) = spent_output(index, &txin.txid, txin.vout, &input)?;
+        set_prevout(txin, &scriptpubkey, value);
+    }
+    // output maps only carry signing metadata
+    for _ in 0..tx.vout.len() {
+        read_map(&mut reader)?;
+    }
+
+    tx.encode_hex_fields();
+    // the txid of the unsigned transaction lacks the scriptsigs
+    tx.meta.txid_hex = hex::encode(get_txid(&tx.serialize(false)));
+    tx.meta.wtxid_hex = hex::encode(get_txid(&tx.serialize(true)));
+    tx.meta.memory_size = tx.memory_size();
+    Ok(tx)
+}
+
+// decodes the PSBTs of a file: a single binary PSBT or one base64 or hex PSBT per line
+// returns: Vec of the finalized transactions or Err(PsbtError) of the first invalid PSBT
+pub fn parse_psbt_file(content: &[u8]) -> Result<Vec<Transaction>, PsbtError> {
+    if content.starts_with(PSBT_MAGIC) {
+        return Ok(vec![deserialize_psbt(content)?]);
+    }
+    let text =
+        std::str::from_utf8(content).map_err(|err| PsbtError::InvalidEncoding(err.to_string()))?;
+
+    let mut transactions = Vec::new();
+    for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        let bytes = match line.starts_with("70736274ff") {
+            true => hex::decode(line).map_err(|err| PsbtError::InvalidEncoding(err.to_string())),
+            false => STANDARD
+                .decode(line)
+                .map_err(|err| PsbtError::InvalidEncoding(err.to_string())),
+        }?;
+        transactions.push(deserialize_psbt(&bytes)?);
+    }
+    Ok(transactions)
+}
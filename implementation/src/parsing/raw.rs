@@ -0,0 +1,308 @@
+// Consensus deserialization of raw transaction hex (getrawtransaction/sendrawtransaction format):
+// version, compact size counted inputs and outputs, the BIP144 marker, flag and witnesses and
+// the locktime. The raw encoding doesn't contain the spent outputs, the prevouts are empty
+// "unknown" scripts of 0 sat until set_prevout() fills them in (e.g. from the parent or a
+// gettxout call).
+
+use super::transaction_structs::{InputType, Script, Transaction, TxIn, TxOut};
+use crate::amount::deserialize_amount;
+use crate::validation::script_asm::disassemble;
+use crate::validation::validate_parsing::{get_txid, scriptpubkey_type};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+
+// reason why raw transaction bytes couldn't be deserialized
+#[derive(Debug, Clone, PartialEq)]
+pub enum RawTxError {
+    InvalidHex(String),
+    // bytes end inside the field
+    UnexpectedEnd { field: &'static str, offset: usize },
+    // count encoded in more bytes than needed
+    NonCanonicalCompactSize { offset: usize },
+    UnknownFlag(u8),      // segwit flag other than 0x01
+    SuperfluousWitness,   // marker and flag with all witnesses empty
+    TrailingBytes(usize), // bytes left after the locktime
+}
+
+impl fmt::Display for RawTxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RawTxError::InvalidHex(message) => write!(f, "invalid transaction hex ({})", message),
+            RawTxError::UnexpectedEnd { field, offset } => {
+                write!(f, "transaction ends in the {} at byte {}", field, offset)
+            }
+            RawTxError::NonCanonicalCompactSize { offset } => {
+                write!(f, "non canonical compact size at byte {}", offset)
+            }
+            RawTxError::UnknownFlag(flag) => write!(f, "unknown segwit flag {:#04x}", flag),
+            RawTxError::SuperfluousWitness => write!(f, "superfluous witness record"),
+            RawTxError::TrailingBytes(count) => {
+                write!(f, "{} bytes left after the locktime", count)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RawTxError {}
+
+// cursor over the raw bytes, every read names the field for the error
+pub(super) struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(super) fn new(bytes: &'a [u8]) -> Self {
+        Reader { bytes, offset: 0 }
+    }
+
+    pub(super) fn remaining(&self) -> usize {
+        self.bytes.len() - self.offset
+    }
+
+    // returns: the next count bytes or Err(RawTxError::UnexpectedEnd)
+    pub(super) fn read(
+        &mut self,
+        count: usize,
+        field: &'static str,
+    ) -> Result<&'a [u8], RawTxError> {
+        if self.remaining() < count {
+            return Err(RawTxError::UnexpectedEnd {
+                field,
+                offset: self.offset,
+            });
+        }
+        let bytes = &self.bytes[self.offset..self.offset + count];
+        self.offset += count;
+        Ok(bytes)
+    }
+
+    pub(super) fn read_u32(&mut self, field: &'static str) -> Result<u32, RawTxError> {
+        Ok(u32::from_le_bytes(self.read(4, field)?.try_into().unwrap()))
+    }
+
+    pub(super) fn read_u64(&mut self, field: &'static str) -> Result<u64, RawTxError> {
+        Ok(u64::from_le_bytes(self.read(8, field)?.try_into().unwrap()))
+    }
+
+    // reads the compact size (varint) of bitcoin core, which has to use the shortest encoding
+    // returns: the decoded number
+    pub(super) fn read_compact_size(&mut self, field: &'static str) -> Result<u64, RawTxError> {
+        let offset = self.offset;
+        let (size, minimum) = match self.read(1, field)?[0] {
+            0xfd => (self.read(2, field)?, 0xfd),
+            0xfe => (self.read(4, field)?, 0x1_0000),
+            0xff => (self.read(8, field)?, 0x1_0000_0000),
+            size => return Ok(size as u64),
+        };
+        let mut size_le = [0u8; 8];
+        size_le[..size.len()].copy_from_slice(size);
+        let size = u64::from_le_bytes(size_le);
+        if size < minimum {
+            return Err(RawTxError::NonCanonicalCompactSize { offset });
+        }
+        Ok(size)
+    }
+
+    // a count above the remaining bytes can't be satisfied and isn't preallocated
+    // returns: compact size count of the following items
+    pub(super) fn read_count(&mut self, field: &'static str) -> Result<usize, RawTxError> {
+        let count = self.read_compact_size(field)?;
+        if count > self.remaining() as u64 {
+            return Err(RawTxError::UnexpectedEnd {
+                field,
+                offset: self.offset,
+            });
+        }
+        Ok(count as usize)
+    }
+
+    // returns: compact size prefixed bytes
+    pub(super) fn read_var_bytes(&mut self, field: &'static str) -> Result<&'a [u8], RawTxError> {
+        let length = self.read_count(field)?;
+        self.read(length, field)
+    }
+}
+
+// returns: input with the outpoint, scriptsig and sequence, the prevout is left empty and the
+// hex fields are set by encode_hex_fields()
+fn read_input(reader: &mut Reader) -> Result<TxIn, RawTxError> {
+    let outpoint = reader.read(36, "outpoint")?.to_vec();
+    let scriptsig_bytes = reader.read_var_bytes("scriptsig")?.to_vec();
+    let sequence = reader.read_u32("sequence")?;
+
+    Ok(TxIn {
+        in_type: InputType::default(),
+        is_coinbase: outpoint[..32].iter().all(|byte| *byte == 0) && outpoint[32..] == [0xff; 4],
+        txid: String::new(),
+        vout: 0,
+        scriptsig: None,
+        scriptsig_asm: (!scriptsig_bytes.is_empty()).then(|| disassemble(&scriptsig_bytes)),
+        prevout: prevout_script(&[], 0),
+        witness: None,
+        inner_witnessscript_asm: None,
+        inner_redeemscript_asm: None,
+        sequence,
+        outpoint,
+        scriptsig_bytes,
+        witness_bytes: None,
+    })
+}
+
+// returns: output with the value and scriptpubkey, without address, the hex field is set by
+// encode_hex_fields()
+fn read_output(reader: &mut Reader) -> Result<TxOut, RawTxError> {
+    let value = reader.read_u64("value")?;
+    let scriptpubkey_bytes = reader.read_var_bytes("scriptpubkey")?.to_vec();
+
+    Ok(TxOut {
+        scriptpubkey: None,
+        scriptpubkey_asm: disassemble(&scriptpubkey_bytes),
+        scriptpubkey_type: scriptpubkey_type(&scriptpubkey_bytes).to_string(),
+        scriptpubkey_address: None,
+        value,
+        scriptpubkey_bytes,
+    })
+}
+
+// returns: Script of the spent output with the json fields derived from the scriptpubkey
+pub fn prevout_script(scriptpubkey: &[u8], value: u64) -> Script {
+    Script {
+        scriptpubkey: hex::encode(scriptpubkey),
+        scriptpubkey_asm: disassemble(scriptpubkey),
+        scriptpubkey_type: scriptpubkey_type(scriptpubkey).to_string(),
+        scriptpubkey_address: None,
+        value,
+        scriptpubkey_bytes: scriptpubkey.to_vec(),
+    }
+}
+
+// sets the spent output of the input and its InputType, the memory size of the transaction
+// has to be updated by the caller
+pub fn set_prevout(txin: &mut TxIn, scriptpubkey: &[u8], value: u64) {
+    txin.prevout = prevout_script(scriptpubkey, value);
+    InputType::fetch_type(txin);
+}
+
+// spent output of a confirmed outpoint for raw transaction sources, as json object
+// {"value": sat, "scriptpubkey": hex}
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct Prevout {
+    #[serde(deserialize_with = "deserialize_amount")]
+    pub value: u64,
+    pub scriptpubkey: String,
+}
+
+// sets the prevouts of all inputs spending an output of a transaction in the slice, the other
+// inputs from confirmed (keyed by "txid:vout")
+// returns: number of inputs left without prevout or Err(message) for an invalid scriptpubkey hex
+pub fn set_prevouts(
+    transactions: &mut [Transaction],
+    confirmed: &HashMap<String, Prevout>,
+) -> Result<usize, String> {
+    let mut outputs: HashMap<String, (Vec<u8>, u64)> = HashMap::new();
+    for tx in transactions.iter() {
+        for (index, txout) in tx.vout.iter().enumerate() {
+            let outpoint = format!("{}:{}", tx.meta.txid_hex, index);
+            outputs.insert(outpoint, (txout.scriptpubkey_bytes.clone(), txout.value));
+        }
+    }
+    for (outpoint, prevout) in confirmed {
+        let scriptpubkey = hex::decode(&prevout.scriptpubkey)
+            .map_err(|err| format!("prevout {}: {}", outpoint, err))?;
+        outputs
+            .entry(outpoint.clone())
+            .or_insert((scriptpubkey, prevout.value));
+    }
+
+    let mut missing = 0;
+    for tx in transactions.iter_mut() {
+        for txin in &mut tx.vin {
+            match outputs.get(&format!("{}:{}", txin.txid, txin.vout)) {
+                Some((scriptpubkey, value)) => set_prevout(txin, scriptpubkey, *value),
+                None => missing += 1,
+            }
+        }
+        tx.meta.memory_size = tx.memory_size();
+    }
+    Ok(missing)
+}
+
+// deserializes the transaction like bitcoin core: an input count of 0 is the segwit marker if
+// followed by the flag 0x01, then every input has a witness stack of which at least one is
+// non-empty. Empty witness stacks are stored as None, txid and wtxid are set in the metadata.
+// returns: the next Transaction of the reader with empty prevouts or Err(RawTxError)
+pub(super) fn read_transaction(reader: &mut Reader) -> Result<Transaction, RawTxError> {
+    let version = reader.read_u32("version")? as i32;
+
+    let mut input_count = reader.read_count("input count")?;
+    let mut segwit = false;
+    if input_count == 0 {
+        match reader.read(1, "segwit flag")?[0] {
+            0x01 => segwit = true,
+            flag => return Err(RawTxError::UnknownFlag(flag)),
+        }
+        input_count = reader.read_count("input count")?;
+    }
+    let mut vin = Vec::with_capacity(input_count);
+    for _ in 0..input_count {
+        vin.push(read_input(reader)?);
+    }
+    let output_count = reader.read_count("output count")?;
+    let mut vout = Vec::with_capacity(output_count);
+    for _ in 0..output_count {
+        vout.push(read_output(reader)?);
+    }
+    if segwit {
+        for txin in &mut vin {
+            let item_count = reader.read_count("witness item count")?;
+            let mut items = Vec::with_capacity(item_count);
+            for _ in 0..item_count {
+                items.push(reader.read_var_bytes("witness item")?.to_vec());
+            }
+            if !items.is_empty() {
+                txin.witness_bytes = Some(items);
+            }
+        }
+        if vin.iter().all(|txin| txin.witness_bytes.is_none()) {
+            return Err(RawTxError::SuperfluousWitness);
+        }
+    }
+    let locktime = reader.read_u32("locktime")?;
+
+    let mut tx = Transaction {
+        meta: Default::default(),
+        version,
+        locktime,
+        vin,
+        vout,
+    };
+    tx.encode_hex_fields();
+    for txin in &mut tx.vin {
+        InputType::fetch_type(txin);
+    }
+    tx.meta.txid_hex = hex::encode(get_txid(&tx.serialize(false)));
+    tx.meta.wtxid_hex = hex::encode(get_txid(&tx.serialize(true)));
+    tx.meta.memory_size = tx.memory_size();
+    Ok(tx)
+}
+
+// returns: Transaction of the raw bytes with empty prevouts or Err(RawTxError), also if bytes
+// are left after the transaction
+pub fn deserialize_transaction(bytes: &[u8]) -> Result<Transaction, RawTxError> {
+    let mut reader = Reader::new(bytes);
+    let tx = read_transaction(&mut reader)?;
+    if reader.remaining() != 0 {
+        return Err(RawTxError::TrailingBytes(reader.remaining()));
+    }
+    Ok(tx)
+}
+
+// returns: Transaction of the raw transaction hex (surrounding whitespace is ignored) with empty
+// prevouts or Err(RawTxError)
+pub fn parse_raw_transaction(raw_hex: &str) -> Result<Transaction, RawTxError> {
+    let bytes =
+        hex::decode(raw_hex.trim()).map_err(|err| RawTxError::InvalidHex(err.to_string()))?;
+    deserialize_transaction(&bytes)
+}
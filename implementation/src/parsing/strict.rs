@@ -0,0 +1,265 @@
+// Strict parsing checks cross-checking the internally redundant data of the mempool json files
+
+use super::transaction_structs::{Transaction, TxIn};
+use crate::amount::parse_json_amount;
+use crate::validation::script_asm::{assemble, disassemble};
+use crate::validation::validate_parsing::{assemble_txid_preimage, get_txid};
+use serde_json::Value;
+use std::collections::HashMap;
+
+const COINBASE_PREVOUT_TXID: &str =
+    "0000000000000000000000000000000000000000000000000000000000000000";
+const COINBASE_PREVOUT_VOUT: u32 = 0xffffffff;
+
+#[derive(Debug, Clone)]
+pub struct Inconsistency {
+    pub file: String,
+    pub field: String, // json path of the field, e.g. vin[0].prevout.value
+    pub message: String,
+}
+
+impl std::fmt::Display for Inconsistency {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}: {}: {}", self.file, self.field, self.message)
+    }
+}
+
+// checks that the integer at field (json path) of the raw json lies in min..=max
+fn check_range(
+    file: &str,
+    value: &Value,
+    field: String,
+    min: i128,
+    max: i128,
+    found: &mut Vec<Inconsistency>,
+) {
+    let number = match value {
+        Value::Number(number) => number
+            .as_u64()
+            .map(i128::from)
+            .or_else(|| number.as_i64().map(i128::from)),
+        _ => None,
+    };
+    let message = match number {
+        Some(number) if number >= min && number <= max => return,
+        Some(number) => format!("{} out of range {}..={}", number, min, max),
+        None => format!("{} is not an integer", value),
+    };
+    found.push(Inconsistency {
+        file: file.to_string(),
+        field,
+        message,
+    });
+}
+
+// checks the integer fields of the raw json against the ranges of their serialized size
+// (version i32, locktime/sequence/vout index u32) and the encoding of the sat values
+// (see parse_json_amount()), out of range files can't be parsed
+// returns: Vec of Inconsistency, empty if all fields are in range
+pub fn check_json_ranges(file: &str, json: &Value) -> Vec<Inconsistency> {
+    let mut found = Vec::new();
+    let u32_max = u32::MAX as i128;
+
+    check_range(
+        file,
+        &json["version"],
+        "version".to_string(),
+        i32::MIN as i128,
+        i32::MAX as i128,
+        &mut found,
+    );
+    check_range(
+        file,
+        &json["locktime"],
+        "locktime".to_string(),
+        0,
+        u32_max,
+        &mut found,
+    );
+    if let Some(inputs) = json["vin"].as_array() {
+        for (index, input) in inputs.iter().enumerate() {
+            let path = format!("vin[{}]", index);
+            check_range(
+                file,
+                &input["vout"],
+                format!("{}.vout", path),
+                0,
+                u32_max,
+                &mut found,
+            );
+            check_range(
+                file,
+                &input["sequence"],
+                format!("{}.sequence", path),
+                0,
+                u32_max,
+                &mut found,
+            );
+        }
+    }
+    for (field, value) in amount_fields(json) {
+        if let Err(message) = parse_json_amount(value) {
+            found.push(Inconsistency {
+                file: file.to_string(),
+                field,
+                message,
+            });
+        }
+    }
+    found
+}
+
+// returns: (json path, value) of the sat value fields of the inputs (prevout) and outputs
+pub(crate) fn amount_fields(json: &Value) -> Vec<(String, &Value)> {
+    let mut fields = Vec::new();
+    if let Some(inputs) = json["vin"].as_array() {
+        for (index, input) in inputs.iter().enumerate() {
+            fields.push((
+                format!("vin[{}].prevout.value", index),
+                &input["prevout"]["value"],
+            ));
+        }
+    }
+    if let Some(outputs) = json["vout"].as_array() {
+        for (index, output) in outputs.iter().enumerate() {
+            fields.push((format!("vout[{}].value", index), &output["value"]));
+        }
+    }
+    fields
+}
+
+// assembles the asm field and compares it against the script hex, scripts ending in a truncated
+// push can't be assembled and are compared by their disassembly
+fn check_asm(file: &str, field: String, script_hex: &str, asm: &str) -> Option<Inconsistency> {
+    let message = match (hex::decode(script_hex), assemble(asm)) {
+        (Err(_), _) => "script is no valid hex".to_string(),
+        (Ok(script), Ok(assembled)) if assembled == script => return None,
+        (Ok(script), _) if disassemble(&script) == asm => return None,
+        (Ok(script), Ok(assembled)) => format!(
+            "asm \"{}\" assembles to {} instead of the script (\"{}\")",
+            asm,
+            hex::encode(assembled),
+            disassemble(&script)
+        ),
+        (Ok(script), Err(reason)) => format!(
+            "asm \"{}\" can't be assembled ({}), script is \"{}\"",
+            asm,
+            reason,
+            disassemble(&script)
+        ),
+    };
+    Some(Inconsistency {
+        file: file.to_string(),
+        field,
+        message,
+    })
+}
+
+fn check_coinbase_flag(file: &str, index: usize, txin: &TxIn) -> Option<Inconsistency> {
+    let coinbase_prevout = txin.txid == COINBASE_PREVOUT_TXID && txin.vout == COINBASE_PREVOUT_VOUT;
+    if txin.is_coinbase == coinbase_prevout {
+        return None;
+    }
+    Some(Inconsistency {
+        file: file.to_string(),
+        field: format!("vin[{}].is_coinbase", index),
+        message: format!(
+            "is_coinbase is {} but the prevout {} the all zero coinbase outpoint",
+            txin.is_coinbase,
+            if coinbase_prevout { "is" } else { "isn't" }
+        ),
+    })
+}
+
+// checks the asm fields against the scripts and the is_coinbase flags against the prevouts
+// returns: Vec of Inconsistency found in the passed Transaction
+pub fn check_transaction(tx: &Transaction) -> Vec<Inconsistency> {
+    let file = tx.meta.json_path.as_deref().unwrap_or("unknown file");
+    let mut found = Vec::new();
+
+    for (index, txin) in tx.vin.iter().enumerate() {
+        found.extend(check_coinbase_flag(file, index, txin));
+        found.extend(check_asm(
+            file,
+            format!("vin[{}].prevout.scriptpubkey_asm", index),
+            &txin.prevout.scriptpubkey,
+            &txin.prevout.scriptpubkey_asm,
+        ));
+        if let Some(scriptsig) = &txin.scriptsig {
+            found.extend(check_asm(
+                file,
+                format!("vin[{}].scriptsig_asm", index),
+                scriptsig,
+                txin.scriptsig_asm.as_deref().unwrap_or(""),
+            ));
+        }
+    }
+    for (index, txout) in tx.vout.iter().enumerate() {
+        found.extend(check_asm(
+            file,
+            format!("vout[{}].scriptpubkey_asm", index),
+            txout.scriptpubkey.as_deref().unwrap_or(""),
+            &txout.scriptpubkey_asm,
+        ));
+    }
+    found
+}
+
+// compares the prevout claims of all inputs spending a transaction of the snapshot
+// against the value and scriptpubkey of the spent output
+// returns: Vec of Inconsistency found in the spending transactions
+pub fn check_prevouts(transactions: &[Transaction]) -> Vec<Inconsistency> {
+    let mut by_txid: HashMap<String, &Transaction> = HashMap::new();
+    for tx in transactions {
+        let txid = hex::encode(get_txid(&assemble_txid_preimage(tx, false)));
+        by_txid.insert(txid, tx);
+    }
+
+    let mut found = Vec::new();
+    for tx in transactions {
+        let file = tx.meta.json_path.as_deref().unwrap_or("unknown file");
+        for (index, txin) in tx.vin.iter().enumerate() {
+            let parent = match by_txid.get(&txin.txid) {
+                Some(parent) => parent,
+                None => continue,
+            };
+            let path = format!("vin[{}]", index);
+            let spent = match parent.vout.get(txin.vout as usize) {
+                Some(spent) => spent,
+                None => {
+                    found.push(Inconsistency {
+                        file: file.to_string(),
+                        field: format!("{}.vout", path),
+                        message: format!(
+                            "parent {} has only {} outputs",
+                            txin.txid,
+                            parent.vout.len()
+                        ),
+                    });
+                    continue;
+                }
+            };
+            if spent.value != txin.prevout.value {
+                found.push(Inconsistency {
+                    file: file.to_string(),
+                    field: format!("{}.prevout.value", path),
+                    message: format!(
+                        "claims {} sat but output {} of parent {} holds {} sat",
+                        txin.prevout.value, txin.vout, txin.txid, spent.value
+                    ),
+                });
+            }
+            if spent.scriptpubkey.as_deref() != Some(txin.prevout.scriptpubkey.as_str()) {
+                found.push(Inconsistency {
+                    file: file.to_string(),
+                    field: format!("{}.prevout.scriptpubkey", path),
+                    message: format!(
+                        "doesn't match the scriptpubkey of output {} of parent {}",
+                        txin.vout, txin.txid
+                    ),
+                });
+            }
+        }
+    }
+    found
+}
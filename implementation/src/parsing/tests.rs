@@ -0,0 +1,920 @@
+// Tests of the mempool sources: raw transactions, bitcoin core dumps, psbt packages and the
+// json directory and file parsers with the gzip and zstd decoders. The tests validating the
+// P2PKH fixtures need the interpreter.
+
+use super::cache::{deserialize_cache, serialize_cache, CacheError, CachedSnapshot};
+use super::compressed::Compression;
+use super::dir_filter::DirFilter;
+use super::esplora::EsploraClient;
+use super::raw::{deserialize_transaction, set_prevout, RawTxError};
+use super::transaction_structs::{InputType, Transaction};
+use super::watch::MempoolWatcher;
+use super::{
+    file_states, parse_transactions_from_dir, parse_transactions_from_dir_skipping, ParseError,
+};
+use crate::validation::validate_parsing::{assemble_txid_preimage, get_txid};
+use crate::validation::ValidationOptions;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::HashMap;
+use std::fs;
+use std::io::Read;
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+#[cfg(feature = "interpreter")]
+use {
+    super::core_mempool::{core_mempool_transactions, parse_core_mempool},
+    super::mempool_dat::{mempool_dat_transactions, parse_mempool_dat, MempoolDatError},
+    super::psbt::{parse_psbt_file, PsbtError},
+    super::raw::Prevout,
+    super::{parse_transactions_from_file, parse_transactions_iter},
+    crate::feerate::vsize,
+    base64::engine::general_purpose::STANDARD,
+    base64::Engine,
+    std::io::{BufRead, BufReader, Write},
+    std::sync::mpsc,
+};
+
+fn fixture() -> Transaction {
+    parse_transactions_from_dir("test_scripts/testfiles/p2pkh")
+        .expect("Parsing the fixtures failed")
+        .remove(0)
+}
+
+// returns: empty directory for the files of the test, unique per test and process so parallel
+// tests and concurrent test runs don't share it
+fn temp_directory(test: &str) -> PathBuf {
+    let directory =
+        std::env::temp_dir().join(format!("block_builder_{}_{}", test, std::process::id()));
+    let _ = fs::remove_dir_all(&directory);
+    fs::create_dir_all(&directory).unwrap();
+    directory
+}
+
+#[test]
+fn raw_transactions_deserialize_like_bitcoin_core() {
+    let tx = fixture();
+    let raw = tx.serialize(true);
+    assert_eq!(raw, tx.serialize(false));
+    let mut raw_tx = deserialize_transaction(&raw).unwrap();
+    assert_eq!(raw_tx.serialize(true), raw);
+    assert_eq!(raw_tx.vin[0].scriptsig_asm, tx.vin[0].scriptsig_asm);
+    // the hex fields encoded from the bytes match the json ones decoded into bytes
+    assert_eq!(
+        (
+            &raw_tx.vin[0].txid,
+            raw_tx.vin[0].vout,
+            &raw_tx.vin[0].outpoint
+        ),
+        (&tx.vin[0].txid, tx.vin[0].vout, &tx.vin[0].outpoint)
+    );
+    assert_eq!(
+        (&raw_tx.vin[0].scriptsig, &raw_tx.vin[0].scriptsig_bytes),
+        (&tx.vin[0].scriptsig, &tx.vin[0].scriptsig_bytes)
+    );
+    for (raw_txout, txout) in raw_tx.vout.iter().zip(&tx.vout) {
+        assert_eq!(
+            (&raw_txout.scriptpubkey, &raw_txout.scriptpubkey_bytes),
+            (&txout.scriptpubkey, &txout.scriptpubkey_bytes)
+        );
+    }
+    assert_eq!(
+        raw_tx.vin[0].in_type,
+        InputType::UNKNOWN("unknown".to_string())
+    );
+    let prevout = tx.vin[0].prevout.clone();
+    set_prevout(
+        &mut raw_tx.vin[0],
+        &prevout.scriptpubkey_bytes,
+        prevout.value,
+    );
+    assert_eq!(raw_tx.vin[0].in_type, InputType::P2PKH);
+    assert_eq!(
+        raw_tx.vin[0].prevout.scriptpubkey_asm,
+        prevout.scriptpubkey_asm
+    );
+
+    assert!(matches!(
+        deserialize_transaction(&raw[..raw.len() - 1]),
+        Err(RawTxError::UnexpectedEnd {
+            field: "locktime",
+            ..
+        })
+    ));
+    let trailing = [raw.as_slice(), &[0]].concat();
+    assert_eq!(
+        deserialize_transaction(&trailing).unwrap_err(),
+        RawTxError::TrailingBytes(1)
+    );
+    // marker and flag with only empty witnesses
+    assert_eq!(
+        deserialize_transaction(&assemble_txid_preimage(&tx, true)).unwrap_err(),
+        RawTxError::SuperfluousWitness
+    );
+    // input count 1 encoded as 0xfd 0x0100
+    let non_canonical = [&raw[..4], &[0xfd, 0x01, 0x00], &raw[5..]].concat();
+    assert_eq!(
+        deserialize_transaction(&non_canonical).unwrap_err(),
+        RawTxError::NonCanonicalCompactSize { offset: 4 }
+    );
+}
+
+// signed native P2WPKH example of BIP143
+const BIP143_SIGNED_TX: &str = "01000000000102fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f00000000494830450221008b9d1dc26ba6a9cb62127b02742fa9d754cd3bebf337f7a55d114c8e5cdd30be022040529b194ba3f9281a99f2b1c0a19c0489bc22ede944ccf4ecbab4cc618ef3ed01eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a0100000000ffffffff02202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac9093510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac000247304402203609e17b84f6a7d30c80bfa610b5b4542f32a8a0d5447a12fb1366d7f01cc44a0220573a954c4518331561406f90300e8f3358f51928d43c212a8caed02de67eebee0121025476c2e83188368da1ff3e292e7acafcdb3566bb0ad253f62fc70f07aeee635711000000";
+const BIP143_SIGNED_TXID: &str = "e8151a2af31c368a35053ddd4bdb285a8595c769a3ad83e0fa02314a602d4609";
+const BIP143_SIGNED_WTXID: &str =
+    "c36c38370907df2324d9ce9d149d191192f338b37665a82e78e76a12c909b762";
+
+#[test]
+fn segwit_transactions_serialize_with_and_without_witness() {
+    let raw = hex::decode(BIP143_SIGNED_TX).unwrap();
+    let mut tx = deserialize_transaction(&raw).unwrap();
+    assert_eq!(tx.serialize(true), raw);
+    assert_eq!(hex::encode(get_txid(&raw)), BIP143_SIGNED_WTXID);
+    // marker, flag and the 108 witness bytes are left out
+    let stripped = tx.serialize(false);
+    assert_eq!(stripped.len(), raw.len() - 2 - 108);
+    assert_eq!(hex::encode(get_txid(&stripped)), BIP143_SIGNED_TXID);
+
+    // without witness data the witness serialization is the txid one
+    for txin in &mut tx.vin {
+        txin.witness = None;
+        txin.witness_bytes = None;
+    }
+    assert_eq!(tx.serialize(true), stripped);
+}
+
+#[cfg(feature = "interpreter")]
+#[test]
+fn core_mempool_dumps_convert_into_valid_transactions() {
+    let mut tx = fixture();
+    tx.validate(&ValidationOptions::default()).unwrap();
+    let mempool = format!(
+        r#"{{"{}": {{"vsize": {}, "weight": {}, "time": 1700000000, "height": 800000,
+            "wtxid": "{}", "fees": {{"base": {}, "modified": 0.0001, "ancestor": 0.0001,
+            "descendant": 0.0001}}, "depends": [], "spentby": [], "bip125-replaceable": false}}}}"#,
+        tx.meta.txid_hex,
+        vsize(tx.meta.weight),
+        tx.meta.weight,
+        tx.meta.wtxid_hex,
+        tx.meta.fee as f64 / 100_000_000.0
+    );
+    let inputs: Vec<String> = tx
+        .vin
+        .iter()
+        .map(|txin| {
+            format!(
+                r#"{{"prevout": {{"value": {}, "scriptPubKey": {{"hex": "{}"}}}}}}"#,
+                txin.prevout.value as f64 / 100_000_000.0,
+                txin.prevout.scriptpubkey
+            )
+        })
+        .collect();
+    let verbose = format!(
+        r#"[{{"txid": "{}", "hex": "{}", "vin": [{}], "fee": 0.0001}}]"#,
+        tx.meta.txid_hex,
+        hex::encode(tx.serialize(true)),
+        inputs.join(", ")
+    );
+
+    let entries = parse_core_mempool(&mempool).unwrap();
+    assert_eq!(entries[&tx.meta.txid_hex].fees.base, tx.meta.fee);
+    let mut core_tx = core_mempool_transactions(&entries, &verbose)
+        .unwrap()
+        .remove(0);
+    assert_eq!(core_tx.meta.fee, tx.meta.fee);
+    assert_eq!(core_tx.validate(&ValidationOptions::default()), Ok(()));
+
+    // every mempool entry needs its transaction
+    assert!(core_mempool_transactions(&entries, "[]").is_err());
+}
+
+#[cfg(feature = "interpreter")]
+#[test]
+fn mempool_dat_files_import_with_and_without_xor_key() {
+    let mut tx = fixture();
+    tx.validate(&ValidationOptions::default()).unwrap();
+    let mut txid = hex::decode(&tx.meta.txid_hex).unwrap();
+    txid.reverse();
+    let body = [
+        &1u64.to_le_bytes()[..],
+        &tx.serialize(true),
+        &1_700_000_000i64.to_le_bytes(),
+        &(-500i64).to_le_bytes(),
+        &[1],
+        &txid,
+        &1000i64.to_le_bytes(),
+        &[1],
+        &txid,
+    ]
+    .concat();
+    let v1 = [&1u64.to_le_bytes()[..], &body].concat();
+    let key = [0x5a, 0x01, 0xff, 0x00, 0x13, 0x37, 0x42, 0x80];
+    let header = [&2u64.to_le_bytes()[..], &[8], &key].concat();
+    let obfuscated: Vec<u8> = body
+        .iter()
+        .enumerate()
+        .map(|(index, byte)| byte ^ key[(header.len() + index) % key.len()])
+        .collect();
+    let v2 = [header, obfuscated].concat();
+
+    for (version, bytes) in [(1, v1), (2, v2)] {
+        let dat = parse_mempool_dat(&bytes).unwrap();
+        assert_eq!(dat.version, version);
+        assert_eq!(dat.transactions[0].time, 1_700_000_000);
+        assert_eq!(dat.transactions[0].fee_delta, -500);
+        assert_eq!(dat.fee_deltas[&tx.meta.txid_hex], 1000);
+        assert_eq!(dat.unbroadcast, vec![tx.meta.txid_hex.clone()]);
+
+        let (mut transactions, missing) =
+            mempool_dat_transactions(dat.clone(), &HashMap::new()).unwrap();
+        assert_eq!(missing, tx.vin.len());
+        assert!(transactions[0]
+            .validate(&ValidationOptions::default())
+            .is_err());
+        let confirmed = tx
+            .vin
+            .iter()
+            .map(|txin| {
+                let prevout = Prevout {
+                    value: txin.prevout.value,
+                    scriptpubkey: txin.prevout.scriptpubkey.clone(),
+                };
+                (format!("{}:{}", txin.txid, txin.vout), prevout)
+            })
+            .collect();
+        (transactions, _) = mempool_dat_transactions(dat, &confirmed).unwrap();
+        assert_eq!(
+            transactions[0].validate(&ValidationOptions::default()),
+            Ok(())
+        );
+        assert_eq!(transactions[0].meta.fee, tx.meta.fee);
+    }
+
+    assert_eq!(
+        parse_mempool_dat(&3u64.to_le_bytes()).unwrap_err(),
+        MempoolDatError::UnsupportedVersion(3)
+    );
+}
+
+// returns: key-value pair of a psbt map
+#[cfg(feature = "interpreter")]
+fn psbt_pair(key: &[u8], value: &[u8]) -> Vec<u8> {
+    [&[key.len() as u8], key, &[value.len() as u8], value].concat()
+}
+
+#[cfg(feature = "interpreter")]
+#[test]
+fn finalized_psbts_extract_into_valid_transactions() {
+    let mut tx = fixture();
+    tx.validate(&ValidationOptions::default()).unwrap();
+    let mut unsigned = tx.clone();
+    for txin in &mut unsigned.vin {
+        txin.scriptsig_bytes = Vec::new();
+    }
+    let unsigned_tx = unsigned.serialize(false);
+    // psbt with the scriptsig of the first input only, the rest lack their final fields
+    let psbt = |finalized: usize| -> Vec<u8> {
+        let mut psbt = [b"psbt\xff".as_slice(), &[1, 0, 0xfd]].concat();
+        psbt.extend((unsigned_tx.len() as u16).to_le_bytes());
+        psbt.extend(&unsigned_tx);
+        psbt.push(0);
+        for (index, txin) in tx.vin.iter().enumerate() {
+            let mut utxo = txin.prevout.value.to_le_bytes().to_vec();
+            utxo.push(txin.prevout.scriptpubkey_bytes.len() as u8);
+            utxo.extend(&txin.prevout.scriptpubkey_bytes);
+            psbt.extend(psbt_pair(&[0x01], &utxo));
+            if index < finalized {
+                psbt.extend(psbt_pair(&[0x07], &txin.scriptsig_bytes));
+            }
+            psbt.push(0);
+        }
+        psbt.extend(vec![0; tx.vout.len()]);
+        psbt
+    };
+
+    let binary = psbt(tx.vin.len());
+    let mut psbt_tx = parse_psbt_file(&binary).unwrap().remove(0);
+    assert_eq!(psbt_tx.serialize(true), tx.serialize(true));
+    assert_eq!(psbt_tx.meta.txid_hex, tx.meta.txid_hex);
+    assert_eq!(psbt_tx.validate(&ValidationOptions::default()), Ok(()));
+    assert_eq!(psbt_tx.meta.fee, tx.meta.fee);
+
+    // a package file with one base64 and one hex psbt per line
+    let text = format!("{}\n{}\n", STANDARD.encode(&binary), hex::encode(&binary));
+    assert_eq!(parse_psbt_file(text.as_bytes()).unwrap().len(), 2);
+
+    if tx.vin.len() > 1 {
+        assert_eq!(
+            parse_psbt_file(&psbt(1)).unwrap_err(),
+            PsbtError::NotFinalized { index: 1 }
+        );
+    }
+    assert!(matches!(
+        parse_psbt_file(b"cHNidP8="),
+        Err(PsbtError::Raw(_))
+    ));
+}
+
+#[cfg(feature = "interpreter")]
+#[test]
+fn transactions_parse_lazily_from_the_iterator() {
+    let directory = "test_scripts/testfiles/p2pkh";
+    let mut valid = 0;
+    for tx in parse_transactions_iter(directory) {
+        valid += tx.unwrap().validate(&ValidationOptions::default()).is_ok() as usize;
+    }
+    assert_eq!(valid, parse_transactions_from_dir(directory).unwrap().len());
+
+    let mut missing = parse_transactions_iter("test_scripts/testfiles/missing");
+    assert!(matches!(missing.next(), Some(Err(ParseError::Io { .. }))));
+    assert!(missing.next().is_none());
+}
+
+#[test]
+fn unparsable_files_are_skipped_and_collected() {
+    let directory = temp_directory("skipped_files");
+    let path = fixture().meta.json_path.unwrap();
+    fs::copy(&path, directory.join("valid.json")).unwrap();
+    fs::write(directory.join("truncated.json"), "{\"version\": 1,").unwrap();
+    fs::write(directory.join("empty.json"), "{}").unwrap();
+    let directory = directory.to_str().unwrap();
+
+    assert!(parse_transactions_from_dir(directory).is_err());
+    let snapshot = parse_transactions_from_dir_skipping(directory, &DirFilter::default()).unwrap();
+    assert_eq!(snapshot.transactions.len(), 1);
+    assert_eq!(snapshot.skipped.len(), 2);
+    assert!(snapshot.skipped.iter().all(|err| matches!(
+        err,
+        ParseError::InvalidJson { path, .. } if !path.ends_with("valid.json")
+    )));
+    assert!(matches!(
+        parse_transactions_from_dir_skipping(
+            "test_scripts/testfiles/missing",
+            &DirFilter::default()
+        ),
+        Err(ParseError::Io { .. })
+    ));
+}
+
+#[test]
+fn directory_filters_select_files_recursively() {
+    let filter = DirFilter {
+        recursive: true,
+        include: vec!["*.json".to_string()],
+        exclude: vec!["rejected/*".to_string(), "**/tmp_?.json".to_string()],
+    };
+    assert!(filter.selects("a.json"));
+    assert!(filter.selects("2024/05/a.json"));
+    assert!(!filter.selects("a.txt"));
+    assert!(!filter.selects("rejected/a.json"));
+    assert!(filter.selects("rejected/old/a.json"));
+    assert!(!filter.selects("tmp_1.json"));
+    assert!(!filter.selects("2024/tmp_2.json"));
+    assert!(filter.selects("tmp_10.json"));
+
+    let directory = temp_directory("recursive_files");
+    fs::create_dir_all(directory.join("sub/rejected")).unwrap();
+    let path = fixture().meta.json_path.unwrap();
+    fs::copy(&path, directory.join("top.json")).unwrap();
+    fs::copy(&path, directory.join("sub/nested.json")).unwrap();
+    fs::copy(&path, directory.join("sub/rejected/invalid.json")).unwrap();
+    let directory = directory.to_str().unwrap();
+
+    let parsed = |filter: &DirFilter| {
+        parse_transactions_from_dir_skipping(directory, filter)
+            .unwrap()
+            .transactions
+            .len()
+    };
+    assert_eq!(parsed(&DirFilter::default()), 1);
+    assert_eq!(parsed(&filter), 3);
+    let filter = DirFilter {
+        exclude: vec!["**/rejected/**".to_string()],
+        ..filter
+    };
+    assert_eq!(parsed(&filter), 2);
+}
+
+#[cfg(feature = "interpreter")]
+#[test]
+fn single_file_mempools_parse_arrays_and_txid_objects() {
+    let transactions = parse_transactions_from_dir("test_scripts/testfiles/p2pkh").unwrap();
+    let jsons: Vec<serde_json::Value> = transactions
+        .iter()
+        .map(|tx| {
+            let path = tx.meta.json_path.as_ref().unwrap();
+            serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap()
+        })
+        .collect();
+    let mut txids = Vec::new();
+    for mut tx in transactions {
+        tx.validate(&ValidationOptions::default()).unwrap();
+        txids.push(tx.meta.txid_hex);
+    }
+    let directory = temp_directory("single_file");
+
+    // array without txid fields and one entry that isn't a transaction
+    let mut array = jsons.clone();
+    array.push(serde_json::json!({ "version": 1 }));
+    let array_path = directory.join("mempool_array.json");
+    fs::write(&array_path, serde_json::Value::Array(array).to_string()).unwrap();
+    let snapshot = parse_transactions_from_file(array_path.to_str().unwrap()).unwrap();
+    assert_eq!(snapshot.transactions.len(), jsons.len());
+    assert!(matches!(
+        &snapshot.skipped[..],
+        [ParseError::InvalidJson { path, .. }] if path.ends_with(&format!("[{}]", jsons.len()))
+    ));
+    for (mut tx, txid) in snapshot.transactions.into_iter().zip(&txids) {
+        assert_eq!(tx.validate(&ValidationOptions::default()), Ok(()));
+        assert_eq!(&tx.meta.txid_hex, txid);
+    }
+
+    // object keyed by txid, the last key names another transaction
+    let mut object = serde_json::Map::new();
+    for (json, txid) in jsons.iter().zip(&txids) {
+        object.insert(txid.clone(), json.clone());
+    }
+    object.insert("00".repeat(32), jsons[0].clone());
+    let object_path = directory.join("mempool_object.json");
+    fs::write(&object_path, serde_json::Value::Object(object).to_string()).unwrap();
+    let snapshot = parse_transactions_from_file(object_path.to_str().unwrap()).unwrap();
+    let mismatches = snapshot
+        .transactions
+        .into_iter()
+        .filter(|tx| tx.clone().validate(&ValidationOptions::default()).is_err())
+        .count();
+    assert_eq!(mismatches, 1);
+}
+
+#[cfg(feature = "interpreter")]
+#[test]
+fn esplora_transactions_are_fetched_with_missing_prevouts() {
+    let mut tx = fixture();
+    tx.validate(&ValidationOptions::default()).unwrap();
+    let path = tx.meta.json_path.clone().unwrap();
+    let txid = tx.meta.txid_hex.clone();
+    let mut json: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+    // the parent serves the prevout the transaction json lacks
+    let vout = tx.vin[0].vout as usize;
+    let mut parent_vout = vec![serde_json::Value::Null; vout + 1];
+    parent_vout[vout] = json["vin"][0]["prevout"].take();
+    let parent = serde_json::json!({ "vout": parent_vout }).to_string();
+    // a sibling spending another output of the same parent, one past its last output
+    let sibling = "11".repeat(32);
+    let mut sibling_json = json.clone();
+    sibling_json["vin"][0]["vout"] = serde_json::json!(vout + 1);
+    let parent_path = format!("/api/tx/{}", tx.vin[0].txid);
+    let responses = HashMap::from([
+        (
+            "/api/mempool/txids".to_string(),
+            format!(r#"["{}", "{}"]"#, txid, "00".repeat(32)),
+        ),
+        (format!("/api/tx/{}", txid), json.to_string()),
+        (format!("/api/tx/{}", sibling), sibling_json.to_string()),
+        (parent_path.clone(), parent),
+    ]);
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let (requested, requests) = mpsc::channel();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut request = String::new();
+            BufReader::new(&stream).read_line(&mut request).unwrap();
+            let path = request.split_whitespace().nth(1).unwrap_or_default();
+            let response = match responses.get(path) {
+                Some(body) => format!("HTTP/1.1 200 OK\r\n\r\n{}", body),
+                None => "HTTP/1.1 404 Not Found\r\n\r\n".to_string(),
+            };
+            requested.send(path.to_string()).unwrap();
+            stream.write_all(response.as_bytes()).unwrap();
+        }
+    });
+
+    let mut client = EsploraClient::new(&format!("http://{}/api/", address)).unwrap();
+    assert_eq!(client.prefix, "/api");
+    let mut transactions = client.fetch_mempool(None).unwrap();
+    assert_eq!(transactions.len(), 1);
+    assert_eq!(transactions[0].vin[0].prevout, tx.vin[0].prevout);
+    assert_eq!(
+        transactions[0].validate(&ValidationOptions::default()),
+        Ok(())
+    );
+    assert!(client.fetch_mempool(Some(0)).unwrap().is_empty());
+
+    // the outputs of the parent are cached, the sibling's vout is out of range of them
+    assert_eq!(
+        client.transaction(&sibling).unwrap_err(),
+        format!(
+            "{}: vout {} out of range, parent {} has {} outputs",
+            sibling,
+            vout + 1,
+            tx.vin[0].txid,
+            vout + 1
+        )
+    );
+    let parent_requests = requests
+        .try_iter()
+        .filter(|path| *path == parent_path)
+        .count();
+    assert_eq!(parent_requests, 1);
+    assert!(EsploraClient::new("https://mempool.space/api").is_err());
+}
+
+#[test]
+fn unresponsive_esplora_servers_time_out() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    // accepts the connections but never responds
+    std::thread::spawn(move || {
+        let mut connections = Vec::new();
+        for stream in listener.incoming() {
+            connections.push(stream);
+        }
+    });
+
+    let mut client = EsploraClient::new(&format!("http://{}", address)).unwrap();
+    client.timeout = Duration::from_millis(100);
+    let started = Instant::now();
+    let err = client.fetch_mempool(None).unwrap_err();
+    assert!(err.starts_with("reading /mempool/txids"), "{}", err);
+    assert!(started.elapsed() < Duration::from_secs(5));
+}
+
+#[cfg(feature = "interpreter")]
+#[test]
+fn compressed_files_and_tarballs_are_decompressed_while_loading() {
+    let mut txids: Vec<String> = parse_transactions_from_dir("test_scripts/testfiles/p2pkh")
+        .unwrap()
+        .into_iter()
+        .map(|tx| tx.meta.txid_hex)
+        .collect();
+    txids.sort();
+
+    // a gzip and a zstd compressed file and a zstd compressed tarball of the third transaction
+    let directory = parse_transactions_from_dir_skipping(
+        "test_scripts/testfiles/compressed/mempool",
+        &DirFilter::default(),
+    )
+    .unwrap();
+    assert!(directory.skipped.is_empty());
+    assert!(directory.transactions.iter().any(|tx| tx
+        .meta
+        .json_path
+        .as_ref()
+        .unwrap()
+        .contains("p2pkh.tar.zst/p2pkh/")));
+    let tarball =
+        parse_transactions_from_file("test_scripts/testfiles/compressed/mempool.tar.gz").unwrap();
+    assert!(tarball.skipped.is_empty());
+    for mut snapshot in [directory, tarball] {
+        let mut parsed: Vec<String> = snapshot
+            .transactions
+            .iter()
+            .map(|tx| tx.meta.txid_hex.clone())
+            .collect();
+        parsed.sort();
+        assert_eq!(parsed, txids);
+        for tx in &mut snapshot.transactions {
+            assert_eq!(tx.validate(&ValidationOptions::default()), Ok(()));
+        }
+    }
+
+    let directory = temp_directory("invalid_compressed");
+    let tarball = fs::read("test_scripts/testfiles/compressed/mempool.tar.gz").unwrap();
+    fs::write(directory.join("invalid.json.gz"), "not gzip").unwrap();
+    fs::write(
+        directory.join("truncated.tar.gz"),
+        &tarball[..tarball.len() / 2],
+    )
+    .unwrap();
+    for file in ["invalid.json.gz", "truncated.tar.gz"] {
+        let path = directory.join(file);
+        assert!(matches!(
+            parse_transactions_from_file(path.to_str().unwrap()),
+            Err(ParseError::Io { .. })
+        ));
+    }
+    fs::remove_dir_all(&directory).unwrap();
+}
+
+const GZIP_FIXTURE: &str = "test_scripts/testfiles/compressed/mempool/00d12b523d8b7ad90e2269767478764c243625539dc59bcd457d14ca1aa4e38c.json.gz";
+const ZSTD_FIXTURE: &str = "test_scripts/testfiles/compressed/mempool/3d9a75a2639ff512bbb012f56577f06b86db2caf33f729a2600db63f70d92aef.json.zst";
+
+// returns: the decompressed bytes or the error message of the decoder
+fn decompress(compression: Compression, compressed: &[u8]) -> Result<Vec<u8>, String> {
+    let mut content = Vec::new();
+    match compression.decoder(compressed).read_to_end(&mut content) {
+        Ok(_) => Ok(content),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+#[test]
+fn truncated_and_corrupted_compressed_files_fail_to_decode() {
+    for (compression, path) in [
+        (Compression::Gzip, GZIP_FIXTURE),
+        (Compression::Zstd, ZSTD_FIXTURE),
+        (
+            Compression::Zstd,
+            "test_scripts/testfiles/compressed/mempool/p2pkh.tar.zst",
+        ),
+    ] {
+        let compressed = fs::read(path).unwrap();
+        let content = decompress(compression, &compressed).unwrap();
+        for length in 1..compressed.len() {
+            assert!(
+                decompress(compression, &compressed[..length]).is_err(),
+                "{} truncated to {} bytes",
+                path,
+                length
+            );
+        }
+        // trailing garbage is read as the start of another member or frame
+        let mut appended = compressed.clone();
+        appended.extend_from_slice(b"garbage");
+        assert!(decompress(compression, &appended).is_err());
+        // concatenated members and frames decode to the concatenated content
+        let concatenated = [compressed.as_slice(), &compressed].concat();
+        assert_eq!(
+            decompress(compression, &concatenated).unwrap(),
+            [content.as_slice(), &content].concat()
+        );
+    }
+
+    // the trailer of a gzip member is its CRC32 and size, zstd frames end with the xxhash64
+    let gzip = fs::read(GZIP_FIXTURE).unwrap();
+    for (from_end, error) in [(8, "gzip: crc error"), (4, "gzip: length error")] {
+        let mut corrupted = gzip.clone();
+        corrupted[gzip.len() - from_end] ^= 1;
+        assert_eq!(decompress(Compression::Gzip, &corrupted), Err(error.into()));
+    }
+    let mut zstd = fs::read(ZSTD_FIXTURE).unwrap();
+    *zstd.last_mut().unwrap() ^= 1;
+    assert_eq!(
+        decompress(Compression::Zstd, &zstd),
+        Err("zstd: content checksum mismatch".into())
+    );
+    // fixed huffman block with a match before the first literal
+    let gzip = hex::decode("1f8b08000000000000ff0302000000000000000000").unwrap();
+    assert_eq!(
+        decompress(Compression::Gzip, &gzip),
+        Err("gzip: distance too far back".into())
+    );
+}
+
+#[test]
+fn zstd_windows_and_blocks_are_capped() {
+    // frame header: magic, descriptor and window descriptor
+    let frame = |descriptor: &str| hex::decode(format!("28b52ffd{}", descriptor)).unwrap();
+    // raw last block of 3 bytes
+    let block = hex::decode("190000616263").unwrap();
+    let window_too_large = Err("zstd: window size above 128 MiB".to_string());
+    for (descriptor, expected) in [
+        ("0088", Ok(b"abc".to_vec())),            // 128 MiB window
+        ("0089", window_too_large.clone()),       // 144 MiB window
+        ("00ff", window_too_large.clone()),       // 3.75 TiB window
+        ("e0ffffffffffffffff", window_too_large), // single segment of 2^64 - 1 bytes
+        // single segment of 128 MiB, the window isn't allocated before the blocks are decoded
+        (
+            "e00000000800000000",
+            Err("zstd: frame content size mismatch".to_string()),
+        ),
+    ] {
+        let compressed = [frame(descriptor), block.clone()].concat();
+        assert_eq!(
+            decompress(Compression::Zstd, &compressed),
+            expected,
+            "{}",
+            descriptor
+        );
+    }
+
+    // a raw block of one byte, then 3 sequences copying 65539 bytes each at offset 1, they would
+    // expand the 13 byte block to 192 KiB
+    let compressed = [
+        frame("0000"),
+        hex::decode("08000061").unwrap(),
+        hex::decode("6d000000035400023400000000000040").unwrap(),
+    ]
+    .concat();
+    assert_eq!(
+        decompress(Compression::Zstd, &compressed),
+        Err("zstd: decompressed block above 128 KiB".into())
+    );
+}
+
+#[test]
+fn randomly_corrupted_compressed_files_fail_without_panicking() {
+    let mut rng = StdRng::seed_from_u64(2571);
+    for (compression, path) in [
+        (Compression::Gzip, GZIP_FIXTURE),
+        (Compression::Zstd, ZSTD_FIXTURE),
+    ] {
+        let compressed = fs::read(path).unwrap();
+        let content = decompress(compression, &compressed).unwrap();
+        for _ in 0..500 {
+            let mut corrupted = compressed.clone();
+            for _ in 0..rng.gen_range(1..=4) {
+                let index = rng.gen_range(0..corrupted.len());
+                corrupted[index] = rng.gen();
+            }
+            // corrupted data that still decodes can't exceed the content by much
+            if let Ok(decoded) = decompress(compression, &corrupted) {
+                assert!(decoded.len() < 2 * content.len() + 1024);
+            }
+        }
+        // random data behind the magic number
+        for length in [16, 256, 4096] {
+            let mut random = compressed[..4].to_vec();
+            random.extend((0..length).map(|_| rng.gen::<u8>()));
+            let _ = decompress(compression, &random);
+        }
+    }
+}
+
+#[test]
+fn validated_transactions_round_trip_through_the_cache() {
+    let mut transactions =
+        parse_transactions_from_dir("test_scripts/testfiles/wrapped-segwit").unwrap();
+    let mut invalid = HashMap::new();
+    for tx in &mut transactions {
+        if let Err(err) = tx.validate(&ValidationOptions::default()) {
+            invalid.insert(tx.meta.txid_hex.clone(), err.to_string());
+        }
+    }
+    invalid.insert("00".repeat(32), "Strict parsing: fee: mismatch".to_string());
+    let snapshot = CachedSnapshot {
+        transactions,
+        invalid,
+    };
+    let bytes = serialize_cache("key", &snapshot);
+
+    assert!(matches!(deserialize_cache(&bytes, "other key"), Ok(None)));
+    assert_eq!(
+        deserialize_cache(&bytes[1..], "key").unwrap_err(),
+        CacheError::MissingMagic
+    );
+    assert!(matches!(
+        deserialize_cache(&bytes[..bytes.len() - 1], "key"),
+        Err(CacheError::Corrupt(_))
+    ));
+
+    let cached = deserialize_cache(&bytes, "key").unwrap().unwrap();
+    assert_eq!(cached.invalid, snapshot.invalid);
+    assert_eq!(cached.transactions.len(), snapshot.transactions.len());
+    for (cached_tx, tx) in cached.transactions.iter().zip(&snapshot.transactions) {
+        assert_eq!(cached_tx.vin, tx.vin);
+        assert_eq!(cached_tx.serialize(true), tx.serialize(true));
+        assert_eq!(cached_tx.meta.json_path, tx.meta.json_path);
+        assert_eq!(cached_tx.meta.wtxid_hex, tx.meta.wtxid_hex);
+        assert_eq!(
+            (
+                cached_tx.meta.weight,
+                cached_tx.meta.fee,
+                cached_tx.meta.sigops_cost
+            ),
+            (tx.meta.weight, tx.meta.fee, tx.meta.sigops_cost)
+        );
+        assert_eq!(cached_tx.meta.memory_size, tx.meta.memory_size);
+    }
+    // cached transactions validate like freshly parsed ones
+    for mut tx in cached.transactions {
+        let verdict = tx.validate(&ValidationOptions::default()).err();
+        assert_eq!(
+            verdict.map(|err| err.to_string()),
+            snapshot.invalid.get(&tx.meta.txid_hex).cloned()
+        );
+    }
+}
+
+#[test]
+fn file_states_change_when_a_file_is_edited_in_place() {
+    let directory = temp_directory("file_states");
+    let path = directory.join("tx.json");
+    fs::write(&path, "{}").unwrap();
+    let filter = DirFilter::default();
+    let before = file_states(directory.to_str().unwrap(), &filter).unwrap();
+    assert_eq!(before.len(), 1);
+    // a single file is listed by itself
+    assert_eq!(
+        file_states(path.to_str().unwrap(), &filter).unwrap(),
+        before
+    );
+
+    fs::write(&path, "{ }").unwrap();
+    let after = file_states(directory.to_str().unwrap(), &filter).unwrap();
+    assert_eq!(after[0].0, before[0].0);
+    assert_ne!(after[0].1, before[0].1);
+    fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn watched_directories_report_added_changed_and_removed_files() {
+    let directory = temp_directory("watch");
+    let sources: Vec<_> = fs::read_dir("test_scripts/testfiles/p2pkh")
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .take(3)
+        .collect();
+    let copy = |source: &Path| {
+        let target = directory.join(source.file_name().unwrap());
+        fs::copy(source, &target).unwrap();
+        target.display().to_string()
+    };
+    let first = copy(&sources[0]);
+    let second = copy(&sources[1]);
+    let mut watcher = MempoolWatcher::new(directory.to_str().unwrap(), DirFilter::default());
+
+    let changes = watcher.poll().unwrap();
+    assert_eq!(changes.added.len(), 2);
+    assert!(changes.removed.is_empty() && changes.skipped.is_empty());
+    assert!(watcher.poll().unwrap().is_empty());
+
+    // added, changed (content of another size) and removed files
+    let third = copy(&sources[2]);
+    let mut content = fs::read_to_string(&second).unwrap();
+    content.push('\n');
+    fs::write(&second, content).unwrap();
+    fs::remove_file(&first).unwrap();
+    fs::write(directory.join("broken.json"), "{").unwrap();
+    let changes = watcher.poll().unwrap();
+    let mut added: Vec<String> = changes
+        .added
+        .iter()
+        .map(|(path, tx)| {
+            assert_eq!(tx.meta.json_path.as_ref(), Some(path));
+            path.clone()
+        })
+        .collect();
+    added.sort();
+    let mut expected = vec![second.clone(), third];
+    expected.sort();
+    assert_eq!(added, expected);
+    let mut removed = changes.removed.clone();
+    removed.sort();
+    let mut expected = vec![first, second];
+    expected.sort();
+    assert_eq!(removed, expected);
+    assert!(matches!(
+        &changes.skipped[..],
+        [ParseError::InvalidJson { .. }]
+    ));
+
+    // the unparsable file is only reported while it changes
+    assert!(watcher.poll().unwrap().is_empty());
+    fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn watched_tarballs_and_single_file_mempools_report_all_their_transactions() {
+    let directory = temp_directory("watch_files");
+    let tarball = directory.join("mempool.tar.gz");
+    fs::copy("test_scripts/testfiles/compressed/mempool.tar.gz", &tarball).unwrap();
+    let transactions: Vec<serde_json::Value> = fs::read_dir("test_scripts/testfiles/p2pkh")
+        .unwrap()
+        .map(|entry| serde_json::from_slice(&fs::read(entry.unwrap().path()).unwrap()).unwrap())
+        .collect();
+    let single_file = directory.join("mempool.json");
+    fs::write(&single_file, serde_json::to_vec(&transactions).unwrap()).unwrap();
+    let mut watcher = MempoolWatcher::new(directory.to_str().unwrap(), DirFilter::default());
+
+    let changes = watcher.poll().unwrap();
+    assert!(changes.skipped.is_empty());
+    for file in [&tarball, &single_file] {
+        let path = file.display().to_string();
+        let count = changes
+            .added
+            .iter()
+            .filter(|(file, _)| *file == path)
+            .count();
+        assert_eq!(count, transactions.len());
+    }
+
+    fs::remove_file(&tarball).unwrap();
+    let changes = watcher.poll().unwrap();
+    assert_eq!(changes.removed, vec![tarball.display().to_string()]);
+    fs::remove_dir_all(&directory).unwrap();
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn watchers_wake_up_when_a_file_is_written() {
+    let directory = temp_directory("watch_wait");
+    let watcher = MempoolWatcher::new(directory.to_str().unwrap(), DirFilter::default());
+    let path = directory.join("tx.json");
+    let writer = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(100));
+        fs::write(path, "{}").unwrap();
+    });
+
+    let started = Instant::now();
+    watcher.wait(Duration::from_secs(30));
+    assert!(started.elapsed() < Duration::from_secs(10));
+    writer.join().unwrap();
+    fs::remove_dir_all(&directory).unwrap();
+}
@@ -1,8 +1,14 @@
 // Definition of data structures to hold a bitcoin transaction and relevant metadata
 
-use crate::validation::utils::{get_outpoint, varint};
+use crate::amount::deserialize_amount;
+use crate::feerate::FeeRate;
+use crate::validation::utils::{double_hash, get_outpoint, hash_sha256, varint};
+use crate::validation::validate_parsing::assemble_txid_preimage;
+use crate::validation::weight_calculation::is_segwit;
 use serde::Deserialize;
 use serde_with::{serde_as, NoneAsEmptyString};
+use std::mem::size_of;
+use std::sync::OnceLock;
 
 #[serde_as]
 #[derive(Deserialize, Debug, Clone)]
@@ -12,7 +18,10 @@ pub struct TxOut {
     pub scriptpubkey_asm: String,
     pub scriptpubkey_type: String,
     pub scriptpubkey_address: Option<String>,
+    #[serde(deserialize_with = "deserialize_amount")]
     pub value: u64,
+    #[serde(skip_deserializing)]
+    pub scriptpubkey_bytes: Vec<u8>, // decoded scriptpubkey, set by decode_hex_fields()
 }
 
 #[derive(Deserialize, Debug, PartialEq, Clone)]
@@ -21,7 +30,10 @@ pub struct Script {
     pub scriptpubkey_asm: String,
     pub scriptpubkey_type: String,
     pub scriptpubkey_address: Option<String>,
+    #[serde(deserialize_with = "deserialize_amount")]
     pub value: u64,
+    #[serde(skip_deserializing)]
+    pub scriptpubkey_bytes: Vec<u8>, // decoded scriptpubkey, set by decode_hex_fields()
 }
 
 #[serde_as]
@@ -41,13 +53,42 @@ pub struct TxIn {
     pub inner_redeemscript_asm: Option<String>,
     pub is_coinbase: bool,
     pub sequence: u32,
+    // decoded hex fields used by the serialization and validation. The hex fields are kept as
+    // the json of the transaction (strict checks, reports, repl), both sides are only set
+    // together by decode_hex_fields() (json sources) or encode_hex_fields() (binary sources)
+    #[serde(skip_deserializing)]
+    pub outpoint: Vec<u8>, // reversed txid bytes + vout index as serialized
+    #[serde(skip_deserializing)]
+    pub scriptsig_bytes: Vec<u8>,
+    #[serde(skip_deserializing)]
+    pub witness_bytes: Option<Vec<Vec<u8>>>,
 }
 
 #[derive(Default, Debug, Clone)]
 pub struct Packet {
     pub packet_weight: u64,
     pub packet_fee_sat: u64,
-    pub packet_feerate_weight: u64, // sat/weight_unit
+    pub packet_feerate: FeeRate,
+}
+
+// BIP143 double SHA256 hashes of all outpoints, sequences and outputs, the same for the
+// signatures of all inputs that commit to them (no ANYONECANPAY, NONE or SINGLE)
+#[derive(Debug, Clone)]
+pub struct SegwitHashes {
+    pub hash_prevouts: [u8; 32],
+    pub hash_sequence: [u8; 32],
+    pub hash_outputs: [u8; 32],
+}
+
+// BIP341 single SHA256 hashes of the transaction data all taproot signatures without
+// ANYONECANPAY commit to (sha_outputs also depends on the sighash type not being NONE/SINGLE)
+#[derive(Debug, Clone)]
+pub struct TaprootHashes {
+    pub sha_prevouts: [u8; 32],
+    pub sha_amounts: [u8; 32],
+    pub sha_scriptpubkeys: [u8; 32],
+    pub sha_sequences: [u8; 32],
+    pub sha_outputs: [u8; 32],
 }
 
 #[derive(Default, Debug, Clone)]
@@ -57,8 +98,24 @@ pub struct TxMetadata {
     pub wtxid_hex: String,
     pub packet_data: Packet,
     pub weight: u64,
+    pub sigops_cost: u64, // BIP141 sigops cost, set by the validation
     pub fee: u64,
     pub parents: Option<Vec<String>>,
+    pub memory_size: usize, // approximate bytes in memory, set during parsing
+    pub base_size: usize,   // serialized bytes without witness, set with the txid
+    pub total_size: usize,  // serialized bytes with witness, set with the wtxid
+    pub segwit_hashes: OnceLock<SegwitHashes>, // set by the first segwit_hashes() call
+    pub taproot_hashes: OnceLock<TaprootHashes>, // set by the first taproot_hashes() call
+}
+
+// returns: hex of the bytes, None if empty (like the json fields)
+pub fn optional_hex(bytes: &[u8]) -> Option<String> {
+    (!bytes.is_empty()).then(|| hex::encode(bytes))
+}
+
+// returns: heap bytes of an optional String
+fn optional_string_size(string: &Option<String>) -> usize {
+    string.as_ref().map_or(0, |string| string.capacity())
 }
 
 // main Transaction struct, containing all other transaction (meta-)data
@@ -73,6 +130,103 @@ pub struct Transaction {
 }
 
 impl Transaction {
+    // decodes the hex scripts, witnesses and outpoint txids once into the byte fields
+    // used by the serialization
+    // returns: Err if a hex field is invalid
+    pub fn decode_hex_fields(&mut self) -> Result<(), hex::FromHexError> {
+        for txin in &mut self.vin {
+            let mut outpoint = hex::decode(&txin.txid)?;
+            outpoint.reverse();
+            outpoint.extend_from_slice(&txin.vout.to_le_bytes());
+            txin.outpoint = outpoint;
+            txin.scriptsig_bytes = match &txin.scriptsig {
+                Some(scriptsig) => hex::decode(scriptsig)?,
+                None => Vec::new(),
+            };
+            txin.witness_bytes = match &txin.witness {
+                Some(witness) => Some(witness.iter().map(hex::decode).collect::<Result<_, _>>()?),
+                None => None,
+            };
+            txin.prevout.scriptpubkey_bytes = hex::decode(&txin.prevout.scriptpubkey)?;
+        }
+        for txout in &mut self.vout {
+            txout.scriptpubkey_bytes = match &txout.scriptpubkey {
+                Some(scriptpubkey) => hex::decode(scriptpubkey)?,
+                None => Vec::new(),
+            };
+        }
+        Ok(())
+    }
+
+    // encodes the byte fields into the hex scripts and witnesses and sets the txid and vout of
+    // the inputs from their outpoint, the inverse of decode_hex_fields() for transactions read
+    // from binary sources (raw transactions, psbt, cache)
+    pub fn encode_hex_fields(&mut self) {
+        for txin in &mut self.vin {
+            // reversed txid bytes + vout index
+            if txin.outpoint.len() == 36 {
+                let mut txid = txin.outpoint[..32].to_vec();
+                txid.reverse();
+                txin.txid = hex::encode(txid);
+                txin.vout = u32::from_le_bytes(txin.outpoint[32..].try_into().unwrap());
+            }
+            txin.scriptsig = optional_hex(&txin.scriptsig_bytes);
+            txin.witness = txin
+                .witness_bytes
+                .as_ref()
+                .map(|items| items.iter().map(hex::encode).collect());
+            txin.prevout.scriptpubkey = hex::encode(&txin.prevout.scriptpubkey_bytes);
+        }
+        for txout in &mut self.vout {
+            txout.scriptpubkey = optional_hex(&txout.scriptpubkey_bytes);
+        }
+    }
+
+    // serializes the transaction in the consensus encoding, with witness the BIP144 marker, flag
+    // and witnesses are included if an input has a witness (the txid serialization otherwise)
+    // returns: raw transaction bytes
+    pub fn serialize(&self, witness: bool) -> Vec<u8> {
+        assemble_txid_preimage(self, witness && is_segwit(self))
+    }
+
+    // approximates the memory used by the transaction: the structs themselves and the heap
+    // allocations of all strings, scripts, witnesses and metadata
+    // returns: size in bytes
+    pub fn memory_size(&self) -> usize {
+        let mut size = size_of::<Transaction>();
+
+        size += optional_string_size(&self.meta.json_path);
+        size += self.meta.txid_hex.capacity() + self.meta.wtxid_hex.capacity();
+        for parent in self.meta.parents.iter().flatten() {
+            size += size_of::<String>() + parent.capacity();
+        }
+        for txin in &self.vin {
+            size += size_of::<TxIn>() + txin.txid.capacity();
+            size +=
+                optional_string_size(&txin.scriptsig) + optional_string_size(&txin.scriptsig_asm);
+            size += optional_string_size(&txin.inner_witnessscript_asm)
+                + optional_string_size(&txin.inner_redeemscript_asm);
+            size += txin.prevout.scriptpubkey.capacity() + txin.prevout.scriptpubkey_asm.capacity();
+            size += txin.prevout.scriptpubkey_type.capacity()
+                + optional_string_size(&txin.prevout.scriptpubkey_address);
+            size += txin.prevout.scriptpubkey_bytes.capacity();
+            for item in txin.witness.iter().flatten() {
+                size += size_of::<String>() + item.capacity();
+            }
+            for item in txin.witness_bytes.iter().flatten() {
+                size += size_of::<Vec<u8>>() + item.capacity();
+            }
+            size += txin.outpoint.capacity() + txin.scriptsig_bytes.capacity();
+        }
+        for txout in &self.vout {
+            size += size_of::<TxOut>() + optional_string_size(&txout.scriptpubkey);
+            size += txout.scriptpubkey_asm.capacity() + txout.scriptpubkey_type.capacity();
+            size += optional_string_size(&txout.scriptpubkey_address);
+            size += txout.scriptpubkey_bytes.capacity();
+        }
+        size
+    }
+
     // return Vec<u8> of all sequences in little endian byte format
     pub fn serialize_all_sequences(&self) -> Vec<u8> {
         let mut all_sequences = Vec::new();
@@ -96,21 +250,65 @@ impl Transaction {
         let mut all_outputs = Vec::new();
         for output in &self.vout {
             all_outputs.extend(&output.value.to_le_bytes());
-            if let Some(scriptpubkey) = &output.scriptpubkey {
-                all_outputs.extend(varint(hex::decode(scriptpubkey).unwrap().len() as u128));
-                all_outputs.extend(hex::decode(scriptpubkey).unwrap());
-            } else {
+            if output.scriptpubkey.is_none() {
                 panic!("No scriptpubkey in output!");
             }
+            all_outputs.extend(varint(output.scriptpubkey_bytes.len() as u128));
+            all_outputs.extend(&output.scriptpubkey_bytes);
         }
         all_outputs
     }
+
+    // computes the BIP143 hashes once for all inputs, the inputs and outputs mustn't change
+    // afterwards
+    // returns: SegwitHashes of the transaction
+    pub fn segwit_hashes(&self) -> &SegwitHashes {
+        let hash = |preimage: Vec<u8>| -> [u8; 32] {
+            double_hash(&preimage)
+                .try_into()
+                .expect("Double hash is not 32 byte!")
+        };
+        self.meta.segwit_hashes.get_or_init(|| SegwitHashes {
+            hash_prevouts: hash(self.serialize_all_outpoints()),
+            hash_sequence: hash(self.serialize_all_sequences()),
+            hash_outputs: hash(self.serialize_all_outputs()),
+        })
+    }
+
+    // computes the BIP341 hashes once for all inputs from the spent outputs of the inputs,
+    // the inputs and outputs mustn't change afterwards
+    // returns: TaprootHashes of the transaction
+    pub fn taproot_hashes(&self) -> &TaprootHashes {
+        let hash = |preimage: Vec<u8>| -> [u8; 32] {
+            hash_sha256(&preimage)
+                .try_into()
+                .expect("SHA256 is not 32 byte!")
+        };
+        self.meta.taproot_hashes.get_or_init(|| {
+            let mut amounts = Vec::new();
+            let mut scriptpubkeys = Vec::new();
+            for txin in &self.vin {
+                amounts.extend(txin.prevout.value.to_le_bytes());
+                scriptpubkeys.extend(varint(txin.prevout.scriptpubkey_bytes.len() as u128));
+                scriptpubkeys.extend(&txin.prevout.scriptpubkey_bytes);
+            }
+            TaprootHashes {
+                sha_prevouts: hash(self.serialize_all_outpoints()),
+                sha_amounts: hash(amounts),
+                sha_scriptpubkeys: hash(scriptpubkeys),
+                sha_sequences: hash(self.serialize_all_sequences()),
+                sha_outputs: hash(self.serialize_all_outputs()),
+            }
+        })
+    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum InputType {
     P2TR,
+    P2PK,
     P2PKH,
+    MULTISIG, // bare multisig scriptpubkey
     P2SH,
     P2WPKH,
     P2WSH,
@@ -133,6 +331,8 @@ impl InputType {
             "v0_p2wsh" => InputType::P2WSH,
             "p2sh" => InputType::P2SH,
             "p2pkh" => InputType::P2PKH,
+            "p2pk" => InputType::P2PK,
+            "multisig" => InputType::MULTISIG,
             _ => InputType::UNKNOWN(type_string.to_string()),
         };
     }
@@ -0,0 +1,159 @@
+// Watcher of a mempool directory for the live template mode. No file notification crate
+// (notify) is vendored: on linux an inotify instance (libc FFI) wakes the watcher as soon as a
+// file of the directory is written, moved or deleted, elsewhere and for the subdirectories of
+// a recursive filter the directory is only listed every interval. Each poll lists the selected
+// files and compares them with the previous listing by modification time and size. Only added
+// and changed files are parsed, removed and changed files are reported by their path.
+// Tarballs and single file mempools are parsed into all their transactions, which are
+// reported together with the path of their file.
+
+use super::dir_filter::DirFilter;
+use super::transaction_structs::Transaction;
+use super::{file_states, parse_watched_file, FileState, ParseError};
+use std::collections::HashMap;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// quiet time after an event until the directory is listed, a burst of writes (e.g. a copied
+// snapshot) is picked up by one poll
+const SETTLE_TIME: Duration = Duration::from_millis(50);
+
+// changes of the mempool directory since the previous poll, a changed file is removed and added
+#[derive(Debug, Default)]
+pub struct MempoolChanges {
+    pub added: Vec<(String, Transaction)>, // transactions of the new and changed files, by path
+    pub removed: Vec<String>,              // paths of the removed and changed files
+    pub skipped: Vec<ParseError>,          // new or changed files that aren't parsable
+}
+
+impl MempoolChanges {
+    // returns: true if no file was added, changed or removed
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.skipped.is_empty()
+    }
+}
+
+// inotify instance watching the files written, moved or deleted in a directory (not in its
+// subdirectories), the events only wake the watcher
+#[cfg(target_os = "linux")]
+struct DirectoryEvents {
+    fd: libc::c_int,
+}
+
+#[cfg(target_os = "linux")]
+impl DirectoryEvents {
+    // returns: None if inotify isn't available (e.g. the limit of watches is reached)
+    fn new(directory: &str) -> Option<Self> {
+        let path = std::ffi::CString::new(directory).ok()?;
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return None;
+        }
+        let events = DirectoryEvents { fd };
+        let mask = libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO | libc::IN_MOVED_FROM | libc::IN_DELETE;
+        let watch = unsafe { libc::inotify_add_watch(fd, path.as_ptr(), mask) };
+        (watch >= 0).then_some(events)
+    }
+
+    // waits at most for the timeout, the arrived events are read and discarded
+    // returns: true if events arrived
+    fn wait(&self, timeout: Duration) -> bool {
+        let mut poll_fd = libc::pollfd {
+            fd: self.fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let millis = timeout.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+        if unsafe { libc::poll(&mut poll_fd, 1, millis) } <= 0 {
+            return false;
+        }
+        let mut buffer = [0u8; 4096];
+        while unsafe { libc::read(self.fd, buffer.as_mut_ptr().cast(), buffer.len()) } > 0 {}
+        true
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for DirectoryEvents {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+// without inotify the watcher sleeps for the whole interval
+#[cfg(not(target_os = "linux"))]
+struct DirectoryEvents;
+
+#[cfg(not(target_os = "linux"))]
+impl DirectoryEvents {
+    fn new(_directory: &str) -> Option<Self> {
+        None
+    }
+
+    fn wait(&self, _timeout: Duration) -> bool {
+        false
+    }
+}
+
+pub struct MempoolWatcher {
+    pub directory: String,
+    pub filter: DirFilter,
+    files: HashMap<String, FileState>, // path -> state of the previous poll
+    events: Option<DirectoryEvents>,
+}
+
+impl MempoolWatcher {
+    // the first poll reports all files of the directory as added
+    pub fn new(directory: &str, filter: DirFilter) -> Self {
+        MempoolWatcher {
+            directory: directory.to_string(),
+            filter,
+            files: HashMap::new(),
+            events: DirectoryEvents::new(directory),
+        }
+    }
+
+    // blocks until files of the directory were written, moved or deleted, at most for the
+    // interval. Without inotify (other platforms, subdirectories) changes are found with a
+    // latency of up to the interval.
+    pub fn wait(&self, interval: Duration) {
+        let started = Instant::now();
+        match &self.events {
+            Some(events) => {
+                if events.wait(interval) {
+                    while started.elapsed() < interval && events.wait(SETTLE_TIME) {}
+                }
+            }
+            None => thread::sleep(interval),
+        }
+    }
+
+    // lists the directory and parses the files added or changed since the previous poll
+    // returns: MempoolChanges or Err(ParseError::Io) if the directory isn't readable
+    pub fn poll(&mut self) -> Result<MempoolChanges, ParseError> {
+        let mut changes = MempoolChanges::default();
+        let mut files = HashMap::new();
+
+        for (file_path, state) in file_states(&self.directory, &self.filter)? {
+            let path = file_path.display().to_string();
+            files.insert(path.clone(), state);
+            match self.files.remove(&path) {
+                Some(previous) if previous == state => continue,
+                Some(_) => changes.removed.push(path.clone()),
+                None => {}
+            }
+            for parsed in parse_watched_file(&file_path) {
+                match parsed {
+                    Ok(tx) => changes.added.push((path.clone(), tx)),
+                    Err(err) => changes.skipped.push(err),
+                }
+            }
+        }
+        // files of the previous poll that weren't listed again
+        changes
+            .removed
+            .extend(self.files.drain().map(|(path, _)| path));
+        self.files = files;
+        Ok(changes)
+    }
+}
@@ -0,0 +1,23 @@
+// End-to-end test against a regtest bitcoind, needs bitcoind in $PATH or $BITCOIND.
+// run with: cargo test --features regtest
+
+use super::{run_end_to_end, RegtestConfig};
+use std::fs;
+
+#[test]
+fn regtest_block_is_accepted() {
+    let config = RegtestConfig::default();
+    let result = run_end_to_end(&config);
+    let _ = fs::remove_dir_all(&config.work_dir);
+    let report = result.unwrap();
+
+    assert_eq!(report.exported, report.broadcast.len());
+    for tx in &report.broadcast {
+        assert!(
+            report.included.contains(&tx.txid),
+            "{} not included: {:?}",
+            tx.label,
+            report.invalid.get(&tx.txid)
+        );
+    }
+}
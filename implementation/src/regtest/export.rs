@@ -0,0 +1,106 @@
+// Exports the node mempool as esplora formatted json files the builder parses
+
+use super::rpc::RpcClient;
+use crate::amount::parse_json_btc;
+use crate::validation::script_asm::disassemble;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::Path;
+
+// returns: esplora scriptpubkey_type of the bitcoind script type
+fn esplora_script_type(core_type: &str) -> &str {
+    match core_type {
+        "pubkey" => "p2pk",
+        "pubkeyhash" => "p2pkh",
+        "scripthash" => "p2sh",
+        "witness_v0_keyhash" => "v0_p2wpkh",
+        "witness_v0_scripthash" => "v0_p2wsh",
+        "witness_v1_taproot" => "v1_p2tr",
+        "nulldata" => "op_return",
+        "multisig" => "multisig",
+        _ => "unknown",
+    }
+}
+
+// converts a decoded bitcoind output into the esplora output (and prevout) format
+fn esplora_output(output: &Value) -> Result<Value, String> {
+    let script_hex = output["scriptPubKey"]["hex"]
+        .as_str()
+        .ok_or("output without scriptPubKey")?;
+    let script = hex::decode(script_hex).map_err(|err| err.to_string())?;
+    Ok(json!({
+        "scriptpubkey": script_hex,
+        "scriptpubkey_asm": disassemble(&script),
+        "scriptpubkey_type": esplora_script_type(output["scriptPubKey"]["type"].as_str().unwrap_or("")),
+        "scriptpubkey_address": output["scriptPubKey"]["address"],
+        "value": parse_json_btc(&output["value"])?,
+    }))
+}
+
+// fetches the spent outputs from the node (mempool or transaction index) and converts the
+// decoded transaction into the esplora format
+// returns: esplora json of the transaction
+fn esplora_transaction(rpc: &RpcClient, decoded: &Value) -> Result<Value, String> {
+    let mut inputs = Vec::new();
+
+    for input in decoded["vin"].as_array().ok_or("transaction without vin")? {
+        let txid = input["txid"].as_str().ok_or("input without txid")?;
+        let vout = input["vout"].as_u64().ok_or("input without vout")?;
+        let parent = rpc.call("getrawtransaction", json!([txid, true]))?;
+        let prevout = esplora_output(&parent["vout"][vout as usize])?;
+        let scriptsig_hex = input["scriptSig"]["hex"].as_str().unwrap_or("");
+        let scriptsig = hex::decode(scriptsig_hex).map_err(|err| err.to_string())?;
+        let mut input_json = json!({
+            "txid": txid,
+            "vout": vout,
+            "prevout": prevout,
+            "scriptsig": scriptsig_hex,
+            "scriptsig_asm": disassemble(&scriptsig),
+            "is_coinbase": false,
+            "sequence": input["sequence"],
+        });
+        if input["txinwitness"].is_array() {
+            input_json["witness"] = input["txinwitness"].clone();
+        }
+        inputs.push(input_json);
+    }
+    let outputs = decoded["vout"]
+        .as_array()
+        .ok_or("transaction without vout")?
+        .iter()
+        .map(esplora_output)
+        .collect::<Result<Vec<Value>, String>>()?;
+
+    Ok(json!({
+        "version": decoded["version"],
+        "locktime": decoded["locktime"],
+        "vin": inputs,
+        "vout": outputs,
+    }))
+}
+
+// writes each mempool transaction as <sha256(txid)>.json into the directory, like the files
+// of the exercise mempool
+// returns: txids of the exported transactions or Err(message) of the failed rpc call
+pub fn export_mempool(rpc: &RpcClient, directory: &Path) -> Result<Vec<String>, String> {
+    fs::create_dir_all(directory)
+        .map_err(|err| format!("creating {}: {}", directory.display(), err))?;
+    let mempool = rpc.call("getrawmempool", json!([]))?;
+    let txids: Vec<String> = mempool
+        .as_array()
+        .ok_or("getrawmempool returned no array")?
+        .iter()
+        .filter_map(|txid| txid.as_str().map(str::to_string))
+        .collect();
+
+    for txid in &txids {
+        let decoded = rpc.call("getrawtransaction", json!([txid, true]))?;
+        let transaction = esplora_transaction(rpc, &decoded)?;
+        let txid_bytes = hex::decode(txid).map_err(|err| err.to_string())?;
+        let file_name = format!("{:x}.json", Sha256::digest(txid_bytes));
+        fs::write(directory.join(file_name), transaction.to_string())
+            .map_err(|err| format!("writing {}: {}", txid, err))?;
+    }
+    Ok(txids)
+}
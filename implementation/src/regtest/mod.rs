@@ -0,0 +1,287 @@
+// End-to-end regtest harness: starts a regtest bitcoind, broadcasts a mix of P2PKH, P2WPKH and
+// P2TR transactions, exports the mempool into the builder, builds a block from it and submits it
+// to the node. Exercises parsing, validation, selection, coinbase, header and submission.
+// run with: cargo test --features regtest (bitcoind path in $BITCOIND, default bitcoind)
+
+#[cfg(test)]
+mod e2e_tests;
+pub mod export;
+pub mod node;
+pub mod rpc;
+
+use self::export::export_mempool;
+use self::node::RegtestNode;
+use self::rpc::RpcClient;
+use crate::amount::{format_btc, parse_btc, SAT_PER_BTC};
+use crate::chain_context::ChainContext;
+use crate::mempool_policy::MempoolPolicy;
+use crate::mining::block_template::parse_node_witness_data;
+use crate::mining::invariants::Limits;
+use crate::mining::mine_block;
+use crate::mining::serialize_block::serialize_block;
+use crate::parsing::parse_transactions_from_dir;
+use crate::parsing::transaction_structs::Transaction;
+use crate::validation::utils::double_hash;
+use crate::validation::{ValidationError, ValidationOptions};
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::path::PathBuf;
+use std::process;
+
+const FUNDING_SAT: u64 = SAT_PER_BTC;
+const SPEND_FEE_SAT: u64 = 10_000;
+
+#[derive(Debug, Clone)]
+pub struct RegtestConfig {
+    pub bitcoind_path: String,
+    pub rpc_port: u16,
+    pub work_dir: PathBuf, // node datadir and exported mempool, removed afterwards
+}
+
+impl Default for RegtestConfig {
+    fn default() -> Self {
+        RegtestConfig {
+            bitcoind_path: env::var("BITCOIND").unwrap_or("bitcoind".to_string()),
+            rpc_port: 18543,
+            work_dir: env::temp_dir().join(format!("block-builder-regtest-{}", process::id())),
+        }
+    }
+}
+
+// transaction broadcast by the harness, the label names the spent and created output type
+#[derive(Debug, Clone)]
+pub struct BroadcastTransaction {
+    pub label: String,
+    pub txid: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct RegtestReport {
+    pub broadcast: Vec<BroadcastTransaction>,
+    pub exported: usize,
+    pub invalid: HashMap<String, ValidationError>, // txid -> rejection reason of the builder
+    pub included: Vec<String>,                     // txids of the submitted block without coinbase
+    pub block_hash: String,
+}
+
+// returns: json amount in BTC with 8 decimals of the sat value
+fn btc_amount(sat: u64) -> Value {
+    serde_json::from_str(&format_btc(sat)).expect("Amount formatting failed")
+}
+
+fn new_address(rpc: &RpcClient, address_type: &str) -> Result<String, String> {
+    rpc.call("getnewaddress", json!(["", address_type]))?
+        .as_str()
+        .map(str::to_string)
+        .ok_or("getnewaddress returned no address".to_string())
+}
+
+// spends the only unspent output of the address (with min_conf confirmations) to the
+// destination address, paying SPEND_FEE_SAT fee
+// returns: txid of the broadcast transaction
+fn spend_address(
+    rpc: &RpcClient,
+    address: &str,
+    destination: &str,
+    min_conf: u32,
+) -> Result<String, String> {
+    let unspent = rpc.call("listunspent", json!([min_conf, 9999999, [address]]))?;
+    let utxo = unspent
+        .get(0)
+        .ok_or(format!("no unspent output of {}", address))?;
+    let value = parse_btc(&utxo["amount"].to_string())?;
+    let raw = rpc.call(
+        "createrawtransaction",
+        json!([
+            [{"txid": utxo["txid"], "vout": utxo["vout"]}],
+            {destination: btc_amount(value - SPEND_FEE_SAT)}
+        ]),
+    )?;
+    let signed = rpc.call("signrawtransactionwithwallet", json!([raw]))?;
+    if signed["complete"] != json!(true) {
+        return Err(format!("signing the spend of {} failed", address));
+    }
+    rpc.call("sendrawtransaction", json!([signed["hex"]]))?
+        .as_str()
+        .map(str::to_string)
+        .ok_or("sendrawtransaction returned no txid".to_string())
+}
+
+// funds a legacy, bech32 and bech32m address from a mature coinbase, confirms the funding and
+// spends each of them (P2PKH, P2WPKH and P2TR inputs) plus an unconfirmed child
+// returns: the transactions left in the mempool
+fn broadcast_transactions(rpc: &RpcClient) -> Result<Vec<BroadcastTransaction>, String> {
+    let mining_address = new_address(rpc, "bech32")?;
+    rpc.call("generatetoaddress", json!([101, mining_address]))?;
+
+    let legacy = new_address(rpc, "legacy")?;
+    let bech32 = new_address(rpc, "bech32")?;
+    let bech32m = new_address(rpc, "bech32m")?;
+    let mut funding = serde_json::Map::new();
+    for address in [&legacy, &bech32, &bech32m] {
+        funding.insert(address.clone(), btc_amount(FUNDING_SAT));
+    }
+    rpc.call("sendmany", json!(["", funding]))?;
+    rpc.call("generatetoaddress", json!([1, mining_address]))?;
+
+    let p2wpkh_destination = new_address(rpc, "bech32")?;
+    let mut broadcast = vec![
+        BroadcastTransaction {
+            label: "p2pkh -> p2wpkh".to_string(),
+            txid: spend_address(rpc, &legacy, &p2wpkh_destination, 1)?,
+        },
+        BroadcastTransaction {
+            label: "p2wpkh -> p2tr".to_string(),
+            txid: spend_address(rpc, &bech32, &new_address(rpc, "bech32m")?, 1)?,
+        },
+        BroadcastTransaction {
+            label: "p2tr -> p2pkh".to_string(),
+            txid: spend_address(rpc, &bech32m, &new_address(rpc, "legacy")?, 1)?,
+        },
+    ];
+    broadcast.push(BroadcastTransaction {
+        label: "unconfirmed p2wpkh -> p2wpkh".to_string(),
+        txid: spend_address(rpc, &p2wpkh_destination, &new_address(rpc, "bech32")?, 0)?,
+    });
+    Ok(broadcast)
+}
+
+// validates the parsed transactions and drops the invalid ones and their mempool descendants,
+// transactions not final in the block of the context are invalid
+// returns: HashMap txid -> valid Transaction, HashMap txid -> rejection reason
+fn validate_mempool(
+    transactions: Vec<Transaction>,
+    context: &ChainContext,
+) -> (
+    HashMap<String, Transaction>,
+    HashMap<String, ValidationError>,
+) {
+    let options = ValidationOptions {
+        finality: Some(context.finality_target()),
+        ..ValidationOptions::standard_sighash_types()
+    };
+    let mut invalid: HashMap<String, ValidationError> = HashMap::new();
+    let mut valid: HashMap<String, Transaction> = HashMap::new();
+
+    for mut tx in transactions {
+        match tx.validate(&options) {
+            Ok(()) => {
+                valid.insert(tx.meta.txid_hex.clone(), tx);
+            }
+            Err(error) => {
+                invalid.insert(tx.meta.txid_hex.clone(), error);
+            }
+        }
+    }
+    loop {
+        let orphaned: HashSet<String> = valid
+            .values()
+            .filter(|tx| tx.vin.iter().any(|txin| invalid.contains_key(&txin.txid)))
+            .map(|tx| tx.meta.txid_hex.clone())
+            .collect();
+        if orphaned.is_empty() {
+            break;
+        }
+        for txid in orphaned {
+            valid.remove(&txid);
+            invalid.insert(txid, ValidationError::InvalidParent);
+        }
+    }
+    (valid, invalid)
+}
+
+// chain tip and witness commitment data of the node the block is built on
+fn node_chain_context(rpc: &RpcClient) -> Result<ChainContext, String> {
+    let chain_info = rpc.call("getblockchaininfo", json!([]))?;
+    let template = rpc.call("getblocktemplate", json!([{"rules": ["segwit"]}]))?;
+    let bits = template["bits"].as_str().ok_or("template without bits")?;
+
+    Ok(ChainContext {
+        height: chain_info["blocks"].as_u64().ok_or("no block count")? + 1,
+        previous_block_hash: chain_info["bestblockhash"]
+            .as_str()
+            .ok_or("no best block hash")?
+            .to_string(),
+        median_time_past: chain_info["mediantime"].as_u64().map(|time| time as u32),
+        bits: u32::from_str_radix(bits, 16).map_err(|err| err.to_string())?,
+        node_witness: Some(parse_node_witness_data(&template.to_string())?),
+        coinbase_heights: HashMap::new(),
+    })
+}
+
+// looks up the confirmed outputs spent by the transactions with gettxout
+// returns: coinbase txid -> height of the spent coinbase outputs
+fn node_coinbase_heights(
+    rpc: &RpcClient,
+    transactions: &[Transaction],
+    height: u64,
+) -> Result<HashMap<String, u64>, String> {
+    let mut coinbase_heights: HashMap<String, u64> = HashMap::new();
+    for txin in transactions.iter().flat_map(|tx| &tx.vin) {
+        // null for unconfirmed (mempool) outputs
+        let output = rpc.call("gettxout", json!([txin.txid, txin.vout, false]))?;
+        if output["coinbase"] == json!(true) {
+            let confirmations = output["confirmations"]
+                .as_u64()
+                .ok_or("gettxout without confirmations")?;
+            coinbase_heights.insert(txin.txid.clone(), height - confirmations);
+        }
+    }
+    Ok(coinbase_heights)
+}
+
+// runs the complete flow against a fresh regtest node
+// returns: RegtestReport of the accepted block or Err(message) of the failed step
+pub fn run_end_to_end(config: &RegtestConfig) -> Result<RegtestReport, String> {
+    let mut node = RegtestNode::start(
+        &config.bitcoind_path,
+        config.work_dir.join("node"),
+        config.rpc_port,
+    )?;
+    node.rpc.call("createwallet", json!(["harness"]))?;
+    node.rpc.wallet = Some("harness".to_string());
+
+    let broadcast = broadcast_transactions(&node.rpc)?;
+    let mempool_dir = config.work_dir.join("mempool");
+    let exported = export_mempool(&node.rpc, &mempool_dir)?;
+    let parsed = parse_transactions_from_dir(mempool_dir.to_str().ok_or("invalid work dir")?)
+        .map_err(|err| err.to_string())?;
+    let mut context = node_chain_context(&node.rpc)?;
+    context.coinbase_heights = node_coinbase_heights(&node.rpc, &parsed, context.height)?;
+    let (mut valid, invalid) = validate_mempool(parsed, &context);
+
+    let block = mine_block(&mut valid, &context, &MempoolPolicy::default(), None)
+        .map_err(|err| err.to_string())?;
+    let mut violations = block.check_invariants(&Limits::default());
+    violations.extend(block.check_chain_context(&context));
+    if !violations.is_empty() {
+        return Err(format!("constructed block violates {:?}", violations));
+    }
+    let submitted = node
+        .rpc
+        .call("submitblock", json!([hex::encode(serialize_block(&block))]))?;
+    if !submitted.is_null() {
+        return Err(format!("node rejected the block: {}", submitted));
+    }
+
+    let header = hex::decode(&block.header_hex).map_err(|err| err.to_string())?;
+    let block_hash: Vec<u8> = double_hash(&header).into_iter().rev().collect();
+    let block_hash = hex::encode(block_hash);
+    let best_block_hash = node.rpc.call("getbestblockhash", json!([]))?;
+    if best_block_hash != json!(block_hash) {
+        return Err(format!(
+            "submitted block {} is not the chain tip {}",
+            block_hash, best_block_hash
+        ));
+    }
+    let _ = std::fs::remove_dir_all(&mempool_dir);
+
+    Ok(RegtestReport {
+        broadcast,
+        exported: exported.len(),
+        invalid,
+        included: block.txids_hex[1..].to_vec(),
+        block_hash,
+    })
+}
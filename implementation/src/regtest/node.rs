@@ -0,0 +1,78 @@
+// Regtest bitcoind process started and stopped by the harness
+
+use super::rpc::RpcClient;
+use serde_json::json;
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(60);
+
+// running regtest node with its own data directory, stopped and cleaned up on drop
+pub struct RegtestNode {
+    process: Child,
+    pub datadir: PathBuf,
+    pub rpc: RpcClient,
+}
+
+impl RegtestNode {
+    // starts bitcoind in regtest mode with transaction index and waits until the rpc
+    // server is out of warmup
+    // returns: RegtestNode or Err(message) if bitcoind can't be started or doesn't respond
+    pub fn start(
+        bitcoind_path: &str,
+        datadir: PathBuf,
+        rpc_port: u16,
+    ) -> Result<RegtestNode, String> {
+        fs::create_dir_all(&datadir)
+            .map_err(|err| format!("creating {}: {}", datadir.display(), err))?;
+        let mut process = Command::new(bitcoind_path)
+            .arg("-regtest")
+            .arg(format!("-datadir={}", datadir.display()))
+            .arg(format!("-rpcport={}", rpc_port))
+            .args(["-server", "-listen=0", "-txindex", "-fallbackfee=0.0002"])
+            .stdout(Stdio::null())
+            .spawn()
+            .map_err(|err| format!("starting {}: {}", bitcoind_path, err))?;
+
+        let address = format!("127.0.0.1:{}", rpc_port);
+        let cookie_path = datadir.join("regtest").join(".cookie");
+        let started = Instant::now();
+        let mut last_error = String::new();
+        while started.elapsed() < STARTUP_TIMEOUT {
+            sleep(Duration::from_millis(250));
+            let rpc = match RpcClient::from_cookie(&address, &cookie_path) {
+                Ok(rpc) => rpc,
+                Err(err) => {
+                    last_error = err;
+                    continue;
+                }
+            };
+            match rpc.call("getblockchaininfo", json!([])) {
+                Ok(_) => {
+                    return Ok(RegtestNode {
+                        process,
+                        datadir,
+                        rpc,
+                    })
+                }
+                Err(err) => last_error = err, // rpc in warmup
+            }
+        }
+        let _ = process.kill();
+        let _ = process.wait();
+        Err(format!("bitcoind didn't start: {}", last_error))
+    }
+}
+
+impl Drop for RegtestNode {
+    fn drop(&mut self) {
+        if self.rpc.call("stop", json!([])).is_err() {
+            let _ = self.process.kill();
+        }
+        let _ = self.process.wait();
+        let _ = fs::remove_dir_all(&self.datadir);
+    }
+}
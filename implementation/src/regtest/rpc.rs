@@ -0,0 +1,94 @@
+// Minimal bitcoind JSON-RPC client over a plain TCP connection (HTTP/1.0, cookie auth)
+
+use serde_json::{json, Value};
+use std::fs;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+// returns: standard base64 encoding (with padding) of the passed bytes
+fn base64(bytes: &[u8]) -> String {
+    let mut encoded = String::new();
+
+    for chunk in bytes.chunks(3) {
+        let mut group = [0u8; 3];
+        group[..chunk.len()].copy_from_slice(chunk);
+        let bits = u32::from_be_bytes([0, group[0], group[1], group[2]]);
+        for index in 0..4 {
+            if index <= chunk.len() {
+                encoded.push(BASE64_ALPHABET[(bits >> (18 - 6 * index)) as usize & 0x3f] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+pub struct RpcClient {
+    pub address: String,        // host:port of the rpc server
+    authorization: String,      // value of the basic auth header
+    pub wallet: Option<String>, // wallet the calls are sent to
+}
+
+impl RpcClient {
+    // reads the user:password pair of the cookie file written by bitcoind on startup
+    // returns: Err if the cookie file can't be read (node not started yet)
+    pub fn from_cookie(address: &str, cookie_path: &Path) -> Result<RpcClient, String> {
+        let cookie = fs::read_to_string(cookie_path)
+            .map_err(|err| format!("reading {}: {}", cookie_path.display(), err))?;
+        Ok(RpcClient {
+            address: address.to_string(),
+            authorization: format!("Basic {}", base64(cookie.trim().as_bytes())),
+            wallet: None,
+        })
+    }
+
+    // sends a single json-rpc request. HTTP/1.0 is used so the response is never chunked.
+    // returns: result of the call or Err(message) on connection and rpc errors
+    pub fn call(&self, method: &str, params: Value) -> Result<Value, String> {
+        let body =
+            json!({"jsonrpc": "1.0", "id": method, "method": method, "params": params}).to_string();
+        let path = match &self.wallet {
+            Some(wallet) => format!("/wallet/{}", wallet),
+            None => "/".to_string(),
+        };
+        let request = format!(
+            "POST {} HTTP/1.0\r\nHost: {}\r\nAuthorization: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            path,
+            self.address,
+            self.authorization,
+            body.len(),
+            body
+        );
+
+        let mut stream = TcpStream::connect(&self.address)
+            .map_err(|err| format!("connecting to {}: {}", self.address, err))?;
+        stream
+            .write_all(request.as_bytes())
+            .map_err(|err| format!("sending {}: {}", method, err))?;
+        let mut response = String::new();
+        stream
+            .read_to_string(&mut response)
+            .map_err(|err| format!("reading {} response: {}", method, err))?;
+
+        // rpc errors are returned with http status 500 and a json body as well
+        let (status, body) = response
+            .split_once("\r\n\r\n")
+            .ok_or(format!("{}: malformed http response", method))?;
+        let reply: Value = serde_json::from_str(body).map_err(|_| {
+            format!(
+                "{}: no json response ({})",
+                method,
+                status.lines().next().unwrap_or("")
+            )
+        })?;
+        if !reply["error"].is_null() {
+            return Err(format!("{}: {}", method, reply["error"]["message"]));
+        }
+        Ok(reply["result"].clone())
+    }
+}
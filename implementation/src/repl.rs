@@ -0,0 +1,303 @@
+use crate::output::output_block;
+use crate::{build_block, includable_transactions, validate_transactions};
+use bitcoin_block_builder::amount::format_btc;
+use bitcoin_block_builder::chain_context::ChainContext;
+use bitcoin_block_builder::feerate::{vsize, FeeRate};
+use bitcoin_block_builder::mempool_policy::MempoolPolicy;
+use bitcoin_block_builder::mining::assign_parents::{assign_mempool_parents, mempool_ancestors};
+use bitcoin_block_builder::mining::construct_coinbase::count_fees;
+use bitcoin_block_builder::mining::{select_transactions, Block};
+use bitcoin_block_builder::parsing::{
+    parse_transactions_from_dir, transaction_structs::Transaction,
+};
+#[cfg(feature = "interpreter")]
+use bitcoin_block_builder::validation::script_trace::trace_input;
+use bitcoin_block_builder::validation::{ValidationError, ValidationOptions};
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::path::Path;
+
+const HELP: &str = "commands:
+  load <dir>        parse all json transactions of the directory
+  validate          validate the loaded transactions and drop invalid and not includable ones
+  show <txid>       print a loaded transaction
+  ancestors <txid>  print the mempool ancestors of a valid transaction
+  trace <txid> <n>  trace the scriptsig + scriptpubkey execution of input n opcode by opcode
+  select            select the transactions of the next block
+  mine              build and check the block (coinbase, header, proof of work)
+  write <path>      write the mined block as output.txt to path
+  help              print this help
+  quit              exit";
+
+// state the repl commands operate on, each step invalidates the results of the later steps
+struct ReplState {
+    context: ChainContext,
+    policy: MempoolPolicy,
+    options: ValidationOptions,
+    context_check: bool, // check the chain context of the mined block
+    parsed: Vec<Transaction>,
+    invalid: HashMap<String, String>, // txid -> rejection reason
+    pending: HashMap<String, String>, // txid -> reason a valid transaction isn't includable yet
+    mempool: Option<HashMap<String, Transaction>>, // block-valid transactions after validate
+    selected: Option<Vec<Transaction>>,
+    block: Option<Block>,
+}
+
+fn load(state: &mut ReplState, dir: &str) -> Result<(), String> {
+    if !Path::new(dir).is_dir() {
+        return Err(format!("{} is no directory", dir));
+    }
+    state.parsed = parse_transactions_from_dir(dir).map_err(|err| err.to_string())?;
+    state.invalid.clear();
+    state.pending.clear();
+    state.mempool = None;
+    state.selected = None;
+    state.block = None;
+    println!("loaded {} transactions", state.parsed.len());
+    Ok(())
+}
+
+fn validate(state: &mut ReplState) -> Result<(), String> {
+    if state.parsed.is_empty() {
+        return Err("nothing loaded, use load <dir>".to_string());
+    }
+    state.invalid = validate_transactions(&mut state.parsed, &state.options, true);
+    let sets = includable_transactions(state.parsed.clone(), &state.invalid, &state.context);
+    state.pending = sets
+        .relay_valid
+        .into_iter()
+        .map(|(txid, pending)| (txid, pending.reason.to_string()))
+        .collect();
+    let mut mempool = sets.block_valid;
+    assign_mempool_parents(&mut mempool);
+    println!(
+        "{} valid, {} not includable yet, {} invalid, {} removed because of invalid mempool parents",
+        mempool.len(),
+        state.pending.len(),
+        state.invalid.len(),
+        state.parsed.len() - mempool.len() - state.pending.len() - state.invalid.len()
+    );
+    state.mempool = Some(mempool);
+    state.selected = None;
+    state.block = None;
+    Ok(())
+}
+
+// returns: the loaded transaction with the txid
+fn loaded_tx<'a>(state: &'a ReplState, txid: &str) -> Result<&'a Transaction, String> {
+    state
+        .parsed
+        .iter()
+        .find(|tx| tx.meta.txid_hex == txid)
+        .ok_or(format!(
+            "{} not loaded (txids are known after validate)",
+            txid
+        ))
+}
+
+fn show(state: &ReplState, txid: &str) -> Result<(), String> {
+    let tx = loaded_tx(state, txid)?;
+
+    println!("txid:     {}", tx.meta.txid_hex);
+    println!("wtxid:    {}", tx.meta.wtxid_hex);
+    println!("file:     {}", tx.meta.json_path.as_deref().unwrap_or("-"));
+    println!("version:  {}  locktime: {}", tx.version, tx.locktime);
+    for (index, txin) in tx.vin.iter().enumerate() {
+        println!(
+            "vin[{}]:   {}:{} {} {} sat",
+            index, txin.txid, txin.vout, txin.prevout.scriptpubkey_type, txin.prevout.value
+        );
+    }
+    for (index, txout) in tx.vout.iter().enumerate() {
+        println!(
+            "vout[{}]:  {} {} sat",
+            index, txout.scriptpubkey_type, txout.value
+        );
+    }
+    if tx.meta.weight > 0 {
+        println!(
+            "fee:      {} BTC, weight: {} wu, vsize: {} vB, {}",
+            format_btc(tx.meta.fee),
+            tx.meta.weight,
+            vsize(tx.meta.weight),
+            FeeRate::from_fee_vsize(tx.meta.fee, vsize(tx.meta.weight))
+        );
+    }
+    if let Some(position) = state
+        .selected
+        .as_ref()
+        .and_then(|selected| selected.iter().position(|tx| tx.meta.txid_hex == txid))
+    {
+        println!("selected: at block position {}", position + 1);
+    }
+    match (state.invalid.get(txid), state.pending.get(txid)) {
+        (Some(reason), _) => println!("invalid:  {}", reason),
+        (None, Some(reason)) => println!("pending:  {}", reason),
+        (None, None) => {
+            if let Some(valid) = state.mempool.as_ref().and_then(|mempool| mempool.get(txid)) {
+                println!(
+                    "parents:  {:?}",
+                    valid.meta.parents.as_deref().unwrap_or(&[])
+                );
+            } else if state.mempool.is_some() {
+                println!("invalid:  {}", ValidationError::InvalidParent);
+            }
+        }
+    }
+    Ok(())
+}
+
+// prints every opcode of the scriptsig + scriptpubkey execution of the input with the stack
+#[cfg(feature = "interpreter")]
+fn trace(state: &ReplState, txid: &str, input: &str) -> Result<(), String> {
+    let tx = loaded_tx(state, txid)?;
+    let index: usize = input
+        .parse()
+        .map_err(|_| format!("{} is no input index", input))?;
+    if index >= tx.vin.len() {
+        return Err(format!("{} has no input {}", txid, index));
+    }
+
+    let (trace, result) = trace_input(tx, index, &state.options);
+    print!("{}", trace);
+    match result {
+        Ok(_) => println!("result:   valid"),
+        Err(err) => println!("result:   {}", err),
+    }
+    Ok(())
+}
+
+fn ancestors(state: &ReplState, txid: &str) -> Result<(), String> {
+    let mempool = state
+        .mempool
+        .as_ref()
+        .ok_or("not validated, use validate")?;
+
+    let ancestors = mempool_ancestors(mempool, txid)?;
+    for ancestor in &ancestors {
+        let tx = &mempool[ancestor];
+        println!(
+            "{} {} BTC, {} wu",
+            ancestor,
+            format_btc(tx.meta.fee),
+            tx.meta.weight
+        );
+    }
+    println!("{} ancestors", ancestors.len());
+    Ok(())
+}
+
+fn select(state: &mut ReplState) -> Result<(), String> {
+    let mempool = state
+        .mempool
+        .as_mut()
+        .ok_or("not validated, use validate")?;
+
+    let (sorted, selected) = select_transactions(mempool, None, &state.policy);
+    let weight: u64 = selected.iter().map(|tx| tx.meta.weight).sum();
+    println!(
+        "selected {} of {} transactions, {} wu, {} BTC fees",
+        selected.len(),
+        sorted.len(),
+        weight,
+        format_btc(count_fees(&selected))
+    );
+    state.selected = Some(selected);
+    Ok(())
+}
+
+fn mine(state: &mut ReplState) -> Result<(), String> {
+    let mempool = state
+        .mempool
+        .as_mut()
+        .ok_or("not validated, use validate")?;
+
+    let block = build_block(
+        mempool,
+        &state.context,
+        &state.policy,
+        None,
+        state.context_check,
+    )
+    .map_err(|err| err.to_string())?;
+    println!("mined block with header {}", block.header_hex);
+    println!(
+        "{} transactions including the coinbase, reward {} BTC",
+        block.txids_hex.len(),
+        format_btc(block.coinbase_tx.reward)
+    );
+    state.block = Some(block);
+    Ok(())
+}
+
+fn write(state: &ReplState, path: &str) -> Result<(), String> {
+    let block = state.block.as_ref().ok_or("nothing mined, use mine")?;
+    output_block(block, Path::new(path)).map_err(|err| err.to_string())?;
+    println!("wrote {}", path);
+    Ok(())
+}
+
+// executes a single repl line
+// returns: Ok(false) if the repl should exit, Err(message) if the command failed
+fn execute(state: &mut ReplState, line: &str) -> Result<bool, String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+
+    match words.as_slice() {
+        [] => {}
+        ["load", dir] => load(state, dir)?,
+        ["validate"] => validate(state)?,
+        ["show", txid] => show(state, txid)?,
+        ["ancestors", txid] => ancestors(state, txid)?,
+        #[cfg(feature = "interpreter")]
+        ["trace", txid, input] => trace(state, txid, input)?,
+        ["select"] => select(state)?,
+        ["mine"] => mine(state)?,
+        ["write", path] => write(state, path)?,
+        ["help"] => println!("{}", HELP),
+        ["quit"] | ["exit"] => return Ok(false),
+        _ => return Err(format!("unknown command: {} (try help)", line.trim())),
+    }
+    Ok(true)
+}
+
+// interactive shell to step through block construction like a run without the repl (the same
+// validation, includability and block checks), reads commands from stdin until
+// quit or end of input
+pub fn run_repl(
+    context: ChainContext,
+    policy: MempoolPolicy,
+    options: ValidationOptions,
+    context_check: bool,
+) {
+    let mut state = ReplState {
+        context,
+        policy,
+        options,
+        context_check,
+        parsed: Vec::new(),
+        invalid: HashMap::new(),
+        pending: HashMap::new(),
+        mempool: None,
+        selected: None,
+        block: None,
+    };
+    println!("{}", HELP);
+
+    loop {
+        print!("> ");
+        io::stdout().flush().expect("Unable to flush stdout");
+        let mut line = String::new();
+        if io::stdin()
+            .read_line(&mut line)
+            .expect("Unable to read stdin")
+            == 0
+        {
+            break;
+        }
+        match execute(&mut state, &line) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(message) => println!("error: {}", message),
+        }
+    }
+}
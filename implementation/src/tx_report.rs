@@ -0,0 +1,208 @@
+// Per-transaction report of a run for auditing why transactions were excluded from the block:
+// the parsed data of every transaction next to its validation verdict, written as a JSON array
+// or as CSV with one row per transaction.
+
+use crate::cli::ReportFormat;
+use crate::output::ReportEntry;
+use bitcoin_block_builder::error::{io_error, BlockBuilderError};
+use bitcoin_block_builder::feerate::{vsize, FeeRate, WITNESS_SCALE_FACTOR};
+use bitcoin_block_builder::parsing::transaction_structs::Transaction;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+const CSV_HEADER: &str =
+    "txid,wtxid,weight,vsize,fee,feerate_sat_vb,input_types,valid,reason,pending\n";
+
+// weight and fee are 0 if the validation stopped before calculating them
+#[derive(Serialize, Debug)]
+pub struct TransactionRow {
+    pub txid: String,
+    pub wtxid: String,
+    pub weight: u64,
+    pub vsize: u64,
+    pub fee: u64,
+    pub feerate_sat_vb: f64, // from the fee and vsize, rounded down to 3 decimals
+    pub input_types: Vec<String>, // scriptpubkey types of the spent prevouts
+    pub valid: bool,
+    pub reason: Option<String>,
+    pub pending: Option<String>, // why a valid transaction isn't includable in the block yet
+}
+
+// has to be called after the validation (txids, weights and fees are set there), the verdict
+// is filled in by write_transaction_report()
+// returns: TransactionRow of each transaction
+pub fn transaction_rows(transactions: &[Transaction]) -> Vec<TransactionRow> {
+    transactions
+        .iter()
+        .map(|tx| {
+            let vsize = vsize(tx.meta.weight);
+            let feerate = FeeRate::from_fee_vsize(tx.meta.fee, vsize);
+            TransactionRow {
+                txid: tx.meta.txid_hex.clone(),
+                wtxid: tx.meta.wtxid_hex.clone(),
+                weight: tx.meta.weight,
+                vsize,
+                fee: tx.meta.fee,
+                feerate_sat_vb: feerate.to_sat_per_kwu() as f64 * WITNESS_SCALE_FACTOR as f64
+                    / 1000.0,
+                input_types: tx
+                    .vin
+                    .iter()
+                    .map(|txin| txin.prevout.scriptpubkey_type.clone())
+                    .collect(),
+                valid: false,
+                reason: None,
+                pending: None,
+            }
+        })
+        .collect()
+}
+
+// quotes the field if it contains a separator, quote or line break
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+// input types are separated by spaces, missing reasons are empty fields
+// returns: the rows as CSV with header
+fn report_csv(rows: &[TransactionRow]) -> String {
+    let mut csv = String::from(CSV_HEADER);
+
+    for row in rows {
+        csv.push_str(&format!(
+            "{},{},{},{},{},{:.3},{},{},{},{}\n",
+            row.txid,
+            row.wtxid,
+            row.weight,
+            row.vsize,
+            row.fee,
+            row.feerate_sat_vb,
+            csv_field(&row.input_types.join(" ")),
+            row.valid,
+            csv_field(row.reason.as_deref().unwrap_or("")),
+            csv_field(row.pending.as_deref().unwrap_or(""))
+        ));
+    }
+    csv
+}
+
+// sets the verdict of the ReportEntry with the same txid on each row and writes the rows
+// in the format to path
+// returns: Err(BlockBuilderError::Io) if writing fails
+pub fn write_transaction_report(
+    path: &Path,
+    format: ReportFormat,
+    mut rows: Vec<TransactionRow>,
+    report: &[ReportEntry],
+) -> Result<(), BlockBuilderError> {
+    let verdicts: HashMap<&str, &ReportEntry> = report
+        .iter()
+        .map(|entry| (entry.txid.as_str(), entry))
+        .collect();
+    for row in &mut rows {
+        if let Some(entry) = verdicts.get(row.txid.as_str()) {
+            row.valid = entry.valid;
+            row.reason = entry.reason.clone();
+            row.pending = entry.pending.clone();
+        }
+    }
+    let contents = match format {
+        ReportFormat::Json => {
+            serde_json::to_string_pretty(&rows).expect("Unable to serialize the transaction report")
+        }
+        ReportFormat::Csv => report_csv(&rows),
+    };
+    fs::write(path, contents).map_err(io_error(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin_block_builder::parsing::parse_transactions_from_dir;
+    use std::env;
+
+    // returns: rows of the transactions "first" and "second", copies of a p2pkh fixture with one
+    // input, weight 561 (141 vB) and a fee of 1410 sat
+    fn fixture_rows() -> Vec<TransactionRow> {
+        let mut tx = parse_transactions_from_dir("test_scripts/testfiles/p2pkh")
+            .expect("Parsing the fixtures failed")
+            .remove(0);
+        tx.vin.truncate(1);
+        tx.meta.weight = 561;
+        tx.meta.fee = 1410;
+        let transactions: Vec<Transaction> = ["first", "second"]
+            .iter()
+            .map(|txid| {
+                let mut tx = tx.clone();
+                tx.meta.txid_hex = txid.to_string();
+                tx.meta.wtxid_hex = format!("{}_wtxid", txid);
+                tx
+            })
+            .collect();
+        transaction_rows(&transactions)
+    }
+
+    // returns: contents of the report written with the verdicts of the ReportEntries
+    fn written_report(format: ReportFormat, report: &[ReportEntry]) -> String {
+        let path = env::temp_dir().join(format!("tx_report_{}_{:?}", std::process::id(), format));
+        write_transaction_report(&path, format, fixture_rows(), report).unwrap();
+        let contents = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        contents
+    }
+
+    fn entry(txid: &str, valid: bool, reason: Option<&str>) -> ReportEntry {
+        ReportEntry {
+            txid: txid.to_string(),
+            valid,
+            reason: reason.map(str::to_string),
+            pending: None,
+            dust: None,
+        }
+    }
+
+    #[test]
+    fn csv_report_has_a_row_with_the_verdict_of_each_transaction() {
+        let report = [
+            entry("first", true, None),
+            entry("second", false, Some("Sighash 0x03, \"SINGLE\"")),
+        ];
+        let csv = written_report(ReportFormat::Csv, &report);
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(format!("{}\n", lines[0]), CSV_HEADER);
+        assert_eq!(
+            lines[1],
+            "first,first_wtxid,561,141,1410,10.000,p2pkh,true,,"
+        );
+        // the reason is quoted, its quotes doubled
+        assert_eq!(
+            lines[2],
+            "second,second_wtxid,561,141,1410,10.000,p2pkh,false,\"Sighash 0x03, \"\"SINGLE\"\"\","
+        );
+    }
+
+    #[test]
+    fn json_report_lists_each_transaction() {
+        // the second transaction has no verdict
+        let json = written_report(ReportFormat::Json, &[entry("first", false, Some("Dust"))]);
+        let rows: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(rows.as_array().unwrap().len(), 2);
+        assert_eq!(rows[0]["txid"], "first");
+        assert_eq!(rows[0]["wtxid"], "first_wtxid");
+        assert_eq!(rows[0]["vsize"], 141);
+        assert_eq!(rows[0]["feerate_sat_vb"], 10.0);
+        assert_eq!(rows[0]["input_types"], serde_json::json!(["p2pkh"]));
+        assert_eq!(rows[0]["valid"], false);
+        assert_eq!(rows[0]["reason"], "Dust");
+        assert_eq!(rows[1]["txid"], "second");
+        assert_eq!(rows[1]["valid"], false);
+        assert!(rows[1]["reason"].is_null());
+    }
+}
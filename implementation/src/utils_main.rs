@@ -1,4 +1,4 @@
-use crate::parsing::transaction_structs::Transaction;
+use bitcoin_block_builder::parsing::transaction_structs::Transaction;
 use std::collections::{HashMap, HashSet};
 
 // Converts a Vec<Transaction> to HashMap<hex txid Sting, Transaction>
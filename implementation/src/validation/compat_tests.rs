@@ -0,0 +1,463 @@
+// Compatibility tests cross-checking the hand-rolled serialization (txid, wtxid, weight, sighash)
+// against the rust-bitcoin crate on the bundled fixtures.
+// run with: cargo test --features compat-tests
+
+use super::script::serialize_legacy_tx;
+use super::sighash::SighashType;
+use super::signature_verification::{get_segwit_commitment_hash, p2wpkh_scriptcode};
+use super::sigops::sigops_cost;
+use super::validate_parsing::validate_txid_hash_filename;
+use super::weight_calculation::{serialized_weight, validate_and_set_weight};
+use crate::address::decode_address;
+use crate::parsing::parse_transactions_from_dir;
+use crate::parsing::raw::parse_raw_transaction;
+use crate::parsing::transaction_structs::{InputType, Transaction};
+use bitcoin::consensus::encode::serialize_hex;
+use bitcoin::hashes::Hash;
+use bitcoin::sighash::{EcdsaSighashType, SighashCache};
+use bitcoin::{absolute, transaction, Amount, OutPoint, ScriptBuf, Sequence, Txid, Witness};
+use std::collections::HashMap;
+use std::str::FromStr;
+#[cfg(feature = "taproot")]
+use {
+    super::taproot::{tapleaf_hash, taproot_signature_hash, TAPSCRIPT_LEAF_VERSION},
+    bitcoin::sighash::{Annex, Prevouts, TapSighashType},
+    bitcoin::taproot::{LeafVersion, TapLeafHash},
+};
+
+const FIXTURE_DIRS: [&str; 8] = [
+    "test_scripts/testfiles/current_test",
+    "test_scripts/testfiles/p2pkh",
+    "test_scripts/testfiles/p2sh",
+    "test_scripts/testfiles/scripts",
+    "test_scripts/testfiles/weight",
+    "test_scripts/testfiles/wrapped-segwit",
+    "test_scripts/testfiles/wtxid",
+    "../mempool",
+];
+
+fn decode_script(hex_script: &Option<String>) -> ScriptBuf {
+    match hex_script {
+        Some(script) => ScriptBuf::from_bytes(hex::decode(script).unwrap()),
+        None => ScriptBuf::new(),
+    }
+}
+
+// builds the rust-bitcoin representation straight from the parsed json fields
+fn to_rust_bitcoin(tx: &Transaction) -> bitcoin::Transaction {
+    let input = tx
+        .vin
+        .iter()
+        .map(|txin| bitcoin::TxIn {
+            previous_output: OutPoint {
+                txid: Txid::from_str(&txin.txid).unwrap(),
+                vout: txin.vout,
+            },
+            script_sig: decode_script(&txin.scriptsig),
+            sequence: Sequence(txin.sequence),
+            witness: match &txin.witness {
+                Some(items) => Witness::from_slice(
+                    &items
+                        .iter()
+                        .map(|item| hex::decode(item).unwrap())
+                        .collect::<Vec<Vec<u8>>>(),
+                ),
+                None => Witness::new(),
+            },
+        })
+        .collect();
+    let output = tx
+        .vout
+        .iter()
+        .map(|txout| bitcoin::TxOut {
+            value: Amount::from_sat(txout.value),
+            script_pubkey: decode_script(&txout.scriptpubkey),
+        })
+        .collect();
+
+    bitcoin::Transaction {
+        version: transaction::Version(tx.version),
+        lock_time: absolute::LockTime::from_consensus(tx.locktime),
+        input,
+        output,
+    }
+}
+
+fn load_fixtures() -> Vec<Transaction> {
+    let mut transactions = Vec::new();
+    for dir in FIXTURE_DIRS {
+        for mut tx in parse_transactions_from_dir(dir).expect("Parsing the fixtures failed") {
+            validate_txid_hash_filename(&mut tx);
+            validate_and_set_weight(&mut tx);
+            transactions.push(tx);
+        }
+    }
+    transactions
+}
+
+#[test]
+fn txid_wtxid_and_weight_match_rust_bitcoin() {
+    for tx in load_fixtures() {
+        let reference = to_rust_bitcoin(&tx);
+        let path = tx.meta.json_path.as_ref().unwrap();
+
+        assert_eq!(
+            tx.meta.txid_hex,
+            reference.txid().to_string(),
+            "txid of {}",
+            path
+        );
+        assert_eq!(
+            tx.meta.wtxid_hex,
+            reference.wtxid().to_string(),
+            "wtxid of {}",
+            path
+        );
+        assert_eq!(
+            tx.meta.weight,
+            reference.weight().to_wu(),
+            "weight of {}",
+            path
+        );
+        assert_eq!(
+            serialized_weight(&tx),
+            reference.weight().to_wu(),
+            "serialized weight of {}",
+            path
+        );
+    }
+}
+
+#[test]
+fn raw_transactions_match_rust_bitcoin() {
+    for tx in load_fixtures() {
+        let path = tx.meta.json_path.as_ref().unwrap();
+        let raw_hex = serialize_hex(&to_rust_bitcoin(&tx));
+        assert_eq!(
+            hex::encode(tx.serialize(true)),
+            raw_hex,
+            "raw hex of {}",
+            path
+        );
+        let mut raw_tx = parse_raw_transaction(&raw_hex)
+            .unwrap_or_else(|err| panic!("raw transaction of {}: {}", path, err));
+        validate_txid_hash_filename(&mut raw_tx);
+
+        assert_eq!(raw_tx.meta.txid_hex, tx.meta.txid_hex, "txid of {}", path);
+        assert_eq!(
+            raw_tx.meta.wtxid_hex, tx.meta.wtxid_hex,
+            "wtxid of {}",
+            path
+        );
+        assert_eq!(raw_tx.locktime, tx.locktime, "locktime of {}", path);
+        for (raw_txin, txin) in raw_tx.vin.iter().zip(&tx.vin) {
+            assert_eq!(raw_txin.txid, txin.txid, "input txid of {}", path);
+            assert_eq!(raw_txin.scriptsig, txin.scriptsig, "scriptsig of {}", path);
+            assert_eq!(
+                raw_txin.witness_bytes,
+                txin.witness_bytes
+                    .clone()
+                    .filter(|witness| !witness.is_empty()),
+                "witness of {}",
+                path
+            );
+        }
+        for (raw_txout, txout) in raw_tx.vout.iter().zip(&tx.vout) {
+            assert_eq!(raw_txout.value, txout.value, "output value of {}", path);
+            assert_eq!(
+                raw_txout.scriptpubkey_asm, txout.scriptpubkey_asm,
+                "asm of {}",
+                path
+            );
+            if txout.scriptpubkey_type != "unknown" {
+                assert_eq!(
+                    raw_txout.scriptpubkey_type, txout.scriptpubkey_type,
+                    "output type of {}",
+                    path
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn sigops_cost_matches_rust_bitcoin() {
+    for tx in load_fixtures() {
+        let reference = to_rust_bitcoin(&tx);
+        let spent: HashMap<OutPoint, bitcoin::TxOut> = reference
+            .input
+            .iter()
+            .zip(&tx.vin)
+            .map(|(input, txin)| {
+                let txout = bitcoin::TxOut {
+                    value: Amount::from_sat(txin.prevout.value),
+                    script_pubkey: ScriptBuf::from_bytes(txin.prevout.scriptpubkey_bytes.clone()),
+                };
+                (input.previous_output, txout)
+            })
+            .collect();
+
+        assert_eq!(
+            sigops_cost(&tx),
+            reference.total_sigop_cost(|outpoint| spent.get(outpoint).cloned()) as u64,
+            "sigops cost of {}",
+            tx.meta.json_path.as_ref().unwrap()
+        );
+    }
+}
+
+#[test]
+fn sighash_all_matches_rust_bitcoin() {
+    for tx in load_fixtures() {
+        let reference = to_rust_bitcoin(&tx);
+        let mut cache = SighashCache::new(&reference);
+        let path = tx.meta.json_path.as_ref().unwrap();
+
+        for (index, txin) in tx.vin.iter().enumerate() {
+            let script_pubkey =
+                ScriptBuf::from_bytes(hex::decode(&txin.prevout.scriptpubkey).unwrap());
+            let expected = match txin.in_type {
+                InputType::P2WPKH => cache
+                    .p2wpkh_signature_hash(
+                        index,
+                        &script_pubkey,
+                        Amount::from_sat(txin.prevout.value),
+                        EcdsaSighashType::All,
+                    )
+                    .unwrap()
+                    .to_byte_array()
+                    .to_vec(),
+                InputType::P2PKH => cache
+                    .legacy_signature_hash(index, &script_pubkey, 0x01)
+                    .unwrap()
+                    .to_byte_array()
+                    .to_vec(),
+                _ => continue,
+            };
+            let computed = match txin.in_type {
+                InputType::P2WPKH => get_segwit_commitment_hash(
+                    &tx,
+                    index,
+                    &p2wpkh_scriptcode(&txin.prevout.scriptpubkey_bytes[2..]),
+                    SighashType::All,
+                ),
+                _ => serialize_legacy_tx(
+                    &tx,
+                    index,
+                    &txin.prevout.scriptpubkey_bytes,
+                    SighashType::All,
+                ),
+            };
+            assert_eq!(computed, expected, "sighash of input {} in {}", index, path);
+        }
+    }
+}
+
+// byte identical inputs must still be signed at their own index
+#[test]
+fn sighash_of_duplicated_input_uses_its_index() {
+    let mut tx = load_fixtures()
+        .into_iter()
+        .find(|tx| tx.vin.len() == 1 && tx.vin[0].in_type == InputType::P2PKH)
+        .expect("a single input p2pkh fixture");
+    tx.vin.push(tx.vin[0].clone());
+    let reference = to_rust_bitcoin(&tx);
+    let cache = SighashCache::new(&reference);
+    let script_code = tx.vin[0].prevout.scriptpubkey_bytes.clone();
+
+    for index in 0..2 {
+        let expected = cache
+            .legacy_signature_hash(index, &ScriptBuf::from_bytes(script_code.clone()), 0x01)
+            .unwrap()
+            .to_byte_array()
+            .to_vec();
+        assert_eq!(
+            serialize_legacy_tx(&tx, index, &script_code, SighashType::All),
+            expected,
+            "sighash of input {}",
+            index
+        );
+    }
+}
+
+#[test]
+fn all_standard_sighash_types_match_rust_bitcoin() {
+    for tx in load_fixtures() {
+        let reference = to_rust_bitcoin(&tx);
+        let mut cache = SighashCache::new(&reference);
+        let path = tx.meta.json_path.as_ref().unwrap();
+
+        for (index, txin) in tx.vin.iter().enumerate() {
+            let script_pubkey =
+                ScriptBuf::from_bytes(hex::decode(&txin.prevout.scriptpubkey).unwrap());
+            for sighash in [0x01, 0x02, 0x03, 0x81, 0x82, 0x83] {
+                let sighash_type = SighashType::from_byte(sighash as u8).unwrap();
+                let (computed, expected) = match txin.in_type {
+                    InputType::P2WPKH => (
+                        get_segwit_commitment_hash(
+                            &tx,
+                            index,
+                            &p2wpkh_scriptcode(&txin.prevout.scriptpubkey_bytes[2..]),
+                            sighash_type,
+                        ),
+                        cache
+                            .p2wpkh_signature_hash(
+                                index,
+                                &script_pubkey,
+                                Amount::from_sat(txin.prevout.value),
+                                EcdsaSighashType::from_consensus(sighash),
+                            )
+                            .unwrap()
+                            .to_byte_array()
+                            .to_vec(),
+                    ),
+                    InputType::P2PKH => (
+                        serialize_legacy_tx(
+                            &tx,
+                            index,
+                            &txin.prevout.scriptpubkey_bytes,
+                            sighash_type,
+                        ),
+                        cache
+                            .legacy_signature_hash(index, &script_pubkey, sighash)
+                            .unwrap()
+                            .to_byte_array()
+                            .to_vec(),
+                    ),
+                    _ => continue,
+                };
+                // rust-bitcoin 0.31 applies the SIGHASH_SINGLE out of range quirk only without
+                // ANYONECANPAY, bitcoin core applies it to SINGLE|ANYONECANPAY as well
+                let expected = if txin.in_type == InputType::P2PKH
+                    && sighash == 0x83
+                    && index >= tx.vout.len()
+                {
+                    let mut one = vec![0u8; 32];
+                    one[0] = 1;
+                    one
+                } else {
+                    expected
+                };
+                assert_eq!(
+                    computed, expected,
+                    "sighash {:#04x} of input {} in {}",
+                    sighash, index, path
+                );
+            }
+        }
+    }
+}
+
+#[cfg(feature = "taproot")]
+#[test]
+fn taproot_sighash_types_match_rust_bitcoin() {
+    for tx in load_fixtures() {
+        let reference = to_rust_bitcoin(&tx);
+        let prevouts: Vec<bitcoin::TxOut> = tx
+            .vin
+            .iter()
+            .map(|txin| bitcoin::TxOut {
+                value: Amount::from_sat(txin.prevout.value),
+                script_pubkey: decode_script(&Some(txin.prevout.scriptpubkey.clone())),
+            })
+            .collect();
+        let mut cache = SighashCache::new(&reference);
+        let path = tx.meta.json_path.as_ref().unwrap();
+
+        for (index, txin) in tx.vin.iter().enumerate() {
+            if txin.in_type != InputType::P2TR {
+                continue;
+            }
+            let witness = &reference.input[index].witness;
+            let annex = witness
+                .last()
+                .filter(|last| witness.len() >= 2 && last.first() == Some(&0x50))
+                .map(|annex| Annex::new(annex).unwrap());
+            // script path spends also commit to the leaf hash of the executed script
+            let leaf = witness.tapscript().map(|script| {
+                assert_eq!(
+                    tapleaf_hash(TAPSCRIPT_LEAF_VERSION, script.as_bytes()),
+                    TapLeafHash::from_script(script, LeafVersion::TapScript)
+                        .to_byte_array()
+                        .to_vec(),
+                    "leaf hash of input {} in {}",
+                    index,
+                    path
+                );
+                TapLeafHash::from_script(script, LeafVersion::TapScript)
+            });
+            // without and after an executed OP_CODESEPARATOR at opcode position 2
+            for (sighash, codesep_pos) in [0x00, 0x01, 0x02, 0x03, 0x81, 0x82, 0x83]
+                .into_iter()
+                .flat_map(|sighash| [(sighash, 0xffffffff), (sighash, 2)])
+            {
+                let expected = cache.taproot_signature_hash(
+                    index,
+                    &Prevouts::All(&prevouts),
+                    annex.clone(),
+                    leaf.map(|leaf| (leaf, codesep_pos)),
+                    TapSighashType::from_consensus_u8(sighash).unwrap(),
+                );
+                let computed = taproot_signature_hash(
+                    &tx,
+                    index,
+                    SighashType::from_byte(sighash).unwrap(),
+                    leaf.as_ref()
+                        .map(|leaf| (leaf.as_byte_array().as_slice(), codesep_pos)),
+                );
+                match expected {
+                    Ok(expected) => assert_eq!(
+                        computed.unwrap(),
+                        expected.to_byte_array().to_vec(),
+                        "taproot sighash {:#04x} (codesep {}) of input {} in {}",
+                        sighash,
+                        codesep_pos,
+                        index,
+                        path
+                    ),
+                    // SIGHASH_SINGLE without output at the input index
+                    Err(_) => assert!(computed.is_err()),
+                }
+            }
+        }
+    }
+}
+
+// BIP173 and BIP350 vectors (valid v0, v1 and v16, v0 with bech32m checksum, invalid v0
+// program length, mixed case), regtest and testnet addresses and a broken base58 checksum
+const ADDRESS_VECTORS: [&str; 10] = [
+    "BC1QW508D6QEJXTDG4Y5R3ZARVARY0C5XW7KV8F3T4",
+    "bc1pw508d6qejxtdg4y5r3zarvary0c5xw7kw508d6qejxtdg4y5r3zarvary0c5xw7kt5nd6y",
+    "BC1SW50QGDZ25J",
+    "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kemeawh",
+    "tb1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vq47zagq",
+    "BC1QR508D6QEJXTDG4Y5R3ZARVARYV98GJ9P",
+    "tb1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3q0sL5k7",
+    "bcrt1qs758ursh4q9z627kt3pp5yysm78ddny6txaqgw",
+    "2N2JD6wb56AfK4tfmM6PwdVmoYk2dCKf4Br",
+    "1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN3",
+];
+
+#[test]
+fn addresses_match_rust_bitcoin() {
+    let mut addresses: Vec<String> = ADDRESS_VECTORS.iter().map(|a| a.to_string()).collect();
+    for tx in load_fixtures() {
+        let prevouts = tx
+            .vin
+            .into_iter()
+            .map(|txin| txin.prevout.scriptpubkey_address);
+        let outputs = tx.vout.into_iter().map(|txout| txout.scriptpubkey_address);
+        addresses.extend(prevouts.chain(outputs).flatten());
+    }
+
+    for address in addresses {
+        let expected = bitcoin::Address::from_str(&address)
+            .map(|address| address.assume_checked().script_pubkey().to_bytes());
+        assert_eq!(
+            decode_address(&address).ok(),
+            expected.ok(),
+            "scriptpubkey of {}",
+            address
+        );
+    }
+}
@@ -0,0 +1,131 @@
+// Classification of the valid transactions by whether they can be included in the block
+// described by the ChainContext. The block-valid set can be mined right away, the relay-valid
+// set is valid but not includable yet: a timelock (nLockTime, BIP68) isn't satisfied, an
+// immature coinbase output is spent or a mempool ancestor isn't includable yet.
+
+use crate::chain_context::ChainContext;
+use crate::mining::timelock::{
+    coinbase_spends_mature, is_final, sequence_locks_satisfied, Confirmation,
+};
+use crate::parsing::transaction_structs::Transaction;
+use std::collections::HashMap;
+use std::fmt;
+
+// reason a valid transaction can't be included in the block yet
+#[derive(Debug, Clone, PartialEq)]
+pub enum PendingReason {
+    Locktime,              // nLockTime not reached at the height or MTP
+    SequenceLock,          // BIP68 relative locktime not satisfied
+    ImmatureCoinbase, // spends a coinbase output with less than COINBASE_MATURITY confirmations
+    PendingParent(String), // txid of a mempool parent that isn't includable yet
+}
+
+impl fmt::Display for PendingReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PendingReason::Locktime => write!(f, "Locktime not reached"),
+            PendingReason::SequenceLock => write!(f, "Relative locktime not satisfied"),
+            PendingReason::ImmatureCoinbase => write!(f, "Spends an immature coinbase output"),
+            PendingReason::PendingParent(txid) => write!(f, "Parent {} not includable yet", txid),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PendingTransaction {
+    pub tx: Transaction,
+    pub reason: PendingReason,
+}
+
+// valid transactions split by includability, both keyed by txid
+#[derive(Debug, Default)]
+pub struct IncludabilitySets {
+    pub block_valid: HashMap<String, Transaction>, // includable in the block
+    pub relay_valid: HashMap<String, PendingTransaction>, // valid, not includable yet
+}
+
+// returns: the reason the transaction isn't includable in the block on its own,
+// None if it is includable
+fn pending_reason(
+    tx: &Transaction,
+    mempool: &HashMap<String, Transaction>,
+    confirmed: &HashMap<String, Confirmation>,
+    context: &ChainContext,
+) -> Option<PendingReason> {
+    let old_confirmation = Confirmation {
+        height: 0,
+        median_time_past: Some(0),
+        coinbase: false,
+    };
+    let confirmation = |index: usize| {
+        let parent = &tx.vin[index].txid;
+        match (confirmed.get(parent), context.coinbase_heights.get(parent)) {
+            (Some(confirmation), _) => Some(*confirmation),
+            (None, _) if mempool.contains_key(parent) => None,
+            (None, Some(height)) => Some(Confirmation {
+                height: *height,
+                median_time_past: None,
+                coinbase: true,
+            }),
+            (None, None) => Some(old_confirmation),
+        }
+    };
+    if !is_final(tx, context.height, context.median_time_past) {
+        Some(PendingReason::Locktime)
+    } else if !sequence_locks_satisfied(tx, context.height, context.median_time_past, confirmation)
+    {
+        Some(PendingReason::SequenceLock)
+    } else if !coinbase_spends_mature(tx, context.height, confirmation) {
+        Some(PendingReason::ImmatureCoinbase)
+    } else {
+        None
+    }
+}
+
+// splits the valid transactions into the block-valid and relay-valid set of the block
+// described by the context. confirmed contains the confirmations of spent outputs known to
+// the caller (e.g. of earlier simulated blocks or coinbase outputs), outputs neither in
+// confirmed nor in the transactions are considered confirmed long enough ago, except for the
+// outputs of the coinbase transactions in ChainContext::coinbase_heights.
+// returns: IncludabilitySets, descendants of relay-valid transactions are relay-valid too
+pub fn classify_includable(
+    transactions: HashMap<String, Transaction>,
+    confirmed: &HashMap<String, Confirmation>,
+    context: &ChainContext,
+) -> IncludabilitySets {
+    let mut reasons: HashMap<String, PendingReason> = transactions
+        .values()
+        .filter_map(|tx| {
+            pending_reason(tx, &transactions, confirmed, context)
+                .map(|reason| (tx.meta.txid_hex.clone(), reason))
+        })
+        .collect();
+    // children of pending transactions can't be included either
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for tx in transactions.values() {
+            if reasons.contains_key(&tx.meta.txid_hex) {
+                continue;
+            }
+            if let Some(parent) = tx.vin.iter().find(|txin| reasons.contains_key(&txin.txid)) {
+                reasons.insert(
+                    tx.meta.txid_hex.clone(),
+                    PendingReason::PendingParent(parent.txid.clone()),
+                );
+                changed = true;
+            }
+        }
+    }
+
+    let mut sets = IncludabilitySets::default();
+    for (txid, tx) in transactions {
+        if let Some(reason) = reasons.remove(&txid) {
+            sets.relay_valid
+                .insert(txid, PendingTransaction { tx, reason });
+        } else {
+            sets.block_valid.insert(txid, tx);
+        }
+    }
+    sets
+}
@@ -1,81 +1,352 @@
-mod script;
+#[cfg(all(test, feature = "compat-tests"))]
+mod compat_tests;
+pub mod includability;
+#[cfg(feature = "interpreter")]
+pub mod script;
+pub mod script_asm;
+#[cfg(feature = "interpreter")]
+pub mod script_error;
+pub mod script_flags;
+#[cfg(all(test, feature = "interpreter"))]
+mod script_tests;
+#[cfg(feature = "interpreter")]
+pub mod script_trace;
+pub mod sighash;
+#[cfg(feature = "interpreter")]
+pub mod signature_checker;
+pub mod signature_encoding;
 mod signature_verification;
+pub mod sigops;
+#[cfg(feature = "taproot")]
+pub mod taproot;
+#[cfg(test)]
+mod tests;
 pub mod utils;
 pub mod validate_parsing;
 pub mod validate_values;
+pub mod validation_error;
 pub mod weight_calculation;
+pub mod weight_stats;
 
-use self::signature_verification::{verify_p2pkh, verify_p2wpkh};
-use self::validate_parsing::validate_txid_hash_filename;
-use self::validate_values::{validate_feerate, validate_values_and_set_fee};
-use self::weight_calculation::validate_and_set_weight;
+use self::script_asm::disassemble_core;
+use self::script_flags::ScriptFlags;
+use self::sighash::SighashType;
+#[cfg(feature = "multisig")]
+use self::signature_verification::verify_bare_multisig;
+#[cfg(feature = "interpreter")]
+use self::signature_verification::{verify_p2pk, verify_p2pkh, verify_p2sh};
+use self::signature_verification::{verify_witness_program, witness_program};
+use self::sigops::{sigops_cost, MAX_STANDARD_TX_SIGOPS_COST};
+use self::validate_parsing::{
+    find_prevout_type_mismatch, validate_addresses, validate_txid_hash_filename,
+};
+use self::validate_values::{
+    find_dust_output, validate_coinbase_spends_mature, validate_feerate, validate_locktime_final,
+    validate_no_coinbase_inputs, validate_no_duplicate_inputs, validate_values_and_set_fee,
+    FinalityTarget,
+};
+pub use self::validation_error::ValidationError;
+use self::weight_calculation::{
+    calculate_weight, serialized_weight, validate_and_set_weight, validate_standard_size,
+    validate_witness_consistency,
+};
+use crate::feerate::{vsize, FeeRate};
 use crate::parsing::transaction_structs::{InputType, Transaction};
 
-pub enum ValidationResult {
-    Valid,
-    Invalid(String), // String = reason
+pub const SIGHASH_ALL: u32 = 0x01;
+pub const SIGHASH_NONE: u32 = 0x02;
+pub const SIGHASH_SINGLE: u32 = 0x03;
+pub const SIGHASH_ANYONECANPAY: u32 = 0x80;
+
+// Options to configure how strict the validation is.
+// accepted_sighash_types: signatures with other sighash types make the transaction invalid
+// flags: script verification rules, ScriptFlags::CONSENSUS or ::STANDARD for standardness policy
+// finality: non-final transactions and immature coinbase spends at the target block are
+// invalid, if None they are valid and left to the includability classification
+// reject_dust: dust relay feerate (sat per 1000 vbytes) below which outputs make the
+// transaction invalid, dust is allowed if None
+// standard_size: enforce the policy limits of the transaction weight, the non-witness size and
+// the scriptsig sizes on top of the consensus weight
+// min_relay_feerate: transactions paying a lower feerate are invalid
+#[derive(Debug, Clone)]
+pub struct ValidationOptions {
+    pub accepted_sighash_types: Vec<SighashType>,
+    pub flags: ScriptFlags,
+    pub finality: Option<FinalityTarget>,
+    pub reject_dust: Option<u64>,
+    pub standard_size: bool,
+    pub min_relay_feerate: FeeRate,
+}
+
+// default is the conservative SIGHASH_ALL only under consensus rules
+impl Default for ValidationOptions {
+    fn default() -> Self {
+        ValidationOptions {
+            accepted_sighash_types: vec![SighashType::All],
+            flags: ScriptFlags::CONSENSUS,
+            finality: None,
+            reject_dust: None,
+            standard_size: false,
+            min_relay_feerate: FeeRate::MIN_RELAY,
+        }
+    }
+}
+
+impl ValidationOptions {
+    // accepts all standard sighash types (ALL, NONE, SINGLE, each optionally | ANYONECANPAY)
+    pub fn standard_sighash_types() -> Self {
+        ValidationOptions {
+            accepted_sighash_types: SighashType::DEFINED[1..].to_vec(),
+            flags: ScriptFlags::CONSENSUS,
+            finality: None,
+            reject_dust: None,
+            standard_size: false,
+            min_relay_feerate: FeeRate::MIN_RELAY,
+        }
+    }
+
+    // returns: true if all flags are set in the options
+    pub fn has(&self, flags: ScriptFlags) -> bool {
+        self.flags.contains(flags)
+    }
+
+    // returns: true if sighash is in the set of accepted sighash types
+    pub fn accepts_sighash(&self, sighash: SighashType) -> bool {
+        self.accepted_sighash_types.contains(&sighash)
+    }
+}
+
+// verification support of an input type or script feature in the compiled feature set
+#[derive(Debug, Clone)]
+pub struct Capability {
+    pub name: &'static str,
+    pub feature: Option<&'static str>, // cargo feature the support depends on
+    pub supported: bool,
+}
+
+// returns: capability matrix of the input types and script features with the support of
+// this build, unsupported inputs make the transaction invalid
+pub fn capabilities() -> Vec<Capability> {
+    let interpreter = cfg!(feature = "interpreter");
+    vec![
+        Capability {
+            name: "p2wpkh",
+            feature: None,
+            supported: true,
+        },
+        Capability {
+            name: "p2pkh",
+            feature: Some("interpreter"),
+            supported: interpreter,
+        },
+        Capability {
+            name: "p2pk",
+            feature: Some("interpreter"),
+            supported: interpreter,
+        },
+        Capability {
+            name: "p2sh",
+            feature: Some("interpreter"),
+            supported: interpreter,
+        },
+        Capability {
+            name: "p2sh-p2wpkh",
+            feature: Some("interpreter"),
+            supported: interpreter,
+        },
+        Capability {
+            name: "p2sh-p2wsh",
+            feature: Some("interpreter"),
+            supported: interpreter,
+        },
+        Capability {
+            name: "p2wsh",
+            feature: Some("interpreter"),
+            supported: interpreter,
+        },
+        Capability {
+            name: "multisig",
+            feature: Some("multisig"),
+            supported: cfg!(feature = "multisig"),
+        },
+        Capability {
+            name: "bare multisig",
+            feature: Some("multisig"),
+            supported: cfg!(feature = "multisig"),
+        },
+        Capability {
+            name: "p2tr",
+            feature: Some("taproot"),
+            supported: cfg!(feature = "taproot"),
+        },
+    ]
 }
 
 // Sanity checks to sort out impossible transactions before doing
 // more compute intensive signature verification. Gets called on each Transaction.
-// Also sets weight and fee in the Transaction while calculating it for the checks.
-// returns: ValidationResult
-fn sanity_checks(tx: &mut Transaction) -> ValidationResult {
-    if !validate_values_and_set_fee(tx) {
-        return ValidationResult::Invalid("Values don't add up.".to_string());
-    }
+// Also sets weight, sigops cost and fee in the Transaction while calculating it for the checks.
+// All checks run, the ones depending on a failed fee or weight calculation are skipped.
+// returns: ValidationError of all failed checks in the order of the checks, empty if all passed
+fn sanity_checks(tx: &mut Transaction, options: &ValidationOptions) -> Vec<ValidationError> {
+    let mut failures: Vec<ValidationError> = Vec::new();
+
+    let fee_set = match validate_values_and_set_fee(tx) {
+        Ok(()) => true,
+        Err(error) => {
+            failures.push(ValidationError::Values(error));
+            false
+        }
+    };
     if !validate_txid_hash_filename(tx) {
-        return ValidationResult::Invalid("Txid does not represent filename!".to_string());
+        failures.push(ValidationError::TxidMismatch);
+    }
+    if let Err(error) = validate_addresses(tx) {
+        failures.push(ValidationError::Address(error));
+    }
+    if let Some((index, actual)) = find_prevout_type_mismatch(tx) {
+        failures.push(ValidationError::PrevoutType {
+            index,
+            claimed: tx.vin[index].prevout.scriptpubkey_type.clone(),
+            actual,
+        });
+    }
+    if !validate_no_coinbase_inputs(tx) {
+        failures.push(ValidationError::CoinbaseInput);
+    }
+    if !validate_no_duplicate_inputs(tx) {
+        failures.push(ValidationError::DuplicateInput);
+    }
+    if let Some(target) = &options.finality {
+        if !validate_locktime_final(tx, target) {
+            failures.push(ValidationError::NonFinal {
+                locktime: tx.locktime,
+                height: target.height,
+            });
+        }
+        if !validate_coinbase_spends_mature(tx, target) {
+            failures.push(ValidationError::ImmatureCoinbaseSpend {
+                height: target.height,
+            });
+        }
+    }
+    if let Some(dust_relay_feerate) = options.reject_dust {
+        if let Some((index, threshold)) = find_dust_output(tx, dust_relay_feerate) {
+            failures.push(ValidationError::Dust {
+                index,
+                value: tx.vout[index].value,
+                threshold,
+            });
+        }
+    }
+    if let Err(error) = validate_witness_consistency(tx, options.has(ScriptFlags::WITNESS)) {
+        failures.push(ValidationError::Witness(error));
     }
     if !validate_and_set_weight(tx) {
-        return ValidationResult::Invalid("Transaction weight too high!".to_string());
+        failures.push(ValidationError::WeightExceeded {
+            weight: calculate_weight(tx) as u64,
+        });
+        return failures;
     }
-    if !validate_feerate(tx) {
-        return ValidationResult::Invalid("too low feerate".to_string());
+    let recomputed_weight = serialized_weight(tx);
+    if tx.meta.weight != recomputed_weight {
+        failures.push(ValidationError::WeightMismatch {
+            computed: tx.meta.weight,
+            serialized: recomputed_weight,
+        });
     }
-    ValidationResult::Valid
+    tx.meta.sigops_cost = sigops_cost(tx);
+    if tx.meta.sigops_cost > MAX_STANDARD_TX_SIGOPS_COST {
+        failures.push(ValidationError::SigopsExceeded {
+            sigops_cost: tx.meta.sigops_cost,
+        });
+    }
+    if options.standard_size {
+        if let Err(error) = validate_standard_size(tx) {
+            failures.push(ValidationError::NonStandardSize(error));
+        }
+    }
+    if fee_set && !validate_feerate(tx, options.min_relay_feerate) {
+        failures.push(ValidationError::FeeTooLow {
+            feerate: FeeRate::from_fee_vsize(tx.meta.fee, vsize(tx.meta.weight)),
+            min_relay_feerate: options.min_relay_feerate,
+        });
+    }
+    failures
 }
 
 // takes a transaction and calls the according signature/script verification
-// function on each input. Implemented checks for p2pkh and p2wpkh.
-// returns: ValidationResult
-fn signature_verification(tx: &Transaction) -> ValidationResult {
-    for txin in &tx.vin {
-        let tx_type = &txin.in_type;
-        let result = match tx_type {
-            InputType::P2WPKH => verify_p2wpkh(tx, txin),
-            InputType::P2PKH => verify_p2pkh(tx, txin),
-            _ => {
-                // println!("Unknown type: {:#?}", tx_type);
-                ValidationResult::Invalid("Input type not implemented!".to_string())
+// function on each input. Witness programs are dispatched by their version (p2wpkh, p2wsh,
+// p2tr, future versions), other implemented checks are p2pkh, p2pk, bare multisig and p2sh.
+// Stops at the first failing input unless all_inputs is set.
+// returns: ValidationError::Input of the failed inputs, empty if all are valid
+fn signature_verification(
+    tx: &Transaction,
+    options: &ValidationOptions,
+    all_inputs: bool,
+) -> Vec<ValidationError> {
+    let mut failures: Vec<ValidationError> = Vec::new();
+
+    for (index, txin) in tx.vin.iter().enumerate() {
+        let scriptpubkey = &txin.prevout.scriptpubkey_bytes;
+        let result = match (witness_program(scriptpubkey), &txin.in_type) {
+            (Some((version, program)), _) => {
+                verify_witness_program(tx, index, version, program, false, options)
             }
+            #[cfg(feature = "interpreter")]
+            (None, InputType::P2PKH) => verify_p2pkh(tx, index, options),
+            #[cfg(feature = "interpreter")]
+            (None, InputType::P2SH) => verify_p2sh(tx, index, options),
+            #[cfg(feature = "interpreter")]
+            (None, InputType::P2PK) => verify_p2pk(tx, index, options),
+            #[cfg(feature = "multisig")]
+            (None, InputType::MULTISIG) => verify_bare_multisig(tx, index, options),
+            #[cfg(not(feature = "interpreter"))]
+            (None, InputType::P2PKH | InputType::P2SH | InputType::P2PK) => {
+                Err(ValidationError::UnsupportedInput(Some("interpreter")))
+            }
+            #[cfg(not(feature = "multisig"))]
+            (None, InputType::MULTISIG) => Err(ValidationError::UnsupportedInput(Some("multisig"))),
+            _ => Err(ValidationError::UnsupportedInput(None)),
         };
-        match result {
-            ValidationResult::Valid => (),
-            ValidationResult::Invalid(msg) => {
-                return ValidationResult::Invalid(msg);
+        // the reason references the spent scriptpubkey in bitcoin core's ASM
+        if let Err(error) = result {
+            failures.push(ValidationError::Input {
+                index,
+                scriptpubkey_asm: disassemble_core(&txin.prevout.scriptpubkey_bytes),
+                error: Box::new(error),
+            });
+            if !all_inputs {
+                break;
             }
         }
     }
-    ValidationResult::Valid
+    failures
 }
 
 // implements validate function that does sanity checks and cryptographic verification
-// returns: ValidationResult enum either ::Valid or ::Invalid(reason String)
+// according to the passed ValidationOptions
+// returns: Ok() or Err(ValidationError) of the first failed check
 impl Transaction {
-    pub fn validate(&mut self) -> ValidationResult {
-        match sanity_checks(self) {
-            ValidationResult::Valid => (),
-            ValidationResult::Invalid(msg) => {
-                return ValidationResult::Invalid(msg);
-            }
+    pub fn validate(&mut self, options: &ValidationOptions) -> Result<(), ValidationError> {
+        if let Some(error) = sanity_checks(self, options).into_iter().next() {
+            return Err(error);
         }
-        match signature_verification(self) {
-            ValidationResult::Valid => (),
-            ValidationResult::Invalid(msg) => {
-                return ValidationResult::Invalid(msg);
-            }
+        match signature_verification(self, options, false)
+            .into_iter()
+            .next()
+        {
+            Some(error) => Err(error),
+            None => Ok(()),
         }
-        ValidationResult::Valid
+    }
+
+    // runs all checks like validate() without stopping at the first failure, the signatures of
+    // all inputs are verified even if the sanity checks failed. For auditing rejected
+    // transactions.
+    // returns: ValidationError of all failed checks, empty if the transaction is valid
+    pub fn validate_all(&mut self, options: &ValidationOptions) -> Vec<ValidationError> {
+        let mut failures = sanity_checks(self, options);
+        failures.extend(signature_verification(self, options, true));
+        failures
     }
 }
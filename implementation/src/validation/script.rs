@@ -1,29 +1,57 @@
 use byteorder::{ByteOrder, LittleEndian};
-use core::panic;
 use hex_literal::hex as hexlit;
-use secp256k1::{ecdsa::Signature, Message, PublicKey};
 use std::collections::VecDeque;
-use std::error::Error;
 
-use super::utils::{decode_num, double_hash, get_outpoint, hash160, hash_sha256, varint};
+use super::script_error::ScriptError;
+use super::script_flags::ScriptFlags;
+use super::script_trace::{ScriptTrace, TraceStep};
+use super::sighash::SighashType;
+use super::signature_checker::{InputContext, LegacyChecker, SignatureChecker};
+#[cfg(feature = "taproot")]
+use super::taproot::{op_checksigadd, TapscriptChecker};
+use super::utils::{
+    decode_num, double_hash, encode_num, get_outpoint, hash160, hash_ripemd160, hash_sha1,
+    hash_sha256, varint,
+};
 use super::validate_parsing::serialize_output;
-use crate::parsing::transaction_structs::{InputType, Transaction, TxIn};
+use super::ValidationOptions;
+use crate::parsing::transaction_structs::{Transaction, TxIn};
 
 // Implementation of Script opcodes for use in tx verification
 // The Stack is represented as VecDeque<Vec<u8>>
-// If an opcode returns Err(reason) script execution fails.
+// If an opcode returns Err(ScriptError) script execution fails.
 // Entry is fn evaluate_script()
 
-fn op_swap(stack: &mut VecDeque<Vec<u8>>) -> Result<(), &'static str> {
+// consensus resource limits of script execution, size and opcode limit don't apply to tapscript
+pub const MAX_SCRIPT_SIZE: usize = 10_000;
+pub const MAX_SCRIPT_ELEMENT_SIZE: usize = 520;
+pub const MAX_OPS_PER_SCRIPT: usize = 201;
+pub const MAX_STACK_SIZE: usize = 1000; // stack and altstack combined
+#[cfg(feature = "multisig")]
+pub const MAX_PUBKEYS_PER_MULTISIG: usize = 20;
+#[cfg(feature = "multisig")]
+pub const MAX_BARE_MULTISIG_PUBKEYS: usize = 3; // standardness of multisig scriptpubkeys
+
+// signature message scheme of the executed script: legacy (scriptpubkey and redeem script),
+// BIP143 (witness script of p2wsh) or BIP342 (tapscript leaf of a taproot script path spend)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SigVersion {
+    Base,
+    WitnessV0,
+    #[cfg(feature = "taproot")]
+    Tapscript,
+}
+
+fn op_swap(stack: &mut VecDeque<Vec<u8>>) -> Result<(), ScriptError> {
     if stack.len() >= 2 {
         let len = stack.len();
         stack.swap(len - 1, len - 2);
         return Ok(());
     }
-    Err("OP_SWAP stack < 2")
+    Err(ScriptError::StackUnderflow("OP_SWAP"))
 }
 
-fn op_equal(stack: &mut VecDeque<Vec<u8>>) -> Result<(), &'static str> {
+fn op_equal(stack: &mut VecDeque<Vec<u8>>) -> Result<(), ScriptError> {
     if stack.len() >= 2 {
         let last = &stack.pop_back().expect("Unwrap op_equal");
         let second_last = &stack.pop_back().expect("OP_Equal");
@@ -32,297 +60,404 @@ fn op_equal(stack: &mut VecDeque<Vec<u8>>) -> Result<(), &'static str> {
             return Ok(());
         }
     } else {
-        return Err("OP_EQUAL stack len < 2");
+        return Err(ScriptError::StackUnderflow("OP_EQUAL"));
     }
     stack.push_back(Vec::new());
     Ok(())
 }
 
-fn op_rot(stack: &mut VecDeque<Vec<u8>>) -> Result<(), &'static str> {
+fn op_rot(stack: &mut VecDeque<Vec<u8>>) -> Result<(), ScriptError> {
     if stack.len() >= 3 {
         let third_item = stack.pop_back().expect("OP_ROT pop_back");
         let second_item = stack.pop_back().expect("OP_ROT pop_back");
         let first_item = stack.pop_back().expect("OP_ROT pop_back");
         stack.push_back(second_item);
-        stack.push_back(first_item);
         stack.push_back(third_item);
+        stack.push_back(first_item);
         return Ok(());
     }
-    Err("OP_ROT stack len < 3")
+    Err(ScriptError::StackUnderflow("OP_ROT"))
 }
 
-fn op_size(stack: &mut VecDeque<Vec<u8>>) -> Result<(), &'static str> {
-    if !stack.is_empty() {
-        if let Some(last) = stack.back() {
-            let length = last.len();
-            let length_bytes = length.to_le_bytes().to_vec();
-            stack.push_back(length_bytes);
-            return Ok(());
-        } else {
-            return Err("OP_SIZE getting last element failed");
-        }
+fn op_size(stack: &mut VecDeque<Vec<u8>>) -> Result<(), ScriptError> {
+    if let Some(last) = stack.back() {
+        stack.push_back(encode_num(last.len() as i64));
+        return Ok(());
     }
-    Err("OP_SIZE stack empty")
+    Err(ScriptError::StackUnderflow("OP_SIZE"))
 }
 
-fn op_over(stack: &mut VecDeque<Vec<u8>>) -> Result<(), &'static str> {
+fn op_over(stack: &mut VecDeque<Vec<u8>>) -> Result<(), ScriptError> {
     let stack_len = stack.len();
     if stack_len >= 2 {
-        if let Some(second_element) = stack.get(stack_len - 2) {
-            stack.push_back(second_element.clone());
-            return Ok(());
-        } else {
-            return Err("OP_OVER getting second element failed");
-        }
+        let second_element = stack[stack_len - 2].clone();
+        stack.push_back(second_element);
+        return Ok(());
     }
-    Err("OP_OVER stack < 2")
+    Err(ScriptError::StackUnderflow("OP_OVER"))
 }
 
-fn op_greaterthan(stack: &mut VecDeque<Vec<u8>>) -> Result<(), &'static str> {
-    let stack_size = stack.len();
-    if stack_size >= 2 {
-        if let Some(b) = stack.pop_back() {
-            if let Some(a) = stack.pop_back() {
-                let a = decode_num(&a);
-                let b = decode_num(&b);
-                if a > b {
-                    stack.push_back(vec![1u8]);
-                } else {
-                    stack.push_back(Vec::new());
-                }
-                return Ok(());
-            } else {
-                return Err("OP_GREATERTHAN second element pop failed");
-            }
-        } else {
-            return Err("OP_GREATERTHAN first element pop failed");
-        }
+// OP_2DUP, OP_3DUP, OP_2OVER: copies count items lying depth items below the top onto the top
+fn op_copy_items(
+    stack: &mut VecDeque<Vec<u8>>,
+    depth: usize,
+    count: usize,
+    opcode: &'static str,
+) -> Result<(), ScriptError> {
+    if stack.len() < depth + count {
+        return Err(ScriptError::StackUnderflow(opcode));
     }
-    Err("OP_GREATERTHAN stack < 2")
+    let start = stack.len() - depth - count;
+    for index in start..start + count {
+        stack.push_back(stack[index].clone());
+    }
+    Ok(())
 }
 
-fn op_equalverify(stack: &mut VecDeque<Vec<u8>>) -> Result<(), &'static str> {
-    op_equal(stack)?;
-    if let Some(bool) = stack.pop_back() {
-        if bool.is_empty() {
-            Err("Equalverify false")
-        } else {
-            Ok(())
-        }
-    } else {
-        Err("OP_EQUALVERIFY stack pop failed")
+// OP_2ROT, OP_2SWAP: moves count items lying depth items below the top onto the top
+fn op_move_items(
+    stack: &mut VecDeque<Vec<u8>>,
+    depth: usize,
+    count: usize,
+    opcode: &'static str,
+) -> Result<(), ScriptError> {
+    if stack.len() < depth + count {
+        return Err(ScriptError::StackUnderflow(opcode));
+    }
+    let start = stack.len() - depth - count;
+    for _ in 0..count {
+        let item = stack.remove(start).expect("Stack index checked");
+        stack.push_back(item);
     }
+    Ok(())
 }
 
-fn op_ifdup(stack: &mut VecDeque<Vec<u8>>) -> Result<(), &'static str> {
-    let length = stack.len();
-    if length < 1 {
-        return Err("OP_IFDUP length < 1");
-    };
-    if let Some(last_item) = stack.get(length - 1) {
-        if last_item.is_empty() {
-            return Ok(());
-        } else {
-            stack.push_back(last_item.clone());
-        }
-        Ok(())
-    } else {
-        Err("OP_IFDUP getting last element failed")
+// OP_2DROP
+fn op_2drop(stack: &mut VecDeque<Vec<u8>>) -> Result<(), ScriptError> {
+    if stack.len() < 2 {
+        return Err(ScriptError::StackUnderflow("OP_2DROP"));
+    }
+    stack.truncate(stack.len() - 2);
+    Ok(())
+}
+
+// OP_NIP: x1 x2 -> x2
+fn op_nip(stack: &mut VecDeque<Vec<u8>>) -> Result<(), ScriptError> {
+    if stack.len() < 2 {
+        return Err(ScriptError::StackUnderflow("OP_NIP"));
+    }
+    stack.remove(stack.len() - 2);
+    Ok(())
+}
+
+// OP_TUCK: x1 x2 -> x2 x1 x2
+fn op_tuck(stack: &mut VecDeque<Vec<u8>>) -> Result<(), ScriptError> {
+    if stack.len() < 2 {
+        return Err(ScriptError::StackUnderflow("OP_TUCK"));
     }
+    let top = stack[stack.len() - 1].clone();
+    stack.insert(stack.len() - 2, top);
+    Ok(())
 }
 
-// Marks transaction as invalid if the relative lock time of the input (enforced by BIP 0068 with nSequence)
-// is not equal to or longer than the value of the top stack item. The precise semantics are described in BIP 0112.
-fn op_checksequenceverify(
+// OP_PICK (copy) and OP_ROLL (move): xn ... x0 n -> xn ... x0 xn
+fn op_pick(
     stack: &mut VecDeque<Vec<u8>>,
-    txin: &TxIn,
-    tx: &Transaction,
-) -> Result<(), &'static str> {
-    let sequence = txin.sequence;
-    let disable_flag = 1 << 31;
-    let locktime_mask = 0x0000ffff;
-    let time_flag = 1 << 22;
-    if stack.is_empty() {
-        return Err("OP_CSV stack empty");
+    roll: bool,
+    require_minimal: bool,
+) -> Result<(), ScriptError> {
+    let opcode = if roll { "OP_ROLL" } else { "OP_PICK" };
+    let n = pop_num(stack, require_minimal, opcode)?;
+    if n < 0 || n as usize >= stack.len() {
+        return Err(ScriptError::StackUnderflow(opcode));
+    }
+    let index = stack.len() - 1 - n as usize;
+    let item = if roll {
+        stack.remove(index).expect("Stack index checked")
+    } else {
+        stack[index].clone()
     };
+    stack.push_back(item);
+    Ok(())
+}
 
-    if let Some(locktime_element) = stack.pop_back() {
-        let number = decode_num(&locktime_element);
-        if number < 0 || locktime_element.is_empty() {
-            return Err("OP_CSV number < 0 or empty");
-        };
-        let number = number as u32;
-
-        if (number & disable_flag) == 0 {
-            if tx.version < 2 {
-                return Err("OP_CSV Transaction version is less than 2.");
-            };
-            if (sequence & disable_flag) != 0 {
-                return Err("OP_CSV Transaction input sequence number disable flag is set.");
-            };
-            if (number & time_flag) != (sequence & time_flag) {
-                return Err("OP_CSV Relative lock-time types are not the same.");
-            };
-
-            let locktime_sequence = sequence & locktime_mask;
-            let locktime_stack = number & locktime_mask;
-            if locktime_stack > locktime_sequence {
-                return Err("OP_CSV Stack > Sequence LT");
-            };
-        }
-    } else {
-        return Err("OP_CSV time pop from stack failed.");
+// OP_RIPEMD160, OP_SHA1, OP_SHA256, OP_HASH160, OP_HASH256: replaces the top item by its hash
+fn op_hash(
+    stack: &mut VecDeque<Vec<u8>>,
+    opcode: &'static str,
+    hash: fn(&[u8]) -> Vec<u8>,
+) -> Result<(), ScriptError> {
+    let item = stack
+        .pop_back()
+        .ok_or(ScriptError::StackUnderflow(opcode))?;
+    stack.push_back(hash(&item));
+    Ok(())
+}
+
+// OP_TOALTSTACK / OP_FROMALTSTACK: moves the top item from one stack onto the other
+fn op_altstack(
+    from: &mut VecDeque<Vec<u8>>,
+    to: &mut VecDeque<Vec<u8>>,
+    opcode: &'static str,
+) -> Result<(), ScriptError> {
+    let item = from.pop_back().ok_or(ScriptError::StackUnderflow(opcode))?;
+    to.push_back(item);
+    Ok(())
+}
+
+// numeric operands are limited to max_length bytes (4 for arithmetic, 5 for locktimes),
+// with require_minimal (MINIMALDATA) they have to be encoded in as few bytes as possible
+// returns: decoded script number or ScriptError::InvalidNumber
+fn script_num(
+    element: &[u8],
+    max_length: usize,
+    require_minimal: bool,
+    opcode: &'static str,
+) -> Result<i128, ScriptError> {
+    if element.len() > max_length || (require_minimal && !is_minimal_num(element)) {
+        return Err(ScriptError::InvalidNumber(opcode));
+    }
+    Ok(decode_num(element))
+}
+
+// the most significant byte may only be 0x00 (positive) or 0x80 (negative) if the sign bit
+// is needed by the byte before, zero is the empty vector
+// returns: true if the script number has no unnecessary trailing (most significant) byte
+fn is_minimal_num(element: &[u8]) -> bool {
+    match element {
+        [] => true,
+        [.., last] if last & 0x7f != 0 => true,
+        [_] => false,
+        [.., second_last, _] => second_last & 0x80 != 0,
+    }
+}
+
+// pops the top stack item as arithmetic operand (script number of at most 4 bytes)
+fn pop_num(
+    stack: &mut VecDeque<Vec<u8>>,
+    require_minimal: bool,
+    opcode: &'static str,
+) -> Result<i64, ScriptError> {
+    match stack.pop_back() {
+        Some(element) => Ok(script_num(&element, 4, require_minimal, opcode)? as i64),
+        None => Err(ScriptError::StackUnderflow(opcode)),
     }
+}
+
+// OP_1ADD, OP_1SUB, OP_NEGATE, OP_ABS, OP_NOT, OP_0NOTEQUAL: a -> operation(a)
+fn op_unary_num(
+    stack: &mut VecDeque<Vec<u8>>,
+    require_minimal: bool,
+    opcode: &'static str,
+    operation: fn(i64) -> i64,
+) -> Result<(), ScriptError> {
+    let a = pop_num(stack, require_minimal, opcode)?;
+    stack.push_back(encode_num(operation(a)));
     Ok(())
 }
 
-fn op_checklocktimeverify(
+// OP_ADD, OP_SUB, OP_BOOLAND, OP_BOOLOR, OP_NUMEQUAL, OP_NUMNOTEQUAL, OP_LESSTHAN,
+// OP_GREATERTHAN, OP_LESSTHANOREQUAL, OP_GREATERTHANOREQUAL, OP_MIN, OP_MAX:
+// a b -> operation(a, b), b is the top stack item
+fn op_binary_num(
     stack: &mut VecDeque<Vec<u8>>,
-    tx: &Transaction,
-    txin: &TxIn,
-) -> Result<(), String> {
-    if stack.is_empty() {
-        return Err("OP_CLTV stack empty".to_string());
-    };
-    if let Some(top_item) = stack.pop_back() {
-        let decoded_number = decode_num(&top_item);
+    require_minimal: bool,
+    opcode: &'static str,
+    operation: fn(i64, i64) -> i64,
+) -> Result<(), ScriptError> {
+    if stack.len() < 2 {
+        return Err(ScriptError::StackUnderflow(opcode));
+    }
+    let b = pop_num(stack, require_minimal, opcode)?;
+    let a = pop_num(stack, require_minimal, opcode)?;
+    stack.push_back(encode_num(operation(a, b)));
+    Ok(())
+}
 
-        if decoded_number < 0 {
-            return Err("OP_CLTV number < 0".to_string());
-        };
-        let decoded_number: u32 = decoded_number as u32;
-        if (decoded_number < 500000000 && tx.locktime > 500000000)
-            || (decoded_number > 500000000 && tx.locktime < 500000000)
-        {
-            return Err("OP_CLTV different locktime types".to_string());
-        }
-        if tx.locktime < decoded_number {
-            return Err(format!(
-                "OP_CLTV locktime {} < {} stack num.",
-                tx.locktime, decoded_number
-            ));
-        }
-        if txin.sequence == 0xffffffff {
-            return Err("OP_CLTV in sequence is 0xffffffff".to_string());
-        }
-    } else {
-        return Err("OP_CLTV pop item failed".to_string());
-    };
+// OP_WITHIN: x min max -> 1 if min <= x < max, otherwise 0
+fn op_within(stack: &mut VecDeque<Vec<u8>>, require_minimal: bool) -> Result<(), ScriptError> {
+    if stack.len() < 3 {
+        return Err(ScriptError::StackUnderflow("OP_WITHIN"));
+    }
+    let max = pop_num(stack, require_minimal, "OP_WITHIN")?;
+    let min = pop_num(stack, require_minimal, "OP_WITHIN")?;
+    let x = pop_num(stack, require_minimal, "OP_WITHIN")?;
+    stack.push_back(encode_num((min <= x && x < max) as i64));
     Ok(())
 }
 
+fn op_equalverify(stack: &mut VecDeque<Vec<u8>>) -> Result<(), ScriptError> {
+    op_equal(stack).map_err(|_| ScriptError::StackUnderflow("OP_EQUALVERIFY"))?;
+    match stack.pop_back() {
+        Some(bool) if bool.is_empty() => Err(ScriptError::VerifyFailed("OP_EQUALVERIFY")),
+        _ => Ok(()),
+    }
+}
+
+fn op_ifdup(stack: &mut VecDeque<Vec<u8>>) -> Result<(), ScriptError> {
+    match stack.back() {
+        Some(last_item) if last_item.is_empty() => Ok(()),
+        Some(last_item) => {
+            stack.push_back(last_item.clone());
+            Ok(())
+        }
+        None => Err(ScriptError::StackUnderflow("OP_IFDUP")),
+    }
+}
+
+// operand of OP_CHECKLOCKTIMEVERIFY and OP_CHECKSEQUENCEVERIFY, the top stack item stays on the
+// stack (usually followed by OP_DROP)
+// returns: top stack item as script number of up to 5 bytes (locktimes above 2^31 - 1)
+fn peek_locktime(
+    stack: &VecDeque<Vec<u8>>,
+    require_minimal: bool,
+    opcode: &'static str,
+) -> Result<i128, ScriptError> {
+    let top_item = stack.back().ok_or(ScriptError::StackUnderflow(opcode))?;
+    script_num(top_item, 5, require_minimal, opcode)
+}
+
+// message signed by SIGHASH_SINGLE signatures of inputs without an output at the same index
+// (uint256 1, consensus quirk of the original implementation)
+const SIGHASH_SINGLE_BUG_HASH: [u8; 32] =
+    hexlit!("0100000000000000000000000000000000000000000000000000000000000000");
+
 // serializes input of legacy transaction into Vec<u8>
-// all inputs except the one that is being verified (parameter) will be returned as 0x00
+// the input that is being verified (signing) contains the script_code, all other inputs
+// will be returned as 0x00, with SIGHASH_NONE and SIGHASH_SINGLE their sequence is zeroed as well
 // returns: byte serialized input as Vec<u8>
-fn serialize_input_legacy(input: &TxIn, signing_txin: &TxIn) -> Vec<u8> {
+fn serialize_input_legacy(
+    input: &TxIn,
+    signing: bool,
+    script_code: &[u8],
+    sighash: SighashType,
+) -> Vec<u8> {
     let mut serialized_input = get_outpoint(input);
+    let base_type = sighash.base_type();
 
-    if input == signing_txin {
-        let scriptpubkey_len = varint(
-            hex::decode(&signing_txin.prevout.scriptpubkey)
-                .expect("serialize_input_legacy hex encoding")
-                .len() as u128,
-        );
-        serialized_input.extend(scriptpubkey_len);
-        serialized_input.extend(
-            hex::decode(&signing_txin.prevout.scriptpubkey)
-                .expect("OP_CHECKSIG scriptpubkey hex decode failed"),
-        );
+    if signing {
+        serialized_input.extend(varint(script_code.len() as u128));
+        serialized_input.extend(script_code);
+        serialized_input.extend(input.sequence.to_le_bytes());
     } else {
         serialized_input.extend(hexlit!("00"));
+        if base_type != SighashType::All {
+            serialized_input.extend(0u32.to_le_bytes());
+        } else {
+            serialized_input.extend(input.sequence.to_le_bytes());
+        }
     }
-    serialized_input.extend(input.sequence.to_le_bytes());
     serialized_input
 }
 
-// Serialize legacy transaction (non segwit) for signature verification of specified input
+// Serialize legacy transaction (non segwit) for signature verification of specified input.
+// SIGHASH_NONE commits to no outputs, SIGHASH_SINGLE only to the output at the input index
+// (the outputs in front of it are serialized as empty outputs with value -1),
+// with SIGHASH_ANYONECANPAY only the signing input is serialized. The script_code of the signing
+// input is the scriptpubkey, or the redeem script for P2SH inputs.
 // returns: double SHA256 digest of serialized transaction
-fn serialize_legacy_tx(tx: &Transaction, signing_txin: &TxIn, sighash: u32) -> Vec<u8> {
+pub(crate) fn serialize_legacy_tx(
+    tx: &Transaction,
+    input_index: usize,
+    script_code: &[u8],
+    sighash: SighashType,
+) -> Vec<u8> {
     let mut preimage: Vec<u8> = Vec::new();
+    let base_type = sighash.base_type();
 
+    if base_type == SighashType::Single && input_index >= tx.vout.len() {
+        return SIGHASH_SINGLE_BUG_HASH.to_vec();
+    }
     preimage.extend(&tx.version.to_le_bytes()); // VERSION
-    preimage.extend(varint(tx.vin.len() as u128)); // INPUT amount
-    for tx_in in &tx.vin {
-        preimage.append(&mut serialize_input_legacy(tx_in, signing_txin));
+    if sighash.anyonecanpay() {
+        preimage.extend(varint(1)); // INPUT amount
+        preimage.append(&mut serialize_input_legacy(
+            &tx.vin[input_index],
+            true,
+            script_code,
+            sighash,
+        ));
+    } else {
+        preimage.extend(varint(tx.vin.len() as u128)); // INPUT amount
+        for (index, tx_in) in tx.vin.iter().enumerate() {
+            preimage.append(&mut serialize_input_legacy(
+                tx_in,
+                index == input_index,
+                script_code,
+                sighash,
+            ));
+        }
     }
-    preimage.extend(varint(tx.vout.len() as u128)); // Output amount
-    for tx_out in &tx.vout {
-        preimage.append(&mut serialize_output(tx_out));
+    match base_type {
+        SighashType::None => preimage.extend(varint(0)),
+        SighashType::Single => {
+            preimage.extend(varint(input_index as u128 + 1)); // Output amount
+            for _ in 0..input_index {
+                preimage.extend(hexlit!("ffffffffffffffff00")); // value -1, empty script
+            }
+            preimage.append(&mut serialize_output(&tx.vout[input_index]));
+        }
+        _ => {
+            preimage.extend(varint(tx.vout.len() as u128)); // Output amount
+            for tx_out in &tx.vout {
+                preimage.append(&mut serialize_output(tx_out));
+            }
+        }
     }
     preimage.extend(tx.locktime.to_le_bytes());
-    preimage.extend(sighash.to_le_bytes());
+    preimage.extend((sighash.to_byte() as u32).to_le_bytes());
     double_hash(&preimage)
 }
 
-// Verify DER encoded signature against message and pubkey
-fn verify_sig_op_checksig(msg: &[u8], pubkey: &[u8], sig: &[u8]) -> Result<(), String> {
-    let sig = Signature::from_der(sig);
-    let mut sig = match sig {
-        Ok(value) => value,
-        Err(err) => {
-            return Err(format!("Loading DER encoded signature failed: {}", err));
+// returns: the script without its OP_CODESEPARATOR opcodes (pushed data is kept)
+pub(crate) fn remove_codeseparators(script: &[u8]) -> Vec<u8> {
+    let mut stripped = Vec::with_capacity(script.len());
+    let mut index = 0;
+
+    while index < script.len() {
+        let start = index;
+        if skip_push(&mut index, script).is_err() {
+            // a truncated push can't be executed, keep the remaining bytes
+            stripped.extend(&script[start..]);
+            break;
+        }
+        index += 1;
+        if script[start] != 0xab {
+            stripped.extend(&script[start..index]);
         }
-    };
-    Signature::normalize_s(&mut sig);
-    let msg: [u8; 32] = msg.try_into().expect("Commitment hash is not 32 byte!");
-    let msg = Message::from_digest(msg);
-    let pubkey = PublicKey::from_slice(pubkey).expect("Pubkey invalid!");
-    let result = sig.verify(&msg, &pubkey);
-    match result {
-        Ok(_) => Ok(()),
-        Err(err) => Err(format!("Signature verification failed: {}", err)),
     }
+    stripped
 }
 
-// implemented for non-witness transactions and SIGHASH_ALL only
-fn op_checksig(stack: &mut VecDeque<Vec<u8>>, tx: &Transaction, txin: &TxIn) -> Result<(), String> {
+// OP_CHECKSIG: sig pubkey -> true if the SignatureChecker accepts the signature, the
+// empty vector if not. script_code and codesep_pos are what the signature commits to.
+fn op_checksig(
+    stack: &mut VecDeque<Vec<u8>>,
+    checker: &dyn SignatureChecker,
+    script_code: &[u8],
+    codesep_pos: u32,
+) -> Result<(), ScriptError> {
     if stack.len() < 2 {
-        return Err("OP_CHECKSIG stack < 2".to_string());
-    };
-    let pubkey = if let Some(pubkey) = stack.pop_back() {
-        pubkey
-    } else {
-        return Err("OP_CHECKSIG popping pubkey from stack failed!".to_string());
-    };
-    let mut der_signature = if let Some(signature) = stack.pop_back() {
-        signature
-    } else {
-        return Err("OP_CHECKSIG popping signature from stack failed!".to_string());
-    };
-    let sighash: u32 = if let Some(sighash_byte) = der_signature.pop() {
-        sighash_byte as u32
-    } else {
-        return Err("OP_CHECKSIG popping sighash from signature failed".to_string());
+        return Err(ScriptError::StackUnderflow("OP_CHECKSIG"));
     };
-    if sighash != 0x00000001 {
-        // SIGHASH_ALL
-        return Err("sighash not implemented".to_string());
-    }
-    let message = match txin.in_type {
-        InputType::P2PKH => serialize_legacy_tx(tx, txin, sighash),
-        InputType::P2SH => serialize_legacy_tx(tx, txin, sighash),
-        _ => panic!("op_checksig unsupported txtype"),
-    };
-    match verify_sig_op_checksig(&message, &pubkey, &der_signature) {
-        Ok(_) => stack.push_back(vec![1u8]),
-        Err(_) => stack.push_back(vec![]),
-    }
+    let pubkey = stack.pop_back().expect("OP_CHECKSIG pop_back");
+    let signature = stack.pop_back().expect("OP_CHECKSIG pop_back");
+    let success = checker.check_sig(&signature, &pubkey, script_code, codesep_pos)?;
+    stack.push_back(if success { vec![1u8] } else { vec![] });
     Ok(())
 }
 
-fn op_verify(stack: &mut VecDeque<Vec<u8>>) -> Result<(), &'static str> {
-    if let Some(top_stack_element) = stack.pop_back() {
-        if top_stack_element.is_empty() {
-            Err("OP_VERIFY not valid")
-        } else {
-            Ok(())
+fn op_verify(stack: &mut VecDeque<Vec<u8>>, opcode: &'static str) -> Result<(), ScriptError> {
+    match stack.pop_back() {
+        Some(top_stack_element) if top_stack_element.is_empty() => {
+            Err(ScriptError::VerifyFailed(opcode))
         }
-    } else {
-        Err("OP_VERIFY popping top stack element failed")
+        Some(_) => Ok(()),
+        None => Err(ScriptError::StackUnderflow(opcode)),
     }
 }
 
-fn op_pushnum(stack: &mut VecDeque<Vec<u8>>, amount: u8) -> Result<(), &'static str> {
+fn op_pushnum(stack: &mut VecDeque<Vec<u8>>, amount: u8) -> Result<(), ScriptError> {
     let number: u8 = amount - 80;
     let number_bytes: Vec<u8> = vec![number];
     stack.push_back(number_bytes);
@@ -333,35 +468,33 @@ fn op_pushbytes(
     stack: &mut VecDeque<Vec<u8>>,
     index: &mut usize,
     script: &[u8],
-) -> Result<(), &'static str> {
+) -> Result<(), ScriptError> {
     let opcode: u8 = script[*index];
-    let mut bytes: Vec<u8> = Vec::new();
 
-    if *index + opcode as usize <= script.len() {
-        bytes.resize(opcode as usize, 0);
-        bytes.clone_from_slice(&script[*index + 1..*index + 1 + opcode as usize]);
-        stack.push_back(bytes);
+    if *index + (opcode as usize) < script.len() {
+        stack.push_back(script[*index + 1..*index + 1 + opcode as usize].to_vec());
         *index += opcode as usize;
     } else {
-        return Err("OP_PUSHBYTES opcode out of range");
+        return Err(ScriptError::InvalidPush);
     }
     Ok(())
 }
 
+// reads the little endian length (amount_bytes long) of the OP_PUSHDATA at current_index
+// returns: length of the pushed data or ScriptError::InvalidPush if it runs out of the script
 pub fn get_pushdata_amount(
     script: &[u8],
     amount_bytes: u8,
     current_index: usize,
-) -> Result<u32, &'static str> {
-    let mut amount_of_bytes_to_push: Vec<u8> = vec![0; amount_bytes as usize];
-
-    amount_of_bytes_to_push
-        .clone_from_slice(&script[current_index + 1..current_index + 1 + amount_bytes as usize]);
+) -> Result<u32, ScriptError> {
+    let length_bytes = script
+        .get(current_index + 1..current_index + 1 + amount_bytes as usize)
+        .ok_or(ScriptError::InvalidPush)?;
     match amount_bytes {
-        1 => Ok(amount_of_bytes_to_push[0] as u32),
-        2 => Ok(LittleEndian::read_u16(&amount_of_bytes_to_push) as u32),
-        4 => Ok(LittleEndian::read_u32(&amount_of_bytes_to_push)),
-        _ => Err("get_pushdata_amount weird amount in match"),
+        1 => Ok(length_bytes[0] as u32),
+        2 => Ok(LittleEndian::read_u16(length_bytes) as u32),
+        4 => Ok(LittleEndian::read_u32(length_bytes)),
+        _ => Err(ScriptError::InvalidPush),
     }
 }
 
@@ -370,177 +503,617 @@ fn op_pushdata(
     amount_bytes: u8,
     index: &mut usize,
     script: &[u8],
-) -> Result<(), &'static str> {
-    let mut data_push: Vec<u8> = Vec::new();
-
-    let amount_of_bytes_to_push = get_pushdata_amount(script, amount_bytes, *index)?;
-    *index += amount_bytes as usize + 1;
-    data_push.resize(amount_of_bytes_to_push as usize, 0);
-    data_push.clone_from_slice(&script[*index..*index + amount_of_bytes_to_push as usize]);
-    stack.push_back(data_push);
-    *index += amount_of_bytes_to_push as usize - 1;
+) -> Result<(), ScriptError> {
+    let amount_of_bytes_to_push = get_pushdata_amount(script, amount_bytes, *index)? as usize;
+    if amount_of_bytes_to_push > MAX_SCRIPT_ELEMENT_SIZE {
+        return Err(ScriptError::PushSize(amount_of_bytes_to_push));
+    }
+    let data_start = *index + amount_bytes as usize + 1;
+    let data_push = script
+        .get(data_start..data_start + amount_of_bytes_to_push)
+        .ok_or(ScriptError::InvalidPush)?;
+    stack.push_back(data_push.to_vec());
+    // index points to the last byte of the push, it is incremented after the opcode
+    *index = data_start + amount_of_bytes_to_push - 1;
     Ok(())
 }
 
-fn op_depth(stack: &mut VecDeque<Vec<u8>>) -> Result<(), &'static str> {
-    stack.push_back(varint(stack.len() as u128));
-    Ok(())
+// skips the data of a push opcode at index without pushing it (in a non executed branch)
+// returns: length of the skipped push data, 0 for other opcodes
+fn skip_push(index: &mut usize, script: &[u8]) -> Result<usize, ScriptError> {
+    let opcode = script[*index];
+    let push_length = match opcode {
+        0x01..=0x4b => opcode as usize,
+        0x4c..=0x4e => {
+            let amount_bytes = 1 << (opcode - 0x4c);
+            let amount = get_pushdata_amount(script, amount_bytes, *index)? as usize;
+            *index += amount_bytes as usize;
+            amount
+        }
+        _ => 0,
+    };
+    if *index + push_length >= script.len() {
+        return Err(ScriptError::InvalidPush);
+    }
+    *index += push_length;
+    Ok(push_length)
 }
 
-// NULL sig1 sig2 ... <number of signatures> pub1 pub2 <number of public keys>
-fn op_checkmultisig(
+// returns: true if the data is pushed with the smallest possible push opcode
+fn is_minimal_push(opcode: u8, data: &[u8]) -> bool {
+    match data {
+        [] => false,                                  // OP_0
+        [value] if (1..=16).contains(value) => false, // OP_1 to OP_16
+        [0x81] => false,                              // OP_1NEGATE
+        _ if data.len() <= 75 => opcode as usize == data.len(),
+        _ if data.len() <= 255 => opcode == 0x4c,
+        _ if data.len() <= 65535 => opcode == 0x4d,
+        _ => true,
+    }
+}
+
+// returns: false for empty byte vectors, zero and negative zero, true otherwise
+pub(crate) fn cast_to_bool(element: &[u8]) -> bool {
+    for (i, byte) in element.iter().enumerate() {
+        if *byte != 0 {
+            // negative zero
+            return !(i == element.len() - 1 && *byte == 0x80);
+        }
+    }
+    false
+}
+
+// OP_IF / OP_NOTIF: pushes the branch condition onto the condition stack. In a non executed
+// branch nothing is popped and the nested branch is not executed either. With minimal_if the
+// condition has to be exactly empty (false) or 0x01 (true).
+fn op_if(
     stack: &mut VecDeque<Vec<u8>>,
-    tx: &Transaction,
-    txin: &TxIn,
-) -> Result<(), &'static str> {
-    let mut signatures: VecDeque<Vec<u8>> = VecDeque::new();
-    let mut pubkeys: VecDeque<Vec<u8>> = VecDeque::new();
-    let number_of_pubkeys;
-    let mut number_of_signatures;
-
-    if let Some(pubkey_amount) = stack.pop_back() {
-        number_of_pubkeys = pubkey_amount[0]; // should be enough for these scripts
-        for _ in 0..number_of_pubkeys {
-            if let Some(pubkey) = stack.pop_back() {
-                pubkeys.push_back(pubkey);
-            } else {
-                return Err("OP_CHECKMULTISIG error popping pubkey from stack");
-            };
+    condition_stack: &mut Vec<bool>,
+    executing: bool,
+    not_if: bool,
+    minimal_if: bool,
+) -> Result<(), ScriptError> {
+    let mut condition = false;
+    if executing {
+        match stack.pop_back() {
+            Some(top) if minimal_if && !matches!(top.as_slice(), [] | [1]) => {
+                return Err(ScriptError::MinimalIf)
+            }
+            Some(top_stack_element) => condition = cast_to_bool(&top_stack_element) != not_if,
+            None => return Err(ScriptError::StackUnderflow("OP_IF")),
         }
-    } else {
-        return Err("OP_CHECKMULTISIG error popping number of pubkeys");
-    };
-    if let Some(signature_amount) = stack.pop_back() {
-        number_of_signatures = signature_amount[0];
-        for _ in 0..number_of_signatures {
-            if let Some(signature) = stack.pop_back() {
-                signatures.push_front(signature);
-            } else {
-                return Err("OP_CHECKMULTISIG error popping signature from stack");
-            };
+    }
+    condition_stack.push(condition);
+    Ok(())
+}
+
+fn op_else(condition_stack: &mut [bool]) -> Result<(), ScriptError> {
+    match condition_stack.last_mut() {
+        Some(condition) => {
+            *condition = !*condition;
+            Ok(())
         }
-        stack.pop_back(); // OP_CHECKMULTISIG BUG
-    } else {
-        return Err("OP_CHECKMULTISIG error popping number of signatures");
-    };
+        None => Err(ScriptError::UnbalancedConditional),
+    }
+}
 
-    'outer: for mut signature in signatures {
-        let mut retry = true;
+fn op_endif(condition_stack: &mut Vec<bool>) -> Result<(), ScriptError> {
+    match condition_stack.pop() {
+        Some(_) => Ok(()),
+        None => Err(ScriptError::UnbalancedConditional),
+    }
+}
 
-        let sighash: u32 = if let Some(sighash_byte) = signature.pop() {
-            sighash_byte as u32
-        } else {
-            return Err("OP_CHECKSIG popping sighash from signature failed");
-        };
-        if sighash != 0x00000001 {
-            // IMPLEMENT OTHER SIGHASH TYPES
-            return Err("OP_CHECKMULTISIG sighash not implemented");
-        };
-        let message = match txin.in_type {
-            InputType::P2SH => serialize_legacy_tx(tx, txin, sighash),
-            _ => panic!("op_checkmultisig unsupported txtype"),
-        };
+fn op_depth(stack: &mut VecDeque<Vec<u8>>) -> Result<(), ScriptError> {
+    stack.push_back(encode_num(stack.len() as i64));
+    Ok(())
+}
 
-        while retry {
-            if let Some(pubkey) = pubkeys.pop_back() {
-                retry = false;
-                match verify_sig_op_checksig(&message, &pubkey, &signature) {
-                    Ok(_) => {
-                        number_of_signatures -= 1;
-                    }
-                    Err(err) => {
-                        println!("{}", err);
-                        retry = true;
-                    }
-                };
-            } else {
-                break 'outer;
-            };
-        }
+// NULL sig1 sig2 ... <number of signatures> pub1 pub2 <number of public keys>
+// 0 to MAX_PUBKEYS_PER_MULTISIG public keys and 0 to number of public keys signatures, the
+// public keys count against the opcode limit of the script (op_count). With
+// ScriptFlags::BARE_MULTISIG a bare multisig scriptpubkey may only have MAX_BARE_MULTISIG_PUBKEYS keys
+#[cfg(feature = "multisig")]
+fn op_checkmultisig(
+    stack: &mut VecDeque<Vec<u8>>,
+    op_count: &mut usize,
+    checker: &dyn SignatureChecker,
+    script_code: &[u8],
+) -> Result<(), ScriptError> {
+    let InputContext { txin, options, .. } = checker.context();
+    let underflow = ScriptError::StackUnderflow("OP_CHECKMULTISIG");
+
+    let pubkey_amount = stack.pop_back().ok_or(underflow.clone())?;
+    let number_of_pubkeys = script_num(
+        &pubkey_amount,
+        4,
+        options.has(ScriptFlags::MINIMALDATA),
+        "OP_CHECKMULTISIG",
+    )?;
+    if number_of_pubkeys < 0 || number_of_pubkeys > MAX_PUBKEYS_PER_MULTISIG as i128 {
+        return Err(ScriptError::PubkeyCount(number_of_pubkeys));
     }
-    if number_of_signatures == 0 {
-        stack.push_back(vec![1u8]);
-    } else {
-        stack.push_back(vec![]);
-    };
+    let pubkey_count = number_of_pubkeys as usize;
+    let bare_multisig =
+        checker.sig_version() == SigVersion::Base && script_code == txin.prevout.scriptpubkey_bytes;
+    if options.has(ScriptFlags::BARE_MULTISIG)
+        && bare_multisig
+        && pubkey_count > MAX_BARE_MULTISIG_PUBKEYS
+    {
+        return Err(ScriptError::PubkeyCount(number_of_pubkeys));
+    }
+    *op_count += pubkey_count;
+    if *op_count > MAX_OPS_PER_SCRIPT {
+        return Err(ScriptError::OpCount);
+    }
+    if stack.len() < pubkey_count {
+        return Err(underflow);
+    }
+    let pubkeys: Vec<Vec<u8>> = stack.split_off(stack.len() - pubkey_count).into();
+    let signature_amount = stack.pop_back().ok_or(underflow.clone())?;
+    let number_of_signatures = script_num(
+        &signature_amount,
+        4,
+        options.has(ScriptFlags::MINIMALDATA),
+        "OP_CHECKMULTISIG",
+    )?;
+    if number_of_signatures < 0 || number_of_signatures > number_of_pubkeys {
+        return Err(ScriptError::SigCount(number_of_signatures));
+    }
+    if stack.len() < number_of_signatures as usize {
+        return Err(underflow);
+    }
+    let signatures: Vec<Vec<u8>> = stack
+        .split_off(stack.len() - number_of_signatures as usize)
+        .into();
+    // OP_CHECKMULTISIG BUG: one extra element is consumed, with NULLDUMMY (BIP147) it has to be
+    // empty so it can't be malleated
+    let dummy = stack.pop_back().ok_or(underflow)?;
+    if options.has(ScriptFlags::NULLDUMMY) && !dummy.is_empty() {
+        return Err(ScriptError::NullDummy);
+    }
+
+    let success = checker.check_multisig(&signatures, &pubkeys, script_code)?;
+    stack.push_back(if success { vec![1u8] } else { vec![] });
     Ok(())
 }
 
-// main script interpretion function
-// executes the script argument and returns Ok() if the script is valid and True
-pub fn evaluate_script(
-    script: Vec<u8>,
-    txin: &TxIn,
-    tx: &Transaction,
-) -> Result<(), Box<dyn Error>> {
-    let mut stack: VecDeque<Vec<u8>> = VecDeque::new();
+// Script interpreter of an input: executes scripts on a stack under the flags of the input
+// options and returns the resulting stack, so the P2SH, P2WSH and taproot flows can chain
+// executions (e.g. scriptsig, then redeem script on the resulting stack). The SignatureChecker
+// selects the script context (legacy, segwit v0, tapscript) and checks the signatures and
+// locktimes, custom checkers can e.g. accept placeholder signatures.
+pub struct ScriptInterpreter<'a> {
+    pub checker: &'a dyn SignatureChecker,
+}
+
+impl<'a> ScriptInterpreter<'a> {
+    pub fn new(checker: &'a dyn SignatureChecker) -> Self {
+        ScriptInterpreter { checker }
+    }
+
+    // executes the script on the stack, signatures are checked against the script_code
+    // (scriptpubkey, redeem script or witness script of the input, the trailing part of the
+    // executed script). After an OP_CODESEPARATOR legacy and segwit v0 signatures only commit
+    // to the script code behind it, tapscript signatures to its opcode position.
+    // returns: stack after the script or the ScriptError it failed with
+    pub fn execute(
+        &self,
+        script: &[u8],
+        script_code: &[u8],
+        mut stack: VecDeque<Vec<u8>>,
+    ) -> Result<VecDeque<Vec<u8>>, ScriptError> {
+        execute_script_traced(self.checker, script, &mut stack, script_code, None)?;
+        Ok(stack)
+    }
+}
+
+// executes the script like ScriptInterpreter::execute() on the passed stack and records every
+// opcode with the stack around it into the trace
+fn execute_script_traced(
+    checker: &dyn SignatureChecker,
+    script: &[u8],
+    stack: &mut VecDeque<Vec<u8>>,
+    script_code: &[u8],
+    mut trace: Option<&mut ScriptTrace>,
+) -> Result<(), ScriptError> {
+    let options = checker.context().options;
+    let sig_version = checker.sig_version();
+    let mut condition_stack: Vec<bool> = Vec::new(); // one entry per open OP_IF
+    let mut altstack: VecDeque<Vec<u8>> = VecDeque::new();
     let mut index = 0;
+    let mut op_count = 0;
+    let mut opcode_position: u32 = 0;
+    let tapscript = is_tapscript(sig_version);
+    let mut signed_code = script_code; // script code behind the last OP_CODESEPARATOR
+    let mut codesep_pos = u32::MAX;
+    let require_minimal = options.has(ScriptFlags::MINIMALDATA);
+    // policy for witness scripts, consensus for tapscripts (BIP342)
+    let minimal_if =
+        tapscript || (sig_version == SigVersion::WitnessV0 && options.has(ScriptFlags::MINIMALIF));
 
+    if !tapscript && script.len() > MAX_SCRIPT_SIZE {
+        return Err(ScriptError::ScriptSize(script.len()));
+    }
+    // the initial stack can come from the witness which isn't checked by a previous execution
+    if let Some(element) = stack.iter().find(|e| e.len() > MAX_SCRIPT_ELEMENT_SIZE) {
+        return Err(ScriptError::PushSize(element.len()));
+    }
+    if stack.len() > MAX_STACK_SIZE {
+        return Err(ScriptError::StackSize);
+    }
     while index < script.len() {
         let opcode = script[index];
+        let executing = !condition_stack.contains(&false);
+        if let Some(trace) = trace.as_deref_mut() {
+            trace.steps.push(TraceStep {
+                position: index,
+                opcode,
+                executed: executing,
+                stack_before: stack.iter().cloned().collect(),
+                stack_after: None,
+            });
+        }
+        // non-push opcodes count against the limit in non executed branches as well
+        if opcode > 0x60 && !tapscript {
+            op_count += 1;
+            if op_count > MAX_OPS_PER_SCRIPT {
+                return Err(ScriptError::OpCount);
+            }
+        }
+        if let Some(name) = disabled_opcode(opcode) {
+            return Err(ScriptError::DisabledOpcode(name));
+        }
         match opcode {
-            0xa8 => {
-                // SHA256
-                if let Some(last) = stack.pop_back() {
-                    stack.push_back(hash_sha256(&last));
-                } else {
-                    return Err("OP_SHA256 stack empty".into());
+            0x63 => op_if(stack, &mut condition_stack, executing, false, minimal_if)?, // OP_IF
+            0x64 => op_if(stack, &mut condition_stack, executing, true, minimal_if)?,  // OP_NOTIF
+            0x67 => op_else(&mut condition_stack)?,                                    // OP_ELSE
+            0x68 => op_endif(&mut condition_stack)?,                                   // OP_ENDIF
+            _ if !executing => {
+                // opcodes of non executed branches, oversized pushes fail nevertheless
+                let push_length = skip_push(&mut index, script)?;
+                if push_length > MAX_SCRIPT_ELEMENT_SIZE {
+                    return Err(ScriptError::PushSize(push_length));
                 }
             }
-            0xa9 => {
-                // OP_HASH160
-                if let Some(last) = stack.pop_back() {
-                    stack.push_back(hash160(&last));
-                } else {
-                    return Err("OP_HASH160 stack empty".into());
+            0xab => {
+                // OP_CODESEPARATOR, tapscript signatures commit to its position instead
+                if !tapscript {
+                    signed_code = &script[index + 1..];
                 }
+                codesep_pos = opcode_position;
             }
+            0xa6 => op_hash(stack, "OP_RIPEMD160", hash_ripemd160)?,
+            0xa7 => op_hash(stack, "OP_SHA1", hash_sha1)?,
+            0xa8 => op_hash(stack, "OP_SHA256", hash_sha256)?,
+            0xa9 => op_hash(stack, "OP_HASH160", hash160)?,
+            0xaa => op_hash(stack, "OP_HASH256", double_hash)?,
             0x75 => {
                 if stack.pop_back().is_none() {
-                    return Err("OP_DROP stack empty".into());
+                    return Err(ScriptError::StackUnderflow("OP_DROP"));
                 }
             } // OP_DROP
-            0x7c => op_swap(&mut stack)?,        // OP_SWAP
+            0x7c => op_swap(stack)?, // OP_SWAP
+            0x6b => op_altstack(stack, &mut altstack, "OP_TOALTSTACK")?,
+            0x6c => op_altstack(&mut altstack, stack, "OP_FROMALTSTACK")?,
+            0x6d => op_2drop(stack)?,
+            0x6e => op_copy_items(stack, 0, 2, "OP_2DUP")?,
+            0x6f => op_copy_items(stack, 0, 3, "OP_3DUP")?,
+            0x70 => op_copy_items(stack, 2, 2, "OP_2OVER")?,
+            0x71 => op_move_items(stack, 4, 2, "OP_2ROT")?,
+            0x72 => op_move_items(stack, 2, 2, "OP_2SWAP")?,
+            0x77 => op_nip(stack)?,
+            0x79 => op_pick(stack, false, require_minimal)?, // OP_PICK
+            0x7a => op_pick(stack, true, require_minimal)?,  // OP_ROLL
+            0x7d => op_tuck(stack)?,
             0x00 => stack.push_back(Vec::new()), // OP_0
             0x76 => {
                 // OP_DUP
                 if let Some(last) = stack.back() {
                     stack.push_back(last.clone());
                 } else {
-                    return Err("OP_DUP stack empty.".into());
+                    return Err(ScriptError::StackUnderflow("OP_DUP"));
                 }
             }
-            0x87 => op_equal(&mut stack)?,       // OP_EQUAL
-            0x7b => op_rot(&mut stack)?,         // OP_ROT
-            0x82 => op_size(&mut stack)?,        // OP_SIZE
-            0x78 => op_over(&mut stack)?,        // OP_OVER
-            0xa0 => op_greaterthan(&mut stack)?, // OP_GREATERTHAN
-            0x88 => op_equalverify(&mut stack)?, // OP_EQUALVERIFY
-            0x73 => op_ifdup(&mut stack)?,       // OP_IFDUP
-            0xb2 => op_checksequenceverify(&mut stack, txin, tx)?, // OP_CSV
-            0xb1 => op_checklocktimeverify(&mut stack, tx, txin)?, // OP_CLTV
-            0xac => op_checksig(&mut stack, tx, txin)?, // OP_CHECKSIG
-            0x74 => op_depth(&mut stack)?,       // OP_DEPTH
+            0x87 => op_equal(stack)?,       // OP_EQUAL
+            0x7b => op_rot(stack)?,         // OP_ROT
+            0x82 => op_size(stack)?,        // OP_SIZE
+            0x78 => op_over(stack)?,        // OP_OVER
+            0x88 => op_equalverify(stack)?, // OP_EQUALVERIFY
+            0x73 => op_ifdup(stack)?,       // OP_IFDUP
+            0xb2 if options.has(ScriptFlags::CHECKSEQUENCEVERIFY) => {
+                // OP_CSV
+                checker.check_sequence(peek_locktime(stack, require_minimal, "OP_CSV")?)?
+            }
+            0xb1 if options.has(ScriptFlags::CHECKLOCKTIMEVERIFY) => {
+                // OP_CLTV
+                checker.check_locktime(peek_locktime(stack, require_minimal, "OP_CLTV")?)?
+            }
+            0x61 => (), // OP_NOP
+            // OP_NOP1, OP_NOP4 to OP_NOP10 and OP_NOP2 / OP_NOP3 without the soft fork flags are
+            // reserved for soft forks, the policy rejects them so upgrades can't split relay
+            0xb0..=0xb9 if options.has(ScriptFlags::DISCOURAGE_UPGRADABLE_NOPS) => {
+                return Err(ScriptError::UpgradableNop(opcode))
+            }
+            0xb0..=0xb9 => (),
+            0xac => op_checksig(stack, checker, signed_code, codesep_pos)?, // OP_CHECKSIG
+            0x74 => op_depth(stack)?,                                       // OP_DEPTH
+            0x69 => op_verify(stack, "OP_VERIFY")?,                         // OP_VERIFY
             0xad => {
                 // OP_CHECKSIGVERIFY
-                op_checksig(&mut stack, tx, txin)?;
-                op_verify(&mut stack)?;
+                op_checksig(stack, checker, signed_code, codesep_pos)?;
+                op_verify(stack, "OP_CHECKSIGVERIFY")?;
+            }
+            0x51..=0x60 => op_pushnum(stack, opcode)?, // OP_PUSHNUM (1-16)
+            0x4f => stack.push_back(encode_num(-1)),   // OP_1NEGATE
+            0x01..=0x4b => op_pushbytes(stack, &mut index, script)?, // OP_PUSHBYTES
+            0x4c => op_pushdata(stack, 1, &mut index, script)?, // OP_PUSHDATA1
+            0x4d => op_pushdata(stack, 2, &mut index, script)?, // OP_PUSHDATA2
+            0x4e => op_pushdata(stack, 4, &mut index, script)?, // OP_PUSHDATA4
+            0x8b => op_unary_num(stack, require_minimal, "OP_1ADD", |a| a + 1)?,
+            0x8c => op_unary_num(stack, require_minimal, "OP_1SUB", |a| a - 1)?,
+            0x8f => op_unary_num(stack, require_minimal, "OP_NEGATE", |a| -a)?,
+            0x90 => op_unary_num(stack, require_minimal, "OP_ABS", |a| a.abs())?,
+            0x91 => op_unary_num(stack, require_minimal, "OP_NOT", |a| (a == 0) as i64)?,
+            0x92 => op_unary_num(stack, require_minimal, "OP_0NOTEQUAL", |a| (a != 0) as i64)?,
+            0x93 => op_binary_num(stack, require_minimal, "OP_ADD", |a, b| a + b)?,
+            0x94 => op_binary_num(stack, require_minimal, "OP_SUB", |a, b| a - b)?,
+            0x9a => op_binary_num(stack, require_minimal, "OP_BOOLAND", |a, b| {
+                (a != 0 && b != 0) as i64
+            })?,
+            0x9b => op_binary_num(stack, require_minimal, "OP_BOOLOR", |a, b| {
+                (a != 0 || b != 0) as i64
+            })?,
+            0x9c => op_binary_num(stack, require_minimal, "OP_NUMEQUAL", |a, b| {
+                (a == b) as i64
+            })?,
+            0x9d => {
+                // OP_NUMEQUALVERIFY
+                op_binary_num(stack, require_minimal, "OP_NUMEQUALVERIFY", |a, b| {
+                    (a == b) as i64
+                })?;
+                op_verify(stack, "OP_NUMEQUALVERIFY")?;
             }
-            0x51..=0x60 => op_pushnum(&mut stack, opcode)?, // OP_PUSHNUM (1-16)
-            0x4f => stack.push_back(vec![255]),             // OP_1NEGATE
-            0x01..=0x4b => op_pushbytes(&mut stack, &mut index, &script)?, // OP_PUSHBYTES
-            0x4c => op_pushdata(&mut stack, 1, &mut index, &script)?, // OP_PUSHDATA1
-            0x4d => op_pushdata(&mut stack, 2, &mut index, &script)?, // OP_PUSHDATA2
-            0x4e => op_pushdata(&mut stack, 4, &mut index, &script)?, // OP_PUSHDATA4
-            0xae => op_checkmultisig(&mut stack, tx, txin)?, // OP_CHECKMULTISIG
-            _ => panic!("no script operator found!"),
+            0x9e => op_binary_num(stack, require_minimal, "OP_NUMNOTEQUAL", |a, b| {
+                (a != b) as i64
+            })?,
+            0x9f => op_binary_num(stack, require_minimal, "OP_LESSTHAN", |a, b| (a < b) as i64)?,
+            0xa0 => op_binary_num(stack, require_minimal, "OP_GREATERTHAN", |a, b| {
+                (a > b) as i64
+            })?,
+            0xa1 => op_binary_num(stack, require_minimal, "OP_LESSTHANOREQUAL", |a, b| {
+                (a <= b) as i64
+            })?,
+            0xa2 => op_binary_num(stack, require_minimal, "OP_GREATERTHANOREQUAL", |a, b| {
+                (a >= b) as i64
+            })?,
+            0xa3 => op_binary_num(stack, require_minimal, "OP_MIN", |a, b| a.min(b))?,
+            0xa4 => op_binary_num(stack, require_minimal, "OP_MAX", |a, b| a.max(b))?,
+            0xa5 => op_within(stack, require_minimal)?, // OP_WITHIN
+            #[cfg(feature = "taproot")]
+            0xae | 0xaf if sig_version == SigVersion::Tapscript => {
+                return Err(ScriptError::DisabledOpcode(
+                    "OP_CHECKMULTISIG(VERIFY) in tapscript",
+                ))
+            }
+            #[cfg(feature = "multisig")]
+            0xae => op_checkmultisig(stack, &mut op_count, checker, signed_code)?, // OP_CHECKMULTISIG
+            #[cfg(not(feature = "multisig"))]
+            0xae => {
+                return Err(ScriptError::DisabledOpcode(
+                    "OP_CHECKMULTISIG without feature multisig",
+                ))
+            }
+            #[cfg(feature = "taproot")]
+            0xba if sig_version == SigVersion::Tapscript => {
+                op_checksigadd(stack, checker, script_code, codesep_pos)?
+                // OP_CHECKSIGADD
+            }
+            _ => return Err(ScriptError::UnknownOpcode(opcode)),
         };
+        if executing
+            && (0x01..=0x4e).contains(&opcode)
+            && options.has(ScriptFlags::MINIMALDATA)
+            && !is_minimal_push(opcode, stack.back().map_or(&[], |data| data))
+        {
+            return Err(ScriptError::MinimalData);
+        }
+        if stack.len() + altstack.len() > MAX_STACK_SIZE {
+            return Err(ScriptError::StackSize);
+        }
+        if let Some(step) = trace
+            .as_deref_mut()
+            .and_then(|trace| trace.steps.last_mut())
+        {
+            step.stack_after = Some(stack.iter().cloned().collect());
+        }
         index += 1;
+        opcode_position += 1;
     }
-    if let Some(last) = stack.pop_back() {
-        if last.is_empty() {
-            return Err("SCRIPT INVALID".into());
-        };
+    if !condition_stack.is_empty() {
+        return Err(ScriptError::UnbalancedConditional);
     }
     Ok(())
 }
+
+// the splice, bitwise logic and multiplication/shift opcodes are disabled since 2010 and fail the
+// script even in a non executed branch. Tapscripts containing them succeed without execution
+// (OP_SUCCESSx, see evaluate_tapscript()). OP_VERIF and OP_VERNOTIF are conditionals that fail
+// wherever they appear, also in tapscript.
+// returns: name of the disabled opcode, None for other opcodes
+fn disabled_opcode(opcode: u8) -> Option<&'static str> {
+    let name = match opcode {
+        0x7e => "OP_CAT",
+        0x7f => "OP_SUBSTR",
+        0x80 => "OP_LEFT",
+        0x81 => "OP_RIGHT",
+        0x83 => "OP_INVERT",
+        0x84 => "OP_AND",
+        0x85 => "OP_OR",
+        0x86 => "OP_XOR",
+        0x8d => "OP_2MUL",
+        0x8e => "OP_2DIV",
+        0x95 => "OP_MUL",
+        0x96 => "OP_DIV",
+        0x97 => "OP_MOD",
+        0x98 => "OP_LSHIFT",
+        0x99 => "OP_RSHIFT",
+        0x65 => "OP_VERIF",
+        0x66 => "OP_VERNOTIF",
+        _ => return None,
+    };
+    Some(name)
+}
+
+// returns: true if the script is executed as tapscript (BIP342)
+fn is_tapscript(sig_version: SigVersion) -> bool {
+    !matches!(sig_version, SigVersion::Base | SigVersion::WitnessV0)
+}
+
+// returns: true if the script only consists of push opcodes (OP_0 to OP_16)
+pub(crate) fn is_push_only(script: &[u8]) -> bool {
+    let mut index = 0;
+
+    while index < script.len() {
+        if script[index] > 0x60 || skip_push(&mut index, script).is_err() {
+            return false;
+        }
+        index += 1;
+    }
+    true
+}
+
+// main script interpretion function
+// executes the scriptsig and the scriptpubkey on the resulting stack according to the
+// ValidationOptions, an OP_IF can't span both scripts. With ScriptFlags::SIGPUSHONLY the
+// scriptsig has to be push only.
+// returns Ok() if the script is valid and True
+pub fn evaluate_script(
+    script_sig: &[u8],
+    script_pubkey: &[u8],
+    input_index: usize,
+    tx: &Transaction,
+    options: &ValidationOptions,
+) -> Result<(), ScriptError> {
+    evaluate_legacy(script_sig, script_pubkey, input_index, tx, options, None)
+}
+
+// evaluate_script() in trace mode, the trace ends at the opcode that failed. The positions of
+// the scriptpubkey opcodes follow the scriptsig.
+// returns: ScriptTrace of the execution and the result of evaluate_script()
+pub(crate) fn evaluate_script_traced(
+    script_sig: &[u8],
+    script_pubkey: &[u8],
+    input_index: usize,
+    tx: &Transaction,
+    options: &ValidationOptions,
+) -> (ScriptTrace, Result<(), ScriptError>) {
+    let mut trace = ScriptTrace::default();
+    let result = evaluate_legacy(
+        script_sig,
+        script_pubkey,
+        input_index,
+        tx,
+        options,
+        Some(&mut trace),
+    );
+    (trace, result)
+}
+
+fn evaluate_legacy(
+    script_sig: &[u8],
+    script_pubkey: &[u8],
+    input_index: usize,
+    tx: &Transaction,
+    options: &ValidationOptions,
+    mut trace: Option<&mut ScriptTrace>,
+) -> Result<(), ScriptError> {
+    if options.has(ScriptFlags::SIGPUSHONLY) && !is_push_only(script_sig) {
+        return Err(ScriptError::SigPushOnly);
+    }
+    let mut stack: VecDeque<Vec<u8>> = VecDeque::new();
+    let checker = LegacyChecker(InputContext::new(tx, input_index, options));
+    execute_script_traced(
+        &checker,
+        script_sig,
+        &mut stack,
+        script_sig,
+        trace.as_deref_mut(),
+    )?;
+    let script_sig_steps = trace.as_ref().map_or(0, |trace| trace.steps.len());
+    let result = execute_script_traced(
+        &checker,
+        script_pubkey,
+        &mut stack,
+        script_pubkey,
+        trace.as_deref_mut(),
+    );
+    if let Some(trace) = trace {
+        for step in &mut trace.steps[script_sig_steps..] {
+            step.position += script_sig.len();
+        }
+    }
+    result?;
+    check_final_stack(&stack, options.has(ScriptFlags::CLEANSTACK))
+}
+
+// checks the stack after the execution of the last script of an input: the top item has to be
+// true, with clean_stack (CLEANSTACK, always for witness scripts) it has to be the only item
+// returns: Ok() or ScriptError::EvalFalse / ::CleanStack
+pub(crate) fn check_final_stack(
+    stack: &VecDeque<Vec<u8>>,
+    clean_stack: bool,
+) -> Result<(), ScriptError> {
+    match stack.back() {
+        Some(_) if clean_stack && stack.len() > 1 => Err(ScriptError::CleanStack),
+        Some(top) if cast_to_bool(top) => Ok(()),
+        _ => Err(ScriptError::EvalFalse),
+    }
+}
+
+// returns: true for the OP_SUCCESSx opcodes of tapscript (BIP342), reserved for soft forks
+#[cfg(feature = "taproot")]
+fn is_op_success(opcode: u8) -> bool {
+    matches!(
+        opcode,
+        0x50 | 0x62 | 0x7e..=0x81 | 0x83..=0x86 | 0x89..=0x8a | 0x8d..=0x8e | 0x95..=0x99 | 0xbb..=0xfe
+    )
+}
+
+// decodes the script until the first OP_SUCCESSx opcode
+// returns: Ok(true) if the script contains an OP_SUCCESSx opcode, Err if a push in front of
+// it is out of range
+#[cfg(feature = "taproot")]
+fn contains_op_success(script: &[u8]) -> Result<bool, ScriptError> {
+    let mut index = 0;
+
+    while index < script.len() {
+        if is_op_success(script[index]) {
+            return Ok(true);
+        }
+        skip_push(&mut index, script)?;
+        index += 1;
+    }
+    Ok(false)
+}
+
+// tapscript execution mode (BIP342) of a taproot script path leaf on the witness stack:
+// a script containing an OP_SUCCESSx opcode succeeds unconditionally without execution,
+// otherwise it is executed with tapscript signature semantics (schnorr, OP_CHECKSIGADD, no
+// OP_CHECKMULTISIG) and exactly one true item has to be left on the stack
+// returns: Ok() if the script is valid and True
+#[cfg(feature = "taproot")]
+pub(crate) fn evaluate_tapscript(
+    script: &[u8],
+    witness_stack: Vec<Vec<u8>>,
+    input_index: usize,
+    tx: &Transaction,
+    options: &ValidationOptions,
+) -> Result<(), ScriptError> {
+    if contains_op_success(script)? {
+        return Ok(());
+    }
+    let checker = TapscriptChecker::new(InputContext::new(tx, input_index, options));
+    let stack = ScriptInterpreter::new(&checker).execute(script, script, witness_stack.into())?;
+    check_final_stack(&stack, true)
+}
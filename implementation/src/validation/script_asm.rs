@@ -0,0 +1,236 @@
+// Conversion of raw script bytes into the ASM notation used in the mempool json files
+// (e.g. "OP_DUP OP_HASH160 OP_PUSHBYTES_20 <hex> OP_EQUALVERIFY OP_CHECKSIG") and into the ASM
+// of bitcoin core used in validation errors (e.g. "OP_DUP OP_HASH160 <hex> OP_EQUALVERIFY ...")
+
+use super::utils::decode_num;
+
+// returns: ASM name of the opcode, push opcodes without their data
+pub fn opcode_name(opcode: u8) -> String {
+    let name = match opcode {
+        0x00 => "OP_0",
+        0x4c => "OP_PUSHDATA1",
+        0x4d => "OP_PUSHDATA2",
+        0x4e => "OP_PUSHDATA4",
+        0x4f => "OP_PUSHNUM_NEG1",
+        0x50 => "OP_RESERVED",
+        0x51..=0x60 => return format!("OP_PUSHNUM_{}", opcode - 0x50),
+        0x61 => "OP_NOP",
+        0x62 => "OP_VER",
+        0x63 => "OP_IF",
+        0x64 => "OP_NOTIF",
+        0x65 => "OP_VERIF",
+        0x66 => "OP_VERNOTIF",
+        0x67 => "OP_ELSE",
+        0x68 => "OP_ENDIF",
+        0x69 => "OP_VERIFY",
+        0x6a => "OP_RETURN",
+        0x6b => "OP_TOALTSTACK",
+        0x6c => "OP_FROMALTSTACK",
+        0x6d => "OP_2DROP",
+        0x6e => "OP_2DUP",
+        0x6f => "OP_3DUP",
+        0x70 => "OP_2OVER",
+        0x71 => "OP_2ROT",
+        0x72 => "OP_2SWAP",
+        0x73 => "OP_IFDUP",
+        0x74 => "OP_DEPTH",
+        0x75 => "OP_DROP",
+        0x76 => "OP_DUP",
+        0x77 => "OP_NIP",
+        0x78 => "OP_OVER",
+        0x79 => "OP_PICK",
+        0x7a => "OP_ROLL",
+        0x7b => "OP_ROT",
+        0x7c => "OP_SWAP",
+        0x7d => "OP_TUCK",
+        0x7e => "OP_CAT",
+        0x7f => "OP_SUBSTR",
+        0x80 => "OP_LEFT",
+        0x81 => "OP_RIGHT",
+        0x82 => "OP_SIZE",
+        0x83 => "OP_INVERT",
+        0x84 => "OP_AND",
+        0x85 => "OP_OR",
+        0x86 => "OP_XOR",
+        0x87 => "OP_EQUAL",
+        0x88 => "OP_EQUALVERIFY",
+        0x89 => "OP_RESERVED1",
+        0x8a => "OP_RESERVED2",
+        0x8b => "OP_1ADD",
+        0x8c => "OP_1SUB",
+        0x8d => "OP_2MUL",
+        0x8e => "OP_2DIV",
+        0x8f => "OP_NEGATE",
+        0x90 => "OP_ABS",
+        0x91 => "OP_NOT",
+        0x92 => "OP_0NOTEQUAL",
+        0x93 => "OP_ADD",
+        0x94 => "OP_SUB",
+        0x95 => "OP_MUL",
+        0x96 => "OP_DIV",
+        0x97 => "OP_MOD",
+        0x98 => "OP_LSHIFT",
+        0x99 => "OP_RSHIFT",
+        0x9a => "OP_BOOLAND",
+        0x9b => "OP_BOOLOR",
+        0x9c => "OP_NUMEQUAL",
+        0x9d => "OP_NUMEQUALVERIFY",
+        0x9e => "OP_NUMNOTEQUAL",
+        0x9f => "OP_LESSTHAN",
+        0xa0 => "OP_GREATERTHAN",
+        0xa1 => "OP_LESSTHANOREQUAL",
+        0xa2 => "OP_GREATERTHANOREQUAL",
+        0xa3 => "OP_MIN",
+        0xa4 => "OP_MAX",
+        0xa5 => "OP_WITHIN",
+        0xa6 => "OP_RIPEMD160",
+        0xa7 => "OP_SHA1",
+        0xa8 => "OP_SHA256",
+        0xa9 => "OP_HASH160",
+        0xaa => "OP_HASH256",
+        0xab => "OP_CODESEPARATOR",
+        0xac => "OP_CHECKSIG",
+        0xad => "OP_CHECKSIGVERIFY",
+        0xae => "OP_CHECKMULTISIG",
+        0xaf => "OP_CHECKMULTISIGVERIFY",
+        0xb0 => "OP_NOP1",
+        0xb1 => "OP_CLTV",
+        0xb2 => "OP_CSV",
+        0xb3..=0xb9 => return format!("OP_NOP{}", opcode - 0xb3 + 4),
+        0xba => "OP_CHECKSIGADD",
+        0xbb..=0xfe => return format!("OP_RETURN_{}", opcode),
+        0xff => "OP_INVALIDOPCODE",
+        0x01..=0x4b => return format!("OP_PUSHBYTES_{}", opcode),
+    };
+    name.to_string()
+}
+
+// opcode with the data of a push opcode, None for other opcodes and truncated pushes
+type Op<'a> = (u8, Option<&'a [u8]>);
+
+// splits the script into its opcodes with the data of push opcodes
+// returns: the opcodes and true if the last push runs over the end of the script
+fn parse_ops(script: &[u8]) -> (Vec<Op<'_>>, bool) {
+    let mut ops = Vec::new();
+    let mut index = 0;
+
+    while index < script.len() {
+        let opcode = script[index];
+        index += 1;
+        let length_bytes = match opcode {
+            0x01..=0x4b => 0,
+            0x4c => 1,
+            0x4d => 2,
+            0x4e => 4,
+            _ => {
+                ops.push((opcode, None));
+                continue;
+            }
+        };
+        if index + length_bytes > script.len() {
+            ops.push((opcode, None));
+            return (ops, true);
+        }
+        let mut push_length = opcode as usize;
+        if length_bytes > 0 {
+            let mut length_le = [0u8; 4];
+            length_le[..length_bytes].copy_from_slice(&script[index..index + length_bytes]);
+            push_length = u32::from_le_bytes(length_le) as usize;
+            index += length_bytes;
+        }
+        if index + push_length > script.len() {
+            ops.push((opcode, None));
+            return (ops, true);
+        }
+        ops.push((opcode, Some(&script[index..index + push_length])));
+        index += push_length;
+    }
+    (ops, false)
+}
+
+// disassembles the raw script bytes into ASM, pushed data is rendered as hex after the push opcode
+// returns: ASM String ("<push past end>" marks a push running over the end of the script)
+pub fn disassemble(script: &[u8]) -> String {
+    let (ops, truncated) = parse_ops(script);
+    let mut tokens: Vec<String> = Vec::new();
+
+    for (opcode, data) in ops {
+        tokens.push(opcode_name(opcode));
+        if let Some(data) = data {
+            tokens.push(hex::encode(data));
+        }
+    }
+    if truncated {
+        tokens.push("<push past end>".to_string());
+    }
+    tokens.join(" ")
+}
+
+// assembles ASM in the notation of disassemble() (mempool json files) back into script bytes,
+// push opcodes are followed by their data as hex and keep their length encoding
+// returns: script bytes or Err(reason) for unknown opcodes and missing or mismatching push data
+pub fn assemble(asm: &str) -> Result<Vec<u8>, String> {
+    let mut script = Vec::new();
+    let mut tokens = asm.split_whitespace();
+
+    while let Some(token) = tokens.next() {
+        let opcode = (0..=u8::MAX)
+            .find(|opcode| opcode_name(*opcode) == token)
+            .ok_or(format!("unknown opcode {}", token))?;
+        script.push(opcode);
+        let length_bytes = match opcode {
+            0x01..=0x4b => 0,
+            0x4c => 1,
+            0x4d => 2,
+            0x4e => 4,
+            _ => continue,
+        };
+        let data = tokens
+            .next()
+            .ok_or(format!("{} without push data", token))
+            .and_then(|data| hex::decode(data).map_err(|_| format!("{} is no hex data", data)))?;
+        if length_bytes == 0 && data.len() != opcode as usize {
+            return Err(format!("{} pushes {} bytes", token, data.len()));
+        }
+        if length_bytes > 0 && data.len() as u64 >= 1 << (8 * length_bytes) {
+            return Err(format!("{} bytes don't fit {}", data.len(), token));
+        }
+        script.extend(&(data.len() as u32).to_le_bytes()[..length_bytes]);
+        script.extend(data);
+    }
+    Ok(script)
+}
+
+// returns: name of the opcode in bitcoin core's ASM, small integers as numbers
+fn core_opcode_name(opcode: u8) -> String {
+    match opcode {
+        0x00 => "0".to_string(),
+        0x4f => "-1".to_string(),
+        0x51..=0x60 => (opcode - 0x50).to_string(),
+        0xb1 => "OP_CHECKLOCKTIMEVERIFY".to_string(),
+        0xb2 => "OP_CHECKSEQUENCEVERIFY".to_string(),
+        0xbb..=0xfe => "OP_UNKNOWN".to_string(),
+        _ => opcode_name(opcode),
+    }
+}
+
+// disassembles the raw script bytes into the ASM of bitcoin core (ScriptToAsmStr): pushes of up
+// to 4 bytes are rendered as script number, longer pushes as hex without the push opcode
+// returns: ASM String ("[error]" marks a push running over the end of the script)
+pub fn disassemble_core(script: &[u8]) -> String {
+    let (ops, truncated) = parse_ops(script);
+    let mut tokens: Vec<String> = Vec::new();
+
+    for (opcode, data) in ops {
+        match data {
+            Some(data) if data.len() <= 4 => tokens.push(decode_num(data).to_string()),
+            Some(data) => tokens.push(hex::encode(data)),
+            None if opcode == 0x00 || opcode > 0x4e => tokens.push(core_opcode_name(opcode)),
+            None => (), // truncated push
+        }
+    }
+    if truncated {
+        tokens.push("[error]".to_string());
+    }
+    tokens.join(" ")
+}
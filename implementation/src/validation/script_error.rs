@@ -0,0 +1,88 @@
+// Reasons a script fails in the interpreter. Every failure of a transaction script is
+// returned as ScriptError and ends up as ValidationError::ScriptError of the failing input,
+// the interpreter never panics on malformed scripts.
+
+use super::script_asm::opcode_name;
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScriptError {
+    UnknownOpcode(u8),
+    StackUnderflow(&'static str), // opcode executed with too few stack items
+    InvalidPush,                  // push opcode whose data runs out of the script
+    UnbalancedConditional,        // OP_ELSE/OP_ENDIF without OP_IF or missing OP_ENDIF
+    VerifyFailed(&'static str),   // *VERIFY opcode with a false top stack item
+    InvalidNumber(&'static str),  // operand of the opcode is no valid script number
+    UnsatisfiedLocktime(String),  // OP_CHECKLOCKTIMEVERIFY / OP_CHECKSEQUENCEVERIFY failed
+    Signature(String),            // invalid signature encoding, sighash type or schnorr signature
+    DisabledOpcode(&'static str), // opcode not available in the script context or build
+    EvalFalse,                    // script finished with an empty stack or a false top item
+    CleanStack,                   // script finished with more than one stack item
+    ScriptSize(usize),            // script longer than MAX_SCRIPT_SIZE bytes
+    PushSize(usize),              // stack element longer than MAX_SCRIPT_ELEMENT_SIZE bytes
+    OpCount,                      // more than MAX_OPS_PER_SCRIPT non-push opcodes
+    StackSize,                    // more than MAX_STACK_SIZE items on stack and altstack
+    MinimalData,                  // push without the smallest push opcode (MINIMALDATA)
+    NullDummy,                    // non empty OP_CHECKMULTISIG dummy element (NULLDUMMY)
+    PubkeyCount(i128),            // OP_CHECKMULTISIG public key count negative or above limit
+    SigCount(i128),               // OP_CHECKMULTISIG signature count negative or above keys
+    UpgradableNop(u8),            // reserved OP_NOPx (DISCOURAGE_UPGRADABLE_NOPS)
+    MinimalIf,                    // OP_IF/OP_NOTIF argument other than empty or 0x01 (MINIMALIF)
+    SigPushOnly,                  // scriptsig with non-push opcodes (SIGPUSHONLY)
+    TapscriptValidationWeight,    // signature checks exceed the BIP342 validation weight budget
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScriptError::UnknownOpcode(opcode) => write!(f, "Unknown opcode {:#04x}", opcode),
+            ScriptError::StackUnderflow(opcode) => {
+                write!(f, "{} with too few stack items", opcode)
+            }
+            ScriptError::InvalidPush => write!(f, "Push data out of script range"),
+            ScriptError::UnbalancedConditional => write!(f, "Unbalanced conditional"),
+            ScriptError::VerifyFailed(opcode) => write!(f, "{} failed", opcode),
+            ScriptError::InvalidNumber(opcode) => {
+                write!(f, "{} operand is no valid script number", opcode)
+            }
+            ScriptError::UnsatisfiedLocktime(reason) => write!(f, "{}", reason),
+            ScriptError::Signature(reason) => write!(f, "{}", reason),
+            ScriptError::DisabledOpcode(opcode) => write!(f, "{} is disabled", opcode),
+            ScriptError::EvalFalse => write!(f, "Script evaluated to false"),
+            ScriptError::CleanStack => write!(f, "Script left more than one stack item"),
+            ScriptError::ScriptSize(size) => write!(f, "Script size {} exceeds the limit", size),
+            ScriptError::PushSize(size) => {
+                write!(f, "Stack element size {} exceeds the limit", size)
+            }
+            ScriptError::OpCount => write!(f, "Script exceeds the opcode limit"),
+            ScriptError::StackSize => write!(f, "Stack size exceeds the limit"),
+            ScriptError::MinimalData => write!(f, "Data push is not minimal"),
+            ScriptError::NullDummy => write!(f, "OP_CHECKMULTISIG dummy element is not empty"),
+            ScriptError::PubkeyCount(count) => {
+                write!(
+                    f,
+                    "OP_CHECKMULTISIG public key count {} out of range",
+                    count
+                )
+            }
+            ScriptError::SigCount(count) => {
+                write!(f, "OP_CHECKMULTISIG signature count {} out of range", count)
+            }
+            ScriptError::MinimalIf => write!(f, "OP_IF/OP_NOTIF argument is not minimal"),
+            ScriptError::SigPushOnly => write!(f, "Scriptsig is not push only"),
+            ScriptError::TapscriptValidationWeight => {
+                write!(f, "Tapscript exceeds its validation weight budget")
+            }
+            ScriptError::UpgradableNop(opcode) => {
+                write!(
+                    f,
+                    "{} is reserved for soft fork upgrades",
+                    opcode_name(*opcode)
+                )
+            }
+        }
+    }
+}
+
+impl Error for ScriptError {}
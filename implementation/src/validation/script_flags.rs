@@ -0,0 +1,110 @@
+// Script verification flags selecting the rules the input scripts are verified under. The
+// consensus set is what a block has to satisfy, the standard set adds the policy rules of
+// relaying nodes. Without a soft fork flag the according scripts are verified like before the
+// soft fork (e.g. witness programs are anyone can spend without WITNESS).
+
+use std::fmt;
+use std::ops::BitOr;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct ScriptFlags(u32);
+
+impl ScriptFlags {
+    pub const NONE: ScriptFlags = ScriptFlags(0);
+    pub const P2SH: ScriptFlags = ScriptFlags(1 << 0); // BIP16, execute the redeem script
+    pub const DERSIG: ScriptFlags = ScriptFlags(1 << 1); // BIP66, strict DER signatures
+    pub const NULLDUMMY: ScriptFlags = ScriptFlags(1 << 2); // BIP147, empty multisig dummy
+    pub const MINIMALDATA: ScriptFlags = ScriptFlags(1 << 3); // minimal pushes and numbers
+    pub const CLEANSTACK: ScriptFlags = ScriptFlags(1 << 4); // one item left by legacy scripts
+    pub const CHECKLOCKTIMEVERIFY: ScriptFlags = ScriptFlags(1 << 5); // BIP65, else OP_NOP2
+    pub const CHECKSEQUENCEVERIFY: ScriptFlags = ScriptFlags(1 << 6); // BIP112, else OP_NOP3
+    pub const WITNESS: ScriptFlags = ScriptFlags(1 << 7); // BIP141, verify witness programs
+    pub const TAPROOT: ScriptFlags = ScriptFlags(1 << 8); // BIP341/342, verify v1 programs
+    pub const BARE_MULTISIG: ScriptFlags = ScriptFlags(1 << 9); // at most 3 bare multisig keys
+    pub const LOW_S: ScriptFlags = ScriptFlags(1 << 10); // S at most half the curve order
+    pub const DISCOURAGE_UPGRADABLE_NOPS: ScriptFlags = ScriptFlags(1 << 11); // reserved NOPs fail
+    pub const MINIMALIF: ScriptFlags = ScriptFlags(1 << 12); // witness OP_IF argument empty or 0x01
+    pub const DISCOURAGE_UPGRADABLE_WITNESS_PROGRAM: ScriptFlags = ScriptFlags(1 << 13); // v2+ fail
+    pub const SIGPUSHONLY: ScriptFlags = ScriptFlags(1 << 14); // push only legacy scriptsigs
+    pub const DISCOURAGE_UPGRADABLE_TAPROOT_VERSION: ScriptFlags = ScriptFlags(1 << 15); // not 0xc0
+
+    // rules of all soft forks active at the exercise block height
+    pub const CONSENSUS: ScriptFlags = ScriptFlags(
+        Self::P2SH.0
+            | Self::DERSIG.0
+            | Self::NULLDUMMY.0
+            | Self::CHECKLOCKTIMEVERIFY.0
+            | Self::CHECKSEQUENCEVERIFY.0
+            | Self::WITNESS.0
+            | Self::TAPROOT.0,
+    );
+    // consensus plus the standardness policy of relaying nodes
+    pub const STANDARD: ScriptFlags = ScriptFlags(
+        Self::CONSENSUS.0
+            | Self::MINIMALDATA.0
+            | Self::CLEANSTACK.0
+            | Self::BARE_MULTISIG.0
+            | Self::LOW_S.0
+            | Self::DISCOURAGE_UPGRADABLE_NOPS.0
+            | Self::MINIMALIF.0
+            | Self::DISCOURAGE_UPGRADABLE_WITNESS_PROGRAM.0
+            | Self::SIGPUSHONLY.0
+            | Self::DISCOURAGE_UPGRADABLE_TAPROOT_VERSION.0,
+    );
+
+    const NAMES: [(ScriptFlags, &'static str); 16] = [
+        (Self::P2SH, "P2SH"),
+        (Self::DERSIG, "DERSIG"),
+        (Self::NULLDUMMY, "NULLDUMMY"),
+        (Self::MINIMALDATA, "MINIMALDATA"),
+        (Self::CLEANSTACK, "CLEANSTACK"),
+        (Self::CHECKLOCKTIMEVERIFY, "CHECKLOCKTIMEVERIFY"),
+        (Self::CHECKSEQUENCEVERIFY, "CHECKSEQUENCEVERIFY"),
+        (Self::WITNESS, "WITNESS"),
+        (Self::TAPROOT, "TAPROOT"),
+        (Self::BARE_MULTISIG, "BARE_MULTISIG"),
+        (Self::LOW_S, "LOW_S"),
+        (
+            Self::DISCOURAGE_UPGRADABLE_NOPS,
+            "DISCOURAGE_UPGRADABLE_NOPS",
+        ),
+        (Self::MINIMALIF, "MINIMALIF"),
+        (
+            Self::DISCOURAGE_UPGRADABLE_WITNESS_PROGRAM,
+            "DISCOURAGE_UPGRADABLE_WITNESS_PROGRAM",
+        ),
+        (Self::SIGPUSHONLY, "SIGPUSHONLY"),
+        (
+            Self::DISCOURAGE_UPGRADABLE_TAPROOT_VERSION,
+            "DISCOURAGE_UPGRADABLE_TAPROOT_VERSION",
+        ),
+    ];
+
+    // returns: true if all flags of other are set
+    pub fn contains(self, other: ScriptFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for ScriptFlags {
+    type Output = ScriptFlags;
+
+    fn bitor(self, other: ScriptFlags) -> ScriptFlags {
+        ScriptFlags(self.0 | other.0)
+    }
+}
+
+// formats the set flags as names separated by | (e.g. P2SH|WITNESS)
+impl fmt::Debug for ScriptFlags {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let names: Vec<&str> = Self::NAMES
+            .iter()
+            .filter(|(flag, _)| self.contains(*flag))
+            .map(|(_, name)| *name)
+            .collect();
+        match names.is_empty() {
+            true => write!(f, "NONE"),
+            false => write!(f, "{}", names.join("|")),
+        }
+    }
+}
@@ -0,0 +1,1820 @@
+// Interpreter tests with vectors of the MINIMALDATA and CLEANSTACK sections of bitcoin core's
+// script_tests.json. The scriptsig is executed first and the scriptpubkey on the resulting
+// stack, without the MINIMALDATA flag all vectors succeed.
+
+use super::script::{
+    cast_to_bool, evaluate_script, evaluate_script_traced, serialize_legacy_tx, ScriptInterpreter,
+    SigVersion,
+};
+use super::script_asm::{assemble, disassemble, disassemble_core, opcode_name};
+use super::script_error::ScriptError;
+use super::script_flags::ScriptFlags;
+use super::sighash::SighashType;
+use super::signature_checker::{InputContext, LegacyChecker, SignatureChecker, WitnessV0Checker};
+#[cfg(feature = "multisig")]
+use super::signature_verification::verify_bare_multisig;
+use super::signature_verification::{
+    get_segwit_commitment_hash, p2wpkh_scriptcode, verify_p2pk, verify_p2sh,
+    verify_witness_program, witness_program,
+};
+use super::sigops::count_sigops;
+use super::utils::{encode_num, hash160, hash_sha256};
+use super::validate_parsing::{find_prevout_type_mismatch, scriptpubkey_type};
+use super::validation_error::{MalformedInput, WitnessError};
+use super::weight_calculation::validate_witness_consistency;
+use super::{ValidationError, ValidationOptions};
+use crate::parsing::parse_transactions_from_dir;
+use crate::parsing::transaction_structs::Transaction;
+use secp256k1::{Message, PublicKey, Secp256k1, SecretKey};
+use std::collections::VecDeque;
+#[cfg(feature = "taproot")]
+use {
+    super::taproot::{
+        tagged_hash, tapleaf_hash, verify_p2tr, TapscriptChecker, TAPSCRIPT_LEAF_VERSION,
+    },
+    secp256k1::{Keypair, Scalar, XOnlyPublicKey},
+};
+
+// scriptsig and scriptpubkey (hex) of the vectors, DROP 1 = 7551
+const NON_MINIMAL_PUSHES: [(&str, &str); 7] = [
+    ("4c00", "7551"),       // OP_PUSHDATA1 of the empty vector instead of OP_0
+    ("4d0000", "7551"),     // OP_PUSHDATA2 of the empty vector
+    ("4e00000000", "7551"), // OP_PUSHDATA4 of the empty vector
+    ("0181", "7551"),       // 0x81 instead of OP_1NEGATE
+    ("0101", "7551"),       // 0x01 instead of OP_1
+    ("0105", "7551"),       // 0x05 instead of OP_5
+    ("0110", "7551"),       // 0x10 instead of OP_16
+];
+
+// scriptsig, scriptpubkey (hex) and opcode of the vectors with non minimal number operands
+const NON_MINIMAL_NUMBERS: [(&str, &str, &str); 9] = [
+    ("51020000", "797551", "OP_PICK"),     // 1 0x0000 PICK DROP 1
+    ("51020000", "7a7551", "OP_ROLL"),     // 1 0x0000 ROLL DROP 1
+    ("020000", "8b7551", "OP_1ADD"),       // 0x0000 1ADD DROP 1
+    ("020000", "917551", "OP_NOT"),        // 0x0000 NOT DROP 1
+    ("0100", "8c7551", "OP_1SUB"),         // 0x00 1SUB DROP 1
+    ("0180", "8f7551", "OP_NEGATE"),       // negative zero NEGATE DROP 1
+    ("020080", "907551", "OP_ABS"),        // negative zero ABS DROP 1
+    ("00020000", "937551", "OP_ADD"),      // 0 0x0000 ADD DROP 1
+    ("0002000051", "a57551", "OP_WITHIN"), // 0 0x0000 1 WITHIN DROP 1
+];
+
+// true and false OP_IF arguments other than 0x01 and the empty vector
+const NON_MINIMAL_IF_ARGUMENTS: [&str; 5] = ["0100", "0102", "52", "4f", "020100"];
+
+fn fixture() -> Transaction {
+    parse_transactions_from_dir("test_scripts/testfiles/p2pkh")
+        .expect("Parsing the fixtures failed")
+        .remove(0)
+}
+
+// returns: Ok() if the scripts execute and leave a true top stack item
+fn run(script_sig: &str, script_pubkey: &str, flags: ScriptFlags) -> Result<(), ScriptError> {
+    run_tx(&fixture(), script_sig, script_pubkey, flags)
+}
+
+// runs the scripts as first input of tx
+// returns: Ok() if the scripts execute and leave a true top stack item
+fn run_tx(
+    tx: &Transaction,
+    script_sig: &str,
+    script_pubkey: &str,
+    flags: ScriptFlags,
+) -> Result<(), ScriptError> {
+    let options = ValidationOptions {
+        flags,
+        ..Default::default()
+    };
+    let checker = LegacyChecker(InputContext::new(tx, 0, &options));
+    let interpreter = ScriptInterpreter::new(&checker);
+    let mut stack = VecDeque::new();
+    for script in [script_sig, script_pubkey] {
+        let script = hex::decode(script).unwrap();
+        stack = interpreter.execute(&script, &script, stack)?;
+    }
+    match stack.back() {
+        Some(top) if cast_to_bool(top) => Ok(()),
+        _ => Err(ScriptError::EvalFalse),
+    }
+}
+
+// appends the push of the data with the smallest push opcode
+fn push_data(script: &mut Vec<u8>, data: &[u8]) {
+    match data.len() {
+        0..=75 => script.push(data.len() as u8),
+        76..=255 => script.extend([0x4c, data.len() as u8]),
+        _ => {
+            script.push(0x4d);
+            script.extend((data.len() as u16).to_le_bytes());
+        }
+    }
+    script.extend_from_slice(data);
+}
+
+// assembles a script in the notation of bitcoin core's script_tests.json: opcode names with or
+// without OP_, numbers, 0x prefixed raw bytes (pushes included) and pushed 'strings'
+fn core_script(asm: &str) -> Vec<u8> {
+    let mut script = Vec::new();
+    for token in asm.split_whitespace() {
+        if let Some(raw) = token.strip_prefix("0x") {
+            script.extend(hex::decode(raw).unwrap());
+        } else if let Some(string) = token.strip_prefix('\'').and_then(|t| t.strip_suffix('\'')) {
+            push_data(&mut script, string.as_bytes());
+        } else if let Ok(number) = token.parse::<i64>() {
+            match number {
+                0 => script.push(0x00),
+                -1 | 1..=16 => script.push((0x50 + number) as u8),
+                _ => push_data(&mut script, &encode_num(number)),
+            }
+        } else {
+            let name = format!("OP_{}", token.strip_prefix("OP_").unwrap_or(token));
+            let opcode = match name.as_str() {
+                "OP_FALSE" => 0x00,
+                "OP_TRUE" => 0x51,
+                "OP_1NEGATE" => 0x4f,
+                "OP_NOP2" | "OP_CHECKLOCKTIMEVERIFY" => 0xb1,
+                "OP_NOP3" | "OP_CHECKSEQUENCEVERIFY" => 0xb2,
+                _ => (0x50..=0xff)
+                    .find(|opcode| opcode_name(*opcode) == name)
+                    .unwrap_or_else(|| panic!("unknown opcode {}", token)),
+            };
+            script.push(opcode);
+        }
+    }
+    script
+}
+
+// executes the scripts in the notation of core_script() like evaluate_script() as first input
+// of the fixture
+fn run_core(script_sig: &str, script_pubkey: &str, flags: ScriptFlags) -> Result<(), ScriptError> {
+    let options = ValidationOptions {
+        flags,
+        ..Default::default()
+    };
+    let (script_sig, script_pubkey) = (core_script(script_sig), core_script(script_pubkey));
+    evaluate_script(&script_sig, &script_pubkey, 0, &fixture(), &options)
+}
+
+// vectors of bitcoin core's script_tests.json: scriptsig, scriptpubkey and the expected result
+type CoreVector = (&'static str, &'static str, Result<(), ScriptError>);
+
+fn assert_core_vectors(vectors: &[CoreVector], flags: ScriptFlags) {
+    for (script_sig, script_pubkey, expected) in vectors {
+        assert_eq!(
+            &run_core(script_sig, script_pubkey, flags),
+            expected,
+            "[\"{}\", \"{}\"]",
+            script_sig,
+            script_pubkey
+        );
+    }
+}
+
+#[test]
+fn non_minimal_pushes_fail_with_minimaldata() {
+    let long_pushes = [
+        format!("4c48{}", "11".repeat(72)),    // OP_PUSHDATA1 of 72 bytes
+        format!("4dff00{}", "11".repeat(255)), // OP_PUSHDATA2 of 255 bytes
+        format!("4e00010000{}", "11".repeat(256)), // OP_PUSHDATA4 of 256 bytes
+    ];
+    let vectors = NON_MINIMAL_PUSHES
+        .iter()
+        .map(|(script_sig, script_pubkey)| (script_sig.to_string(), *script_pubkey))
+        .chain(
+            long_pushes
+                .into_iter()
+                .map(|script_sig| (script_sig, "7551")),
+        );
+    for (script_sig, script_pubkey) in vectors {
+        assert_eq!(
+            run(&script_sig, script_pubkey, ScriptFlags::STANDARD),
+            Err(ScriptError::MinimalData),
+            "{} {}",
+            script_sig,
+            script_pubkey
+        );
+        assert_eq!(
+            run(&script_sig, script_pubkey, ScriptFlags::CONSENSUS),
+            Ok(())
+        );
+    }
+}
+
+#[test]
+fn minimal_pushes_succeed_with_minimaldata() {
+    let vectors = [
+        ("0100", "7551"),                                     // 0x00 is no small integer
+        (&format!("4b{}", "11".repeat(75))[..], "7551"),      // direct push of 75 bytes
+        (&format!("4c4c{}", "11".repeat(76))[..], "7551"),    // OP_PUSHDATA1 of 76 bytes
+        (&format!("4d0001{}", "11".repeat(256))[..], "7551"), // OP_PUSHDATA2 of 256 bytes
+    ];
+    for (script_sig, script_pubkey) in vectors {
+        assert_eq!(
+            run(script_sig, script_pubkey, ScriptFlags::STANDARD),
+            Ok(()),
+            "{} {}",
+            script_sig,
+            script_pubkey
+        );
+    }
+}
+
+#[test]
+fn non_minimal_numbers_fail_with_minimaldata() {
+    for (script_sig, script_pubkey, opcode) in NON_MINIMAL_NUMBERS {
+        assert_eq!(
+            run(script_sig, script_pubkey, ScriptFlags::STANDARD),
+            Err(ScriptError::InvalidNumber(opcode)),
+            "{} {}",
+            script_sig,
+            script_pubkey
+        );
+        assert_eq!(
+            run(script_sig, script_pubkey, ScriptFlags::CONSENSUS),
+            Ok(())
+        );
+    }
+}
+
+#[test]
+fn minimal_numbers_succeed_with_minimaldata() {
+    let vectors = [
+        ("028000", "8b02810087"), // 128 1ADD 129 EQUAL, 0x00 keeps the sign bit clear
+        ("02ff80", "8f02ff0087"), // -255 NEGATE 255 EQUAL
+        ("4f", "8f5187"),         // OP_1NEGATE NEGATE 1 EQUAL
+    ];
+    for (script_sig, script_pubkey) in vectors {
+        assert_eq!(
+            run(script_sig, script_pubkey, ScriptFlags::STANDARD),
+            Ok(()),
+            "{} {}",
+            script_sig,
+            script_pubkey
+        );
+    }
+}
+
+#[cfg(feature = "multisig")]
+#[test]
+fn non_minimal_multisig_counts_fail_with_minimaldata() {
+    // 0 0 0x0000 CHECKMULTISIG DROP 1 and 0 0x0000 0 CHECKMULTISIG DROP 1
+    for script_sig in ["0000020000", "0002000000"] {
+        assert_eq!(
+            run(script_sig, "ae7551", ScriptFlags::STANDARD),
+            Err(ScriptError::InvalidNumber("OP_CHECKMULTISIG"))
+        );
+        assert_eq!(run(script_sig, "ae7551", ScriptFlags::CONSENSUS), Ok(()));
+    }
+}
+
+#[cfg(feature = "multisig")]
+#[test]
+fn multisig_counts_out_of_range_fail() {
+    // 0 0 -1 CHECKMULTISIG and 0 0 21 CHECKMULTISIG
+    for (script_sig, count) in [("00004f", -1), ("00000115", 21)] {
+        assert_eq!(
+            run(script_sig, "ae", ScriptFlags::CONSENSUS),
+            Err(ScriptError::PubkeyCount(count))
+        );
+    }
+    // 0 -1 0 CHECKMULTISIG and 0 <sig> <sig> 2 <pubkey> 1 CHECKMULTISIG
+    assert_eq!(
+        run("004f00", "ae", ScriptFlags::CONSENSUS),
+        Err(ScriptError::SigCount(-1))
+    );
+    assert_eq!(
+        run("00010101015201025151", "ae", ScriptFlags::CONSENSUS),
+        Err(ScriptError::SigCount(2))
+    );
+    // 0 0 20 pubkeys 20 CHECKMULTISIG: the 20 keys and the opcode fill the opcode limit of 201
+    let script_sig = format!("0000{}0114", "0102".repeat(20));
+    let nops = "61".repeat(180);
+    assert_eq!(
+        run(&script_sig, &format!("{}ae", nops), ScriptFlags::CONSENSUS),
+        Ok(())
+    );
+    assert_eq!(
+        run(
+            &script_sig,
+            &format!("61{}ae", nops),
+            ScriptFlags::CONSENSUS
+        ),
+        Err(ScriptError::OpCount)
+    );
+}
+
+#[test]
+fn final_stack_needs_one_true_item_with_cleanstack() {
+    let tx = fixture();
+    let evaluate = |script: &str, flags: ScriptFlags| {
+        let options = ValidationOptions {
+            flags,
+            ..Default::default()
+        };
+        evaluate_script(&[], &hex::decode(script).unwrap(), 0, &tx, &options)
+    };
+    // 1 1: true top item but two items left
+    assert_eq!(evaluate("5151", ScriptFlags::CONSENSUS), Ok(()));
+    assert_eq!(
+        evaluate("5151", ScriptFlags::STANDARD),
+        Err(ScriptError::CleanStack)
+    );
+    // 1 0: single true item below a false top item
+    assert_eq!(
+        evaluate("5100", ScriptFlags::STANDARD),
+        Err(ScriptError::CleanStack)
+    );
+    assert_eq!(
+        evaluate("5100", ScriptFlags::CONSENSUS),
+        Err(ScriptError::EvalFalse)
+    );
+    // empty stack, 0x00 and negative zero are false regardless of the flags
+    for script in ["", "0100", "0180"] {
+        assert_eq!(
+            evaluate(script, ScriptFlags::CONSENSUS),
+            Err(ScriptError::EvalFalse)
+        );
+    }
+    assert_eq!(evaluate("51", ScriptFlags::STANDARD), Ok(()));
+}
+
+#[test]
+fn scriptsig_and_scriptpubkey_execute_separately() {
+    let tx = fixture();
+    let evaluate = |script_sig: &str, script_pubkey: &str, flags: ScriptFlags| {
+        let options = ValidationOptions {
+            flags,
+            ..Default::default()
+        };
+        let script_sig = hex::decode(script_sig).unwrap();
+        evaluate_script(
+            &script_sig,
+            &hex::decode(script_pubkey).unwrap(),
+            0,
+            &tx,
+            &options,
+        )
+    };
+    // "1 IF", "1 ENDIF": IF/ENDIF can't span scriptSig/scriptPubKey
+    assert_eq!(
+        evaluate("5163", "5168", ScriptFlags::CONSENSUS),
+        Err(ScriptError::UnbalancedConditional)
+    );
+    // "0 IF", "ENDIF 1" and "1", "IF 1 ENDIF": the open branch doesn't skip the scriptpubkey
+    // and the scriptpubkey can't close a branch of the scriptsig
+    assert_eq!(
+        evaluate("0063", "6851", ScriptFlags::CONSENSUS),
+        Err(ScriptError::UnbalancedConditional)
+    );
+    assert_eq!(evaluate("51", "635168", ScriptFlags::CONSENSUS), Ok(()));
+    // "NOP", "1": non-push scriptsig opcodes are consensus valid but not standard
+    assert_eq!(evaluate("61", "51", ScriptFlags::CONSENSUS), Ok(()));
+    assert_eq!(
+        evaluate("61", "51", ScriptFlags::STANDARD),
+        Err(ScriptError::SigPushOnly)
+    );
+}
+
+#[test]
+fn conditionals_execute_nested_branches() {
+    assert_core_vectors(
+        &[
+            ("1 1", "IF IF 1 ELSE 0 ENDIF ENDIF", Ok(())),
+            ("1 0", "IF IF 1 ELSE 0 ENDIF ENDIF", Ok(())),
+            (
+                "1 1",
+                "IF IF 1 ELSE 0 ENDIF ELSE IF 0 ELSE 1 ENDIF ENDIF",
+                Ok(()),
+            ),
+            (
+                "0 0",
+                "IF IF 1 ELSE 0 ENDIF ELSE IF 0 ELSE 1 ENDIF ENDIF",
+                Ok(()),
+            ),
+            ("1 0", "NOTIF IF 1 ELSE 0 ENDIF ENDIF", Ok(())),
+            ("1 1", "NOTIF IF 1 ELSE 0 ENDIF ENDIF", Ok(())),
+            (
+                "0 1",
+                "NOTIF IF 1 ELSE 0 ENDIF ELSE IF 0 ELSE 1 ENDIF ENDIF",
+                Ok(()),
+            ),
+            // every ELSE inverts the branch
+            ("0", "IF 0 ELSE 1 ELSE 0 ENDIF", Ok(())),
+            ("1", "IF 1 ELSE 0 ELSE ENDIF", Ok(())),
+            ("1", "IF ELSE 0 ELSE 1 ENDIF", Ok(())),
+            ("1", "IF 0 ELSE 1 ELSE 0 ENDIF", Err(ScriptError::EvalFalse)),
+            ("'' 1", "IF ELSE 1 ENDIF", Err(ScriptError::EvalFalse)),
+            // reserved and unknown opcodes are only invalid if executed
+            ("0", "IF RETURN ENDIF 1", Ok(())),
+            ("0", "IF VER ELSE 1 ENDIF", Ok(())),
+            ("0", "IF RESERVED RESERVED1 RESERVED2 ELSE 1 ENDIF", Ok(())),
+            ("0", "IF 0xba ELSE 1 ENDIF", Ok(())),
+            (
+                "1",
+                "IF RETURN ENDIF",
+                Err(ScriptError::UnknownOpcode(0x6a)),
+            ),
+            (
+                "1",
+                "IF VER ELSE 1 ENDIF",
+                Err(ScriptError::UnknownOpcode(0x62)),
+            ),
+            // unbalanced conditionals
+            ("0", "IF", Err(ScriptError::UnbalancedConditional)),
+            ("0", "NOTIF", Err(ScriptError::UnbalancedConditional)),
+            ("1", "ELSE 1", Err(ScriptError::UnbalancedConditional)),
+            ("1", "ENDIF 1", Err(ScriptError::UnbalancedConditional)),
+            (
+                "1",
+                "IF ENDIF ENDIF",
+                Err(ScriptError::UnbalancedConditional),
+            ),
+            (
+                "1 1",
+                "IF ELSE ELSE ENDIF ELSE",
+                Err(ScriptError::UnbalancedConditional),
+            ),
+            ("", "IF 1 ENDIF", Err(ScriptError::StackUnderflow("OP_IF"))),
+        ],
+        ScriptFlags::CONSENSUS,
+    );
+}
+
+#[test]
+fn arithmetic_opcodes_operate_on_4_byte_numbers() {
+    // results may be 5 bytes long, only the operands are limited to 4 bytes
+    let vectors: [CoreVector; 24] = [
+        ("2147483647", "1ADD 2147483648 EQUAL", Ok(())),
+        ("-2147483647", "1SUB -2147483648 EQUAL", Ok(())),
+        ("2147483647", "DUP ADD 4294967294 EQUAL", Ok(())),
+        ("-2147483647", "DUP SUB 0 EQUAL", Ok(())),
+        (
+            "2147483648",
+            "1ADD 1",
+            Err(ScriptError::InvalidNumber("OP_1ADD")),
+        ),
+        (
+            "2147483648",
+            "NEGATE 1",
+            Err(ScriptError::InvalidNumber("OP_NEGATE")),
+        ),
+        (
+            "-2147483648",
+            "1ADD 1",
+            Err(ScriptError::InvalidNumber("OP_1ADD")),
+        ),
+        (
+            "2147483648 1",
+            "ADD 1",
+            Err(ScriptError::InvalidNumber("OP_ADD")),
+        ),
+        (
+            "0x05 0x0100000000",
+            "0NOTEQUAL 1",
+            Err(ScriptError::InvalidNumber("OP_0NOTEQUAL")),
+        ),
+        ("-1", "ABS 1 EQUAL", Ok(())),
+        ("-1", "NEGATE 1 EQUAL", Ok(())),
+        ("0x01 0x80", "NOT", Ok(())), // negative zero
+        ("0x01 0x80", "0NOTEQUAL 0 EQUAL", Ok(())),
+        ("1 0", "BOOLAND NOT", Ok(())),
+        ("0 0x01 0x80", "BOOLOR NOT", Ok(())),
+        ("11 10", "SUB 1 NUMEQUAL", Ok(())),
+        ("0x01 0x01 1", "NUMEQUALVERIFY 1", Ok(())),
+        (
+            "1 2",
+            "NUMEQUALVERIFY 1",
+            Err(ScriptError::VerifyFailed("OP_NUMEQUALVERIFY")),
+        ),
+        ("-1 0", "LESSTHAN 1 0 GREATERTHAN BOOLAND", Ok(())),
+        (
+            "1 1",
+            "LESSTHANOREQUAL 1 1 GREATERTHANOREQUAL BOOLAND",
+            Ok(()),
+        ),
+        ("1 -2", "MIN -2 EQUAL 1 -2 MAX 1 EQUAL BOOLAND", Ok(())),
+        ("0 0 1", "WITHIN 1 0 1 WITHIN NOT BOOLAND", Ok(())), // min inclusive, max exclusive
+        ("1 1", "NUMNOTEQUAL NOT", Ok(())),
+        ("1", "ADD", Err(ScriptError::StackUnderflow("OP_ADD"))),
+    ];
+    assert_core_vectors(&vectors, ScriptFlags::CONSENSUS);
+}
+
+#[test]
+fn stack_opcodes_move_and_copy_items() {
+    let vectors: [CoreVector; 20] = [
+        (
+            "1 2",
+            "2DUP 2 EQUALVERIFY 1 EQUALVERIFY 2 EQUALVERIFY 1 EQUAL",
+            Ok(()),
+        ),
+        (
+            "1 2 3",
+            "3DUP DEPTH 6 EQUALVERIFY 3 EQUALVERIFY 2 EQUALVERIFY 1 EQUALVERIFY 2DROP 1 EQUAL",
+            Ok(()),
+        ),
+        (
+            "1 2 3 4",
+            "2OVER 2 EQUALVERIFY 1 EQUALVERIFY DEPTH 4 EQUAL",
+            Ok(()),
+        ),
+        (
+            "1 2 3 4",
+            "2SWAP 2 EQUALVERIFY 1 EQUALVERIFY 4 EQUALVERIFY 3 EQUAL",
+            Ok(()),
+        ),
+        (
+            "1 2 3 4 5 6",
+            "2ROT 2 EQUALVERIFY 1 EQUALVERIFY 6 EQUALVERIFY 5 EQUALVERIFY 4 EQUALVERIFY 3 EQUAL",
+            Ok(()),
+        ),
+        ("0 1", "NIP", Ok(())),
+        ("1 0", "NIP", Err(ScriptError::EvalFalse)),
+        ("0 1", "TUCK DEPTH 3 EQUALVERIFY SWAP 2DROP", Ok(())),
+        ("1 0", "PICK", Ok(())),
+        ("1 0 1", "PICK DEPTH 3 EQUALVERIFY", Ok(())),
+        ("1 0 1", "ROLL DEPTH 2 EQUALVERIFY", Ok(())),
+        ("1", "1 PICK", Err(ScriptError::StackUnderflow("OP_PICK"))),
+        ("1", "-1 ROLL", Err(ScriptError::StackUnderflow("OP_ROLL"))),
+        ("1 0", "TOALTSTACK DROP FROMALTSTACK NOT", Ok(())),
+        // the altstack isn't shared between scriptsig and scriptpubkey
+        (
+            "1 TOALTSTACK",
+            "FROMALTSTACK 1",
+            Err(ScriptError::StackUnderflow("OP_FROMALTSTACK")),
+        ),
+        ("1", "2DUP", Err(ScriptError::StackUnderflow("OP_2DUP"))),
+        ("1 2", "3DUP", Err(ScriptError::StackUnderflow("OP_3DUP"))),
+        ("1", "2DROP 1", Err(ScriptError::StackUnderflow("OP_2DROP"))),
+        ("1", "NIP", Err(ScriptError::StackUnderflow("OP_NIP"))),
+        ("1", "TUCK", Err(ScriptError::StackUnderflow("OP_TUCK"))),
+    ];
+    assert_core_vectors(&vectors, ScriptFlags::CONSENSUS);
+}
+
+#[test]
+fn hash_opcodes_match_the_core_digests() {
+    let vectors: [CoreVector; 16] = [
+        (
+            "''",
+            "RIPEMD160 0x14 0x9c1185a5c5e9fc54612808977ee8f548b2258d31 EQUAL",
+            Ok(()),
+        ),
+        (
+            "'a'",
+            "RIPEMD160 0x14 0x0bdc9d2d256b3ee9daae347be6f4dc835a467ffe EQUAL",
+            Ok(()),
+        ),
+        (
+            "'abcdefghijklmnopqrstuvwxyz'",
+            "RIPEMD160 0x14 0xf71c27109c692c1b56bbdceb5b9d2865b3708dbc EQUAL",
+            Ok(()),
+        ),
+        (
+            "''",
+            "SHA1 0x14 0xda39a3ee5e6b4b0d3255bfef95601890afd80709 EQUAL",
+            Ok(()),
+        ),
+        (
+            "'a'",
+            "SHA1 0x14 0x86f7e437faa5a7fce15d1ddcb9eaeaea377667b8 EQUAL",
+            Ok(()),
+        ),
+        (
+            "'abcdefghijklmnopqrstuvwxyz'",
+            "SHA1 0x14 0x32d10c7b8cf96570ca04ce37f2a19d84240d3a89 EQUAL",
+            Ok(()),
+        ),
+        (
+            "''",
+            "SHA256 0x20 0xe3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855 EQUAL",
+            Ok(()),
+        ),
+        (
+            "'a'",
+            "SHA256 0x20 0xca978112ca1bbdcafac231b39a23dc4da786eff8147c4e72b9807785afee48bb EQUAL",
+            Ok(()),
+        ),
+        ("''", "DUP HASH160 SWAP SHA256 RIPEMD160 EQUAL", Ok(())),
+        ("''", "DUP HASH256 SWAP SHA256 SHA256 EQUAL", Ok(())),
+        (
+            "''",
+            "NOP HASH160 0x14 0xb472a266d0bd89c13706a4132ccfb16f7c3b9fcb EQUAL",
+            Ok(()),
+        ),
+        (
+            "'a'",
+            "HASH160 NOP 0x14 0x994355199e516ff76c4fa4aab39337b9d84cf12b EQUAL",
+            Ok(()),
+        ),
+        (
+            "''",
+            "HASH256 0x20 0x5df6e0e2761359d30a8275058e299fcc0381534545f55cf43e41983f5d4c9456 EQUAL",
+            Ok(()),
+        ),
+        (
+            "'a'",
+            "HASH256 0x20 0xbf5d3affb73efd2ec6c36ad3112dd933efed63c4e1cbffcfa88e2759c144f2d8 EQUAL",
+            Ok(()),
+        ),
+        (
+            "",
+            "RIPEMD160",
+            Err(ScriptError::StackUnderflow("OP_RIPEMD160")),
+        ),
+        ("", "SHA1", Err(ScriptError::StackUnderflow("OP_SHA1"))),
+    ];
+    assert_core_vectors(&vectors, ScriptFlags::CONSENSUS);
+}
+
+#[test]
+fn resource_limits_apply_to_each_script() {
+    let options = ValidationOptions::default();
+    let tx = fixture();
+    let run_bytes = |script_sig: &[u8], script_pubkey: &[u8]| {
+        evaluate_script(script_sig, script_pubkey, 0, &tx, &options)
+    };
+    let repeat = |asm: &str, count| vec![asm; count].join(" ");
+
+    // MAX_SCRIPT_ELEMENT_SIZE, oversized pushes fail in non executed branches as well
+    let push = |length: usize| {
+        format!(
+            "0x4d 0x{} 0x{}",
+            hex::encode((length as u16).to_le_bytes()),
+            "42".repeat(length)
+        )
+    };
+    assert_eq!(
+        run_core(&push(520), "DROP 1", ScriptFlags::CONSENSUS),
+        Ok(())
+    );
+    assert_eq!(
+        run_core(&push(521), "DROP 1", ScriptFlags::CONSENSUS),
+        Err(ScriptError::PushSize(521))
+    );
+    let unexecuted = format!("0 IF {} ENDIF 1", push(521));
+    assert_eq!(
+        run_core("", &unexecuted, ScriptFlags::CONSENSUS),
+        Err(ScriptError::PushSize(521))
+    );
+
+    // MAX_OPS_PER_SCRIPT counts the non push opcodes of each script, executed or not
+    let nops = |count| format!("{} 1", repeat("NOP", count));
+    assert_eq!(run_core("", &nops(201), ScriptFlags::CONSENSUS), Ok(()));
+    assert_eq!(
+        run_core("", &nops(202), ScriptFlags::CONSENSUS),
+        Err(ScriptError::OpCount)
+    );
+    let unexecuted_nops = |count| format!("0 IF {} ENDIF 1", repeat("NOP", count));
+    assert_eq!(
+        run_core("", &unexecuted_nops(199), ScriptFlags::CONSENSUS),
+        Ok(())
+    );
+    assert_eq!(
+        run_core("", &unexecuted_nops(200), ScriptFlags::CONSENSUS),
+        Err(ScriptError::OpCount)
+    );
+    assert_eq!(
+        run_core(&repeat("NOP", 201), &nops(201), ScriptFlags::CONSENSUS),
+        Ok(())
+    );
+
+    // MAX_STACK_SIZE of stack and altstack combined
+    assert_eq!(
+        run_core("", &repeat("1", 1000), ScriptFlags::CONSENSUS),
+        Ok(())
+    );
+    assert_eq!(
+        run_core("", &repeat("1", 1001), ScriptFlags::CONSENSUS),
+        Err(ScriptError::StackSize)
+    );
+    assert_eq!(
+        run_core(&repeat("1", 500), &repeat("1", 500), ScriptFlags::CONSENSUS),
+        Ok(())
+    );
+    let altstack = |count| format!("1 TOALTSTACK {}", repeat("1", count));
+    assert_eq!(run_core("", &altstack(999), ScriptFlags::CONSENSUS), Ok(()));
+    assert_eq!(
+        run_core("", &altstack(1000), ScriptFlags::CONSENSUS),
+        Err(ScriptError::StackSize)
+    );
+
+    // MAX_SCRIPT_SIZE: 0 IF <OP_0 filler> ENDIF 1
+    let sized_script =
+        |size: usize| [vec![0x00, 0x63], vec![0x00; size - 4], vec![0x68, 0x51]].concat();
+    assert_eq!(run_bytes(&[], &sized_script(10_000)), Ok(()));
+    assert_eq!(
+        run_bytes(&[], &sized_script(10_001)),
+        Err(ScriptError::ScriptSize(10_001))
+    );
+    assert_eq!(
+        run_bytes(&sized_script(5_000), &sized_script(6_000)),
+        Ok(())
+    );
+}
+
+#[test]
+fn codeseparator_executes_as_nop() {
+    // 1 CODESEPARATOR, CODESEPARATOR 1 and a separator in a non executed branch
+    for script_pubkey in ["51ab", "ab51", "510063ab68"] {
+        assert_eq!(run("", script_pubkey, ScriptFlags::STANDARD), Ok(()));
+    }
+}
+
+#[test]
+fn codeseparator_trims_the_signed_script_code() {
+    let options = ValidationOptions::default();
+    let key = SecretKey::from_slice(&[1; 32]).unwrap();
+    let pubkey = PublicKey::from_secret_key(&Secp256k1::new(), &key).serialize();
+    let pubkey = format!("0x21 0x{}", hex::encode(pubkey));
+    let asm = |script: &str| core_script(&script.replace("<pubkey>", &pubkey));
+
+    // scriptpubkey, script code of the legacy signature and script code of the BIP143 signature
+    // of the witness script: the code behind the last executed OP_CODESEPARATOR, legacy script
+    // codes without any OP_CODESEPARATOR
+    for (script, legacy_code, witness_code) in [
+        (
+            "<pubkey> CHECKSIG",
+            "<pubkey> CHECKSIG",
+            "<pubkey> CHECKSIG",
+        ),
+        (
+            "NOP CODESEPARATOR <pubkey> CHECKSIG",
+            "<pubkey> CHECKSIG",
+            "<pubkey> CHECKSIG",
+        ),
+        ("<pubkey> CODESEPARATOR CHECKSIG", "CHECKSIG", "CHECKSIG"),
+        (
+            "CODESEPARATOR <pubkey> CODESEPARATOR CHECKSIG",
+            "CHECKSIG",
+            "CHECKSIG",
+        ),
+        (
+            "0 IF CODESEPARATOR ENDIF <pubkey> CHECKSIG",
+            "0 IF ENDIF <pubkey> CHECKSIG",
+            "0 IF CODESEPARATOR ENDIF <pubkey> CHECKSIG",
+        ),
+        (
+            "<pubkey> CHECKSIGVERIFY CODESEPARATOR 1",
+            "<pubkey> CHECKSIGVERIFY 1",
+            "<pubkey> CHECKSIGVERIFY CODESEPARATOR 1",
+        ),
+    ] {
+        let script = asm(script);
+        let tx = spend(Vec::new(), script.clone(), None);
+        for (script_code, valid) in [
+            (asm(legacy_code), true),
+            (script.clone(), script == asm(legacy_code)),
+        ] {
+            let digest = serialize_legacy_tx(&tx, 0, &script_code, SighashType::All);
+            let script_sig = signature_scriptsig(&sign_digest(&digest, &key, 0x01));
+            let result = evaluate_script(&script_sig, &script, 0, &tx, &options);
+            assert_eq!(result.is_ok(), valid, "{}", disassemble_core(&script));
+        }
+
+        let program = hash_sha256(&script);
+        let tx = spend(
+            Vec::new(),
+            [[0x00, 0x20].as_slice(), &program].concat(),
+            None,
+        );
+        for (script_code, valid) in [
+            (asm(witness_code), true),
+            (script.clone(), script == asm(witness_code)),
+        ] {
+            let mut tx = tx.clone();
+            let signature = sign_segwit(&tx, &key, &script_code);
+            tx.vin[0].witness_bytes = Some(vec![signature, script.clone()]);
+            let result = verify_witness_program(&tx, 0, 0, &program, false, &options);
+            assert_eq!(result.is_ok(), valid, "{}", disassemble_core(&script));
+        }
+    }
+}
+
+#[test]
+fn disabled_opcodes_fail_in_non_executed_branches() {
+    let disabled = [
+        (0x7e, "OP_CAT"),
+        (0x7f, "OP_SUBSTR"),
+        (0x80, "OP_LEFT"),
+        (0x81, "OP_RIGHT"),
+        (0x83, "OP_INVERT"),
+        (0x84, "OP_AND"),
+        (0x85, "OP_OR"),
+        (0x86, "OP_XOR"),
+        (0x8d, "OP_2MUL"),
+        (0x8e, "OP_2DIV"),
+        (0x95, "OP_MUL"),
+        (0x96, "OP_DIV"),
+        (0x97, "OP_MOD"),
+        (0x98, "OP_LSHIFT"),
+        (0x99, "OP_RSHIFT"),
+        (0x65, "OP_VERIF"),
+        (0x66, "OP_VERNOTIF"),
+    ];
+    for (opcode, name) in disabled {
+        // 1 1 <opcode> and 1 0 IF <opcode> ENDIF
+        for script_pubkey in [
+            format!("5151{:02x}", opcode),
+            format!("510063{:02x}68", opcode),
+        ] {
+            assert_eq!(
+                run("", &script_pubkey, ScriptFlags::CONSENSUS),
+                Err(ScriptError::DisabledOpcode(name))
+            );
+        }
+    }
+    // "0 IF VERIF ENDIF 1" and "0", "IF VERNOTIF ELSE 1 ENDIF": illegal everywhere
+    assert_eq!(
+        run("", "0063656851", ScriptFlags::CONSENSUS),
+        Err(ScriptError::DisabledOpcode("OP_VERIF"))
+    );
+    assert_eq!(
+        run("00", "6366675168", ScriptFlags::CONSENSUS),
+        Err(ScriptError::DisabledOpcode("OP_VERNOTIF"))
+    );
+}
+
+#[test]
+fn upgradable_nops_fail_with_discourage_flag() {
+    // OP_NOP is no reserved NOP
+    assert_eq!(run("", "6151", ScriptFlags::STANDARD), Ok(()));
+    for opcode in [0xb0, 0xb3, 0xb4, 0xb5, 0xb6, 0xb7, 0xb8, 0xb9] {
+        let script_pubkey = format!("{:02x}51", opcode);
+        assert_eq!(run("", &script_pubkey, ScriptFlags::CONSENSUS), Ok(()));
+        assert_eq!(
+            run("", &script_pubkey, ScriptFlags::STANDARD),
+            Err(ScriptError::UpgradableNop(opcode))
+        );
+    }
+    // OP_NOP2 / OP_NOP3 without CHECKLOCKTIMEVERIFY / CHECKSEQUENCEVERIFY
+    let flags = ScriptFlags::P2SH | ScriptFlags::DISCOURAGE_UPGRADABLE_NOPS;
+    for opcode in [0xb1, 0xb2] {
+        assert_eq!(
+            run("", &format!("{:02x}51", opcode), flags),
+            Err(ScriptError::UpgradableNop(opcode))
+        );
+    }
+}
+
+#[test]
+fn trace_ends_at_the_failing_opcode() {
+    let tx = fixture();
+    // 1 0 IF 2 ENDIF 0 VERIFY
+    let script = hex::decode("5100635268006951").unwrap();
+    let (trace, result) =
+        evaluate_script_traced(&[], &script, 0, &tx, &ValidationOptions::default());
+
+    assert_eq!(result, Err(ScriptError::VerifyFailed("OP_VERIFY")));
+    let positions: Vec<usize> = trace.steps.iter().map(|step| step.position).collect();
+    assert_eq!(positions, [0, 1, 2, 3, 4, 5, 6]);
+    assert!(!trace.steps[3].executed); // OP_2 in the false branch
+    assert_eq!(trace.steps[3].stack_after, Some(vec![vec![1]]));
+    let failed = trace.steps.last().unwrap();
+    assert_eq!(failed.stack_before, [vec![1], vec![]]);
+    assert_eq!(failed.stack_after, None);
+}
+
+#[test]
+fn disassemble_core_matches_bitcoin_core_asm() {
+    let vectors = [
+        (
+            "76a91489abcdefabbaabbaabbaabbaabbaabbaabbaabba88ac",
+            "OP_DUP OP_HASH160 89abcdefabbaabbaabbaabbaabbaabbaabbaabba OP_EQUALVERIFY OP_CHECKSIG",
+        ),
+        // small pushes are script numbers, OP_0 to OP_16 and OP_1NEGATE numbers too
+        (
+            "00514f018102ff0060b1b2",
+            "0 1 -1 -1 255 16 OP_CHECKLOCKTIMEVERIFY OP_CHECKSEQUENCEVERIFY",
+        ),
+        ("0504deadbeef", "04deadbeef"),
+        ("4c0100ba", "0 OP_CHECKSIGADD"),
+        ("ac4c05ab", "OP_CHECKSIG [error]"),
+    ];
+    for (script, asm) in vectors {
+        assert_eq!(disassemble_core(&hex::decode(script).unwrap()), asm);
+    }
+}
+
+#[test]
+fn assemble_reverses_disassemble() {
+    let scripts = [
+        "76a91489abcdefabbaabbaabbaabbaabbaabbaabbaabba88ac",
+        "0014751e76e8199196d454941c45d1b3a323f1433bd6",
+        "6a4c0401020304",
+        "4d0300aabbcc4e0100000000514f60b1bb",
+    ];
+    for script in scripts {
+        let script = hex::decode(script).unwrap();
+        assert_eq!(assemble(&disassemble(&script)), Ok(script));
+    }
+    assert!(assemble("OP_PUSHBYTES_2 aa").is_err());
+    assert!(assemble("OP_DUP OP_PUSHDATA1").is_err());
+    assert!(assemble("OP_FOO").is_err());
+}
+
+// runs <locktime> OP_CLTV OP_DROP OP_1 in a transaction with the nLockTime and input sequence
+fn run_cltv(locktime_push: &str, tx_locktime: u32, sequence: u32) -> Result<(), ScriptError> {
+    let mut tx = fixture();
+    tx.locktime = tx_locktime;
+    tx.vin[0].sequence = sequence;
+    run_tx(&tx, locktime_push, "b17551", ScriptFlags::CONSENSUS)
+}
+
+#[test]
+fn checklocktimeverify_follows_bip65() {
+    // locktime push, nLockTime of the transaction
+    let satisfied = [
+        ("00", 0),
+        ("00", 499_999_999),
+        ("04ff64cd1d", 499_999_999), // 499999999, highest height
+        ("040065cd1d", 500_000_000), // 500000000, lowest time
+        ("040065cd1d", u32::MAX),
+        ("05ffffffff00", u32::MAX), // 5 byte number
+    ];
+    for (locktime, tx_locktime) in satisfied {
+        assert_eq!(run_cltv(locktime, tx_locktime, 0), Ok(()), "{}", locktime);
+    }
+    let unsatisfied = [
+        ("4f", u32::MAX),              // -1
+        ("0101", 0),                   // 1 > nLockTime
+        ("04ff64cd1d", 500_000_000),   // height against time
+        ("040065cd1d", 499_999_999),   // time against height
+        ("050000000001", u32::MAX),    // 2^32 exceeds every nLockTime
+        ("050065cd1d01", 500_000_000), // 2^32 + 500000000 isn't truncated to 32 bits
+    ];
+    for (locktime, tx_locktime) in unsatisfied {
+        assert!(
+            matches!(
+                run_cltv(locktime, tx_locktime, 0),
+                Err(ScriptError::UnsatisfiedLocktime(_))
+            ),
+            "{}",
+            locktime
+        );
+    }
+    assert!(matches!(
+        run_cltv("00", 0, 0xffffffff),
+        Err(ScriptError::UnsatisfiedLocktime(_))
+    ));
+    assert_eq!(
+        run_cltv("06000000000001", u32::MAX, 0),
+        Err(ScriptError::InvalidNumber("OP_CLTV"))
+    );
+}
+
+// runs <locktime> OP_CSV OP_DROP OP_1 in a transaction with the version and input sequence
+fn run_csv(locktime_push: &str, version: i32, sequence: u32) -> Result<(), ScriptError> {
+    let mut tx = fixture();
+    tx.version = version;
+    tx.vin[0].sequence = sequence;
+    run_tx(&tx, locktime_push, "b27551", ScriptFlags::CONSENSUS)
+}
+
+#[test]
+fn checksequenceverify_follows_bip112() {
+    // locktime push, version, input sequence
+    let satisfied = [
+        ("00", 2, 0),
+        ("0114", 2, 20), // 20 blocks
+        ("0114", 2, 0xffff),
+        ("0114", -1, 20),              // versions are compared unsigned
+        ("03140040", 2, 0x0040_0014),  // 20 * 512 seconds
+        ("050000008000", 1, u32::MAX), // disable flag in a 5 byte number
+        ("050000008001", 1, 0),        // flags and value are the lower 32 bits
+        ("05140000000f", 2, 20),
+    ];
+    for (locktime, version, sequence) in satisfied {
+        assert_eq!(run_csv(locktime, version, sequence), Ok(()), "{}", locktime);
+    }
+    let unsatisfied = [
+        ("4f", 2, 20),                 // -1
+        ("0115", 2, 20),               // 21 > 20 blocks
+        ("0114", 1, 20),               // version 1
+        ("0114", 2, 0x8000_0014),      // disable flag in the sequence
+        ("0114", 2, 0x0040_0014),      // blocks against time
+        ("03140040", 2, 20),           // time against blocks
+        ("0500000000ff", 2, u32::MAX), // negative
+    ];
+    for (locktime, version, sequence) in unsatisfied {
+        assert!(
+            matches!(
+                run_csv(locktime, version, sequence),
+                Err(ScriptError::UnsatisfiedLocktime(_))
+            ),
+            "{}",
+            locktime
+        );
+    }
+    // the locktime stays on the stack
+    let mut tx = fixture();
+    tx.vin[0].sequence = 20;
+    assert_eq!(run_tx(&tx, "0114", "b2", ScriptFlags::CONSENSUS), Ok(()));
+    assert_eq!(
+        run_tx(&tx, "0114", "b275", ScriptFlags::CONSENSUS),
+        Err(ScriptError::EvalFalse)
+    );
+}
+
+// executes the script alone under the sig version
+fn run_as(script: &str, sig_version: SigVersion, flags: ScriptFlags) -> Result<(), ScriptError> {
+    let tx = fixture();
+    let options = ValidationOptions {
+        flags,
+        ..Default::default()
+    };
+    let script = hex::decode(script).unwrap();
+    let context = InputContext::new(&tx, 0, &options);
+    let checker: Box<dyn SignatureChecker> = match sig_version {
+        SigVersion::Base => Box::new(LegacyChecker(context)),
+        SigVersion::WitnessV0 => Box::new(WitnessV0Checker(context)),
+        #[cfg(feature = "taproot")]
+        SigVersion::Tapscript => Box::new(TapscriptChecker::new(context)),
+    };
+    ScriptInterpreter::new(checker.as_ref())
+        .execute(&script, &script, VecDeque::new())
+        .map(|_| ())
+}
+
+#[test]
+fn minimal_if_applies_to_witness_scripts_with_policy() {
+    let minimal_if = ScriptFlags::CONSENSUS | ScriptFlags::MINIMALIF;
+    // <argument> OP_IF OP_1 OP_ELSE OP_1 OP_ENDIF and the same with OP_NOTIF
+    for argument in ["00", "51"] {
+        for conditional in ["63", "64"] {
+            let script = format!("{}{}51675168", argument, conditional);
+            assert_eq!(run_as(&script, SigVersion::WitnessV0, minimal_if), Ok(()));
+        }
+    }
+    for argument in NON_MINIMAL_IF_ARGUMENTS {
+        let script = format!("{}6351675168", argument);
+        assert_eq!(run_as(&script, SigVersion::Base, minimal_if), Ok(()));
+        assert_eq!(
+            run_as(&script, SigVersion::WitnessV0, ScriptFlags::CONSENSUS),
+            Ok(())
+        );
+        assert_eq!(
+            run_as(&script, SigVersion::WitnessV0, minimal_if),
+            Err(ScriptError::MinimalIf)
+        );
+    }
+}
+
+#[cfg(feature = "taproot")]
+#[test]
+fn minimal_if_is_consensus_in_tapscripts() {
+    for argument in ["00", "51"] {
+        let script = format!("{}6351675168", argument);
+        assert_eq!(
+            run_as(&script, SigVersion::Tapscript, ScriptFlags::CONSENSUS),
+            Ok(())
+        );
+    }
+    for argument in NON_MINIMAL_IF_ARGUMENTS {
+        let script = format!("{}6351675168", argument);
+        assert_eq!(
+            run_as(&script, SigVersion::Tapscript, ScriptFlags::CONSENSUS),
+            Err(ScriptError::MinimalIf)
+        );
+    }
+    // the argument of a non executed OP_IF isn't popped
+    assert_eq!(
+        run_as(
+            "52006352636868",
+            SigVersion::Tapscript,
+            ScriptFlags::CONSENSUS
+        ),
+        Ok(())
+    );
+}
+
+// returns: fixture whose first input spends a single leaf taproot output by its script path,
+// the witness is the initial stack, the leaf script and the control block
+#[cfg(feature = "taproot")]
+fn script_path_spend(leaf_version: u8, script: &str, stack: Vec<Vec<u8>>) -> Transaction {
+    let secp = Secp256k1::new();
+    let keypair = Keypair::from_seckey_slice(&secp, &[1; 32]).unwrap();
+    let (internal_key, _) = XOnlyPublicKey::from_keypair(&keypair);
+    let script = hex::decode(script).unwrap();
+    let merkle_root = tapleaf_hash(leaf_version, &script);
+    let tweak = tagged_hash(
+        "TapTweak",
+        &[&internal_key.serialize()[..], &merkle_root].concat(),
+    );
+    let tweak = Scalar::from_be_bytes(tweak.try_into().unwrap()).unwrap();
+    let (output_key, parity) = internal_key.add_tweak(&secp, &tweak).unwrap();
+
+    let mut tx = fixture();
+    let txin = &mut tx.vin[0];
+    txin.prevout.scriptpubkey_bytes = [&[0x51, 0x20][..], &output_key.serialize()].concat();
+    txin.scriptsig = None;
+    txin.scriptsig_bytes = Vec::new();
+    let control_block = [
+        &[leaf_version | parity.to_u8()][..],
+        &internal_key.serialize(),
+    ]
+    .concat();
+    let mut witness = stack;
+    witness.extend([script, control_block]);
+    txin.witness_bytes = Some(witness);
+    tx
+}
+
+#[cfg(feature = "taproot")]
+#[test]
+fn unknown_leaf_versions_are_valid_but_not_standard() {
+    let options = |flags| ValidationOptions {
+        flags,
+        ..Default::default()
+    };
+    // OP_RETURN fails the script if it is executed as tapscript
+    let tx = script_path_spend(TAPSCRIPT_LEAF_VERSION, "6a", Vec::new());
+    assert!(verify_p2tr(&tx, 0, &options(ScriptFlags::CONSENSUS)).is_err());
+    let tx = script_path_spend(0xc2, "6a", Vec::new());
+    assert_eq!(
+        verify_p2tr(&tx, 0, &options(ScriptFlags::CONSENSUS)),
+        Ok(())
+    );
+    assert_eq!(
+        verify_p2tr(&tx, 0, &options(ScriptFlags::STANDARD)),
+        Err(ValidationError::MalformedInput(
+            MalformedInput::ReservedLeafVersion(0xc2)
+        ))
+    );
+}
+
+#[cfg(feature = "taproot")]
+#[test]
+fn tapscript_signature_checks_use_up_the_validation_weight_budget() {
+    let options = ValidationOptions {
+        flags: ScriptFlags::CONSENSUS,
+        ..Default::default()
+    };
+    // <0xaa> <1 byte pubkey of unknown type> CHECKSIGVERIFY succeeds with any non empty signature
+    let checks = |count: usize| "01aa01bbad".repeat(count);
+    // budget 50 + 47 byte witness: one signature check (50) fits, two don't
+    let tx = script_path_spend(
+        TAPSCRIPT_LEAF_VERSION,
+        &format!("{}51", checks(1)),
+        Vec::new(),
+    );
+    assert_eq!(verify_p2tr(&tx, 0, &options), Ok(()));
+    let tx = script_path_spend(
+        TAPSCRIPT_LEAF_VERSION,
+        &format!("{}51", checks(2)),
+        Vec::new(),
+    );
+    assert_eq!(
+        verify_p2tr(&tx, 0, &options),
+        Err(ValidationError::ScriptError(
+            ScriptError::TapscriptValidationWeight
+        ))
+    );
+    // a dropped 64 byte witness item raises the budget to 163
+    let script = format!("75{}51", checks(2));
+    let tx = script_path_spend(TAPSCRIPT_LEAF_VERSION, &script, vec![vec![0; 64]]);
+    assert_eq!(verify_p2tr(&tx, 0, &options), Ok(()));
+    // OP_CHECKSIGADD counts like OP_CHECKSIGVERIFY, empty signatures are free
+    let script = format!("01aa0001bbba{}", "0001bbac75".repeat(10));
+    let tx = script_path_spend(TAPSCRIPT_LEAF_VERSION, &script, Vec::new());
+    assert_eq!(verify_p2tr(&tx, 0, &options), Ok(()));
+    let script = "01aa0001bbba7501aa0001bbba";
+    let tx = script_path_spend(TAPSCRIPT_LEAF_VERSION, script, Vec::new());
+    assert_eq!(
+        verify_p2tr(&tx, 0, &options),
+        Err(ValidationError::ScriptError(
+            ScriptError::TapscriptValidationWeight
+        ))
+    );
+}
+
+#[cfg(feature = "taproot")]
+#[test]
+fn taproot_hashes_are_shared_by_all_key_path_spends() {
+    // mainnet key path spends of three P2TR outputs with SIGHASH_DEFAULT and SIGHASH_ALL
+    let fixtures = parse_transactions_from_dir("test_scripts/testfiles/taproot")
+        .expect("Parsing the fixtures failed");
+    let options = ValidationOptions::default();
+    for tx in &fixtures {
+        assert!(tx.meta.taproot_hashes.get().is_none());
+        for index in 0..tx.vin.len() {
+            assert_eq!(verify_p2tr(tx, index, &options), Ok(()));
+            assert!(tx.meta.taproot_hashes.get().is_some());
+        }
+    }
+    // without ANYONECANPAY the signatures commit to the amounts, scriptpubkeys and sequences of
+    // all inputs
+    let modifications: [fn(&mut Transaction); 3] = [
+        |tx| tx.vin[2].prevout.value += 1,
+        |tx| tx.vin[2].prevout.scriptpubkey_bytes[2] ^= 1,
+        |tx| tx.vin[2].sequence ^= 1,
+    ];
+    for modify in modifications {
+        let mut tx = fixtures[0].clone();
+        tx.meta.taproot_hashes = Default::default();
+        modify(&mut tx);
+        assert!(verify_p2tr(&tx, 0, &options).is_err());
+    }
+}
+
+// legacy checker that accepts every non empty signature
+struct PlaceholderChecker<'a>(LegacyChecker<'a>);
+
+impl SignatureChecker for PlaceholderChecker<'_> {
+    fn context(&self) -> InputContext<'_> {
+        self.0.context()
+    }
+
+    fn sig_version(&self) -> SigVersion {
+        SigVersion::Base
+    }
+
+    fn check_sig(&self, signature: &[u8], _: &[u8], _: &[u8], _: u32) -> Result<bool, ScriptError> {
+        Ok(!signature.is_empty())
+    }
+
+    #[cfg(feature = "multisig")]
+    fn check_multisig(
+        &self,
+        signatures: &[Vec<u8>],
+        _: &[Vec<u8>],
+        _: &[u8],
+    ) -> Result<bool, ScriptError> {
+        Ok(signatures.iter().all(|signature| !signature.is_empty()))
+    }
+}
+
+#[test]
+fn interpreter_returns_the_stack_and_uses_the_signature_checker() {
+    let tx = fixture();
+    let options = ValidationOptions::default();
+    let context = InputContext::new(&tx, 0, &options);
+    let checker = LegacyChecker(context);
+    let interpreter = ScriptInterpreter::new(&checker);
+    // OP_1 OP_2 OP_SWAP
+    let stack = interpreter.execute(&[0x51, 0x52, 0x7c], &[], VecDeque::new());
+    assert_eq!(stack, Ok(VecDeque::from([vec![2], vec![1]])));
+
+    // <placeholder signature with SIGHASH_ALL> <pubkey> OP_CHECKSIG
+    let signature = hex::decode("300602010102010101").unwrap();
+    let stack = VecDeque::from([signature, vec![0x02; 33]]);
+    let script = [0xac];
+    let result = interpreter.execute(&script, &script, stack.clone());
+    assert_eq!(result, Ok(VecDeque::from([vec![]])));
+
+    let checker = PlaceholderChecker(LegacyChecker(context));
+    let result = ScriptInterpreter::new(&checker).execute(&script, &script, stack);
+    assert_eq!(result, Ok(VecDeque::from([vec![1]])));
+}
+
+#[test]
+fn unknown_witness_versions_are_anyone_can_spend_but_not_standard() {
+    // programs are single pushes of 2 to 40 bytes after OP_0 or OP_1 to OP_16
+    assert_eq!(
+        witness_program(&[[0x00, 0x14].as_slice(), &[7; 20]].concat()),
+        Some((0, &[7; 20][..]))
+    );
+    assert_eq!(
+        witness_program(&[0x60, 0x02, 1, 2]),
+        Some((16, &[1, 2][..]))
+    );
+    assert_eq!(
+        witness_program(&[[0x51, 0x29].as_slice(), &[7; 41]].concat()),
+        None
+    );
+    assert_eq!(witness_program(&[0x51, 0x01, 1]), None);
+    assert_eq!(witness_program(&[0x76, 0x02, 1, 2]), None);
+
+    // OP_2 <32 bytes> spent with an empty scriptsig
+    let mut tx = fixture();
+    tx.vin[0].scriptsig_bytes = Vec::new();
+    tx.vin[0].prevout.scriptpubkey_bytes = [[0x52, 0x20].as_slice(), &[7; 32]].concat();
+    let txin = &tx.vin[0];
+    let (version, program) = witness_program(&txin.prevout.scriptpubkey_bytes).unwrap();
+    assert_eq!(version, 2);
+
+    let verify = |flags| {
+        let options = ValidationOptions {
+            flags,
+            ..Default::default()
+        };
+        verify_witness_program(&tx, 0, version, program, false, &options)
+    };
+    assert_eq!(verify(ScriptFlags::CONSENSUS), Ok(()));
+    assert!(verify(ScriptFlags::STANDARD).is_err());
+}
+
+#[test]
+fn sighash_types_parse_defined_bytes_only() {
+    for sighash_type in SighashType::DEFINED {
+        assert_eq!(
+            SighashType::from_byte(sighash_type.to_byte()),
+            Some(sighash_type)
+        );
+    }
+    for byte in [0x04, 0x21, 0x41, 0x80, 0x84, 0xff] {
+        assert_eq!(SighashType::from_byte(byte), None);
+    }
+    assert_eq!(SighashType::Default.base_type(), SighashType::All);
+    assert_eq!(
+        SighashType::SingleAnyoneCanPay.base_type(),
+        SighashType::Single
+    );
+    assert!(SighashType::NoneAnyoneCanPay.anyonecanpay());
+    assert!(!SighashType::Default.anyonecanpay());
+
+    // <placeholder signature with undefined sighash type 0x04> <pubkey> CHECKSIG
+    let script_sig = format!("0930060201010201010421{}", "02".repeat(33));
+    assert_eq!(
+        run(&script_sig, "ac", ScriptFlags::CONSENSUS),
+        Err(ScriptError::Signature(
+            "OP_CHECKSIG undefined sighash type 0x04".to_string()
+        ))
+    );
+}
+
+// signs the first input of tx, spending its prevout scriptpubkey, with SIGHASH_ALL
+// returns: DER signature with sighash byte
+fn sign_first_input(tx: &Transaction, secret_key: &SecretKey) -> Vec<u8> {
+    sign_first_input_as(tx, secret_key, SighashType::All)
+}
+
+// signs the first input of tx, spending its prevout scriptpubkey, with the sighash type
+// returns: DER signature with sighash byte
+fn sign_first_input_as(tx: &Transaction, secret_key: &SecretKey, sighash: SighashType) -> Vec<u8> {
+    let digest = serialize_legacy_tx(tx, 0, &tx.vin[0].prevout.scriptpubkey_bytes, sighash);
+    sign_digest(&digest, secret_key, sighash.to_byte())
+}
+
+// returns: DER signature of the digest with the sighash byte
+fn sign_digest(digest: &[u8], secret_key: &SecretKey, sighash_byte: u8) -> Vec<u8> {
+    let message = Message::from_digest(digest.try_into().unwrap());
+    let signature = Secp256k1::new().sign_ecdsa(&message, secret_key);
+    let mut signature = signature.serialize_der().to_vec();
+    signature.push(sighash_byte);
+    signature
+}
+
+// signs the first input of tx with SIGHASH_ALL, committing to the script code like BIP143
+// returns: DER signature with sighash byte
+fn sign_segwit(tx: &Transaction, secret_key: &SecretKey, script_code: &[u8]) -> Vec<u8> {
+    let digest = get_segwit_commitment_hash(tx, 0, script_code, SighashType::All);
+    sign_digest(&digest, secret_key, 0x01)
+}
+
+// returns: the scriptsig pushing the signature
+fn signature_scriptsig(signature: &[u8]) -> Vec<u8> {
+    let mut scriptsig = Vec::new();
+    push_data(&mut scriptsig, signature);
+    scriptsig
+}
+
+// returns: fixture spending the scriptpubkey with the scriptsig prefix and a signature of the
+// secret key
+fn signed_spend(scriptpubkey: Vec<u8>, prefix: &[u8], secret_key: &SecretKey) -> Transaction {
+    let mut tx = fixture();
+    tx.vin[0].prevout.scriptpubkey_bytes = scriptpubkey;
+    let signature = sign_first_input(&tx, secret_key);
+    let mut scriptsig = prefix.to_vec();
+    scriptsig.push(signature.len() as u8);
+    scriptsig.extend(signature);
+    tx.vin[0].scriptsig = Some(hex::encode(&scriptsig));
+    tx.vin[0].scriptsig_bytes = scriptsig;
+    tx
+}
+
+// returns: fixture spending the scriptpubkey with the scriptsig and witness
+fn spend(scriptsig: Vec<u8>, scriptpubkey: Vec<u8>, witness: Option<Vec<Vec<u8>>>) -> Transaction {
+    let mut tx = fixture();
+    tx.vin[0].scriptsig = Some(hex::encode(&scriptsig));
+    tx.vin[0].scriptsig_bytes = scriptsig;
+    tx.vin[0].prevout.scriptpubkey_bytes = scriptpubkey;
+    tx.vin[0].witness = witness
+        .as_ref()
+        .map(|witness| witness.iter().map(hex::encode).collect());
+    tx.vin[0].witness_bytes = witness;
+    tx
+}
+
+// returns: the script error of a failed scriptsig, scriptpubkey, redeem or witness script
+fn script_error(result: Result<(), ValidationError>) -> Result<(), ScriptError> {
+    match result {
+        Ok(()) => Ok(()),
+        Err(ValidationError::ScriptError(error) | ValidationError::ScriptFailed { error, .. }) => {
+            Err(error)
+        }
+        Err(err) => panic!("no script error: {:?}", err),
+    }
+}
+
+#[test]
+fn none_and_single_signatures_commit_to_their_outputs() {
+    let key = SecretKey::from_slice(&[1; 32]).unwrap();
+    let pubkey = PublicKey::from_secret_key(&Secp256k1::new(), &key).serialize();
+    // <pubkey> OP_CHECKSIG spent by the first of two inputs of a transaction with two outputs
+    let options = ValidationOptions::standard_sighash_types();
+    let mut unsigned = fixture();
+    unsigned.vin[0].prevout.scriptpubkey_bytes = [[0x21].as_slice(), &pubkey, &[0xac]].concat();
+    let mut second_input = unsigned.vin[0].clone();
+    second_input.vout += 1;
+    unsigned.vin.push(second_input);
+    let mut second_output = unsigned.vout[0].clone();
+    second_output.value += 1;
+    unsigned.vout.truncate(1);
+    unsigned.vout.push(second_output);
+
+    let modifications: [fn(&mut Transaction); 4] = [
+        |tx| tx.vout[0].value += 1, // the output at the input index
+        |tx| tx.vout[1].value += 1,
+        |tx| tx.vin[1].sequence -= 1,
+        |tx| {
+            let mut input = tx.vin[1].clone();
+            input.vout += 1;
+            tx.vin.push(input);
+        },
+    ];
+    // signature stays valid after the modifications
+    for (sighash, valid) in [
+        (SighashType::All, [false, false, false, false]),
+        (SighashType::None, [true, true, true, false]),
+        (SighashType::Single, [false, true, true, false]),
+        (SighashType::AllAnyoneCanPay, [false, false, true, true]),
+        (SighashType::NoneAnyoneCanPay, [true, true, true, true]),
+        (SighashType::SingleAnyoneCanPay, [false, true, true, true]),
+    ] {
+        let signature = sign_first_input_as(&unsigned, &key, sighash);
+        let mut signed = unsigned.clone();
+        signed.vin[0].scriptsig_bytes = signature_scriptsig(&signature);
+        assert_eq!(verify_p2pk(&signed, 0, &options), Ok(()));
+        for (modify, valid) in modifications.iter().zip(valid) {
+            let mut tx = signed.clone();
+            modify(&mut tx);
+            let result = verify_p2pk(&tx, 0, &options);
+            assert_eq!(result.is_ok(), valid, "{:?}", sighash);
+        }
+    }
+
+    // SIGHASH_SINGLE without output at the input index signs the uint256 1
+    let mut tx = unsigned;
+    tx.vout.clear();
+    let one =
+        hex::decode("0100000000000000000000000000000000000000000000000000000000000000").unwrap();
+    for (sighash_byte, valid) in [(0x03, true), (0x83, true), (0x01, false)] {
+        tx.vin[0].scriptsig_bytes = signature_scriptsig(&sign_digest(&one, &key, sighash_byte));
+        let result = verify_p2pk(&tx, 0, &options);
+        assert_eq!(result.is_ok(), valid, "{:#04x}", sighash_byte);
+    }
+}
+
+#[test]
+fn p2pk_and_bare_multisig_inputs_verify() {
+    let secp = Secp256k1::new();
+    let keys: Vec<SecretKey> = (1..=4)
+        .map(|byte| SecretKey::from_slice(&[byte; 32]).unwrap())
+        .collect();
+    let pubkeys: Vec<Vec<u8>> = keys
+        .iter()
+        .map(|key| PublicKey::from_secret_key(&secp, key).serialize().to_vec())
+        .collect();
+    let options = |flags| ValidationOptions {
+        flags,
+        ..Default::default()
+    };
+
+    // <pubkey> OP_CHECKSIG
+    let p2pk = [[0x21].as_slice(), &pubkeys[0], &[0xac]].concat();
+    let tx = signed_spend(p2pk.clone(), &[], &keys[0]);
+    let result = verify_p2pk(&tx, 0, &options(ScriptFlags::STANDARD));
+    assert_eq!(result, Ok(()));
+    let tx = signed_spend(p2pk, &[], &keys[1]);
+    let result = verify_p2pk(&tx, 0, &options(ScriptFlags::STANDARD));
+    assert!(result.is_err());
+
+    // OP_0 <signature> | OP_1 <pubkeys> OP_n OP_CHECKMULTISIG signed by the last key, more than 3
+    // public keys are non standard
+    #[cfg(feature = "multisig")]
+    for (count, standard) in [(3, true), (4, false)] {
+        let mut multisig = vec![0x51];
+        for pubkey in &pubkeys[..count] {
+            multisig.push(0x21);
+            multisig.extend(pubkey);
+        }
+        multisig.extend([0x50 + count as u8, 0xae]);
+        let tx = signed_spend(multisig, &[0x00], &keys[count - 1]);
+        let result = verify_bare_multisig(&tx, 0, &options(ScriptFlags::CONSENSUS));
+        assert_eq!(result, Ok(()));
+        let result = verify_bare_multisig(&tx, 0, &options(ScriptFlags::STANDARD));
+        assert_eq!(result.is_ok(), standard);
+    }
+}
+
+#[test]
+fn p2sh_inputs_execute_the_redeem_script() {
+    let options = |flags| ValidationOptions {
+        flags,
+        ..Default::default()
+    };
+    let run_p2sh = |script_sig, script_pubkey, flags| {
+        let tx = spend(core_script(script_sig), core_script(script_pubkey), None);
+        verify_p2sh(&tx, 0, &options(flags))
+    };
+    // P2SH vectors of script_tests.json, without the P2SH flag only the hash is checked
+    let vectors: [CoreVector; 4] = [
+        (
+            "0x01 0x51",
+            "HASH160 0x14 0xda1745e9b549bd0bfa1a569971c77eba30cd5a4b EQUAL",
+            Ok(()),
+        ),
+        (
+            "0x01 0x50",
+            "HASH160 0x14 0xece424a6bb6ddf4db592c0faed60685047a361b1 EQUAL",
+            Err(ScriptError::UnknownOpcode(0x50)), // OP_RESERVED in the redeem script
+        ),
+        (
+            "0x01 0x62",
+            "HASH160 0x14 0x0f4d7845db968f2a81b530b6f3c1d6246d4c7e01 EQUAL",
+            Err(ScriptError::UnknownOpcode(0x62)), // OP_VER in the redeem script
+        ),
+        (
+            "0",
+            "HASH160 0x14 0xb472a266d0bd89c13706a4132ccfb16f7c3b9fcb EQUAL",
+            Err(ScriptError::EvalFalse), // empty redeem script
+        ),
+    ];
+    for (script_sig, script_pubkey, expected) in vectors {
+        let result = run_p2sh(script_sig, script_pubkey, ScriptFlags::CONSENSUS);
+        assert_eq!(script_error(result), expected, "{}", script_sig);
+        let result = run_p2sh(script_sig, script_pubkey, ScriptFlags::NONE);
+        assert_eq!(result, Ok(()), "{}", script_sig);
+    }
+    // 1 | 1 left on the stack by the redeem script 1
+    let (script_sig, script_pubkey) = (
+        "1 0x01 0x51",
+        "HASH160 0x14 0xda1745e9b549bd0bfa1a569971c77eba30cd5a4b EQUAL",
+    );
+    assert_eq!(
+        run_p2sh(script_sig, script_pubkey, ScriptFlags::CONSENSUS),
+        Ok(())
+    );
+    let result = run_p2sh(script_sig, script_pubkey, ScriptFlags::STANDARD);
+    assert_eq!(script_error(result), Err(ScriptError::CleanStack));
+    // the scriptsig has to be push only with the P2SH flag
+    let (script_sig, script_pubkey) = (
+        "NOP 0x01 0x51",
+        "HASH160 0x14 0xda1745e9b549bd0bfa1a569971c77eba30cd5a4b EQUAL",
+    );
+    assert_eq!(
+        run_p2sh(script_sig, script_pubkey, ScriptFlags::CONSENSUS),
+        Err(ValidationError::MalformedInput(
+            MalformedInput::P2shNotPushOnly
+        ))
+    );
+    assert_eq!(
+        run_p2sh(script_sig, script_pubkey, ScriptFlags::NONE),
+        Ok(())
+    );
+    // a redeem script that doesn't match the hash
+    let result = run_p2sh(
+        "0x01 0x52",
+        "HASH160 0x14 0xda1745e9b549bd0bfa1a569971c77eba30cd5a4b EQUAL",
+        ScriptFlags::NONE,
+    );
+    assert!(matches!(
+        result,
+        Err(ValidationError::MalformedInput(
+            MalformedInput::RedeemScriptHash { .. }
+        ))
+    ));
+
+    // <signature> <redeem script> | P2SH of <pubkey> OP_CHECKSIG, the signature commits to the
+    // redeem script as script code
+    let key = SecretKey::from_slice(&[1; 32]).unwrap();
+    let pubkey = PublicKey::from_secret_key(&Secp256k1::new(), &key).serialize();
+    let redeem_script = [[0x21].as_slice(), &pubkey, &[0xac]].concat();
+    let script_pubkey = [[0xa9, 0x14].as_slice(), &hash160(&redeem_script), &[0x87]].concat();
+    for (script_code, valid) in [(&redeem_script, true), (&script_pubkey, false)] {
+        let mut tx = spend(Vec::new(), script_pubkey.clone(), None);
+        let digest = serialize_legacy_tx(&tx, 0, script_code, SighashType::All);
+        let mut script_sig = signature_scriptsig(&sign_digest(&digest, &key, 0x01));
+        push_data(&mut script_sig, &redeem_script);
+        tx.vin[0].scriptsig_bytes = script_sig;
+        let result = verify_p2sh(&tx, 0, &options(ScriptFlags::STANDARD));
+        assert_eq!(result.is_ok(), valid);
+    }
+}
+
+#[test]
+fn p2sh_wrapped_witness_programs_verify_like_native_ones() {
+    let options = |flags| ValidationOptions {
+        flags,
+        ..Default::default()
+    };
+    let key = SecretKey::from_slice(&[1; 32]).unwrap();
+    let pubkey = PublicKey::from_secret_key(&Secp256k1::new(), &key)
+        .serialize()
+        .to_vec();
+    let witness_script = [[0x21].as_slice(), &pubkey, &[0xac]].concat();
+    // P2SH-P2WPKH and P2SH-P2WSH of <pubkey> OP_CHECKSIG with their BIP143 script code and the
+    // witness after the signature
+    let p2wpkh = [[0x00, 0x14].as_slice(), &hash160(&pubkey)].concat();
+    let p2wsh = [[0x00, 0x20].as_slice(), &hash_sha256(&witness_script)].concat();
+    for (redeem_script, script_code, witness_item) in [
+        (p2wpkh, p2wpkh_scriptcode(&hash160(&pubkey)), pubkey.clone()),
+        (p2wsh, witness_script.clone(), witness_script.clone()),
+    ] {
+        let script_pubkey = [[0xa9, 0x14].as_slice(), &hash160(&redeem_script), &[0x87]].concat();
+        let mut script_sig = Vec::new();
+        push_data(&mut script_sig, &redeem_script);
+        let spend_with = |script_sig: &[u8], signature: Option<Vec<u8>>| {
+            let mut tx = spend(script_sig.to_vec(), script_pubkey.clone(), None);
+            let signature = signature.unwrap_or_else(|| sign_segwit(&tx, &key, &script_code));
+            tx.vin[0].witness_bytes = Some(vec![signature, witness_item.clone()]);
+            tx
+        };
+
+        let tx = spend_with(&script_sig, None);
+        assert_eq!(verify_p2sh(&tx, 0, &options(ScriptFlags::STANDARD)), Ok(()));
+        // a legacy signature of the script code
+        let tx = spend(script_sig.clone(), script_pubkey.clone(), None);
+        let digest = serialize_legacy_tx(&tx, 0, &script_code, SighashType::All);
+        let tx = spend_with(&script_sig, Some(sign_digest(&digest, &key, 0x01)));
+        assert!(verify_p2sh(&tx, 0, &options(ScriptFlags::STANDARD)).is_err());
+        // the scriptsig has to be the single push of the redeem script
+        let tx = spend_with(&[[0x51].as_slice(), &script_sig].concat(), None);
+        assert_eq!(
+            verify_p2sh(&tx, 0, &options(ScriptFlags::CONSENSUS)),
+            Err(ValidationError::MalformedInput(
+                MalformedInput::WrappedSegwitScriptsig
+            ))
+        );
+        // without witness the program is anyone can spend before segwit
+        let tx = spend(script_sig.clone(), script_pubkey.clone(), None);
+        assert_eq!(verify_p2sh(&tx, 0, &options(ScriptFlags::P2SH)), Ok(()));
+        assert_eq!(
+            verify_p2sh(&tx, 0, &options(ScriptFlags::CONSENSUS)),
+            Err(ValidationError::MalformedInput(
+                MalformedInput::MissingWitness
+            ))
+        );
+    }
+}
+
+#[test]
+fn p2wsh_inputs_execute_the_witness_script() {
+    let options = |flags| ValidationOptions {
+        flags,
+        ..Default::default()
+    };
+    let run_p2wsh = |witness: &[&str], script_pubkey, flags| {
+        let witness = witness.iter().map(|item| hex::decode(item).unwrap());
+        let script_pubkey = core_script(script_pubkey);
+        let tx = spend(Vec::new(), script_pubkey.clone(), Some(witness.collect()));
+        let (version, program) = witness_program(&script_pubkey).unwrap();
+        verify_witness_program(&tx, 0, version, program, false, &options(flags))
+    };
+    // witness and scriptpubkey of the P2WSH vectors of script_tests.json, the witness script is
+    // the last item. A clean stack is consensus in witness scripts.
+    let op_true = "0 0x20 0x4ae81572f06e1b88fd5ced7a1a000945432e83e1551e6f721ee9c00b8cc33260";
+    let op_false = "0 0x20 0x6e340b9cffb37a989ca544e6bb780a2c78901d3fb33738768511a30617afa01d";
+    let vectors: [(&[&str], &str, Result<(), ScriptError>); 4] = [
+        (&["51"], op_true, Ok(())),
+        (&["51", "51"], op_true, Err(ScriptError::CleanStack)),
+        (&["00"], op_false, Err(ScriptError::EvalFalse)),
+        (&["", "00"], op_false, Err(ScriptError::CleanStack)), // checked before the top item
+    ];
+    for (witness, script_pubkey, expected) in vectors {
+        let result = run_p2wsh(witness, script_pubkey, ScriptFlags::CONSENSUS);
+        assert_eq!(script_error(result), expected, "{:?}", witness);
+        // anyone can spend before segwit
+        let result = run_p2wsh(witness, script_pubkey, ScriptFlags::P2SH);
+        assert_eq!(result, Ok(()), "{:?}", witness);
+    }
+    // witness script that doesn't match the program
+    let result = run_p2wsh(&["52"], op_true, ScriptFlags::CONSENSUS);
+    assert!(matches!(
+        result,
+        Err(ValidationError::MalformedInput(
+            MalformedInput::WitnessScriptHash { .. }
+        ))
+    ));
+    let result = run_p2wsh(&[], op_true, ScriptFlags::CONSENSUS);
+    assert_eq!(
+        result,
+        Err(ValidationError::MalformedInput(
+            MalformedInput::MissingWitness
+        ))
+    );
+
+    // <signature> <witness script> | 0 <sha256(<pubkey> OP_CHECKSIG)>, the BIP143 message commits
+    // to the witness script with its length prefix
+    let key = SecretKey::from_slice(&[1; 32]).unwrap();
+    let pubkey = PublicKey::from_secret_key(&Secp256k1::new(), &key).serialize();
+    let witness_script = [[0x21].as_slice(), &pubkey, &[0xac]].concat();
+    let program = hash_sha256(&witness_script);
+    let script_pubkey = [[0x00, 0x20].as_slice(), &program].concat();
+    let prefixed_script = [[witness_script.len() as u8].as_slice(), &witness_script].concat();
+    let tx = spend(Vec::new(), script_pubkey, None);
+    let legacy_digest = serialize_legacy_tx(&tx, 0, &witness_script, SighashType::All);
+    for (signature, valid) in [
+        (sign_segwit(&tx, &key, &witness_script), true),
+        (sign_segwit(&tx, &key, &prefixed_script), false),
+        (sign_digest(&legacy_digest, &key, 0x01), false),
+    ] {
+        let mut tx = tx.clone();
+        tx.vin[0].witness_bytes = Some(vec![signature, witness_script.clone()]);
+        let result =
+            verify_witness_program(&tx, 0, 0, &program, false, &options(ScriptFlags::STANDARD));
+        assert_eq!(result.is_ok(), valid);
+    }
+    // native witness programs need an empty scriptsig
+    let mut tx = tx.clone();
+    tx.vin[0].scriptsig_bytes = vec![0x51];
+    tx.vin[0].witness_bytes = Some(vec![
+        sign_segwit(&tx, &key, &witness_script),
+        witness_script,
+    ]);
+    assert_eq!(
+        verify_witness_program(&tx, 0, 0, &program, false, &options(ScriptFlags::STANDARD)),
+        Err(ValidationError::MalformedInput(
+            MalformedInput::NonEmptyScriptsig("Native segwit")
+        ))
+    );
+}
+
+#[test]
+fn multisig_sigops_are_accurate_only_in_redeem_and_witness_scripts() {
+    // OP_2 <pubkey> <pubkey> OP_2 OP_CHECKMULTISIG, OP_CHECKSIG, OP_CHECKSIGVERIFY
+    let key = format!("21{}", "02".repeat(33));
+    let multisig = hex::decode(format!("52{}{}52ae", key, key)).unwrap();
+    assert_eq!(count_sigops(&multisig, false), 20);
+    assert_eq!(count_sigops(&multisig, true), 2);
+    assert_eq!(count_sigops(&hex::decode("acad").unwrap(), false), 2);
+    // pushed opcode bytes aren't counted, a truncated push ends the script
+    assert_eq!(count_sigops(&hex::decode("02acae").unwrap(), false), 0);
+    assert_eq!(count_sigops(&hex::decode("ac4c05ac").unwrap(), false), 1);
+}
+
+#[test]
+fn witnesses_have_to_match_the_marker_and_the_spent_programs() {
+    let mut tx = fixture();
+    assert_eq!(validate_witness_consistency(&tx, true), Ok(()));
+
+    // marker and flag serialized for an empty witness
+    tx.vin[0].witness = Some(Vec::new());
+    tx.vin[0].witness_bytes = Some(Vec::new());
+    assert_eq!(
+        validate_witness_consistency(&tx, false),
+        Err(WitnessError::AllEmpty)
+    );
+    assert_eq!(
+        tx.validate(&ValidationOptions::default()),
+        Err(ValidationError::Witness(WitnessError::AllEmpty))
+    );
+
+    // witness on a P2PKH input, valid before segwit
+    tx.vin[0].witness = Some(vec!["01".to_string()]);
+    tx.vin[0].witness_bytes = Some(vec![vec![0x01]]);
+    assert_eq!(validate_witness_consistency(&tx, false), Ok(()));
+    assert_eq!(
+        validate_witness_consistency(&tx, true),
+        Err(WitnessError::WithoutProgram(0))
+    );
+
+    // OP_0 <20 bytes> as P2SH redeem script may carry a witness
+    let redeem_script = [[0x00, 0x14].as_slice(), &[7; 20]].concat();
+    tx.vin[0].scriptsig_bytes = [[0x16].as_slice(), &redeem_script].concat();
+    tx.vin[0].prevout.scriptpubkey_bytes = [[0xa9, 0x14].as_slice(), &[0; 20], &[0x87]].concat();
+    assert_eq!(validate_witness_consistency(&tx, true), Ok(()));
+}
+
+#[test]
+fn prevouts_have_to_match_their_claimed_type() {
+    let mut tx = fixture();
+    assert_eq!(find_prevout_type_mismatch(&tx), None);
+
+    let p2wpkh = [[0x00, 0x14].as_slice(), &[7; 20]].concat();
+    let p2sh = [[0xa9, 0x14].as_slice(), &[7; 20], &[0x87]].concat();
+    let multisig = [[0x51, 0x21].as_slice(), &[2; 33], &[0x51, 0xae]].concat();
+    assert_eq!(scriptpubkey_type(&p2wpkh), "v0_p2wpkh");
+    assert_eq!(scriptpubkey_type(&p2sh), "p2sh");
+    assert_eq!(scriptpubkey_type(&multisig), "multisig");
+    assert_eq!(scriptpubkey_type(&multisig[..36]), "unknown");
+
+    // P2SH scriptpubkey claimed as p2pkh would be verified as P2PKH
+    tx.vin[0].prevout.scriptpubkey_bytes = p2sh;
+    assert_eq!(find_prevout_type_mismatch(&tx), Some((0, "p2sh")));
+    let failures = tx.validate_all(&ValidationOptions::default());
+    assert!(failures.iter().any(|error| matches!(
+        error,
+        ValidationError::PrevoutType {
+            index: 0,
+            actual: "p2sh",
+            ..
+        }
+    )));
+
+    // esplora reports bare multisig as unknown
+    tx.vin[0].prevout.scriptpubkey_bytes = multisig;
+    tx.vin[0].prevout.scriptpubkey_type = "unknown".to_string();
+    assert_eq!(find_prevout_type_mismatch(&tx), None);
+}
@@ -0,0 +1,76 @@
+// Trace of a script execution for debugging rejected transactions: every opcode the interpreter
+// steps over is recorded with the stack before and after it. The step an error occurred at has
+// no stack after it, the error itself is returned next to the trace.
+
+use super::script::evaluate_script_traced;
+use super::script_asm::opcode_name;
+use super::script_error::ScriptError;
+use super::ValidationOptions;
+use crate::parsing::transaction_structs::Transaction;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceStep {
+    pub position: usize, // byte index of the opcode in the executed script
+    pub opcode: u8,
+    pub executed: bool, // false in a non executed OP_IF branch
+    pub stack_before: Vec<Vec<u8>>,
+    pub stack_after: Option<Vec<Vec<u8>>>, // None if the opcode failed
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ScriptTrace {
+    pub steps: Vec<TraceStep>,
+}
+
+// formats a stack bottom to top as hex items, the empty item as 0 (like OP_0 in ASM)
+fn format_stack(stack: &[Vec<u8>]) -> String {
+    let items: Vec<String> = stack
+        .iter()
+        .map(|item| match item.is_empty() {
+            true => "0".to_string(),
+            false => hex::encode(item),
+        })
+        .collect();
+    format!("[{}]", items.join(" "))
+}
+
+// one line per step: position, opcode, stack before -> stack after (or FAILED)
+impl fmt::Display for ScriptTrace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for step in &self.steps {
+            let after = match &step.stack_after {
+                Some(stack) => format_stack(stack),
+                None => "FAILED".to_string(),
+            };
+            writeln!(
+                f,
+                "{:>5} {:<20}{} {} -> {}",
+                step.position,
+                opcode_name(step.opcode),
+                if step.executed { "" } else { " (skipped)" },
+                format_stack(&step.stack_before),
+                after
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// traces the legacy evaluation of the input: scriptsig and then the scriptpubkey, as
+// evaluate_script() executes P2PKH inputs. Redeem scripts and witness scripts aren't traced.
+// returns: ScriptTrace and the result of the evaluation
+pub fn trace_input(
+    tx: &Transaction,
+    input_index: usize,
+    options: &ValidationOptions,
+) -> (ScriptTrace, Result<(), ScriptError>) {
+    let txin = &tx.vin[input_index];
+    evaluate_script_traced(
+        &txin.scriptsig_bytes,
+        &txin.prevout.scriptpubkey_bytes,
+        input_index,
+        tx,
+        options,
+    )
+}
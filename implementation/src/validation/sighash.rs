@@ -0,0 +1,70 @@
+// Sighash type of a signature: the trailing byte of ECDSA signatures and 65 byte schnorr
+// signatures selects the parts of the transaction the signature commits to. Only the defined
+// combinations of ALL, NONE and SINGLE with ANYONECANPAY are parsed, DEFAULT (0x00) is the
+// type of 64 byte taproot signatures without sighash byte.
+
+use super::{SIGHASH_ALL, SIGHASH_ANYONECANPAY, SIGHASH_NONE, SIGHASH_SINGLE};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SighashType {
+    Default, // taproot only, commits like All
+    All,
+    None,
+    Single,
+    AllAnyoneCanPay,
+    NoneAnyoneCanPay,
+    SingleAnyoneCanPay,
+}
+
+impl SighashType {
+    // all defined sighash types, the ECDSA standard types follow Default
+    pub const DEFINED: [SighashType; 7] = [
+        SighashType::Default,
+        SighashType::All,
+        SighashType::None,
+        SighashType::Single,
+        SighashType::AllAnyoneCanPay,
+        SighashType::NoneAnyoneCanPay,
+        SighashType::SingleAnyoneCanPay,
+    ];
+
+    // returns: SighashType of the sighash byte or None if the byte is no defined type
+    pub fn from_byte(byte: u8) -> Option<SighashType> {
+        Self::DEFINED
+            .into_iter()
+            .find(|sighash_type| sighash_type.to_byte() == byte)
+    }
+
+    // returns: sighash byte, the signature hashes commit to it
+    pub fn to_byte(self) -> u8 {
+        let byte = match self {
+            SighashType::Default => 0x00,
+            SighashType::All => SIGHASH_ALL,
+            SighashType::None => SIGHASH_NONE,
+            SighashType::Single => SIGHASH_SINGLE,
+            SighashType::AllAnyoneCanPay => SIGHASH_ALL | SIGHASH_ANYONECANPAY,
+            SighashType::NoneAnyoneCanPay => SIGHASH_NONE | SIGHASH_ANYONECANPAY,
+            SighashType::SingleAnyoneCanPay => SIGHASH_SINGLE | SIGHASH_ANYONECANPAY,
+        };
+        byte as u8
+    }
+
+    // returns: true if only the signing input is committed to
+    pub fn anyonecanpay(self) -> bool {
+        matches!(
+            self,
+            SighashType::AllAnyoneCanPay
+                | SighashType::NoneAnyoneCanPay
+                | SighashType::SingleAnyoneCanPay
+        )
+    }
+
+    // returns: All, None or Single selecting the committed outputs (Default commits like All)
+    pub fn base_type(self) -> SighashType {
+        match self {
+            SighashType::None | SighashType::NoneAnyoneCanPay => SighashType::None,
+            SighashType::Single | SighashType::SingleAnyoneCanPay => SighashType::Single,
+            _ => SighashType::All,
+        }
+    }
+}
@@ -0,0 +1,316 @@
+// Checks of the opcodes that depend on the spending transaction: the signatures of OP_CHECKSIG,
+// OP_CHECKMULTISIG and OP_CHECKSIGADD and the locktimes of OP_CHECKLOCKTIMEVERIFY and
+// OP_CHECKSEQUENCEVERIFY. The interpreter executes the scripts of every context the same way and
+// leaves the signature scheme to the SignatureChecker of the context: LegacyChecker (scriptpubkey
+// and redeem script), WitnessV0Checker (BIP143 witness script) and TapscriptChecker (BIP341/342,
+// taproot.rs).
+
+use super::script::{remove_codeseparators, serialize_legacy_tx, SigVersion};
+use super::script_error::ScriptError;
+use super::sighash::SighashType;
+use super::signature_encoding::check_signature_encoding;
+use super::signature_verification::{get_segwit_commitment_hash, verify_ecdsa_signature};
+use super::ValidationOptions;
+use crate::mining::timelock::{
+    LOCKTIME_THRESHOLD, SEQUENCE_FINAL, SEQUENCE_LOCKTIME_DISABLE_FLAG, SEQUENCE_LOCKTIME_MASK,
+    SEQUENCE_LOCKTIME_TYPE_FLAG,
+};
+use crate::parsing::transaction_structs::{Transaction, TxIn};
+
+// input whose scripts are executed and the options they are verified under. The signature
+// messages use the input index, identical inputs are distinguished by it.
+#[derive(Clone, Copy)]
+pub struct InputContext<'a> {
+    pub tx: &'a Transaction,
+    pub input_index: usize,
+    pub txin: &'a TxIn, // input at input_index
+    pub options: &'a ValidationOptions,
+}
+
+impl<'a> InputContext<'a> {
+    pub fn new(tx: &'a Transaction, input_index: usize, options: &'a ValidationOptions) -> Self {
+        InputContext {
+            tx,
+            input_index,
+            txin: &tx.vin[input_index],
+            options,
+        }
+    }
+}
+
+pub trait SignatureChecker {
+    // returns: InputContext the checks are done against
+    fn context(&self) -> InputContext<'_>;
+
+    // returns: signature message scheme of the checker, selects the script rules of the context
+    fn sig_version(&self) -> SigVersion;
+
+    // OP_CHECKSIG(VERIFY) and OP_CHECKSIGADD: checks the signature (with sighash byte) against
+    // the public key. script_code is the script the signature commits to, codesep_pos the opcode
+    // position of the last executed OP_CODESEPARATOR (0xffffffff if none).
+    // returns: Ok(true) for a valid signature, Ok(false) for a failing check that doesn't abort
+    // the script or Err if the script fails
+    fn check_sig(
+        &self,
+        signature: &[u8],
+        pubkey: &[u8],
+        script_code: &[u8],
+        codesep_pos: u32,
+    ) -> Result<bool, ScriptError>;
+
+    // OP_CHECKMULTISIG(VERIFY): every signature has to match one of the public keys, in the
+    // order of the public keys (signatures and pubkeys bottom to top of the stack)
+    // returns: Ok(true) if all signatures match, Ok(false) or Err if the script fails
+    #[cfg(feature = "multisig")]
+    fn check_multisig(
+        &self,
+        signatures: &[Vec<u8>],
+        pubkeys: &[Vec<u8>],
+        script_code: &[u8],
+    ) -> Result<bool, ScriptError>;
+
+    // BIP65: the locktime (script number of up to 5 bytes, locktimes above 2^31 - 1 don't fit
+    // 4 bytes) has to be of the same type (height below LOCKTIME_THRESHOLD, else time) as the
+    // nLockTime of the transaction and reached by it, the input mustn't be final
+    // returns: Ok() or ScriptError::UnsatisfiedLocktime
+    fn check_locktime(&self, locktime: i128) -> Result<(), ScriptError> {
+        let InputContext { tx, txin, .. } = self.context();
+        let unsatisfied = |reason: String| Err(ScriptError::UnsatisfiedLocktime(reason));
+
+        if locktime < 0 {
+            return unsatisfied(format!("OP_CLTV negative locktime {}", locktime));
+        };
+        let threshold = LOCKTIME_THRESHOLD as i128;
+        let tx_locktime = tx.locktime as i128;
+        if (locktime < threshold) != (tx_locktime < threshold) {
+            return unsatisfied(format!(
+                "OP_CLTV locktime {} and nLockTime {} of different types",
+                locktime, tx.locktime
+            ));
+        }
+        if locktime > tx_locktime {
+            return unsatisfied(format!(
+                "OP_CLTV nLockTime {} < locktime {}",
+                tx.locktime, locktime
+            ));
+        }
+        if txin.sequence == SEQUENCE_FINAL {
+            return unsatisfied("OP_CLTV in sequence is 0xffffffff".to_string());
+        }
+        Ok(())
+    }
+
+    // BIP112: the sequence (script number of up to 5 bytes) is a relative locktime unless its
+    // disable flag is set. A relative locktime has to be of the same type (blocks or 512 second
+    // units) as the BIP68 lock of the input sequence and at most its value, which requires a
+    // version 2 transaction and a sequence without disable flag.
+    // returns: Ok() or ScriptError::UnsatisfiedLocktime
+    fn check_sequence(&self, sequence: i128) -> Result<(), ScriptError> {
+        let InputContext { tx, txin, .. } = self.context();
+        let unsatisfied = |reason: String| Err(ScriptError::UnsatisfiedLocktime(reason));
+
+        if sequence < 0 {
+            return unsatisfied(format!("OP_CSV negative locktime {}", sequence));
+        };
+        // the flags and the lock value are in the lower 32 bits
+        let locktime = sequence as u32;
+        if locktime & SEQUENCE_LOCKTIME_DISABLE_FLAG != 0 {
+            return Ok(());
+        }
+        // the version is compared unsigned, negative versions are above 2
+        if (tx.version as u32) < 2 {
+            return unsatisfied(format!("OP_CSV transaction version {} < 2", tx.version));
+        };
+        if txin.sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG != 0 {
+            return unsatisfied(format!(
+                "OP_CSV disable flag set in sequence {:#010x}",
+                txin.sequence
+            ));
+        };
+        if locktime & SEQUENCE_LOCKTIME_TYPE_FLAG != txin.sequence & SEQUENCE_LOCKTIME_TYPE_FLAG {
+            return unsatisfied(format!(
+                "OP_CSV locktime {:#x} and sequence {:#010x} of different types",
+                locktime, txin.sequence
+            ));
+        };
+        if locktime & SEQUENCE_LOCKTIME_MASK > txin.sequence & SEQUENCE_LOCKTIME_MASK {
+            return unsatisfied(format!(
+                "OP_CSV sequence lock {} < locktime {}",
+                txin.sequence & SEQUENCE_LOCKTIME_MASK,
+                locktime & SEQUENCE_LOCKTIME_MASK
+            ));
+        };
+        Ok(())
+    }
+}
+
+// legacy signatures (scriptpubkey, redeem script) commit to the script code without its
+// OP_CODESEPARATOR opcodes
+pub struct LegacyChecker<'a>(pub InputContext<'a>);
+
+// BIP143 signatures of witness scripts (and the p2wpkh scriptcode)
+pub struct WitnessV0Checker<'a>(pub InputContext<'a>);
+
+impl LegacyChecker<'_> {
+    fn message(&self, script_code: &[u8], sighash: SighashType) -> Vec<u8> {
+        let InputContext {
+            tx, input_index, ..
+        } = self.0;
+        serialize_legacy_tx(
+            tx,
+            input_index,
+            &remove_codeseparators(script_code),
+            sighash,
+        )
+    }
+}
+
+impl WitnessV0Checker<'_> {
+    fn message(&self, script_code: &[u8], sighash: SighashType) -> Vec<u8> {
+        get_segwit_commitment_hash(self.0.tx, self.0.input_index, script_code, sighash)
+    }
+}
+
+impl SignatureChecker for LegacyChecker<'_> {
+    fn context(&self) -> InputContext<'_> {
+        self.0
+    }
+
+    fn sig_version(&self) -> SigVersion {
+        SigVersion::Base
+    }
+
+    fn check_sig(
+        &self,
+        signature: &[u8],
+        pubkey: &[u8],
+        script_code: &[u8],
+        _codesep_pos: u32,
+    ) -> Result<bool, ScriptError> {
+        let message = |sighash| self.message(script_code, sighash);
+        check_ecdsa_sig(self.0.options, signature, pubkey, message)
+    }
+
+    #[cfg(feature = "multisig")]
+    fn check_multisig(
+        &self,
+        signatures: &[Vec<u8>],
+        pubkeys: &[Vec<u8>],
+        script_code: &[u8],
+    ) -> Result<bool, ScriptError> {
+        let message = |sighash| self.message(script_code, sighash);
+        check_ecdsa_multisig(self.0.options, signatures, pubkeys, message)
+    }
+}
+
+impl SignatureChecker for WitnessV0Checker<'_> {
+    fn context(&self) -> InputContext<'_> {
+        self.0
+    }
+
+    fn sig_version(&self) -> SigVersion {
+        SigVersion::WitnessV0
+    }
+
+    fn check_sig(
+        &self,
+        signature: &[u8],
+        pubkey: &[u8],
+        script_code: &[u8],
+        _codesep_pos: u32,
+    ) -> Result<bool, ScriptError> {
+        let message = |sighash| self.message(script_code, sighash);
+        check_ecdsa_sig(self.0.options, signature, pubkey, message)
+    }
+
+    #[cfg(feature = "multisig")]
+    fn check_multisig(
+        &self,
+        signatures: &[Vec<u8>],
+        pubkeys: &[Vec<u8>],
+        script_code: &[u8],
+    ) -> Result<bool, ScriptError> {
+        let message = |sighash| self.message(script_code, sighash);
+        check_ecdsa_multisig(self.0.options, signatures, pubkeys, message)
+    }
+}
+
+// signature message and DER signature (without sighash byte) of an ECDSA signature
+type SignedMessage<'s> = (Vec<u8>, &'s [u8]);
+
+// checks the encoding and the sighash type of the ECDSA signature (with sighash byte) and
+// computes the message it signs with message(sighash)
+// returns: message and DER signature, None for the empty signature or Err if the encoding or
+// the sighash type fails the script
+fn ecdsa_message<'s>(
+    options: &ValidationOptions,
+    signature: &'s [u8],
+    opcode: &str,
+    message: impl Fn(SighashType) -> Vec<u8>,
+) -> Result<Option<SignedMessage<'s>>, ScriptError> {
+    check_signature_encoding(signature, options.flags)
+        .map_err(|err| ScriptError::Signature(err.to_string()))?;
+    let (sighash_byte, der_signature) = match signature.split_last() {
+        Some((sighash_byte, der_signature)) => (*sighash_byte, der_signature),
+        // an empty signature fails the check without aborting the script
+        None => return Ok(None),
+    };
+    let sighash = SighashType::from_byte(sighash_byte).ok_or_else(|| {
+        ScriptError::Signature(format!(
+            "{} undefined sighash type {:#04x}",
+            opcode, sighash_byte
+        ))
+    })?;
+    if !options.accepts_sighash(sighash) {
+        return Err(ScriptError::Signature(format!(
+            "{} sighash {:#04x} not accepted",
+            opcode, sighash_byte
+        )));
+    }
+    Ok(Some((message(sighash), der_signature)))
+}
+
+// returns: Ok(true) if the ECDSA signature is valid for the public key, Ok(false) if not
+fn check_ecdsa_sig(
+    options: &ValidationOptions,
+    signature: &[u8],
+    pubkey: &[u8],
+    message: impl Fn(SighashType) -> Vec<u8>,
+) -> Result<bool, ScriptError> {
+    Ok(
+        match ecdsa_message(options, signature, "OP_CHECKSIG", message)? {
+            Some((message, der_signature)) => {
+                verify_ecdsa_signature(&message, pubkey, der_signature).is_ok()
+            }
+            None => false,
+        },
+    )
+}
+
+// every signature is tried against the remaining public keys until one matches, keys that
+// didn't match are skipped for the following signatures
+// returns: Ok(true) if all ECDSA signatures matched a public key
+#[cfg(feature = "multisig")]
+fn check_ecdsa_multisig(
+    options: &ValidationOptions,
+    signatures: &[Vec<u8>],
+    pubkeys: &[Vec<u8>],
+    message: impl Fn(SighashType) -> Vec<u8>,
+) -> Result<bool, ScriptError> {
+    let mut pubkeys = pubkeys.iter();
+
+    for signature in signatures {
+        let (message, der_signature) =
+            match ecdsa_message(options, signature, "OP_CHECKMULTISIG", &message)? {
+                Some(signed) => signed,
+                None => return Ok(false),
+            };
+        let matched = pubkeys
+            .by_ref()
+            .any(|pubkey| verify_ecdsa_signature(&message, pubkey, der_signature).is_ok());
+        if !matched {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
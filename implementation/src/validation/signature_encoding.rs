@@ -0,0 +1,64 @@
+// Encoding checks of ECDSA signatures (DER signature + sighash byte) selected by the script
+// flags: strict DER encoding (BIP66, ScriptFlags::DERSIG) and the low S policy
+// (ScriptFlags::LOW_S) that rejects the malleable high S form of a signature.
+
+use super::script_flags::ScriptFlags;
+use super::validation_error::SignatureError;
+use secp256k1::ecdsa::Signature;
+
+// checks the BIP66 DER encoding of the signature including the trailing sighash byte:
+// 0x30 [total-length] 0x02 [R-length] [R] 0x02 [S-length] [S] [sighash]
+// returns: true if the signature is strict DER encoded
+pub fn is_strict_der(sig: &[u8]) -> bool {
+    if sig.len() < 9 || sig.len() > 73 {
+        return false;
+    }
+    if sig[0] != 0x30 || sig[1] as usize != sig.len() - 3 {
+        return false;
+    }
+    let len_r = sig[3] as usize;
+    if 5 + len_r >= sig.len() {
+        return false;
+    }
+    let len_s = sig[5 + len_r] as usize;
+    if len_r + len_s + 7 != sig.len() {
+        return false;
+    }
+    // R and S are positive integers without unnecessary leading zero bytes
+    let integer_valid = |start: usize, length: usize| {
+        sig[start - 2] == 0x02
+            && length != 0
+            && sig[start] & 0x80 == 0
+            && !(length > 1 && sig[start] == 0x00 && sig[start + 1] & 0x80 == 0)
+    };
+    integer_valid(4, len_r) && integer_valid(6 + len_r, len_s)
+}
+
+// returns: true if the S value of the DER signature (without sighash byte) is at most
+// half the curve order, false for high S or signatures that can't be parsed
+pub fn is_low_s(der_signature: &[u8]) -> bool {
+    match Signature::from_der_lax(der_signature) {
+        Ok(signature) => {
+            let mut normalized = signature;
+            normalized.normalize_s();
+            normalized == signature
+        }
+        Err(_) => false,
+    }
+}
+
+// checks the encoding of a non empty signature (with sighash byte) according to the flags,
+// empty signatures are a valid way to fail a signature check
+// returns: Ok() or Err(SignatureError)
+pub fn check_signature_encoding(sig: &[u8], flags: ScriptFlags) -> Result<(), SignatureError> {
+    if sig.is_empty() {
+        return Ok(());
+    }
+    if flags.contains(ScriptFlags::DERSIG) && !is_strict_der(sig) {
+        return Err(SignatureError::NotStrictDer);
+    }
+    if flags.contains(ScriptFlags::LOW_S) && !is_low_s(&sig[..sig.len() - 1]) {
+        return Err(SignatureError::HighS);
+    }
+    Ok(())
+}
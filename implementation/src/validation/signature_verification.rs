@@ -1,105 +1,448 @@
+#[cfg(feature = "taproot")]
+use super::taproot::verify_p2tr;
+use super::validate_parsing::serialize_output;
+#[cfg(feature = "interpreter")]
 use super::{
-    script::evaluate_script,
-    utils::{double_hash, get_outpoint, hash160},
-    ValidationResult,
+    script::{check_final_stack, evaluate_script, is_push_only, ScriptInterpreter},
+    script_asm::disassemble_core,
+    signature_checker::{InputContext, LegacyChecker, WitnessV0Checker},
+    utils::hash_sha256,
 };
-use crate::parsing::transaction_structs::{Transaction, TxIn};
+use super::{
+    script_flags::ScriptFlags,
+    sighash::SighashType,
+    signature_encoding::check_signature_encoding,
+    utils::{double_hash, get_outpoint, hash160, varint},
+    validation_error::{MalformedInput, SignatureError},
+    ValidationError, ValidationOptions,
+};
+use crate::parsing::transaction_structs::Transaction;
 use hex_literal::hex as hexlit;
-use secp256k1::{ecdsa::Signature, Message, PublicKey};
+use secp256k1::{ecdsa::Signature, Message, PublicKey, Secp256k1, VerifyOnly};
+#[cfg(feature = "interpreter")]
+use std::collections::VecDeque;
+use std::sync::OnceLock;
 
-// deserializes pubkey from p2wpkh scriptpubkey and reserializes it with the
+// returns: verification-only secp256k1 context shared by all validation threads, created on
+// first use (signature checks never need the signing tables of a full context)
+pub(crate) fn secp_context() -> &'static Secp256k1<VerifyOnly> {
+    static CONTEXT: OnceLock<Secp256k1<VerifyOnly>> = OnceLock::new();
+    CONTEXT.get_or_init(Secp256k1::verification_only)
+}
+
+// Verify DER encoded signature (without sighash byte) against the 32 byte message and pubkey,
+// the encoding is checked against the script flags by check_signature_encoding() before, so
+// the lax DER parser is used and high S values are normalized
+// returns: Ok() or Err(SignatureError)
+pub(crate) fn verify_ecdsa_signature(
+    msg: &[u8],
+    pubkey: &[u8],
+    sig: &[u8],
+) -> Result<(), SignatureError> {
+    let mut sig = Signature::from_der_lax(sig).map_err(SignatureError::EcdsaEncoding)?;
+    sig.normalize_s();
+    let msg: [u8; 32] = msg.try_into().expect("Commitment hash is not 32 byte!");
+    let msg = Message::from_digest(msg);
+    let pubkey = PublicKey::from_slice(pubkey).map_err(SignatureError::Pubkey)?;
+    secp_context()
+        .verify_ecdsa(&msg, &sig, &pubkey)
+        .map_err(SignatureError::EcdsaVerification)
+}
+
+// serializes the 20 byte pubkey hash of a p2wpkh witness program with the
 // according opcodes to the scriptcode used in the tx commitment.
-// returns: scriptcode of the input as Vec<u8>
-fn serialize_p2wpkh_scriptcode(txin: &TxIn) -> Vec<u8> {
+// returns: scriptcode (without length prefix) as Vec<u8>
+pub(crate) fn p2wpkh_scriptcode(pubkey_hash: &[u8]) -> Vec<u8> {
     let mut scriptcode = Vec::new();
-    let mut scriptpubkey_bytes =
-        hex::decode(&txin.prevout.scriptpubkey).expect("Error decoding scriptpubkey hex!");
-    scriptcode.extend(hexlit!("1976a914"));
-    scriptcode.extend(scriptpubkey_bytes.split_off(2));
+    scriptcode.extend(hexlit!("76a914"));
+    scriptcode.extend(pubkey_hash);
     scriptcode.extend(hexlit!("88ac"));
     scriptcode
 }
 
 // Assembles transaction commitment according to BIP143 and returns
-// the double sha256 digest as 32 byte Vec<u8>
-fn get_segwit_commitment_hash(tx: &Transaction, txin: &TxIn) -> Vec<u8> {
+// the double sha256 digest as 32 byte Vec<u8>. Hashes the sighash type doesn't commit to
+// are replaced by 32 zero bytes: hashPrevouts with ANYONECANPAY, hashSequence with
+// ANYONECANPAY, NONE or SINGLE and hashOutputs with NONE (SINGLE commits to the output
+// at the input index only, if it exists). The hashes of all prevouts, sequences and outputs
+// are cached in the transaction metadata (Transaction::segwit_hashes()). The script_code is
+// the p2wpkh scriptcode or the witness script of p2wsh inputs.
+pub(crate) fn get_segwit_commitment_hash(
+    tx: &Transaction,
+    input_index: usize,
+    script_code: &[u8],
+    sighash: SighashType,
+) -> Vec<u8> {
+    let txin = &tx.vin[input_index];
     let mut commitment = Vec::new();
+    let hashes = tx.segwit_hashes();
+    let anyonecanpay = sighash.anyonecanpay();
+    let base_type = sighash.base_type();
+
     commitment.extend(tx.version.to_le_bytes());
-    commitment.extend(double_hash(&tx.serialize_all_outpoints()));
-    commitment.extend(double_hash(&tx.serialize_all_sequences()));
+    if anyonecanpay {
+        commitment.extend([0u8; 32]);
+    } else {
+        commitment.extend(hashes.hash_prevouts);
+    }
+    if anyonecanpay || base_type != SighashType::All {
+        commitment.extend([0u8; 32]);
+    } else {
+        commitment.extend(hashes.hash_sequence);
+    }
     commitment.extend(get_outpoint(txin));
-    commitment.extend(serialize_p2wpkh_scriptcode(txin)); // add len prefix if p2wsh
+    commitment.extend(varint(script_code.len() as u128));
+    commitment.extend(script_code);
     commitment.extend(txin.prevout.value.to_le_bytes());
     commitment.extend(txin.sequence.to_le_bytes());
-    commitment.extend(double_hash(&tx.serialize_all_outputs()));
+    match base_type {
+        SighashType::None => commitment.extend([0u8; 32]),
+        SighashType::Single => match tx.vout.get(input_index) {
+            Some(txout) => commitment.extend(double_hash(&serialize_output(txout))),
+            None => commitment.extend([0u8; 32]),
+        },
+        _ => commitment.extend(hashes.hash_outputs),
+    }
     commitment.extend(tx.locktime.to_le_bytes());
-    commitment.extend(hexlit!("01000000")); // sighash_all <- implement others later on
+    commitment.extend((sighash.to_byte() as u32).to_le_bytes());
     double_hash(&commitment)
 }
 
-// Used to verify the signature in the p2wpkh input witness against the bip143 tx commitment hash
-// returns: ValidationResult ::Valid or ::Invalid(reason String)
-fn verify_signature_p2wpkh(msg: &[u8], pubkey: &[u8], sig: &[u8]) -> ValidationResult {
+// Used to verify the signature in the p2wpkh input witness against the bip143 tx commitment hash,
+// the encoding is checked against the script flags by check_signature_encoding() before
+// returns: Ok() or Err(ValidationError::Signature)
+fn verify_signature_p2wpkh(msg: &[u8], pubkey: &[u8], sig: &[u8]) -> Result<(), ValidationError> {
     let sig = &sig[..sig.len() - 1]; // remove sighash byte
-    let sig = Signature::from_der(sig);
-    let mut sig = match sig {
-        Ok(value) => value,
-        Err(err) => {
-            return ValidationResult::Invalid(format!(
-                "Loading DER encoded signature failed: {}",
-                err
-            ));
-        }
-    };
-    Signature::normalize_s(&mut sig);
-    let msg: [u8; 32] = msg.try_into().expect("Commitment hash is not 32 byte!");
-    let msg = Message::from_digest(msg);
-    let pubkey = PublicKey::from_slice(pubkey).expect("Pubkey invalid!");
-    let result = sig.verify(&msg, &pubkey);
-    match result {
-        Ok(_) => ValidationResult::Valid,
-        Err(err) => ValidationResult::Invalid(format!("Signature verification failed: {}", err)),
-    }
+    verify_ecdsa_signature(msg, pubkey, sig).map_err(ValidationError::Signature)
 }
 
 // Assembles tx commitment (BIP143), deserializes pubkey and signature from witness
-// then verifies witness pubkey and scriptpubkey equality and the signature of the given TxIn.
-// returns ValidationResult::Valid or ::Invalid(reason String)
-pub fn verify_p2wpkh(tx: &Transaction, txin: &TxIn) -> ValidationResult {
-    let msg: Vec<u8> = get_segwit_commitment_hash(tx, txin);
-    if let Some(witness) = &txin.witness {
-        let witness_sig = hex::decode(&witness[0]).expect("Witness sig decoding failed!");
-        let witness_pk = hex::decode(&witness[1]).expect("Witness pk hex decoding failed!");
-        let witness_pubkey_20bit = hash160(&witness_pk);
-        let scriptpubkey_pubkey = hex::decode(txin.prevout.scriptpubkey.clone().split_off(4))
-            .expect("Scriptpubkey pubkey decoding failed");
-        if witness_pubkey_20bit == scriptpubkey_pubkey {
-            verify_signature_p2wpkh(&msg, &witness_pk, &witness_sig)
+// then verifies the witness pubkey against the 20 byte pubkey hash of the witness program
+// (scriptpubkey or P2SH redeem script) and the signature of the given TxIn.
+// returns: Ok() or Err(ValidationError)
+fn verify_p2wpkh_program(
+    tx: &Transaction,
+    input_index: usize,
+    pubkey_hash: &[u8],
+    options: &ValidationOptions,
+) -> Result<(), ValidationError> {
+    let txin = &tx.vin[input_index];
+    if let Some(witness) = &txin.witness_bytes {
+        if witness.len() != 2 {
+            return Err(ValidationError::MalformedInput(
+                MalformedInput::P2wpkhWitnessItems(witness.len()),
+            ));
+        }
+        let witness_sig = &witness[0];
+        let sighash_byte = match witness_sig.last() {
+            Some(sighash_byte) => *sighash_byte,
+            None => {
+                return Err(ValidationError::MalformedInput(
+                    MalformedInput::EmptyWitnessSignature,
+                ))
+            }
+        };
+        let sighash = match SighashType::from_byte(sighash_byte) {
+            Some(sighash) if options.accepts_sighash(sighash) => sighash,
+            Some(_) => return Err(ValidationError::SighashNotAccepted(sighash_byte)),
+            None => return Err(ValidationError::UndefinedSighash(sighash_byte)),
+        };
+        check_signature_encoding(witness_sig, options.flags).map_err(ValidationError::Signature)?;
+        let msg: Vec<u8> =
+            get_segwit_commitment_hash(tx, input_index, &p2wpkh_scriptcode(pubkey_hash), sighash);
+        let witness_pk = &witness[1];
+        let witness_pubkey_20bit = hash160(witness_pk);
+        if witness_pubkey_20bit == pubkey_hash {
+            verify_signature_p2wpkh(&msg, witness_pk, witness_sig)
         } else {
-            ValidationResult::Invalid(format!(
-                "Pubkeys unequal, witness: {} | scriptpubkey: {}",
-                hex::encode(witness_pubkey_20bit),
-                hex::encode(scriptpubkey_pubkey)
+            Err(ValidationError::Signature(
+                SignatureError::PubkeyHashMismatch {
+                    witness: hex::encode(witness_pubkey_20bit),
+                    scriptpubkey: hex::encode(pubkey_hash),
+                },
             ))
         }
     } else {
-        ValidationResult::Invalid("No witness in transaction!".to_string())
+        Err(ValidationError::MalformedInput(
+            MalformedInput::MissingWitness,
+        ))
+    }
+}
+
+// returns: witness version (0 to 16) and program if the script is a segwit witness program
+// (version opcode OP_0 or OP_1 to OP_16 followed by a single 2 to 40 byte push)
+pub(crate) fn witness_program(script: &[u8]) -> Option<(u8, &[u8])> {
+    if script.len() < 4 || script.len() > 42 || script[1] as usize != script.len() - 2 {
+        return None;
+    }
+    match script[0] {
+        0x00 => Some((0, &script[2..])),
+        0x51..=0x60 => Some((script[0] - 0x50, &script[2..])),
+        _ => None,
+    }
+}
+
+// Verifies the witness program of a native (scriptpubkey) or P2SH wrapped (redeem script, p2sh)
+// segwit input by its version: v0 is P2WPKH or P2WSH by the program length, a 32 byte v1
+// program of a native output is P2TR. Without the soft fork flags and for unknown versions
+// (including P2SH wrapped v1) the program is anyone can spend, the policy flag
+// DISCOURAGE_UPGRADABLE_WITNESS_PROGRAM rejects unknown versions so upgrades can't split relay.
+// returns: Ok() or Err(ValidationError)
+pub(crate) fn verify_witness_program(
+    tx: &Transaction,
+    input_index: usize,
+    version: u8,
+    program: &[u8],
+    p2sh: bool,
+    options: &ValidationOptions,
+) -> Result<(), ValidationError> {
+    let txin = &tx.vin[input_index];
+    if !options.has(ScriptFlags::WITNESS) {
+        return Ok(());
+    }
+    if !p2sh && !txin.scriptsig_bytes.is_empty() {
+        return Err(ValidationError::MalformedInput(
+            MalformedInput::NonEmptyScriptsig("Native segwit"),
+        ));
+    }
+    let taproot = version == 1 && program.len() == 32 && !p2sh;
+    match version {
+        0 => verify_witness_program_v0(tx, input_index, program, options),
+        #[cfg(feature = "taproot")]
+        1 if taproot && options.has(ScriptFlags::TAPROOT) => verify_p2tr(tx, input_index, options),
+        #[cfg(not(feature = "taproot"))]
+        1 if taproot && options.has(ScriptFlags::TAPROOT) => {
+            Err(ValidationError::UnsupportedInput(Some("taproot")))
+        }
+        1 if taproot => Ok(()),
+        _ if options.has(ScriptFlags::DISCOURAGE_UPGRADABLE_WITNESS_PROGRAM) => Err(
+            ValidationError::MalformedInput(MalformedInput::ReservedWitnessProgram {
+                version,
+                length: program.len(),
+            }),
+        ),
+        _ => Ok(()),
+    }
+}
+
+// Checks SHA256(witness script) (last witness item) against the 32 byte witness program and
+// executes the witness script on the remaining witness items. Signatures commit to the
+// witness script as BIP143 scriptcode. Like in consensus exactly one true item has to be
+// left on the stack.
+// returns: Ok() or Err(ValidationError)
+#[cfg(feature = "interpreter")]
+fn verify_p2wsh_program(
+    tx: &Transaction,
+    input_index: usize,
+    script_hash: &[u8],
+    options: &ValidationOptions,
+) -> Result<(), ValidationError> {
+    let txin = &tx.vin[input_index];
+    let witness = match &txin.witness_bytes {
+        Some(witness) if !witness.is_empty() => witness,
+        _ => {
+            return Err(ValidationError::MalformedInput(
+                MalformedInput::MissingWitness,
+            ))
+        }
+    };
+    let witness_script = &witness[witness.len() - 1];
+    if hash_sha256(witness_script) != script_hash {
+        return Err(ValidationError::MalformedInput(
+            MalformedInput::WitnessScriptHash {
+                script_hash: hex::encode(hash_sha256(witness_script)),
+                program: hex::encode(script_hash),
+            },
+        ));
+    }
+    let stack: VecDeque<Vec<u8>> = witness[..witness.len() - 1].iter().cloned().collect();
+    let checker = WitnessV0Checker(InputContext::new(tx, input_index, options));
+    if let Err(err) = ScriptInterpreter::new(&checker)
+        .execute(witness_script, witness_script, stack)
+        // BIP141 requires a clean stack after witness scripts regardless of the flags
+        .and_then(|stack| check_final_stack(&stack, true))
+    {
+        return Err(ValidationError::ScriptFailed {
+            script: format!("Witness script [{}]", disassemble_core(witness_script)),
+            error: err,
+        });
+    }
+    Ok(())
+}
+
+// dispatches a version 0 witness program by its length (20 byte p2wpkh, 32 byte p2wsh)
+// returns: Ok() or Err(ValidationError)
+fn verify_witness_program_v0(
+    tx: &Transaction,
+    input_index: usize,
+    program: &[u8],
+    options: &ValidationOptions,
+) -> Result<(), ValidationError> {
+    match program.len() {
+        20 => verify_p2wpkh_program(tx, input_index, program, options),
+        #[cfg(feature = "interpreter")]
+        32 => verify_p2wsh_program(tx, input_index, program, options),
+        #[cfg(not(feature = "interpreter"))]
+        32 => Err(ValidationError::UnsupportedInput(Some("interpreter"))),
+        length => Err(ValidationError::MalformedInput(
+            MalformedInput::WitnessV0ProgramLength(length),
+        )),
+    }
+}
+
+// Executes the scriptsig of an input spending a bare script (P2PKH, P2PK, bare multisig) and
+// the scriptpubkey on the resulting stack (evaluate_script())
+// returns: Ok() or Err(ValidationError)
+#[cfg(feature = "interpreter")]
+fn verify_bare_script(
+    tx: &Transaction,
+    input_index: usize,
+    script_type: &'static str,
+    options: &ValidationOptions,
+) -> Result<(), ValidationError> {
+    let txin = &tx.vin[input_index];
+    if txin.scriptsig.is_none() {
+        return Err(ValidationError::MalformedInput(
+            MalformedInput::EmptyScriptsig(script_type),
+        ));
+    }
+    evaluate_script(
+        &txin.scriptsig_bytes,
+        &txin.prevout.scriptpubkey_bytes,
+        input_index,
+        tx,
+        options,
+    )?;
+    Ok(())
+}
+
+// <signature> <pubkey> | OP_DUP OP_HASH160 <pubkey hash> OP_EQUALVERIFY OP_CHECKSIG
+// returns: Ok() or Err(ValidationError)
+#[cfg(feature = "interpreter")]
+pub fn verify_p2pkh(
+    tx: &Transaction,
+    input_index: usize,
+    options: &ValidationOptions,
+) -> Result<(), ValidationError> {
+    verify_bare_script(tx, input_index, "P2PKH", options)
+}
+
+// <signature> | <pubkey> OP_CHECKSIG
+// returns: Ok() or Err(ValidationError)
+#[cfg(feature = "interpreter")]
+pub fn verify_p2pk(
+    tx: &Transaction,
+    input_index: usize,
+    options: &ValidationOptions,
+) -> Result<(), ValidationError> {
+    verify_bare_script(tx, input_index, "P2PK", options)
+}
+
+// OP_0 <signatures> | OP_m <pubkeys> OP_n OP_CHECKMULTISIG, with ScriptFlags::BARE_MULTISIG
+// the scriptpubkey may only have MAX_BARE_MULTISIG_PUBKEYS public keys
+// returns: Ok() or Err(ValidationError)
+#[cfg(feature = "multisig")]
+pub fn verify_bare_multisig(
+    tx: &Transaction,
+    input_index: usize,
+    options: &ValidationOptions,
+) -> Result<(), ValidationError> {
+    verify_bare_script(tx, input_index, "Bare multisig", options)
+}
+
+// verifies a P2SH wrapped segwit input (P2SH-P2WPKH, P2SH-P2WSH or a future version), the
+// scriptsig has to be the single push of the witness program redeem script
+// returns: Ok() or Err(ValidationError)
+#[cfg(feature = "interpreter")]
+fn verify_nested_segwit(
+    tx: &Transaction,
+    input_index: usize,
+    redeem_script: &[u8],
+    options: &ValidationOptions,
+) -> Result<(), ValidationError> {
+    let txin = &tx.vin[input_index];
+    let mut redeem_push = vec![redeem_script.len() as u8];
+    redeem_push.extend(redeem_script);
+    if txin.scriptsig_bytes != redeem_push {
+        return Err(ValidationError::MalformedInput(
+            MalformedInput::WrappedSegwitScriptsig,
+        ));
+    }
+    match witness_program(redeem_script) {
+        Some((version, program)) => {
+            verify_witness_program(tx, input_index, version, program, true, options)
+        }
+        None => Err(ValidationError::MalformedInput(
+            MalformedInput::RedeemScriptNoWitnessProgram,
+        )),
     }
 }
 
-// Assembles the evaluation script from scriptsig and scriptpubkey and calls validating function
-// returns ValidationResult::Valid or ::Invalid(reason String)
-pub fn verify_p2pkh(tx: &Transaction, txin: &TxIn) -> ValidationResult {
-    let mut script: Vec<u8> = Vec::new();
-    script.extend(
-        hex::decode(txin.scriptsig.as_ref().expect("p2pkh scriptsig empty"))
-            .expect("verify p2pkh scriptsig hex decode failed"),
-    );
-    script.extend(
-        hex::decode(&txin.prevout.scriptpubkey).expect("p2pkh scriptpubkey hex decode failed"),
-    );
-    match evaluate_script(script, txin, tx) {
-        Ok(_) => ValidationResult::Valid,
-        Err(err) => ValidationResult::Invalid(err.to_string()),
+// Executes the push only scriptsig, checks HASH160(redeem script) (top stack item) against the
+// hash of the scriptpubkey (OP_HASH160 <20 byte hash> OP_EQUAL) and executes the redeem script
+// on the remaining scriptsig stack. Signatures commit to the redeem script as script code.
+// Without ScriptFlags::P2SH the matching hash is sufficient, without ::WITNESS a witness
+// program redeem script is anyone can spend.
+// returns: Ok() or Err(ValidationError)
+#[cfg(feature = "interpreter")]
+pub fn verify_p2sh(
+    tx: &Transaction,
+    input_index: usize,
+    options: &ValidationOptions,
+) -> Result<(), ValidationError> {
+    let txin = &tx.vin[input_index];
+    let scriptpubkey = &txin.prevout.scriptpubkey_bytes;
+    if scriptpubkey.len() != 23 {
+        return Err(ValidationError::MalformedInput(
+            MalformedInput::P2shScriptpubkey,
+        ));
+    }
+    if options.has(ScriptFlags::P2SH) && !is_push_only(&txin.scriptsig_bytes) {
+        return Err(ValidationError::MalformedInput(
+            MalformedInput::P2shNotPushOnly,
+        ));
+    }
+    let checker = LegacyChecker(InputContext::new(tx, input_index, options));
+    let interpreter = ScriptInterpreter::new(&checker);
+    let mut stack = match interpreter.execute(&txin.scriptsig_bytes, &[], VecDeque::new()) {
+        Ok(stack) => stack,
+        Err(err) => {
+            return Err(ValidationError::ScriptFailed {
+                script: "P2SH scriptsig".to_string(),
+                error: err,
+            })
+        }
+    };
+    let redeem_script = match stack.pop_back() {
+        Some(redeem_script) => redeem_script,
+        None => {
+            return Err(ValidationError::MalformedInput(
+                MalformedInput::EmptyScriptsig("P2SH"),
+            ))
+        }
+    };
+    if hash160(&redeem_script) != scriptpubkey[2..22] {
+        return Err(ValidationError::MalformedInput(
+            MalformedInput::RedeemScriptHash {
+                redeem_script_hash: hex::encode(hash160(&redeem_script)),
+                scriptpubkey_hash: hex::encode(&scriptpubkey[2..22]),
+            },
+        ));
+    }
+    if !options.has(ScriptFlags::P2SH) {
+        return Ok(());
+    }
+    if witness_program(&redeem_script).is_some() {
+        return verify_nested_segwit(tx, input_index, &redeem_script, options);
+    }
+    if let Err(err) = interpreter
+        .execute(&redeem_script, &redeem_script, stack)
+        .and_then(|stack| check_final_stack(&stack, options.has(ScriptFlags::CLEANSTACK)))
+    {
+        return Err(ValidationError::ScriptFailed {
+            script: format!("Redeem script [{}]", disassemble_core(&redeem_script)),
+            error: err,
+        });
     }
+    Ok(())
 }
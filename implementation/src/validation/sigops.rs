@@ -0,0 +1,119 @@
+// Signature operation counting of the block sigops budget (BIP141 sigops cost): legacy sigops of
+// scriptsigs and scriptpubkeys and the sigops of P2SH redeem scripts count 4 each, sigops of
+// witness programs count 1. Legacy scripts count every OP_CHECKMULTISIG with 20 keys, redeem
+// and witness scripts with the key count of a preceding OP_1 to OP_16.
+
+use super::signature_verification::witness_program;
+use crate::feerate::WITNESS_SCALE_FACTOR;
+use crate::parsing::transaction_structs::{Transaction, TxIn};
+
+pub const MAX_BLOCK_SIGOPS_COST: u64 = 80_000;
+pub const MAX_STANDARD_TX_SIGOPS_COST: u64 = MAX_BLOCK_SIGOPS_COST / 5;
+const MAX_PUBKEYS_PER_MULTISIG: u64 = 20;
+
+// returns: opcodes of the script with the data of push opcodes, stops at a truncated push
+fn instructions(script: &[u8]) -> Vec<(u8, &[u8])> {
+    let mut instructions = Vec::new();
+    let mut index = 0;
+
+    while index < script.len() {
+        let opcode = script[index];
+        index += 1;
+        let length = match opcode {
+            0x01..=0x4b => opcode as usize,
+            0x4c..=0x4e => {
+                let length_bytes = 1 << (opcode - 0x4c);
+                if index + length_bytes > script.len() {
+                    break;
+                }
+                let mut length_le = [0u8; 4];
+                length_le[..length_bytes].copy_from_slice(&script[index..index + length_bytes]);
+                index += length_bytes;
+                u32::from_le_bytes(length_le) as usize
+            }
+            _ => 0,
+        };
+        if index + length > script.len() {
+            break;
+        }
+        instructions.push((opcode, &script[index..index + length]));
+        index += length;
+    }
+    instructions
+}
+
+// counts OP_CHECKSIG(VERIFY) as 1 and OP_CHECKMULTISIG(VERIFY) as 20, accurate counts the
+// multisig with the key count of a preceding OP_1 to OP_16
+// returns: sigops of the script
+pub fn count_sigops(script: &[u8], accurate: bool) -> u64 {
+    let mut sigops = 0;
+    let mut last_opcode = 0xff;
+
+    for (opcode, _) in instructions(script) {
+        match opcode {
+            0xac | 0xad => sigops += 1,
+            0xae | 0xaf => {
+                sigops += match last_opcode {
+                    0x51..=0x60 if accurate => (last_opcode - 0x50) as u64,
+                    _ => MAX_PUBKEYS_PER_MULTISIG,
+                }
+            }
+            _ => {}
+        }
+        last_opcode = opcode;
+    }
+    sigops
+}
+
+// returns: true if the script is a P2SH scriptpubkey (OP_HASH160 <20 bytes> OP_EQUAL)
+fn is_p2sh(script: &[u8]) -> bool {
+    script.len() == 23 && script[0] == 0xa9 && script[1] == 0x14 && script[22] == 0x87
+}
+
+// returns: redeem script (last push) of a push only scriptsig spending a P2SH output
+pub(crate) fn redeem_script(txin: &TxIn) -> Option<&[u8]> {
+    if !is_p2sh(&txin.prevout.scriptpubkey_bytes) {
+        return None;
+    }
+    let instructions = instructions(&txin.scriptsig_bytes);
+    if instructions.iter().any(|(opcode, _)| *opcode > 0x60) {
+        return None;
+    }
+    instructions.last().map(|(_, data)| *data)
+}
+
+// P2WPKH spends count 1, P2WSH spends the accurate sigops of the witness script (last
+// witness item), native or nested in P2SH. Other witness versions have no sigops.
+// returns: witness sigops of the input
+fn witness_sigops(txin: &TxIn) -> u64 {
+    let program_script = match redeem_script(txin) {
+        Some(redeem_script) => redeem_script,
+        None => &txin.prevout.scriptpubkey_bytes,
+    };
+    match witness_program(program_script) {
+        Some((0, program)) if program.len() == 20 => 1,
+        Some((0, program)) if program.len() == 32 => txin
+            .witness_bytes
+            .as_ref()
+            .and_then(|witness| witness.last())
+            .map_or(0, |witness_script| count_sigops(witness_script, true)),
+        _ => 0,
+    }
+}
+
+// returns: sigops cost of the transaction
+pub fn sigops_cost(tx: &Transaction) -> u64 {
+    let mut cost = 0;
+
+    for txin in &tx.vin {
+        cost += count_sigops(&txin.scriptsig_bytes, false) * WITNESS_SCALE_FACTOR;
+        if let Some(redeem_script) = redeem_script(txin) {
+            cost += count_sigops(redeem_script, true) * WITNESS_SCALE_FACTOR;
+        }
+        cost += witness_sigops(txin);
+    }
+    for txout in &tx.vout {
+        cost += count_sigops(&txout.scriptpubkey_bytes, false) * WITNESS_SCALE_FACTOR;
+    }
+    cost
+}
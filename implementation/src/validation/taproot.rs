@@ -0,0 +1,392 @@
+// Taproot (segwit v1, BIP341/BIP342) input verification: key path spends are verified
+// against the output key, script path spends by the control block commitment and the
+// execution of the tapscript leaf. Signatures are BIP340 schnorr signatures.
+
+use super::script::{evaluate_tapscript, SigVersion};
+use super::script_error::ScriptError;
+use super::script_flags::ScriptFlags;
+use super::sighash::SighashType;
+use super::signature_checker::{InputContext, SignatureChecker};
+use super::signature_verification::secp_context;
+use super::utils::{decode_num, encode_num, get_outpoint, hash_sha256, varint};
+use super::validate_parsing::serialize_output;
+use super::validation_error::{MalformedInput, SignatureError};
+use super::{ValidationError, ValidationOptions};
+use crate::parsing::transaction_structs::Transaction;
+use secp256k1::{schnorr, Message, Parity, Scalar, XOnlyPublicKey};
+use std::cell::Cell;
+use std::collections::VecDeque;
+
+// leaf version of BIP342 tapscript, the only leaf version executed
+pub const TAPSCRIPT_LEAF_VERSION: u8 = 0xc0;
+// BIP342 validation weight budget of a script path spend: the offset plus the serialized
+// witness size, every signature opcode with a non empty signature uses VALIDATION_WEIGHT_PER_SIGOP
+pub const VALIDATION_WEIGHT_OFFSET: i64 = 50;
+pub const VALIDATION_WEIGHT_PER_SIGOP: i64 = 50;
+// first byte of the optional last witness item carrying the annex
+const ANNEX_TAG: u8 = 0x50;
+const CONTROL_BLOCK_BASE_SIZE: usize = 33;
+const CONTROL_BLOCK_NODE_SIZE: usize = 32;
+const CONTROL_BLOCK_MAX_NODES: usize = 128;
+
+// returns: BIP340 tagged hash sha256(sha256(tag) || sha256(tag) || msg)
+pub fn tagged_hash(tag: &str, msg: &[u8]) -> Vec<u8> {
+    let tag_hash = hash_sha256(tag.as_bytes());
+    let mut preimage = tag_hash.clone();
+    preimage.extend(&tag_hash);
+    preimage.extend(msg);
+    hash_sha256(&preimage)
+}
+
+// returns: TapLeaf hash of the script with the leaf version
+pub fn tapleaf_hash(leaf_version: u8, script: &[u8]) -> Vec<u8> {
+    let mut leaf = vec![leaf_version];
+    leaf.extend(varint(script.len() as u128));
+    leaf.extend(script);
+    tagged_hash("TapLeaf", &leaf)
+}
+
+// returns: TapBranch hash of the two child hashes, sorted lexicographically
+fn tapbranch_hash(left: &[u8], right: &[u8]) -> Vec<u8> {
+    let mut branch = Vec::new();
+    if left < right {
+        branch.extend(left);
+        branch.extend(right);
+    } else {
+        branch.extend(right);
+        branch.extend(left);
+    }
+    tagged_hash("TapBranch", &branch)
+}
+
+// splits the annex (last item starting with 0x50 if there are at least two items) from the
+// witness
+// returns: (witness items without annex, Some(annex))
+fn split_annex(witness: &[Vec<u8>]) -> (&[Vec<u8>], Option<&[u8]>) {
+    match witness.split_last() {
+        Some((last, rest)) if !rest.is_empty() && last.first() == Some(&ANNEX_TAG) => {
+            (rest, Some(last))
+        }
+        _ => (witness, None),
+    }
+}
+
+// Assembles the BIP341 signature message of the input and returns its TapSighash. Without
+// ANYONECANPAY it commits to the amounts and scriptpubkeys of all spent outputs, script path
+// signatures additionally commit to the leaf hash and the opcode position of the last executed
+// OP_CODESEPARATOR (0xffffffff if none was executed). The hashes of the transaction data shared
+// by all inputs are computed once per transaction (Transaction::taproot_hashes()).
+// returns: 32 byte sighash or Err(SignatureError::SingleWithoutOutput) for SIGHASH_SINGLE
+// without output at the input index
+pub fn taproot_signature_hash(
+    tx: &Transaction,
+    input_index: usize,
+    hash_type: SighashType,
+    leaf: Option<(&[u8], u32)>, // leaf hash and OP_CODESEPARATOR position
+) -> Result<Vec<u8>, SignatureError> {
+    let txin = &tx.vin[input_index];
+    let anyonecanpay = hash_type.anyonecanpay();
+    let base_type = hash_type.base_type();
+    let annex = txin
+        .witness_bytes
+        .as_deref()
+        .and_then(|witness| split_annex(witness).1);
+
+    let mut message = vec![0x00]; // epoch
+    message.push(hash_type.to_byte());
+    message.extend(tx.version.to_le_bytes());
+    message.extend(tx.locktime.to_le_bytes());
+    if !anyonecanpay {
+        let hashes = tx.taproot_hashes();
+        message.extend(hashes.sha_prevouts);
+        message.extend(hashes.sha_amounts);
+        message.extend(hashes.sha_scriptpubkeys);
+        message.extend(hashes.sha_sequences);
+    }
+    if base_type == SighashType::All {
+        message.extend(tx.taproot_hashes().sha_outputs);
+    }
+    let extension_flag = if leaf.is_some() { 1 } else { 0 };
+    message.push(extension_flag * 2 + annex.is_some() as u8); // spend type
+    if anyonecanpay {
+        message.extend(get_outpoint(txin));
+        message.extend(txin.prevout.value.to_le_bytes());
+        message.extend(varint(txin.prevout.scriptpubkey_bytes.len() as u128));
+        message.extend(&txin.prevout.scriptpubkey_bytes);
+        message.extend(txin.sequence.to_le_bytes());
+    } else {
+        message.extend((input_index as u32).to_le_bytes());
+    }
+    if let Some(annex) = annex {
+        let mut serialized_annex = varint(annex.len() as u128);
+        serialized_annex.extend(annex);
+        message.extend(hash_sha256(&serialized_annex));
+    }
+    if base_type == SighashType::Single {
+        match tx.vout.get(input_index) {
+            Some(txout) => message.extend(hash_sha256(&serialize_output(txout))),
+            None => return Err(SignatureError::SingleWithoutOutput),
+        }
+    }
+    if let Some((leaf_hash, codesep_pos)) = leaf {
+        message.extend(leaf_hash);
+        message.push(0x00); // key version
+        message.extend(codesep_pos.to_le_bytes()); // OP_CODESEPARATOR position
+    }
+    Ok(tagged_hash("TapSighash", &message))
+}
+
+// Verifies the 64 byte (SIGHASH_DEFAULT) or 65 byte (explicit, non zero sighash byte)
+// schnorr signature against the x-only pubkey and the taproot signature hash
+// returns: Ok(()) if the signature is valid or Err(SignatureError)
+fn verify_schnorr_signature(
+    tx: &Transaction,
+    input_index: usize,
+    pubkey: &[u8],
+    signature: &[u8],
+    leaf: Option<(&[u8], u32)>,
+    options: &ValidationOptions,
+) -> Result<(), SignatureError> {
+    let (signature, hash_type) = match signature.len() {
+        64 => (signature, SighashType::Default),
+        65 => match SighashType::from_byte(signature[64]) {
+            Some(SighashType::Default) => return Err(SignatureError::ExplicitDefaultSighash),
+            Some(hash_type) => (&signature[..64], hash_type),
+            None => return Err(SignatureError::TaprootSighash(signature[64])),
+        },
+        length => return Err(SignatureError::SchnorrLength(length)),
+    };
+    if hash_type != SighashType::Default && !options.accepts_sighash(hash_type) {
+        return Err(SignatureError::SighashNotAccepted(hash_type.to_byte()));
+    }
+    let sighash = taproot_signature_hash(tx, input_index, hash_type, leaf)?;
+    let msg: [u8; 32] = sighash.try_into().expect("Taproot sighash is not 32 byte!");
+    let signature =
+        schnorr::Signature::from_slice(signature).map_err(SignatureError::SchnorrEncoding)?;
+    let pubkey = XOnlyPublicKey::from_slice(pubkey).map_err(SignatureError::XOnlyPubkey)?;
+    secp_context()
+        .verify_schnorr(&signature, &Message::from_digest(msg), &pubkey)
+        .map_err(SignatureError::SchnorrVerification)
+}
+
+// BIP341 signatures of tapscript leaves, the signature message commits to the leaf hash and the
+// position of the last executed OP_CODESEPARATOR. The signature checks use up the validation
+// weight budget of the input witness.
+pub struct TapscriptChecker<'a> {
+    context: InputContext<'a>,
+    validation_weight_left: Cell<i64>,
+}
+
+impl<'a> TapscriptChecker<'a> {
+    pub fn new(context: InputContext<'a>) -> Self {
+        let witness = context.txin.witness_bytes.as_deref().unwrap_or_default();
+        let witness_size: usize = varint(witness.len() as u128).len()
+            + witness
+                .iter()
+                .map(|item| varint(item.len() as u128).len() + item.len())
+                .sum::<usize>();
+        TapscriptChecker {
+            context,
+            validation_weight_left: Cell::new(VALIDATION_WEIGHT_OFFSET + witness_size as i64),
+        }
+    }
+}
+
+impl SignatureChecker for TapscriptChecker<'_> {
+    fn context(&self) -> InputContext<'_> {
+        self.context
+    }
+
+    fn sig_version(&self) -> SigVersion {
+        SigVersion::Tapscript
+    }
+
+    // signature check of OP_CHECKSIG, OP_CHECKSIGVERIFY and OP_CHECKSIGADD in tapscript
+    // (BIP342): an empty signature fails the check, a non empty signature uses up validation
+    // weight and aborts the script if it fails. Pubkeys of unknown types (not 32 byte) are
+    // reserved for upgrades and succeed with any signature.
+    fn check_sig(
+        &self,
+        signature: &[u8],
+        pubkey: &[u8],
+        script_code: &[u8],
+        codesep_pos: u32,
+    ) -> Result<bool, ScriptError> {
+        let InputContext {
+            tx,
+            input_index,
+            options,
+            ..
+        } = self.context;
+        if !signature.is_empty() {
+            let left = self.validation_weight_left.get() - VALIDATION_WEIGHT_PER_SIGOP;
+            if left < 0 {
+                return Err(ScriptError::TapscriptValidationWeight);
+            }
+            self.validation_weight_left.set(left);
+        }
+        if pubkey.is_empty() {
+            return Err(ScriptError::Signature(
+                "Tapscript signature check with empty pubkey".to_string(),
+            ));
+        }
+        if signature.is_empty() {
+            return Ok(false);
+        }
+        if pubkey.len() == 32 {
+            let leaf_hash = tapleaf_hash(TAPSCRIPT_LEAF_VERSION, script_code);
+            let leaf = Some((&leaf_hash[..], codesep_pos));
+            verify_schnorr_signature(tx, input_index, pubkey, signature, leaf, options)
+                .map_err(|err| ScriptError::Signature(err.to_string()))?;
+        }
+        Ok(true)
+    }
+
+    // OP_CHECKMULTISIG is disabled in tapscript, the interpreter fails before calling it
+    #[cfg(feature = "multisig")]
+    fn check_multisig(
+        &self,
+        _signatures: &[Vec<u8>],
+        _pubkeys: &[Vec<u8>],
+        _script_code: &[u8],
+    ) -> Result<bool, ScriptError> {
+        Err(ScriptError::DisabledOpcode(
+            "OP_CHECKMULTISIG(VERIFY) in tapscript",
+        ))
+    }
+}
+
+// OP_CHECKSIGADD: sig n pubkey -> n + 1 if the signature check succeeds, n for an empty
+// signature. Replaces OP_CHECKMULTISIG in tapscript.
+pub(crate) fn op_checksigadd(
+    stack: &mut VecDeque<Vec<u8>>,
+    checker: &dyn SignatureChecker,
+    script: &[u8],
+    codesep_pos: u32,
+) -> Result<(), ScriptError> {
+    if stack.len() < 3 {
+        return Err(ScriptError::StackUnderflow("OP_CHECKSIGADD"));
+    }
+    let pubkey = stack.pop_back().expect("OP_CHECKSIGADD pop_back");
+    let number = stack.pop_back().expect("OP_CHECKSIGADD pop_back");
+    let signature = stack.pop_back().expect("OP_CHECKSIGADD pop_back");
+    if number.len() > 4 {
+        return Err(ScriptError::InvalidNumber("OP_CHECKSIGADD"));
+    }
+    let success = checker.check_sig(&signature, &pubkey, script, codesep_pos)?;
+    stack.push_back(encode_num(decode_num(&number) as i64 + success as i64));
+    Ok(())
+}
+
+// verifies the merkle path of the control block from the leaf hash to the merkle root and
+// checks that the output key is the internal key tweaked with it (parity in the control byte)
+// returns: Ok(()) if the output key commits to the leaf or Err(MalformedInput)
+fn verify_control_block(
+    control_block: &[u8],
+    output_key: &[u8],
+    leaf_hash: Vec<u8>,
+) -> Result<(), MalformedInput> {
+    let mut node = leaf_hash;
+    for path_node in control_block[CONTROL_BLOCK_BASE_SIZE..].chunks(CONTROL_BLOCK_NODE_SIZE) {
+        node = tapbranch_hash(&node, path_node);
+    }
+    let internal_key = &control_block[1..CONTROL_BLOCK_BASE_SIZE];
+    let mut tweak_preimage = internal_key.to_vec();
+    tweak_preimage.extend(&node);
+    let tweak: [u8; 32] = tagged_hash("TapTweak", &tweak_preimage)
+        .try_into()
+        .expect("Tagged hash is not 32 byte!");
+
+    let internal_key =
+        XOnlyPublicKey::from_slice(internal_key).map_err(MalformedInput::InternalKey)?;
+    let output_key = XOnlyPublicKey::from_slice(output_key).map_err(MalformedInput::OutputKey)?;
+    let tweak = Scalar::from_be_bytes(tweak).map_err(|_| MalformedInput::TweakOutOfRange)?;
+    let parity = Parity::from_u8(control_block[0] & 1).expect("Parity is 0 or 1");
+    if internal_key.tweak_add_check(secp_context(), &output_key, parity, tweak) {
+        Ok(())
+    } else {
+        Err(MalformedInput::ControlBlockCommitment)
+    }
+}
+
+// Script path spend: the last witness item is the control block, the one before the leaf
+// script, the remaining items are the initial stack. Only tapscript leaves (0xc0) are
+// executed, unknown leaf versions are reserved for soft forks and valid without execution,
+// the policy flag DISCOURAGE_UPGRADABLE_TAPROOT_VERSION rejects them.
+// returns: Ok() or Err(ValidationError)
+fn verify_script_path(
+    tx: &Transaction,
+    input_index: usize,
+    output_key: &[u8],
+    witness: &[Vec<u8>],
+    options: &ValidationOptions,
+) -> Result<(), ValidationError> {
+    let (control_block, witness) = witness.split_last().expect("Script path witness empty");
+    let (script, witness) = witness
+        .split_last()
+        .expect("Script path witness without script");
+    let path_length = control_block.len().wrapping_sub(CONTROL_BLOCK_BASE_SIZE);
+    if control_block.len() < CONTROL_BLOCK_BASE_SIZE
+        || path_length % CONTROL_BLOCK_NODE_SIZE != 0
+        || path_length / CONTROL_BLOCK_NODE_SIZE > CONTROL_BLOCK_MAX_NODES
+    {
+        return Err(ValidationError::MalformedInput(
+            MalformedInput::ControlBlockSize(control_block.len()),
+        ));
+    }
+    let leaf_version = control_block[0] & 0xfe;
+    verify_control_block(
+        control_block,
+        output_key,
+        tapleaf_hash(leaf_version, script),
+    )
+    .map_err(ValidationError::MalformedInput)?;
+    if leaf_version != TAPSCRIPT_LEAF_VERSION {
+        if options.has(ScriptFlags::DISCOURAGE_UPGRADABLE_TAPROOT_VERSION) {
+            return Err(ValidationError::MalformedInput(
+                MalformedInput::ReservedLeafVersion(leaf_version),
+            ));
+        }
+        return Ok(());
+    }
+
+    evaluate_tapscript(script, witness.to_vec(), input_index, tx, options)?;
+    Ok(())
+}
+
+// verifies a p2tr input (OP_1 <32 byte output key>), after removing the annex a single
+// witness item is a key path signature, more items are a script path spend
+// returns: Ok() or Err(ValidationError)
+pub fn verify_p2tr(
+    tx: &Transaction,
+    input_index: usize,
+    options: &ValidationOptions,
+) -> Result<(), ValidationError> {
+    let txin = &tx.vin[input_index];
+    let scriptpubkey = &txin.prevout.scriptpubkey_bytes;
+    if scriptpubkey.len() != 34 {
+        return Err(ValidationError::MalformedInput(
+            MalformedInput::P2trScriptpubkey,
+        ));
+    }
+    if !txin.scriptsig_bytes.is_empty() {
+        return Err(ValidationError::MalformedInput(
+            MalformedInput::NonEmptyScriptsig("P2TR"),
+        ));
+    }
+    let witness = match &txin.witness_bytes {
+        Some(witness) if !witness.is_empty() => split_annex(witness).0,
+        _ => {
+            return Err(ValidationError::MalformedInput(
+                MalformedInput::MissingWitness,
+            ))
+        }
+    };
+    let output_key = &scriptpubkey[2..];
+    if witness.len() == 1 {
+        verify_schnorr_signature(tx, input_index, output_key, &witness[0], None, options)
+            .map_err(ValidationError::Signature)
+    } else {
+        verify_script_path(tx, input_index, output_key, witness, options)
+    }
+}
@@ -0,0 +1,406 @@
+// Tests of the transaction checks that don't execute scripts, they run in every feature set.
+// The fixture spends a P2WPKH output, which is verified without the interpreter.
+
+use super::sighash::SighashType;
+use super::signature_verification::{get_segwit_commitment_hash, verify_witness_program};
+use super::validate_values::{
+    dust_threshold, find_dust_output, validate_coinbase_spends_mature, validate_feerate,
+    validate_locktime_final, validate_no_duplicate_inputs, validate_sequence_locks_final,
+    validate_values_and_set_fee, FinalityTarget,
+};
+use super::validation_error::{SizeLimit, ValueError};
+use super::weight_calculation::validate_standard_size;
+use super::{ValidationError, ValidationOptions};
+use crate::feerate::{vsize, FeeRate};
+use crate::parsing::parse_transactions_from_dir;
+use crate::parsing::raw::{parse_raw_transaction, set_prevout};
+use crate::parsing::transaction_structs::Transaction;
+use std::collections::HashMap;
+
+fn fixture() -> Transaction {
+    parse_transactions_from_dir("test_scripts/testfiles/weight")
+        .expect("Parsing the fixtures failed")
+        .remove(0)
+}
+
+#[test]
+fn inputs_spending_the_same_outpoint_are_rejected() {
+    let mut tx = fixture();
+    assert!(validate_no_duplicate_inputs(&tx));
+    let mut duplicate = tx.vin[0].clone();
+    duplicate.sequence = 0;
+    tx.vin.push(duplicate);
+    assert!(!validate_no_duplicate_inputs(&tx));
+    tx.vin.last_mut().unwrap().vout += 1;
+    assert!(validate_no_duplicate_inputs(&tx));
+}
+
+#[test]
+fn locktimes_are_final_only_once_reached() {
+    let mut tx = fixture();
+    let target = FinalityTarget {
+        height: 800_000,
+        median_time_past: Some(1_700_000_000),
+        coinbase_heights: HashMap::new(),
+    };
+    tx.vin[0].sequence = 0xfffffffe;
+    for (locktime, is_final) in [
+        (0, true),
+        (799_999, true),
+        (800_000, false), // the locktime is the last height the transaction is invalid at
+        (1_699_999_999, true),
+        (1_700_000_000, false),
+    ] {
+        tx.locktime = locktime;
+        assert_eq!(
+            validate_locktime_final(&tx, &target),
+            is_final,
+            "{}",
+            locktime
+        );
+    }
+    // only final sequences disable the locktime
+    for txin in tx.vin.iter_mut() {
+        txin.sequence = 0xffffffff;
+    }
+    assert!(validate_locktime_final(&tx, &target));
+}
+
+#[test]
+fn relative_locktimes_of_mempool_parents_are_not_satisfied() {
+    let mut tx = fixture();
+    let target = FinalityTarget {
+        height: 800_000,
+        median_time_past: Some(1_700_000_000),
+        coinbase_heights: HashMap::new(),
+    };
+    let parent = tx.vin[0].txid.clone();
+    let in_mempool = |txid: &str| txid == parent;
+    tx.version = 2;
+    for txin in tx.vin.iter_mut() {
+        txin.sequence = 0xffffffff; // disable flag set
+    }
+    tx.vin[0].sequence = 1; // 1 block
+    assert!(!validate_sequence_locks_final(&tx, &target, in_mempool));
+    assert!(validate_sequence_locks_final(&tx, &target, |_| false));
+    tx.vin[0].sequence = 1 << 22 | 1; // 512 seconds
+    assert!(!validate_sequence_locks_final(&tx, &target, in_mempool));
+    tx.vin[0].sequence = 0;
+    assert!(validate_sequence_locks_final(&tx, &target, in_mempool));
+    // version 1 transactions don't enforce BIP68
+    tx.vin[0].sequence = 1;
+    tx.version = 1;
+    assert!(validate_sequence_locks_final(&tx, &target, in_mempool));
+}
+
+#[test]
+fn coinbase_outputs_are_spendable_after_100_blocks() {
+    let tx = fixture();
+    let mut target = FinalityTarget {
+        height: 800_000,
+        median_time_past: None,
+        coinbase_heights: HashMap::new(),
+    };
+    // outputs of unknown transactions aren't checked
+    assert!(validate_coinbase_spends_mature(&tx, &target));
+    for (coinbase_height, mature) in [(799_900, true), (799_901, false)] {
+        target
+            .coinbase_heights
+            .insert(tx.vin[0].txid.clone(), coinbase_height);
+        assert_eq!(validate_coinbase_spends_mature(&tx, &target), mature);
+    }
+}
+
+#[test]
+fn dust_thresholds_depend_on_the_output_type() {
+    let mut tx = fixture();
+    let mut txout = tx.vout[0].clone();
+    for (scriptpubkey, threshold) in [
+        (format!("76a914{}88ac", "00".repeat(20)), 546), // P2PKH
+        (format!("0014{}", "00".repeat(20)), 294),       // P2WPKH
+        (format!("5120{}", "00".repeat(32)), 330),       // P2TR
+        ("6a0401020304".to_string(), 0),                 // OP_RETURN
+    ] {
+        txout.scriptpubkey_bytes = hex::decode(scriptpubkey).unwrap();
+        assert_eq!(dust_threshold(&txout, 3000), threshold);
+    }
+    txout.scriptpubkey_bytes = hex::decode(format!("0014{}", "00".repeat(20))).unwrap();
+    txout.value = 293;
+    tx.vout.push(txout);
+    let dust_index = tx.vout.len() - 1;
+    assert!(tx.vout[..dust_index].iter().all(|txout| txout.value >= 546));
+    assert_eq!(find_dust_output(&tx, 3000), Some((dust_index, 294)));
+    assert_eq!(find_dust_output(&tx, 1000), None);
+}
+
+#[test]
+fn standard_size_limits() {
+    let mut tx = fixture();
+    assert_eq!(tx.validate(&ValidationOptions::default()), Ok(()));
+    assert_eq!(validate_standard_size(&tx), Ok(()));
+
+    let mut oversized = tx.clone();
+    oversized.meta.weight = 400_001;
+    assert_eq!(
+        validate_standard_size(&oversized),
+        Err(SizeLimit::Weight(400_001))
+    );
+    let mut small = tx.clone();
+    small.meta.base_size = 64;
+    assert_eq!(
+        validate_standard_size(&small),
+        Err(SizeLimit::NonWitnessSize(64))
+    );
+    tx.vin[0].scriptsig_bytes = vec![0; 1651];
+    assert_eq!(
+        validate_standard_size(&tx),
+        Err(SizeLimit::ScriptsigSize {
+            index: 0,
+            size: 1651
+        })
+    );
+    tx.vin[0].scriptsig_bytes.pop();
+    assert_eq!(validate_standard_size(&tx), Ok(()));
+}
+
+#[test]
+fn values_are_checked_against_max_money() {
+    let max_money = 21_000_000 * 100_000_000;
+    let mut tx = fixture();
+    tx.vin.truncate(1);
+    tx.vout.truncate(1);
+    tx.vin[0].prevout.value = 1000;
+    tx.vout[0].value = 600;
+    assert_eq!(validate_values_and_set_fee(&mut tx), Ok(()));
+    assert_eq!(tx.meta.fee, 400);
+
+    tx.vout[0].value = 1001;
+    assert_eq!(
+        validate_values_and_set_fee(&mut tx),
+        Err(ValueError::OutputsAboveInputs {
+            output_sum: 1001,
+            input_sum: 1000
+        })
+    );
+    tx.vout[0].value = max_money + 1;
+    assert_eq!(
+        validate_values_and_set_fee(&mut tx),
+        Err(ValueError::AboveMaxMoney {
+            kind: "Output",
+            index: 0,
+            value: max_money + 1
+        })
+    );
+    // sums overflowing u64 and sums above MAX_MONEY are rejected
+    tx.vin[0].prevout.value = max_money;
+    tx.vin.push(tx.vin[0].clone());
+    tx.vout[0].value = 1;
+    assert_eq!(
+        validate_values_and_set_fee(&mut tx),
+        Err(ValueError::SumAboveMaxMoney("Input"))
+    );
+    tx.vin[1].prevout.value = u64::MAX;
+    assert_eq!(
+        validate_values_and_set_fee(&mut tx),
+        Err(ValueError::AboveMaxMoney {
+            kind: "Input",
+            index: 1,
+            value: u64::MAX
+        })
+    );
+    tx.vin[1].prevout.value = 0;
+    assert_eq!(validate_values_and_set_fee(&mut tx), Ok(()));
+    assert_eq!(tx.meta.fee, max_money - 1);
+}
+
+#[test]
+fn feerates_keep_sub_sat_per_vbyte_resolution() {
+    // 1 sat for 3 WU (1.333 sat/vB) and 1 sat for 4 WU (1 sat/vB) were both 0 sat/WU
+    assert_eq!(FeeRate::from_fee_weight(1, 3).to_sat_per_kwu(), 333);
+    assert!(FeeRate::from_fee_weight(1, 3) > FeeRate::from_fee_weight(1, 4));
+    assert_eq!(FeeRate::from_fee_weight(1, 4), FeeRate::MIN_RELAY);
+    assert!(FeeRate::from_fee_weight(999, 4000) < FeeRate::MIN_RELAY);
+    assert_eq!(
+        FeeRate::from_fee_weight(u64::MAX, 1).to_sat_per_kwu(),
+        u64::MAX
+    );
+    assert_eq!(
+        FeeRate::from_fee_weight(1, 0),
+        FeeRate::from_sat_per_kwu(1000)
+    );
+    assert_eq!(
+        FeeRate::from_fee_weight(4937, 561).to_string(),
+        "35.200 sat/vB"
+    );
+}
+
+#[test]
+fn vsize_rounds_up_to_whole_vbytes() {
+    assert_eq!(vsize(0), 0);
+    assert_eq!(vsize(561), 141);
+    assert_eq!(vsize(564), 141);
+    // 140 sat for 561 WU are 0.998 sat/vB, with a floored vsize of 140 vB they were 1 sat/vB
+    let mut tx = fixture();
+    tx.meta.weight = 561;
+    tx.meta.fee = 140;
+    assert!(!validate_feerate(&tx, FeeRate::MIN_RELAY));
+    tx.meta.fee = 141;
+    assert!(validate_feerate(&tx, FeeRate::MIN_RELAY));
+    assert_eq!(FeeRate::from_fee_vsize(141, 141), FeeRate::MIN_RELAY);
+}
+
+#[test]
+fn min_relay_feerate_is_configurable() {
+    let mut tx = fixture();
+    tx.meta.weight = 561;
+    tx.meta.fee = 141;
+    assert!(validate_feerate(&tx, FeeRate::from_sat_per_kvb(1000)));
+    assert!(!validate_feerate(&tx, FeeRate::from_sat_per_kvb(1001)));
+    assert!(validate_feerate(&tx, FeeRate::ZERO));
+    tx.meta.fee = 0;
+    assert!(validate_feerate(&tx, FeeRate::ZERO));
+    // sat/kvB floors round up to the next sat/kWU instead of being lowered
+    assert_eq!(FeeRate::from_sat_per_kvb(1001).to_sat_per_kwu(), 251);
+    assert_eq!(FeeRate::from_sat_per_kvb(1000), FeeRate::MIN_RELAY);
+
+    let mut tx = fixture();
+    let options = ValidationOptions {
+        min_relay_feerate: FeeRate::from_sat_per_vb(1_000_000),
+        ..Default::default()
+    };
+    let result = tx.validate(&options);
+    assert!(matches!(
+        result,
+        Err(ValidationError::FeeTooLow { min_relay_feerate, .. })
+            if min_relay_feerate == options.min_relay_feerate
+    ));
+}
+
+#[test]
+fn validate_all_collects_every_failed_check() {
+    assert!(fixture()
+        .validate_all(&ValidationOptions::default())
+        .is_empty());
+
+    // spending the first outpoint twice changes the txid and invalidates all signatures, a new
+    // fixture because the segwit hashes are cached by the first validation
+    let mut tx = fixture();
+    tx.vin.push(tx.vin[0].clone());
+    let failures = tx.validate_all(&ValidationOptions::default());
+    assert_eq!(
+        failures[..2],
+        [
+            ValidationError::TxidMismatch,
+            ValidationError::DuplicateInput
+        ]
+    );
+    let failed_inputs = failures
+        .iter()
+        .filter(|error| matches!(error, ValidationError::Input { .. }));
+    assert_eq!(failed_inputs.count(), tx.vin.len());
+    assert_eq!(
+        tx.validate(&ValidationOptions::default()),
+        Err(failures[0].clone())
+    );
+    assert_eq!(failures[0].to_string(), "Txid does not represent filename!");
+}
+
+#[test]
+fn validation_errors_display_the_report_reasons() {
+    let unsupported = ValidationError::UnsupportedInput(Some("interpreter"));
+    let input = ValidationError::Input {
+        index: 1,
+        scriptpubkey_asm: "OP_1".to_string(),
+        error: Box::new(unsupported.clone()),
+    };
+    assert_eq!(
+        input.to_string(),
+        "Input 1 [OP_1]: Input type not compiled in (feature interpreter)"
+    );
+    assert_eq!(input.root(), &unsupported);
+    assert_eq!(
+        ValidationError::UnsupportedInput(None).to_string(),
+        "Input type not implemented!"
+    );
+    assert_eq!(
+        ValidationError::SigopsExceeded {
+            sigops_cost: 16_004
+        }
+        .to_string(),
+        "Sigops cost 16004 above standard 16000!"
+    );
+    assert_eq!(
+        ValidationError::UndefinedSighash(4).to_string(),
+        "Undefined sighash type 0x04"
+    );
+    // wrapped check errors are displayed as they are
+    let size = SizeLimit::ScriptsigSize {
+        index: 0,
+        size: 1651,
+    };
+    assert_eq!(
+        ValidationError::NonStandardSize(size.clone()).to_string(),
+        size.to_string()
+    );
+    assert_eq!(
+        size.to_string(),
+        "Input 0 scriptsig size 1651 above standard 1650!"
+    );
+}
+
+// native P2WPKH example of BIP143, the second input spends 6 BTC of the P2WPKH output
+const BIP143_UNSIGNED_TX: &str = "0100000002fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f0000000000eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a0100000000ffffffff02202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac9093510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac11000000";
+const BIP143_SIGNED_TX: &str = "01000000000102fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f00000000494830450221008b9d1dc26ba6a9cb62127b02742fa9d754cd3bebf337f7a55d114c8e5cdd30be022040529b194ba3f9281a99f2b1c0a19c0489bc22ede944ccf4ecbab4cc618ef3ed01eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a0100000000ffffffff02202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac9093510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac000247304402203609e17b84f6a7d30c80bfa610b5b4542f32a8a0d5447a12fb1366d7f01cc44a0220573a954c4518331561406f90300e8f3358f51928d43c212a8caed02de67eebee0121025476c2e83188368da1ff3e292e7acafcdb3566bb0ad253f62fc70f07aeee635711000000";
+const BIP143_P2WPKH: &str = "00141d0f172a0ecb48aee1be1f2687d2963ae33f71a1";
+
+// returns: the BIP143 example transaction with the spent P2WPKH output of the second input
+fn bip143_example(raw_tx: &str) -> Transaction {
+    let mut tx = parse_raw_transaction(raw_tx).unwrap();
+    set_prevout(
+        &mut tx.vin[1],
+        &hex::decode(BIP143_P2WPKH).unwrap(),
+        600_000_000,
+    );
+    tx
+}
+
+#[test]
+fn segwit_hashes_are_cached_for_all_inputs() {
+    let tx = bip143_example(BIP143_UNSIGNED_TX);
+    assert!(tx.meta.segwit_hashes.get().is_none());
+    let hashes = tx.segwit_hashes();
+    assert_eq!(
+        hex::encode(hashes.hash_prevouts),
+        "96b827c8483d4e9b96712b6713a7b68d6e8003a781feba36c31143470b4efd37"
+    );
+    assert_eq!(
+        hex::encode(hashes.hash_sequence),
+        "52b0a642eea2fb7ae638c36f6252b6750293dbe574a806984b8e4d8548339a3b"
+    );
+    assert_eq!(
+        hex::encode(hashes.hash_outputs),
+        "863ef3e1a92afbfdb97f31ad0fc7683ee943e9abcf2501590ff8f6551f47e5e5"
+    );
+    assert!(tx.meta.segwit_hashes.get().is_some());
+
+    // scriptcode of the P2WPKH program
+    let script_code = hex::decode("76a9141d0f172a0ecb48aee1be1f2687d2963ae33f71a188ac").unwrap();
+    let sighash = get_segwit_commitment_hash(&tx, 1, &script_code, SighashType::All);
+    assert_eq!(
+        hex::encode(&sighash),
+        "c37af31116d1b27caf68aae9e3ac82f1477929014d5b917657d0eb49478cb670"
+    );
+    // the types that don't commit to the cached hashes replace them
+    for sighash_type in [SighashType::None, SighashType::AllAnyoneCanPay] {
+        let other = get_segwit_commitment_hash(&tx, 1, &script_code, sighash_type);
+        assert_ne!(other, sighash);
+    }
+
+    let tx = bip143_example(BIP143_SIGNED_TX);
+    let program = tx.vin[1].prevout.scriptpubkey_bytes[2..].to_vec();
+    let options = ValidationOptions::default();
+    assert_eq!(
+        verify_witness_program(&tx, 1, 0, &program, false, &options),
+        Ok(())
+    );
+}
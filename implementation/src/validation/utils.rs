@@ -1,47 +1,39 @@
 use crate::parsing::transaction_structs::TxIn;
 use num_traits::ToPrimitive;
 use ripemd::Ripemd160;
+use sha1::Sha1;
 use sha2::{Digest, Sha256};
 
 // returns: outpoint (rev txid bytes + index) of TxIn as serialized byte Vec<u8>
 pub fn get_outpoint(input: &TxIn) -> Vec<u8> {
-    let mut outpoint: Vec<u8> = hex::decode(&input.txid)
-        .expect("Failed to decode transaction ID")
-        .into_iter()
-        .rev()
-        .collect();
-    let outpoint_index = input.vout.to_le_bytes();
-    outpoint.extend_from_slice(&outpoint_index);
-    outpoint
+    input.outpoint.clone()
 }
 
 // returns: sha256 digest of passed byte slice as Vec<u8>
 pub fn hash_sha256(preimage: &[u8]) -> Vec<u8> {
-    let digest = preimage.to_owned();
-    let mut hasher = Sha256::new();
-
-    hasher.update(&digest);
-    hasher.finalize_reset().to_vec()
+    Sha256::digest(preimage).to_vec()
 }
 
 // Hashes byte slice argument bytes twice
 // returns: Vec<u8> of the second hash bytes
 pub fn double_hash(preimage: &[u8]) -> Vec<u8> {
-    let mut digest: Vec<u8> = preimage.to_owned();
+    Sha256::digest(Sha256::digest(preimage)).to_vec()
+}
 
-    for _ in 0..2 {
-        digest = hash_sha256(&digest);
-    }
-    digest
+// returns: ripemd160 digest of passed byte slice as Vec<u8>
+pub fn hash_ripemd160(preimage: &[u8]) -> Vec<u8> {
+    Ripemd160::digest(preimage).to_vec()
+}
+
+// returns: sha1 digest of passed byte slice as Vec<u8>
+pub fn hash_sha1(preimage: &[u8]) -> Vec<u8> {
+    Sha1::digest(preimage).to_vec()
 }
 
 // applies sha256 and ripemd160 hash on passed byte slice
 // returns: 20 byte hash as Vec<u8>
 pub fn hash160(preimage: &[u8]) -> Vec<u8> {
-    let preimage = hash_sha256(preimage);
-    let mut hasher = Ripemd160::new();
-    hasher.update(&preimage);
-    hasher.finalize().to_vec()
+    hash_ripemd160(&hash_sha256(preimage))
 }
 
 // converts a given u128 integer to a little endian Vec<u8>
@@ -72,6 +64,41 @@ pub fn varint(n: u128) -> Vec<u8> {
 // Byte vectors are interpreted as Booleans where
 // False is represented by any representation of zero and True is represented by any representation of non-zero.
 pub fn decode_num(number: &[u8]) -> i128 {
-    let number = num_bigint::BigInt::from_signed_bytes_le(number);
-    number.to_i128().expect("number outside of i128 scope")
+    let mut magnitude = number.to_vec();
+    let negative = match magnitude.last_mut() {
+        Some(last) => {
+            let negative = *last & 0x80 != 0;
+            *last &= 0x7f;
+            negative
+        }
+        None => return 0,
+    };
+    let magnitude = num_bigint::BigUint::from_bytes_le(&magnitude)
+        .to_i128()
+        .expect("number outside of i128 scope");
+    if negative {
+        -magnitude
+    } else {
+        magnitude
+    }
+}
+
+// encodes the number as minimal script number (little-endian magnitude, sign in the most
+// significant bit), 0 is encoded as empty vector
+pub fn encode_num(number: i64) -> Vec<u8> {
+    let mut magnitude = number.unsigned_abs();
+    let mut encoded = Vec::new();
+
+    while magnitude > 0 {
+        encoded.push((magnitude & 0xff) as u8);
+        magnitude >>= 8;
+    }
+    if let Some(last) = encoded.last_mut() {
+        if *last & 0x80 != 0 {
+            encoded.push(if number < 0 { 0x80 } else { 0x00 });
+        } else if number < 0 {
+            *last |= 0x80;
+        }
+    }
+    encoded
 }
@@ -1,5 +1,7 @@
 use super::utils::*;
+use super::validation_error::AddressError;
 use super::weight_calculation::is_segwit;
+use crate::address::decode_address;
 use crate::parsing::transaction_structs::{Transaction, TxIn, TxOut};
 use hex_literal::hex as hexlit;
 use sha2::{Digest, Sha256};
@@ -20,24 +22,61 @@ fn hash_txid(txid: Vec<u8>) -> String {
     format!("{:x}", hasher.finalize())
 }
 
+// appends the byte serialized &TxIn to the buffer
+fn write_input(input: &TxIn, buffer: &mut Vec<u8>) {
+    buffer.extend_from_slice(&input.outpoint);
+    buffer.extend(varint(input.scriptsig_bytes.len() as u128));
+    buffer.extend_from_slice(&input.scriptsig_bytes);
+    buffer.extend_from_slice(&input.sequence.to_le_bytes());
+}
+
+// appends the byte serialized &TxOut to the buffer
+fn write_output(output: &TxOut, buffer: &mut Vec<u8>) {
+    buffer.extend_from_slice(&output.value.to_le_bytes());
+    buffer.extend(varint(output.scriptpubkey_bytes.len() as u128));
+    buffer.extend_from_slice(&output.scriptpubkey_bytes);
+}
+
+// appends the witnesses of all inputs in the given &Transaction to the buffer
+fn write_witnesses(tx: &Transaction, buffer: &mut Vec<u8>) {
+    for input in &tx.vin {
+        if let Some(witness) = input.witness_bytes.as_ref() {
+            buffer.extend(varint(witness.len() as u128));
+            for witness_element in witness {
+                buffer.extend(varint(witness_element.len() as u128));
+                buffer.extend_from_slice(witness_element);
+            }
+        } else {
+            buffer.extend(hexlit!("00")); // non witness inputs
+        };
+    }
+}
+
+// upper bound of the serialized size of the transaction (varints counted with 9 bytes)
+// used to preallocate the serialization buffers
+fn serialized_size_bound(tx: &Transaction) -> usize {
+    let mut size = 4 + 2 + 9 + 9 + 4; // version, marker + flag, in/output count, locktime
+    for input in &tx.vin {
+        size += 36 + 9 + input.scriptsig_bytes.len() + 4 + 9;
+        if let Some(witness) = input.witness_bytes.as_ref() {
+            size += witness
+                .iter()
+                .map(|element| 9 + element.len())
+                .sum::<usize>();
+        }
+    }
+    for output in &tx.vout {
+        size += 8 + 9 + output.scriptpubkey_bytes.len();
+    }
+    size
+}
+
 // serialize given &TxIn to a byte-Vec<u8> for later use in assembling the full transaction
 // used for calculation of txid
 // returns: Vec<u8> of the byte serialized &TxIn
 pub fn serialize_input(input: &TxIn) -> Vec<u8> {
-    let mut serialized_input = get_outpoint(input);
-    let scriptsig_len = match &input.scriptsig {
-        Some(s) => hex::decode(s).expect("Hex decode ss len failed").len(),
-        None => 0,
-    };
-    let scriptsig_len = varint(scriptsig_len as u128);
-    let scriptsig_bytes = match &input.scriptsig {
-        Some(s) => hex::decode(s).expect("Hex decode ss bytes failed!"),
-        None => Vec::new(),
-    };
-    let sequence_bytes = input.sequence.to_le_bytes();
-    serialized_input.extend(scriptsig_len);
-    serialized_input.extend(scriptsig_bytes);
-    serialized_input.extend_from_slice(&sequence_bytes);
+    let mut serialized_input = Vec::with_capacity(36 + 9 + input.scriptsig_bytes.len() + 4);
+    write_input(input, &mut serialized_input);
     serialized_input
 }
 
@@ -45,102 +84,168 @@ pub fn serialize_input(input: &TxIn) -> Vec<u8> {
 // used for calculation of the txid
 // returns: Vec<u8> of the byte serialized &TxOut
 pub fn serialize_output(output: &TxOut) -> Vec<u8> {
-    let mut serialized_output: Vec<u8> = Vec::new();
-    let value = output.value.to_le_bytes();
-    let pubkey_script_len = match &output.scriptpubkey {
-        Some(s) => hex::decode(s)
-            .expect("hex decode output s len failed!")
-            .len(),
-        None => 0,
-    };
-    let pubkey_script_len = varint(pubkey_script_len as u128);
-    let pubkey_script_bytes = match &output.scriptpubkey {
-        Some(s) => hex::decode(s).expect("Hex decode output s failed!"),
-        None => Vec::new(),
-    };
-    serialized_output.extend_from_slice(&value);
-    serialized_output.extend(pubkey_script_len);
-    serialized_output.extend(pubkey_script_bytes);
+    let mut serialized_output = Vec::with_capacity(8 + 9 + output.scriptpubkey_bytes.len());
+    write_output(output, &mut serialized_output);
     serialized_output
 }
 
 // byte-serializes all witnesses in the given &Transaction
 // returns: Vec<u8> of the byte representation of all witnesses in the transaction
-fn serialize_witnesses_with_amount(tx: &Transaction) -> Vec<u8> {
+pub fn serialize_witnesses_with_amount(tx: &Transaction) -> Vec<u8> {
     let mut witnesses: Vec<u8> = Vec::new();
+    write_witnesses(tx, &mut witnesses);
+    witnesses
+}
 
-    for input in &tx.vin {
-        if let Some(witnesses_hex) = input.witness.as_ref() {
-            witnesses.extend(varint(witnesses_hex.len() as u128));
-            for witness_element in witnesses_hex {
-                let witness_element_bytes: Vec<u8> =
-                    hex::decode(witness_element).expect("decoding witness hex failed");
-                witnesses.extend(varint(witness_element_bytes.len() as u128));
-                witnesses.extend(witness_element_bytes);
-            }
-        } else {
-            witnesses.extend(hexlit!("00").to_vec()); // non witness inputs
-        };
+// serializes the transaction like assemble_txid_preimage() into the passed buffer,
+// the buffer is cleared first so it can be reused for several transactions
+pub fn assemble_txid_preimage_into(tx: &Transaction, witness: bool, buffer: &mut Vec<u8>) {
+    buffer.clear();
+    buffer.reserve(serialized_size_bound(tx));
+    buffer.extend_from_slice(&tx.version.to_le_bytes());
+    if witness {
+        buffer.extend(hexlit!("0001"))
+    };
+    buffer.extend(varint(tx.vin.len() as u128));
+    for tx_in in &tx.vin {
+        write_input(tx_in, buffer);
     }
-    witnesses
+    buffer.extend(varint(tx.vout.len() as u128));
+    for tx_out in &tx.vout {
+        write_output(tx_out, buffer);
+    }
+    if witness {
+        write_witnesses(tx, buffer);
+    };
+    buffer.extend_from_slice(&tx.locktime.to_le_bytes());
 }
 
 // assembles/serializes the transaction according to the reference.
 // includes witness, marker and flag if argument witness is true for wtxid calculation
 // if witness argument is false the returned bytes represent the data to hash for the txid
 // returns: Vec<u8> of assembled transaction byte, either for txid or wtxid
-fn assemble_txid_preimage(tx: &Transaction, witness: bool) -> Vec<u8> {
+pub fn assemble_txid_preimage(tx: &Transaction, witness: bool) -> Vec<u8> {
     let mut preimage: Vec<u8> = Vec::new();
-    let version: [u8; 4] = tx.version.to_le_bytes();
+    assemble_txid_preimage_into(tx, witness, &mut preimage);
+    preimage
+}
 
-    let len_inputs: Vec<u8> = varint(tx.vin.len() as u128);
-    let mut all_input_bytes: Vec<u8> = Vec::new();
-    for tx_in in &tx.vin {
-        all_input_bytes.append(&mut serialize_input(tx_in));
+// decodes the scriptpubkey_address of every prevout and output and compares the scriptpubkey
+// it pays to with the scriptpubkey of the json, scripts without address (p2pk, bare multisig,
+// op_return) aren't checked
+// returns: Ok() or Err(AddressError) for an invalid address or a mismatch
+pub fn validate_addresses(tx: &Transaction) -> Result<(), AddressError> {
+    let prevouts = tx.vin.iter().map(|txin| {
+        let prevout = &txin.prevout;
+        (&prevout.scriptpubkey_address, &prevout.scriptpubkey_bytes)
+    });
+    let outputs = tx
+        .vout
+        .iter()
+        .map(|txout| (&txout.scriptpubkey_address, &txout.scriptpubkey_bytes));
+    for (address, scriptpubkey) in prevouts.chain(outputs) {
+        if let Some(address) = address {
+            if decode_address(address).map_err(AddressError::Invalid)? != *scriptpubkey {
+                return Err(AddressError::Mismatch {
+                    address: address.clone(),
+                    scriptpubkey: hex::encode(scriptpubkey),
+                });
+            }
+        }
     }
-    let len_outputs = varint(tx.vout.len() as u128);
-    let mut all_output_bytes: Vec<u8> = Vec::new();
-    for tx_out in &tx.vout {
-        all_output_bytes.append(&mut serialize_output(tx_out));
+    Ok(())
+}
+
+// returns: true if the script is OP_m <33 or 65 byte pubkeys> OP_n OP_CHECKMULTISIG with
+// n pubkeys and m <= n
+fn is_bare_multisig(script: &[u8]) -> bool {
+    if script.len() < 3 || !(0x51..=0x60).contains(&script[0]) || script[script.len() - 1] != 0xae {
+        return false;
+    }
+    let key_count = script[script.len() - 2];
+    let mut index = 1;
+    let mut keys = 0;
+    while index < script.len() - 2 {
+        let length = script[index] as usize;
+        if length != 33 && length != 65 {
+            return false;
+        }
+        index += 1 + length;
+        keys += 1;
+    }
+    index == script.len() - 2
+        && (0x51..=0x60).contains(&key_count)
+        && keys == (key_count - 0x50) as usize
+        && script[0] <= key_count
+}
+
+// classifies the script by the standard templates with the names of the esplora json
+// returns: scriptpubkey_type of the script, "unknown" if it matches no template
+pub fn scriptpubkey_type(script: &[u8]) -> &'static str {
+    match script {
+        [0x76, 0xa9, 0x14, .., 0x88, 0xac] if script.len() == 25 => "p2pkh",
+        [0xa9, 0x14, .., 0x87] if script.len() == 23 => "p2sh",
+        [0x00, 0x14, ..] if script.len() == 22 => "v0_p2wpkh",
+        [0x00, 0x20, ..] if script.len() == 34 => "v0_p2wsh",
+        [0x51, 0x20, ..] if script.len() == 34 => "v1_p2tr",
+        [0x21, .., 0xac] if script.len() == 35 => "p2pk",
+        [0x41, .., 0xac] if script.len() == 67 => "p2pk",
+        [0x6a, ..] => "op_return",
+        _ if is_bare_multisig(script) => "multisig",
+        _ => "unknown",
     }
-    let locktime = tx.locktime.to_le_bytes();
-    preimage.extend_from_slice(&version);
-    if witness {
-        preimage.extend(hexlit!("0001"))
-    };
-    preimage.extend(len_inputs);
-    preimage.extend(all_input_bytes);
-    preimage.extend_from_slice(&len_outputs);
-    preimage.extend(all_output_bytes);
-    if witness {
-        preimage.extend(&serialize_witnesses_with_amount(tx));
-    };
-    preimage.extend_from_slice(&locktime);
-    preimage
+}
+
+// compares the scriptpubkey_type claimed by the prevout of each input with the type of its
+// scriptpubkey, bare multisig may also be claimed as unknown
+// returns: index and actual type of the first input whose prevout claims another type
+pub fn find_prevout_type_mismatch(tx: &Transaction) -> Option<(usize, &'static str)> {
+    tx.vin.iter().enumerate().find_map(|(index, txin)| {
+        let actual = scriptpubkey_type(&txin.prevout.scriptpubkey_bytes);
+        let claimed = txin.prevout.scriptpubkey_type.as_str();
+        match (claimed, actual) {
+            _ if claimed == actual => None,
+            ("unknown", "multisig") => None,
+            _ => Some((index, actual)),
+        }
+    })
 }
 
 // calculates txid and wtxid of the passed Transaction. Compares hash of txid
-// against json filename to validate correct parsing and re-serialization.
-// stores wtxid and txid in the &mut Transaction for further use.
+// against json filename to validate correct parsing and re-serialization, transactions without
+// json file (raw or node sources) against the txid their source set in the metadata.
+// stores wtxid, txid and the serialized sizes in the &mut Transaction for further use.
 // returns: true if triple hash of transaction data (without witness) equals json filename
 pub fn validate_txid_hash_filename(tx: &mut Transaction) -> bool {
-    let tx_preimage = assemble_txid_preimage(tx, false);
-    let txid_bytes = get_txid(&tx_preimage);
+    let source_txid = tx
+        .meta
+        .json_path
+        .is_none()
+        .then(|| tx.meta.txid_hex.clone());
+    let mut preimage: Vec<u8> = Vec::new();
+    assemble_txid_preimage_into(tx, false, &mut preimage);
+    let txid_bytes = get_txid(&preimage);
+    tx.meta.base_size = preimage.len();
 
     let wtxid_bytes: Vec<u8> = if is_segwit(tx) {
-        let wtx_preimage = assemble_txid_preimage(tx, true);
-        get_txid(&wtx_preimage)
+        assemble_txid_preimage_into(tx, true, &mut preimage);
+        get_txid(&preimage)
     } else {
         txid_bytes.clone()
     };
+    tx.meta.total_size = preimage.len();
     tx.meta.txid_hex = hex::encode(&txid_bytes);
     tx.meta.wtxid_hex = hex::encode(wtxid_bytes);
+    if let Some(source_txid) = source_txid {
+        return source_txid == tx.meta.txid_hex;
+    }
     let triple_hashed = hash_txid(txid_bytes);
     if let Some(json_path) = tx.meta.json_path.as_ref() {
         let path = Path::new(json_path);
-        if let Some(filename) = path.file_stem() {
+        // name up to the first dot, compressed files end in .json.gz or .json.zst
+        if let Some(filename) = path.file_name() {
             if let Some(filename_str) = filename.to_str() {
-                return filename_str == triple_hashed;
+                return filename_str.split('.').next() == Some(triple_hashed.as_str());
             }
         }
     }
@@ -1,42 +1,175 @@
-use crate::parsing::transaction_structs::Transaction;
+use super::signature_verification::witness_program;
+use super::utils::varint;
+use super::validation_error::ValueError;
+use crate::amount::MAX_MONEY;
+use crate::feerate::{vsize, FeeRate};
+use crate::mining::timelock::{
+    coinbase_spends_mature, is_final, sequence_locks_satisfied, Confirmation,
+};
+use crate::parsing::transaction_structs::{Transaction, TxOut};
+use std::collections::{HashMap, HashSet};
 
-// checks the input sum of the passed &mut Transaction against the output sum
-// to prevent money creation. Also checks if there are inputs and outputs.
-// Sets the delta between input and output as fee (in satoshi) in the &mut Transaction.
-// returns: true if valid
-pub fn validate_values_and_set_fee(tx: &mut Transaction) -> bool {
-    let mut input_sum = 0;
-    let mut output_sum = 0;
+const NULL_OUTPOINT_TXID: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+const NULL_OUTPOINT_VOUT: u32 = 0xffffffff;
+const OP_RETURN: u8 = 0x6a;
+// longer scriptpubkeys are unspendable
+const MAX_SCRIPT_SIZE: usize = 10_000;
+// default dust relay feerate of bitcoin core in sat per 1000 vbytes
+pub const DUST_RELAY_FEERATE: u64 = 3000;
+// vbytes of the input spending an output: outpoint, scriptsig length, sequence and a 107 byte
+// P2PKH like scriptsig, which is discounted to a quarter as witness of witness programs
+const DUST_INPUT_SIZE: usize = 32 + 4 + 1 + 107 + 4;
+const DUST_WITNESS_INPUT_SIZE: usize = 32 + 4 + 1 + 107 / 4 + 4;
 
-    if tx.vin.is_empty() || tx.vout.is_empty() {
-        // no in or outputs
-        return false;
-    }
+// block the transactions have to be final in to be valid
+#[derive(Debug, Clone, PartialEq)]
+pub struct FinalityTarget {
+    pub height: u64,                            // height of the block that is being built
+    pub median_time_past: Option<u32>,          // MTP of the chain tip, time locktimes pass if None
+    pub coinbase_heights: HashMap<String, u64>, // coinbase txid -> height, others aren't checked
+}
+
+// checks that no input claims the null outpoint (all zero txid and index 0xffffffff) or the
+// coinbase flag, only the coinbase constructed by the miner may spend it
+// returns: true if valid
+pub fn validate_no_coinbase_inputs(tx: &Transaction) -> bool {
     for txin in &tx.vin {
-        input_sum += txin.prevout.value;
-    }
-    for txout in &tx.vout {
-        output_sum += txout.value;
+        if txin.is_coinbase || (txin.txid == NULL_OUTPOINT_TXID && txin.vout == NULL_OUTPOINT_VOUT)
+        {
+            return false;
+        }
     }
-    if input_sum < output_sum {
-        // no inflation!
-        return false;
+    true
+}
+
+// checks that every input spends a different outpoint, spending one twice would double the
+// input value
+// returns: true if valid
+pub fn validate_no_duplicate_inputs(tx: &Transaction) -> bool {
+    let mut outpoints: HashSet<(&str, u32)> = HashSet::with_capacity(tx.vin.len());
+    tx.vin
+        .iter()
+        .all(|txin| outpoints.insert((txin.txid.as_str(), txin.vout)))
+}
+
+// checks that the nLockTime is reached at the target block height (height locktimes) or MTP
+// (time locktimes), a transaction whose inputs all have the sequence 0xffffffff is final
+// regardless of its locktime
+// returns: true if valid
+pub fn validate_locktime_final(tx: &Transaction, target: &FinalityTarget) -> bool {
+    is_final(tx, target.height, target.median_time_past)
+}
+
+// checks the BIP68 relative locktimes of version 2 or later transactions at the target block.
+// Outputs of mempool transactions (in_mempool(txid)) are confirmed in the same block, other
+// spent outputs are considered confirmed long enough ago.
+// returns: true if valid
+pub fn validate_sequence_locks_final(
+    tx: &Transaction,
+    target: &FinalityTarget,
+    in_mempool: impl Fn(&str) -> bool,
+) -> bool {
+    let confirmation = |index: usize| match in_mempool(&tx.vin[index].txid) {
+        true => None,
+        false => Some(Confirmation {
+            height: 0,
+            median_time_past: Some(0),
+            coinbase: false,
+        }),
+    };
+    sequence_locks_satisfied(tx, target.height, target.median_time_past, confirmation)
+}
+
+// checks that the spent outputs of the coinbase transactions with known heights have
+// COINBASE_MATURITY confirmations at the target block
+// returns: true if valid
+pub fn validate_coinbase_spends_mature(tx: &Transaction, target: &FinalityTarget) -> bool {
+    let confirmation = |index: usize| {
+        target
+            .coinbase_heights
+            .get(&tx.vin[index].txid)
+            .map(|height| Confirmation {
+                height: *height,
+                median_time_past: None,
+                coinbase: true,
+            })
+    };
+    coinbase_spends_mature(tx, target.height, confirmation)
+}
+
+// the dust threshold is the fee of the output and the input spending it at the dust relay
+// feerate (sat per 1000 vbytes), unspendable outputs (OP_RETURN) have none
+// returns: dust threshold of the output in satoshi
+pub fn dust_threshold(txout: &TxOut, dust_relay_feerate: u64) -> u64 {
+    let script = &txout.scriptpubkey_bytes;
+    if script.first() == Some(&OP_RETURN) || script.len() > MAX_SCRIPT_SIZE {
+        return 0;
     }
-    if input_sum > (20999999 * 100000000) || output_sum > (20999999 * 100000000) {
-        // this is unrealistic
-        return false;
+    let output_size = 8 + varint(script.len() as u128).len() + script.len();
+    let input_size = match witness_program(script) {
+        Some(_) => DUST_WITNESS_INPUT_SIZE,
+        None => DUST_INPUT_SIZE,
     };
-    tx.meta.fee = input_sum - output_sum;
-    true
+    (output_size + input_size) as u64 * dust_relay_feerate / 1000
+}
+
+// returns: (index, threshold) of the first output with a value below its dust threshold
+pub fn find_dust_output(tx: &Transaction, dust_relay_feerate: u64) -> Option<(usize, u64)> {
+    tx.vout
+        .iter()
+        .map(|txout| (txout.value, dust_threshold(txout, dust_relay_feerate)))
+        .enumerate()
+        .find(|(_, (value, threshold))| value < threshold)
+        .map(|(index, (_, threshold))| (index, threshold))
 }
 
-// checks if feerate is below 1sat/vbyte which is not being relayed (standard)
-// returns: true if > 1 sat/vbyte
-pub fn validate_feerate(tx: &Transaction) -> bool {
-    let vbyte_size: u64 = tx.meta.weight / 4;
-    let feerate = tx.meta.fee / vbyte_size;
-    if feerate < 1 {
-        return false;
+// sums the values with checked additions, every value and the sum have to be in the money
+// range up to MAX_MONEY
+// returns: sum or Err(ValueError) naming the first value out of range
+fn sum_money_range(
+    values: impl Iterator<Item = u64>,
+    kind: &'static str,
+) -> Result<u64, ValueError> {
+    let mut sum: u64 = 0;
+    for (index, value) in values.enumerate() {
+        if value > MAX_MONEY {
+            return Err(ValueError::AboveMaxMoney { kind, index, value });
+        }
+        sum = match sum.checked_add(value) {
+            Some(sum) if sum <= MAX_MONEY => sum,
+            _ => return Err(ValueError::SumAboveMaxMoney(kind)),
+        };
     }
-    true
+    Ok(sum)
+}
+
+// checks the input sum of the passed &mut Transaction against the output sum
+// to prevent money creation. Also checks if there are inputs and outputs and that all values
+// and sums are in the money range.
+// Sets the delta between input and output as fee (in satoshi) in the &mut Transaction.
+// returns: Ok() or Err(ValueError)
+pub fn validate_values_and_set_fee(tx: &mut Transaction) -> Result<(), ValueError> {
+    if tx.vin.is_empty() {
+        return Err(ValueError::NoInputs);
+    }
+    if tx.vout.is_empty() {
+        return Err(ValueError::NoOutputs);
+    }
+    let input_sum = sum_money_range(tx.vin.iter().map(|txin| txin.prevout.value), "Input")?;
+    let output_sum = sum_money_range(tx.vout.iter().map(|txout| txout.value), "Output")?;
+    // no inflation!
+    tx.meta.fee = input_sum
+        .checked_sub(output_sum)
+        .ok_or(ValueError::OutputsAboveInputs {
+            output_sum,
+            input_sum,
+        })?;
+    Ok(())
+}
+
+// checks if feerate is below the minimum relay feerate (1 sat/vbyte by default) below which
+// transactions are not being relayed (standard), the vsize is rounded up like in bitcoin core
+// returns: true if >= min_relay_feerate
+pub fn validate_feerate(tx: &Transaction, min_relay_feerate: FeeRate) -> bool {
+    FeeRate::from_fee_vsize(tx.meta.fee, vsize(tx.meta.weight)) >= min_relay_feerate
 }
@@ -0,0 +1,243 @@
+// Reasons a transaction is rejected by the validation. The Display output is the reason written
+// to the validation report, failures of an input are wrapped in ::Input with the input index and
+// the spent scriptpubkey so they can still be filtered by the underlying error. The checks with
+// several failure modes carry their own enum (ValueError, AddressError, ..) instead of a message.
+
+#[cfg(feature = "interpreter")]
+use super::script_error::ScriptError;
+use super::sigops::MAX_STANDARD_TX_SIGOPS_COST;
+use super::weight_calculation::{
+    MAX_STANDARD_SCRIPTSIG_SIZE, MAX_STANDARD_TX_WEIGHT, MIN_STANDARD_TX_NONWITNESS_SIZE,
+};
+use crate::feerate::FeeRate;
+use secp256k1::Error as SecpError;
+use thiserror::Error;
+
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum ValidationError {
+    #[error(transparent)]
+    Values(ValueError), // invalid, out of range or unbalanced input and output values
+    #[error("Txid does not represent filename!")]
+    TxidMismatch, // txid of the transaction doesn't match its file name
+    #[error(transparent)]
+    Address(AddressError), // address of a prevout or output doesn't match its scriptpubkey
+    #[error("Input {index} prevout claims type {claimed} but its scriptpubkey is {actual}!")]
+    PrevoutType {
+        index: usize,
+        claimed: String,
+        actual: &'static str,
+    }, // scriptpubkey of a prevout doesn't match its claimed scriptpubkey_type
+    #[error("Input claims the coinbase null outpoint!")]
+    CoinbaseInput, // input claims the coinbase null outpoint
+    #[error("Outpoint spent by two inputs!")]
+    DuplicateInput, // outpoint spent by two inputs
+    #[error("Locktime {locktime} not final at height {height}!")]
+    NonFinal { locktime: u32, height: u64 },
+    #[error("Spends an immature coinbase output at height {height}!")]
+    ImmatureCoinbaseSpend { height: u64 },
+    #[error("Relative locktime not satisfied at height {height}!")]
+    RelativeLocktime { height: u64 }, // BIP68 relative locktime not satisfied
+    #[error("Output {index} value {value} below dust threshold {threshold}!")]
+    Dust {
+        index: usize,
+        value: u64,
+        threshold: u64,
+    },
+    #[error(transparent)]
+    Witness(WitnessError), // marker and flag without witness data or an unexpected witness
+    #[error("Transaction weight {weight} too high!")]
+    WeightExceeded { weight: u64 },
+    #[error("Weight self-consistency check failed: computed {computed}, serialized {serialized}")]
+    WeightMismatch { computed: u64, serialized: u64 },
+    #[error("Sigops cost {sigops_cost} above standard {MAX_STANDARD_TX_SIGOPS_COST}!")]
+    SigopsExceeded { sigops_cost: u64 },
+    #[error(transparent)]
+    NonStandardSize(SizeLimit), // exceeds a policy size limit (--policy)
+    #[error("too low feerate {feerate}, below {min_relay_feerate}")]
+    FeeTooLow {
+        feerate: FeeRate,
+        min_relay_feerate: FeeRate,
+    },
+    #[error("Invalid mempool parent")]
+    InvalidParent, // spends an output of an invalid mempool transaction
+    #[error("Input {index} [{scriptpubkey_asm}]: {error}")]
+    Input {
+        index: usize,
+        scriptpubkey_asm: String, // spent scriptpubkey in bitcoin core's ASM
+        error: Box<ValidationError>,
+    },
+    #[error("{}", unsupported_input(.0))]
+    UnsupportedInput(Option<&'static str>), // cargo feature not compiled in, None if unimplemented
+    #[error(transparent)]
+    MalformedInput(MalformedInput), // witness or scriptsig structure doesn't fit the spent output
+    #[error(transparent)]
+    Signature(SignatureError), // invalid signature encoding, public key or ecdsa/schnorr signature
+    #[error("Sighash {0:#04x} not accepted")]
+    SighashNotAccepted(u8), // defined sighash type not in ValidationOptions::accepted_sighash_types
+    #[error("Undefined sighash type {0:#04x}")]
+    UndefinedSighash(u8),
+    #[cfg(feature = "interpreter")]
+    #[error(transparent)]
+    ScriptError(#[from] ScriptError), // failure of the scriptsig and scriptpubkey or tapscript execution
+    #[cfg(feature = "interpreter")]
+    #[error("{script}: {error}")]
+    ScriptFailed { script: String, error: ScriptError }, // failure of a redeem/witness script
+}
+
+impl ValidationError {
+    // returns: the error of the input for ::Input, else the error itself
+    pub fn root(&self) -> &ValidationError {
+        match self {
+            ValidationError::Input { error, .. } => error.root(),
+            _ => self,
+        }
+    }
+}
+
+// returns: the reason of ValidationError::UnsupportedInput
+fn unsupported_input(feature: &Option<&str>) -> String {
+    match feature {
+        Some(feature) => format!("Input type not compiled in (feature {})", feature),
+        None => "Input type not implemented!".to_string(),
+    }
+}
+
+// invalid, out of range or unbalanced input and output values
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum ValueError {
+    #[error("Transaction has no inputs!")]
+    NoInputs,
+    #[error("Transaction has no outputs!")]
+    NoOutputs,
+    #[error("{kind} {index} value {value} above MAX_MONEY!")]
+    AboveMaxMoney {
+        kind: &'static str, // "Input" or "Output"
+        index: usize,
+        value: u64,
+    },
+    #[error("{0} value sum above MAX_MONEY!")]
+    SumAboveMaxMoney(&'static str), // "Input" or "Output"
+    #[error("Output sum {output_sum} above input sum {input_sum}!")]
+    OutputsAboveInputs { output_sum: u64, input_sum: u64 },
+}
+
+// address of a prevout or output that isn't valid or pays to another scriptpubkey
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum AddressError {
+    #[error("{0}")]
+    Invalid(String), // reason of decode_address()
+    #[error("Address {address} doesn't match scriptpubkey {scriptpubkey}")]
+    Mismatch {
+        address: String,
+        scriptpubkey: String, // hex
+    },
+}
+
+// witness data that is inconsistent with the transaction serialization or the spent outputs
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum WitnessError {
+    #[error("Marker and flag set but all witnesses are empty!")]
+    AllEmpty, // marker and flag set without any witness item
+    #[error("Input {0} has a witness but spends no witness program!")]
+    WithoutProgram(usize), // index of an input with a witness that spends no witness program
+}
+
+// exceeded standard size limit, the limits are the constants of weight_calculation
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum SizeLimit {
+    #[error("Transaction weight {0} above standard {MAX_STANDARD_TX_WEIGHT}!")]
+    Weight(u64), // above MAX_STANDARD_TX_WEIGHT
+    #[error("Transaction non-witness size {0} below standard {MIN_STANDARD_TX_NONWITNESS_SIZE}!")]
+    NonWitnessSize(usize), // below MIN_STANDARD_TX_NONWITNESS_SIZE
+    #[error("Input {index} scriptsig size {size} above standard {MAX_STANDARD_SCRIPTSIG_SIZE}!")]
+    ScriptsigSize { index: usize, size: usize }, // above MAX_STANDARD_SCRIPTSIG_SIZE
+}
+
+// scriptsig, witness or scriptpubkey structure that doesn't fit the spent output type
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum MalformedInput {
+    #[error("No witness in transaction!")]
+    MissingWitness,
+    #[error("P2WPKH witness has {0} instead of 2 items")]
+    P2wpkhWitnessItems(usize), // number of witness items instead of 2
+    #[error("Empty witness signature!")]
+    EmptyWitnessSignature,
+    #[error("{0} scriptsig is not empty")]
+    NonEmptyScriptsig(&'static str), // "Native segwit" or "P2TR"
+    #[error("{0} scriptsig empty")]
+    EmptyScriptsig(&'static str), // spent output type
+    #[error("Witness v{version} program of {length} bytes is reserved for soft fork upgrades")]
+    ReservedWitnessProgram { version: u8, length: usize },
+    #[error("Witness v0 program with invalid length {0}")]
+    WitnessV0ProgramLength(usize),
+    #[error("Witness script hash {script_hash} doesn't match witness program {program}")]
+    WitnessScriptHash {
+        script_hash: String, // hex
+        program: String,     // hex
+    },
+    #[error("P2SH wrapped segwit scriptsig is not a single push of the redeem script")]
+    WrappedSegwitScriptsig, // P2SH wrapped segwit scriptsig isn't a single push
+    #[error("Redeem script is no witness program")]
+    RedeemScriptNoWitnessProgram,
+    #[error("P2SH scriptpubkey has no 20 byte hash")]
+    P2shScriptpubkey, // P2SH scriptpubkey without 20 byte hash
+    #[error("P2SH scriptsig is not push only")]
+    P2shNotPushOnly,
+    #[error(
+        "Redeem script hash {redeem_script_hash} doesn't match scriptpubkey {scriptpubkey_hash}"
+    )]
+    RedeemScriptHash {
+        redeem_script_hash: String, // hex
+        scriptpubkey_hash: String,  // hex
+    },
+    #[error("P2TR scriptpubkey has no 32 byte key")]
+    P2trScriptpubkey, // P2TR scriptpubkey without 32 byte key
+    #[error("Control block with invalid size {0}")]
+    ControlBlockSize(usize),
+    #[error("Invalid control block internal key: {0}")]
+    InternalKey(SecpError), // control block internal key isn't an x-only public key
+    #[error("Invalid taproot output key: {0}")]
+    OutputKey(SecpError), // taproot output key isn't an x-only public key
+    #[error("Taproot tweak out of range")]
+    TweakOutOfRange,
+    #[error("Control block doesn't commit to the taproot output key")]
+    ControlBlockCommitment, // output key isn't the internal key tweaked with the merkle root
+    #[error("Taproot leaf version {0:#04x} is reserved for soft fork upgrades")]
+    ReservedLeafVersion(u8), // leaf version other than tapscript (DISCOURAGE_UPGRADABLE_TAPROOT_VERSION)
+}
+
+// invalid signature encoding, sighash type, public key or ecdsa/schnorr signature
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum SignatureError {
+    #[error("Signature is not strict DER encoded")]
+    NotStrictDer,
+    #[error("Signature has a high S value")]
+    HighS,
+    #[error("Pubkeys unequal, witness: {witness} | scriptpubkey: {scriptpubkey}")]
+    PubkeyHashMismatch {
+        witness: String,      // hash160 of the witness pubkey, hex
+        scriptpubkey: String, // hex
+    },
+    #[error("Loading DER encoded signature failed: {0}")]
+    EcdsaEncoding(SecpError),
+    #[error("Invalid pubkey: {0}")]
+    Pubkey(SecpError),
+    #[error("Signature verification failed: {0}")]
+    EcdsaVerification(SecpError),
+    #[error("Explicit SIGHASH_DEFAULT byte in schnorr signature")]
+    ExplicitDefaultSighash, // 65 byte schnorr signature with the SIGHASH_DEFAULT byte
+    #[error("Invalid taproot sighash type {0:#04x}")]
+    TaprootSighash(u8), // undefined sighash type of a schnorr signature
+    #[error("Schnorr signature with invalid length {0}")]
+    SchnorrLength(usize),
+    #[error("Sighash {0:#04x} not accepted")]
+    SighashNotAccepted(u8),
+    #[error("SIGHASH_SINGLE without output at the input index")]
+    SingleWithoutOutput, // SIGHASH_SINGLE without output at the input index
+    #[error("Loading schnorr signature failed: {0}")]
+    SchnorrEncoding(SecpError),
+    #[error("Loading x-only pubkey failed: {0}")]
+    XOnlyPubkey(SecpError),
+    #[error("Schnorr signature verification failed: {0}")]
+    SchnorrVerification(SecpError),
+}
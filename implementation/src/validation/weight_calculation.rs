@@ -1,6 +1,16 @@
+use super::signature_verification::witness_program;
+use super::sigops::redeem_script;
+use super::validation_error::{SizeLimit, WitnessError};
 use crate::parsing::transaction_structs::Transaction;
 use crate::validation::utils::varint;
-use crate::validation::validate_parsing::{serialize_input, serialize_output};
+use crate::validation::validate_parsing::{
+    serialize_input, serialize_output, serialize_witnesses_with_amount,
+};
+
+// standardness limits of relaying nodes
+pub const MAX_STANDARD_TX_WEIGHT: u64 = 400_000;
+pub const MIN_STANDARD_TX_NONWITNESS_SIZE: usize = 65; // smaller ones could be merkle tree nodes
+pub const MAX_STANDARD_SCRIPTSIG_SIZE: usize = 1650; // fits a 15-of-15 P2SH multisig spend
 
 // Weight multipliers for calculation of weight units from bytes:
 // -------------------
@@ -23,6 +33,38 @@ pub fn is_segwit(tx: &Transaction) -> bool {
     false
 }
 
+// BIP144 forbids marker and flag without any witness data (a node serializes such a transaction
+// without them, so its wtxid and weight would differ). With the WITNESS rules (witness_rules)
+// only inputs spending a witness program, natively or as P2SH redeem script, may carry a witness.
+// returns: Ok() or Err(WitnessError) of the first inconsistency
+pub fn validate_witness_consistency(
+    tx: &Transaction,
+    witness_rules: bool,
+) -> Result<(), WitnessError> {
+    let has_witness = |index: usize| {
+        tx.vin[index]
+            .witness_bytes
+            .as_ref()
+            .is_some_and(|witness| !witness.is_empty())
+    };
+    if is_segwit(tx) && !(0..tx.vin.len()).any(has_witness) {
+        return Err(WitnessError::AllEmpty);
+    }
+    if !witness_rules {
+        return Ok(());
+    }
+    for (index, txin) in tx.vin.iter().enumerate() {
+        let program_script = match redeem_script(txin) {
+            Some(redeem_script) => redeem_script,
+            None => &txin.prevout.scriptpubkey_bytes,
+        };
+        if has_witness(index) && witness_program(program_script).is_none() {
+            return Err(WitnessError::WithoutProgram(index));
+        }
+    }
+    Ok(())
+}
+
 // returns: size of the complete input part of the transaction as u32
 fn input_weight_sum(tx: &Transaction) -> u32 {
     let mut input_weight_sum: u32 = 0;
@@ -43,25 +85,16 @@ fn output_weight_sum(tx: &Transaction) -> u32 {
     output_weight_sum
 }
 
-// returns: size in bytes of all witnesses contained in a transaction as u32
+// returns: size in bytes of all serialized witnesses (item counts, length prefixes
+// and the empty witness of non-witness inputs included) contained in a transaction as u32
 fn witness_weight_sum(tx: &Transaction) -> u32 {
-    let mut witness_weight_sum: u32 = 0;
-    for txin in &tx.vin {
-        if let Some(hex_witness_vec) = &txin.witness {
-            for witness in hex_witness_vec {
-                witness_weight_sum += hex::decode(witness)
-                    .expect("witness weight calculation hex decode failed")
-                    .len() as u32;
-            }
-        };
-    }
-    witness_weight_sum
+    serialize_witnesses_with_amount(tx).len() as u32
 }
 
 // calls the functions to calculate the weight of the different components
 // of the transactions. Multiplies and sums them.
 // returns: tx weight as u32
-fn calculate_weight(tx: &Transaction) -> u32 {
+pub fn calculate_weight(tx: &Transaction) -> u32 {
     let mut weight: u32 = 4 * 4; // Version: 4 bytes x 4
     if is_segwit(tx) {
         weight += 2; // marker 1 byte + flag 1 byte
@@ -84,3 +117,36 @@ pub fn validate_and_set_weight(tx: &mut Transaction) -> bool {
     tx.meta.weight = weight as u64;
     true
 }
+
+// checks the policy size limits: at most MAX_STANDARD_TX_WEIGHT, at least
+// MIN_STANDARD_TX_NONWITNESS_SIZE serialized bytes without witness and scriptsigs of at most
+// MAX_STANDARD_SCRIPTSIG_SIZE bytes. Needs the weight and base size set by the validation.
+// returns: Ok() or Err(SizeLimit) of the first exceeded limit
+pub fn validate_standard_size(tx: &Transaction) -> Result<(), SizeLimit> {
+    if tx.meta.weight > MAX_STANDARD_TX_WEIGHT {
+        return Err(SizeLimit::Weight(tx.meta.weight));
+    }
+    if tx.meta.base_size < MIN_STANDARD_TX_NONWITNESS_SIZE {
+        return Err(SizeLimit::NonWitnessSize(tx.meta.base_size));
+    }
+    if let Some((index, txin)) = tx
+        .vin
+        .iter()
+        .enumerate()
+        .find(|(_, txin)| txin.scriptsig_bytes.len() > MAX_STANDARD_SCRIPTSIG_SIZE)
+    {
+        return Err(SizeLimit::ScriptsigSize {
+            index,
+            size: txin.scriptsig_bytes.len(),
+        });
+    }
+    Ok(())
+}
+
+// recomputes the weight from the sizes of the serializations cached by
+// validate_txid_hash_filename() (base size * 3 + total size, BIP141), independent of the
+// component sums of calculate_weight()
+// returns: weight of the serialized transaction as u64
+pub fn serialized_weight(tx: &Transaction) -> u64 {
+    (tx.meta.base_size * 3 + tx.meta.total_size) as u64
+}
@@ -0,0 +1,53 @@
+// Distribution of the weights of the validated transactions for the stats output: a histogram
+// with power of two buckets and the transactions far above the typical weight (more than
+// 3 interquartile ranges above the third quartile), which are worth a look when a serializer
+// change is tested on real mempool data.
+
+use crate::parsing::transaction_structs::Transaction;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WeightStats {
+    pub histogram: Vec<(u64, usize)>, // (lower bound of the bucket, transactions), ascending
+    pub outlier_threshold: u64,       // weights above are outliers
+    pub outliers: Vec<(String, u64)>, // (txid, weight), heaviest first
+}
+
+// returns: lower bound of the power of two bucket [2^n, 2^(n+1)) containing weight
+fn bucket(weight: u64) -> u64 {
+    match weight {
+        0 => 0,
+        _ => 1 << (63 - weight.leading_zeros()),
+    }
+}
+
+// returns: WeightStats of the transaction weights (meta.weight)
+pub fn weight_stats<'a>(transactions: impl Iterator<Item = &'a Transaction>) -> WeightStats {
+    let mut weights: Vec<(u64, &str)> = transactions
+        .map(|tx| (tx.meta.weight, tx.meta.txid_hex.as_str()))
+        .collect();
+    if weights.is_empty() {
+        return WeightStats::default();
+    }
+    weights.sort_unstable();
+
+    let mut histogram: BTreeMap<u64, usize> = BTreeMap::new();
+    for (weight, _) in &weights {
+        *histogram.entry(bucket(*weight)).or_default() += 1;
+    }
+    let first_quartile = weights[weights.len() / 4].0;
+    let third_quartile = weights[weights.len() * 3 / 4].0;
+    let outlier_threshold = third_quartile + 3 * (third_quartile - first_quartile);
+    let outliers = weights
+        .iter()
+        .rev()
+        .take_while(|(weight, _)| *weight > outlier_threshold)
+        .map(|(weight, txid)| (txid.to_string(), *weight))
+        .collect();
+
+    WeightStats {
+        histogram: histogram.into_iter().collect(),
+        outlier_threshold,
+        outliers,
+    }
+}